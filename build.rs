@@ -1,4 +1,21 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("subscribe.proto")?;
+    // The descriptor set is embedded in the binary for GetProtoDescriptors
+    // and server reflection
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("orderbook_descriptor.bin"))
+        // The REST gateway returns the unary responses as JSON
+        .type_attribute(".orderbook", "#[derive(serde::Serialize)]")
+        .compile(
+            &[
+                "subscribe.proto",
+                "grpc/health/v1/health.proto",
+                "grpc/reflection/v1alpha/reflection.proto",
+                "arrow/flight/protocol/Flight.proto",
+            ],
+            &["."],
+        )?;
+    // The v1 API is embedded as source only
+    println!("cargo:rerun-if-changed=proto/orderbook.proto");
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,8 @@
+//! Arbitrary bytes as a binary order file, in each format
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use orderbook_engine_fuzz::decode_binary;
+
+fuzz_target!(|data: &[u8]| decode_binary(data));
@@ -0,0 +1,15 @@
+//! Arbitrary bytes as a line of the node feed
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use orderbook_engine_fuzz::{check_validated, parser};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(order) = parser().parse_line(line) {
+        check_validated(&order);
+    }
+});
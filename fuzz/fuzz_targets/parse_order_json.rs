@@ -0,0 +1,62 @@
+//! Well-formed order statuses with arbitrary field values, to get past JSON
+//! parsing and into validation
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use orderbook_engine_fuzz::{check_validated, parser};
+use serde_json::{json, Value};
+
+/// Prices and sizes come as strings or numbers
+#[derive(Arbitrary, Debug)]
+enum Amount {
+    Float(f64),
+    Int(i64),
+    Text(String),
+}
+
+impl Amount {
+    fn to_json(&self) -> Value {
+        match self {
+            Amount::Float(value) => json!(value),
+            Amount::Int(value) => json!(value),
+            Amount::Text(text) => json!(text),
+        }
+    }
+}
+
+#[derive(Arbitrary, Debug)]
+struct Status {
+    oid: u64,
+    coin: String,
+    side: String,
+    limit_px: Amount,
+    sz: Amount,
+    is_trigger: bool,
+    trigger_condition: String,
+    timestamp: u64,
+    status: String,
+    user: String,
+}
+
+fuzz_target!(|status: Status| {
+    let line = json!({
+        "order": {
+            "oid": status.oid,
+            "coin": status.coin,
+            "side": status.side,
+            "limitPx": status.limit_px.to_json(),
+            "sz": status.sz.to_json(),
+            "isTrigger": status.is_trigger,
+            "triggerCondition": status.trigger_condition,
+            "timestamp": status.timestamp,
+        },
+        "status": status.status,
+        "user": status.user,
+    })
+    .to_string();
+    if let Ok(order) = parser().parse_line(&line) {
+        check_validated(&order);
+    }
+});
@@ -0,0 +1,92 @@
+//! The engine's order parser and binary decoder, for the fuzz targets.
+//!
+//! The engine is a binary crate, so there's no library to depend on. Its
+//! modules are compiled here instead, by path, with every module they use,
+//! under the same names so their `crate::` paths resolve.
+
+#![allow(dead_code, unused_imports, unused_variables)]
+
+#[path = "../../src/alerts.rs"]
+mod alerts;
+#[path = "../../src/binary_format.rs"]
+mod binary_format;
+#[path = "../../src/book_metrics.rs"]
+mod book_metrics;
+#[path = "../../src/conformance_vectors.rs"]
+mod conformance_vectors;
+#[path = "../../src/contagion.rs"]
+mod contagion;
+#[path = "../../src/degradation.rs"]
+mod degradation;
+#[path = "../../src/event_log.rs"]
+mod event_log;
+#[path = "../../src/fast_orderbook.rs"]
+mod fast_orderbook;
+#[path = "../../src/file_rotation.rs"]
+mod file_rotation;
+#[path = "../../src/file_wakeups.rs"]
+mod file_wakeups;
+#[path = "../../src/mark_price.rs"]
+mod mark_price;
+#[path = "../../src/mark_price_v2.rs"]
+mod mark_price_v2;
+#[path = "../../src/market_processor.rs"]
+mod market_processor;
+#[path = "../../src/market_scheduler.rs"]
+mod market_scheduler;
+#[path = "../../src/market_tiers.rs"]
+mod market_tiers;
+#[path = "../../src/memory_profile.rs"]
+mod memory_profile;
+#[path = "../../src/mmap_reader.rs"]
+mod mmap_reader;
+#[path = "../../src/oid_epochs.rs"]
+mod oid_epochs;
+#[path = "../../src/order_parser.rs"]
+pub mod order_parser;
+#[path = "../../src/price_ticks.rs"]
+mod price_ticks;
+#[path = "../../src/quote_stuffing.rs"]
+mod quote_stuffing;
+
+use market_processor::BinaryOrder;
+use order_parser::{OrderParser, ValidatedOrder};
+
+/// Limits of `parser()`, lower than the defaults so they're easy to cross
+pub const MAX_PRICE: f64 = 1_000_000.0;
+pub const MAX_SIZE: f64 = 10_000.0;
+
+pub fn parser() -> OrderParser {
+    OrderParser::new().with_limits(MAX_PRICE, MAX_SIZE)
+}
+
+/// Panic if `order` got through validation without meeting it
+pub fn check_validated(order: &ValidatedOrder) {
+    assert!(order.price.is_finite() && order.price > 0.0 && order.price <= MAX_PRICE, "price {}", order.price);
+    assert!(order.size.is_finite() && order.size > 0.0 && order.size <= MAX_SIZE, "size {}", order.size);
+    assert!(!order.coin.is_empty() && order.coin.len() <= 20, "coin {:?}", order.coin);
+}
+
+/// Decode `bytes` as plain and checksummed v1 records and as v2 frames,
+/// panicking on any record that decodes out of place or unvalidated
+pub fn decode_binary(bytes: &[u8]) {
+    let mut out = Vec::new();
+    for decode in [
+        |bytes: &[u8], out: &mut Vec<_>| BinaryOrder::decode_batch(bytes, false, out),
+        |bytes: &[u8], out: &mut Vec<_>| BinaryOrder::decode_batch(bytes, true, out),
+        |bytes: &[u8], out: &mut Vec<_>| BinaryOrder::decode_frames(bytes, out),
+    ] {
+        out.clear();
+        decode(bytes, &mut out);
+        let mut last_end = 0;
+        for (end, order) in &out {
+            assert!(last_end < *end && *end <= bytes.len(), "record ends at {} after {} of {}", end, last_end, bytes.len());
+            last_end = *end;
+            if let Ok(order) = order {
+                assert!(order.price.is_finite() && order.price > 0.0, "price {}", order.price);
+                assert!(order.size.is_finite() && order.size >= 0.0, "size {}", order.size);
+                assert!(order.status <= 2, "status {}", order.status);
+            }
+        }
+    }
+}
@@ -0,0 +1,13 @@
+#!/bin/bash
+# Package the v1 and v2 proto files with the v2 descriptor set as
+# dist/orderbook-protos-<version>.tar.gz for client teams
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+version=$(grep -m1 '^version' Cargo.toml | cut -d'"' -f2)
+out="dist/orderbook-protos-$version"
+
+rm -rf "$out"
+cargo run --release --bin orderbook-service-realtime -- export-protos --out-dir "$out"
+tar -czf "$out.tar.gz" -C dist "orderbook-protos-$version"
+echo "Wrote $out.tar.gz"
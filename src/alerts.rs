@@ -0,0 +1,116 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::contagion::StressedMarket;
+use crate::degradation::DegradationEvent;
+use crate::order_parser::ErrorCategory;
+
+/// Alerts kept for subscribers that join after the fact
+const RECENT_ALERTS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl std::str::FromStr for Severity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        [Severity::Info, Severity::Warning, Severity::Error, Severity::Critical]
+            .into_iter()
+            .find(|severity| severity.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| anyhow::anyhow!("Unknown severity: {}", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertKind {
+    /// Errors in one category reached the threshold within the window
+    ErrorRateSpike {
+        category: ErrorCategory,
+        count: usize,
+        window: Duration,
+    },
+    /// The service entered or left degraded mode
+    Degradation(Arc<DegradationEvent>),
+    /// Several markets of one correlated group stressed at once
+    Contagion {
+        group: String,
+        group_size: usize,
+        markets: Vec<StressedMarket>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Alert {
+    pub alert_id: u64,
+    pub timestamp_ms: u64,
+    pub severity: Severity,
+    pub kind: AlertKind,
+}
+
+/// Fan-out point for operational alerts
+pub struct Alerts {
+    next_alert_id: AtomicU64,
+    recent: Mutex<VecDeque<Arc<Alert>>>,
+    output_tx: broadcast::Sender<Arc<Alert>>,
+}
+
+impl Alerts {
+    pub fn new() -> Self {
+        Self {
+            next_alert_id: AtomicU64::new(1),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_ALERTS)),
+            output_tx: broadcast::channel(256).0,
+        }
+    }
+
+    pub fn raise(&self, severity: Severity, kind: AlertKind) -> Arc<Alert> {
+        let alert = Arc::new(Alert {
+            alert_id: self.next_alert_id.fetch_add(1, Ordering::Relaxed),
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+            severity,
+            kind,
+        });
+
+        warn!("Alert {} ({}): {:?}", alert.alert_id, severity.as_str(), alert.kind);
+
+        {
+            let mut recent = self.recent.lock();
+            if recent.len() == RECENT_ALERTS {
+                recent.pop_front();
+            }
+            recent.push_back(alert.clone());
+        }
+        let _ = self.output_tx.send(alert.clone());
+        alert
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Alert>> {
+        self.output_tx.subscribe()
+    }
+
+    pub fn recent(&self) -> Vec<Arc<Alert>> {
+        self.recent.lock().iter().cloned().collect()
+    }
+}
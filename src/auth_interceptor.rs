@@ -1,7 +1,12 @@
 use tonic::{Request, Status};
-use std::collections::HashSet;
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 
 /// Simple API key authentication interceptor
 #[derive(Clone)]
@@ -138,6 +143,333 @@ impl<S> AuthWrapper<S> {
     }
 }
 
+/// What a key may call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Scope {
+    /// Books and other market data
+    ReadOnly,
+    /// Stop order queries and streams
+    StopOrders,
+    /// Everything, including tiers, tees and forced resnapshots
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ReadOnly => "read-only",
+            Scope::StopOrders => "stop-orders",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "read-only" | "read" => Ok(Scope::ReadOnly),
+            "stop-orders" => Ok(Scope::StopOrders),
+            "admin" => Ok(Scope::Admin),
+            other => Err(anyhow!("Unknown scope: {} (expected read-only, stop-orders or admin)", other)),
+        }
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Self> {
+        s.parse()
+    }
+}
+
+fn default_scopes() -> HashSet<Scope> {
+    HashSet::from([Scope::ReadOnly])
+}
+
+/// Scopes and markets granted to one API key
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyPermissions {
+    #[serde(default = "default_scopes")]
+    pub scopes: HashSet<Scope>,
+    /// Market ids the key may see; every market if unset
+    #[serde(default)]
+    pub markets: Option<HashSet<u32>>,
+    /// Overrides of the default stream quota
+    #[serde(default)]
+    pub max_streams: Option<usize>,
+    #[serde(default)]
+    pub max_markets: Option<usize>,
+}
+
+impl KeyPermissions {
+    /// Every scope and market, for listeners without auth
+    pub fn full() -> Self {
+        Self { scopes: HashSet::from([Scope::Admin]), markets: None, max_streams: None, max_markets: None }
+    }
+
+    /// Read-only on every market, for valid keys without an entry
+    pub fn unlisted() -> Self {
+        Self { scopes: default_scopes(), markets: None, max_streams: None, max_markets: None }
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), Status> {
+        if self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin) {
+            Ok(())
+        } else {
+            Err(Status::permission_denied(format!("API key lacks the {} scope", scope.as_str())))
+        }
+    }
+
+    pub fn allows_market(&self, market_id: u32) -> bool {
+        self.markets.as_ref().is_none_or(|markets| markets.contains(&market_id))
+    }
+
+    pub fn check_markets<'a>(&self, market_ids: impl IntoIterator<Item = &'a u32>) -> Result<(), Status> {
+        match market_ids.into_iter().find(|id| !self.allows_market(**id)) {
+            Some(id) => Err(Status::permission_denied(format!("API key may not access market {}", id))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for KeyPermissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut scopes: Vec<&str> = self.scopes.iter().map(Scope::as_str).collect();
+        scopes.sort_unstable();
+        write!(f, "{}", scopes.join(", "))?;
+        match &self.markets {
+            Some(markets) => {
+                let mut markets: Vec<u32> = markets.iter().copied().collect();
+                markets.sort_unstable();
+                write!(f, " on markets {:?}", markets)?;
+            }
+            None => write!(f, " on every market")?,
+        }
+        if let Some(max) = self.max_streams {
+            write!(f, ", at most {} streams", max)?;
+        }
+        if let Some(max) = self.max_markets {
+            write!(f, ", at most {} streamed markets", max)?;
+        }
+        Ok(())
+    }
+}
+
+/// Permissions by API key from a JSON object, e.g.
+/// `{"desk-a": {"scopes": ["read-only"], "markets": [0, 1]}}`
+pub fn load_permissions(path: &Path) -> anyhow::Result<HashMap<String, KeyPermissions>> {
+    serde_json::from_str(&std::fs::read_to_string(path)?)
+        .with_context(|| format!("Invalid API key permissions file {}", path.display()))
+}
+
+/// Streams one key may have open at once; unlimited where unset
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamLimits {
+    pub max_streams: Option<usize>,
+    /// Markets across all of the key's streams, counting a market once
+    /// per stream
+    pub max_markets: Option<usize>,
+}
+
+/// Open streams and subscribed markets per API key
+#[derive(Debug, Default)]
+pub struct StreamQuotas {
+    defaults: StreamLimits,
+    usage: Mutex<HashMap<String, (usize, usize)>>,
+}
+
+/// The caller's share of the stream quotas, as attached by its listener's
+/// policy; callers without a key aren't limited
+#[derive(Clone, Default)]
+pub struct StreamQuota {
+    account: Option<(String, StreamLimits, Arc<StreamQuotas>)>,
+}
+
+impl StreamQuota {
+    /// Count a stream over `markets` markets until the guard is dropped
+    pub fn acquire(&self, markets: usize) -> Result<QuotaGuard, Status> {
+        let mut guard = QuotaGuard { account: self.account.clone(), streams: 0, markets: 0 };
+        guard.claim(1, markets)?;
+        Ok(guard)
+    }
+}
+
+/// Keeps a stream counted against its key
+#[derive(Debug)]
+pub struct QuotaGuard {
+    account: Option<(String, StreamLimits, Arc<StreamQuotas>)>,
+    streams: usize,
+    markets: usize,
+}
+
+impl QuotaGuard {
+    /// Count the stream under `markets` markets instead, if the quota allows
+    pub fn resize(&mut self, markets: usize) -> Result<(), Status> {
+        self.claim(self.streams, markets)
+    }
+
+    fn claim(&mut self, streams: usize, markets: usize) -> Result<(), Status> {
+        let Some((key, limits, quotas)) = &self.account else {
+            return Ok(());
+        };
+        let mut usage = quotas.usage.lock();
+        let (open_streams, open_markets) = usage.entry(key.clone()).or_default();
+        let others = (*open_streams - self.streams, *open_markets - self.markets);
+        if let Some(max) = limits.max_streams.filter(|max| others.0 + streams > *max) {
+            return Err(Status::resource_exhausted(format!("API key already has {} of {} streams open", others.0, max)));
+        }
+        if let Some(max) = limits.max_markets.filter(|max| others.1 + markets > *max) {
+            return Err(Status::resource_exhausted(format!(
+                "API key streams {} markets; {} more would exceed its limit of {}",
+                others.1, markets, max
+            )));
+        }
+        (*open_streams, *open_markets) = (others.0 + streams, others.1 + markets);
+        (self.streams, self.markets) = (streams, markets);
+        Ok(())
+    }
+}
+
+impl Drop for QuotaGuard {
+    fn drop(&mut self) {
+        if let Some((key, _, quotas)) = &self.account {
+            let mut usage = quotas.usage.lock();
+            if let Some((streams, markets)) = usage.get_mut(key) {
+                (*streams, *markets) = (*streams - self.streams, *markets - self.markets);
+                if *streams == 0 {
+                    usage.remove(key);
+                }
+            }
+        }
+    }
+}
+
+/// Valid API keys, the permissions of those with an entry, and the stream
+/// quotas shared by every listener
+#[derive(Clone, Default)]
+pub struct ApiKeys {
+    keys: HashSet<String>,
+    permissions: Arc<HashMap<String, Arc<KeyPermissions>>>,
+    quotas: Arc<StreamQuotas>,
+}
+
+impl From<HashSet<String>> for ApiKeys {
+    fn from(keys: HashSet<String>) -> Self {
+        Self { keys, ..Default::default() }
+    }
+}
+
+impl ApiKeys {
+    /// Keys with permissions are valid whether or not they're in `keys`
+    pub fn new(mut keys: HashSet<String>, permissions: HashMap<String, KeyPermissions>) -> Self {
+        keys.extend(permissions.keys().cloned());
+        let permissions = permissions.into_iter().map(|(key, permissions)| (key, Arc::new(permissions))).collect();
+        Self { keys, permissions: Arc::new(permissions), quotas: Arc::default() }
+    }
+
+    /// Limits for keys without their own; call before any listener starts
+    pub fn set_stream_limits(&mut self, defaults: StreamLimits) {
+        self.quotas = Arc::new(StreamQuotas { defaults, usage: Mutex::default() });
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn restricted(&self) -> usize {
+        self.permissions.len()
+    }
+
+    /// What each key may do on a listener requiring auth, by the key's first
+    /// few characters, for the startup log
+    pub fn effective_permissions(&self) -> Vec<(String, Arc<KeyPermissions>)> {
+        let mut keys: Vec<&String> = self.keys.iter().collect();
+        keys.sort_unstable();
+        keys.into_iter()
+            .map(|key| {
+                let permissions = self.permissions.get(key).cloned().unwrap_or_else(|| Arc::new(KeyPermissions::unlisted()));
+                (masked(key), permissions)
+            })
+            .collect()
+    }
+}
+
+/// Enough of a key to tell it apart in logs
+fn masked(key: &str) -> String {
+    match key.char_indices().nth(4) {
+        Some((end, _)) if key.len() > 8 => format!("{}***", &key[..end]),
+        _ => "***".to_string(),
+    }
+}
+
+/// The caller's permissions, as attached by its listener's policy
+pub fn permissions<T>(request: &Request<T>) -> Arc<KeyPermissions> {
+    request.extensions().get::<Arc<KeyPermissions>>().cloned().unwrap_or_else(|| Arc::new(KeyPermissions::full()))
+}
+
+pub fn stream_quota<T>(request: &Request<T>) -> StreamQuota {
+    request.extensions().get::<StreamQuota>().cloned().unwrap_or_default()
+}
+
+/// API key check and rate limit for one listener, applied to every call
+/// before it reaches the service. On listeners requiring auth, the key's
+/// permissions travel with the request for the service to enforce; keys
+/// without an entry are read-only.
+#[derive(Clone)]
+pub struct ListenerPolicy {
+    api_key_interceptor: ApiKeyInterceptor,
+    rate_limiter: Option<RateLimitInterceptor>,
+    permissions: Arc<HashMap<String, Arc<KeyPermissions>>>,
+    quotas: Arc<StreamQuotas>,
+    require_auth: bool,
+}
+
+impl ListenerPolicy {
+    pub fn new(api_keys: ApiKeys, require_auth: bool, rate_limit: Option<u32>) -> Self {
+        Self {
+            api_key_interceptor: ApiKeyInterceptor::new(api_keys.keys, require_auth),
+            rate_limiter: rate_limit.map(RateLimitInterceptor::new),
+            permissions: api_keys.permissions,
+            quotas: api_keys.quotas,
+            require_auth,
+        }
+    }
+}
+
+impl tonic::service::Interceptor for ListenerPolicy {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        self.api_key_interceptor.validate_request(&request)?;
+        let key = request.metadata().get("x-api-key").and_then(|v| v.to_str().ok()).map(String::from);
+        
+        // Stream opens count like unary calls
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.check_rate_limit(key.as_deref().unwrap_or("anonymous"))?;
+        }
+        
+        if let Some(key) = key.filter(|_| self.require_auth) {
+            let permissions = self.permissions.get(&key).cloned().unwrap_or_else(|| Arc::new(KeyPermissions::unlisted()));
+            let defaults = self.quotas.defaults;
+            let limits = StreamLimits {
+                max_streams: permissions.max_streams.or(defaults.max_streams),
+                max_markets: permissions.max_markets.or(defaults.max_markets),
+            };
+            request.extensions_mut().insert(StreamQuota { account: Some((key, limits, self.quotas.clone())) });
+            request.extensions_mut().insert(permissions);
+        }
+        
+        Ok(request)
+    }
+}
+
 // Macro to implement auth wrapper for service
 #[macro_export]
 macro_rules! impl_auth_wrapper {
@@ -216,4 +548,115 @@ mod tests {
         // Different client should work
         assert!(limiter.check_rate_limit("client2").is_ok());
     }
+    
+    #[test]
+    fn test_listener_policy() {
+        use tonic::service::Interceptor;
+        
+        let keys: HashSet<String> = ["key-a".to_string()].into_iter().collect();
+        let request = |key: Option<&str>| {
+            let mut request = Request::new(());
+            if let Some(key) = key {
+                request.metadata_mut().insert("x-api-key", key.parse().unwrap());
+            }
+            request
+        };
+        
+        let mut open = ListenerPolicy::new(keys.clone().into(), false, None);
+        assert!(open.call(request(None)).is_ok());
+        
+        let mut locked = ListenerPolicy::new(keys.into(), true, Some(1));
+        assert_eq!(locked.call(request(None)).unwrap_err().code(), tonic::Code::Unauthenticated);
+        assert!(locked.call(request(Some("key-a"))).is_ok());
+        assert_eq!(locked.call(request(Some("key-a"))).unwrap_err().code(), tonic::Code::ResourceExhausted);
+    }
+    
+    #[test]
+    fn test_key_permissions() {
+        use tonic::service::Interceptor;
+        
+        let granted: HashMap<String, KeyPermissions> = serde_json::from_str(
+            r#"{"desk": {"markets": [0, 1]}, "stops": {"scopes": ["stop-orders"]}, "ops": {"scopes": ["admin"]}}"#,
+        )
+        .unwrap();
+        let keys = ApiKeys::new(["open".to_string()].into_iter().collect(), granted);
+        assert_eq!((keys.len(), keys.restricted()), (4, 3));
+        
+        let mut policy = ListenerPolicy::new(keys.clone(), true, None);
+        let call = |policy: &mut ListenerPolicy, key: &str| {
+            let mut request = Request::new(());
+            request.metadata_mut().insert("x-api-key", key.parse().unwrap());
+            permissions(&policy.call(request).unwrap())
+        };
+        
+        let desk = call(&mut policy, "desk");
+        assert!(desk.require(Scope::ReadOnly).is_ok());
+        assert_eq!(desk.require(Scope::StopOrders).unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert!(desk.check_markets(&[1, 0]).is_ok());
+        assert_eq!(desk.check_markets(&[0, 5]).unwrap_err().code(), tonic::Code::PermissionDenied);
+        
+        let stops = call(&mut policy, "stops");
+        assert!(stops.require(Scope::ReadOnly).is_err());
+        assert!(stops.require(Scope::StopOrders).is_ok() && stops.allows_market(5));
+        assert!(call(&mut policy, "ops").require(Scope::Admin).is_ok());
+        
+        // Valid keys without an entry only read market data
+        let unlisted = call(&mut policy, "open");
+        assert_eq!(*unlisted, KeyPermissions::unlisted());
+        assert!(unlisted.require(Scope::ReadOnly).is_ok() && unlisted.allows_market(5));
+        assert_eq!(unlisted.require(Scope::Admin).unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert_eq!(unlisted.require(Scope::StopOrders).unwrap_err().code(), tonic::Code::PermissionDenied);
+        
+        // Listeners without auth don't restrict anyone
+        let mut open = ListenerPolicy::new(keys.clone(), false, None);
+        assert_eq!(*call(&mut open, "desk"), KeyPermissions::full());
+        
+        assert!(serde_json::from_str::<KeyPermissions>(r#"{"scopes": ["write"]}"#).is_err());
+        
+        let logged: Vec<String> = keys.effective_permissions().iter().map(|(key, p)| format!("{}: {}", key, p)).collect();
+        assert_eq!(logged, ["***: read-only on markets [0, 1]", "***: read-only on every market", "***: admin on every market", "***: stop-orders on every market"]);
+        assert_eq!(masked("0123456789abcdef"), "0123***");
+    }
+    
+    #[test]
+    fn test_stream_quotas() {
+        use tonic::service::Interceptor;
+        
+        let granted: HashMap<String, KeyPermissions> = serde_json::from_str(r#"{"big": {"max_markets": 500}}"#).unwrap();
+        let mut keys = ApiKeys::new(["small".to_string()].into_iter().collect(), granted);
+        keys.set_stream_limits(StreamLimits { max_streams: Some(2), max_markets: Some(100) });
+        let quotas = keys.quotas.clone();
+        let usage = |key: &str| quotas.usage.lock().get(key).copied().unwrap_or_default();
+        let mut policy = ListenerPolicy::new(keys, true, None);
+        let quota = |policy: &mut ListenerPolicy, key: &str| {
+            let mut request = Request::new(());
+            request.metadata_mut().insert("x-api-key", key.parse().unwrap());
+            stream_quota(&policy.call(request).unwrap())
+        };
+        
+        let small = quota(&mut policy, "small");
+        let a = small.acquire(60).unwrap();
+        assert_eq!(small.acquire(60).unwrap_err().code(), tonic::Code::ResourceExhausted);
+        let mut b = small.acquire(40).unwrap();
+        assert_eq!(usage("small"), (2, 100));
+        assert_eq!(small.acquire(0).unwrap_err().code(), tonic::Code::ResourceExhausted);
+        
+        // Growing a stream counts against what the others leave
+        assert!(b.resize(41).is_err());
+        b.resize(10).unwrap();
+        assert_eq!(usage("small"), (2, 70));
+        drop(a);
+        b.resize(100).unwrap();
+        
+        // Per-key overrides, and keys are counted apart
+        let big = quota(&mut policy, "big");
+        let c = big.acquire(190).unwrap();
+        assert!(big.acquire(190).is_ok());
+        assert_eq!(usage("big"), (1, 190));
+        drop((b, c));
+        assert_eq!((usage("small"), usage("big")), ((0, 0), (0, 0)));
+        
+        // Callers without a key aren't counted
+        assert!(StreamQuota::default().acquire(10_000).is_ok());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,598 @@
+//! Conformance suite for the orderbook gRPC service.
+//!
+//! Exercises every RPC against a running server, validates protocol
+//! invariants and exits nonzero if any are violated. Intended for CI and for
+//! third-party client implementers checking their assumptions.
+
+use clap::Parser;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+use tonic::{Code, Request};
+
+pub mod pb {
+    tonic::include_proto!("orderbook");
+}
+
+use pb::orderbook_service_client::OrderbookServiceClient;
+use pb::{
+    DeltaSubscribeRequest, Empty, GetMarkPriceRequest, GetOrderbookRequest, Level, LevelChange, OrderbookSnapshot,
+    StopOrdersRequest, SubscribeRequest, TradesSubscribeRequest,
+};
+
+type Client = OrderbookServiceClient<Channel>;
+
+/// Levels per side covered by a book checksum
+const CHECKSUM_LEVELS: usize = 10;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Orderbook service conformance suite", long_about = None)]
+struct Args {
+    /// Server endpoint
+    #[arg(long, default_value = "http://127.0.0.1:50052")]
+    endpoint: String,
+
+    /// API key sent as x-api-key
+    #[arg(long)]
+    api_key: Option<String>,
+
+    /// Market IDs to check (comma-separated). Defaults to the first 5 markets.
+    #[arg(long)]
+    markets: Option<String>,
+
+    /// How long to observe the orderbook stream
+    #[arg(long, default_value = "10")]
+    stream_secs: u64,
+}
+
+/// Collected results for a conformance run
+#[derive(Default)]
+struct Report {
+    passed: u32,
+    skipped: Vec<String>,
+    violations: Vec<String>,
+}
+
+impl Report {
+    fn check(&mut self, ok: bool, description: impl FnOnce() -> String) {
+        if ok {
+            self.passed += 1;
+        } else {
+            let description = description();
+            println!("  FAIL {}", description);
+            self.violations.push(description);
+        }
+    }
+
+    fn skip(&mut self, description: String) {
+        println!("  SKIP {}", description);
+        self.skipped.push(description);
+    }
+}
+
+fn request<T>(message: T, api_key: &Option<String>) -> Request<T> {
+    let mut request = Request::new(message);
+    if let Some(key) = api_key {
+        if let Ok(value) = key.parse() {
+            request.metadata_mut().insert("x-api-key", value);
+        }
+    }
+    request
+}
+
+/// Validate level ordering and sizes for one snapshot
+fn check_snapshot(report: &mut Report, context: &str, snapshot: &OrderbookSnapshot) {
+    let strictly = |levels: &[Level], descending: bool| {
+        levels.windows(2).all(|w| {
+            if descending {
+                w[0].price > w[1].price
+            } else {
+                w[0].price < w[1].price
+            }
+        })
+    };
+
+    report.check(strictly(&snapshot.bids, true), || {
+        format!("{}: bids not strictly descending", context)
+    });
+    report.check(strictly(&snapshot.asks, false), || {
+        format!("{}: asks not strictly ascending", context)
+    });
+
+    let sizes_positive = snapshot
+        .bids
+        .iter()
+        .chain(snapshot.asks.iter())
+        .all(|l| l.quantity > 0.0 && l.price > 0.0 && l.price.is_finite());
+    report.check(sizes_positive, || {
+        format!("{}: level with non-positive price or quantity", context)
+    });
+
+    if let (Some(bid), Some(ask)) = (snapshot.bids.first(), snapshot.asks.first()) {
+        report.check(bid.price < ask.price, || {
+            format!("{}: crossed book (bid {} >= ask {})", context, bid.price, ask.price)
+        });
+    }
+}
+
+/// The server's book checksum: CRC-32 over the top levels of each side,
+/// bids then asks best first, as big-endian price then quantity
+fn book_checksum(bids: &[Level], asks: &[Level]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    let top = |levels: &[Level]| levels.len().min(CHECKSUM_LEVELS);
+    for level in bids[..top(bids)].iter().chain(&asks[..top(asks)]) {
+        hasher.update(&level.price.to_be_bytes());
+        hasher.update(&level.quantity.to_be_bytes());
+    }
+    hasher.finalize()
+}
+
+/// A book mirrored from the delta stream
+#[derive(Default)]
+struct MirrorBook {
+    sequence: u64,
+    /// Price bits -> (price, quantity)
+    bids: HashMap<u64, (f64, f64)>,
+    asks: HashMap<u64, (f64, f64)>,
+}
+
+impl MirrorBook {
+    fn from_snapshot(snapshot: &OrderbookSnapshot) -> Self {
+        let side = |levels: &[Level]| levels.iter().map(|l| (l.price.to_bits(), (l.price, l.quantity))).collect();
+        Self { sequence: snapshot.sequence, bids: side(&snapshot.bids), asks: side(&snapshot.asks) }
+    }
+
+    /// Apply one level change; false if the action or side is unknown
+    fn apply(&mut self, change: &LevelChange) -> bool {
+        if change.action == "clear" {
+            self.bids.clear();
+            self.asks.clear();
+            return true;
+        }
+        let side = match change.side.as_str() {
+            "B" => &mut self.bids,
+            "A" => &mut self.asks,
+            _ => return false,
+        };
+        match change.action.as_str() {
+            "add" | "change" => {
+                side.insert(change.price.to_bits(), (change.price, change.quantity));
+            }
+            "remove" => {
+                side.remove(&change.price.to_bits());
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn checksum(&self) -> u32 {
+        let levels = |side: &HashMap<u64, (f64, f64)>, descending: bool| {
+            let mut levels: Vec<Level> = side
+                .values()
+                .map(|(price, quantity)| Level { price: *price, quantity: *quantity, ..Default::default() })
+                .collect();
+            levels.sort_by(|a, b| if descending { b.price.total_cmp(&a.price) } else { a.price.total_cmp(&b.price) });
+            levels
+        };
+        book_checksum(&levels(&self.bids, true), &levels(&self.asks, false))
+    }
+}
+
+async fn check_markets(client: &mut Client, args: &Args, report: &mut Report) -> anyhow::Result<Vec<(u32, String)>> {
+    println!("GetMarkets");
+    let markets = client.get_markets(request(Empty {}, &args.api_key)).await?.into_inner().markets;
+
+    report.check(!markets.is_empty(), || "GetMarkets returned no markets".to_string());
+
+    let unique: HashSet<u32> = markets.iter().map(|m| m.id).collect();
+    report.check(unique.len() == markets.len(), || "GetMarkets returned duplicate ids".to_string());
+    report.check(markets.iter().all(|m| !m.symbol.is_empty()), || {
+        "GetMarkets returned an empty symbol".to_string()
+    });
+
+    let mut markets: Vec<(u32, String)> = markets.into_iter().map(|m| (m.id, m.symbol)).collect();
+    markets.sort_by_key(|(id, _)| *id);
+
+    let selected = match &args.markets {
+        Some(ids) => {
+            let wanted: HashSet<u32> = ids.split(',').filter_map(|s| s.trim().parse().ok()).collect();
+            markets.into_iter().filter(|(id, _)| wanted.contains(id)).collect()
+        }
+        None => markets.into_iter().take(5).collect(),
+    };
+
+    Ok(selected)
+}
+
+async fn check_orderbooks(
+    client: &mut Client,
+    args: &Args,
+    markets: &[(u32, String)],
+    cold: &HashSet<u32>,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    println!("GetOrderbook");
+    for (market_id, symbol) in markets {
+        let snapshot = client
+            .get_orderbook(request(GetOrderbookRequest { market_id: *market_id, depth: 20, ..Default::default() }, &args.api_key))
+            .await?
+            .into_inner();
+
+        let context = format!("GetOrderbook {}", symbol);
+        report.check(snapshot.market_id == *market_id, || format!("{}: wrong market_id", context));
+        report.check(&snapshot.symbol == symbol, || format!("{}: symbol mismatch", context));
+        report.check(snapshot.bids.len() <= 20 && snapshot.asks.len() <= 20, || {
+            format!("{}: depth limit not respected", context)
+        });
+        check_snapshot(report, &context, &snapshot);
+
+        // Cold markets are cut to one level, too few to hash
+        match snapshot.checksum {
+            Some(checksum) if !cold.contains(market_id) => {
+                report.check(checksum == book_checksum(&snapshot.bids, &snapshot.asks), || {
+                    format!("{}: checksum doesn't match the levels at sequence {}", context, snapshot.sequence)
+                });
+            }
+            Some(_) => {}
+            None => report.check(false, || format!("{}: live book without a checksum", context)),
+        }
+    }
+
+    let missing = client
+        .get_orderbook(request(GetOrderbookRequest { market_id: u32::MAX, depth: 1, ..Default::default() }, &args.api_key))
+        .await;
+    report.check(matches!(&missing, Err(s) if s.code() == Code::NotFound), || {
+        "GetOrderbook for unknown market did not return NOT_FOUND".to_string()
+    });
+
+    Ok(())
+}
+
+async fn check_stream(client: &mut Client, args: &Args, markets: &[(u32, String)], report: &mut Report) -> anyhow::Result<()> {
+    println!("SubscribeOrderbook ({}s)", args.stream_secs);
+    let market_ids: Vec<u32> = markets.iter().map(|(id, _)| *id).collect();
+    let wanted: HashSet<u32> = market_ids.iter().copied().collect();
+
+    let mut stream = client
+        .subscribe_orderbook(request(
+            SubscribeRequest { market_ids, ..Default::default() },
+            &args.api_key,
+        ))
+        .await?
+        .into_inner();
+
+    let deadline = Instant::now() + Duration::from_secs(args.stream_secs);
+    let mut last_sequence: HashMap<u32, u64> = HashMap::new();
+    let mut messages = 0u64;
+    let mut gaps = 0u64;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let snapshot = match tokio::time::timeout(remaining, stream.next()).await {
+            Ok(Some(Ok(snapshot))) => snapshot,
+            Ok(Some(Err(status))) => {
+                report.check(false, || format!("SubscribeOrderbook stream error: {}", status));
+                break;
+            }
+            Ok(None) => {
+                report.check(false, || "SubscribeOrderbook stream ended early".to_string());
+                break;
+            }
+            Err(_) => break,
+        };
+        messages += 1;
+
+        let context = format!("SubscribeOrderbook market {} seq {}", snapshot.market_id, snapshot.sequence);
+        report.check(wanted.contains(&snapshot.market_id), || {
+            format!("{}: message for unsubscribed market", context)
+        });
+        check_snapshot(report, &context, &snapshot);
+
+        if let Some(previous) = last_sequence.insert(snapshot.market_id, snapshot.sequence) {
+            report.check(snapshot.sequence >= previous, || {
+                format!("{}: sequence went backwards from {}", context, previous)
+            });
+            if snapshot.sequence > previous + 1 {
+                // Snapshots coalesce deltas, so a gap is expected; clients must not assume contiguity
+                gaps += 1;
+            }
+        }
+    }
+
+    report.check(last_sequence.keys().all(|id| wanted.contains(id)), || {
+        "SubscribeOrderbook sent unrequested markets".to_string()
+    });
+    report.check(wanted.iter().all(|id| last_sequence.contains_key(id)), || {
+        "SubscribeOrderbook did not send an initial snapshot for every market".to_string()
+    });
+    println!("  {} messages, {} sequence gaps (coalesced)", messages, gaps);
+
+    Ok(())
+}
+
+async fn check_stop_orders(client: &mut Client, args: &Args, markets: &[(u32, String)], report: &mut Report) -> anyhow::Result<()> {
+    println!("GetStopOrders");
+    for (market_id, symbol) in markets {
+        let orders = client
+            .get_stop_orders(request(
+                StopOrdersRequest {
+                    filter: Some(pb::stop_orders_request::Filter::MarketId(*market_id)),
+                    rank_by_risk: true,
+                    ..Default::default()
+                },
+                &args.api_key,
+            ))
+            .await?
+            .into_inner()
+            .orders;
+
+        let context = format!("GetStopOrders {}", symbol);
+        report.check(orders.windows(2).all(|w| w[0].risk_score >= w[1].risk_score), || {
+            format!("{}: ranked orders not sorted by risk", context)
+        });
+
+        for ranked in &orders {
+            let Some(order) = &ranked.order else {
+                report.check(false, || format!("{}: ranked entry without order", context));
+                continue;
+            };
+            report.check(order.price > 0.0 && order.size > 0.0, || {
+                format!("{}: order {} has non-positive price or size", context, order.id)
+            });
+            report.check(order.side == "B" || order.side == "A", || {
+                format!("{}: order {} has invalid side {:?}", context, order.id, order.side)
+            });
+            report.check((order.notional - order.price * order.size).abs() <= 1e-6 * order.notional.abs().max(1.0), || {
+                format!("{}: order {} notional != price * size", context, order.id)
+            });
+            report.check((0.0..=100.0).contains(&ranked.risk_score), || {
+                format!("{}: order {} risk score out of range", context, order.id)
+            });
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_mark_prices(client: &mut Client, args: &Args, markets: &[(u32, String)], report: &mut Report) -> anyhow::Result<()> {
+    println!("GetMarkPrice");
+    for (market_id, symbol) in markets {
+        match client
+            .get_mark_price(request(GetMarkPriceRequest { market_id: *market_id }, &args.api_key))
+            .await
+        {
+            Ok(response) => {
+                let response = response.into_inner();
+                report.check(response.market_id == *market_id, || {
+                    format!("GetMarkPrice {}: wrong market_id", symbol)
+                });
+                if let Some(mark) = response.hl_mark_price {
+                    report.check(mark.mark_price > 0.0 && mark.mark_price.is_finite(), || {
+                        format!("GetMarkPrice {}: invalid mark price {}", symbol, mark.mark_price)
+                    });
+                }
+            }
+            Err(status) if status.code() == Code::Unimplemented => {
+                report.skip("GetMarkPrice: not enabled on server".to_string());
+                return Ok(());
+            }
+            // One-sided books have no mark price yet
+            Err(status) if status.code() == Code::Unavailable => {
+                report.skip(format!("GetMarkPrice {}: {}", symbol, status.message()));
+            }
+            Err(status) => report.check(false, || format!("GetMarkPrice {}: {}", symbol, status)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the markets currently served cold
+async fn check_market_tiers(client: &mut Client, args: &Args, report: &mut Report) -> anyhow::Result<HashSet<u32>> {
+    println!("GetMarketTiers");
+    let tiers = client.get_market_tiers(request(Empty {}, &args.api_key)).await?.into_inner();
+
+    let valid = |t: &str| t == "HOT" || t == "COLD";
+    report.check(valid(&tiers.default_tier), || {
+        format!("GetMarketTiers: invalid default tier {:?}", tiers.default_tier)
+    });
+    report.check(tiers.tiers.iter().all(|t| valid(&t.tier)), || {
+        "GetMarketTiers: invalid tier value".to_string()
+    });
+
+    Ok(tiers.tiers.iter().filter(|t| t.tier == "COLD").map(|t| t.market_id).collect())
+}
+
+async fn check_deltas(client: &mut Client, args: &Args, markets: &[(u32, String)], report: &mut Report) -> anyhow::Result<()> {
+    println!("SubscribeDeltas ({}s)", args.stream_secs);
+    let market_ids: Vec<u32> = markets.iter().map(|(id, _)| *id).collect();
+    let wanted: HashSet<u32> = market_ids.iter().copied().collect();
+
+    let mut stream = client
+        .subscribe_deltas(request(DeltaSubscribeRequest { market_ids, ..Default::default() }, &args.api_key))
+        .await?
+        .into_inner();
+
+    let deadline = Instant::now() + Duration::from_secs(args.stream_secs);
+    let mut books: HashMap<u32, MirrorBook> = HashMap::new();
+    let (mut snapshots, mut deltas, mut checksums) = (0u64, 0u64, 0u64);
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let message = match tokio::time::timeout(remaining, stream.next()).await {
+            Ok(Some(Ok(message))) => message,
+            Ok(Some(Err(status))) => {
+                report.check(false, || format!("SubscribeDeltas stream error: {}", status));
+                break;
+            }
+            Ok(None) => {
+                report.check(false, || "SubscribeDeltas stream ended early".to_string());
+                break;
+            }
+            Err(_) => break,
+        };
+
+        match message.payload {
+            Some(pb::delta_message::Payload::Snapshot(snapshot)) => {
+                snapshots += 1;
+                let context = format!("SubscribeDeltas snapshot market {} seq {}", snapshot.market_id, snapshot.sequence);
+                report.check(wanted.contains(&snapshot.market_id), || {
+                    format!("{}: message for unsubscribed market", context)
+                });
+                check_snapshot(report, &context, &snapshot);
+                if let Some(checksum) = snapshot.checksum {
+                    checksums += 1;
+                    report.check(checksum == book_checksum(&snapshot.bids, &snapshot.asks), || {
+                        format!("{}: checksum doesn't match the snapshot's levels", context)
+                    });
+                }
+                books.insert(snapshot.market_id, MirrorBook::from_snapshot(&snapshot));
+            }
+            Some(pb::delta_message::Payload::Delta(delta)) => {
+                deltas += 1;
+                let context = format!("SubscribeDeltas delta market {} seq {}", delta.market_id, delta.sequence);
+                report.check(wanted.contains(&delta.market_id), || {
+                    format!("{}: message for unsubscribed market", context)
+                });
+                report.check(delta.sequence > delta.prev_sequence, || {
+                    format!("{}: sequence not after prev_sequence {}", context, delta.prev_sequence)
+                });
+                let Some(book) = books.get_mut(&delta.market_id) else {
+                    report.check(false, || format!("{}: delta before the market's snapshot", context));
+                    continue;
+                };
+                // The default lag policy resyncs with a snapshot, so deltas never skip
+                if delta.prev_sequence != book.sequence {
+                    report.check(false, || {
+                        format!("{}: gap, prev_sequence {} but the book is at {}", context, delta.prev_sequence, book.sequence)
+                    });
+                    books.remove(&delta.market_id);
+                    continue;
+                }
+                let known = delta.changes.iter().all(|change| book.apply(change));
+                report.check(known, || format!("{}: unknown level change action or side", context));
+                book.sequence = delta.sequence;
+                if let Some(checksum) = delta.checksum {
+                    checksums += 1;
+                    report.check(checksum == book.checksum(), || {
+                        format!("{}: checksum mismatch, mirrored book diverged", context)
+                    });
+                }
+            }
+            Some(pb::delta_message::Payload::Lag(lag)) => {
+                report.check(false, || {
+                    format!("SubscribeDeltas: lag notice ({}) under the default resync policy", lag.policy)
+                });
+            }
+            None => report.check(false, || "SubscribeDeltas: message without payload".to_string()),
+        }
+    }
+
+    report.check(wanted.iter().all(|id| books.contains_key(id)), || {
+        "SubscribeDeltas did not send an initial snapshot for every market".to_string()
+    });
+    println!("  {} snapshots, {} deltas, {} checksums verified", snapshots, deltas, checksums);
+
+    Ok(())
+}
+
+async fn check_trades(client: &mut Client, args: &Args, markets: &[(u32, String)], report: &mut Report) -> anyhow::Result<()> {
+    println!("SubscribeTrades ({}s)", args.stream_secs);
+    let market_ids: Vec<u32> = markets.iter().map(|(id, _)| *id).collect();
+    let wanted: HashSet<u32> = market_ids.iter().copied().collect();
+
+    let mut stream = match client.subscribe_trades(request(TradesSubscribeRequest { market_ids }, &args.api_key)).await {
+        Ok(response) => response.into_inner(),
+        Err(status) if status.code() == Code::Unavailable => {
+            report.skip(format!("SubscribeTrades: {}", status.message()));
+            return Ok(());
+        }
+        Err(status) => return Err(status.into()),
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(args.stream_secs);
+    let mut seen: HashSet<(u32, u64)> = HashSet::new();
+    let mut trades = 0u64;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let trade = match tokio::time::timeout(remaining, stream.next()).await {
+            Ok(Some(Ok(trade))) => trade,
+            Ok(Some(Err(status))) => {
+                report.check(false, || format!("SubscribeTrades stream error: {}", status));
+                break;
+            }
+            Ok(None) => {
+                report.check(false, || "SubscribeTrades stream ended early".to_string());
+                break;
+            }
+            Err(_) => break,
+        };
+        trades += 1;
+
+        let context = format!("SubscribeTrades market {} tid {}", trade.market_id, trade.tid);
+        report.check(wanted.contains(&trade.market_id), || {
+            format!("{}: trade for unsubscribed market", context)
+        });
+        report.check(trade.price > 0.0 && trade.price.is_finite() && trade.size > 0.0, || {
+            format!("{}: non-positive price or size", context)
+        });
+        report.check(trade.side == "B" || trade.side == "A", || {
+            format!("{}: invalid side {:?}", context, trade.side)
+        });
+        report.check(trade.timestamp > 0, || format!("{}: missing timestamp", context));
+        // One trade per match, not one per side of the fill
+        report.check(seen.insert((trade.market_id, trade.tid)), || {
+            format!("{}: trade sent twice", context)
+        });
+    }
+
+    println!("  {} trades", trades);
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    println!("Connecting to {}", args.endpoint);
+    let mut client = OrderbookServiceClient::connect(args.endpoint.clone()).await?;
+    let mut report = Report::default();
+
+    let markets = check_markets(&mut client, &args, &mut report).await?;
+    let cold = check_market_tiers(&mut client, &args, &mut report).await?;
+    check_orderbooks(&mut client, &args, &markets, &cold, &mut report).await?;
+    check_stream(&mut client, &args, &markets, &mut report).await?;
+    check_deltas(&mut client, &args, &markets, &mut report).await?;
+    check_trades(&mut client, &args, &markets, &mut report).await?;
+    check_stop_orders(&mut client, &args, &markets, &mut report).await?;
+    check_mark_prices(&mut client, &args, &markets, &mut report).await?;
+
+    println!(
+        "\n{} checks passed, {} skipped, {} violations",
+        report.passed,
+        report.skipped.len(),
+        report.violations.len()
+    );
+
+    if !report.violations.is_empty() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,223 @@
+//! Framed binary order files (v2).
+//!
+//! v1 files are bare 38-byte records, optionally followed by a CRC-32. With
+//! nothing to find a record boundary by, one torn write shifts every record
+//! after it. v2 files start with an 8-byte header, `HPOB` and a little-endian
+//! `u16` version of 2 then 2 reserved bytes, followed by frames:
+//!
+//! | bytes | field                                        |
+//! |-------|----------------------------------------------|
+//! | 4     | frame magic `F7 4F 52 44`                    |
+//! | 2     | payload length, little-endian                |
+//! | len   | payload: a v1 record, then any later fields  |
+//! | 4     | CRC-32 of the length and payload             |
+//!
+//! A frame that doesn't check out is skipped up to the next frame magic, so
+//! a torn write costs the records it touched and nothing after them. Files
+//! without the header are read as v1.
+
+use anyhow::{anyhow, bail, Result};
+use zerocopy::little_endian::U16;
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
+
+use crate::mmap_reader::crc32;
+
+pub const FILE_MAGIC: [u8; 4] = *b"HPOB";
+pub const FRAME_MAGIC: [u8; 4] = [0xF7, b'O', b'R', b'D'];
+pub const VERSION: u16 = 2;
+/// Longest payload accepted; anything longer is a corrupt length
+pub const MAX_PAYLOAD: usize = 1024;
+
+/// Bytes before the first frame of a v2 file
+pub const HEADER_LEN: usize = std::mem::size_of::<FileHeader>();
+const FRAME_OVERHEAD: usize = std::mem::size_of::<FrameHeader>() + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFormat {
+    /// Bare records
+    V1,
+    /// Header and framed records
+    V2,
+}
+
+impl BinaryFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryFormat::V1 => "v1",
+            BinaryFormat::V2 => "v2",
+        }
+    }
+
+    /// Where the first record starts
+    pub fn data_offset(&self) -> u64 {
+        match self {
+            BinaryFormat::V1 => 0,
+            BinaryFormat::V2 => HEADER_LEN as u64,
+        }
+    }
+
+    /// The format of a file starting with `bytes`, or `None` while it's too
+    /// short to tell
+    pub fn detect(bytes: &[u8]) -> Result<Option<Self>> {
+        let Ok((header, _)) = FileHeader::ref_from_prefix(bytes) else {
+            let partial = bytes.len().min(FILE_MAGIC.len());
+            return Ok((bytes[..partial] != FILE_MAGIC[..partial]).then_some(BinaryFormat::V1));
+        };
+        if header.magic != FILE_MAGIC {
+            return Ok(Some(BinaryFormat::V1));
+        }
+        match header.version.get() {
+            VERSION => Ok(Some(BinaryFormat::V2)),
+            version => bail!("unsupported binary order file version {}", version),
+        }
+    }
+}
+
+#[derive(FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct FileHeader {
+    magic: [u8; 4],
+    version: U16,
+    reserved: U16,
+}
+
+#[derive(FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct FrameHeader {
+    magic: [u8; 4],
+    len: U16,
+}
+
+/// The header a v2 file starts with
+pub fn file_header() -> Vec<u8> {
+    FileHeader { magic: FILE_MAGIC, version: VERSION.into(), reserved: 0.into() }.as_bytes().to_vec()
+}
+
+/// Append `payload` to `out` as one frame
+pub fn write_frame(out: &mut Vec<u8>, payload: &[u8]) {
+    assert!(payload.len() <= MAX_PAYLOAD, "payload of {} bytes", payload.len());
+    out.extend_from_slice(&FRAME_MAGIC);
+    let start = out.len();
+    out.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    out.extend_from_slice(payload);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_le_bytes());
+}
+
+/// Walks the frames in `bytes`, yielding where each ends and its payload,
+/// or the error for a stretch skipped to resynchronize. Stops at a frame
+/// that isn't all there yet, which the writer may still be finishing.
+pub struct Frames<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+pub fn frames(bytes: &[u8]) -> Frames<'_> {
+    Frames { bytes, pos: 0 }
+}
+
+impl<'a> Frames<'a> {
+    /// Check the frame at the current position and return its payload
+    /// length, or `Ok(None)` if it's cut off
+    fn frame(&self) -> Result<Option<usize>> {
+        let rest = &self.bytes[self.pos..];
+        let (header, body) = FrameHeader::ref_from_prefix(rest).map_err(|_| anyhow!("frame cut off"))?;
+        if header.magic != FRAME_MAGIC {
+            bail!("no frame magic");
+        }
+        let len = header.len.get() as usize;
+        if len > MAX_PAYLOAD {
+            bail!("frame length {} over {}", len, MAX_PAYLOAD);
+        }
+        if body.len() < len + 4 {
+            return Ok(None);
+        }
+        let stored = u32::from_le_bytes(body[len..len + 4].try_into().unwrap());
+        let computed = crc32(&rest[FRAME_MAGIC.len()..FRAME_MAGIC.len() + 2 + len]);
+        if stored != computed {
+            bail!("checksum mismatch: stored {:08x}, computed {:08x}", stored, computed);
+        }
+        Ok(Some(len))
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = (usize, Result<&'a [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.len() - self.pos < FRAME_OVERHEAD {
+            return None;
+        }
+        let error = match self.frame() {
+            Ok(Some(len)) => {
+                let start = self.pos + FRAME_OVERHEAD - 4;
+                self.pos = start + len + 4;
+                return Some((self.pos, Ok(&self.bytes[start..start + len])));
+            }
+            Ok(None) => return None,
+            Err(e) => e,
+        };
+
+        // Resynchronize on the next frame magic, keeping a tail that may be
+        // the start of one
+        let from = self.pos + 1;
+        let next = self.bytes[from..]
+            .windows(FRAME_MAGIC.len())
+            .position(|window| window == FRAME_MAGIC)
+            .map(|at| from + at)
+            .unwrap_or(self.bytes.len() + 1 - FRAME_MAGIC.len());
+        let skipped = next - self.pos;
+        self.pos = next;
+        Some((next, Err(anyhow!("skipped {} bytes to resynchronize: {}", skipped, error))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_format_from_header() {
+        let header = file_header();
+        assert_eq!(header.len() as u64, BinaryFormat::V2.data_offset());
+        assert_eq!(BinaryFormat::detect(&header).unwrap(), Some(BinaryFormat::V2));
+        assert_eq!(BinaryFormat::detect(&header[..3]).unwrap(), None);
+        assert_eq!(BinaryFormat::detect(b"").unwrap(), None);
+        assert_eq!(BinaryFormat::detect(&[0u8; 38]).unwrap(), Some(BinaryFormat::V1));
+        assert_eq!(BinaryFormat::detect(&[1, 2]).unwrap(), Some(BinaryFormat::V1));
+
+        let mut future = header;
+        future[4] = 3;
+        assert!(BinaryFormat::detect(&future).is_err());
+    }
+
+    #[test]
+    fn test_frames_resynchronize_after_torn_write() {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, b"first");
+        // A write torn partway through its payload, then the writer goes on
+        let mut torn = Vec::new();
+        write_frame(&mut torn, b"torn record");
+        bytes.extend(&torn[..9]);
+        write_frame(&mut bytes, b"second");
+        let complete = bytes.len();
+        write_frame(&mut bytes, b"in progress");
+        bytes.truncate(bytes.len() - 2);
+
+        let frames: Vec<_> = frames(&bytes).collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].1.as_ref().unwrap(), b"first");
+        let skipped = frames[1].1.as_ref().unwrap_err().to_string();
+        assert!(skipped.starts_with("skipped 9 bytes"), "{}", skipped);
+        assert_eq!(frames[2].1.as_ref().unwrap(), b"second");
+        // The unfinished frame is left for the writer
+        assert_eq!(frames[2].0, complete);
+
+        // Garbage without any frame magic is skipped but for a possible
+        // partial magic
+        let garbage = [0x55u8; 64];
+        let frames: Vec<_> = super::frames(&garbage).collect();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].0, 61);
+    }
+}
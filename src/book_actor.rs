@@ -0,0 +1,409 @@
+//! Single-writer ownership of order books.
+//!
+//! Each market's book is mutated only by its actor task. Everything else
+//! sends commands and reads the snapshots the actor publishes after each
+//! batch, so no two call sites can interleave writes to the same book.
+
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::warn;
+
+use crate::book_shards::{BookShards, ShardCounters};
+use crate::crossed_books::{CrossGuard, CrossRepair};
+use crate::fast_orderbook::{FastOrderbook, Order, SequencedDelta};
+use crate::mark_price_v2::CEXPrices;
+use crate::market_processor::MarketUpdate;
+use crate::market_scheduler::MarketScheduler;
+use crate::oid_epochs::{OidEpochs, OpenCheck};
+use crate::pipeline_latency::{PipelineLatency, Stage};
+use crate::publish_batching::BatchWindows;
+use crate::virtual_time::VirtualClock;
+use crate::watermarks::WatermarkTracker;
+
+/// Commands queued per market; a full mailbox applies backpressure to the feed
+const MAILBOX_SIZE: usize = 65_536;
+
+/// Commands applied before publishing one snapshot and one update
+const MAX_BATCH: usize = 256;
+
+#[derive(Debug)]
+pub enum BookCommand {
+    Add { order: Order, is_buy: bool },
+    /// Cancels and fills, found by id wherever the order rests
+    Remove { order_id: u64 },
+    /// A new size or price for a resting order
+    Modify { order_id: u64, price: f64, size: f64 },
+    OraclePrice(f64),
+    CexPrices(CEXPrices),
+    LastTrade(f64),
+    /// Acknowledged once every earlier command has been applied and published
+    Barrier(oneshot::Sender<()>),
+}
+
+/// Sending side of one book's actor
+#[derive(Clone)]
+pub struct BookHandle {
+    market_id: u32,
+    tx: mpsc::Sender<BookCommand>,
+}
+
+impl BookHandle {
+    pub async fn send(&self, command: BookCommand) -> Result<()> {
+        self.tx
+            .send(command)
+            .await
+            .map_err(|_| anyhow!("Book actor for market {} stopped", self.market_id))
+    }
+}
+
+/// How actors repair their books, where they run and what they time
+#[derive(Clone)]
+pub struct ActorOptions {
+    pub crossed_repair: CrossRepair,
+    /// Dedicated threads; `None` runs actors as tasks of the current runtime
+    pub shards: Option<Arc<BookShards>>,
+    /// Where applied and published opens are timed
+    pub latency: Option<Arc<PipelineLatency>>,
+    /// Replay time: updates are stamped with it and published only at
+    /// barriers, never by a batching window, so runs publish alike
+    pub clock: Option<Arc<VirtualClock>>,
+}
+
+impl Default for ActorOptions {
+    fn default() -> Self {
+        Self { crossed_repair: CrossRepair::Older, shards: None, latency: None, clock: None }
+    }
+}
+
+pub struct BookActors {
+    handles: RwLock<HashMap<u32, BookHandle>>,
+    update_tx: broadcast::Sender<MarketUpdate>,
+    watermarks: Arc<WatermarkTracker>,
+    scheduler: Arc<MarketScheduler>,
+    batching: Arc<BatchWindows>,
+    options: ActorOptions,
+}
+
+impl BookActors {
+    /// Spawn one actor per book. Actors publish a `MarketUpdate` per applied
+    /// batch, or per batching window where `batching` sets one, report
+    /// applied opens to `watermarks`, process each batch within the budget
+    /// `scheduler` grants their market, and otherwise run as `options` say.
+    pub fn spawn(
+        orderbooks: &HashMap<u32, Arc<FastOrderbook>>,
+        update_tx: broadcast::Sender<MarketUpdate>,
+        watermarks: Arc<WatermarkTracker>,
+        scheduler: Arc<MarketScheduler>,
+        batching: Arc<BatchWindows>,
+        options: ActorOptions,
+    ) -> Self {
+        let actors = Self {
+            handles: RwLock::new(HashMap::new()),
+            update_tx,
+            watermarks,
+            scheduler,
+            batching,
+            options,
+        };
+        for orderbook in orderbooks.values() {
+            actors.add(orderbook.clone());
+        }
+        actors
+    }
+
+    /// Spawn the actor of a book listed after startup
+    pub fn add(&self, orderbook: Arc<FastOrderbook>) {
+        let (tx, rx) = mpsc::channel(MAILBOX_SIZE);
+        let market_id = orderbook.market_id;
+        let publisher = Publisher {
+            orderbook,
+            update_tx: self.update_tx.clone(),
+            watermarks: self.watermarks.clone(),
+            batching: self.batching.clone(),
+            latency: self.options.latency.clone(),
+            clock: self.options.clock.clone(),
+            deltas: Vec::new(),
+            opens: Vec::new(),
+        };
+        let (scheduler, crossing) = (self.scheduler.clone(), CrossGuard::new(self.options.crossed_repair));
+        match &self.options.shards {
+            Some(shards) => shards.spawn(market_id, &tx, |counters| run(publisher, rx, scheduler, crossing, Some(counters))),
+            None => {
+                tokio::spawn(run(publisher, rx, scheduler, crossing, None));
+            }
+        }
+        self.handles.write().insert(market_id, BookHandle { market_id, tx });
+    }
+
+    /// Stop routing to a book. Its actor applies what was already sent and
+    /// stops once the last handle is dropped.
+    pub fn retire(&self, market_id: u32) -> bool {
+        self.handles.write().remove(&market_id).is_some()
+    }
+
+    pub fn handle(&self, market_id: u32) -> Option<BookHandle> {
+        self.handles.read().get(&market_id).cloned()
+    }
+
+    /// Wait until every book has applied everything sent before this call
+    pub async fn barrier(&self) {
+        // Queue every barrier before waiting, so the books drain concurrently
+        let handles: Vec<BookHandle> = self.handles.read().values().cloned().collect();
+        let mut acks = Vec::with_capacity(handles.len());
+        for handle in &handles {
+            let (ack_tx, ack_rx) = oneshot::channel();
+            match handle.send(BookCommand::Barrier(ack_tx)).await {
+                Ok(()) => acks.push(ack_rx),
+                Err(e) => warn!("Barrier incomplete: {}", e),
+            }
+        }
+        for ack in acks {
+            let _ = ack.await;
+        }
+    }
+}
+
+/// Applied changes not yet visible to readers, and where they go
+struct Publisher {
+    orderbook: Arc<FastOrderbook>,
+    update_tx: broadcast::Sender<MarketUpdate>,
+    watermarks: Arc<WatermarkTracker>,
+    batching: Arc<BatchWindows>,
+    latency: Option<Arc<PipelineLatency>>,
+    clock: Option<Arc<VirtualClock>>,
+    deltas: Vec<SequencedDelta>,
+    /// Node timestamps of the orders opened by the held deltas
+    opens: Vec<u64>,
+}
+
+impl Publisher {
+    /// Publish the snapshot and one update covering every held delta.
+    /// Readers see the whole batch or none of it.
+    fn flush(&mut self) {
+        if !self.deltas.is_empty() {
+            self.orderbook.publish();
+            self.batching.record(self.orderbook.market_id, self.deltas.len());
+            let timestamp_ns = match &self.clock {
+                Some(clock) => clock.now_ns(),
+                None => std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64,
+            };
+            if let Some(mut update) = MarketUpdate::from_deltas(self.orderbook.market_id, timestamp_ns, std::mem::take(&mut self.deltas)) {
+                // The snapshot just published is the book as of the update
+                update.checksum = Some(self.orderbook.snapshot().checksum);
+                update.event_ms = self.opens.iter().min().copied();
+                let _ = self.update_tx.send(update);
+            }
+            if let Some(latency) = &self.latency {
+                latency.record(self.orderbook.market_id, Stage::Published, &self.opens);
+            }
+        }
+        for timestamp_ms in self.opens.drain(..) {
+            self.watermarks.observe_applied(timestamp_ms, true);
+        }
+    }
+}
+
+async fn run(
+    mut publisher: Publisher,
+    mut rx: mpsc::Receiver<BookCommand>,
+    scheduler: Arc<MarketScheduler>,
+    mut crossing: CrossGuard,
+    shard: Option<Arc<ShardCounters>>,
+) {
+    let orderbook = publisher.orderbook.clone();
+    let window = publisher.batching.window(orderbook.market_id);
+    let mut batch = Vec::with_capacity(MAX_BATCH);
+    let mut barriers = Vec::new();
+    let mut oids = OidEpochs::from_book(&orderbook);
+    // When the held deltas are due, while a batching window is open
+    let mut flush_at: Option<tokio::time::Instant> = None;
+
+    loop {
+        let received = match flush_at {
+            Some(deadline) => tokio::select! {
+                received = rx.recv_many(&mut batch, MAX_BATCH) => received,
+                _ = tokio::time::sleep_until(deadline) => {
+                    publisher.flush();
+                    flush_at = None;
+                    continue;
+                }
+            },
+            None => rx.recv_many(&mut batch, MAX_BATCH).await,
+        };
+        if received == 0 {
+            break;
+        }
+        
+        scheduler.admit(orderbook.market_id, batch.len() + rx.len()).await;
+        let started = tokio::time::Instant::now();
+        let commands = batch.len();
+        let opened = publisher.opens.len();
+        
+        for command in batch.drain(..) {
+            match command {
+                BookCommand::Add { order, is_buy } => {
+                    match oids.open(&order, is_buy, &orderbook.integrity) {
+                        OpenCheck::New => {}
+                        OpenCheck::Duplicate => continue,
+                        OpenCheck::Reused(previous) => {
+                            warn!(
+                                "Market {}: oid {} reused (first seen {}ms, now {}ms); replacing the old order",
+                                orderbook.market_id, order.id, previous.first_seen_ms, order.timestamp
+                            );
+                            publisher.deltas.extend(orderbook.remove_order(order.id));
+                        }
+                    }
+                    publisher.deltas.push(orderbook.add_order(order, is_buy));
+                    publisher.opens.push(order.timestamp);
+                }
+                BookCommand::Remove { order_id } => {
+                    let removed = orderbook.remove_order(order_id);
+                    if removed.is_some() {
+                        oids.close(order_id);
+                    }
+                    publisher.deltas.extend(removed);
+                }
+                BookCommand::Modify { order_id, price, size } => {
+                    let modified = orderbook.modify_order(order_id, size, price);
+                    if !modified.is_empty() {
+                        oids.modified(order_id, price);
+                    }
+                    publisher.deltas.extend(modified);
+                }
+                BookCommand::OraclePrice(price) => orderbook.update_oracle_price(price),
+                BookCommand::CexPrices(prices) => orderbook.update_cex_prices(prices),
+                BookCommand::LastTrade(price) => orderbook.update_last_trade(price),
+                BookCommand::Barrier(ack) => barriers.push(ack),
+            }
+        }
+        
+        if let Some(latency) = &publisher.latency {
+            latency.record(orderbook.market_id, Stage::Applied, &publisher.opens[opened..]);
+        }
+        
+        // Readers never see a crossing the repair removes
+        for (order_id, evicted) in crossing.check(&orderbook) {
+            oids.close(order_id);
+            publisher.deltas.push(evicted);
+        }
+
+        if flush_at.is_none() && !publisher.deltas.is_empty() && publisher.clock.is_none() {
+            flush_at = Some(started + window);
+        }
+        // Barriers promise everything before them is published
+        if !barriers.is_empty() || flush_at.is_some_and(|deadline| deadline <= tokio::time::Instant::now()) {
+            publisher.flush();
+            flush_at = None;
+        }
+        for ack in barriers.drain(..) {
+            let _ = ack.send(());
+        }
+        
+        scheduler.charge(orderbook.market_id, started.elapsed());
+        if let Some(shard) = &shard {
+            shard.record(commands, started.elapsed());
+        }
+        // Let other markets' actors run before taking the next batch
+        if !rx.is_empty() {
+            tokio::task::yield_now().await;
+        }
+    }
+    publisher.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_scheduler::SchedulerConfig;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_actor_applies_in_order_and_publishes() {
+        let orderbook = Arc::new(FastOrderbook::new(3, "BTC".to_string()));
+        let (update_tx, mut update_rx) = broadcast::channel(16);
+        let watermarks = Arc::new(WatermarkTracker::new(HashMap::new(), Duration::ZERO));
+        let scheduler = Arc::new(MarketScheduler::new(SchedulerConfig::default()));
+        let latency = Arc::new(PipelineLatency::new());
+        let actors = BookActors::spawn(
+            &[(3, orderbook.clone())].into_iter().collect(),
+            update_tx,
+            watermarks,
+            scheduler.clone(),
+            Arc::default(),
+            ActorOptions { latency: Some(latency.clone()), ..Default::default() },
+        );
+        let book = actors.handle(3).unwrap();
+
+        book.send(BookCommand::Add { order: Order { id: 1, price: 100.0, size: 1.0, timestamp: 5 }, is_buy: true })
+            .await
+            .unwrap();
+        book.send(BookCommand::Add { order: Order { id: 2, price: 101.0, size: 2.0, timestamp: 6 }, is_buy: false })
+            .await
+            .unwrap();
+        book.send(BookCommand::Remove { order_id: 1 }).await.unwrap();
+        actors.barrier().await;
+
+        let snapshot = orderbook.snapshot();
+        assert_eq!(snapshot.sequence, 3);
+        assert!(snapshot.bids.is_empty());
+        assert_eq!(snapshot.asks, vec![(101.0, 2.0)]);
+
+        // Updates cover every delta exactly once, in order
+        let mut sequences = Vec::new();
+        let mut checksum = None;
+        let mut event_ms = None;
+        while let Ok(update) = update_rx.try_recv() {
+            sequences.extend(update.deltas.iter().map(|d| d.sequence));
+            checksum = update.checksum;
+            event_ms = event_ms.or(update.event_ms);
+        }
+        assert_eq!(sequences, vec![1, 2, 3]);
+        assert_eq!(checksum, Some(snapshot.checksum));
+        assert_eq!(event_ms, Some(5));
+        
+        // Both opens were timed as applied and as published
+        let stages = &latency.markets(&[3])[0].stages;
+        assert_eq!(stages.iter().map(|stage| stage.count).collect::<Vec<_>>(), vec![2, 2, 0]);
+        
+        let timings = scheduler.timings();
+        assert_eq!(timings[0].market_id, 3);
+        assert!(timings[0].batches >= 1);
+    }
+
+    #[tokio::test]
+    async fn test_batching_window_coalesces_updates() {
+        let orderbook = Arc::new(FastOrderbook::new(3, "BTC".to_string()));
+        let (update_tx, mut update_rx) = broadcast::channel(16);
+        let watermarks = Arc::new(WatermarkTracker::new(HashMap::new(), Duration::ZERO));
+        let scheduler = Arc::new(MarketScheduler::new(SchedulerConfig::default()));
+        let batching = Arc::new(BatchWindows::new(Duration::from_millis(200), HashMap::new()));
+        let actors = BookActors::spawn(&[(3, orderbook.clone())].into_iter().collect(), update_tx, watermarks, scheduler, batching.clone(), ActorOptions::default());
+        let book = actors.handle(3).unwrap();
+
+        for id in 1..=3 {
+            book.send(BookCommand::Add { order: Order { id, price: 100.0, size: 1.0, timestamp: 0 }, is_buy: true })
+                .await
+                .unwrap();
+            tokio::task::yield_now().await;
+        }
+        // Held until the window closes, book and update alike
+        assert!(update_rx.try_recv().is_err());
+        assert_eq!(orderbook.snapshot().sequence, 0);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let update = update_rx.try_recv().unwrap();
+        assert_eq!(update.deltas.iter().map(|d| d.sequence).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(orderbook.snapshot().sequence, 3);
+
+        // A barrier doesn't wait for the window
+        book.send(BookCommand::Remove { order_id: 1 }).await.unwrap();
+        actors.barrier().await;
+        assert_eq!(update_rx.try_recv().unwrap().sequence, 4);
+
+        let stats = batching.stats(3);
+        assert_eq!((stats.updates, stats.deltas, stats.max_deltas), (2, 4, 3));
+    }
+}
@@ -0,0 +1,272 @@
+//! Cross-validation of local books against Hyperliquid's public L2 API.
+//!
+//! A background task fetches `l2Book` for each tracked market in turn and
+//! diffs it against the published book: levels the API has and the book
+//! lacks, levels the book has within the API's range that the API lacks,
+//! and the size drift of levels both have. The two books are taken moments
+//! apart, so small drift on busy markets is expected; persistent missing
+//! levels or large drift mean the book has diverged.
+
+use anyhow::Result;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::fast_orderbook::{BookSnapshot, FastOrderbook};
+use crate::price_ticks::{TickSize, Ticks};
+
+const API_URL: &str = "https://api.hyperliquid.xyz/info";
+
+/// Levels per side `l2Book` returns at most
+pub const API_LEVELS: usize = 20;
+
+/// How far one side of the local book is from the API's
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LevelDiff {
+    /// API levels compared
+    pub compared: usize,
+    /// API levels the local book lacks
+    pub missing: usize,
+    /// Local levels within the API's range that the API lacks
+    pub extra: usize,
+    /// Mean and largest size difference of levels both have, in bps of the
+    /// API size
+    pub mean_drift_bps: f64,
+    pub max_drift_bps: f64,
+}
+
+impl LevelDiff {
+    fn merge(self, other: LevelDiff) -> LevelDiff {
+        let matched = |diff: &LevelDiff| (diff.compared - diff.missing) as f64;
+        let both = matched(&self) + matched(&other);
+        LevelDiff {
+            compared: self.compared + other.compared,
+            missing: self.missing + other.missing,
+            extra: self.extra + other.extra,
+            mean_drift_bps: if both > 0.0 {
+                (self.mean_drift_bps * matched(&self) + other.mean_drift_bps * matched(&other)) / both
+            } else {
+                0.0
+            },
+            max_drift_bps: self.max_drift_bps.max(other.max_drift_bps),
+        }
+    }
+}
+
+/// Diff one side, both listed best first. A full page of API levels only
+/// covers prices down to its last level; beyond that the API is silent.
+fn diff_side(api: &[(f64, f64)], local: &[(f64, f64)], tick_size: TickSize, is_bid: bool) -> LevelDiff {
+    let ticks = |price: f64| {
+        let ticks = tick_size.to_ticks(price);
+        // Better prices compare greater on both sides
+        if is_bid { ticks } else { -ticks }
+    };
+    let worst = (api.len() >= API_LEVELS).then(|| api.last().map(|(price, _)| ticks(*price))).flatten();
+    let mut local: HashMap<Ticks, f64> = local
+        .iter()
+        .filter(|(price, _)| worst.is_none_or(|worst| ticks(*price) >= worst))
+        .map(|(price, size)| (ticks(*price), *size))
+        .collect();
+
+    let mut diff = LevelDiff { compared: api.len(), ..Default::default() };
+    let mut total_drift = 0.0;
+    for (price, api_size) in api {
+        match local.remove(&ticks(*price)) {
+            Some(size) => {
+                let drift = if *api_size > 0.0 { (size - api_size).abs() / api_size * 10_000.0 } else { 0.0 };
+                total_drift += drift;
+                diff.max_drift_bps = diff.max_drift_bps.max(drift);
+            }
+            None => diff.missing += 1,
+        }
+    }
+    diff.extra = local.len();
+    let matched = diff.compared - diff.missing;
+    if matched > 0 {
+        diff.mean_drift_bps = total_drift / matched as f64;
+    }
+    diff
+}
+
+/// Both sides of `local` against the API's book
+pub fn diff_book(api_bids: &[(f64, f64)], api_asks: &[(f64, f64)], local: &BookSnapshot, tick_size: TickSize) -> LevelDiff {
+    diff_side(api_bids, &local.bids, tick_size, true).merge(diff_side(api_asks, &local.asks, tick_size, false))
+}
+
+/// The latest check of one market
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BookDrift {
+    pub market_id: u32,
+    /// Time of the API's book, ms since epoch
+    pub checked_ms: u64,
+    /// Sequence of the local book compared
+    pub sequence: u64,
+    pub diff: LevelDiff,
+    /// Any level missing or extra, or drift over the threshold
+    pub divergent: bool,
+    pub checks: u64,
+    pub divergent_checks: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct L2Book {
+    time: u64,
+    levels: Vec<Vec<L2Level>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct L2Level {
+    px: String,
+    sz: String,
+}
+
+fn parse_levels(levels: Option<&Vec<L2Level>>) -> Vec<(f64, f64)> {
+    levels
+        .into_iter()
+        .flatten()
+        .filter_map(|level| Some((level.px.parse().ok()?, level.sz.parse().ok()?)))
+        .collect()
+}
+
+pub struct BookDriftMonitor {
+    threshold_bps: f64,
+    markets: RwLock<HashMap<u32, BookDrift>>,
+}
+
+impl BookDriftMonitor {
+    /// Checks count as divergent once a level's size drifts `threshold_bps`
+    pub fn new(threshold_bps: f64) -> Self {
+        Self { threshold_bps, markets: RwLock::new(HashMap::new()) }
+    }
+
+    /// Record a check of `orderbook` against the API's levels at `checked_ms`
+    pub fn record(
+        &self,
+        orderbook: &FastOrderbook,
+        api_bids: &[(f64, f64)],
+        api_asks: &[(f64, f64)],
+        checked_ms: u64,
+    ) -> BookDrift {
+        let local = orderbook.snapshot();
+        let diff = diff_book(api_bids, api_asks, &local, orderbook.tick_size());
+        let divergent = diff.missing > 0 || diff.extra > 0 || diff.max_drift_bps > self.threshold_bps;
+
+        let mut markets = self.markets.write();
+        let drift = markets.entry(orderbook.market_id).or_insert(BookDrift { market_id: orderbook.market_id, ..Default::default() });
+        drift.checked_ms = checked_ms;
+        drift.sequence = local.sequence;
+        drift.diff = diff;
+        drift.divergent = divergent;
+        drift.checks += 1;
+        drift.divergent_checks += divergent as u64;
+        *drift
+    }
+
+    pub fn get(&self, market_id: u32) -> Option<BookDrift> {
+        self.markets.read().get(&market_id).copied()
+    }
+
+    /// Every checked market, by id
+    pub fn all(&self) -> Vec<BookDrift> {
+        let mut markets: Vec<_> = self.markets.read().values().copied().collect();
+        markets.sort_by_key(|drift| drift.market_id);
+        markets
+    }
+
+    /// Check each of `coins` (market id to Hyperliquid coin) every
+    /// `interval`, one market at a time
+    pub fn start(
+        self: &Arc<Self>,
+        orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+        coins: HashMap<u32, String>,
+        interval: Duration,
+    ) {
+        let monitor = self.clone();
+        let mut coins: Vec<(u32, String)> = coins.into_iter().filter(|(id, _)| orderbooks.contains_key(id)).collect();
+        coins.sort();
+        info!("Cross-checking {} books against the L2 API every {:?}", coins.len(), interval);
+
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let mut divergent = 0;
+                for (market_id, coin) in &coins {
+                    let book = match Self::fetch(&client, coin).await {
+                        Ok(book) => book,
+                        Err(e) => {
+                            error!("Failed to fetch the L2 book of {}: {}", coin, e);
+                            continue;
+                        }
+                    };
+                    let (bids, asks) = (parse_levels(book.levels.first()), parse_levels(book.levels.get(1)));
+                    let drift = monitor.record(&orderbooks[market_id], &bids, &asks, book.time);
+                    let diff = drift.diff;
+                    if drift.divergent {
+                        divergent += 1;
+                        warn!(
+                            "Book {} diverges from the L2 API at sequence {}: {} of {} levels missing, {} extra, size drift {:.1} bps mean, {:.1} max",
+                            coin, drift.sequence, diff.missing, diff.compared, diff.extra, diff.mean_drift_bps, diff.max_drift_bps
+                        );
+                    } else {
+                        debug!("Book {} matches the L2 API at sequence {}", coin, drift.sequence);
+                    }
+                }
+                info!("Cross-checked {} books against the L2 API, {} divergent", coins.len(), divergent);
+            }
+        });
+    }
+
+    async fn fetch(client: &reqwest::Client, coin: &str) -> Result<L2Book> {
+        Ok(client
+            .post(API_URL)
+            .json(&serde_json::json!({"type": "l2Book", "coin": coin}))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::Order;
+
+    #[test]
+    fn test_books_are_diffed_within_the_api_range() {
+        let book = FastOrderbook::with_tick_size(0, "BTC".to_string(), TickSize::new(0.5).unwrap());
+        let orders = [(100.0, 2.0, true), (99.0, 1.0, true), (98.0, 1.0, true), (101.0, 1.1, false)];
+        for (id, (price, size, is_buy)) in orders.into_iter().enumerate() {
+            book.add_order(Order { id: id as u64, price, size, timestamp: 0 }, is_buy);
+        }
+        book.publish();
+
+        // 99.5 is missing locally; 99 isn't in the API's book; ask size drifts 10%
+        let bids = [(100.0, 2.0), (99.5, 1.0), (98.0, 1.0)];
+        let asks = [(101.0, 1.0)];
+        let diff = diff_book(&bids, &asks, &book.snapshot(), book.tick_size());
+        assert_eq!((diff.compared, diff.missing, diff.extra), (4, 1, 1));
+        assert!((diff.max_drift_bps - 1000.0).abs() < 1e-6);
+        assert!((diff.mean_drift_bps - 1000.0 / 3.0).abs() < 1e-6);
+
+        // A full page of API levels says nothing about prices beyond it
+        let page: Vec<(f64, f64)> = (0..API_LEVELS).map(|i| (130.0 - i as f64 * 0.5, 1.0)).collect();
+        let diff = diff_side(&page, &[(130.0, 1.0), (98.0, 1.0)], book.tick_size(), true);
+        assert_eq!((diff.missing, diff.extra), (API_LEVELS - 1, 0));
+
+        let monitor = BookDriftMonitor::new(500.0);
+        let drift = monitor.record(&book, &bids, &asks, 7);
+        assert!(drift.divergent);
+        let drift = monitor.record(&book, &[(100.0, 2.0), (99.0, 1.0), (98.0, 1.0)], &[(101.0, 1.1)], 8);
+        assert!(!drift.divergent);
+        assert_eq!((drift.checks, drift.divergent_checks, drift.checked_ms), (2, 1, 8));
+        assert_eq!(monitor.all(), vec![drift]);
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::degradation::DegradationMonitor;
+use crate::fast_orderbook::{BookSnapshot, FastOrderbook};
+use crate::market_processor::MarketUpdate;
+use crate::market_tiers::MarketTiers;
+use crate::quote_stuffing::QuoteStuffingFilter;
+
+/// Levels per side that volume imbalance is reported over
+pub const IMBALANCE_LEVELS: [usize; 3] = [1, 5, 10];
+
+/// Distances from the mid, in basis points, that depth is reported within
+pub const DEPTH_BANDS_BPS: [u32; 4] = [10, 25, 50, 100];
+
+/// Resting size on each side of the top `levels` levels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelImbalance {
+    pub levels: usize,
+    pub bid_size: f64,
+    pub ask_size: f64,
+}
+
+impl LevelImbalance {
+    /// `(bid - ask) / (bid + ask)`, from -1 (all asks) to 1 (all bids)
+    pub fn imbalance(&self) -> f64 {
+        (self.bid_size - self.ask_size) / (self.bid_size + self.ask_size)
+    }
+}
+
+/// Resting size on each side within `bps` of the mid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandDepth {
+    pub bps: u32,
+    pub bid_size: f64,
+    pub ask_size: f64,
+}
+
+/// Shape of one published book
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookMetrics {
+    pub market_id: u32,
+    pub sequence: u64,
+    pub timestamp_us: i64,
+    pub mid: f64,
+    pub spread_bps: f64,
+    pub microprice: Option<f64>,
+    pub depth_weighted_mid: Option<f64>,
+    pub imbalances: Vec<LevelImbalance>,
+    pub depths: Vec<BandDepth>,
+}
+
+impl BookMetrics {
+    /// `None` while either side is empty
+    pub fn compute(market_id: u32, book: &BookSnapshot, timestamp_us: i64) -> Option<Self> {
+        let (best_bid, best_ask) = (book.bids.first()?.0, book.asks.first()?.0);
+        let mid = (best_bid + best_ask) / 2.0;
+        let size = |levels: &[(f64, f64)]| levels.iter().map(|(_, size)| size).sum::<f64>();
+
+        let imbalances = IMBALANCE_LEVELS
+            .iter()
+            .map(|&levels| LevelImbalance {
+                levels,
+                bid_size: size(&book.bids[..levels.min(book.bids.len())]),
+                ask_size: size(&book.asks[..levels.min(book.asks.len())]),
+            })
+            .collect();
+
+        // Levels are sorted best first, so each side stops at the band edge
+        let depths = DEPTH_BANDS_BPS
+            .iter()
+            .map(|&bps| {
+                let offset = mid * bps as f64 / 10_000.0;
+                let within = |levels: &[(f64, f64)], inside: &dyn Fn(f64) -> bool| {
+                    levels.iter().take_while(|(price, _)| inside(*price)).map(|(_, size)| size).sum::<f64>()
+                };
+                BandDepth {
+                    bps,
+                    bid_size: within(&book.bids, &|price| price >= mid - offset),
+                    ask_size: within(&book.asks, &|price| price <= mid + offset),
+                }
+            })
+            .collect();
+
+        Some(Self {
+            market_id,
+            sequence: book.sequence,
+            timestamp_us,
+            mid,
+            spread_bps: (best_ask - best_bid) / mid * 10_000.0,
+            microprice: book.microprice,
+            depth_weighted_mid: book.depth_weighted_mid,
+            imbalances,
+            depths,
+        })
+    }
+}
+
+/// Computes metrics for hot markets from every published update
+pub struct BookMetricsEngine {
+    output_tx: broadcast::Sender<Arc<BookMetrics>>,
+}
+
+impl Default for BookMetricsEngine {
+    fn default() -> Self {
+        Self { output_tx: broadcast::channel(10_000).0 }
+    }
+}
+
+impl BookMetricsEngine {
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<BookMetrics>> {
+        self.output_tx.subscribe()
+    }
+
+    pub fn start(
+        self: Arc<Self>,
+        orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+        mut update_rx: broadcast::Receiver<MarketUpdate>,
+        market_tiers: Arc<MarketTiers>,
+        degradation: Arc<DegradationMonitor>,
+        exclude_stuffing: Option<Arc<QuoteStuffingFilter>>,
+    ) {
+        info!(
+            "Starting book metrics (imbalance over {:?} levels, depth within {:?} bps)",
+            IMBALANCE_LEVELS, DEPTH_BANDS_BPS
+        );
+
+        tokio::spawn(async move {
+            loop {
+                let update = match update_rx.recv().await {
+                    Ok(update) => update,
+                    // Only the latest book matters
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                // Like other analytics, hot markets only and paused while degraded
+                if self.output_tx.receiver_count() == 0
+                    || !market_tiers.is_hot(update.market_id)
+                    || degradation.is_degraded()
+                {
+                    continue;
+                }
+
+                let Some(orderbook) = orderbooks.get(&update.market_id) else { continue };
+                let timestamp_us = (update.timestamp_ns / 1000) as i64;
+                let published = orderbook.snapshot();
+                let unstuffed = exclude_stuffing.as_ref().and_then(|stuffing| stuffing.without_flagged(update.market_id, &published));
+                if let Some(metrics) = BookMetrics::compute(update.market_id, unstuffed.as_ref().unwrap_or(&published), timestamp_us) {
+                    let _ = self.output_tx.send(Arc::new(metrics));
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_imbalance_and_band_depth() {
+        let book = BookSnapshot::new(
+            9,
+            vec![(99.95, 2.0), (99.9, 1.0), (99.5, 4.0), (98.0, 10.0)],
+            vec![(100.05, 1.0), (100.2, 3.0), (101.5, 5.0)],
+        );
+        let metrics = BookMetrics::compute(0, &book, 0).unwrap();
+
+        assert_eq!((metrics.mid, metrics.sequence), (100.0, 9));
+        assert!((metrics.spread_bps - 10.0).abs() < 1e-6);
+
+        let top = metrics.imbalances[0];
+        assert_eq!((top.levels, top.bid_size, top.ask_size), (1, 2.0, 1.0));
+        assert!((top.imbalance() - 1.0 / 3.0).abs() < 1e-9);
+        // Fewer levels than asked for counts what there is
+        assert_eq!((metrics.imbalances[1].bid_size, metrics.imbalances[1].ask_size), (17.0, 9.0));
+
+        // 10bps of 100 is 0.1: 99.9..100.1
+        assert_eq!(metrics.depths[0], BandDepth { bps: 10, bid_size: 3.0, ask_size: 1.0 });
+        // 50bps: 99.5..100.5
+        assert_eq!(metrics.depths[2], BandDepth { bps: 50, bid_size: 7.0, ask_size: 4.0 });
+
+        assert!(BookMetrics::compute(0, &BookSnapshot::new(1, vec![(99.0, 1.0)], vec![]), 0).is_none());
+    }
+}
@@ -0,0 +1,211 @@
+//! Book actors on dedicated, pinned threads.
+//!
+//! By default every book's actor is a task on the shared runtime, where in a
+//! burst ~190 actors compete with the feed reader, streams and analytics for
+//! the same workers. With shards, each actor runs on one of N threads, each
+//! with its own single-threaded runtime and pinned to a core. A market's
+//! shard is a consistent hash of its id: it keeps its shard as other markets
+//! are listed and delisted, and only about 1/N of markets move when N changes.
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+use crate::book_actor::BookCommand;
+
+/// Which of `shards` shards `market_id` belongs to: jump consistent hashing
+/// (Lamping and Veach) of the scrambled id
+pub fn shard_of(market_id: u32, shards: usize) -> usize {
+    // splitmix64, so neighbouring ids don't start from neighbouring seeds
+    let mut key = (market_id as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    key = (key ^ (key >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    key = (key ^ (key >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    key ^= key >> 31;
+
+    let (mut bucket, mut next) = (0i64, 0i64);
+    while next < shards as i64 {
+        bucket = next;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    bucket as usize
+}
+
+/// Work one shard's actors have done
+#[derive(Debug, Default)]
+pub struct ShardCounters {
+    batches: AtomicU64,
+    commands: AtomicU64,
+    busy_ns: AtomicU64,
+}
+
+impl ShardCounters {
+    pub fn record(&self, commands: usize, busy: Duration) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.commands.fetch_add(commands as u64, Ordering::Relaxed);
+        self.busy_ns.fetch_add(busy.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardStats {
+    pub shard: usize,
+    /// The core the thread is pinned to, if it could be
+    pub core: Option<usize>,
+    pub markets: usize,
+    /// Commands waiting in the shard's mailboxes
+    pub queued: usize,
+    pub batches: u64,
+    pub commands: u64,
+    /// Time spent applying batches
+    pub busy_ms: u64,
+}
+
+struct Shard {
+    runtime: tokio::runtime::Handle,
+    core: Option<usize>,
+    counters: Arc<ShardCounters>,
+    /// Each market's mailbox, weak so a retired book's actor still stops
+    mailboxes: Mutex<HashMap<u32, mpsc::WeakSender<BookCommand>>>,
+    /// Dropping it stops the thread
+    _stop: oneshot::Sender<()>,
+}
+
+pub struct BookShards {
+    shards: Vec<Shard>,
+}
+
+impl BookShards {
+    /// Start `count` threads, each pinned to its own core while there are
+    /// cores left to go around
+    pub fn start(count: usize) -> Result<Self> {
+        let cores = core_affinity::get_core_ids().unwrap_or_default();
+        let mut shards = Vec::with_capacity(count);
+        for shard in 0..count {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            let core = (!cores.is_empty()).then(|| cores[shard % cores.len()]);
+            let (stop_tx, stop_rx) = oneshot::channel::<()>();
+            let handle = runtime.handle().clone();
+            std::thread::Builder::new().name(format!("book-shard-{}", shard)).spawn(move || {
+                if let Some(core) = core {
+                    if !core_affinity::set_for_current(core) {
+                        warn!("Couldn't pin book shard {} to core {}", shard, core.id);
+                    }
+                }
+                let _ = runtime.block_on(stop_rx);
+            })?;
+            shards.push(Shard {
+                runtime: handle,
+                core: core.map(|core| core.id),
+                counters: Arc::default(),
+                mailboxes: Mutex::new(HashMap::new()),
+                _stop: stop_tx,
+            });
+        }
+        info!("Running book actors on {} shards", count);
+        Ok(Self { shards })
+    }
+
+    /// Run `actor`, given its shard's counters, on the shard of `market_id`
+    /// with mailbox `tx`
+    pub fn spawn<F, Fut>(&self, market_id: u32, tx: &mpsc::Sender<BookCommand>, actor: F)
+    where
+        F: FnOnce(Arc<ShardCounters>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let shard = &self.shards[shard_of(market_id, self.shards.len())];
+        shard.mailboxes.lock().insert(market_id, tx.downgrade());
+        shard.runtime.spawn(actor(shard.counters.clone()));
+    }
+
+    pub fn stats(&self) -> Vec<ShardStats> {
+        self.shards
+            .iter()
+            .enumerate()
+            .map(|(i, shard)| {
+                let mut mailboxes = shard.mailboxes.lock();
+                // Retired books' mailboxes are gone once their actors stop
+                mailboxes.retain(|_, tx| tx.strong_count() > 0);
+                let queued = mailboxes
+                    .values()
+                    .filter_map(|tx| tx.upgrade())
+                    .map(|tx| tx.max_capacity() - tx.capacity())
+                    .sum();
+                ShardStats {
+                    shard: i,
+                    core: shard.core,
+                    markets: mailboxes.len(),
+                    queued,
+                    batches: shard.counters.batches.load(Ordering::Relaxed),
+                    commands: shard.counters.commands.load(Ordering::Relaxed),
+                    busy_ms: shard.counters.busy_ns.load(Ordering::Relaxed) / 1_000_000,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_actor::{ActorOptions, BookActors};
+    use crate::fast_orderbook::{FastOrderbook, Order};
+    use crate::market_scheduler::{MarketScheduler, SchedulerConfig};
+    use crate::watermarks::WatermarkTracker;
+    use tokio::sync::broadcast;
+
+    #[test]
+    fn test_markets_hash_consistently() {
+        let mut counts = [0; 4];
+        for market_id in 0..200 {
+            let shard = shard_of(market_id, 4);
+            counts[shard] += 1;
+            // Growing to five shards only moves markets onto the new one
+            let grown = shard_of(market_id, 5);
+            assert!(grown == shard || grown == 4);
+            assert_eq!(shard_of(market_id, 1), 0);
+        }
+        assert!(counts.iter().all(|&count| (30..=70).contains(&count)), "{:?}", counts);
+    }
+
+    #[tokio::test]
+    async fn test_actors_run_on_their_shards() {
+        let shards = Arc::new(BookShards::start(2).unwrap());
+        let orderbooks: HashMap<u32, Arc<FastOrderbook>> =
+            (0..8).map(|id| (id, Arc::new(FastOrderbook::new(id, format!("M{}", id))))).collect();
+        let actors = BookActors::spawn(
+            &orderbooks,
+            broadcast::channel(64).0,
+            Arc::new(WatermarkTracker::new(HashMap::new(), Duration::ZERO)),
+            Arc::new(MarketScheduler::new(SchedulerConfig::default())),
+            Arc::default(),
+            ActorOptions { shards: Some(shards.clone()), ..Default::default() },
+        );
+        for market_id in 0..8 {
+            let order = Order { id: market_id as u64, price: 100.0, size: 1.0, timestamp: 0 };
+            actors.handle(market_id).unwrap().send(BookCommand::Add { order, is_buy: true }).await.unwrap();
+        }
+        actors.barrier().await;
+        assert!(orderbooks.values().all(|book| book.snapshot().bids == vec![(100.0, 1.0)]));
+
+        let stats = shards.stats();
+        assert_eq!(stats.iter().map(|s| s.markets).sum::<usize>(), 8);
+        for (shard, stats) in stats.iter().enumerate() {
+            let markets = (0..8).filter(|id| shard_of(*id, 2) == shard).count();
+            assert_eq!((stats.markets, stats.queued), (markets, 0));
+            // Each market's add, then its barrier
+            assert_eq!(stats.commands, 2 * markets as u64);
+        }
+
+        actors.retire(0);
+        drop(actors);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(shards.stats().iter().map(|s| s.markets).sum::<usize>(), 0);
+    }
+}
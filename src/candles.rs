@@ -0,0 +1,275 @@
+//! OHLCV bars per market at fixed intervals.
+//!
+//! Two series are kept per market and interval: one priced by trades and one
+//! by the book mid. Both carry the traded volume. Bars are aligned to
+//! multiples of the interval since the epoch, and a window without any
+//! event has no bar.
+
+use parking_lot::RwLock;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::fast_orderbook::FastOrderbook;
+use crate::fills::Trade;
+use crate::market_processor::MarketUpdate;
+use crate::memory_profile::MemoryUsage;
+
+/// Supported intervals by name, in milliseconds
+pub const INTERVALS: [(&str, u64); 4] = [("1s", 1_000), ("1m", 60_000), ("5m", 300_000), ("1h", 3_600_000)];
+
+/// Bars kept per market, source and interval when none is configured
+pub const DEFAULT_HISTORY: usize = 1000;
+
+/// What prices a series' bars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandleSource {
+    Trades,
+    Mid,
+}
+
+impl CandleSource {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "trades" => Some(Self::Trades),
+            "mid" => Some(Self::Mid),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Trades => "trades",
+            Self::Mid => "mid",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    pub start_ms: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub trades: u32,
+}
+
+/// A bar that changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct CandleUpdate {
+    pub market_id: u32,
+    pub source: CandleSource,
+    pub interval_ms: u64,
+    pub candle: Candle,
+}
+
+#[derive(Debug)]
+struct Series {
+    interval_ms: u64,
+    bars: VecDeque<Candle>,
+}
+
+impl Series {
+    /// The bar covering `timestamp_ms`, opening a new one at `open`, or at the
+    /// last close for volume alone. `None` for windows before the last bar
+    /// that have none.
+    fn bar(&mut self, timestamp_ms: u64, open: Option<f64>, history: usize) -> Option<&mut Candle> {
+        let start_ms = timestamp_ms - timestamp_ms % self.interval_ms;
+        if self.bars.back().is_some_and(|last| last.start_ms >= start_ms) {
+            let index = self.bars.iter().rposition(|bar| bar.start_ms == start_ms)?;
+            return self.bars.get_mut(index);
+        }
+
+        let open = open.or(self.bars.back().map(|last| last.close))?;
+        if self.bars.len() >= history {
+            self.bars.pop_front();
+        }
+        self.bars.push_back(Candle { start_ms, open, high: open, low: open, close: open, volume: 0.0, trades: 0 });
+        self.bars.back_mut()
+    }
+
+    /// Apply a price and/or volume, returning the bar if it changed
+    fn record(&mut self, timestamp_ms: u64, price: Option<f64>, size: Option<f64>, history: usize) -> Option<Candle> {
+        let opened = self.bars.back().map(|last| last.start_ms);
+        let bar = self.bar(timestamp_ms, price, history)?;
+        let before = *bar;
+        if let Some(price) = price {
+            bar.high = bar.high.max(price);
+            bar.low = bar.low.min(price);
+            bar.close = price;
+        }
+        if let Some(size) = size {
+            bar.volume += size;
+            bar.trades += 1;
+        }
+        (*bar != before || opened != Some(bar.start_ms)).then_some(*bar)
+    }
+}
+
+pub struct CandleAggregator {
+    history: usize,
+    series: RwLock<HashMap<(u32, CandleSource, u64), Series>>,
+    update_tx: broadcast::Sender<Arc<CandleUpdate>>,
+}
+
+impl Default for CandleAggregator {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY)
+    }
+}
+
+impl CandleAggregator {
+    pub fn new(history: usize) -> Self {
+        Self {
+            history: history.max(1),
+            series: RwLock::new(HashMap::new()),
+            update_tx: broadcast::channel(10_000).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<CandleUpdate>> {
+        self.update_tx.subscribe()
+    }
+
+    /// Up to `limit` of the latest bars starting at or after `start_ms`,
+    /// oldest first. A `limit` of 0 returns all of them.
+    pub fn candles(&self, market_id: u32, source: CandleSource, interval_ms: u64, start_ms: u64, limit: usize) -> Vec<Candle> {
+        let series = self.series.read();
+        let Some(series) = series.get(&(market_id, source, interval_ms)) else { return Vec::new() };
+        let bars: Vec<Candle> = series.bars.iter().filter(|bar| bar.start_ms >= start_ms).copied().collect();
+        let skip = if limit == 0 { 0 } else { bars.len().saturating_sub(limit) };
+        bars[skip..].to_vec()
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for series in self.series.read().values() {
+            usage += MemoryUsage::new(series.bars.len(), series.bars.capacity() * std::mem::size_of::<Candle>());
+        }
+        usage
+    }
+
+    /// The bar in progress
+    pub fn latest(&self, market_id: u32, source: CandleSource, interval_ms: u64) -> Option<Candle> {
+        self.series.read().get(&(market_id, source, interval_ms))?.bars.back().copied()
+    }
+
+    fn record(&self, market_id: u32, source: CandleSource, timestamp_ms: u64, price: Option<f64>, size: Option<f64>) {
+        let mut series = self.series.write();
+        for (_, interval_ms) in INTERVALS {
+            let bars = series
+                .entry((market_id, source, interval_ms))
+                .or_insert_with(|| Series { interval_ms, bars: VecDeque::new() });
+            if let Some(candle) = bars.record(timestamp_ms, price, size, self.history) {
+                let _ = self.update_tx.send(Arc::new(CandleUpdate { market_id, source, interval_ms, candle }));
+            }
+        }
+    }
+
+    pub fn record_trade(&self, trade: &Trade) {
+        self.record(trade.market_id, CandleSource::Trades, trade.timestamp_ms, Some(trade.price), Some(trade.size));
+        self.record(trade.market_id, CandleSource::Mid, trade.timestamp_ms, None, Some(trade.size));
+    }
+
+    pub fn record_mid(&self, market_id: u32, timestamp_ms: u64, mid: f64) {
+        self.record(market_id, CandleSource::Mid, timestamp_ms, Some(mid), None);
+    }
+
+    /// Follow book updates for mids and, when fills are read, trades
+    pub fn start(
+        self: Arc<Self>,
+        orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+        mut update_rx: broadcast::Receiver<MarketUpdate>,
+        mut trades: Option<broadcast::Receiver<Arc<Trade>>>,
+    ) {
+        info!("Building {} candles from {}", INTERVALS.map(|(name, _)| name).join("/"), if trades.is_some() { "trades and mids" } else { "mids" });
+
+        tokio::spawn(async move {
+            loop {
+                let next_trade = async {
+                    match trades.as_mut() {
+                        Some(trades) => trades.recv().await,
+                        None => std::future::pending().await,
+                    }
+                };
+
+                tokio::select! {
+                    result = update_rx.recv() => match result {
+                        Ok(update) => {
+                            // The mid only moves with the touch
+                            if !update.deltas.iter().any(|d| d.at_touch) {
+                                continue;
+                            }
+                            let Some(orderbook) = orderbooks.get(&update.market_id) else { continue };
+                            let published = orderbook.snapshot();
+                            if let (Some((bid, _)), Some((ask, _))) = (published.bids.first(), published.asks.first()) {
+                                self.record_mid(update.market_id, update.timestamp_ns / 1_000_000, (bid + ask) / 2.0);
+                            }
+                        }
+                        // A missed mid only loses intrabar detail
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    result = next_trade => match result {
+                        Ok(trade) => self.record_trade(&trade),
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => trades = None,
+                    },
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(timestamp_ms: u64, price: f64, size: f64) -> Trade {
+        Trade { market_id: 0, price, size, side: "B".to_string(), oid: 1, tid: 1, timestamp_ms }
+    }
+
+    #[test]
+    fn test_trade_and_mid_bars() {
+        let candles = CandleAggregator::new(2);
+        let mut updates = candles.subscribe();
+
+        candles.record_mid(0, 60_500, 100.0);
+        candles.record_trade(&trade(61_000, 101.0, 2.0));
+        candles.record_trade(&trade(62_000, 99.0, 1.0));
+        candles.record_mid(0, 62_500, 100.5);
+
+        let minute = |source| candles.candles(0, source, 60_000, 0, 0);
+        assert_eq!(
+            minute(CandleSource::Trades),
+            vec![Candle { start_ms: 60_000, open: 101.0, high: 101.0, low: 99.0, close: 99.0, volume: 3.0, trades: 2 }]
+        );
+        assert_eq!(
+            minute(CandleSource::Mid),
+            vec![Candle { start_ms: 60_000, open: 100.0, high: 100.5, low: 100.0, close: 100.5, volume: 3.0, trades: 2 }]
+        );
+
+        // Trades open a mid bar at the last mid close
+        candles.record_trade(&trade(125_000, 98.0, 1.0));
+        let latest = candles.latest(0, CandleSource::Mid, 60_000).unwrap();
+        assert_eq!((latest.start_ms, latest.open, latest.close, latest.volume), (120_000, 100.5, 100.5, 1.0));
+
+        // History is capped, and late events for windows without a bar are dropped
+        candles.record_mid(0, 185_000, 97.0);
+        assert_eq!(minute(CandleSource::Mid).iter().map(|c| c.start_ms).collect::<Vec<_>>(), vec![120_000, 180_000]);
+        candles.record_trade(&trade(1_000, 50.0, 1.0));
+        assert_eq!(candles.candles(0, CandleSource::Trades, 60_000, 0, 1)[0].start_ms, 120_000);
+        assert_eq!(candles.candles(0, CandleSource::Trades, 60_000, 121_000, 0), vec![]);
+
+        // An unchanged mid in the same bar isn't broadcast
+        while updates.try_recv().is_ok() {}
+        candles.record_mid(0, 185_500, 97.0);
+        assert!(updates.try_recv().is_err());
+        candles.record_mid(0, 185_700, 96.0);
+        let update = updates.try_recv().unwrap();
+        assert_eq!((update.source, update.interval_ms, update.candle.low), (CandleSource::Mid, 1_000, 96.0));
+    }
+}
@@ -0,0 +1,340 @@
+//! Perp prices from the five exchanges whose weighted median is one input
+//! of Hyperliquid's mark price (Binance, OKX, Bybit, Gate, MEXC).
+//!
+//! One websocket per venue streams tickers for every tracked coin and
+//! reconnects with backoff when it drops or goes quiet. Each quote remembers
+//! when it arrived, and a venue's price is left out of a coin's `CEXPrices`
+//! once it is older than the staleness limit, so a stalled venue can't pin
+//! the median. Every second each book is sent its coin's fresh prices.
+//!
+//! Coins with a `k` prefix (priced per 1000 units) are skipped, since the
+//! venues don't quote them consistently.
+
+use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
+use parking_lot::RwLock;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
+
+use crate::book_actor::{BookActors, BookCommand};
+use crate::mark_price_v2::CEXPrices;
+
+/// How often books are sent their coin's prices
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Application-level pings, for the venues that expect them
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Bybit accepts at most this many topics per subscribe request
+const BYBIT_TOPICS_PER_REQUEST: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Venue {
+    Binance,
+    Okx,
+    Bybit,
+    Gate,
+    Mexc,
+}
+
+impl Venue {
+    pub const ALL: [Venue; 5] = [Venue::Binance, Venue::Okx, Venue::Bybit, Venue::Gate, Venue::Mexc];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Venue::Binance => "binance",
+            Venue::Okx => "okx",
+            Venue::Bybit => "bybit",
+            Venue::Gate => "gate",
+            Venue::Mexc => "mexc",
+        }
+    }
+
+    /// The venue's USDT perp for a Hyperliquid coin
+    fn symbol(self, coin: &str) -> String {
+        match self {
+            Venue::Binance | Venue::Bybit => format!("{}USDT", coin),
+            Venue::Okx => format!("{}-USDT-SWAP", coin),
+            Venue::Gate | Venue::Mexc => format!("{}_USDT", coin),
+        }
+    }
+
+    fn url(self, coins: &[String]) -> String {
+        match self {
+            // Streams are chosen in the URL rather than subscribed to
+            Venue::Binance => format!(
+                "wss://fstream.binance.com/stream?streams={}",
+                coins.iter().map(|coin| format!("{}@markPrice@1s", self.symbol(coin).to_lowercase())).collect::<Vec<_>>().join("/")
+            ),
+            Venue::Okx => "wss://ws.okx.com:8443/ws/v5/public".to_string(),
+            Venue::Bybit => "wss://stream.bybit.com/v5/public/linear".to_string(),
+            Venue::Gate => "wss://fx-ws.gateio.ws/v4/ws/usdt".to_string(),
+            Venue::Mexc => "wss://contract.mexc.com/edge".to_string(),
+        }
+    }
+
+    fn subscriptions(self, coins: &[String]) -> Vec<Value> {
+        let symbols: Vec<String> = coins.iter().map(|coin| self.symbol(coin)).collect();
+        match self {
+            Venue::Binance => Vec::new(),
+            Venue::Okx => vec![json!({
+                "op": "subscribe",
+                "args": symbols.iter().map(|symbol| json!({ "channel": "tickers", "instId": symbol })).collect::<Vec<_>>(),
+            })],
+            Venue::Bybit => symbols
+                .chunks(BYBIT_TOPICS_PER_REQUEST)
+                .map(|chunk| json!({ "op": "subscribe", "args": chunk.iter().map(|symbol| format!("tickers.{}", symbol)).collect::<Vec<_>>() }))
+                .collect(),
+            Venue::Gate => vec![json!({ "time": unix_secs(), "channel": "futures.tickers", "event": "subscribe", "payload": symbols })],
+            Venue::Mexc => symbols.iter().map(|symbol| json!({ "method": "sub.ticker", "param": { "symbol": symbol } })).collect(),
+        }
+    }
+
+    /// Binance pings from its side, which tungstenite answers
+    fn ping(self) -> Option<Message> {
+        match self {
+            Venue::Binance => None,
+            Venue::Okx => Some(Message::Text("ping".to_string())),
+            Venue::Bybit => Some(Message::Text(json!({ "op": "ping" }).to_string())),
+            Venue::Gate => Some(Message::Text(json!({ "time": unix_secs(), "channel": "futures.ping" }).to_string())),
+            Venue::Mexc => Some(Message::Text(json!({ "method": "ping" }).to_string())),
+        }
+    }
+
+    /// (venue symbol, price) pairs in a text frame; acks and pongs have none
+    fn parse(self, text: &str) -> Vec<(String, f64)> {
+        let Ok(message) = serde_json::from_str::<Value>(text) else { return Vec::new() };
+        let quote = |item: &Value, symbol: &str, price: &str| {
+            let price = match item.get(price)? {
+                Value::String(price) => price.parse().ok()?,
+                Value::Number(price) => price.as_f64()?,
+                _ => return None,
+            };
+            Some((item.get(symbol)?.as_str()?.to_string(), price))
+        };
+        let items = |items: &Value, symbol: &str, price: &str| -> Vec<(String, f64)> {
+            items.as_array().map_or_else(Vec::new, |items| items.iter().filter_map(|item| quote(item, symbol, price)).collect())
+        };
+        match self {
+            Venue::Binance => quote(&message["data"], "s", "p").into_iter().collect(),
+            Venue::Okx => items(&message["data"], "instId", "last"),
+            // Deltas only carry the fields that changed
+            Venue::Bybit => quote(&message["data"], "symbol", "lastPrice").into_iter().collect(),
+            Venue::Gate if message["event"] == "update" => items(&message["result"], "contract", "last"),
+            Venue::Mexc if message["channel"] == "push.ticker" => quote(&message["data"], "symbol", "lastPrice").into_iter().collect(),
+            Venue::Gate | Venue::Mexc => Vec::new(),
+        }
+    }
+}
+
+fn unix_secs() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Quote {
+    price: f64,
+    received: Instant,
+}
+
+pub struct CexFeeds {
+    coins: Vec<String>,
+    /// Quotes and venues older than this are stale
+    max_age: Duration,
+    /// Latest quote per coin, indexed by venue
+    quotes: RwLock<HashMap<String, [Option<Quote>; 5]>>,
+    /// Latest message of any kind per venue
+    last_message: RwLock<[Option<Instant>; 5]>,
+}
+
+impl CexFeeds {
+    pub fn new(coins: impl IntoIterator<Item = String>, max_age: Duration) -> Self {
+        let mut coins: Vec<String> = coins.into_iter().filter(|coin| !coin.starts_with('k')).collect();
+        coins.sort();
+        coins.dedup();
+        Self { coins, max_age, quotes: RwLock::new(HashMap::new()), last_message: RwLock::new([None; 5]) }
+    }
+
+    pub fn record(&self, venue: Venue, coin: &str, price: f64, received: Instant) {
+        if !price.is_finite() || price <= 0.0 {
+            return;
+        }
+        self.quotes.write().entry(coin.to_string()).or_default()[venue as usize] = Some(Quote { price, received });
+    }
+
+    /// Each venue's price for `coin`, unset where missing or stale
+    pub fn prices(&self, coin: &str, now: Instant) -> CEXPrices {
+        let quotes = self.quotes.read().get(coin).copied().unwrap_or_default();
+        let fresh = |venue: Venue| {
+            quotes[venue as usize].filter(|quote| now.saturating_duration_since(quote.received) <= self.max_age).map(|quote| quote.price)
+        };
+        CEXPrices {
+            binance: fresh(Venue::Binance),
+            okx: fresh(Venue::Okx),
+            bybit: fresh(Venue::Bybit),
+            gate: fresh(Venue::Gate),
+            mexc: fresh(Venue::Mexc),
+        }
+    }
+
+    /// Venues that have sent nothing within the staleness limit
+    pub fn stale_venues(&self, now: Instant) -> Vec<Venue> {
+        let last_message = self.last_message.read();
+        Venue::ALL
+            .into_iter()
+            .filter(|venue| last_message[*venue as usize].is_none_or(|last| now.saturating_duration_since(last) > self.max_age))
+            .collect()
+    }
+
+    /// Connect to every venue and send each market's prices to its book.
+    /// `markets` maps market ids to Hyperliquid coins.
+    pub fn start(self: Arc<Self>, markets: Vec<(u32, String)>, books: Arc<BookActors>) {
+        info!("Starting CEX price feeds for {} coins ({:?} staleness limit)", self.coins.len(), self.max_age);
+        for venue in Venue::ALL {
+            tokio::spawn(self.clone().run_venue(venue));
+        }
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PUBLISH_INTERVAL);
+            let mut stale = Vec::new();
+            loop {
+                interval.tick().await;
+                let now = Instant::now();
+                let now_stale = self.stale_venues(now);
+                if now_stale != stale {
+                    if now_stale.is_empty() {
+                        info!("All CEX price feeds are fresh");
+                    } else {
+                        warn!("Stale CEX price feeds: {:?}", now_stale.iter().map(|venue| venue.as_str()).collect::<Vec<_>>());
+                    }
+                    stale = now_stale;
+                }
+                for (market_id, coin) in &markets {
+                    if let Some(book) = books.handle(*market_id) {
+                        let _ = book.send(BookCommand::CexPrices(self.prices(coin, now))).await;
+                    }
+                }
+            }
+        });
+    }
+
+    async fn run_venue(self: Arc<Self>, venue: Venue) {
+        let coins: HashMap<String, String> = self.coins.iter().map(|coin| (venue.symbol(coin), coin.clone())).collect();
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            let connected = Instant::now();
+            if let Err(e) = self.stream(venue, &coins).await {
+                warn!("{} price feed disconnected: {}", venue.as_str(), e);
+            }
+            // A connection that delivered anything was healthy
+            if self.last_message.read()[venue as usize].is_some_and(|last| last > connected) {
+                backoff = Duration::from_secs(1);
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    async fn stream(&self, venue: Venue, coins: &HashMap<String, String>) -> Result<()> {
+        let (mut ws, _) = tokio_tungstenite::connect_async(venue.url(&self.coins)).await?;
+        for subscription in venue.subscriptions(&self.coins) {
+            ws.send(Message::Text(subscription.to_string())).await?;
+        }
+        info!("Connected to {} price feed", venue.as_str());
+
+        let mut last_message = Instant::now();
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if last_message.elapsed() > self.max_age {
+                        return Err(anyhow!("nothing received for {:?}", last_message.elapsed()));
+                    }
+                    if let Some(ping) = venue.ping() {
+                        ws.send(ping).await?;
+                    }
+                }
+                message = ws.next() => {
+                    let text = match message.ok_or_else(|| anyhow!("stream ended"))?? {
+                        Message::Text(text) => text,
+                        Message::Close(frame) => return Err(anyhow!("closed by venue: {:?}", frame)),
+                        _ => continue,
+                    };
+                    last_message = Instant::now();
+                    self.last_message.write()[venue as usize] = Some(last_message);
+                    for (symbol, price) in venue.parse(&text) {
+                        if let Some(coin) = coins.get(&symbol) {
+                            self.record(venue, coin, price, last_message);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_each_venues_tickers() {
+        let parsed = |venue: Venue, text: &str| venue.parse(text);
+        let btc = |symbol: &str, price: f64| vec![(symbol.to_string(), price)];
+
+        assert_eq!(
+            parsed(Venue::Binance, r#"{"stream":"btcusdt@markPrice@1s","data":{"e":"markPriceUpdate","s":"BTCUSDT","p":"64000.10"}}"#),
+            btc("BTCUSDT", 64000.1)
+        );
+        assert_eq!(
+            parsed(Venue::Okx, r#"{"arg":{"channel":"tickers","instId":"BTC-USDT-SWAP"},"data":[{"instId":"BTC-USDT-SWAP","last":"64001"}]}"#),
+            btc("BTC-USDT-SWAP", 64001.0)
+        );
+        assert_eq!(
+            parsed(Venue::Bybit, r#"{"topic":"tickers.BTCUSDT","type":"snapshot","data":{"symbol":"BTCUSDT","lastPrice":"64002.5"}}"#),
+            btc("BTCUSDT", 64002.5)
+        );
+        assert!(parsed(Venue::Bybit, r#"{"topic":"tickers.BTCUSDT","type":"delta","data":{"symbol":"BTCUSDT","bid1Price":"1"}}"#).is_empty());
+        assert_eq!(
+            parsed(Venue::Gate, r#"{"channel":"futures.tickers","event":"update","result":[{"contract":"BTC_USDT","last":"64003"}]}"#),
+            btc("BTC_USDT", 64003.0)
+        );
+        assert!(parsed(Venue::Gate, r#"{"channel":"futures.tickers","event":"subscribe","result":{"status":"success"}}"#).is_empty());
+        assert_eq!(
+            parsed(Venue::Mexc, r#"{"channel":"push.ticker","data":{"symbol":"BTC_USDT","lastPrice":64004.5},"symbol":"BTC_USDT"}"#),
+            btc("BTC_USDT", 64004.5)
+        );
+        assert!(parsed(Venue::Okx, "pong").is_empty());
+
+        for venue in Venue::ALL {
+            assert!(venue.url(&["BTC".to_string()]).starts_with("wss://"));
+        }
+        assert_eq!(Venue::Bybit.subscriptions(&(0..25).map(|i| format!("C{}", i)).collect::<Vec<_>>()).len(), 3);
+    }
+
+    #[test]
+    fn test_stale_quotes_are_left_out() {
+        let feeds = CexFeeds::new(["BTC".to_string(), "kPEPE".to_string()], Duration::from_secs(10));
+        assert_eq!(feeds.coins, vec!["BTC".to_string()]);
+
+        let start = Instant::now();
+        feeds.record(Venue::Binance, "BTC", 100.0, start);
+        feeds.record(Venue::Okx, "BTC", 101.0, start + Duration::from_secs(8));
+        feeds.record(Venue::Gate, "BTC", f64::NAN, start);
+
+        let prices = feeds.prices("BTC", start + Duration::from_secs(5));
+        assert_eq!((prices.binance, prices.okx, prices.gate), (Some(100.0), Some(101.0), None));
+        let prices = feeds.prices("BTC", start + Duration::from_secs(15));
+        assert_eq!((prices.binance, prices.okx), (None, Some(101.0)));
+        assert_eq!(feeds.prices("ETH", start).binance, None);
+
+        feeds.last_message.write()[Venue::Okx as usize] = Some(start);
+        assert_eq!(feeds.stale_venues(start + Duration::from_secs(5)), vec![Venue::Binance, Venue::Bybit, Venue::Gate, Venue::Mexc]);
+        assert_eq!(feeds.stale_venues(start + Duration::from_secs(11)).len(), 5);
+    }
+}
@@ -0,0 +1,346 @@
+//! Book reconstruction test vectors for client SDKs.
+//!
+//! Each vector is a starting snapshot, the delta messages `SubscribeDeltas`
+//! would send after it and the book they must produce, all generated by
+//! running scripted orders through `FastOrderbook` itself. A client library
+//! in any language passes a vector when applying the deltas to the snapshot
+//! reproduces the expected book and the checksum after every delta.
+//!
+//! Vectors are served by `GetConformanceVectors`, written by
+//! `export-conformance-vectors` and bundled under `conformance/`.
+
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::fast_orderbook::{BookSnapshot, FastOrderbook, Order, SequencedDelta};
+
+/// Bump when the vectors or the checksum change meaning
+pub const FORMAT_VERSION: u32 = 1;
+
+pub const CHECKSUM_ALGORITHM: &str =
+    "CRC-32 (IEEE) over each bid best first, then each ask best first, as big-endian IEEE-754 doubles price then quantity";
+
+/// Deterministic timestamps, microseconds: this base plus the sequence
+const BASE_TIMESTAMP_US: i64 = 1_700_000_000_000_000;
+
+/// CRC-32 of a book's levels, as described by `CHECKSUM_ALGORITHM`
+pub fn book_checksum(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for (price, quantity) in bids.iter().chain(asks) {
+        hasher.update(&price.to_be_bytes());
+        hasher.update(&quantity.to_be_bytes());
+    }
+    hasher.finalize()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorBook {
+    pub sequence: u64,
+    /// (price, quantity), best first
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+}
+
+impl From<&BookSnapshot> for VectorBook {
+    fn from(snapshot: &BookSnapshot) -> Self {
+        Self { sequence: snapshot.sequence, bids: snapshot.bids.clone(), asks: snapshot.asks.clone() }
+    }
+}
+
+/// A `LevelChange` of the delta stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorChange {
+    pub action: String,
+    pub side: String,
+    pub price: f64,
+    pub quantity: f64,
+}
+
+/// An `OrderbookDelta` of the delta stream and the book's checksum after it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorDelta {
+    pub sequence: u64,
+    pub prev_sequence: u64,
+    pub timestamp: i64,
+    pub changes: Vec<VectorChange>,
+    pub checksum: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConformanceVector {
+    pub name: String,
+    pub description: String,
+    pub market_id: u32,
+    pub symbol: String,
+    pub snapshot: VectorBook,
+    pub snapshot_checksum: u32,
+    pub deltas: Vec<VectorDelta>,
+    pub expected: VectorBook,
+    pub expected_checksum: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Add { id: u64, is_buy: bool, price: f64, size: f64 },
+    Remove { id: u64 },
+    Clear,
+}
+
+struct Scenario {
+    name: &'static str,
+    description: &'static str,
+    setup: Vec<Op>,
+    /// Each batch becomes one delta message
+    batches: Vec<Vec<Op>>,
+}
+
+/// Runs a scenario's orders through a fresh book
+struct Runner {
+    book: FastOrderbook,
+}
+
+impl Runner {
+    fn apply(&self, op: Op) -> Option<SequencedDelta> {
+        match op {
+            Op::Add { id, is_buy, price, size } => Some(self.book.add_order(Order { id, price, size, timestamp: 0 }, is_buy)),
+            Op::Remove { id } => self.book.remove_order(id),
+            Op::Clear => Some(self.book.clear()),
+        }
+    }
+}
+
+fn run(market_id: u32, symbol: &str, scenario: Scenario) -> ConformanceVector {
+    let runner = Runner { book: FastOrderbook::new(market_id, symbol.to_string()) };
+    for op in scenario.setup {
+        runner.apply(op);
+    }
+    runner.book.publish();
+    let snapshot = VectorBook::from(&*runner.book.snapshot());
+
+    let mut deltas = Vec::new();
+    for batch in scenario.batches {
+        let applied: Vec<SequencedDelta> = batch.into_iter().filter_map(|op| runner.apply(op)).collect();
+        let Some(first) = applied.first() else { continue };
+        runner.book.publish();
+        let published = runner.book.snapshot();
+        deltas.push(VectorDelta {
+            sequence: published.sequence,
+            prev_sequence: first.sequence - 1,
+            timestamp: BASE_TIMESTAMP_US + published.sequence as i64,
+            changes: applied
+                .iter()
+                .map(|delta| {
+                    let (action, side, price) = delta.level_action();
+                    VectorChange { action: action.to_string(), side: side.to_string(), price, quantity: delta.level_size }
+                })
+                .collect(),
+            checksum: book_checksum(&published.bids, &published.asks),
+        });
+    }
+
+    let expected = VectorBook::from(&*runner.book.snapshot());
+    ConformanceVector {
+        name: scenario.name.to_string(),
+        description: scenario.description.to_string(),
+        market_id,
+        symbol: symbol.to_string(),
+        snapshot_checksum: book_checksum(&snapshot.bids, &snapshot.asks),
+        snapshot,
+        deltas,
+        expected_checksum: book_checksum(&expected.bids, &expected.asks),
+        expected,
+    }
+}
+
+fn bid(id: u64, price: f64, size: f64) -> Op {
+    Op::Add { id, is_buy: true, price, size }
+}
+
+fn ask(id: u64, price: f64, size: f64) -> Op {
+    Op::Add { id, is_buy: false, price, size }
+}
+
+/// Orders from a fixed linear congruential generator, so the vector never
+/// changes between builds
+fn randomized_batches(count: usize) -> Vec<Vec<Op>> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = |bound: u64| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) % bound
+    };
+    let mut live: Vec<u64> = Vec::new();
+    let mut batches = Vec::new();
+    for id in 1..=count as u64 {
+        let batch_size = 1 + next(4) as usize;
+        let mut batch = Vec::with_capacity(batch_size);
+        for _ in 0..batch_size {
+            if !live.is_empty() && next(10) < 4 {
+                batch.push(Op::Remove { id: live.swap_remove(next(live.len() as u64) as usize) });
+                continue;
+            }
+            let order_id = id * 10 + batch.len() as u64;
+            let is_buy = next(2) == 0;
+            // Half-dollar ticks up to $10 from a $100 mid, sizes in lots of 0.001
+            let offset = (1 + next(20)) as f64 * 0.5;
+            let price = if is_buy { 100.0 - offset } else { 100.0 + offset };
+            let size = (1 + next(5000)) as f64 / 1000.0;
+            live.push(order_id);
+            batch.push(Op::Add { id: order_id, is_buy, price, size });
+        }
+        batches.push(batch);
+    }
+    batches
+}
+
+fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario {
+            name: "level_lifecycle",
+            description: "Levels created, grown, shrunk and removed on both sides, including changes at the touch",
+            setup: vec![bid(1, 100.0, 1.0), bid(2, 99.5, 2.0), ask(3, 100.5, 1.5), ask(4, 101.0, 3.0)],
+            batches: vec![
+                vec![bid(5, 100.0, 0.25)],
+                vec![bid(6, 99.75, 4.0)],
+                vec![Op::Remove { id: 1 }],
+                vec![Op::Remove { id: 5 }],
+                vec![ask(7, 100.25, 0.5), Op::Remove { id: 3 }],
+                vec![Op::Remove { id: 7 }, ask(8, 100.75, 2.0)],
+            ],
+        },
+        Scenario {
+            name: "queue_at_one_level",
+            description: "Several orders at one price leaving out of arrival order; the level survives until the last",
+            setup: vec![bid(1, 50.0, 1.0), bid(2, 50.0, 2.0), bid(3, 50.0, 3.0), ask(4, 51.0, 1.0)],
+            batches: vec![
+                vec![Op::Remove { id: 2 }],
+                vec![bid(5, 50.0, 0.5), Op::Remove { id: 1 }],
+                vec![Op::Remove { id: 3 }],
+                vec![Op::Remove { id: 5 }],
+            ],
+        },
+        Scenario {
+            name: "clear_and_rebuild",
+            description: "A clear that empties both sides followed by new levels in the same message",
+            setup: vec![bid(1, 10.0, 5.0), bid(2, 9.9, 1.0), ask(3, 10.1, 2.0), ask(4, 10.2, 7.0)],
+            batches: vec![vec![bid(5, 9.8, 1.0)], vec![Op::Clear, bid(6, 10.05, 3.0), ask(7, 10.15, 1.0)], vec![ask(8, 10.15, 2.0)]],
+        },
+        Scenario {
+            name: "from_empty_book",
+            description: "Deltas building a book from an empty snapshot until one side empties again",
+            setup: Vec::new(),
+            batches: vec![
+                vec![bid(1, 1.25, 100.0)],
+                vec![ask(2, 1.5, 50.0), ask(3, 1.75, 25.0)],
+                vec![bid(4, 1.0, 10.0)],
+                vec![Op::Remove { id: 2 }, Op::Remove { id: 3 }],
+            ],
+        },
+        Scenario {
+            name: "randomized",
+            description: "Seeded random adds and cancels in batches of one to four, exercising every action",
+            setup: (1..=10).flat_map(|i| [bid(1000 + i, 100.0 - i as f64 * 0.5, 1.0), ask(2000 + i, 100.0 + i as f64 * 0.5, 1.0)]).collect(),
+            batches: randomized_batches(200),
+        },
+    ]
+}
+
+/// Every vector, generated by the current engine
+pub fn generate() -> Vec<ConformanceVector> {
+    scenarios().into_iter().enumerate().map(|(market_id, scenario)| run(market_id as u32, "TEST", scenario)).collect()
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// Directory to write one JSON file per vector into
+    #[arg(long, default_value = "conformance")]
+    pub out_dir: PathBuf,
+}
+
+/// Write each vector as `<name>.json` and a VERSION file under `dir`
+pub fn export(dir: &Path) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for vector in generate() {
+        let path = dir.join(format!("{}.json", vector.name));
+        std::fs::write(&path, serde_json::to_string_pretty(&vector)? + "\n")?;
+        written.push(path);
+    }
+    let path = dir.join("VERSION");
+    std::fs::write(&path, format!("{}\n", FORMAT_VERSION))?;
+    written.push(path);
+    Ok(written)
+}
+
+pub fn run_export(args: ExportArgs) -> Result<()> {
+    for path in export(&args.out_dir)? {
+        info!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a client does with a delta; the reference for SDK authors
+    fn apply(book: &mut VectorBook, delta: &VectorDelta) {
+        for change in &delta.changes {
+            if change.action == "clear" {
+                book.bids.clear();
+                book.asks.clear();
+                continue;
+            }
+            let (levels, descending) = if change.side == "B" { (&mut book.bids, true) } else { (&mut book.asks, false) };
+            let position = levels.binary_search_by(|(price, _)| {
+                let ordering = price.partial_cmp(&change.price).unwrap();
+                if descending { ordering.reverse() } else { ordering }
+            });
+            match (position, change.quantity > 0.0) {
+                (Ok(index), true) => levels[index].1 = change.quantity,
+                (Ok(index), false) => {
+                    levels.remove(index);
+                }
+                (Err(index), true) => levels.insert(index, (change.price, change.quantity)),
+                (Err(_), false) => panic!("Removed a missing level at {}", change.price),
+            }
+        }
+        book.sequence = delta.sequence;
+    }
+
+    #[test]
+    fn test_vectors_reconstruct_their_books() {
+        let vectors = generate();
+        for vector in &vectors {
+            let mut book = vector.snapshot.clone();
+            assert_eq!(book_checksum(&book.bids, &book.asks), vector.snapshot_checksum);
+            for delta in &vector.deltas {
+                assert_eq!(delta.prev_sequence, book.sequence, "{}: gap", vector.name);
+                apply(&mut book, delta);
+                assert_eq!(book_checksum(&book.bids, &book.asks), delta.checksum, "{} at {}", vector.name, delta.sequence);
+            }
+            assert_eq!(book, vector.expected, "{}", vector.name);
+        }
+
+        let actions: std::collections::HashSet<_> =
+            vectors.iter().flat_map(|v| &v.deltas).flat_map(|d| &d.changes).map(|c| c.action.as_str()).collect();
+        assert_eq!(actions.len(), 4);
+        assert!(vectors.iter().any(|v| v.snapshot.bids.is_empty()));
+        // Generation is deterministic
+        assert_eq!(vectors, generate());
+    }
+
+    /// The vectors bundled with the repository match what the engine
+    /// generates today; regenerate with `export-conformance-vectors`
+    #[test]
+    fn test_bundled_vectors_are_current() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance");
+        for vector in generate() {
+            let path = dir.join(format!("{}.json", vector.name));
+            let bundled = std::fs::read_to_string(&path).unwrap();
+            assert!(bundled == serde_json::to_string_pretty(&vector).unwrap() + "\n", "{} is stale", path.display());
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Alerts for stress across correlated markets at once.
+//!
+//! Operators group markets that move together, e.g. majors. Each market's
+//! book metrics are compared with a slow baseline of its own spread and
+//! depth near the mid. A spread several times its baseline is a blowout, and
+//! depth down to a fraction of its baseline is a collapse. When enough
+//! markets of one group are stressed within a short window, the event is
+//! systemic rather than one market's glitch, and a contagion alert lists
+//! the markets involved.
+
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::alerts::{AlertKind, Alerts, Severity};
+use crate::book_metrics::BookMetrics;
+
+/// Depth is measured within this many bps of the mid, one of the book
+/// metrics' bands
+const DEPTH_BAND_BPS: u32 = 50;
+
+/// Time constant of the spread and depth baselines
+const BASELINE_TIME_CONSTANT: Duration = Duration::from_secs(300);
+
+/// Samples before a market's baseline is trusted
+const MIN_BASELINE_SAMPLES: u64 = 50;
+
+/// Minimum time between alerts for one group
+const GROUP_COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ContagionConfig {
+    /// Spread over this multiple of its baseline is a blowout
+    pub spread_factor: f64,
+    /// Depth under this fraction of its baseline is a collapse
+    pub depth_fraction: f64,
+    /// Stressed markets a group needs for an alert
+    pub min_markets: usize,
+    /// How close together their stress must be
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketGroup {
+    pub name: String,
+    pub market_ids: Vec<u32>,
+}
+
+/// One market's part in a contagion alert
+#[derive(Debug, Clone, PartialEq)]
+pub struct StressedMarket {
+    pub market_id: u32,
+    pub symbol: String,
+    pub spread_blowout: bool,
+    pub depth_collapse: bool,
+    pub spread_bps: f64,
+    pub baseline_spread_bps: f64,
+    pub depth: f64,
+    pub baseline_depth: f64,
+    /// Exchange time of the stressed book, microseconds
+    pub timestamp_us: i64,
+}
+
+#[derive(Debug, Default)]
+struct Baseline {
+    spread_bps: f64,
+    depth: f64,
+    samples: u64,
+    last_us: i64,
+}
+
+impl Baseline {
+    fn update(&mut self, spread_bps: f64, depth: f64, timestamp_us: i64) {
+        if self.samples == 0 {
+            (self.spread_bps, self.depth) = (spread_bps, depth);
+        } else {
+            let dt = (timestamp_us - self.last_us).max(0) as f64 / 1e6;
+            let alpha = 1.0 - (-dt / BASELINE_TIME_CONSTANT.as_secs_f64()).exp();
+            self.spread_bps += alpha * (spread_bps - self.spread_bps);
+            self.depth += alpha * (depth - self.depth);
+        }
+        self.samples += 1;
+        self.last_us = timestamp_us;
+    }
+}
+
+#[derive(Default)]
+struct State {
+    baselines: HashMap<u32, Baseline>,
+    /// Latest stress per market
+    stressed: HashMap<u32, StressedMarket>,
+    /// Exchange time of each group's last alert
+    last_alert_us: HashMap<usize, i64>,
+}
+
+pub struct ContagionDetector {
+    config: ContagionConfig,
+    groups: Vec<MarketGroup>,
+    symbols: HashMap<u32, String>,
+    state: Mutex<State>,
+}
+
+impl ContagionDetector {
+    pub fn new(config: ContagionConfig, groups: Vec<MarketGroup>, symbols: HashMap<u32, String>) -> Self {
+        Self { config, groups, symbols, state: Mutex::new(State::default()) }
+    }
+
+    /// Compare `metrics` with its market's baseline; the alert, if this
+    /// completes a group's stress
+    pub fn observe(&self, metrics: &BookMetrics) -> Option<AlertKind> {
+        let market_id = metrics.market_id;
+        if !self.groups.iter().any(|group| group.market_ids.contains(&market_id)) {
+            return None;
+        }
+        let depth = metrics
+            .depths
+            .iter()
+            .find(|band| band.bps == DEPTH_BAND_BPS)
+            .map_or(0.0, |band| band.bid_size + band.ask_size);
+        let now_us = metrics.timestamp_us;
+
+        let mut state = self.state.lock();
+        let baseline = state.baselines.entry(market_id).or_default();
+        let stress = (baseline.samples >= MIN_BASELINE_SAMPLES)
+            .then(|| {
+                let spread_blowout = metrics.spread_bps > baseline.spread_bps * self.config.spread_factor;
+                let depth_collapse = depth < baseline.depth * self.config.depth_fraction;
+                (spread_blowout || depth_collapse).then(|| StressedMarket {
+                    market_id,
+                    symbol: self.symbols.get(&market_id).cloned().unwrap_or_default(),
+                    spread_blowout,
+                    depth_collapse,
+                    spread_bps: metrics.spread_bps,
+                    baseline_spread_bps: baseline.spread_bps,
+                    depth,
+                    baseline_depth: baseline.depth,
+                    timestamp_us: now_us,
+                })
+            })
+            .flatten();
+        // Stressed books would drag the baseline toward the stress
+        let Some(stress) = stress else {
+            baseline.update(metrics.spread_bps, depth, now_us);
+            return None;
+        };
+        state.stressed.insert(market_id, stress);
+
+        let window_us = self.config.window.as_micros() as i64;
+        let cooldown_us = GROUP_COOLDOWN.as_micros() as i64;
+        for (index, group) in self.groups.iter().enumerate() {
+            if !group.market_ids.contains(&market_id)
+                || state.last_alert_us.get(&index).is_some_and(|last| now_us - last < cooldown_us)
+            {
+                continue;
+            }
+            let markets: Vec<StressedMarket> = group
+                .market_ids
+                .iter()
+                .filter_map(|id| state.stressed.get(id))
+                .filter(|stress| (now_us - stress.timestamp_us).abs() <= window_us)
+                .cloned()
+                .collect();
+            if markets.len() >= self.config.min_markets {
+                state.last_alert_us.insert(index, now_us);
+                return Some(AlertKind::Contagion { group: group.name.clone(), group_size: group.market_ids.len(), markets });
+            }
+        }
+        None
+    }
+
+    /// Raise contagion alerts from published book metrics
+    pub fn start(self: Arc<Self>, mut metrics_rx: broadcast::Receiver<Arc<BookMetrics>>, alerts: Arc<Alerts>) {
+        info!(
+            "Watching {} market groups for contagion ({} markets within {:?})",
+            self.groups.len(),
+            self.config.min_markets,
+            self.config.window
+        );
+        tokio::spawn(async move {
+            loop {
+                let metrics = match metrics_rx.recv().await {
+                    Ok(metrics) => metrics,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if let Some(kind) = self.observe(&metrics) {
+                    alerts.raise(Severity::Critical, kind);
+                }
+            }
+        });
+    }
+}
+
+/// Parse `NAME=COIN,COIN;NAME=COIN,...` into group names and their coins
+pub fn parse_groups(spec: &str) -> Result<Vec<(String, Vec<String>)>> {
+    spec.split(';')
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            let (name, coins) = group
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected NAME=COIN,COIN, got {}", group))?;
+            let coins: Vec<String> = coins.split(',').map(str::trim).filter(|c| !c.is_empty()).map(String::from).collect();
+            if coins.len() < 2 {
+                return Err(anyhow!("Market group {} needs at least two markets", name.trim()));
+            }
+            Ok((name.trim().to_string(), coins))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_metrics::BandDepth;
+
+    fn metrics(market_id: u32, timestamp_us: i64, spread_bps: f64, depth: f64) -> BookMetrics {
+        BookMetrics {
+            market_id,
+            sequence: 0,
+            timestamp_us,
+            mid: 100.0,
+            spread_bps,
+            microprice: None,
+            depth_weighted_mid: None,
+            imbalances: Vec::new(),
+            depths: vec![BandDepth { bps: DEPTH_BAND_BPS, bid_size: depth / 2.0, ask_size: depth / 2.0 }],
+        }
+    }
+
+    #[test]
+    fn test_alerts_when_a_group_is_stressed_together() {
+        let config = ContagionConfig { spread_factor: 3.0, depth_fraction: 0.3, min_markets: 2, window: Duration::from_secs(5) };
+        let groups = vec![MarketGroup { name: "majors".to_string(), market_ids: vec![0, 1, 2] }];
+        let symbols = [(0, "BTC"), (1, "ETH"), (2, "SOL"), (3, "DOGE")].into_iter().map(|(id, s)| (id, s.to_string())).collect();
+        let detector = ContagionDetector::new(config, groups, symbols);
+
+        for i in 0..MIN_BASELINE_SAMPLES as i64 {
+            for market_id in 0..4 {
+                assert_eq!(detector.observe(&metrics(market_id, i * 100_000, 2.0, 1000.0)), None);
+            }
+        }
+        let t = 10_000_000;
+
+        // One market alone, or one outside the group, is not contagion
+        assert_eq!(detector.observe(&metrics(0, t, 10.0, 1000.0)), None);
+        assert_eq!(detector.observe(&metrics(3, t, 10.0, 100.0)), None);
+        // Too long after the first to count together
+        assert_eq!(detector.observe(&metrics(1, t + 6_000_000, 2.0, 200.0)), None);
+
+        let Some(AlertKind::Contagion { group, group_size, markets }) = detector.observe(&metrics(2, t + 7_000_000, 9.0, 100.0)) else {
+            panic!("Expected a contagion alert");
+        };
+        assert_eq!((group.as_str(), group_size), ("majors", 3));
+        let stressed: Vec<_> = markets.iter().map(|m| (m.symbol.as_str(), m.spread_blowout, m.depth_collapse)).collect();
+        assert_eq!(stressed, vec![("ETH", false, true), ("SOL", true, true)]);
+        assert!((markets[0].baseline_depth - 1000.0).abs() < 1e-9);
+
+        // The group is cooling down
+        assert_eq!(detector.observe(&metrics(0, t + 7_500_000, 10.0, 1000.0)), None);
+
+        assert_eq!(parse_groups("majors=BTC,ETH;l2=ARB, OP;").unwrap()[1], ("l2".to_string(), vec!["ARB".to_string(), "OP".to_string()]));
+        assert!(parse_groups("majors").is_err());
+        assert!(parse_groups("solo=BTC").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Detection and repair of crossed books.
+//!
+//! A book whose best bid reaches its best ask can't be the exchange's: it
+//! would have matched those orders. Crossing comes from stale or out of order
+//! statuses, such as a missed fill leaving an order behind that later orders
+//! crossed. Each book's actor checks after every batch, logs a crossing with
+//! its sequence, and evicts crossed orders until the book is whole again.
+
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use tracing::warn;
+
+use crate::fast_orderbook::{FastOrderbook, SequencedDelta};
+
+/// Which crossed orders a book evicts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossRepair {
+    /// Log and count crossings; the book stays crossed
+    Off,
+    /// Of the two best orders, the one that rested first; the later one was
+    /// placed against a book that no longer had it
+    Older,
+    /// Crossed bids
+    Bids,
+    /// Crossed asks
+    Asks,
+}
+
+impl CrossRepair {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CrossRepair::Off => "off",
+            CrossRepair::Older => "older",
+            CrossRepair::Bids => "bids",
+            CrossRepair::Asks => "asks",
+        }
+    }
+}
+
+impl FromStr for CrossRepair {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(CrossRepair::Off),
+            "older" => Ok(CrossRepair::Older),
+            "bids" => Ok(CrossRepair::Bids),
+            "asks" => Ok(CrossRepair::Asks),
+            other => Err(anyhow!("Unknown crossed book repair: {} (expected off, older, bids or asks)", other)),
+        }
+    }
+}
+
+/// One book's crossing check, run by its writer
+#[derive(Debug)]
+pub struct CrossGuard {
+    repair: CrossRepair,
+    crossed: bool,
+}
+
+impl CrossGuard {
+    pub fn new(repair: CrossRepair) -> Self {
+        Self { repair, crossed: false }
+    }
+
+    /// Check the book after a batch, evicting crossed orders as configured.
+    /// Returns each evicted oid with its removal.
+    pub fn check(&mut self, orderbook: &FastOrderbook) -> Vec<(u64, SequencedDelta)> {
+        let Some((bid, ask)) = orderbook.crossing() else {
+            self.crossed = false;
+            return Vec::new();
+        };
+        // A book left crossed is reported once, not after every batch
+        if !self.crossed {
+            orderbook.integrity.crossed();
+            warn!(
+                "Market {} crossed at sequence {}: bid {} (oid {}) >= ask {} (oid {}); repair: {}",
+                orderbook.market_id,
+                orderbook.sequence.load(Ordering::Acquire),
+                bid.price,
+                bid.id,
+                ask.price,
+                ask.id,
+                self.repair.as_str()
+            );
+        }
+
+        let mut evicted = Vec::new();
+        let mut next = Some((bid, ask));
+        while let Some((bid, ask)) = next {
+            let stale = match self.repair {
+                CrossRepair::Off => break,
+                CrossRepair::Older if bid.timestamp <= ask.timestamp => bid,
+                CrossRepair::Older => ask,
+                CrossRepair::Bids => bid,
+                CrossRepair::Asks => ask,
+            };
+            let Some(removed) = orderbook.remove_order(stale.id) else {
+                break;
+            };
+            evicted.push((stale.id, removed));
+            next = orderbook.crossing();
+        }
+
+        orderbook.integrity.evicted(evicted.len());
+        self.crossed = next.is_some();
+        if !evicted.is_empty() {
+            warn!(
+                "Market {}: evicted {} crossed orders, through sequence {}",
+                orderbook.market_id,
+                evicted.len(),
+                orderbook.sequence.load(Ordering::Acquire)
+            );
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::Order;
+
+    fn order(id: u64, price: f64, timestamp: u64) -> Order {
+        Order { id, price, size: 1.0, timestamp }
+    }
+
+    #[test]
+    fn test_crossed_orders_are_evicted() {
+        let book = FastOrderbook::new(0, "BTC".to_string());
+        book.add_order(order(1, 100.0, 10), true);
+        book.add_order(order(2, 99.0, 11), true);
+        book.add_order(order(3, 101.0, 12), false);
+        let mut guard = CrossGuard::new(CrossRepair::Older);
+        assert!(guard.check(&book).is_empty());
+
+        // Asks placed later crossed both bids, which must have been filled
+        book.add_order(order(4, 98.0, 20), false);
+        let evicted = guard.check(&book);
+        assert_eq!(evicted.iter().map(|(oid, _)| *oid).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(evicted[1].1.sequence, 6);
+        assert!(book.crossing().is_none());
+        assert_eq!(book.integrity.stats().crossed_books, 1);
+        assert_eq!(book.integrity.stats().crossed_evictions, 2);
+
+        // Off only reports, once per crossing
+        book.add_order(order(5, 102.0, 30), true);
+        let mut guard = CrossGuard::new(CrossRepair::Off);
+        assert!(guard.check(&book).is_empty());
+        assert!(guard.check(&book).is_empty());
+        assert_eq!(book.integrity.stats().crossed_books, 2);
+
+        let evicted = CrossGuard::new(CrossRepair::Bids).check(&book);
+        assert_eq!(evicted.iter().map(|(oid, _)| *oid).collect::<Vec<_>>(), vec![5]);
+    }
+
+    #[test]
+    fn test_repairs_parse() {
+        for repair in [CrossRepair::Off, CrossRepair::Older, CrossRepair::Bids, CrossRepair::Asks] {
+            assert_eq!(repair.as_str().parse::<CrossRepair>().unwrap(), repair);
+        }
+        assert!("newer".parse::<CrossRepair>().is_err());
+    }
+}
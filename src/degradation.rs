@@ -0,0 +1,272 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::alerts::{AlertKind, Alerts, Severity};
+use crate::market_scheduler::MarketScheduler;
+use crate::market_tiers::MarketTier;
+
+/// When the service counts as overloaded, and what it sheds while it is
+#[derive(Debug, Clone, Copy)]
+pub struct DegradationConfig {
+    pub check_interval: Duration,
+    /// Commands queued for one book; 0 disables the signal
+    pub max_backlog: usize,
+    /// Updates one subscriber stream is behind; 0 disables the signal
+    pub max_fanout_lag: usize,
+    /// Share of a check interval one market spent held back by its budget;
+    /// 0 disables the signal
+    pub max_throttled_share: f64,
+    /// Consecutive overloaded checks before degrading
+    pub enter_after: u32,
+    /// Consecutive clear checks before recovering
+    pub exit_after: u32,
+    /// Cold markets publish this many times less often while degraded
+    pub cold_interval_factor: u32,
+    /// Best-effort streams get one snapshot per market per interval while degraded
+    pub conflation_interval: Duration,
+}
+
+impl Default for DegradationConfig {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(1),
+            max_backlog: 4096,
+            max_fanout_lag: 10_000,
+            max_throttled_share: 0.5,
+            enter_after: 3,
+            exit_after: 10,
+            cold_interval_factor: 5,
+            conflation_interval: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Load measured over one check interval
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pressure {
+    /// Largest book backlog
+    pub backlog: usize,
+    /// Furthest any subscriber stream fell behind the update feed
+    pub fanout_lag: usize,
+    /// Largest share of the interval a market was held back by its budget
+    pub throttled_share: f64,
+}
+
+/// A degradation state change
+#[derive(Debug, Clone, PartialEq)]
+pub struct DegradationEvent {
+    pub event_id: u64,
+    pub timestamp_ms: u64,
+    pub degraded: bool,
+    /// Signals over their thresholds when degradation started; empty on recovery
+    pub reasons: Vec<String>,
+    pub pressure: Pressure,
+}
+
+#[derive(Debug, Default)]
+struct Streaks {
+    overloaded: u32,
+    clear: u32,
+}
+
+/// Watches processing and fan-out load and switches the service into a
+/// degraded mode while it stays overloaded. Hot markets keep full fidelity;
+/// cold markets, best-effort streams and analytics give way.
+pub struct DegradationMonitor {
+    config: DegradationConfig,
+    degraded: AtomicBool,
+    next_event_id: AtomicU64,
+    fanout_lag: AtomicUsize,
+    streaks: Mutex<Streaks>,
+    current: Mutex<Arc<DegradationEvent>>,
+    output_tx: broadcast::Sender<Arc<DegradationEvent>>,
+}
+
+impl DegradationMonitor {
+    pub fn new(config: DegradationConfig) -> Self {
+        Self {
+            config: DegradationConfig {
+                check_interval: config.check_interval.max(Duration::from_millis(10)),
+                enter_after: config.enter_after.max(1),
+                exit_after: config.exit_after.max(1),
+                cold_interval_factor: config.cold_interval_factor.max(1),
+                ..config
+            },
+            degraded: AtomicBool::new(false),
+            next_event_id: AtomicU64::new(1),
+            fanout_lag: AtomicUsize::new(0),
+            streaks: Mutex::new(Streaks::default()),
+            current: Mutex::new(Arc::new(DegradationEvent {
+                event_id: 0,
+                timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+                degraded: false,
+                reasons: Vec::new(),
+                pressure: Pressure::default(),
+            })),
+            output_tx: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    pub fn conflation_interval(&self) -> Duration {
+        self.config.conflation_interval
+    }
+
+    /// Minimum time between snapshots of a market in `tier`
+    pub fn publish_interval(&self, tier: MarketTier) -> Duration {
+        match tier {
+            MarketTier::Cold if self.is_degraded() => tier.min_publish_interval() * self.config.cold_interval_factor,
+            _ => tier.min_publish_interval(),
+        }
+    }
+
+    /// Report how many updates a subscriber stream has yet to receive
+    pub fn record_fanout_lag(&self, lag: usize) {
+        self.fanout_lag.fetch_max(lag, Ordering::Relaxed);
+    }
+
+    /// The latest state, as the event that entered it
+    pub fn current(&self) -> Arc<DegradationEvent> {
+        self.current.lock().clone()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<DegradationEvent>> {
+        self.output_tx.subscribe()
+    }
+
+    fn reasons(&self, pressure: &Pressure) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if self.config.max_backlog > 0 && pressure.backlog > self.config.max_backlog {
+            reasons.push(format!("book backlog {} > {}", pressure.backlog, self.config.max_backlog));
+        }
+        if self.config.max_fanout_lag > 0 && pressure.fanout_lag > self.config.max_fanout_lag {
+            reasons.push(format!("fanout lag {} > {}", pressure.fanout_lag, self.config.max_fanout_lag));
+        }
+        if self.config.max_throttled_share > 0.0 && pressure.throttled_share > self.config.max_throttled_share {
+            reasons.push(format!(
+                "throttled {:.0}% of interval > {:.0}%",
+                pressure.throttled_share * 100.0,
+                self.config.max_throttled_share * 100.0
+            ));
+        }
+        reasons
+    }
+
+    /// Apply one interval's measurements, returning the state change if any
+    pub fn observe(&self, pressure: Pressure) -> Option<Arc<DegradationEvent>> {
+        let reasons = self.reasons(&pressure);
+        let degraded = self.is_degraded();
+
+        let change = {
+            let mut streaks = self.streaks.lock();
+            if reasons.is_empty() {
+                streaks.overloaded = 0;
+                streaks.clear += 1;
+            } else {
+                streaks.clear = 0;
+                streaks.overloaded += 1;
+            }
+
+            if !degraded && streaks.overloaded >= self.config.enter_after {
+                Some(true)
+            } else if degraded && streaks.clear >= self.config.exit_after {
+                Some(false)
+            } else {
+                None
+            }
+        }?;
+
+        let event = Arc::new(DegradationEvent {
+            event_id: self.next_event_id.fetch_add(1, Ordering::Relaxed),
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+            degraded: change,
+            reasons,
+            pressure,
+        });
+        self.degraded.store(change, Ordering::Relaxed);
+        *self.current.lock() = event.clone();
+        let _ = self.output_tx.send(event.clone());
+        Some(event)
+    }
+
+    /// Sample load every check interval and raise an alert on each change
+    pub fn start(self: Arc<Self>, scheduler: Arc<MarketScheduler>, alerts: Arc<Alerts>) {
+        info!(
+            "Starting degradation monitor (backlog > {}, fanout lag > {}, throttled > {:.0}%)",
+            self.config.max_backlog,
+            self.config.max_fanout_lag,
+            self.config.max_throttled_share * 100.0
+        );
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.check_interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            let mut last_throttled: HashMap<u32, Duration> = HashMap::new();
+
+            loop {
+                ticker.tick().await;
+
+                let mut pressure = Pressure {
+                    fanout_lag: self.fanout_lag.swap(0, Ordering::Relaxed),
+                    ..Default::default()
+                };
+                for timing in scheduler.timings() {
+                    pressure.backlog = pressure.backlog.max(timing.backlog);
+                    let previous = last_throttled.insert(timing.market_id, timing.throttled).unwrap_or_default();
+                    let share = (timing.throttled - previous).as_secs_f64() / self.config.check_interval.as_secs_f64();
+                    pressure.throttled_share = pressure.throttled_share.max(share);
+                }
+
+                let Some(event) = self.observe(pressure) else { continue };
+                if event.degraded {
+                    warn!("Entering degraded mode: {}", event.reasons.join(", "));
+                    alerts.raise(Severity::Critical, AlertKind::Degradation(event));
+                } else {
+                    info!("Leaving degraded mode");
+                    alerts.raise(Severity::Info, AlertKind::Degradation(event));
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enter_and_exit_with_hysteresis() {
+        let monitor = DegradationMonitor::new(DegradationConfig {
+            enter_after: 2,
+            exit_after: 2,
+            ..Default::default()
+        });
+        let overloaded = Pressure { backlog: 10_000, ..Default::default() };
+
+        assert!(monitor.observe(overloaded).is_none());
+        // A single clear check resets the streak
+        assert!(monitor.observe(Pressure::default()).is_none());
+        assert!(monitor.observe(overloaded).is_none());
+
+        let event = monitor.observe(overloaded).unwrap();
+        assert!(event.degraded && monitor.is_degraded());
+        assert_eq!(event.reasons, vec!["book backlog 10000 > 4096".to_string()]);
+        assert_eq!(monitor.publish_interval(MarketTier::Cold), Duration::from_secs(5));
+        assert_eq!(monitor.publish_interval(MarketTier::Hot), Duration::ZERO);
+        assert!(monitor.observe(overloaded).is_none());
+
+        assert!(monitor.observe(Pressure::default()).is_none());
+        let event = monitor.observe(Pressure::default()).unwrap();
+        assert!(!event.degraded && !monitor.is_degraded());
+        assert_eq!(monitor.current().event_id, event.event_id);
+        assert_eq!(monitor.publish_interval(MarketTier::Cold), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,468 @@
+//! Append-only log of every book update, one directory of segments per
+//! market.
+//!
+//! Each `MarketUpdate` is written as a frame of its length, a CRC32 of its
+//! payload and the bincode payload. Segments are named by the time and
+//! sequence of their first update, roll over at a size limit and are deleted
+//! once older than the retention. Every segment starts with a checkpoint of
+//! the market's resting orders, so the book at any logged moment is that
+//! checkpoint plus the updates after it. Frames are flushed to the OS
+//! whenever the writer catches up with the update channel; a torn frame at
+//! the end of a segment ends reading of that segment.
+//!
+//! Sequences restart when the service starts without a state snapshot, so
+//! across restarts only timestamp ranges are unambiguous.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+use tracing::{error, info, warn};
+
+use crate::event_log::{BookEvent, BookState};
+use crate::fast_orderbook::{FastOrderbook, SequencedDelta};
+use crate::market_processor::MarketUpdate;
+
+/// Frames larger than this are treated as corruption
+const MAX_FRAME_BYTES: u32 = 64 << 20;
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    pub dir: PathBuf,
+    pub segment_bytes: u64,
+    /// Segments older than this are deleted; `None` keeps everything
+    pub retention: Option<Duration>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum WalRecord {
+    /// The book before the segment's first update
+    Checkpoint { timestamp_ns: u64, book: BookState },
+    Update { timestamp_ns: u64, deltas: Vec<SequencedDelta> },
+}
+
+/// Deltas to replay; every bound is inclusive and optional
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WalRange {
+    pub from_sequence: Option<u64>,
+    pub to_sequence: Option<u64>,
+    pub from_ns: Option<u64>,
+    pub to_ns: Option<u64>,
+}
+
+impl WalRange {
+    fn admits_sequence(&self, sequence: u64) -> bool {
+        self.from_sequence.is_none_or(|from| sequence >= from) && self.to_sequence.is_none_or(|to| sequence <= to)
+    }
+
+    fn admits_time(&self, timestamp_ns: u64) -> bool {
+        self.from_ns.is_none_or(|from| timestamp_ns >= from) && self.to_ns.is_none_or(|to| timestamp_ns <= to)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    path: PathBuf,
+    start_ns: u64,
+    first_sequence: u64,
+}
+
+fn segment_name(start_ns: u64, first_sequence: u64) -> String {
+    format!("{:020}-{:020}.wal", start_ns, first_sequence)
+}
+
+/// A market's segments in write order
+fn list_segments(dir: &Path) -> Result<Vec<Segment>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+    };
+    let mut segments = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let parsed = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".wal"))
+            .and_then(|stem| stem.split_once('-'))
+            .and_then(|(start, first)| Some((start.parse().ok()?, first.parse().ok()?)));
+        if let Some((start_ns, first_sequence)) = parsed {
+            segments.push(Segment { path, start_ns, first_sequence });
+        }
+    }
+    segments.sort_by_key(|segment| (segment.start_ns, segment.first_sequence));
+    Ok(segments)
+}
+
+fn encode_frame(record: &WalRecord) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(record)?;
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// The next intact record, or `None` at the end of the segment or the first
+/// damaged frame
+fn read_frame(reader: &mut impl Read) -> Option<WalRecord> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header).ok()?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if len > MAX_FRAME_BYTES {
+        return None;
+    }
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload).ok()?;
+    if crc32fast::hash(&payload) != crc {
+        return None;
+    }
+    bincode::deserialize(&payload).ok()
+}
+
+struct OpenSegment {
+    writer: BufWriter<File>,
+    bytes: u64,
+    dirty: bool,
+}
+
+/// What the writer thread keeps between updates
+#[derive(Default)]
+struct WalWriter {
+    open: HashMap<u32, OpenSegment>,
+    /// Each market's resting orders as of its last logged delta
+    books: HashMap<u32, BookState>,
+}
+
+impl WalWriter {
+    /// Take every book's current orders, closing open segments so the next
+    /// update of each market starts a checkpointed one
+    fn resync(&mut self, orderbooks: &HashMap<u32, Arc<FastOrderbook>>) {
+        for (market_id, mut segment) in self.open.drain() {
+            if let Err(e) = segment.writer.flush() {
+                error!("Delta WAL flush failed for market {}: {}", market_id, e);
+            }
+        }
+        self.books = orderbooks
+            .iter()
+            .map(|(market_id, orderbook)| (*market_id, orderbook.event_log().lock().current_state()))
+            .collect();
+    }
+}
+
+pub struct DeltaWal {
+    config: WalConfig,
+    frames_written: AtomicU64,
+    updates_missed: AtomicU64,
+}
+
+impl DeltaWal {
+    pub fn new(config: WalConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir).with_context(|| format!("creating {}", config.dir.display()))?;
+        Ok(Self { config, frames_written: AtomicU64::new(0), updates_missed: AtomicU64::new(0) })
+    }
+
+    fn market_dir(&self, market_id: u32) -> PathBuf {
+        self.config.dir.join(market_id.to_string())
+    }
+
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written.load(Ordering::Relaxed)
+    }
+
+    /// Updates dropped because the writer lagged the update channel
+    pub fn updates_missed(&self) -> u64 {
+        self.updates_missed.load(Ordering::Relaxed)
+    }
+
+    /// Write every update from `updates` on a dedicated thread until the
+    /// channel closes. Checkpoints start from the books' current orders,
+    /// which are taken again whenever the writer falls behind.
+    pub fn start(
+        self: &Arc<Self>,
+        mut updates: broadcast::Receiver<MarketUpdate>,
+        orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+    ) -> Result<()> {
+        let wal = self.clone();
+        std::thread::Builder::new().name("delta-wal".to_string()).spawn(move || {
+            let mut writer = WalWriter::default();
+            writer.resync(&orderbooks);
+            loop {
+                match updates.blocking_recv() {
+                    Ok(update) => wal.append(&mut writer, &update),
+                    Err(RecvError::Lagged(n)) => wal.missed(&mut writer, &orderbooks, n),
+                    Err(RecvError::Closed) => break,
+                }
+                // Drain what is queued before flushing
+                loop {
+                    match updates.try_recv() {
+                        Ok(update) => wal.append(&mut writer, &update),
+                        Err(TryRecvError::Lagged(n)) => wal.missed(&mut writer, &orderbooks, n),
+                        Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+                    }
+                }
+                wal.flush(&mut writer);
+            }
+            wal.flush(&mut writer);
+            info!("Delta WAL closed after {} frames, {} updates missed", wal.frames_written(), wal.updates_missed());
+        })?;
+        info!("Writing delta WAL to {}", self.config.dir.display());
+        Ok(())
+    }
+
+    fn missed(&self, writer: &mut WalWriter, orderbooks: &HashMap<u32, Arc<FastOrderbook>>, n: u64) {
+        warn!("Delta WAL lagged, {} updates not logged; checkpointing again", n);
+        self.updates_missed.fetch_add(n, Ordering::Relaxed);
+        writer.resync(orderbooks);
+    }
+
+    fn append(&self, writer: &mut WalWriter, update: &MarketUpdate) {
+        let book = writer.books.entry(update.market_id).or_default();
+        // After a resync the book may already hold some of these
+        let deltas: Vec<SequencedDelta> =
+            update.deltas.iter().filter(|delta| delta.sequence > book.sequence).cloned().collect();
+        let Some(first_sequence) = deltas.first().map(|delta| delta.sequence) else { return };
+        let open = &mut writer.open;
+        let result = (|| -> Result<()> {
+            let full = open.get(&update.market_id).is_none_or(|segment| segment.bytes >= self.config.segment_bytes);
+            if full {
+                if let Some(mut segment) = open.remove(&update.market_id) {
+                    segment.writer.flush()?;
+                }
+                let dir = self.market_dir(update.market_id);
+                fs::create_dir_all(&dir)?;
+                let path = dir.join(segment_name(update.timestamp_ns, first_sequence));
+                let file = OpenOptions::new().create(true).append(true).open(&path)?;
+                let mut segment = OpenSegment { bytes: file.metadata()?.len(), writer: BufWriter::new(file), dirty: true };
+                let checkpoint = encode_frame(&WalRecord::Checkpoint { timestamp_ns: update.timestamp_ns, book: book.clone() })?;
+                segment.writer.write_all(&checkpoint)?;
+                segment.bytes += checkpoint.len() as u64;
+                open.insert(update.market_id, segment);
+                self.prune(update.market_id)?;
+            }
+            let frame = encode_frame(&WalRecord::Update { timestamp_ns: update.timestamp_ns, deltas: deltas.clone() })?;
+            let segment = open.get_mut(&update.market_id).expect("segment opened above");
+            segment.writer.write_all(&frame)?;
+            segment.bytes += frame.len() as u64;
+            segment.dirty = true;
+            Ok(())
+        })();
+        // The book follows the feed whether or not the write succeeded
+        for delta in &deltas {
+            book.apply(&BookEvent::from_delta(delta, update.timestamp_ns));
+        }
+        match result {
+            Ok(()) => {
+                self.frames_written.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                // Start a fresh segment with the next update
+                error!("Delta WAL write failed for market {}: {}", update.market_id, e);
+                open.remove(&update.market_id);
+            }
+        }
+    }
+
+    fn flush(&self, writer: &mut WalWriter) {
+        writer.open.retain(|market_id, segment| {
+            if !segment.dirty {
+                return true;
+            }
+            segment.dirty = false;
+            match segment.writer.flush() {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("Delta WAL flush failed for market {}: {}", market_id, e);
+                    false
+                }
+            }
+        });
+    }
+
+    /// Delete a market's segments that ended before the retention window,
+    /// always keeping the newest
+    fn prune(&self, market_id: u32) -> Result<()> {
+        let Some(retention) = self.config.retention else { return Ok(()) };
+        let now_ns = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?.as_nanos() as u64;
+        let cutoff = now_ns.saturating_sub(retention.as_nanos() as u64);
+        let segments = list_segments(&self.market_dir(market_id))?;
+        for pair in segments.windows(2) {
+            // A segment ends where the next one starts
+            if pair[1].start_ns < cutoff {
+                fs::remove_file(&pair[0].path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Call `f` with each logged update of a market in `range`, trimmed to the
+    /// deltas inside it, until it returns false. Returns the updates passed.
+    pub fn replay(&self, market_id: u32, range: WalRange, mut f: impl FnMut(MarketUpdate) -> bool) -> Result<usize> {
+        let segments = list_segments(&self.market_dir(market_id))?;
+        let mut passed = 0;
+        for (i, segment) in segments.iter().enumerate() {
+            if range.to_ns.is_some_and(|to| segment.start_ns > to) {
+                break;
+            }
+            // Skip segments wholly before the range
+            if let Some(next) = segments.get(i + 1) {
+                if range.from_ns.is_some_and(|from| next.start_ns <= from) {
+                    continue;
+                }
+                let same_run = next.first_sequence > segment.first_sequence;
+                if same_run && range.from_sequence.is_some_and(|from| next.first_sequence <= from) {
+                    continue;
+                }
+            }
+
+            let file = match File::open(&segment.path) {
+                Ok(file) => file,
+                // Removed by retention since it was listed
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let mut reader = BufReader::new(file);
+            while let Some(record) = read_frame(&mut reader) {
+                let WalRecord::Update { timestamp_ns, deltas } = record else { continue };
+                if range.to_ns.is_some_and(|to| timestamp_ns > to) {
+                    return Ok(passed);
+                }
+                if !range.admits_time(timestamp_ns) {
+                    continue;
+                }
+                let deltas: Vec<_> = deltas.into_iter().filter(|delta| range.admits_sequence(delta.sequence)).collect();
+                if let Some(update) = MarketUpdate::from_deltas(market_id, timestamp_ns, deltas) {
+                    passed += 1;
+                    if !f(update) {
+                        return Ok(passed);
+                    }
+                }
+            }
+        }
+        Ok(passed)
+    }
+
+    /// A market's resting orders as of the last update logged at or before
+    /// `timestamp_ns`, or `None` if that is before the oldest segment
+    pub fn state_at(&self, market_id: u32, timestamp_ns: u64) -> Result<Option<BookState>> {
+        let segments = list_segments(&self.market_dir(market_id))?;
+        let Some(segment) = segments.iter().rev().find(|segment| segment.start_ns <= timestamp_ns) else {
+            return Ok(None);
+        };
+        let file = match File::open(&segment.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut reader = BufReader::new(file);
+        let Some(WalRecord::Checkpoint { mut book, .. }) = read_frame(&mut reader) else {
+            return Ok(None);
+        };
+        while let Some(WalRecord::Update { timestamp_ns: logged_ns, deltas }) = read_frame(&mut reader) {
+            if logged_ns > timestamp_ns {
+                break;
+            }
+            for delta in &deltas {
+                book.apply(&BookEvent::from_delta(delta, logged_ns));
+            }
+        }
+        Ok(Some(book))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::OrderbookDelta;
+
+    fn update(market_id: u32, first_sequence: u64, timestamp_ns: u64) -> MarketUpdate {
+        let deltas = (0..2)
+            .map(|i| SequencedDelta {
+                sequence: first_sequence + i,
+                delta: OrderbookDelta::AddBid { price: 100.0, size: 1.0, order_id: first_sequence + i },
+                level_size: 1.0,
+                queue_position: 0,
+                order_size: 1.0,
+                at_touch: false,
+            })
+            .collect();
+        MarketUpdate::from_deltas(market_id, timestamp_ns, deltas).unwrap()
+    }
+
+    fn replayed(wal: &DeltaWal, range: WalRange) -> Vec<u64> {
+        let mut sequences = Vec::new();
+        wal.replay(0, range, |update| {
+            sequences.extend(update.deltas.iter().map(|delta| delta.sequence));
+            true
+        })
+        .unwrap();
+        sequences
+    }
+
+    #[test]
+    fn test_replay_ranges_across_segments() {
+        let dir = std::env::temp_dir().join(format!("delta_wal_{}", std::process::id()));
+        // Small enough that every couple of frames starts a segment
+        let config = WalConfig { dir: dir.clone(), segment_bytes: 200, retention: None };
+        let wal = DeltaWal::new(config).unwrap();
+        let mut writer = WalWriter::default();
+        for i in 0..10 {
+            wal.append(&mut writer, &update(0, 1 + i * 2, 1_000 + i * 100));
+        }
+        wal.append(&mut writer, &update(1, 1, 1_000));
+        wal.flush(&mut writer);
+        assert!(list_segments(&wal.market_dir(0)).unwrap().len() > 2);
+
+        assert_eq!(replayed(&wal, WalRange::default()), (1..=20).collect::<Vec<_>>());
+        // Sequence bounds trim within an update
+        let range = WalRange { from_sequence: Some(4), to_sequence: Some(9), ..Default::default() };
+        assert_eq!(replayed(&wal, range), (4..=9).collect::<Vec<_>>());
+        // Time bounds select whole updates
+        let range = WalRange { from_ns: Some(1_250), to_ns: Some(1_500), ..Default::default() };
+        assert_eq!(replayed(&wal, range), (7..=12).collect::<Vec<_>>());
+
+        // A torn frame at the tail ends the segment without an error
+        let last = list_segments(&wal.market_dir(0)).unwrap().pop().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&last.path).unwrap();
+        file.write_all(&[50, 0, 0, 0, 1, 2]).unwrap();
+        assert_eq!(replayed(&wal, WalRange::default()).len(), 20);
+
+        // Stopping early
+        let mut seen = 0;
+        assert_eq!(wal.replay(0, WalRange::default(), |_| { seen += 1; seen < 3 }).unwrap(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_state_at_from_checkpoints() {
+        let dir = std::env::temp_dir().join(format!("delta_wal_state_{}", std::process::id()));
+        let config = WalConfig { dir: dir.clone(), segment_bytes: 300, retention: None };
+        let wal = DeltaWal::new(config).unwrap();
+        let mut writer = WalWriter::default();
+        for i in 0..10 {
+            wal.append(&mut writer, &update(0, 1 + i * 2, 1_000 + i * 100));
+        }
+        // Already held after a resync, so not logged again
+        wal.append(&mut writer, &update(0, 19, 2_000));
+        wal.flush(&mut writer);
+        assert!(list_segments(&wal.market_dir(0)).unwrap().len() > 2);
+
+        assert!(wal.state_at(0, 999).unwrap().is_none());
+        for (timestamp_ns, orders) in [(1_000, 2), (1_099, 2), (1_450, 10), (1_900, 20), (5_000, 20)] {
+            let state = wal.state_at(0, timestamp_ns).unwrap().unwrap();
+            assert_eq!((state.orders.len(), state.sequence), (orders, orders as u64));
+        }
+        assert!(wal.state_at(1, 5_000).unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -4,7 +4,8 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{info, warn, error};
-use crate::symbology::{TradableProduct, MarketInfo, ProductInfo, ExecutionInfo, SymbologyService};
+use crate::market_ids::{MarketIdAllocator, MarketKey};
+use crate::symbology::{TradableProduct, MarketInfo, ProductInfo, ExecutionInfo, SymbolMatch, SymbologyService};
 
 #[derive(Debug, Deserialize)]
 struct HyperliquidMeta {
@@ -28,7 +29,14 @@ pub struct DynamicMarketRegistry {
     coin_to_id: Arc<RwLock<HashMap<String, u32>>>,
     market_info: Arc<RwLock<HashMap<TradableProduct, MarketInfo>>>,
     symbol_to_id: Arc<RwLock<HashMap<TradableProduct, u32>>>,
+    // Ids for every source's markets, and markets registered by non-Hyperliquid sources
+    market_ids: Arc<RwLock<MarketIdAllocator>>,
+    registered: Arc<RwLock<HashMap<u32, MarketInfo>>>,
     last_update: Arc<RwLock<std::time::Instant>>,
+    // Bumped whenever the set of markets may have changed
+    generation: Arc<std::sync::atomic::AtomicU64>,
+    // Active market count after each applied refresh
+    refresh_tx: tokio::sync::broadcast::Sender<usize>,
 }
 
 impl DynamicMarketRegistry {
@@ -38,7 +46,11 @@ impl DynamicMarketRegistry {
             coin_to_id: Arc::new(RwLock::new(HashMap::new())),
             market_info: Arc::new(RwLock::new(HashMap::new())),
             symbol_to_id: Arc::new(RwLock::new(HashMap::new())),
+            market_ids: Arc::new(RwLock::new(MarketIdAllocator::default())),
+            registered: Arc::new(RwLock::new(HashMap::new())),
             last_update: Arc::new(RwLock::new(std::time::Instant::now())),
+            generation: Arc::new(std::sync::atomic::AtomicU64::new(0)),
+            refresh_tx: tokio::sync::broadcast::channel(16).0,
         }
     }
 
@@ -61,9 +73,10 @@ impl DynamicMarketRegistry {
         let mut new_symbol_to_id = HashMap::new();
         let mut active_count = 0;
         
-        for (id, asset) in meta.universe.iter().enumerate() {
+        let mut market_ids = self.market_ids.write().await;
+        for (index, asset) in meta.universe.iter().enumerate() {
             if !asset.is_delisted.unwrap_or(false) {
-                let id = id as u32;
+                let id = market_ids.assign(MarketKey::HyperliquidPerp(index as u32))?;
                 new_markets.insert(id, asset.name.clone());
                 new_coin_to_id.insert(asset.name.clone(), id);
                 
@@ -89,6 +102,15 @@ impl DynamicMarketRegistry {
             active_count, 
             meta.universe.len()
         );
+        drop(market_ids);
+        
+        // Markets from other sources survive refreshes
+        for (id, market_info) in self.registered.read().await.iter() {
+            new_markets.insert(*id, market_info.symbol.to_string());
+            new_symbol_to_id.insert(market_info.symbol.clone(), *id);
+            new_market_info.insert(market_info.symbol.clone(), market_info.clone());
+            active_count += 1;
+        }
         
         // Update atomically
         *self.markets.write().await = new_markets;
@@ -96,10 +118,55 @@ impl DynamicMarketRegistry {
         *self.market_info.write().await = new_market_info;
         *self.symbol_to_id.write().await = new_symbol_to_id;
         *self.last_update.write().await = std::time::Instant::now();
+        self.generation.fetch_add(1, std::sync::atomic::Ordering::Release);
         
+        let _ = self.refresh_tx.send(active_count);
         Ok(())
     }
     
+    /// Add a market from a source other than Hyperliquid perps. Its id comes
+    /// from a range that can't collide with theirs; registering the same key
+    /// again returns the same id.
+    pub async fn register_market(&self, key: MarketKey, symbol: TradableProduct, sz_decimals: u32) -> Result<u32> {
+        let id = self.market_ids.write().await.assign(key.clone())?;
+        if let Some(existing) = self.symbol_to_id.read().await.get(&symbol) {
+            if *existing != id {
+                anyhow::bail!("Symbol {} already belongs to market {}", symbol, existing);
+            }
+        }
+        
+        let market_info = MarketInfo::external(id, key, symbol.clone(), sz_decimals);
+        info!("Registered {} ({}) as market {}", symbol, market_info.key, id);
+        
+        self.registered.write().await.insert(id, market_info.clone());
+        self.markets.write().await.insert(id, symbol.to_string());
+        self.symbol_to_id.write().await.insert(symbol.clone(), id);
+        self.market_info.write().await.insert(symbol, market_info);
+        self.generation.fetch_add(1, std::sync::atomic::Ordering::Release);
+        Ok(id)
+    }
+    
+    /// Changes whenever a refresh or registration may have changed markets,
+    /// for caching what is derived from them
+    pub fn generation(&self) -> u64 {
+        self.generation.load(std::sync::atomic::Ordering::Acquire)
+    }
+    
+    /// Source key of every active market
+    pub async fn get_market_keys(&self) -> HashMap<u32, MarketKey> {
+        self.market_info
+            .read()
+            .await
+            .values()
+            .map(|info| (info.id, info.key.clone()))
+            .collect()
+    }
+    
+    /// Notified with the active market count whenever a refresh is applied
+    pub fn subscribe_refreshes(&self) -> tokio::sync::broadcast::Receiver<usize> {
+        self.refresh_tx.subscribe()
+    }
+    
     pub async fn get_market_id(&self, coin: &str) -> Option<u32> {
         // First try direct coin lookup (backward compatibility)
         if let Some(id) = self.coin_to_id.read().await.get(coin).copied() {
@@ -139,6 +206,45 @@ impl DynamicMarketRegistry {
         result
     }
     
+    /// Size decimals per market id
+    pub async fn get_sz_decimals(&self) -> HashMap<u32, u32> {
+        self.market_info
+            .read()
+            .await
+            .values()
+            .map(|info| (info.id, info.product_info.sz_decimals))
+            .collect()
+    }
+    
+    /// Hyperliquid's coin name of every active Hyperliquid market, as its
+    /// API takes them
+    pub async fn get_hyperliquid_coins(&self) -> HashMap<u32, String> {
+        self.coin_to_id.read().await.iter().map(|(coin, id)| (*id, coin.clone())).collect()
+    }
+    
+    /// Price increment of every active market
+    pub async fn get_tick_sizes(&self) -> HashMap<u32, f64> {
+        self.market_info
+            .read()
+            .await
+            .values()
+            .map(|info| (info.id, info.execution_info.tick_size))
+            .collect()
+    }
+    
+    /// Markets whose symbol matches `query`, best match first, then by base
+    pub async fn search_markets(&self, query: &str) -> Vec<(SymbolMatch, MarketInfo)> {
+        let mut matches: Vec<_> = self
+            .market_info
+            .read()
+            .await
+            .iter()
+            .filter_map(|(symbol, info)| Some((SymbolMatch::of(query, symbol)?, info.clone())))
+            .collect();
+        matches.sort_by(|(a, x), (b, y)| a.cmp(b).then_with(|| x.symbol.symbol().cmp(y.symbol.symbol())));
+        matches
+    }
+    
     pub async fn is_valid_coin(&self, coin: &str) -> bool {
         self.coin_to_id.read().await.contains_key(coin)
     }
@@ -183,24 +289,14 @@ impl SymbologyService for DynamicMarketRegistry {
     }
     
     async fn get_execution_info(&self, symbol: &TradableProduct, venue: &str) -> Result<Option<ExecutionInfo>> {
-        if venue != "HYPERLIQUID" {
-            return Ok(None);
-        }
-        
         Ok(self.market_info.read().await
             .get(symbol)
+            .filter(|info| info.execution_info.execution_venue == venue)
             .map(|info| info.execution_info.clone()))
     }
     
     async fn search_symbols(&self, query: &str) -> Result<Vec<TradableProduct>> {
-        let query_upper = query.to_uppercase();
-        let market_info = self.market_info.read().await;
-        
-        Ok(market_info
-            .keys()
-            .filter(|symbol| symbol.base().contains(&query_upper))
-            .cloned()
-            .collect())
+        Ok(self.search_markets(query).await.into_iter().map(|(_, info)| info.symbol).collect())
     }
     
     async fn get_market_info(&self, symbol: &TradableProduct) -> Result<Option<MarketInfo>> {
@@ -234,4 +330,21 @@ mod tests {
         println!("Total active markets: {}", market_count);
         assert!(market_count > 150); // Should have many markets
     }
+    
+    #[tokio::test]
+    async fn test_registered_markets_get_external_ids() {
+        let registry = DynamicMarketRegistry::new();
+        let symbol = TradableProduct::from_str("BINANCE-BTC/USDT-SPOT").unwrap();
+        let key = MarketKey::External { source: "binance".to_string(), symbol: "BTCUSDT".to_string() };
+        
+        let id = registry.register_market(key.clone(), symbol.clone(), 5).await.unwrap();
+        assert_eq!(id, crate::market_ids::EXTERNAL_ID_BASE);
+        assert_eq!(registry.register_market(key.clone(), symbol, 5).await.unwrap(), id);
+        
+        assert_eq!(registry.get_market_id("BINANCE-BTC/USDT-SPOT").await, Some(id));
+        // A Hyperliquid coin of the same base is a different market
+        assert_eq!(registry.get_market_id("BTC").await, None);
+        assert_eq!(registry.get_market_keys().await.get(&id), Some(&key));
+        assert_eq!(registry.get_sz_decimals().await.get(&id), Some(&5));
+    }
 }
\ No newline at end of file
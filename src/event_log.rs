@@ -0,0 +1,360 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::fast_orderbook::{FastOrderbook, OrderbookDelta, SequencedDelta};
+use crate::memory_profile::MemoryUsage;
+
+/// Events retained per book before older ones are folded into the base state
+pub const DEFAULT_MAX_EVENTS: usize = 10_000;
+
+/// Bids and asks as (price, size) levels, best first
+pub type BookLevels = (Vec<(f64, f64)>, Vec<(f64, f64)>);
+
+/// One applied book mutation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookEvent {
+    pub sequence: u64,
+    pub timestamp_ns: u64,
+    pub delta: OrderbookDelta,
+}
+
+impl BookEvent {
+    pub fn from_delta(delta: &SequencedDelta, timestamp_ns: u64) -> Self {
+        Self {
+            sequence: delta.sequence,
+            timestamp_ns,
+            delta: delta.delta.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RestingOrder {
+    pub is_buy: bool,
+    pub price: f64,
+    pub size: f64,
+}
+
+/// Book state materialized from events up to `sequence`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookState {
+    pub sequence: u64,
+    pub orders: HashMap<u64, RestingOrder>,
+}
+
+impl BookState {
+    pub fn apply(&mut self, event: &BookEvent) {
+        match event.delta {
+            OrderbookDelta::AddBid { price, size, order_id } => {
+                self.orders.insert(order_id, RestingOrder { is_buy: true, price, size });
+            }
+            OrderbookDelta::AddAsk { price, size, order_id } => {
+                self.orders.insert(order_id, RestingOrder { is_buy: false, price, size });
+            }
+            OrderbookDelta::RemoveBid { order_id, .. } | OrderbookDelta::RemoveAsk { order_id, .. } => {
+                self.orders.remove(&order_id);
+            }
+            OrderbookDelta::Clear => {
+                self.orders.clear();
+            }
+            OrderbookDelta::ChangeLevel { is_buy, price, size, order_id } => {
+                self.orders.insert(order_id, RestingOrder { is_buy, price, size });
+            }
+        }
+        self.sequence = event.sequence;
+    }
+
+    /// Aggregate resting orders into (price, size) levels, best first
+    pub fn levels(&self, depth: usize) -> BookLevels {
+        let mut bids: HashMap<u64, (f64, f64)> = HashMap::new();
+        let mut asks: HashMap<u64, (f64, f64)> = HashMap::new();
+
+        for order in self.orders.values() {
+            let side = if order.is_buy { &mut bids } else { &mut asks };
+            let level = side.entry(order.price.to_bits()).or_insert((order.price, 0.0));
+            level.1 += order.size;
+        }
+
+        let mut bids: Vec<(f64, f64)> = bids.into_values().collect();
+        let mut asks: Vec<(f64, f64)> = asks.into_values().collect();
+        bids.sort_by(|a, b| b.0.total_cmp(&a.0));
+        asks.sort_by(|a, b| a.0.total_cmp(&b.0));
+        bids.truncate(depth);
+        asks.truncate(depth);
+
+        (bids, asks)
+    }
+}
+
+/// Append-only log of book events with bounded retention.
+///
+/// Compaction folds the oldest events into `base`, so the log always
+/// satisfies: `base` + retained events == current book. Any state with a
+/// sequence in `base_sequence()..=last_sequence()` can be reconstructed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventLog {
+    base: BookState,
+    events: VecDeque<BookEvent>,
+    max_events: usize,
+}
+
+impl EventLog {
+    pub fn new(max_events: usize) -> Self {
+        Self {
+            base: BookState::default(),
+            events: VecDeque::with_capacity(max_events.min(1024)),
+            max_events: max_events.max(1),
+        }
+    }
+
+    pub fn append(&mut self, event: BookEvent) {
+        debug_assert!(
+            event.sequence > self.last_sequence(),
+            "events must be appended in sequence order"
+        );
+        self.events.push_back(event);
+
+        // Compact in chunks so the cost is amortized across appends
+        if self.events.len() > self.max_events {
+            self.compact(self.max_events * 3 / 4);
+        }
+    }
+
+    /// Change how many events are retained, compacting now if over the new
+    /// limit
+    pub fn set_max_events(&mut self, max_events: usize) {
+        self.max_events = max_events.max(1);
+        if self.events.len() > self.max_events {
+            self.compact(self.max_events);
+        }
+    }
+
+    /// Fold the oldest events into the base state until at most `retain` remain
+    pub fn compact(&mut self, retain: usize) {
+        while self.events.len() > retain {
+            if let Some(event) = self.events.pop_front() {
+                self.base.apply(&event);
+            }
+        }
+    }
+
+    /// Oldest sequence that can still be reconstructed
+    pub fn base_sequence(&self) -> u64 {
+        self.base.sequence
+    }
+
+    pub fn last_sequence(&self) -> u64 {
+        self.events.back().map(|e| e.sequence).unwrap_or(self.base.sequence)
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Time of the oldest retained event; earlier states can't be
+    /// reconstructed from this log
+    pub fn first_timestamp_ns(&self) -> Option<u64> {
+        self.events.front().map(|e| e.timestamp_ns)
+    }
+
+    /// Retained events, plus the orders of the compacted base
+    pub fn memory_usage(&self) -> MemoryUsage {
+        // Each map slot also has a control byte
+        let base = self.base.orders.capacity() * (std::mem::size_of::<(u64, RestingOrder)>() + 1);
+        MemoryUsage::new(
+            self.events.len(),
+            self.events.capacity() * std::mem::size_of::<BookEvent>() + base,
+        )
+    }
+
+    /// Events after `sequence`, or `None` if some of them were compacted away
+    pub fn events_since(&self, sequence: u64) -> Option<Vec<BookEvent>> {
+        if sequence < self.base.sequence {
+            return None;
+        }
+
+        Some(
+            self.events
+                .iter()
+                .filter(|e| e.sequence > sequence)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Reconstruct the book as it was right after `sequence` was applied
+    pub fn state_at(&self, sequence: u64) -> Option<BookState> {
+        if sequence < self.base.sequence || sequence > self.last_sequence() {
+            return None;
+        }
+
+        let mut state = self.base.clone();
+        for event in self.events.iter().take_while(|e| e.sequence <= sequence) {
+            state.apply(event);
+        }
+        Some(state)
+    }
+
+    /// Reconstruct the book as of the last event at or before `timestamp_ns`
+    pub fn state_at_time(&self, timestamp_ns: u64) -> Option<BookState> {
+        let sequence = self
+            .events
+            .iter()
+            .take_while(|e| e.timestamp_ns <= timestamp_ns)
+            .last()
+            .map(|e| e.sequence)
+            .unwrap_or(self.base.sequence);
+        self.state_at(sequence)
+    }
+
+    pub fn current_state(&self) -> BookState {
+        let mut state = self.base.clone();
+        for event in &self.events {
+            state.apply(event);
+        }
+        state
+    }
+
+    /// Serialize the log (base state plus retained events)
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_atomic(path, &self.encode()?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = std::fs::read(path.as_ref())?;
+        let log: EventLog = bincode::deserialize(&bytes)?;
+
+        if log.events.iter().zip(log.events.iter().skip(1)).any(|(a, b)| b.sequence <= a.sequence) {
+            bail!("Event log {} is not in sequence order", path.as_ref().display());
+        }
+
+        Ok(log)
+    }
+}
+
+/// Where a market's event log is persisted within `dir`
+pub fn log_path(dir: &Path, market_id: u32) -> PathBuf {
+    dir.join(format!("{}.evlog", market_id))
+}
+
+/// Restore every book that has a persisted log in `dir`. Returns how many
+/// were restored; markets without a log stay empty.
+pub fn restore_books(dir: &Path, orderbooks: &HashMap<u32, Arc<FastOrderbook>>) -> Result<usize> {
+    if !dir.is_dir() {
+        bail!("Event log directory {} does not exist", dir.display());
+    }
+
+    let mut restored = 0;
+    for (market_id, orderbook) in orderbooks {
+        let path = log_path(dir, *market_id);
+        if !path.exists() {
+            continue;
+        }
+        match EventLog::load(&path) {
+            Ok(log) => {
+                info!(
+                    "Restored {} from {} (sequences {}..={})",
+                    orderbook.symbol,
+                    path.display(),
+                    log.base_sequence(),
+                    log.last_sequence()
+                );
+                orderbook.restore(log);
+                restored += 1;
+            }
+            Err(e) => warn!("Skipping unreadable event log {}: {}", path.display(), e),
+        }
+    }
+    Ok(restored)
+}
+
+/// Write via a temp file and rename so readers never see a partial log
+pub fn write_atomic(path: impl AsRef<Path>, bytes: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(sequence: u64, delta: OrderbookDelta) -> BookEvent {
+        BookEvent { sequence, timestamp_ns: sequence * 1000, delta }
+    }
+
+    fn sample_log(max_events: usize) -> EventLog {
+        let mut log = EventLog::new(max_events);
+        log.append(event(1, OrderbookDelta::AddBid { price: 100.0, size: 1.0, order_id: 1 }));
+        log.append(event(2, OrderbookDelta::AddBid { price: 100.0, size: 2.0, order_id: 2 }));
+        log.append(event(3, OrderbookDelta::AddAsk { price: 101.0, size: 1.5, order_id: 3 }));
+        log.append(event(4, OrderbookDelta::RemoveBid { price: 100.0, order_id: 1 }));
+        log.append(event(5, OrderbookDelta::AddBid { price: 99.0, size: 4.0, order_id: 4 }));
+        log
+    }
+
+    #[test]
+    fn test_point_in_time_reconstruction() {
+        let log = sample_log(100);
+
+        let (bids, asks) = log.state_at(3).unwrap().levels(10);
+        assert_eq!(bids, vec![(100.0, 3.0)]);
+        assert_eq!(asks, vec![(101.0, 1.5)]);
+
+        let (bids, _) = log.state_at(5).unwrap().levels(10);
+        assert_eq!(bids, vec![(100.0, 2.0), (99.0, 4.0)]);
+
+        assert_eq!(log.state_at_time(2500).unwrap().sequence, 2);
+        assert!(log.state_at(6).is_none());
+    }
+
+    #[test]
+    fn test_compaction_preserves_current_state() {
+        let uncompacted = sample_log(100);
+        let compacted = sample_log(2);
+
+        assert!(compacted.len() <= 2);
+        assert!(compacted.base_sequence() > 0);
+        assert_eq!(
+            compacted.current_state().levels(10),
+            uncompacted.current_state().levels(10)
+        );
+
+        // Compacted history is reported as unavailable rather than wrong
+        assert!(compacted.state_at(1).is_none());
+        assert!(compacted.events_since(0).is_none());
+        assert_eq!(compacted.events_since(compacted.base_sequence()).unwrap().len(), compacted.len());
+    }
+
+    #[test]
+    fn test_restore_books_from_persisted_logs() {
+        let dir = std::env::temp_dir().join(format!("event_log_restore_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        sample_log(3).save(log_path(&dir, 7)).unwrap();
+
+        let orderbooks: HashMap<u32, Arc<FastOrderbook>> = [7, 8]
+            .into_iter()
+            .map(|id| (id, Arc::new(FastOrderbook::new(id, "BTC".to_string()))))
+            .collect();
+        assert_eq!(restore_books(&dir, &orderbooks).unwrap(), 1);
+
+        let restored = &orderbooks[&7];
+        assert_eq!(restored.get_snapshot(10), sample_log(100).current_state().levels(10));
+        assert_eq!(restored.snapshot().sequence, 5);
+        // The restored log keeps serving history and further appends
+        assert_eq!(restored.event_log().lock().last_sequence(), 5);
+        assert_eq!(orderbooks[&8].snapshot().sequence, 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,13 +1,26 @@
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
+use crate::conformance_vectors::book_checksum;
+use crate::event_log::{BookEvent, BookLevels, EventLog, DEFAULT_MAX_EVENTS};
+use crate::memory_profile::MemoryUsage;
+use crate::oid_epochs::IntegrityCounters;
+use crate::price_ticks::{TickSize, Ticks};
 use crate::mark_price::{MarkPriceCalculator, MarkPriceResult};
-use crate::mark_price_v2::{HyperliquidMarkPriceCalculator, MarkPriceInputs, CEXPrices, MarkPriceResult as HLMarkPriceResult};
+use crate::mark_price_v2::{HyperliquidMarkPriceCalculator, MarkPriceInputs, CEXPrices, MarkEmaState, MarkPriceResult as HLMarkPriceResult};
 
 const MAX_PRICE_LEVELS: usize = 1000;
 const ORDERS_PER_LEVEL: usize = 8;
 
+/// Levels per side averaged into the depth-weighted mid
+pub const DEPTH_WEIGHTED_LEVELS: usize = 5;
+
+/// Levels per side covered by a book's checksum
+pub const CHECKSUM_LEVELS: usize = 10;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Order {
     pub id: u64,
@@ -18,44 +31,208 @@ pub struct Order {
 
 #[derive(Debug)]
 pub struct PriceLevel {
+    /// What levels are keyed and ordered by
+    pub ticks: Ticks,
     pub price: f64,
     pub total_size: f64,
     pub orders: SmallVec<[Order; ORDERS_PER_LEVEL]>,
 }
 
 impl PriceLevel {
-    fn new(price: f64) -> Self {
+    fn new(ticks: Ticks, price: f64) -> Self {
         Self {
+            ticks,
             price,
             total_size: 0.0,
             orders: SmallVec::new(),
         }
     }
     
-    fn add_order(&mut self, order: Order) {
+    /// Queue the order at the back, returning its position
+    fn add_order(&mut self, order: Order) -> usize {
         self.orders.push(order);
         self.total_size += order.size;
+        self.orders.len() - 1
     }
     
-    fn remove_order(&mut self, order_id: u64) -> bool {
-        if let Some(pos) = self.orders.iter().position(|o| o.id == order_id) {
-            let order = self.orders.remove(pos);
-            self.total_size -= order.size;
-            true
-        } else {
-            false
+    /// Take the order out of the queue, returning where it was
+    fn remove_order(&mut self, order_id: u64) -> Option<(usize, Order)> {
+        let pos = self.orders.iter().position(|o| o.id == order_id)?;
+        let order = self.orders.remove(pos);
+        self.total_size -= order.size;
+        Some((pos, order))
+    }
+    
+    /// Change an order's size where it stands, returning its position
+    fn resize_order(&mut self, order_id: u64, size: f64) -> Option<usize> {
+        let pos = self.orders.iter().position(|o| o.id == order_id)?;
+        self.total_size += size - self.orders[pos].size;
+        self.orders[pos].size = size;
+        Some(pos)
+    }
+}
+
+/// Every resting order as of `sequence`, best price first and in queue
+/// order within a price
+#[derive(Debug, Clone, Default)]
+pub struct OrderSnapshot {
+    pub sequence: u64,
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+
+/// Immutable copy of every level, as of `sequence`, with the prices most
+/// consumers derive from it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookSnapshot {
+    pub sequence: u64,
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+    pub microprice: Option<f64>,
+    pub depth_weighted_mid: Option<f64>,
+    /// CRC-32 of the top `CHECKSUM_LEVELS` levels of each side
+    pub checksum: u32,
+}
+
+impl BookSnapshot {
+    pub fn new(sequence: u64, bids: Vec<(f64, f64)>, asks: Vec<(f64, f64)>) -> Self {
+        Self {
+            sequence,
+            checksum: top_checksum(&bids, &asks),
+            microprice: microprice(&bids, &asks),
+            depth_weighted_mid: depth_weighted_mid(&bids, &asks, DEPTH_WEIGHTED_LEVELS),
+            bids,
+            asks,
         }
     }
 }
 
+/// The book checksum of levels listed best first, over the top
+/// `CHECKSUM_LEVELS` of each side
+pub fn top_checksum(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> u32 {
+    book_checksum(&bids[..bids.len().min(CHECKSUM_LEVELS)], &asks[..asks.len().min(CHECKSUM_LEVELS)])
+}
+
+/// Collapse levels into buckets of `bucket_size`, best first. Bids round
+/// down to their bucket and asks round up, so a bucket never looks better
+/// than the levels in it.
+pub fn aggregate_levels(levels: &[(f64, f64)], bucket_size: f64, is_bid: bool) -> Vec<(f64, f64)> {
+    let mut buckets: Vec<(f64, f64)> = Vec::new();
+    for (price, size) in levels {
+        // Tolerate prices a hair off a bucket boundary
+        let ratio = price / bucket_size;
+        let index = if is_bid { (ratio + 1e-9).floor() } else { (ratio - 1e-9).ceil() };
+        let bucket = index * bucket_size;
+        match buckets.last_mut() {
+            Some(last) if last.0 == bucket => last.1 += size,
+            _ => buckets.push((bucket, *size)),
+        }
+    }
+    buckets
+}
+
+/// How much of an order to fill
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FillTarget {
+    Size(f64),
+    /// In quote currency
+    Notional(f64),
+}
+
+/// The result of filling an order against resting levels
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LevelWalk {
+    pub filled_size: f64,
+    pub filled_notional: f64,
+    /// Levels taken from, including a partly filled last one
+    pub levels_consumed: usize,
+    pub worst_price: Option<f64>,
+    /// False if the levels ran out first
+    pub complete: bool,
+}
+
+impl LevelWalk {
+    pub fn avg_price(&self) -> Option<f64> {
+        (self.filled_size > 0.0).then(|| self.filled_notional / self.filled_size)
+    }
+
+    /// How far the average fill is from `reference`, in bps, positive when worse
+    pub fn slippage_bps(&self, reference: f64, is_buy: bool) -> Option<f64> {
+        let avg = self.avg_price()?;
+        let worse = if is_buy { avg - reference } else { reference - avg };
+        Some(worse / reference * 10_000.0)
+    }
+}
+
+/// Fill `target` against `levels`, best first, as a market order would
+pub fn walk_levels(levels: &[(f64, f64)], target: FillTarget) -> LevelWalk {
+    let mut walk = LevelWalk::default();
+    for &(price, size) in levels {
+        let remaining = match target {
+            FillTarget::Size(total) => total - walk.filled_size,
+            FillTarget::Notional(total) => (total - walk.filled_notional) / price,
+        };
+        if remaining <= 0.0 {
+            break;
+        }
+        let fill = remaining.min(size);
+        walk.filled_size += fill;
+        walk.filled_notional += fill * price;
+        walk.levels_consumed += 1;
+        walk.worst_price = Some(price);
+    }
+    walk.complete = match target {
+        FillTarget::Size(total) => walk.filled_size >= total - 1e-12,
+        FillTarget::Notional(total) => walk.filled_notional >= total - 1e-9,
+    };
+    walk
+}
+
+/// Resting notional on both sides within `bps` of the mid; zero while
+/// either side is empty
+pub fn notional_within(bids: &[(f64, f64)], asks: &[(f64, f64)], bps: f64) -> f64 {
+    let (Some((bid, _)), Some((ask, _))) = (bids.first(), asks.first()) else { return 0.0 };
+    let mid = (bid + ask) / 2.0;
+    let (low, high) = (mid * (1.0 - bps / 10_000.0), mid * (1.0 + bps / 10_000.0));
+    let notional = |levels: &[(f64, f64)], inside: &dyn Fn(f64) -> bool| -> f64 {
+        levels.iter().take_while(|(price, _)| inside(*price)).map(|(price, size)| price * size).sum()
+    };
+    notional(bids, &|price| price >= low) + notional(asks, &|price| price <= high)
+}
+
+/// Best bid and ask weighted by the opposite side's size, leaning toward
+/// the side that is closer to being traded through
+pub fn microprice(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> Option<f64> {
+    let (&(bid, bid_size), &(ask, ask_size)) = (bids.first()?, asks.first()?);
+    let total = bid_size + ask_size;
+    (total > 0.0).then(|| (bid * ask_size + ask * bid_size) / total)
+}
+
+/// Mean of each side's size-weighted price over its top `levels` levels
+pub fn depth_weighted_mid(bids: &[(f64, f64)], asks: &[(f64, f64)], levels: usize) -> Option<f64> {
+    let side_price = |side: &[(f64, f64)]| {
+        let top = &side[..side.len().min(levels)];
+        let size: f64 = top.iter().map(|(_, size)| size).sum();
+        (size > 0.0).then(|| top.iter().map(|(price, size)| price * size).sum::<f64>() / size)
+    };
+    Some((side_price(bids)? + side_price(asks)?) / 2.0)
+}
+
 pub struct FastOrderbook {
     pub market_id: u32,
     pub symbol: String,
     
+    // Prices are snapped to this grid and levels keyed by tick
+    tick_size: TickSize,
+    
     // Pre-allocated arrays for price levels
     bid_levels: RwLock<Vec<PriceLevel>>,
     ask_levels: RwLock<Vec<PriceLevel>>,
     
+    // Side (true for bids) and level of every resting order, so removals
+    // need only the order id. Never locked together with a side.
+    order_index: Mutex<HashMap<u64, (bool, Ticks)>>,
+    
     // Atomic counters for lock-free stats
     pub sequence: AtomicU64,
     pub bid_count: AtomicUsize,
@@ -77,19 +254,77 @@ pub struct FastOrderbook {
     oracle_price: RwLock<Option<f64>>,
     cex_prices: RwLock<Option<CEXPrices>>,
     last_trade_price: RwLock<Option<f64>>,
+    
+    // Every applied mutation, with bounded retention
+    event_log: Mutex<EventLog>,
+    
+    // Feed anomalies absorbed by the book's writer
+    pub integrity: IntegrityCounters,
+    
+    // What readers see; replaced by the book's writer after each batch
+    published: RwLock<Arc<BookSnapshot>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum OrderbookDelta {
     AddBid { price: f64, size: f64, order_id: u64 },
     AddAsk { price: f64, size: f64, order_id: u64 },
     RemoveBid { price: f64, order_id: u64 },
     RemoveAsk { price: f64, order_id: u64 },
     Clear,
+    /// A resting order's size changed in place; `size` is its new size
+    ChangeLevel { is_buy: bool, price: f64, size: f64, order_id: u64 },
+}
+
+/// A delta tagged with the book sequence number its mutation produced.
+///
+/// The sequence is assigned while the side's write lock is held, so for a
+/// single writer the order of sequences is exactly the order in which the
+/// mutations were applied. Consecutive deltas from one book differ by one.
+/// Every sequenced delta is also appended to the book's event log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SequencedDelta {
+    pub sequence: u64,
+    pub delta: OrderbookDelta,
+    /// Size left at the delta's price level afterwards; zero once the level
+    /// is gone and for `Clear`
+    #[serde(default)]
+    pub level_size: f64,
+    /// The order's place in its level's queue, 0 at the front. For removals,
+    /// where it was before leaving.
+    #[serde(default)]
+    pub queue_position: usize,
+    /// Size of the order added or removed; zero for `Clear`
+    #[serde(default)]
+    pub order_size: f64,
+    /// The delta changed the best level of its side
+    #[serde(default)]
+    pub at_touch: bool,
+}
+
+impl SequencedDelta {
+    /// Action, side and price of the level change a client applies for
+    /// this delta: "add", "change", "remove" or "clear", and "B" or "A"
+    pub fn level_action(&self) -> (&'static str, &'static str, f64) {
+        match self.delta {
+            // A new level holds exactly the order that created it
+            OrderbookDelta::AddBid { price, size, .. } => (if self.level_size == size { "add" } else { "change" }, "B", price),
+            OrderbookDelta::AddAsk { price, size, .. } => (if self.level_size == size { "add" } else { "change" }, "A", price),
+            OrderbookDelta::RemoveBid { price, .. } => (if self.level_size == 0.0 { "remove" } else { "change" }, "B", price),
+            OrderbookDelta::RemoveAsk { price, .. } => (if self.level_size == 0.0 { "remove" } else { "change" }, "A", price),
+            OrderbookDelta::Clear => ("clear", "", 0.0),
+            OrderbookDelta::ChangeLevel { is_buy, price, .. } => ("change", if is_buy { "B" } else { "A" }, price),
+        }
+    }
 }
 
 impl FastOrderbook {
     pub fn new(market_id: u32, symbol: String) -> Self {
+        Self::with_tick_size(market_id, symbol, TickSize::default())
+    }
+    
+    /// A book whose prices are whole multiples of `tick_size`
+    pub fn with_tick_size(market_id: u32, symbol: String, tick_size: TickSize) -> Self {
         // Extract base currency from TradableProduct format for impact notional
         let base_currency = if symbol.contains('/') {
             symbol.split('/').next().unwrap_or(&symbol).to_string()
@@ -107,6 +342,7 @@ impl FastOrderbook {
         Self {
             market_id,
             symbol,
+            tick_size,
             bid_levels: RwLock::new(Vec::with_capacity(MAX_PRICE_LEVELS)),
             ask_levels: RwLock::new(Vec::with_capacity(MAX_PRICE_LEVELS)),
             sequence: AtomicU64::new(0),
@@ -125,103 +361,169 @@ impl FastOrderbook {
             oracle_price: RwLock::new(None),
             cex_prices: RwLock::new(None),
             last_trade_price: RwLock::new(None),
+            event_log: Mutex::new(EventLog::new(DEFAULT_MAX_EVENTS)),
+            integrity: IntegrityCounters::default(),
+            published: RwLock::new(Arc::new(BookSnapshot::default())),
+            order_index: Mutex::new(HashMap::new()),
         }
     }
     
-    pub fn add_order(&self, order: Order, is_buy: bool) -> OrderbookDelta {
-        self.sequence.fetch_add(1, Ordering::Relaxed);
+    pub fn tick_size(&self) -> TickSize {
+        self.tick_size
+    }
+    
+    /// The tick of `price` and the price on the grid it stands for
+    fn snap(&self, price: f64) -> (Ticks, f64) {
+        let ticks = self.tick_size.to_ticks(price);
+        (ticks, self.tick_size.to_price(ticks))
+    }
+    
+    /// Add an order at its price snapped to the tick grid. Order ids must be
+    /// unique among resting orders.
+    pub fn add_order(&self, order: Order, is_buy: bool) -> SequencedDelta {
         self.total_orders.fetch_add(1, Ordering::Relaxed);
+        let (ticks, price) = self.snap(order.price);
+        self.order_index.lock().insert(order.id, (is_buy, ticks));
+        let order = Order { price, ..order };
         
         if is_buy {
             let mut bids = self.bid_levels.write();
             
             // Find or create price level
-            let pos = bids.binary_search_by(|level| {
-                level.price.partial_cmp(&order.price).unwrap().reverse()
-            });
+            let pos = bids.binary_search_by(|level| level.ticks.cmp(&ticks).reverse());
             
-            match pos {
-                Ok(idx) => {
-                    bids[idx].add_order(order);
-                }
+            let (queue_position, level_size) = match pos {
+                Ok(idx) => (bids[idx].add_order(order), bids[idx].total_size),
                 Err(idx) => {
-                    let mut level = PriceLevel::new(order.price);
-                    level.add_order(order);
+                    let mut level = PriceLevel::new(ticks, price);
+                    let queue_position = level.add_order(order);
+                    let level_size = level.total_size;
                     bids.insert(idx, level);
                     self.bid_count.fetch_add(1, Ordering::Relaxed);
+                    (queue_position, level_size)
                 }
-            }
+            };
             
-            OrderbookDelta::AddBid {
+            self.record(OrderbookDelta::AddBid {
                 price: order.price,
                 size: order.size,
                 order_id: order.id,
-            }
+            }, level_size, queue_position, order.size, matches!(pos, Ok(0) | Err(0)))
         } else {
             let mut asks = self.ask_levels.write();
             
             // Find or create price level
-            let pos = asks.binary_search_by(|level| {
-                level.price.partial_cmp(&order.price).unwrap()
-            });
+            let pos = asks.binary_search_by(|level| level.ticks.cmp(&ticks));
             
-            match pos {
-                Ok(idx) => {
-                    asks[idx].add_order(order);
-                }
+            let (queue_position, level_size) = match pos {
+                Ok(idx) => (asks[idx].add_order(order), asks[idx].total_size),
                 Err(idx) => {
-                    let mut level = PriceLevel::new(order.price);
-                    level.add_order(order);
+                    let mut level = PriceLevel::new(ticks, price);
+                    let queue_position = level.add_order(order);
+                    let level_size = level.total_size;
                     asks.insert(idx, level);
                     self.ask_count.fetch_add(1, Ordering::Relaxed);
+                    (queue_position, level_size)
                 }
-            }
+            };
             
-            OrderbookDelta::AddAsk {
+            self.record(OrderbookDelta::AddAsk {
                 price: order.price,
                 size: order.size,
                 order_id: order.id,
-            }
+            }, level_size, queue_position, order.size, matches!(pos, Ok(0) | Err(0)))
         }
     }
     
-    pub fn remove_order(&self, order_id: u64, price: f64, is_buy: bool) -> Option<OrderbookDelta> {
-        self.sequence.fetch_add(1, Ordering::Relaxed);
+    /// The front order of each side's best level, while the bid reaches the
+    /// ask. Reads the live levels, not the published snapshot.
+    pub fn crossing(&self) -> Option<(Order, Order)> {
+        let bids = self.bid_levels.read();
+        let asks = self.ask_levels.read();
+        let (bid, ask) = (bids.first()?, asks.first()?);
+        // Empty levels are removed, so every level has a front order
+        (bid.ticks >= ask.ticks).then(|| (bid.orders[0], ask.orders[0]))
+    }
+    
+    /// Remove a resting order by id, wherever it rests. The sequence only
+    /// advances when an order was found, so every increment corresponds to
+    /// exactly one emitted delta.
+    pub fn remove_order(&self, order_id: u64) -> Option<SequencedDelta> {
+        self.take_order(order_id).map(|(delta, _)| delta)
+    }
+    
+    /// Change a resting order's size and price by id. At the same price the
+    /// order is updated in place, keeping its queue position, with one
+    /// `ChangeLevel` delta. A new price moves it to the back of its new
+    /// level: a removal, then an add. Empty when the order isn't resting.
+    pub fn modify_order(&self, order_id: u64, new_size: f64, new_price: f64) -> Vec<SequencedDelta> {
+        let Some((is_buy, ticks)) = self.order_index.lock().get(&order_id).copied() else {
+            return Vec::new();
+        };
+        let (new_ticks, price) = self.snap(new_price);
+        if new_ticks != ticks {
+            return match self.take_order(order_id) {
+                Some((removed, order)) => vec![removed, self.add_order(Order { price, size: new_size, ..order }, is_buy)],
+                None => Vec::new(),
+            };
+        }
         
+        let mut levels = if is_buy { self.bid_levels.write() } else { self.ask_levels.write() };
+        let Ok(idx) = levels.binary_search_by(|level| {
+            let ord = level.ticks.cmp(&ticks);
+            if is_buy { ord.reverse() } else { ord }
+        }) else {
+            return Vec::new();
+        };
+        let Some(queue_position) = levels[idx].resize_order(order_id, new_size) else {
+            return Vec::new();
+        };
+        let delta = OrderbookDelta::ChangeLevel { is_buy, price, size: new_size, order_id };
+        vec![self.record(delta, levels[idx].total_size, queue_position, new_size, idx == 0)]
+    }
+    
+    /// Take a resting order out of the book, with the delta recording it
+    fn take_order(&self, order_id: u64) -> Option<(SequencedDelta, Order)> {
+        let (is_buy, ticks) = self.order_index.lock().remove(&order_id)?;
+        let price = self.tick_size.to_price(ticks);
         if is_buy {
             let mut bids = self.bid_levels.write();
             
-            if let Ok(idx) = bids.binary_search_by(|level| {
-                level.price.partial_cmp(&price).unwrap().reverse()
-            }) {
-                if bids[idx].remove_order(order_id) {
+            if let Ok(idx) = bids.binary_search_by(|level| level.ticks.cmp(&ticks).reverse()) {
+                if let Some((queue_position, removed)) = bids[idx].remove_order(order_id) {
                     self.total_orders.fetch_sub(1, Ordering::Relaxed);
                     
                     // Remove empty level
-                    if bids[idx].orders.is_empty() {
+                    let level_size = if bids[idx].orders.is_empty() {
                         bids.remove(idx);
                         self.bid_count.fetch_sub(1, Ordering::Relaxed);
-                    }
+                        0.0
+                    } else {
+                        bids[idx].total_size
+                    };
                     
-                    return Some(OrderbookDelta::RemoveBid { price, order_id });
+                    let delta = OrderbookDelta::RemoveBid { price, order_id };
+                    return Some((self.record(delta, level_size, queue_position, removed.size, idx == 0), removed));
                 }
             }
         } else {
             let mut asks = self.ask_levels.write();
             
-            if let Ok(idx) = asks.binary_search_by(|level| {
-                level.price.partial_cmp(&price).unwrap()
-            }) {
-                if asks[idx].remove_order(order_id) {
+            if let Ok(idx) = asks.binary_search_by(|level| level.ticks.cmp(&ticks)) {
+                if let Some((queue_position, removed)) = asks[idx].remove_order(order_id) {
                     self.total_orders.fetch_sub(1, Ordering::Relaxed);
                     
                     // Remove empty level
-                    if asks[idx].orders.is_empty() {
+                    let level_size = if asks[idx].orders.is_empty() {
                         asks.remove(idx);
                         self.ask_count.fetch_sub(1, Ordering::Relaxed);
-                    }
+                        0.0
+                    } else {
+                        asks[idx].total_size
+                    };
                     
-                    return Some(OrderbookDelta::RemoveAsk { price, order_id });
+                    let delta = OrderbookDelta::RemoveAsk { price, order_id };
+                    return Some((self.record(delta, level_size, queue_position, removed.size, idx == 0), removed));
                 }
             }
         }
@@ -229,72 +531,188 @@ impl FastOrderbook {
         None
     }
     
-    pub fn get_snapshot(&self, depth: usize) -> (Vec<(f64, f64)>, Vec<(f64, f64)>) {
+    /// Assign the next sequence to an applied mutation and append it to the
+    /// event log. Callers must hold the write lock of the side being mutated.
+    fn record(
+        &self,
+        delta: OrderbookDelta,
+        level_size: f64,
+        queue_position: usize,
+        order_size: f64,
+        at_touch: bool,
+    ) -> SequencedDelta {
+        let delta = SequencedDelta {
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed) + 1,
+            delta,
+            level_size,
+            queue_position,
+            order_size,
+            at_touch,
+        };
+        
+        let timestamp_ns = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        self.event_log.lock().append(BookEvent::from_delta(&delta, timestamp_ns));
+        
+        delta
+    }
+    
+    /// The book's event log. Deltas returned by mutations are exactly the
+    /// events appended here, so replay, history and persistence all derive
+    /// from this log.
+    pub fn event_log(&self) -> &Mutex<EventLog> {
+        &self.event_log
+    }
+    
+    /// Retain at most `max_events` in the event log, e.g. as the market's
+    /// tier changes
+    pub fn set_event_retention(&self, max_events: usize) {
+        self.event_log.lock().set_max_events(max_events);
+    }
+    
+    /// Replace the book with the state recorded in `log` and keep the log
+    /// for replay. Only for books whose writer hasn't started.
+    pub fn restore(&self, log: EventLog) {
+        let state = log.current_state();
+        let mut orders: Vec<_> = state.orders.iter().collect();
+        // Order ids increase over time, so this approximates queue priority
+        orders.sort_unstable_by_key(|(order_id, _)| **order_id);
+        
+        let mut index = HashMap::with_capacity(orders.len());
+        let mut bids = self.bid_levels.write();
+        let mut asks = self.ask_levels.write();
+        bids.clear();
+        asks.clear();
+        for (order_id, resting) in orders {
+            let (ticks, price) = self.snap(resting.price);
+            let order = Order { id: *order_id, price, size: resting.size, timestamp: 0 };
+            index.insert(*order_id, (resting.is_buy, ticks));
+            let levels = if resting.is_buy { &mut *bids } else { &mut *asks };
+            let pos = levels.binary_search_by(|level| {
+                let ord = level.ticks.cmp(&ticks);
+                if resting.is_buy { ord.reverse() } else { ord }
+            });
+            match pos {
+                Ok(idx) => {
+                    levels[idx].add_order(order);
+                }
+                Err(idx) => {
+                    let mut level = PriceLevel::new(ticks, price);
+                    level.add_order(order);
+                    levels.insert(idx, level);
+                }
+            }
+        }
+        
+        self.bid_count.store(bids.len(), Ordering::Relaxed);
+        self.ask_count.store(asks.len(), Ordering::Relaxed);
+        self.total_orders.store(state.orders.len(), Ordering::Relaxed);
+        self.sequence.store(log.last_sequence(), Ordering::Release);
+        drop(bids);
+        drop(asks);
+        *self.order_index.lock() = index;
+        
+        *self.event_log.lock() = log;
+        self.publish();
+    }
+    
+    /// Copy the current levels into a new snapshot for readers. Only the
+    /// book's writer calls this, once its mutations form a consistent state.
+    pub fn publish(&self) {
         let bids = self.bid_levels.read();
         let asks = self.ask_levels.read();
+        let sequence = self.sequence.load(Ordering::Acquire);
+        let bid_levels = bids.iter().map(|level| (level.price, level.total_size)).collect();
+        let ask_levels = asks.iter().map(|level| (level.price, level.total_size)).collect();
+        drop(bids);
+        drop(asks);
         
-        let bid_snapshot: Vec<_> = bids
-            .iter()
-            .take(depth)
-            .map(|level| (level.price, level.total_size))
-            .collect();
-            
-        let ask_snapshot: Vec<_> = asks
-            .iter()
-            .take(depth)
-            .map(|level| (level.price, level.total_size))
-            .collect();
-            
-        (bid_snapshot, ask_snapshot)
+        let snapshot = BookSnapshot::new(sequence, bid_levels, ask_levels);
+        
+        *self.published.write() = Arc::new(snapshot);
     }
     
-    pub fn get_best_bid_ask(&self) -> Option<(f64, f64)> {
+    /// Heap held by the levels, the order index and the published snapshot,
+    /// in resting orders.
+    /// The event log is reported separately.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let side = |levels: &Vec<PriceLevel>| {
+            let spilled: usize = levels
+                .iter()
+                .filter(|level| level.orders.spilled())
+                .map(|level| level.orders.capacity() * std::mem::size_of::<Order>())
+                .sum();
+            levels.capacity() * std::mem::size_of::<PriceLevel>() + spilled
+        };
+        let published = self.snapshot();
+        let published_bytes = (published.bids.capacity() + published.asks.capacity()) * std::mem::size_of::<(f64, f64)>();
+        let index_bytes = self.order_index.lock().capacity() * std::mem::size_of::<(u64, (bool, Ticks))>();
+        let bytes = side(&self.bid_levels.read()) + side(&self.ask_levels.read()) + index_bytes + published_bytes;
+        MemoryUsage::new(self.total_orders.load(Ordering::Relaxed), bytes)
+    }
+    
+    /// Every resting order, for order-by-order consumers. Unlike `snapshot`
+    /// this may fall inside a batch; `sequence` says exactly where.
+    pub fn orders(&self) -> OrderSnapshot {
         let bids = self.bid_levels.read();
         let asks = self.ask_levels.read();
+        OrderSnapshot {
+            sequence: self.sequence.load(Ordering::Acquire),
+            bids: bids.iter().flat_map(|level| level.orders.iter().copied()).collect(),
+            asks: asks.iter().flat_map(|level| level.orders.iter().copied()).collect(),
+        }
+    }
+    
+    /// The latest published state. Never observes a half-applied batch.
+    pub fn snapshot(&self) -> Arc<BookSnapshot> {
+        self.published.read().clone()
+    }
+    
+    pub fn get_snapshot(&self, depth: usize) -> BookLevels {
+        let snapshot = self.snapshot();
+        (
+            snapshot.bids.iter().take(depth).copied().collect(),
+            snapshot.asks.iter().take(depth).copied().collect(),
+        )
+    }
+    
+    pub fn get_best_bid_ask(&self) -> Option<(f64, f64)> {
+        let snapshot = self.snapshot();
         
-        match (bids.first(), asks.first()) {
-            (Some(bid), Some(ask)) => Some((bid.price, ask.price)),
+        match (snapshot.bids.first(), snapshot.asks.first()) {
+            (Some(bid), Some(ask)) => Some((bid.0, ask.0)),
             _ => None,
         }
     }
     
-    pub fn clear(&self) {
-        self.bid_levels.write().clear();
-        self.ask_levels.write().clear();
+    pub fn clear(&self) -> SequencedDelta {
+        let mut bids = self.bid_levels.write();
+        let mut asks = self.ask_levels.write();
+        bids.clear();
+        asks.clear();
         self.bid_count.store(0, Ordering::Relaxed);
         self.ask_count.store(0, Ordering::Relaxed);
         self.total_orders.store(0, Ordering::Relaxed);
-        self.sequence.fetch_add(1, Ordering::Relaxed);
+        self.order_index.lock().clear();
+        self.record(OrderbookDelta::Clear, 0.0, 0, 0.0, true)
     }
     
     pub fn update_mark_price(&self) -> Option<MarkPriceResult> {
-        let bids = self.bid_levels.read();
-        let asks = self.ask_levels.read();
+        let snapshot = self.snapshot();
         
-        if bids.is_empty() || asks.is_empty() {
+        if snapshot.bids.is_empty() || snapshot.asks.is_empty() {
             return None;
         }
         
-        // Get orderbook data for mark price calculation
-        let bid_levels: Vec<(f64, f64)> = bids
-            .iter()
-            .take(20)  // Use top 20 levels for impact calculation
-            .map(|level| (level.price, level.total_size))
-            .collect();
-            
-        let ask_levels: Vec<(f64, f64)> = asks
-            .iter()
-            .take(20)
-            .map(|level| (level.price, level.total_size))
-            .collect();
-        
-        // Release read locks before taking write lock
-        drop(bids);
-        drop(asks);
+        // Use top 20 levels for impact calculation
+        let bid_levels = &snapshot.bids[..snapshot.bids.len().min(20)];
+        let ask_levels = &snapshot.asks[..snapshot.asks.len().min(20)];
         
         // Calculate new mark price
         let mut calc = self.mark_price_calc.write();
-        let mark_price_result = calc.calculate_mark_price(&bid_levels, &ask_levels);
+        let mark_price_result = calc.calculate_mark_price(bid_levels, ask_levels);
         
         // Store result
         if let Some(ref result) = mark_price_result {
@@ -329,19 +747,7 @@ impl FastOrderbook {
     }
     
     pub fn calculate_hl_mark_price(&self) -> Option<HLMarkPriceResult> {
-        let bids = self.bid_levels.read();
-        let asks = self.ask_levels.read();
-        
-        if bids.is_empty() || asks.is_empty() {
-            return None;
-        }
-        
-        let best_bid = bids[0].price;
-        let best_ask = asks[0].price;
-        
-        // Release read locks
-        drop(bids);
-        drop(asks);
+        let (best_bid, best_ask) = self.get_best_bid_ask()?;
         
         let inputs = MarkPriceInputs {
             best_bid,
@@ -359,6 +765,15 @@ impl FastOrderbook {
         Some(result)
     }
     
+    /// The mark price EMAs, for state snapshots
+    pub fn mark_ema_state(&self) -> MarkEmaState {
+        self.hl_mark_price_calc.read().ema_state()
+    }
+    
+    pub fn restore_mark_ema(&self, state: MarkEmaState) {
+        self.hl_mark_price_calc.write().restore_ema(state);
+    }
+    
     pub fn get_hl_mark_price(&self) -> Option<HLMarkPriceResult> {
         self.last_hl_mark_price.read().clone()
     }
@@ -378,4 +793,425 @@ impl FastOrderbook {
     pub fn get_cex_prices(&self) -> Option<CEXPrices> {
         self.cex_prices.read().clone()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    
+    fn order(id: u64, price: f64, size: f64) -> Order {
+        Order { id, price, size, timestamp: 0 }
+    }
+    
+    #[test]
+    fn test_delta_sequences_follow_application_order() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        
+        let deltas = [
+            book.add_order(order(1, 100.0, 1.0), true),
+            book.add_order(order(2, 101.0, 1.0), false),
+            book.remove_order(1).unwrap(),
+            book.add_order(order(3, 99.0, 2.0), true),
+        ];
+        
+        let sequences: Vec<u64> = deltas.iter().map(|d| d.sequence).collect();
+        assert_eq!(sequences, vec![1, 2, 3, 4]);
+        assert!(matches!(deltas[2].delta, OrderbookDelta::RemoveBid { order_id: 1, .. }));
+        assert_eq!(book.sequence.load(Ordering::Relaxed), 4);
+        
+        // Each delta carries what is left at its level
+        let level_sizes: Vec<f64> = deltas.iter().map(|d| d.level_size).collect();
+        assert_eq!(level_sizes, vec![1.0, 1.0, 0.0, 2.0]);
+        assert_eq!(book.add_order(order(4, 99.0, 0.5), true).level_size, 2.5);
+        assert_eq!(book.remove_order(3).unwrap().level_size, 0.5);
+    }
+    
+    #[test]
+    fn test_at_touch_marks_best_level_changes() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        
+        assert!(book.add_order(order(1, 100.0, 1.0), true).at_touch);
+        assert!(!book.add_order(order(2, 99.0, 1.0), true).at_touch);
+        assert!(book.add_order(order(3, 100.0, 1.0), true).at_touch);
+        assert!(book.add_order(order(4, 101.0, 1.0), false).at_touch);
+        assert!(!book.add_order(order(5, 102.0, 1.0), false).at_touch);
+        
+        assert!(!book.remove_order(2).unwrap().at_touch);
+        assert!(book.remove_order(1).unwrap().at_touch);
+        assert!(!book.remove_order(5).unwrap().at_touch);
+    }
+    
+    #[test]
+    fn test_queue_positions() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        
+        let positions: Vec<usize> = [(1, 1.0), (2, 2.0), (3, 3.0)]
+            .into_iter()
+            .map(|(id, size)| book.add_order(order(id, 100.0, size), false).queue_position)
+            .collect();
+        assert_eq!(positions, vec![0, 1, 2]);
+        book.add_order(order(4, 99.0, 1.0), true);
+        
+        let removed = book.remove_order(2).unwrap();
+        assert_eq!((removed.queue_position, removed.order_size), (1, 2.0));
+        
+        // Orders behind a removal move up
+        let orders = book.orders();
+        assert_eq!(orders.sequence, 5);
+        assert_eq!(orders.asks.iter().map(|o| o.id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(orders.bids.iter().map(|o| o.id).collect::<Vec<_>>(), vec![4]);
+    }
+    
+    #[test]
+    fn test_missed_remove_does_not_advance_sequence() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        
+        book.add_order(order(1, 100.0, 1.0), true);
+        assert!(book.remove_order(42).is_none());
+        assert!(book.remove_order(1).is_some());
+        assert!(book.remove_order(1).is_none());
+        
+        let next = book.add_order(order(2, 100.0, 1.0), true);
+        assert_eq!(next.sequence, 3);
+    }
+    
+    #[test]
+    fn test_removals_resolve_by_order_id() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        book.add_order(order(1, 100.0, 1.0), true);
+        book.add_order(order(2, 101.0, 2.0), false);
+        book.add_order(order(3, 101.0, 1.0), false);
+        
+        let removed = book.remove_order(3).unwrap();
+        assert_eq!(removed.level_action(), ("change", "A", 101.0));
+        assert_eq!((removed.order_size, removed.level_size, removed.queue_position), (1.0, 2.0, 1));
+        
+        // A restored book knows the orders of its log and no others
+        let restored = FastOrderbook::new(0, "BTC/USD".to_string());
+        restored.add_order(order(9, 90.0, 1.0), true);
+        restored.restore(std::mem::replace(&mut *book.event_log().lock(), EventLog::new(DEFAULT_MAX_EVENTS)));
+        assert!(restored.remove_order(9).is_none());
+        assert_eq!(restored.remove_order(2).unwrap().level_action(), ("remove", "A", 101.0));
+        
+        // A cleared book forgets its orders
+        book.clear();
+        assert!(book.remove_order(1).is_none());
+    }
+    
+    #[test]
+    fn test_checksum_covers_top_levels() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        for id in 0..12 {
+            book.add_order(order(id, 100.0 - id as f64, 1.0), true);
+        }
+        book.add_order(order(20, 101.0, 2.0), false);
+        book.publish();
+        let published = book.snapshot();
+        assert_eq!(published.checksum, book_checksum(&published.bids[..CHECKSUM_LEVELS], &published.asks));
+        
+        // Levels below the top ten don't change it; anything above does
+        book.add_order(order(30, 80.0, 1.0), true);
+        book.publish();
+        assert_eq!(book.snapshot().checksum, published.checksum);
+        book.add_order(order(31, 101.0, 0.5), false);
+        book.publish();
+        assert_ne!(book.snapshot().checksum, published.checksum);
+    }
+    
+    #[test]
+    fn test_modify_updates_in_place_or_moves() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        book.add_order(order(1, 100.0, 1.0), true);
+        book.add_order(order(2, 100.0, 2.0), true);
+        book.add_order(order(3, 100.0, 3.0), true);
+        
+        // Same price: the order keeps its place
+        let modified = book.modify_order(2, 0.5, 100.0);
+        assert_eq!(modified.len(), 1);
+        assert!(matches!(modified[0].delta, OrderbookDelta::ChangeLevel { is_buy: true, size, order_id: 2, .. } if size == 0.5));
+        assert_eq!(modified[0].level_action(), ("change", "B", 100.0));
+        assert_eq!((modified[0].sequence, modified[0].level_size, modified[0].queue_position), (4, 4.5, 1));
+        assert_eq!(book.orders().bids.iter().map(|o| (o.id, o.size)).collect::<Vec<_>>(), vec![(1, 1.0), (2, 0.5), (3, 3.0)]);
+        
+        // New price: out of the old level, to the back of the new one
+        let moved = book.modify_order(1, 1.0, 101.0);
+        assert_eq!(moved.iter().map(|d| d.level_action()).collect::<Vec<_>>(), vec![("change", "B", 100.0), ("add", "B", 101.0)]);
+        assert!(moved[1].at_touch);
+        assert!(book.modify_order(42, 1.0, 100.0).is_empty());
+        
+        // The event log replays modifies to the same book
+        book.publish();
+        let state = book.event_log().lock().current_state();
+        assert_eq!(state.levels(10).0, book.snapshot().bids);
+        assert!(book.remove_order(1).is_some());
+    }
+    
+    #[test]
+    fn test_event_log_matches_book() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        
+        book.add_order(order(1, 100.0, 1.0), true);
+        book.add_order(order(2, 100.0, 0.5), true);
+        book.add_order(order(3, 101.0, 2.0), false);
+        book.remove_order(1);
+        book.publish();
+        
+        let log = book.event_log().lock();
+        assert_eq!(log.last_sequence(), book.sequence.load(Ordering::Relaxed));
+        assert_eq!(log.current_state().levels(10), book.get_snapshot(10));
+    }
+    
+    #[test]
+    fn test_readers_only_see_published_state() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        
+        book.add_order(order(1, 100.0, 1.0), true);
+        book.add_order(order(2, 101.0, 1.0), false);
+        assert_eq!(book.get_best_bid_ask(), None);
+        
+        book.publish();
+        let published = book.snapshot();
+        book.remove_order(1);
+        assert_eq!(published.sequence, 2);
+        assert_eq!(book.get_best_bid_ask(), Some((100.0, 101.0)));
+        
+        book.publish();
+        assert_eq!(book.snapshot().bids, vec![]);
+        assert_eq!(published.bids, vec![(100.0, 1.0)]);
+    }
+    
+    #[test]
+    fn test_aggregate_levels() {
+        let bids = [(105_009.0, 1.0), (105_001.0, 2.0), (105_000.0, 0.5), (104_990.5, 1.0)];
+        assert_eq!(
+            aggregate_levels(&bids, 10.0, true),
+            vec![(105_000.0, 3.5), (104_990.0, 1.0)]
+        );
+        
+        let asks = [(105_000.0, 1.0), (105_000.5, 2.0), (105_010.0, 1.0), (105_021.0, 4.0)];
+        assert_eq!(
+            aggregate_levels(&asks, 10.0, false),
+            vec![(105_000.0, 1.0), (105_010.0, 3.0), (105_030.0, 4.0)]
+        );
+        
+        // Float noise on a boundary stays in that bucket
+        assert_eq!(aggregate_levels(&[(0.3, 1.0)], 0.1, true), vec![(0.30000000000000004, 1.0)]);
+    }
+    
+    #[test]
+    fn test_walk_levels() {
+        let asks = [(100.0, 1.0), (101.0, 2.0), (103.0, 1.0)];
+        let walk = walk_levels(&asks, FillTarget::Size(2.0));
+        assert_eq!((walk.filled_size, walk.filled_notional, walk.levels_consumed), (2.0, 201.0, 2));
+        assert_eq!((walk.worst_price, walk.complete), (Some(101.0), true));
+        assert!((walk.slippage_bps(100.0, true).unwrap() - 50.0).abs() < 1e-9);
+        
+        // By notional: 100 + 202 + 51.5
+        let walk = walk_levels(&asks, FillTarget::Notional(353.5));
+        assert!((walk.filled_size - 3.5).abs() < 1e-12);
+        assert_eq!((walk.levels_consumed, walk.complete), (3, true));
+        
+        // More than the book holds fills what there is
+        let walk = walk_levels(&asks, FillTarget::Size(10.0));
+        assert_eq!((walk.filled_size, walk.complete), (4.0, false));
+        
+        // Selling into bids is worse below the reference
+        let bids = [(99.0, 1.0), (98.0, 1.0)];
+        assert!((walk_levels(&bids, FillTarget::Size(2.0)).slippage_bps(99.0, false).unwrap() - 50.505050).abs() < 1e-3);
+        assert_eq!(walk_levels(&[], FillTarget::Size(1.0)).avg_price(), None);
+    }
+    
+    #[test]
+    fn test_notional_within() {
+        // Mid 100: 1% is 99..101
+        let bids = [(99.5, 2.0), (99.0, 1.0), (98.0, 10.0)];
+        let asks = [(100.5, 1.0), (102.0, 10.0)];
+        assert_eq!(notional_within(&bids, &asks, 100.0), 199.0 + 99.0 + 100.5);
+        assert_eq!(notional_within(&bids, &[], 100.0), 0.0);
+    }
+    
+    #[test]
+    fn test_microprice_and_depth_weighted_mid() {
+        let book = FastOrderbook::new(0, "BTC/USD".to_string());
+        book.publish();
+        assert_eq!(book.snapshot().microprice, None);
+        
+        book.add_order(order(1, 100.0, 3.0), true);
+        book.add_order(order(2, 99.0, 1.0), true);
+        book.add_order(order(3, 102.0, 1.0), false);
+        book.publish();
+        
+        let snapshot = book.snapshot();
+        // Heavy bid pulls the microprice toward the ask
+        assert_eq!(snapshot.microprice, Some((100.0 * 1.0 + 102.0 * 3.0) / 4.0));
+        assert_eq!(snapshot.depth_weighted_mid, Some((99.75 + 102.0) / 2.0));
+        assert_eq!(depth_weighted_mid(&snapshot.bids, &snapshot.asks, 1), Some(101.0));
+    }
+    
+    #[test]
+    fn test_prices_snap_to_ticks() {
+        let book = FastOrderbook::with_tick_size(0, "BTC/USD".to_string(), TickSize::new(0.1).unwrap());
+        
+        // Float noise doesn't split a level
+        book.add_order(order(1, 0.3, 1.0), true);
+        let delta = book.add_order(order(2, 0.1 + 0.2, 2.0), true);
+        assert_eq!((delta.level_size, delta.level_action()), (3.0, ("change", "B", 0.3)));
+        book.add_order(order(3, 0.4, 1.0), true);
+        book.add_order(order(4, 0.5, 1.0), false);
+        book.publish();
+        assert_eq!(book.snapshot().bids, vec![(0.4, 1.0), (0.3, 3.0)]);
+        
+        // Removals find the level the same way
+        assert!(book.remove_order(2).is_some());
+        assert_eq!(book.orders().bids.iter().map(|o| (o.id, o.price)).collect::<Vec<_>>(), vec![(3, 0.4), (1, 0.3)]);
+    }
+    
+    /// A mutation of a random sequence. Ids come from a small pool, so most
+    /// modifies, cancels and fills find a resting order.
+    #[derive(Debug, Clone)]
+    enum Op {
+        Add { id: u64, is_buy: bool, tick: u32, size: f64 },
+        Modify { id: u64, tick: u32, size: f64 },
+        Cancel { id: u64 },
+        /// Fill a quarter to all of the order
+        Fill { id: u64, quarters: u32 },
+    }
+    
+    fn op() -> impl Strategy<Value = Op> {
+        let id = 1..40u64;
+        let size = 0.001..100.0f64;
+        prop_oneof![
+            3 => (id.clone(), any::<bool>(), 0..40u32, size.clone())
+                .prop_map(|(id, is_buy, tick, size)| Op::Add { id, is_buy, tick, size }),
+            1 => (id.clone(), 0..40u32, size).prop_map(|(id, tick, size)| Op::Modify { id, tick, size }),
+            1 => id.clone().prop_map(|id| Op::Cancel { id }),
+            1 => (id, 1..=4u32).prop_map(|(id, quarters)| Op::Fill { id, quarters }),
+        ]
+    }
+    
+    /// Both sides share the price range, so books cross like the feed's do
+    fn tick_price(tick: u32) -> f64 {
+        90.0 + tick as f64 * 0.5
+    }
+    
+    /// Resting orders by id: side, price and size
+    type Model = HashMap<u64, (bool, f64, f64)>;
+    
+    fn check_invariants(book: &FastOrderbook, model: &Model, sequence: u64) -> Result<(), TestCaseError> {
+        let index = book.order_index.lock().clone();
+        let bids = book.bid_levels.read();
+        let asks = book.ask_levels.read();
+        // Best first, one level per tick
+        prop_assert!(bids.windows(2).all(|pair| pair[0].ticks > pair[1].ticks));
+        prop_assert!(asks.windows(2).all(|pair| pair[0].ticks < pair[1].ticks));
+        
+        let mut resting = 0;
+        for (is_buy, levels) in [(true, &*bids), (false, &*asks)] {
+            for level in levels {
+                prop_assert!(!level.orders.is_empty(), "empty level at {}", level.price);
+                let sum: f64 = level.orders.iter().map(|o| o.size).sum();
+                prop_assert!((level.total_size - sum).abs() <= 1e-9 * sum.max(1.0), "level {} totals {}, its orders {}", level.price, level.total_size, sum);
+                for o in &level.orders {
+                    prop_assert!(o.size > 0.0);
+                    prop_assert_eq!(o.price, level.price);
+                    prop_assert_eq!(index.get(&o.id), Some(&(is_buy, level.ticks)));
+                    prop_assert_eq!(model.get(&o.id), Some(&(is_buy, o.price, o.size)));
+                    resting += 1;
+                }
+            }
+        }
+        prop_assert_eq!(resting, model.len());
+        prop_assert_eq!(index.len(), model.len());
+        prop_assert_eq!(book.total_orders.load(Ordering::Relaxed), model.len());
+        prop_assert_eq!(book.bid_count.load(Ordering::Relaxed), bids.len());
+        prop_assert_eq!(book.ask_count.load(Ordering::Relaxed), asks.len());
+        prop_assert_eq!(book.sequence.load(Ordering::Relaxed), sequence);
+        Ok(())
+    }
+    
+    /// Same prices, sizes equal but for summation order
+    fn check_levels_match(levels: &[(f64, f64)], expected: &[(f64, f64)]) -> Result<(), TestCaseError> {
+        prop_assert_eq!(levels.len(), expected.len());
+        for (level, expected) in levels.iter().zip(expected) {
+            prop_assert_eq!(level.0, expected.0);
+            prop_assert!((level.1 - expected.1).abs() <= 1e-9 * expected.1.max(1.0), "{:?} != {:?}", level, expected);
+        }
+        Ok(())
+    }
+    
+    proptest! {
+        #[test]
+        fn test_random_operations_keep_invariants(ops in prop::collection::vec(op(), 1..200)) {
+            let tick_size = TickSize::new(0.5).unwrap();
+            let book = FastOrderbook::with_tick_size(0, "BTC/USD".to_string(), tick_size);
+            let mut model = Model::new();
+            let mut sequence = 0;
+            
+            for op in ops {
+                match op {
+                    Op::Add { id, is_buy, tick, size } => {
+                        // Ids are unique among resting orders
+                        if model.contains_key(&id) {
+                            continue;
+                        }
+                        let delta = book.add_order(order(id, tick_price(tick), size), is_buy);
+                        sequence += 1;
+                        prop_assert_eq!(delta.sequence, sequence);
+                        model.insert(id, (is_buy, tick_price(tick), size));
+                    }
+                    Op::Modify { id, tick, size } => {
+                        let deltas = book.modify_order(id, size, tick_price(tick));
+                        match model.get_mut(&id) {
+                            Some(resting) => {
+                                // In place at the same price, else removed and re-added
+                                prop_assert_eq!(deltas.len(), if resting.1 == tick_price(tick) { 1 } else { 2 });
+                                (resting.1, resting.2) = (tick_price(tick), size);
+                            }
+                            None => prop_assert!(deltas.is_empty()),
+                        }
+                        sequence += deltas.len() as u64;
+                    }
+                    Op::Cancel { id } => {
+                        let removed = book.remove_order(id);
+                        prop_assert_eq!(removed.is_some(), model.remove(&id).is_some());
+                        sequence += removed.is_some() as u64;
+                    }
+                    Op::Fill { id, quarters } => match model.get_mut(&id) {
+                        Some(_) if quarters == 4 => {
+                            prop_assert!(book.remove_order(id).is_some());
+                            model.remove(&id);
+                            sequence += 1;
+                        }
+                        Some(resting) => {
+                            resting.2 *= (4 - quarters) as f64 / 4.0;
+                            prop_assert_eq!(book.modify_order(id, resting.2, resting.1).len(), 1);
+                            sequence += 1;
+                        }
+                        None => prop_assert!(book.remove_order(id).is_none()),
+                    },
+                }
+                check_invariants(&book, &model, sequence)?;
+            }
+            
+            // Replaying the event log builds the same book
+            book.publish();
+            let published = book.snapshot();
+            let state = book.event_log().lock().current_state();
+            prop_assert_eq!(state.sequence, sequence);
+            prop_assert_eq!(state.orders.len(), model.len());
+            for (id, resting) in &state.orders {
+                prop_assert_eq!(model.get(id), Some(&(resting.is_buy, resting.price, resting.size)));
+            }
+            let (bids, asks) = state.levels(usize::MAX);
+            check_levels_match(&published.bids, &bids)?;
+            check_levels_match(&published.asks, &asks)?;
+            
+            // So does restoring a book from it
+            let restored = FastOrderbook::with_tick_size(0, "BTC/USD".to_string(), tick_size);
+            restored.restore(std::mem::replace(&mut *book.event_log().lock(), EventLog::new(DEFAULT_MAX_EVENTS)));
+            check_invariants(&restored, &model, sequence)?;
+            check_levels_match(&restored.snapshot().bids, &published.bids)?;
+            check_levels_match(&restored.snapshot().asks, &published.asks)?;
+        }
+    }
+}
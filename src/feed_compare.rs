@@ -0,0 +1,309 @@
+//! Side-by-side comparison of two service instances.
+//!
+//! Sequences are local to each instance, so updates are matched by book
+//! content: a snapshot seen on one feed is matched to the first identical
+//! snapshot on the other within `match_window`. Latency is the receive-time
+//! difference of matched snapshots, measured on this host so that the two
+//! servers' clocks don't matter.
+
+use anyhow::{bail, Result};
+use clap::Args;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tonic::Request;
+use tracing::{info, warn};
+
+use crate::grpc_server::pb::orderbook_service_client::OrderbookServiceClient;
+use crate::grpc_server::pb::{OrderbookSnapshot, SubscribeRequest};
+
+#[derive(Args, Debug, Clone)]
+pub struct CompareArgs {
+    /// First instance endpoint
+    #[arg(long)]
+    pub a: String,
+
+    /// Second instance endpoint
+    #[arg(long)]
+    pub b: String,
+
+    /// Market IDs to compare (comma-separated)
+    #[arg(long, value_delimiter = ',', default_value = "0,1,5")]
+    pub markets: Vec<u32>,
+
+    /// Levels per side to subscribe to and compare
+    #[arg(long, default_value = "20")]
+    pub depth: u32,
+
+    /// How long to run the comparison
+    #[arg(long, default_value = "60")]
+    pub duration_secs: u64,
+
+    /// How long to wait for the other feed before counting an update as missing
+    #[arg(long, default_value = "5000")]
+    pub match_window_ms: u64,
+
+    /// API key sent as x-api-key to both instances
+    #[arg(long)]
+    pub api_key: Option<String>,
+}
+
+/// Feed index: 0 = A, 1 = B
+type Side = usize;
+
+fn fingerprint(snapshot: &OrderbookSnapshot) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for level in snapshot.bids.iter().chain(snapshot.asks.iter()) {
+        level.price.to_bits().hash(&mut hasher);
+        level.quantity.to_bits().hash(&mut hasher);
+    }
+    snapshot.bids.len().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Matching state and statistics for one market
+#[derive(Debug, Default)]
+pub struct MarketComparison {
+    messages: [u64; 2],
+    /// Book states seen on one side and not yet on the other
+    pending: [HashMap<u64, Instant>; 2],
+    /// Updates one side delivered that the other never did
+    missing: [u64; 2],
+    /// Receive time on B minus receive time on A, in microseconds
+    latencies_us: Vec<i64>,
+    latest: [Option<u64>; 2],
+    divergent_since: Option<Instant>,
+    divergent_checks: u64,
+}
+
+impl MarketComparison {
+    pub fn on_book(&mut self, side: Side, book: u64, received: Instant) {
+        let other = 1 - side;
+        self.messages[side] += 1;
+        self.latest[side] = Some(book);
+
+        if let Some(other_received) = self.pending[other].remove(&book) {
+            let (a, b) = if side == 0 { (received, other_received) } else { (other_received, received) };
+            let latency_us = if b >= a {
+                b.duration_since(a).as_micros() as i64
+            } else {
+                -(a.duration_since(b).as_micros() as i64)
+            };
+            self.latencies_us.push(latency_us);
+        } else {
+            self.pending[side].entry(book).or_insert(received);
+        }
+    }
+
+    /// Count unmatched updates older than `window` as missing on the other feed,
+    /// and track whether the latest books disagree for longer than `window`
+    pub fn expire(&mut self, now: Instant, window: Duration) {
+        for side in 0..2 {
+            let before = self.pending[side].len();
+            self.pending[side].retain(|_, received| now.duration_since(*received) <= window);
+            self.missing[1 - side] += (before - self.pending[side].len()) as u64;
+        }
+
+        match (self.latest[0], self.latest[1]) {
+            (Some(a), Some(b)) if a != b => {
+                let since = *self.divergent_since.get_or_insert(now);
+                if now.duration_since(since) > window {
+                    self.divergent_checks += 1;
+                }
+            }
+            _ => self.divergent_since = None,
+        }
+    }
+
+    pub fn matched(&self) -> usize {
+        self.latencies_us.len()
+    }
+
+    /// Latency percentile (B minus A) in microseconds
+    pub fn latency_percentile(&self, percentile: f64) -> Option<i64> {
+        if self.latencies_us.is_empty() {
+            return None;
+        }
+        let mut sorted = self.latencies_us.clone();
+        sorted.sort_unstable();
+        let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+        Some(sorted[index])
+    }
+}
+
+async fn subscribe(
+    side: Side,
+    endpoint: String,
+    args: CompareArgs,
+    tx: mpsc::Sender<(Side, OrderbookSnapshot, Instant)>,
+) -> Result<()> {
+    let mut client = OrderbookServiceClient::connect(endpoint.clone()).await?;
+
+    let mut request = Request::new(SubscribeRequest {
+        market_ids: args.markets.clone(),
+        depth: args.depth,
+        ..Default::default()
+    });
+    if let Some(key) = &args.api_key {
+        request.metadata_mut().insert("x-api-key", key.parse()?);
+    }
+
+    let mut stream = client.subscribe_orderbook(request).await?.into_inner();
+    info!("Subscribed to {} markets on {}", args.markets.len(), endpoint);
+
+    while let Some(snapshot) = stream.next().await {
+        let snapshot = snapshot?;
+        if tx.send((side, snapshot, Instant::now())).await.is_err() {
+            break;
+        }
+    }
+
+    bail!("Stream from {} ended", endpoint)
+}
+
+fn format_ms(latency_us: Option<i64>) -> String {
+    latency_us
+        .map(|us| format!("{:+.2}", us as f64 / 1000.0))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn print_report(args: &CompareArgs, markets: &BTreeMap<u32, MarketComparison>) {
+    println!();
+    println!("A = {}", args.a);
+    println!("B = {}", args.b);
+    println!("Latency is B minus A in ms (negative = B faster)");
+    println!();
+    println!(
+        "{:>6} {:>9} {:>9} {:>8} {:>9} {:>9} {:>9} {:>9} {:>11}",
+        "market", "msgs A", "msgs B", "matched", "miss A", "miss B", "p50 ms", "p99 ms", "diverged s"
+    );
+
+    let mut a_faster = 0;
+    let mut total_matched = 0;
+    for (market_id, market) in markets {
+        println!(
+            "{:>6} {:>9} {:>9} {:>8} {:>9} {:>9} {:>9} {:>9} {:>11}",
+            market_id,
+            market.messages[0],
+            market.messages[1],
+            market.matched(),
+            market.missing[0],
+            market.missing[1],
+            format_ms(market.latency_percentile(0.5)),
+            format_ms(market.latency_percentile(0.99)),
+            market.divergent_checks,
+        );
+        a_faster += market.latencies_us.iter().filter(|l| **l > 0).count();
+        total_matched += market.matched();
+    }
+
+    if total_matched > 0 {
+        println!();
+        println!(
+            "A delivered first for {:.1}% of {} matched updates",
+            a_faster as f64 * 100.0 / total_matched as f64,
+            total_matched
+        );
+    }
+}
+
+pub async fn run(args: CompareArgs) -> Result<()> {
+    let match_window = Duration::from_millis(args.match_window_ms);
+    let (tx, mut rx) = mpsc::channel(10_000);
+
+    for (side, endpoint) in [(0, args.a.clone()), (1, args.b.clone())] {
+        let tx = tx.clone();
+        let args = args.clone();
+        tokio::spawn(async move {
+            if let Err(e) = subscribe(side, endpoint, args, tx).await {
+                warn!("Feed {} failed: {}", if side == 0 { "A" } else { "B" }, e);
+            }
+        });
+    }
+    drop(tx);
+
+    let mut markets: BTreeMap<u32, MarketComparison> =
+        args.markets.iter().map(|id| (*id, MarketComparison::default())).collect();
+
+    let deadline = tokio::time::sleep(Duration::from_secs(args.duration_secs));
+    tokio::pin!(deadline);
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            message = rx.recv() => {
+                let Some((side, snapshot, received)) = message else {
+                    warn!("Both feeds ended");
+                    break;
+                };
+                markets
+                    .entry(snapshot.market_id)
+                    .or_default()
+                    .on_book(side, fingerprint(&snapshot), received);
+            }
+            _ = ticker.tick() => {
+                let now = Instant::now();
+                for market in markets.values_mut() {
+                    market.expire(now, match_window);
+                }
+            }
+            _ = &mut deadline => break,
+        }
+    }
+
+    print_report(&args, &markets);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_latency_and_missing() {
+        let mut market = MarketComparison::default();
+        let t0 = Instant::now();
+        let window = Duration::from_secs(1);
+
+        // Same book arrives on A, then on B 3ms later
+        market.on_book(0, 10, t0);
+        market.on_book(1, 10, t0 + Duration::from_millis(3));
+        // B is first for the next book
+        market.on_book(1, 11, t0 + Duration::from_millis(5));
+        market.on_book(0, 11, t0 + Duration::from_millis(6));
+        // A never sees this one
+        market.on_book(1, 12, t0 + Duration::from_millis(10));
+
+        assert_eq!(market.matched(), 2);
+        assert_eq!(market.latency_percentile(0.0), Some(-1000));
+        assert_eq!(market.latency_percentile(1.0), Some(3000));
+
+        market.expire(t0 + Duration::from_secs(2), window);
+        assert_eq!(market.missing, [1, 0]);
+    }
+
+    #[test]
+    fn test_divergence_requires_persistence() {
+        let mut market = MarketComparison::default();
+        let t0 = Instant::now();
+        let window = Duration::from_secs(1);
+
+        market.on_book(0, 1, t0);
+        market.on_book(1, 2, t0);
+        market.expire(t0, window);
+        assert_eq!(market.divergent_checks, 0);
+
+        market.expire(t0 + Duration::from_secs(2), window);
+        assert_eq!(market.divergent_checks, 1);
+
+        // Converging resets the clock
+        market.on_book(1, 1, t0 + Duration::from_secs(2));
+        market.expire(t0 + Duration::from_secs(3), window);
+        market.on_book(1, 3, t0 + Duration::from_secs(3));
+        market.expire(t0 + Duration::from_secs(3), window);
+        assert_eq!(market.divergent_checks, 1);
+    }
+}
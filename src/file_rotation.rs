@@ -0,0 +1,93 @@
+//! Noticing a followed file truncated or replaced under its path.
+//!
+//! Readers keep an offset into the file they follow. Once the file is
+//! truncated that offset points past its end, and once another file is put
+//! at the path, a reader holding the old one never sees another line; either
+//! way reading silently stops. Readers check the path against the file they
+//! started on and read the new contents from the start.
+
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// What happened to a followed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange {
+    Unchanged,
+    /// Shorter than the bytes already read
+    Truncated,
+    /// A different file (inode) is at the path
+    Replaced,
+}
+
+impl FileChange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileChange::Unchanged => "unchanged",
+            FileChange::Truncated => "truncated",
+            FileChange::Replaced => "replaced",
+        }
+    }
+}
+
+/// The file a reader follows, as it was when reading started
+#[derive(Debug, Clone)]
+pub struct FileWatch {
+    path: PathBuf,
+    /// `None` until the file exists
+    inode: Option<u64>,
+}
+
+impl FileWatch {
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), inode: inode(path) }
+    }
+
+    /// Compare the file at the path with the one read up to `offset`, if
+    /// known. After a replacement the watch follows the new file.
+    pub fn check(&mut self, offset: Option<u64>) -> FileChange {
+        let Ok(metadata) = std::fs::metadata(&self.path) else {
+            return FileChange::Unchanged;
+        };
+        match self.inode.replace(metadata.ino()) {
+            // A file appearing where there was none is just the start of it
+            None => FileChange::Unchanged,
+            Some(inode) if inode != metadata.ino() => FileChange::Replaced,
+            Some(_) if offset.is_some_and(|offset| metadata.len() < offset) => FileChange::Truncated,
+            Some(_) => FileChange::Unchanged,
+        }
+    }
+}
+
+fn inode(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|metadata| metadata.ino())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncation_and_replacement_are_detected() {
+        let dir = std::env::temp_dir().join(format!("file-rotation-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("7");
+
+        let mut watch = FileWatch::new(&path);
+        assert_eq!(watch.check(Some(0)), FileChange::Unchanged);
+        std::fs::write(&path, "a\nb\n").unwrap();
+        assert_eq!(watch.check(Some(0)), FileChange::Unchanged);
+        assert_eq!(watch.check(Some(4)), FileChange::Unchanged);
+
+        std::fs::write(&path, "c\n").unwrap();
+        assert_eq!(watch.check(Some(4)), FileChange::Truncated);
+        // Without a known offset only replacement shows
+        assert_eq!(watch.check(None), FileChange::Unchanged);
+
+        std::fs::rename(&path, dir.join("7.1")).unwrap();
+        std::fs::write(&path, "d\n").unwrap();
+        assert_eq!(watch.check(None), FileChange::Replaced);
+        assert_eq!(watch.check(Some(2)), FileChange::Unchanged);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+//! Wakeups for a reader tailing a file, as soon as the writer appends.
+//!
+//! The file's directory is watched rather than the file, so a file that is
+//! created, replaced or rotated later still wakes the reader. Events can be
+//! dropped (e.g. an overflowing inotify queue) or unsupported on some
+//! filesystems, so the reader also wakes on a slow fallback poll.
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::warn;
+
+/// Longest wait for a write while the file is watched
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Poll interval when the file can't be watched
+const UNWATCHED_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct FileWakeups {
+    notify: Arc<Notify>,
+    poll_interval: Duration,
+    // Events stop when the watcher is dropped
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl FileWakeups {
+    /// Wake on changes to `path`, falling back to polling if it can't be
+    /// watched
+    pub fn watch(path: &Path) -> Self {
+        let notify = Arc::new(Notify::new());
+        let watcher = match Self::watcher(path, notify.clone()) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!("Can't watch {} for writes, polling every {:?}: {}", path.display(), UNWATCHED_POLL_INTERVAL, e);
+                None
+            }
+        };
+        let poll_interval = if watcher.is_some() { FALLBACK_POLL_INTERVAL } else { UNWATCHED_POLL_INTERVAL };
+        Self { notify, poll_interval, _watcher: watcher }
+    }
+
+    fn watcher(path: &Path, notify: Arc<Notify>) -> notify::Result<RecommendedWatcher> {
+        let file_name = path.file_name().map(|name| name.to_os_string());
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let wake = match event {
+                // Reads and opens don't add anything to read
+                Ok(event) => {
+                    !matches!(event.kind, EventKind::Access(_))
+                        && event.paths.iter().any(|changed| changed.file_name() == file_name.as_deref())
+                }
+                // Events may have been lost
+                Err(_) => true,
+            };
+            if wake {
+                notify.notify_one();
+            }
+        })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    }
+
+    /// Wait until the file changes or the poll interval passes. A change
+    /// since the last wait returns at once.
+    pub async fn wait(&self) {
+        tokio::select! {
+            _ = self.notify.notified() => {}
+            _ = tokio::time::sleep(self.poll_interval) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_writes_wake_the_reader() {
+        let path = std::env::temp_dir().join(format!("file_wakeups_{}.json", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let wakeups = FileWakeups::watch(&path);
+        assert_eq!(wakeups.poll_interval, FALLBACK_POLL_INTERVAL);
+
+        // A write before the reader waits isn't lost
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{}\n").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), wakeups.notify.notified()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let _ = tokio::time::timeout(Duration::from_millis(10), wakeups.notify.notified()).await;
+
+        // With nothing written, waiting ends at the fallback poll
+        let started = tokio::time::Instant::now();
+        wakeups.wait().await;
+        assert!(started.elapsed() >= FALLBACK_POLL_INTERVAL);
+
+        // Other files in the directory don't wake it
+        let other = path.with_extension("other");
+        std::fs::write(&other, b"{}\n").unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(200), wakeups.notify.notified()).await.is_err());
+
+        std::fs::remove_file(&other).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
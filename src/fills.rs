@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::broadcast;
+use tracing::{error, info};
+
+use crate::alerts::Severity;
+use crate::book_actor::{BookActors, BookCommand};
+use crate::dynamic_markets::DynamicMarketRegistry;
+use crate::open_interest::OpenInterestTracker;
+use crate::order_parser::{deserialize_price, deserialize_size, ErrorBuffer, ErrorCategory};
+
+/// Default location of the node's hourly fill files
+pub const DEFAULT_FILLS_DIR: &str = "/home/hluser/hl/data/node_fills/hourly";
+
+/// One side of a match as the node writes it
+#[derive(Debug, Clone, Deserialize)]
+pub struct RawFill {
+    pub coin: String,
+    #[serde(deserialize_with = "deserialize_price")]
+    pub px: f64,
+    #[serde(deserialize_with = "deserialize_size")]
+    pub sz: f64,
+    pub side: String,
+    pub time: u64,
+    pub oid: u64,
+    /// Taker side of the match
+    pub crossed: bool,
+    #[serde(default)]
+    pub tid: u64,
+    /// The user's signed position before the fill
+    #[serde(default, rename = "startPosition", deserialize_with = "deserialize_size")]
+    pub start_position: f64,
+}
+
+/// A match, reported once from the taker's fill
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trade {
+    pub market_id: u32,
+    pub price: f64,
+    pub size: f64,
+    /// Aggressor side, "B" or "A"
+    pub side: String,
+    /// Taker's order id
+    pub oid: u64,
+    pub tid: u64,
+    pub timestamp_ms: u64,
+}
+
+/// Fills in a line: `[user, fill]`, or a block of them as
+/// `{"events": [[user, fill], ...]}`
+pub fn parse_fills(line: &str) -> Result<Vec<RawFill>> {
+    let fill = |event: Value| -> Result<RawFill> {
+        match event {
+            Value::Array(mut pair) if pair.len() == 2 => Ok(serde_json::from_value(pair.pop().unwrap())?),
+            other => Err(anyhow!("Expected [user, fill], got {}", other)),
+        }
+    };
+
+    match serde_json::from_str(line)? {
+        Value::Object(mut block) => match block.remove("events") {
+            Some(Value::Array(events)) => events.into_iter().map(fill).collect(),
+            _ => Err(anyhow!("Fill block without events")),
+        },
+        event => Ok(vec![fill(event)?]),
+    }
+}
+
+/// Tails the node's fill file, sets each book's last trade price,
+/// broadcasts trades and keeps open interest
+pub struct FillMonitor {
+    market_registry: Arc<DynamicMarketRegistry>,
+    error_buffer: Arc<ErrorBuffer>,
+    trade_tx: broadcast::Sender<Arc<Trade>>,
+    trades: AtomicU64,
+    open_interest: Arc<OpenInterestTracker>,
+}
+
+impl FillMonitor {
+    pub fn new(market_registry: Arc<DynamicMarketRegistry>, error_buffer: Arc<ErrorBuffer>) -> Self {
+        Self {
+            market_registry,
+            error_buffer,
+            trade_tx: broadcast::channel(10_000).0,
+            trades: AtomicU64::new(0),
+            open_interest: Arc::new(OpenInterestTracker::new()),
+        }
+    }
+
+    pub fn open_interest(&self) -> &Arc<OpenInterestTracker> {
+        &self.open_interest
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Trade>> {
+        self.trade_tx.subscribe()
+    }
+
+    pub fn trades(&self) -> u64 {
+        self.trades.load(Ordering::Relaxed)
+    }
+
+    /// The trade a fill reports, if it is the taker's side
+    fn trade(market_id: u32, fill: RawFill) -> Option<Trade> {
+        if !fill.crossed {
+            return None;
+        }
+        Some(Trade {
+            market_id,
+            price: fill.px,
+            size: fill.sz,
+            side: fill.side,
+            oid: fill.oid,
+            tid: fill.tid,
+            timestamp_ms: fill.time,
+        })
+    }
+
+    pub fn start(self: Arc<Self>, data_path: String, book_actors: Arc<BookActors>) {
+        info!("Reading fills from: {}", data_path);
+
+        tokio::spawn(async move {
+            if let Err(e) = self.process_fills(&data_path, &book_actors).await {
+                error!("Fill monitor failed: {}", e);
+            }
+        });
+    }
+
+    async fn process_fills(&self, data_path: &str, book_actors: &BookActors) -> Result<()> {
+        let mut cmd = Command::new("docker")
+            .args(["exec", "hyperliquid-node-1", "tail", "-n", "0", "-f", data_path])
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let stdout = cmd.stdout.take().ok_or_else(|| anyhow!("Failed to get stdout"))?;
+        let mut lines = BufReader::new(stdout).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            let fills = match parse_fills(&line) {
+                Ok(fills) => fills,
+                Err(e) => {
+                    self.error_buffer.record(ErrorCategory::Parse, Severity::Warning, None, format!("Invalid fill: {}", e), &line);
+                    continue;
+                }
+            };
+
+            for fill in fills {
+                let Some(market_id) = self.market_registry.get_market_id(&fill.coin).await else { continue };
+                self.open_interest.apply_fill(market_id, fill.start_position, fill.sz, fill.side == "B", fill.time);
+                let Some(trade) = Self::trade(market_id, fill) else { continue };
+                if let Some(book) = book_actors.handle(trade.market_id) {
+                    let _ = book.send(BookCommand::LastTrade(trade.price)).await;
+                }
+                self.trades.fetch_add(1, Ordering::Relaxed);
+                let _ = self.trade_tx.send(Arc::new(trade));
+            }
+        }
+
+        self.error_buffer.record(ErrorCategory::Io, Severity::Critical, None, format!("Fill feed {} ended", data_path), "");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fill_lines() {
+        let fill = r#"{"coin":"BTC","px":"105000.5","sz":"0.01","side":"B","time":1700000000000,"startPosition":"0.0",
+            "dir":"Open Long","closedPnl":"0.0","hash":"0xabc","oid":42,"crossed":true,"fee":"0.1","tid":7,"feeToken":"USDC"}"#;
+
+        let single = parse_fills(&format!(r#"["0xuser", {}]"#, fill)).unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!((single[0].px, single[0].sz, single[0].oid, single[0].tid), (105000.5, 0.01, 42, 7));
+        assert_eq!(single[0].start_position, 0.0);
+        assert!(single[0].crossed);
+
+        let block = parse_fills(&format!(r#"{{"block_number":1,"events":[["0xa",{}],["0xb",{}]]}}"#, fill, fill)).unwrap();
+        assert_eq!(block.len(), 2);
+
+        assert!(parse_fills(r#"{"block_number":1}"#).is_err());
+        assert!(parse_fills(r#"["0xuser"]"#).is_err());
+    }
+}
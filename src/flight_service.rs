@@ -0,0 +1,501 @@
+//! Arrow Flight for bulk export: current books, recorded deltas and candles
+//! as Arrow record batches, so pandas and polars clients get columns instead
+//! of one gRPC message per row.
+//!
+//! Tickets and command descriptors are JSON queries naming a dataset, e.g.
+//! `{"dataset": "deltas", "market_id": 0, "from_timestamp_ms": 1700000000000}`.
+//! Path descriptors are `[dataset, market_id]`, or `["snapshots"]` for every
+//! market. ListFlights lists one flight per dataset and market, filtered to a
+//! dataset when the criteria name one. Snapshot and delta columns match the
+//! Parquet recorder's.
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt32Array};
+use arrow_ipc::writer::{self, DictionaryTracker, IpcDataGenerator, IpcWriteOptions};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use crate::auth_interceptor::{self, KeyPermissions, Scope};
+use crate::candles::{Candle, CandleAggregator};
+use crate::delta_wal::DeltaWal;
+use crate::fast_orderbook::FastOrderbook;
+use crate::grpc_server::{candle_series, wal_range};
+use crate::market_recorder::{delta_rows, level_rows, DeltaRow, LevelRow, Row};
+use crate::market_tiers::MarketTiers;
+
+pub mod pb {
+    tonic::include_proto!("arrow.flight.protocol");
+}
+
+use pb::flight_descriptor::DescriptorType;
+use pb::flight_service_server::FlightService;
+use pb::{Criteria, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, SchemaResult, Ticket};
+
+/// Delta rows per record batch
+const DELTA_BATCH_ROWS: usize = 65_536;
+
+/// What a ticket or command descriptor asks for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "dataset", rename_all = "snake_case")]
+enum Query {
+    /// The published books; no markets means all of them, depth 0 the
+    /// tier's maximum
+    Snapshots {
+        #[serde(default)]
+        market_ids: Vec<u32>,
+        #[serde(default)]
+        depth: u32,
+    },
+    /// Deltas from the write-ahead log, with zero leaving a bound open
+    Deltas {
+        market_id: u32,
+        #[serde(default)]
+        from_sequence: u64,
+        #[serde(default)]
+        to_sequence: u64,
+        #[serde(default)]
+        from_timestamp_ms: i64,
+        #[serde(default)]
+        to_timestamp_ms: i64,
+    },
+    /// Bars from `start_ms`, defaulting to 1m trade candles
+    Candles {
+        market_id: u32,
+        #[serde(default)]
+        interval: String,
+        #[serde(default)]
+        source: String,
+        #[serde(default)]
+        start_ms: u64,
+        #[serde(default)]
+        limit: u32,
+    },
+}
+
+impl Query {
+    fn dataset(&self) -> &'static str {
+        match self {
+            Query::Snapshots { .. } => "snapshots",
+            Query::Deltas { .. } => "deltas",
+            Query::Candles { .. } => "candles",
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, Status> {
+        serde_json::from_slice(bytes).map_err(|e| Status::invalid_argument(format!("Invalid query: {}", e)))
+    }
+
+    fn from_path(path: &[String]) -> Result<Self, Status> {
+        let market_id = |s: &String| s.parse().map_err(|_| Status::invalid_argument(format!("Invalid market id {}", s)));
+        match path {
+            [dataset] if dataset == "snapshots" => Ok(Query::Snapshots { market_ids: Vec::new(), depth: 0 }),
+            [dataset, market] => match dataset.as_str() {
+                "snapshots" => Ok(Query::Snapshots { market_ids: vec![market_id(market)?], depth: 0 }),
+                "deltas" => Ok(Query::Deltas {
+                    market_id: market_id(market)?,
+                    from_sequence: 0,
+                    to_sequence: 0,
+                    from_timestamp_ms: 0,
+                    to_timestamp_ms: 0,
+                }),
+                "candles" => Ok(Query::Candles {
+                    market_id: market_id(market)?,
+                    interval: String::new(),
+                    source: String::new(),
+                    start_ms: 0,
+                    limit: 0,
+                }),
+                other => Err(Status::invalid_argument(format!("Unknown dataset {}", other))),
+            },
+            _ => Err(Status::invalid_argument(format!("Unknown path {:?}", path))),
+        }
+    }
+
+    fn from_descriptor(descriptor: &FlightDescriptor) -> Result<Self, Status> {
+        match descriptor.r#type() {
+            DescriptorType::Cmd => Self::parse(&descriptor.cmd),
+            DescriptorType::Path => Self::from_path(&descriptor.path),
+            DescriptorType::Unknown => Err(Status::invalid_argument("Descriptor has no type")),
+        }
+    }
+
+    /// The markets the query names; none for every market
+    fn market_ids(&self) -> &[u32] {
+        match self {
+            Query::Snapshots { market_ids, .. } => market_ids,
+            Query::Deltas { market_id, .. } | Query::Candles { market_id, .. } => std::slice::from_ref(market_id),
+        }
+    }
+
+    fn schema(&self) -> SchemaRef {
+        match self {
+            Query::Snapshots { .. } => LevelRow::schema(),
+            Query::Deltas { .. } => DeltaRow::schema(),
+            Query::Candles { .. } => candle_schema(),
+        }
+    }
+}
+
+fn candle_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("market_id", DataType::UInt32, false),
+        Field::new("interval", DataType::Utf8, false),
+        Field::new("source", DataType::Utf8, false),
+        Field::new("timestamp", DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())), false),
+        Field::new("open", DataType::Float64, false),
+        Field::new("high", DataType::Float64, false),
+        Field::new("low", DataType::Float64, false),
+        Field::new("close", DataType::Float64, false),
+        Field::new("volume", DataType::Float64, false),
+        Field::new("trades", DataType::UInt32, false),
+    ]))
+}
+
+fn candle_batch(market_id: u32, interval: &str, source: &str, candles: &[Candle]) -> Result<RecordBatch, ArrowError> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter_values(candles.iter().map(|_| market_id))),
+        Arc::new(StringArray::from_iter_values(candles.iter().map(|_| interval))),
+        Arc::new(StringArray::from_iter_values(candles.iter().map(|_| source))),
+        Arc::new(TimestampMillisecondArray::from_iter_values(candles.iter().map(|c| c.start_ms as i64)).with_timezone("UTC")),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.open))),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.high))),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.low))),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.close))),
+        Arc::new(Float64Array::from_iter_values(candles.iter().map(|c| c.volume))),
+        Arc::new(UInt32Array::from_iter_values(candles.iter().map(|c| c.trades))),
+    ];
+    RecordBatch::try_new(candle_schema(), columns)
+}
+
+fn rows_batch<R: Row>(rows: &[R]) -> Result<RecordBatch, ArrowError> {
+    RecordBatch::try_new(R::schema(), R::columns(rows))
+}
+
+fn encoding_failed(e: ArrowError) -> Status {
+    Status::internal(format!("Encoding record batch failed: {}", e))
+}
+
+/// `schema` as an encapsulated IPC message, as FlightInfo and SchemaResult
+/// carry it
+fn encapsulated_schema(schema: &Schema) -> Result<Vec<u8>, Status> {
+    let options = IpcWriteOptions::default();
+    let encoded = IpcDataGenerator::default().schema_to_bytes_with_dictionary_tracker(
+        schema,
+        &mut DictionaryTracker::new(false),
+        &options,
+    );
+    let mut bytes = Vec::new();
+    writer::write_message(&mut bytes, encoded, &options).map_err(encoding_failed)?;
+    Ok(bytes)
+}
+
+/// Encodes one DoGet stream: its schema, then its batches
+struct Encoder {
+    generator: IpcDataGenerator,
+    tracker: DictionaryTracker,
+    options: IpcWriteOptions,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Self {
+            generator: IpcDataGenerator::default(),
+            tracker: DictionaryTracker::new(false),
+            options: IpcWriteOptions::default(),
+        }
+    }
+
+    fn schema(&mut self, schema: &Schema) -> FlightData {
+        let encoded = self.generator.schema_to_bytes_with_dictionary_tracker(schema, &mut self.tracker, &self.options);
+        FlightData { data_header: encoded.ipc_message, ..Default::default() }
+    }
+
+    fn batch(&mut self, batch: Result<RecordBatch, ArrowError>) -> Result<FlightData, Status> {
+        // None of the datasets have dictionary columns
+        let (_, encoded) = batch
+            .and_then(|batch| self.generator.encoded_batch(&batch, &mut self.tracker, &self.options))
+            .map_err(encoding_failed)?;
+        Ok(FlightData { data_header: encoded.ipc_message, data_body: encoded.arrow_data, ..Default::default() })
+    }
+}
+
+pub struct ArrowFlightService {
+    orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+    market_tiers: Arc<MarketTiers>,
+    candles: Arc<CandleAggregator>,
+    delta_wal: Option<Arc<DeltaWal>>,
+}
+
+impl ArrowFlightService {
+    pub fn new(
+        orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+        market_tiers: Arc<MarketTiers>,
+        candles: Arc<CandleAggregator>,
+        delta_wal: Option<Arc<DeltaWal>>,
+    ) -> Self {
+        Self { orderbooks, market_tiers, candles, delta_wal }
+    }
+
+    fn market(&self, market_id: u32) -> Result<&Arc<FastOrderbook>, Status> {
+        self.orderbooks.get(&market_id).ok_or_else(|| Status::not_found(format!("Market {} not found", market_id)))
+    }
+
+    fn wal(&self) -> Result<Arc<DeltaWal>, Status> {
+        self.delta_wal.clone().ok_or_else(|| Status::unavailable("The delta write-ahead log is not enabled"))
+    }
+
+    /// Rejects a query DoGet couldn't answer, or that names markets the
+    /// caller may not see
+    fn check(&self, query: &Query, permissions: &KeyPermissions) -> Result<(), Status> {
+        permissions.require(Scope::ReadOnly)?;
+        permissions.check_markets(query.market_ids())?;
+        match query {
+            Query::Snapshots { market_ids, .. } => market_ids.iter().try_for_each(|id| self.market(*id).map(|_| ())),
+            Query::Deltas { market_id, from_sequence, to_sequence, from_timestamp_ms, to_timestamp_ms } => {
+                self.market(*market_id)?;
+                self.wal()?;
+                wal_range(*from_sequence, *to_sequence, *from_timestamp_ms, *to_timestamp_ms).map(|_| ())
+            }
+            Query::Candles { market_id, interval, source, .. } => {
+                self.market(*market_id)?;
+                candle_series(interval, source).map(|_| ())
+            }
+        }
+    }
+
+    fn flight_info(&self, query: &Query, descriptor: FlightDescriptor, permissions: &KeyPermissions) -> Result<FlightInfo, Status> {
+        self.check(query, permissions)?;
+        let ticket = serde_json::to_vec(query).map_err(|e| Status::internal(e.to_string()))?;
+        Ok(FlightInfo {
+            schema: encapsulated_schema(&query.schema())?,
+            flight_descriptor: Some(descriptor),
+            endpoint: vec![FlightEndpoint { ticket: Some(Ticket { ticket }), ..Default::default() }],
+            total_records: -1,
+            total_bytes: -1,
+            ordered: true,
+            app_metadata: Vec::new(),
+        })
+    }
+
+    /// The batches of a snapshot or candle query, which are in memory
+    fn batches(&self, query: &Query, permissions: &KeyPermissions) -> Result<Vec<Result<RecordBatch, ArrowError>>, Status> {
+        match query {
+            Query::Snapshots { market_ids, depth } => {
+                let mut market_ids = if market_ids.is_empty() {
+                    self.orderbooks.keys().copied().filter(|id| permissions.allows_market(*id)).collect()
+                } else {
+                    market_ids.clone()
+                };
+                market_ids.sort_unstable();
+                let timestamp_ms = chrono::Utc::now().timestamp_millis();
+                market_ids
+                    .into_iter()
+                    .map(|market_id| {
+                        let orderbook = self.market(market_id)?;
+                        let max_depth = self.market_tiers.tier(market_id).max_depth();
+                        let depth = if *depth == 0 { max_depth } else { (*depth as usize).min(max_depth) };
+                        Ok(rows_batch(&level_rows(market_id, timestamp_ms, &orderbook.snapshot(), depth)))
+                    })
+                    .collect()
+            }
+            Query::Candles { market_id, interval, source, start_ms, limit } => {
+                let (interval, interval_ms, source) = candle_series(interval, source)?;
+                let candles = self.candles.candles(*market_id, source, interval_ms, *start_ms, *limit as usize);
+                Ok(vec![candle_batch(*market_id, interval, source.as_str(), &candles)])
+            }
+            Query::Deltas { .. } => Ok(Vec::new()),
+        }
+    }
+}
+
+type FlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
+
+#[tonic::async_trait]
+impl FlightService for ArrowFlightService {
+    type ListFlightsStream = FlightStream<FlightInfo>;
+
+    async fn list_flights(&self, request: Request<Criteria>) -> Result<Response<Self::ListFlightsStream>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let dataset = String::from_utf8_lossy(&request.get_ref().expression).trim().to_string();
+        let mut market_ids: Vec<u32> = self.orderbooks.keys().copied().filter(|id| permissions.allows_market(*id)).collect();
+        market_ids.sort_unstable();
+
+        let mut infos = Vec::new();
+        for market_id in market_ids {
+            let mut datasets = vec!["snapshots", "candles"];
+            if self.delta_wal.is_some() {
+                datasets.push("deltas");
+            }
+            for name in datasets {
+                let path = vec![name.to_string(), market_id.to_string()];
+                let query = Query::from_path(&path)?;
+                if !dataset.is_empty() && dataset != query.dataset() {
+                    continue;
+                }
+                let descriptor = FlightDescriptor { r#type: DescriptorType::Path as i32, path, ..Default::default() };
+                infos.push(self.flight_info(&query, descriptor, &permissions));
+            }
+        }
+        Ok(Response::new(Box::pin(tokio_stream::iter(infos)) as Self::ListFlightsStream))
+    }
+
+    async fn get_flight_info(&self, request: Request<FlightDescriptor>) -> Result<Response<FlightInfo>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        let descriptor = request.into_inner();
+        let query = Query::from_descriptor(&descriptor)?;
+        Ok(Response::new(self.flight_info(&query, descriptor, &permissions)?))
+    }
+
+    async fn get_schema(&self, request: Request<FlightDescriptor>) -> Result<Response<SchemaResult>, Status> {
+        let query = Query::from_descriptor(request.get_ref())?;
+        self.check(&query, &auth_interceptor::permissions(&request))?;
+        Ok(Response::new(SchemaResult { schema: encapsulated_schema(&query.schema())? }))
+    }
+
+    type DoGetStream = FlightStream<FlightData>;
+
+    async fn do_get(&self, request: Request<Ticket>) -> Result<Response<Self::DoGetStream>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        let query = Query::parse(&request.get_ref().ticket)?;
+        self.check(&query, &permissions)?;
+        info!("Flight DoGet: {:?}", query);
+
+        let mut encoder = Encoder::new();
+        let schema = encoder.schema(&query.schema());
+        let Query::Deltas { market_id, from_sequence, to_sequence, from_timestamp_ms, to_timestamp_ms } = query else {
+            let mut messages = vec![Ok(schema)];
+            messages.extend(self.batches(&query, &permissions)?.into_iter().map(|batch| encoder.batch(batch)));
+            return Ok(Response::new(Box::pin(tokio_stream::iter(messages)) as Self::DoGetStream));
+        };
+
+        let wal = self.wal()?;
+        let range = wal_range(from_sequence, to_sequence, from_timestamp_ms, to_timestamp_ms)?;
+        let (tx, rx) = mpsc::channel(16);
+        tokio::task::spawn_blocking(move || {
+            if tx.blocking_send(Ok(schema)).is_err() {
+                return;
+            }
+            let mut rows = Vec::new();
+            let mut send = |rows: &mut Vec<DeltaRow>| {
+                let batch = encoder.batch(rows_batch(rows));
+                rows.clear();
+                tx.blocking_send(batch).is_ok()
+            };
+            let replayed = wal.replay(market_id, range, |update| {
+                rows.extend(delta_rows(&update));
+                rows.len() < DELTA_BATCH_ROWS || send(&mut rows)
+            });
+            match replayed {
+                Ok(_) if !rows.is_empty() => {
+                    send(&mut rows);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(Status::internal(format!("Reading the write-ahead log failed: {}", e))));
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx)) as Self::DoGetStream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::Order;
+    use crate::market_tiers::MarketTier;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::Float64Type;
+    use arrow_ipc::reader::StreamReader;
+    use arrow_ipc::writer::EncodedData;
+    use tokio_stream::StreamExt;
+
+    fn service() -> ArrowFlightService {
+        let book = Arc::new(FastOrderbook::new(3, "BTC".to_string()));
+        for (id, price, is_buy) in [(1, 100.0, true), (2, 99.0, true), (3, 101.0, false)] {
+            book.add_order(Order { id, price, size: 2.0, timestamp: 0 }, is_buy);
+        }
+        book.publish();
+        ArrowFlightService::new(
+            [(3, book)].into_iter().collect(),
+            Arc::new(MarketTiers::new(MarketTier::Hot)),
+            Arc::new(CandleAggregator::new(10)),
+            None,
+        )
+    }
+
+    /// The batches of a DoGet stream, read back as an IPC stream
+    async fn read(service: &ArrowFlightService, query: &Query) -> Vec<RecordBatch> {
+        let ticket = Ticket { ticket: serde_json::to_vec(query).unwrap() };
+        let mut stream = service.do_get(Request::new(ticket)).await.unwrap().into_inner();
+        let mut bytes = Vec::new();
+        while let Some(data) = stream.next().await {
+            let data = data.unwrap();
+            let encoded = EncodedData { ipc_message: data.data_header, arrow_data: data.data_body };
+            writer::write_message(&mut bytes, encoded, &IpcWriteOptions::default()).unwrap();
+        }
+        StreamReader::try_new(&bytes[..], None).unwrap().collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn test_queries_from_tickets_and_paths() {
+        let ticket = br#"{"dataset": "candles", "market_id": 3, "interval": "5m"}"#;
+        assert_eq!(
+            Query::parse(ticket).unwrap(),
+            Query::Candles { market_id: 3, interval: "5m".to_string(), source: String::new(), start_ms: 0, limit: 0 }
+        );
+        let path = |parts: &[&str]| Query::from_path(&parts.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert_eq!(path(&["snapshots"]).unwrap(), Query::Snapshots { market_ids: vec![], depth: 0 });
+        assert_eq!(path(&["deltas", "3"]).unwrap().dataset(), "deltas");
+        assert!(path(&["deltas"]).is_err());
+        assert!(path(&["trades", "3"]).is_err());
+        assert!(Query::parse(br#"{"dataset": "deltas"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_do_get_streams_record_batches() {
+        let service = service();
+        let batches = read(&service, &Query::Snapshots { market_ids: vec![], depth: 1 }).await;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].schema(), LevelRow::schema());
+        let prices: Vec<f64> = batches[0].column(5).as_primitive::<Float64Type>().values().to_vec();
+        assert_eq!(prices, vec![100.0, 101.0]);
+
+        let candles = Query::Candles { market_id: 3, interval: String::new(), source: String::new(), start_ms: 0, limit: 0 };
+        assert_eq!(read(&service, &candles).await[0].num_rows(), 0);
+
+        // Deltas need the write-ahead log, and markets must exist
+        let deltas = Query::Deltas { market_id: 3, from_sequence: 0, to_sequence: 0, from_timestamp_ms: 0, to_timestamp_ms: 0 };
+        let full = KeyPermissions::full();
+        assert_eq!(service.check(&deltas, &full).unwrap_err().code(), tonic::Code::Unavailable);
+        let missing = Query::Snapshots { market_ids: vec![9], depth: 0 };
+        assert_eq!(service.check(&missing, &full).unwrap_err().code(), tonic::Code::NotFound);
+
+        let listed: Vec<_> = service.list_flights(Request::new(Criteria::default())).await.unwrap().into_inner().collect().await;
+        assert_eq!(listed.len(), 2);
+        let schema = arrow_ipc::convert::try_schema_from_ipc_buffer(&listed[0].as_ref().unwrap().schema).unwrap();
+        assert_eq!(Arc::new(schema), LevelRow::schema());
+
+        // Keys limited to other markets can neither read nor list this one
+        let restricted = Arc::new(KeyPermissions {
+            scopes: [Scope::ReadOnly].into_iter().collect(),
+            markets: Some([0].into_iter().collect()),
+            max_streams: None,
+            max_markets: None,
+        });
+        let mut request = Request::new(Ticket { ticket: serde_json::to_vec(&candles).unwrap() });
+        request.extensions_mut().insert(restricted.clone());
+        assert_eq!(service.do_get(request).await.err().map(|e| e.code()), Some(tonic::Code::PermissionDenied));
+        let mut request = Request::new(Criteria::default());
+        request.extensions_mut().insert(restricted);
+        assert_eq!(service.list_flights(request).await.unwrap().into_inner().collect::<Vec<_>>().await.len(), 0);
+    }
+}
@@ -1,15 +1,56 @@
-use crate::fast_orderbook::FastOrderbook;
+use crate::degradation::{DegradationConfig, DegradationEvent, DegradationMonitor};
+use crate::delta_wal::{DeltaWal, WalRange};
+use crate::fast_orderbook::{self, BookSnapshot, FastOrderbook, FillTarget, OrderbookDelta, SequencedDelta};
+use crate::book_metrics::BookMetricsEngine;
+use crate::book_drift::{BookDrift, BookDriftMonitor};
+use crate::auth_interceptor::{self, KeyPermissions, QuotaGuard, Scope};
+use crate::candles::{self, CandleAggregator, CandleSource};
+use crate::conformance_vectors::{self, ConformanceVector, VectorBook};
+use crate::fills::FillMonitor;
+use crate::market_fanout::{LagPolicy, MarketFanout};
+use crate::market_lifecycle::MarketBooks;
+use crate::price_ticks::TickSize;
+use crate::shared_encoding::{Encoded, EncodedStream};
 use crate::market_processor::MarketUpdate;
-use crate::stop_orders::StopOrderManager;
+use crate::mark_price_service::{MarkPriceService, MarkPriceUpdateEvent, CALCULATION_VERSION};
+use crate::market_scheduler::{MarketScheduler, SchedulerConfig};
+use crate::memory_profile::{self, MemoryReport, MemoryUsage};
+use crate::publish_batching::{BatchWindows, BATCH_SIZE_BUCKETS};
+use crate::quote_stuffing::{QuoteStuffingFilter, StuffingAction, StuffingStats};
+use crate::stop_orders::{StopOrder, StopOrderEventKind, StopOrderManager};
 use crate::dynamic_markets::DynamicMarketRegistry;
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use crate::market_tiers::{MarketTier, MarketTiers};
+use crate::order_flow_imbalance::OfiEngine;
+use crate::position_pnl::PositionPnlTracker;
+use crate::proto_descriptors;
+use crate::replay_cache::ReplayCache;
+use crate::rpc_cache::{CacheStats, RpcCache};
+use crate::resume_buffer::ResumeBuffer;
+use crate::snapshot_barrier::SnapshotBarrier;
+use crate::robust_order_processor::RobustOrderProcessor;
+use crate::service_health::ServiceHealth;
+use crate::book_shards::BookShards;
+use crate::pipeline_latency::{PipelineLatency, Stage, StageLatency};
+use crate::stream_tee::{TeeSlot, TeeWriter};
+use crate::subscribers::Subscribers;
+use crate::trigger_cascade;
+use crate::alerts::{Alert as OperationalAlert, AlertKind, Alerts, Severity};
+use crate::order_parser::{ErrorBuffer, ErrorCategory, ErrorQuery};
+use crate::session_events::{SessionEvent as SessionBoundary, SessionEventKind, SessionEvents};
+use crate::watermarks::WatermarkTracker;
+use crate::size_normalization::{SizeNormalizer, SizeOptions};
+use crate::user_activity::{ActivityMetric, UserActivity as ActivitySummary, UserActivityTracker};
+use crate::subscription_templates::{MarketRef, SubscriptionTemplate, SubscriptionTemplates};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
 use tokio_stream::Stream;
-use tonic::{Request, Response, Status};
-use tracing::info;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{info, warn};
 
 pub mod pb {
     tonic::include_proto!("orderbook");
@@ -17,397 +58,3712 @@ pub mod pb {
 
 use pb::orderbook_service_server::{OrderbookService, OrderbookServiceServer};
 use pb::{
-    Empty as GetMarketsRequest, MarketsResponse as GetMarketsResponse, GetOrderbookRequest, Market,
-    OrderbookSnapshot as PbOrderbookSnapshot, Level, SubscribeRequest,
+    Empty as GetMarketsRequest, MarketsResponse as GetMarketsResponse, GetOrderbookRequest, Market, SearchSymbolsRequest, SearchSymbolsResponse, SymbolMatch as PbSymbolMatch,
+    OrderbookSnapshot as PbOrderbookSnapshot, Level, SubscribeRequest, SubscriptionChange, GetOrderbookAtRequest,
     StopOrdersRequest, StopOrdersResponse, StopOrder as PbStopOrder, RankedStopOrder as PbRankedStopOrder,
+    StopOrdersSubscribeRequest, StopOrderEvent as PbStopOrderEvent,
+    StopOrderHeatmapRequest, StopOrderHeatmap, StopOrderHeatmapBucket,
+    TriggerCascadeRequest, TriggerCascadeRound, TriggerCascadeResponse,
     HyperliquidMarkPrice as PbHLMarkPrice, CexPriceSnapshot as PbCEXPrices,
     MarkPriceSubscribeRequest, MarkPriceUpdate, GetMarkPriceRequest, MarkPriceResponse,
+    SetMarketTierRequest, MarketTier as PbMarketTier, MarketTiersResponse,
+    ResnapshotRequest, ResnapshotResponse,
+    ErrorsRequest, ErrorsResponse, ErrorRecord as PbErrorRecord, ErrorCount, AlertsRequest, Alert as PbAlert, ErrorRateSpike, Contagion, StressedMarket as PbStressedMarket,
+    AnalyticsSubscribeRequest, AnalyticsUpdate, OrderFlowImbalance, OfiHorizon,
+    BookMetricsSubscribeRequest, BookMetrics as PbBookMetrics, LevelImbalance, BandDepth,
+    TemplateSubscribeRequest, TemplatesResponse, SubscriptionTemplate as PbSubscriptionTemplate,
+    SessionEventsRequest, SessionEvent as PbSessionEvent, HourRollover, DayChange, RegistryRefresh,
+    WatermarksRequest, Watermark as PbWatermark,
+    PositionPnlSubscribeRequest, PositionPnlUpdate, PositionPnl as PbPositionPnl,
+    UserActivityRequest, UserActivity as PbUserActivity, UserLeaderboardRequest, UserLeaderboardResponse,
+    StuffingStatsRequest, StuffingStatsResponse, StuffingStats as PbStuffingStats, UserStuffing, MarketStuffing,
+    MarketTimingsRequest, MarketTimingsResponse, MarketTiming as PbMarketTiming, BatchSizeBucket,
+    DegradationState,
+    DeltaSubscribeRequest, DeltaMessage, OrderbookDelta as PbOrderbookDelta, LevelChange, ReplayDeltasRequest,
+    StatsResponse, BookShard, ServiceHealthResponse, ParserHealth, LatencyStatsRequest, LatencyStatsResponse, MarketLatency as PbMarketLatency, StageLatency as PbStageLatency, CircuitBreakerHealth, OpenCircuit, MarketStats, BookIntegrity, RpcCacheStats, StopOrderStats as PbStopOrderStats, SubscribersResponse, SubscriberInfo as PbSubscriberInfo, TeeRequest, TeeResponse, MemoryStatsResponse, MemoryComponent, AllocatorStats as PbAllocatorStats,
+    L3SubscribeRequest, L3Message, L3Snapshot, L3Update, L3Order, L3Event,
+    ProtoDescriptorsResponse, ProtoFile as PbProtoFile, ConformanceVectorsResponse, ConformanceVector as PbConformanceVector,
+    BboSubscribeRequest, Bbo, AggregatedDepthRequest, ImpactRequest, ImpactResponse, impact_request, TradesSubscribeRequest, Trade as PbTrade,
+    OpenInterestRequest, OpenInterestResponse, OpenInterest as PbOpenInterest,
+    BookDriftRequest, BookDriftResponse, BookDrift as PbBookDrift,
+    CandlesRequest, CandlesResponse, CandlesSubscribeRequest, Candle as PbCandle,
 };
 
+// Leaderboard size when the request doesn't set one
+const DEFAULT_LEADERBOARD_LIMIT: usize = 20;
+
+// Depth streamed to subscribers of hot markets
+const STREAM_DEPTH: usize = 50;
+
+fn now_micros() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_micros() as i64
+}
+
+/// Top `depth` levels of the published book, labeled with the sequence they
+/// reflect rather than the live one, which may be mid-batch
+fn build_snapshot(market_id: u32, orderbook: &FastOrderbook, depth: usize, timestamp: i64) -> PbOrderbookSnapshot {
+    let published = orderbook.snapshot();
+    let mut snapshot = levels_snapshot(
+        market_id,
+        &orderbook.symbol,
+        published.bids.iter().take(depth).copied().collect(),
+        published.asks.iter().take(depth).copied().collect(),
+        timestamp,
+        published.sequence,
+    );
+    snapshot.microprice = published.microprice;
+    snapshot.depth_weighted_mid = published.depth_weighted_mid;
+    snapshot.checksum = Some(published.checksum);
+    annotate_ticks(&mut snapshot, orderbook.tick_size());
+    snapshot
+}
+
+/// Give each level its price in ticks of the book's grid
+fn annotate_ticks(snapshot: &mut PbOrderbookSnapshot, tick_size: TickSize) {
+    snapshot.tick_size = tick_size.size();
+    for level in snapshot.bids.iter_mut().chain(snapshot.asks.iter_mut()) {
+        level.price_ticks = tick_size.to_ticks(level.price);
+    }
+}
+
+fn hl_mark_price_to_pb(event: &MarkPriceUpdateEvent) -> PbHLMarkPrice {
+    PbHLMarkPrice {
+        mark_price: event.result.mark_price,
+        oracle_adjusted: event.result.oracle_adjusted.unwrap_or(0.0),
+        internal_median: event.result.internal_median,
+        cex_median: event.result.cex_median.unwrap_or(0.0),
+        used_fallback: event.result.used_fallback,
+        oracle_price: event.oracle_price.unwrap_or(0.0),
+        last_trade: event.last_trade.unwrap_or(0.0),
+        cex_prices: event.cex_prices.as_ref().map(|cex| PbCEXPrices {
+            binance: cex.binance.unwrap_or(0.0),
+            okx: cex.okx.unwrap_or(0.0),
+            bybit: cex.bybit.unwrap_or(0.0),
+            gate: cex.gate.unwrap_or(0.0),
+            mexc: cex.mexc.unwrap_or(0.0),
+        }),
+    }
+}
+
+fn rpc_cache_stats(method: &str, stats: CacheStats) -> RpcCacheStats {
+    RpcCacheStats {
+        method: method.to_string(),
+        hits: stats.hits,
+        misses: stats.misses,
+        entries: stats.entries as u32,
+        hit_rate: stats.hit_rate(),
+    }
+}
+
+/// Add the size fields a subscriber asked for. Unknown precision leaves lots unset.
+fn annotate_sizes(
+    snapshot: &mut PbOrderbookSnapshot,
+    orderbook: &FastOrderbook,
+    normalizer: &SizeNormalizer,
+    sizes: SizeOptions,
+) {
+    let sz_decimals = if sizes.normalized { normalizer.sz_decimals(snapshot.market_id) } else { None };
+    let notional_price = if sizes.notional { crate::position_pnl::mark_price(orderbook) } else { None };
+    if let Some(sz_decimals) = sz_decimals {
+        snapshot.sz_decimals = sz_decimals;
+    }
+    if let Some(price) = notional_price {
+        snapshot.notional_price = price;
+    }
+
+    for level in snapshot.bids.iter_mut().chain(snapshot.asks.iter_mut()) {
+        if let Some(sz_decimals) = sz_decimals {
+            level.lots = SizeNormalizer::lots(level.quantity, sz_decimals);
+        }
+        if let Some(price) = notional_price {
+            level.notional = level.quantity * price;
+        }
+    }
+}
+
+fn session_event_to_pb(event: &SessionBoundary) -> PbSessionEvent {
+    let kind = match &event.kind {
+        SessionEventKind::HourRollover { hour_file, hour_start_ms } => pb::session_event::Kind::HourRollover(HourRollover {
+            hour_file: hour_file.clone(),
+            hour_start: *hour_start_ms as i64,
+        }),
+        SessionEventKind::DayChange { date } => pb::session_event::Kind::DayChange(DayChange { date: date.clone() }),
+        SessionEventKind::RegistryRefresh { market_count } => pb::session_event::Kind::RegistryRefresh(RegistryRefresh {
+            market_count: *market_count as u32,
+        }),
+    };
+
+    PbSessionEvent {
+        event_id: event.event_id,
+        timestamp: (event.timestamp_ns / 1000) as i64,
+        sequences: event.sequences.iter().map(|(k, v)| (*k, *v)).collect(),
+        kind: Some(kind),
+    }
+}
+
+fn alert_to_pb(alert: &OperationalAlert) -> PbAlert {
+    let kind = match &alert.kind {
+        AlertKind::ErrorRateSpike { category, count, window } => pb::alert::Kind::ErrorRateSpike(ErrorRateSpike {
+            category: category.as_str().to_string(),
+            count: *count as u32,
+            window_secs: window.as_secs() as u32,
+        }),
+        AlertKind::Degradation(event) => pb::alert::Kind::Degradation(degradation_to_pb(event)),
+        AlertKind::Contagion { group, group_size, markets } => pb::alert::Kind::Contagion(Contagion {
+            group: group.clone(),
+            group_size: *group_size as u32,
+            markets: markets
+                .iter()
+                .map(|market| PbStressedMarket {
+                    market_id: market.market_id,
+                    symbol: market.symbol.clone(),
+                    spread_blowout: market.spread_blowout,
+                    depth_collapse: market.depth_collapse,
+                    spread_bps: market.spread_bps,
+                    baseline_spread_bps: market.baseline_spread_bps,
+                    depth: market.depth,
+                    baseline_depth: market.baseline_depth,
+                    timestamp: market.timestamp_us,
+                })
+                .collect(),
+        }),
+    };
+
+    PbAlert {
+        alert_id: alert.alert_id,
+        timestamp: alert.timestamp_ms as i64,
+        severity: alert.severity.as_str().to_string(),
+        kind: Some(kind),
+    }
+}
+
+fn degradation_to_pb(event: &DegradationEvent) -> DegradationState {
+    DegradationState {
+        event_id: event.event_id,
+        timestamp: event.timestamp_ms as i64,
+        degraded: event.degraded,
+        reasons: event.reasons.clone(),
+        backlog: event.pressure.backlog as u32,
+        fanout_lag: event.pressure.fanout_lag as u32,
+        throttled_share: event.pressure.throttled_share,
+    }
+}
+
+fn user_activity_to_pb(activity: ActivitySummary, window: Duration) -> PbUserActivity {
+    PbUserActivity {
+        user: activity.user,
+        window_secs: window.as_secs() as u32,
+        orders: activity.orders,
+        cancels: activity.cancels,
+        fills: activity.fills,
+        order_rate: activity.order_rate,
+        cancel_to_trade: activity.cancel_to_trade,
+        notional_velocity: activity.notional_velocity,
+        market_count: activity.market_count as u32,
+        order_rate_percentile: activity.order_rate_percentile,
+        cancel_to_trade_percentile: activity.cancel_to_trade_percentile,
+        notional_velocity_percentile: activity.notional_velocity_percentile,
+    }
+}
+
+/// Full-depth snapshot at exactly the published sequence, for delta streams
+/// A stop order with its distance from its market's current mid
+fn stop_order_to_pb(orderbooks: &MarketBooks, market_id: u32, order: StopOrder) -> PbStopOrder {
+    let current_mid = orderbooks
+        .get(&market_id)
+        .and_then(|orderbook| orderbook.get_best_bid_ask())
+        .map_or(0.0, |(best_bid, best_ask)| (best_bid + best_ask) / 2.0);
+    let distance_from_mid_bps = if current_mid > 0.0 { ((order.price - current_mid).abs() / current_mid) * 10000.0 } else { 0.0 };
+    PbStopOrder {
+        id: order.id,
+        notional: order.price * order.size,
+        user: order.user,
+        market_id,
+        coin: order.coin,
+        side: order.side,
+        price: order.price,
+        size: order.size,
+        trigger_condition: order.trigger_condition,
+        timestamp: order.timestamp,
+        distance_from_mid_bps,
+        current_mid_price: current_mid,
+    }
+}
+
+fn published_snapshot(market_id: u32, orderbook: &FastOrderbook) -> PbOrderbookSnapshot {
+    let published = orderbook.snapshot();
+    let mut snapshot = levels_snapshot(
+        market_id,
+        &orderbook.symbol,
+        published.bids.clone(),
+        published.asks.clone(),
+        now_micros(),
+        published.sequence,
+    );
+    snapshot.microprice = published.microprice;
+    snapshot.depth_weighted_mid = published.depth_weighted_mid;
+    snapshot.checksum = Some(published.checksum);
+    annotate_ticks(&mut snapshot, orderbook.tick_size());
+    snapshot
+}
+
+fn vector_to_pb(vector: ConformanceVector) -> PbConformanceVector {
+    let book = |book: VectorBook| levels_snapshot(vector.market_id, &vector.symbol, book.bids, book.asks, 0, book.sequence);
+    PbConformanceVector {
+        name: vector.name.clone(),
+        description: vector.description.clone(),
+        snapshot: Some(book(vector.snapshot.clone())),
+        expected: Some(book(vector.expected.clone())),
+        snapshot_checksum: vector.snapshot_checksum,
+        delta_checksums: vector.deltas.iter().map(|delta| delta.checksum).collect(),
+        expected_checksum: vector.expected_checksum,
+        deltas: vector
+            .deltas
+            .iter()
+            .map(|delta| PbOrderbookDelta {
+                market_id: vector.market_id,
+                sequence: delta.sequence,
+                prev_sequence: delta.prev_sequence,
+                timestamp: delta.timestamp,
+                changes: delta
+                    .changes
+                    .iter()
+                    .map(|change| LevelChange {
+                        action: change.action.clone(),
+                        side: change.side.clone(),
+                        price: change.price,
+                        quantity: change.quantity,
+                        ..Default::default()
+                    })
+                    .collect(),
+                // Vectors check the whole book, in delta_checksums
+                checksum: None,
+            })
+            .collect(),
+    }
+}
+
+fn level_change(delta: &SequencedDelta, tick_size: TickSize) -> LevelChange {
+    let (action, side, price) = delta.level_action();
+    LevelChange {
+        action: action.to_string(),
+        side: side.to_string(),
+        price,
+        quantity: delta.level_size,
+        price_ticks: tick_size.to_ticks(price),
+    }
+}
+
+fn delta_message(delta: PbOrderbookDelta) -> DeltaMessage {
+    DeltaMessage { payload: Some(pb::delta_message::Payload::Delta(delta)) }
+}
+
+/// A subscription's lag policy; `None` keeps the stream's own behaviour
+fn lag_policy(policy: &str) -> Result<Option<LagPolicy>, Status> {
+    match policy {
+        "" => Ok(None),
+        policy => policy.parse().map(Some).map_err(|e: anyhow::Error| Status::invalid_argument(e.to_string())),
+    }
+}
+
+/// Tells a client its stream missed `missed` updates of its markets
+fn lag_notice(missed: u64, market_ids: &HashSet<u32>, policy: LagPolicy) -> pb::Lag {
+    let mut market_ids: Vec<u32> = market_ids.iter().copied().collect();
+    market_ids.sort_unstable();
+    pb::Lag { missed_updates: missed, market_ids, policy: policy.as_str().to_string(), timestamp: now_micros() }
+}
+
+/// Ends a stream under the disconnect policy
+fn fell_behind(missed: u64) -> Status {
+    Status::resource_exhausted(format!("Stream fell behind by {} updates", missed))
+}
+
+/// How often snapshot-only streams send the markets that moved, as often
+/// as cold markets are published
+const SNAPSHOT_ONLY_INTERVAL: Duration = MarketTier::Cold.min_publish_interval();
+
+/// Deltas taking a client book from `after_sequence` to the newest buffered
+/// update, with the sequence they end at. `None` if they are no longer all
+/// buffered.
+fn resume_deltas(
+    buffer: &ResumeBuffer,
+    market_id: u32,
+    orderbook: &FastOrderbook,
+    after_sequence: u64,
+) -> Option<(u64, Vec<PbOrderbookDelta>)> {
+    if after_sequence == orderbook.snapshot().sequence {
+        return Some((after_sequence, Vec::new()));
+    }
+
+    let mut barrier = SnapshotBarrier::default();
+    barrier.record(market_id, after_sequence);
+    let deltas = buffer
+        .since(market_id, after_sequence)?
+        .iter()
+        .filter_map(|update| Some(update_to_delta(update, barrier.admit(update)?, orderbook.tick_size())))
+        .collect();
+    Some((barrier.sequence(market_id)?, deltas))
+}
+
+/// Level changes of `deltas`, the part of `update` a client still needs
+fn update_to_delta(update: &MarketUpdate, deltas: &[SequencedDelta], tick_size: TickSize) -> PbOrderbookDelta {
+    PbOrderbookDelta {
+        market_id: update.market_id,
+        sequence: update.sequence,
+        prev_sequence: deltas.first().map_or(update.sequence, |d| d.sequence - 1),
+        timestamp: (update.timestamp_ns / 1000) as i64,
+        changes: deltas.iter().map(|delta| level_change(delta, tick_size)).collect(),
+        checksum: update.checksum,
+    }
+}
+
+/// The write-ahead log range of a replay request, where zero leaves a bound
+/// open
+pub(crate) fn wal_range(from_sequence: u64, to_sequence: u64, from_ms: i64, to_ms: i64) -> Result<WalRange, Status> {
+    let bound = |value: u64| (value > 0).then_some(value);
+    let range = WalRange {
+        from_sequence: bound(from_sequence),
+        to_sequence: bound(to_sequence),
+        from_ns: bound(from_ms.max(0) as u64 * 1_000_000),
+        // Through the end of the last millisecond
+        to_ns: bound(to_ms.max(0) as u64 * 1_000_000).map(|to| to + 999_999),
+    };
+    if range.from_sequence.zip(range.to_sequence).is_some_and(|(from, to)| from > to)
+        || range.from_ns.zip(range.to_ns).is_some_and(|(from, to)| from > to)
+    {
+        return Err(Status::invalid_argument("Replay range ends before it starts"));
+    }
+    Ok(range)
+}
+
+/// Interval in milliseconds and source of a candle request, with defaults
+pub(crate) fn candle_series(interval: &str, source: &str) -> Result<(&'static str, u64, CandleSource), Status> {
+    let interval = if interval.is_empty() { "1m" } else { interval };
+    let (name, interval_ms) = candles::INTERVALS
+        .into_iter()
+        .find(|(name, _)| *name == interval)
+        .ok_or_else(|| Status::invalid_argument(format!("Unknown interval {}", interval)))?;
+    let source = if source.is_empty() { CandleSource::Trades } else {
+        CandleSource::parse(source).ok_or_else(|| Status::invalid_argument(format!("Unknown candle source {}", source)))?
+    };
+    Ok((name, interval_ms, source))
+}
+
+fn candle_to_pb(market_id: u32, interval: &str, source: CandleSource, candle: &candles::Candle) -> PbCandle {
+    PbCandle {
+        market_id,
+        interval: interval.to_string(),
+        source: source.as_str().to_string(),
+        start_time: candle.start_ms as i64,
+        open: candle.open,
+        high: candle.high,
+        low: candle.low,
+        close: candle.close,
+        volume: candle.volume,
+        trades: candle.trades,
+    }
+}
+
+/// Top of the published book
+fn bbo(market_id: u32, published: &BookSnapshot) -> Bbo {
+    let level = |(price, quantity): &(f64, f64)| Level { price: *price, quantity: *quantity, ..Default::default() };
+    Bbo {
+        market_id,
+        sequence: published.sequence,
+        timestamp: now_micros(),
+        bid: published.bids.first().map(level),
+        ask: published.asks.first().map(level),
+        microprice: published.microprice,
+        depth_weighted_mid: published.depth_weighted_mid,
+    }
+}
+
+/// Every resting order of a market, at exactly the sequence it reflects
+fn l3_snapshot(market_id: u32, orderbook: &FastOrderbook) -> L3Snapshot {
+    let orders = orderbook.orders();
+    // Positions restart at every price
+    let queue = |orders: &[fast_orderbook::Order]| {
+        let mut queue_position = 0;
+        orders
+            .iter()
+            .enumerate()
+            .map(|(i, order)| {
+                queue_position = if i > 0 && orders[i - 1].price == order.price { queue_position + 1 } else { 0 };
+                L3Order { oid: order.id, price: order.price, size: order.size, queue_position, stuffing: false }
+            })
+            .collect()
+    };
+
+    L3Snapshot {
+        market_id,
+        sequence: orders.sequence,
+        timestamp: now_micros(),
+        bids: queue(&orders.bids),
+        asks: queue(&orders.asks),
+        resync: false,
+    }
+}
+
+fn l3_event(delta: &SequencedDelta) -> L3Event {
+    let (action, side, oid, price) = match delta.delta {
+        OrderbookDelta::AddBid { price, order_id, .. } => ("add", "B", order_id, price),
+        OrderbookDelta::AddAsk { price, order_id, .. } => ("add", "A", order_id, price),
+        OrderbookDelta::RemoveBid { price, order_id } => ("remove", "B", order_id, price),
+        OrderbookDelta::RemoveAsk { price, order_id } => ("remove", "A", order_id, price),
+        OrderbookDelta::Clear => ("clear", "", 0, 0.0),
+        OrderbookDelta::ChangeLevel { is_buy, price, order_id, .. } => ("modify", if is_buy { "B" } else { "A" }, order_id, price),
+    };
+
+    L3Event {
+        sequence: delta.sequence,
+        action: action.to_string(),
+        side: side.to_string(),
+        oid,
+        price,
+        size: delta.order_size,
+        queue_position: delta.queue_position as u32,
+        stuffing: false,
+    }
+}
+
+/// Order events of `deltas`, the part of `update` a client still needs
+fn update_to_l3(update: &MarketUpdate, deltas: &[SequencedDelta]) -> L3Update {
+    L3Update {
+        market_id: update.market_id,
+        sequence: update.sequence,
+        prev_sequence: deltas.first().map_or(update.sequence, |d| d.sequence - 1),
+        timestamp: (update.timestamp_ns / 1000) as i64,
+        events: deltas.iter().map(l3_event).collect(),
+    }
+}
+
+/// A stream task's output, recorded as it is sent while the subscriber is teed
+fn teed<T: prost::Message + 'static>(
+    rx: tokio::sync::mpsc::Receiver<Result<T, Status>>,
+    tee: Arc<TeeSlot>,
+) -> impl Stream<Item = Result<T, Status>> + Send {
+    tokio_stream::StreamExt::map(tokio_stream::wrappers::ReceiverStream::new(rx), move |item| {
+        if let Ok(message) = &item {
+            tee.record(message);
+        }
+        item
+    })
+}
+
+fn levels_snapshot(
+    market_id: u32,
+    symbol: &str,
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+    timestamp: i64,
+    sequence: u64,
+) -> PbOrderbookSnapshot {
+    PbOrderbookSnapshot {
+        market_id,
+        symbol: symbol.to_string(),
+        timestamp,
+        sequence,
+        bids: bids
+            .into_iter()
+            .map(|(price, quantity)| Level { price, quantity, ..Default::default() })
+            .collect(),
+        asks: asks
+            .into_iter()
+            .map(|(price, quantity)| Level { price, quantity, ..Default::default() })
+            .collect(),
+        resync: false,
+        ..Default::default()
+    }
+}
+
 
 // Delta streaming service for optimized low-latency updates
 pub struct DeltaStreamingService {
-    orderbooks: HashMap<u32, Arc<FastOrderbook>>,
-    update_rx: Arc<RwLock<broadcast::Receiver<MarketUpdate>>>,
+    orderbooks: MarketBooks,
+    fanout: Arc<MarketFanout>,
     stop_order_manager: Arc<StopOrderManager>,
     market_registry: Arc<DynamicMarketRegistry>,
-    // COMMENTED OUT DUE TO COMPILATION ERRORS
-    // mark_price_service: Option<Arc<crate::mark_price_service::MarkPriceService>>,
-    // mark_price_rx: Arc<RwLock<Option<broadcast::Receiver<crate::mark_price_service::MarkPriceUpdateEvent>>>>,
+    market_tiers: Arc<MarketTiers>,
+    // Operator-requested resnapshots, fanned out to every active stream
+    resnapshot_tx: broadcast::Sender<Arc<Vec<u32>>>,
+    ofi_engine: Arc<OfiEngine>,
+    pnl_tracker: Arc<PositionPnlTracker>,
+    replay_cache: Arc<ReplayCache>,
+    session_events: Arc<SessionEvents>,
+    watermarks: Arc<WatermarkTracker>,
+    error_buffer: Arc<ErrorBuffer>,
+    alerts: Arc<Alerts>,
+    templates: Arc<SubscriptionTemplates>,
+    size_normalizer: Arc<SizeNormalizer>,
+    user_activity: Arc<UserActivityTracker>,
+    market_scheduler: Arc<MarketScheduler>,
+    publish_batching: Arc<BatchWindows>,
+    degradation: Arc<DegradationMonitor>,
+    subscribers: Arc<Subscribers>,
+    processor: Option<Arc<RobustOrderProcessor>>,
+    book_shards: Option<Arc<BookShards>>,
+    latency: Option<Arc<PipelineLatency>>,
+    started: Instant,
+    resume_buffer: Arc<ResumeBuffer>,
+    fills: Option<Arc<FillMonitor>>,
+    candles: Arc<CandleAggregator>,
+    book_metrics: Arc<BookMetricsEngine>,
+    tee_dir: PathBuf,
+    stuffing: Option<Arc<QuoteStuffingFilter>>,
+    // Polled unary answers, by request and the state they were built from
+    orderbook_cache: RpcCache<OrderbookCacheKey, OrderbookVersion, PbOrderbookSnapshot>,
+    markets_cache: RpcCache<(), u64, Vec<Market>>,
+    mark_prices: Option<Arc<MarkPriceService>>,
+    delta_wal: Option<Arc<DeltaWal>>,
+    book_drift: Option<Arc<BookDriftMonitor>>,
+}
+
+/// Market, effective depth, normalized sizes and notional of a GetOrderbook
+type OrderbookCacheKey = (u32, usize, bool, bool);
+
+/// Published sequence, size decimals and notional price bits behind a
+/// GetOrderbook answer
+type OrderbookVersion = (u64, Option<u32>, Option<u64>);
+
+/// What a single orderbook stream publishes
+struct StreamOptions {
+    market_ids: HashSet<u32>,
+    depth: usize,
+    /// Minimum time between snapshots of one market; zero publishes every update
+    update_interval: Duration,
+    /// Levels smaller than this are left out of snapshots
+    min_quantity: f64,
+    /// Replay this much recent history before going live
+    replay: Duration,
+    sizes: SizeOptions,
+    /// Switch to conflated snapshots while the service is degraded
+    best_effort: bool,
+    /// Last sequence the client saw, by market
+    resume: HashMap<u32, u64>,
+    /// What to do on falling behind; disconnects if unset
+    lag_policy: Option<LagPolicy>,
+    /// The caller's, for markets added later
+    permissions: Arc<KeyPermissions>,
+    /// Holds the stream's place in its key's quota
+    quota: QuotaGuard,
 }
 
 impl DeltaStreamingService {
     pub fn new(
-        orderbooks: HashMap<u32, Arc<FastOrderbook>>,
-        update_rx: broadcast::Receiver<MarketUpdate>,
+        orderbooks: MarketBooks,
+        fanout: Arc<MarketFanout>,
         stop_order_manager: Arc<StopOrderManager>,
         market_registry: Arc<DynamicMarketRegistry>,
+        market_tiers: Arc<MarketTiers>,
+        ofi_engine: Arc<OfiEngine>,
+        pnl_tracker: Arc<PositionPnlTracker>,
+        replay_cache: Arc<ReplayCache>,
+        session_events: Arc<SessionEvents>,
+        watermarks: Arc<WatermarkTracker>,
+        error_buffer: Arc<ErrorBuffer>,
+        alerts: Arc<Alerts>,
     ) -> Self {
         Self {
             orderbooks,
-            update_rx: Arc::new(RwLock::new(update_rx)),
+            fanout,
             stop_order_manager,
             market_registry,
-            // COMMENTED OUT DUE TO COMPILATION ERRORS
-            // mark_price_service: None,
-            // mark_price_rx: Arc::new(RwLock::new(None)),
+            market_tiers,
+            resnapshot_tx: broadcast::channel(16).0,
+            ofi_engine,
+            pnl_tracker,
+            replay_cache,
+            session_events,
+            watermarks,
+            error_buffer,
+            alerts,
+            templates: Arc::new(SubscriptionTemplates::default()),
+            size_normalizer: Arc::new(SizeNormalizer::default()),
+            user_activity: Arc::new(UserActivityTracker::default()),
+            market_scheduler: Arc::new(MarketScheduler::new(SchedulerConfig::default())),
+            publish_batching: Arc::default(),
+            degradation: Arc::new(DegradationMonitor::new(DegradationConfig::default())),
+            subscribers: Arc::new(Subscribers::default()),
+            processor: None,
+            book_shards: None,
+            latency: None,
+            started: Instant::now(),
+            resume_buffer: Arc::new(ResumeBuffer::default()),
+            fills: None,
+            candles: Arc::new(CandleAggregator::default()),
+            book_metrics: Arc::new(BookMetricsEngine::default()),
+            tee_dir: std::env::temp_dir().join("orderbook-tees"),
+            stuffing: None,
+            orderbook_cache: RpcCache::default(),
+            markets_cache: RpcCache::default(),
+            mark_prices: None,
+            delta_wal: None,
+            book_drift: None,
         }
     }
     
-    // COMMENTED OUT DUE TO COMPILATION ERRORS
-    // pub fn set_mark_price_service(
-    //     &mut self,
-    //     mark_price_service: Arc<crate::mark_price_service::MarkPriceService>,
-    //     mark_price_rx: broadcast::Receiver<crate::mark_price_service::MarkPriceUpdateEvent>,
-    // ) {
-    //     self.mark_price_service = Some(mark_price_service);
-    //     *self.mark_price_rx.write() = Some(mark_price_rx);
-    // }
+    pub fn set_subscription_templates(&mut self, templates: SubscriptionTemplates) {
+        self.templates = Arc::new(templates);
+    }
+    
+    pub fn set_size_normalizer(&mut self, size_normalizer: Arc<SizeNormalizer>) {
+        self.size_normalizer = size_normalizer;
+    }
     
-}
+    pub fn set_user_activity(&mut self, user_activity: Arc<UserActivityTracker>) {
+        self.user_activity = user_activity;
+    }
+    
+    pub fn set_market_scheduler(&mut self, market_scheduler: Arc<MarketScheduler>) {
+        self.market_scheduler = market_scheduler;
+    }
+    
+    pub fn set_publish_batching(&mut self, publish_batching: Arc<BatchWindows>) {
+        self.publish_batching = publish_batching;
+    }
+    
+    pub fn set_degradation(&mut self, degradation: Arc<DegradationMonitor>) {
+        self.degradation = degradation;
+    }
+    
+    pub fn set_resume_buffer(&mut self, resume_buffer: Arc<ResumeBuffer>) {
+        self.resume_buffer = resume_buffer;
+    }
+    
+    /// Source of trades for SubscribeTrades
+    pub fn set_fills(&mut self, fills: Arc<FillMonitor>) {
+        self.fills = Some(fills);
+    }
+    
+    pub fn set_candles(&mut self, candles: Arc<CandleAggregator>) {
+        self.candles = candles;
+    }
+    
+    pub fn set_book_metrics(&mut self, book_metrics: Arc<BookMetricsEngine>) {
+        self.book_metrics = book_metrics;
+    }
+    
+    /// Quote stuffing detection, for GetStuffingStats and its action
+    pub fn set_stuffing_filter(&mut self, stuffing: Arc<QuoteStuffingFilter>) {
+        self.stuffing = Some(stuffing);
+    }
+    
+    /// The stuffing filter, if it's set to `action`
+    fn stuffing_action(&self, action: StuffingAction) -> Option<Arc<QuoteStuffingFilter>> {
+        self.stuffing.clone().filter(|stuffing| stuffing.config().action == action)
+    }
+    
+    /// Where StartTee writes its files
+    pub fn set_tee_dir(&mut self, tee_dir: PathBuf) {
+        self.tee_dir = tee_dir;
+    }
+    
+    /// Heap estimates of everything that grows with history or load
+    fn memory_report(&self) -> MemoryReport {
+        let mut books = MemoryUsage::default();
+        let mut event_logs = MemoryUsage::default();
+        for orderbook in self.orderbooks.all().values() {
+            books += orderbook.memory_usage();
+            event_logs += orderbook.event_log().lock().memory_usage();
+        }
+        let resume_buffer = self.resume_buffer.memory_usage();
+        
+        // Queued updates are sized like the buffered ones
+        let fanout = self.fanout.memory_usage();
+        let update_bytes = match resume_buffer.items {
+            0 => std::mem::size_of::<MarketUpdate>(),
+            items => resume_buffer.bytes / items,
+        };
+        
+        MemoryReport::new(vec![
+            ("books", books),
+            ("event_logs", event_logs),
+            ("resume_buffer", resume_buffer),
+            ("replay_cache", self.replay_cache.memory_usage()),
+            ("candles", self.candles.memory_usage()),
+            ("update_channel", MemoryUsage::new(fanout.items, fanout.bytes + fanout.items * update_bytes)),
+        ])
+    }
+    
+    /// Source of feed progress and circuit states for GetStats and
+    /// GetServiceHealth
+    pub fn set_processor(&mut self, processor: Arc<RobustOrderProcessor>) {
+        self.processor = Some(processor);
+    }
+    
+    /// Threads the book actors run on, for GetStats
+    pub fn set_book_shards(&mut self, book_shards: Arc<BookShards>) {
+        self.book_shards = Some(book_shards);
+    }
+    
+    /// Pipeline latencies for GetLatencyStats; streams record their sends
+    pub fn set_pipeline_latency(&mut self, latency: Arc<PipelineLatency>) {
+        self.latency = Some(latency);
+    }
+    
+    /// Resolve a template's markets to ids served by this instance
+    async fn resolve_template_markets(&self, name: &str, template: &SubscriptionTemplate) -> Result<Vec<u32>, Status> {
+        let mut market_ids = Vec::with_capacity(template.markets.len());
+        for market in &template.markets {
+            let market_id = match market {
+                MarketRef::Id(id) => Some(*id),
+                MarketRef::Coin(coin) => self.market_registry.get_market_id(coin).await,
+            };
+            match market_id {
+                Some(id) if self.orderbooks.contains_key(&id) => market_ids.push(id),
+                _ => {
+                    return Err(Status::failed_precondition(format!(
+                        "Template {} references unavailable market {:?}",
+                        name, market
+                    )))
+                }
+            }
+        }
+        Ok(market_ids)
+    }
+    
+    /// A market named by its symbol ("HYPERLIQUID-BTC/USD-PERP") or coin ("BTC")
+    async fn resolve_symbol(&self, symbol: &str) -> Result<u32, Status> {
+        match self.market_registry.get_market_id(symbol).await {
+            Some(id) if self.orderbooks.contains_key(&id) => Ok(id),
+            _ => Err(Status::not_found(format!("Market {} not found", symbol))),
+        }
+    }
 
-#[tonic::async_trait]
-impl OrderbookService for DeltaStreamingService {
-    type SubscribeOrderbookStream =
-        Pin<Box<dyn Stream<Item = Result<PbOrderbookSnapshot, Status>> + Send>>;
+    /// Markets a stream over `market_ids` counts against its key's quota,
+    /// for streams where none means every market
+    fn quota_markets(&self, market_ids: &HashSet<u32>) -> usize {
+        if market_ids.is_empty() {
+            self.orderbooks.len()
+        } else {
+            market_ids.len()
+        }
+    }
 
-    async fn subscribe_orderbook(
-        &self,
-        request: Request<SubscribeRequest>,
-    ) -> Result<Response<Self::SubscribeOrderbookStream>, Status> {
-        let subscribe_request = request.into_inner();
-        let requested_markets: std::collections::HashSet<u32> =
-            subscribe_request.market_ids.into_iter().collect();
+    async fn resolve_symbols(&self, symbols: &[String]) -> Result<Vec<u32>, Status> {
+        let mut market_ids = Vec::with_capacity(symbols.len());
+        for symbol in symbols {
+            market_ids.push(self.resolve_symbol(symbol).await?);
+        }
+        Ok(market_ids)
+    }
+    
+    /// Source of mark prices for SubscribeMarkPrices and GetMarkPrice
+    pub fn set_mark_price_service(&mut self, mark_prices: Arc<MarkPriceService>) {
+        self.mark_prices = Some(mark_prices);
+    }
+
+    pub fn set_delta_wal(&mut self, wal: Arc<DeltaWal>) {
+        self.delta_wal = Some(wal);
+    }
+
+    /// Checks of the books against the L2 API, for GetBookDrift and GetStats
+    pub fn set_book_drift(&mut self, monitor: Arc<BookDriftMonitor>) {
+        self.book_drift = Some(monitor);
+    }
+
+    fn pb_book_drift(&self, drift: &BookDrift) -> PbBookDrift {
+        PbBookDrift {
+            market_id: drift.market_id,
+            symbol: self.orderbooks.get(&drift.market_id).map(|ob| ob.symbol.clone()).unwrap_or_default(),
+            checked_ms: drift.checked_ms as i64,
+            sequence: drift.sequence,
+            compared_levels: drift.diff.compared as u32,
+            missing_levels: drift.diff.missing as u32,
+            extra_levels: drift.diff.extra as u32,
+            mean_size_drift_bps: drift.diff.mean_drift_bps,
+            max_size_drift_bps: drift.diff.max_drift_bps,
+            divergent: drift.divergent,
+            checks: drift.checks,
+            divergent_checks: drift.divergent_checks,
+        }
+    }
+
+    /// SubscribeDeltas, as messages encoded once per update and shared by
+    /// every stream of its market
+    pub(crate) fn encoded_deltas(&self, request: Request<DeltaSubscribeRequest>) -> Result<EncodedStream, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        let market_ids: HashSet<u32> = req.market_ids.into_iter().collect();
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
+        }
+        permissions.check_markets(&market_ids)?;
+        let resume = req.resume_from_sequence;
+        let lag_policy = lag_policy(&req.lag_policy)?;
+
+        info!("New incremental delta subscription for markets: {:?}", market_ids);
+
+        // Subscribe before taking snapshots so no update falls in between
+        let mut rx = self.fanout.subscribe(market_ids.iter().copied());
+        let mut resnapshot_rx = self.resnapshot_tx.subscribe();
+        let orderbooks = self.orderbooks.clone();
+        let market_tiers = self.market_tiers.clone();
+        let degradation = self.degradation.clone();
+        let resume_buffer = self.resume_buffer.clone();
+        let quota = quota.acquire(market_ids.len())?;
+
+        let subscriber = self.subscribers.register("SubscribeDeltas", peer, market_ids.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let latency = self.latency.clone();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            // Sequence each market's client book is at
+            let mut barrier = SnapshotBarrier::default();
+            // Bring a client book at `after` up to date with buffered deltas,
+            // or replace it with a snapshot when they aren't all buffered
+            let catch_up = |market_id: u32, after: Option<u64>, resync: bool, barrier: &mut SnapshotBarrier| {
+                let Some(orderbook) = orderbooks.get(&market_id) else { return Vec::new() };
+                let resumed = after.and_then(|after| resume_deltas(&resume_buffer, market_id, &orderbook, after));
+                if let Some((sequence, deltas)) = resumed {
+                    barrier.record(market_id, sequence);
+                    return deltas.into_iter().map(|delta| Encoded::new(&delta_message(delta))).collect();
+                }
+
+                let mut snapshot = published_snapshot(market_id, &orderbook);
+                snapshot.resync = resync;
+                snapshot.gap_detected = after.is_some();
+                barrier.record(market_id, snapshot.sequence);
+                vec![Encoded::new(&DeltaMessage { payload: Some(pb::delta_message::Payload::Snapshot(snapshot)) })]
+            };
+
+            let mut pending = Vec::new();
+            for market_id in &market_ids {
+                pending.extend(catch_up(*market_id, resume.get(market_id).copied(), false, &mut barrier));
+            }
+            // Markets whose next update continues past a flagged gap
+            let mut gapped = HashSet::new();
+            let mut snapshot_only = false;
+            let mut snapshot_ticker = tokio::time::interval(SNAPSHOT_ONLY_INTERVAL);
+            snapshot_ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            // The market and oldest open of the update now pending
+            let mut pending_event = None;
+            loop {
+                for message in pending.drain(..) {
+                    if tx.send(Ok(message)).await.is_err() {
+                        return;
+                    }
+                }
+                if let (Some(latency), Some((market_id, event_ms))) = (&latency, pending_event.take()) {
+                    latency.record(market_id, Stage::Sent, &[event_ms]);
+                }
+
+                tokio::select! {
+                    result = rx.recv() => match result {
+                        Ok(_) if snapshot_only => degradation.record_fanout_lag(rx.queued()),
+                        // Cold markets are resnapshotted on the ticker instead
+                        Ok(update) if !market_tiers.is_hot(update.market_id) => degradation.record_fanout_lag(rx.queued()),
+                        Ok(update) => {
+                            degradation.record_fanout_lag(rx.queued());
+                            // Updates published before this stream subscribed
+                            // are only in the buffer
+                            if !gapped.remove(&update.market_id) && barrier.has_gap(&update) {
+                                let after = barrier.sequence(update.market_id);
+                                pending.extend(catch_up(update.market_id, after, false, &mut barrier));
+                            }
+                            // Whole updates go out encoded once for every
+                            // stream of the market
+                            let tick_size = orderbooks.get(&update.market_id).map_or_else(TickSize::default, |orderbook| orderbook.tick_size());
+                            match barrier.admit(&update) {
+                                Some(deltas) if deltas.len() == update.deltas.len() => {
+                                    pending.push(update.encoded(|update| {
+                                        Encoded::new(&delta_message(update_to_delta(update, &update.deltas, tick_size)))
+                                    }));
+                                }
+                                Some(deltas) => pending.push(Encoded::new(&delta_message(update_to_delta(&update, deltas, tick_size)))),
+                                None => continue,
+                            }
+                            pending_event = update.event_ms.map(|event_ms| (update.market_id, event_ms));
+                        }
+                        // Patch from the buffer where possible, else replace the book
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => match lag_policy {
+                            None => {
+                                warn!("Delta stream lagged by {} updates, catching up", skipped);
+                                for market_id in &market_ids {
+                                    let after = barrier.sequence(*market_id);
+                                    pending.extend(catch_up(*market_id, after, true, &mut barrier));
+                                }
+                            }
+                            Some(LagPolicy::Disconnect) => {
+                                warn!("Delta stream lagged by {} updates, disconnecting", skipped);
+                                let _ = tx.send(Err(fell_behind(skipped))).await;
+                                return;
+                            }
+                            Some(policy) => {
+                                warn!("Delta stream lagged by {} updates ({})", skipped, policy.as_str());
+                                let notice = lag_notice(skipped, &market_ids, policy);
+                                pending.push(Encoded::new(&DeltaMessage { payload: Some(pb::delta_message::Payload::Lag(notice)) }));
+                                gapped.extend(market_ids.iter().copied());
+                                snapshot_only |= policy == LagPolicy::SnapshotOnly;
+                            }
+                        },
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = snapshot_ticker.tick() => {
+                        for market_id in market_ids.iter().filter(|id| snapshot_only || !market_tiers.is_hot(**id)) {
+                            let Some(orderbook) = orderbooks.get(market_id) else { continue };
+                            if barrier.sequence(*market_id) != Some(orderbook.snapshot().sequence) {
+                                pending.extend(catch_up(*market_id, None, true, &mut barrier));
+                            }
+                        }
+                    }
+                    result = resnapshot_rx.recv() => match result {
+                        Ok(requested) => {
+                            for market_id in requested.iter().filter(|id| market_ids.contains(id)) {
+                                pending.extend(catch_up(*market_id, None, true, &mut barrier));
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            for market_id in &market_ids {
+                                pending.extend(catch_up(*market_id, None, true, &mut barrier));
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+            }
+        });
 
-        info!("New delta subscription for markets: {:?}", requested_markets);
+        Ok(Box::pin(teed(rx_stream, tee)))
+    }
 
-        // Clone the broadcast receiver
-        let mut rx = self.update_rx.write().resubscribe();
+    fn spawn_orderbook_stream(
+        &self,
+        method: &'static str,
+        peer: Option<SocketAddr>,
+        options: StreamOptions,
+        mut changes: Option<tokio::sync::mpsc::Receiver<SubscriptionChange>>,
+    ) -> <Self as OrderbookService>::SubscribeOrderbookStream {
+        let StreamOptions { market_ids: mut requested_markets, mut depth, mut update_interval, min_quantity, replay, sizes, best_effort, resume, lag_policy, permissions, quota } = options;
+        let fanout = self.fanout.clone();
+        let mut rx = fanout.subscribe(requested_markets.iter().copied());
         let orderbooks = self.orderbooks.clone();
+        let market_tiers = self.market_tiers.clone();
+        let mut resnapshot_rx = self.resnapshot_tx.subscribe();
 
         // Create a channel for the stream
         let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
 
+        let replay_cache = self.replay_cache.clone();
+        let resume_buffer = self.resume_buffer.clone();
+        let degradation = self.degradation.clone();
+        let subscriber = self.subscribers.register(method, peer, requested_markets.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let latency = self.latency.clone();
+        let size_normalizer = self.size_normalizer.clone();
+        let filter = move |mut snapshot: PbOrderbookSnapshot, orderbook: &FastOrderbook| {
+            if min_quantity > 0.0 {
+                snapshot.bids.retain(|level| level.quantity >= min_quantity);
+                snapshot.asks.retain(|level| level.quantity >= min_quantity);
+            }
+            annotate_sizes(&mut snapshot, orderbook, &size_normalizer, sizes);
+            snapshot
+        };
+        let depth_for = {
+            let market_tiers = self.market_tiers.clone();
+            move |market_id: u32, depth: usize| depth.min(market_tiers.tier(market_id).max_depth())
+        };
+        let snapshot = {
+            let depth_for = depth_for.clone();
+            let filter = filter.clone();
+            move |market_id: u32, orderbook: &FastOrderbook, depth: usize, timestamp: i64| {
+                filter(build_snapshot(market_id, orderbook, depth_for(market_id, depth), timestamp), orderbook)
+            }
+        };
+
         // Spawn a task to handle the stream
         tokio::spawn(async move {
+            let mut subscriber = subscriber;
+            
+            // Sequence of the last book sent per market; updates it covers
+            // are skipped
+            let mut barrier = SnapshotBarrier::default();
+            // When each market was last sent, to pace throttled ones
+            let mut last_sent: HashMap<u32, Instant> = HashMap::new();
+            
+            // Late joiners of hot, unthrottled markets can ask for recent
+            // history, so they see the same frames as existing subscribers
+            let replay_since_ns = replay_cache.now_ns().saturating_sub(replay.as_nanos() as u64);
+            
             // Send initial snapshots
             for market_id in &requested_markets {
                 if let Some(orderbook) = orderbooks.get(market_id) {
-                    let (bids, asks) = orderbook.get_snapshot(50);
+                    // Resuming clients already hold the book unless it moved on
+                    let mut gap_detected = false;
+                    if let Some(after_sequence) = resume.get(market_id) {
+                        let sequence = orderbook.snapshot().sequence;
+                        if *after_sequence == sequence {
+                            barrier.record(*market_id, sequence);
+                            last_sent.insert(*market_id, Instant::now());
+                            continue;
+                        }
+                        gap_detected = resume_buffer.since(*market_id, *after_sequence).is_none();
+                    }
                     
-                    let snapshot = PbOrderbookSnapshot {
-                        market_id: *market_id,
-                        symbol: orderbook.symbol.clone(),
-                        timestamp: std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_micros() as i64,
-                        sequence: orderbook.sequence.load(std::sync::atomic::Ordering::Relaxed),
-                        bids: bids
-                            .into_iter()
-                            .map(|(price, quantity)| Level {
-                                price,
-                                quantity,
-                                })
-                            .collect(),
-                        asks: asks
-                            .into_iter()
-                            .map(|(price, quantity)| Level {
-                                price,
-                                quantity,
-                                })
-                            .collect(),
-                    };
+                    if !replay.is_zero() && update_interval.is_zero() && market_tiers.is_hot(*market_id) {
+                        let frames = replay_cache
+                            .replay(*market_id, &orderbook, replay_since_ns, depth_for(*market_id, depth))
+                            .unwrap_or_default();
+                        if let Some(last) = frames.last() {
+                            barrier.record(*market_id, last.sequence);
+                            last_sent.insert(*market_id, Instant::now());
+                        }
+                        for frame in frames {
+                            // Frames hold only the streamed depth, which is what
+                            // the derived prices of a replayed book can cover
+                            let microprice = fast_orderbook::microprice(&frame.bids, &frame.asks);
+                            let depth_weighted_mid = fast_orderbook::depth_weighted_mid(
+                                &frame.bids,
+                                &frame.asks,
+                                fast_orderbook::DEPTH_WEIGHTED_LEVELS,
+                            );
+                            let mut snapshot = levels_snapshot(
+                                *market_id,
+                                &orderbook.symbol,
+                                frame.bids,
+                                frame.asks,
+                                (frame.timestamp_ns / 1000) as i64,
+                                frame.sequence,
+                            );
+                            snapshot.microprice = microprice;
+                            snapshot.depth_weighted_mid = depth_weighted_mid;
+                            annotate_ticks(&mut snapshot, orderbook.tick_size());
+                            let snapshot = filter(snapshot, &orderbook);
+                            if tx.send(Ok(snapshot)).await.is_err() {
+                                return;
+                            }
+                        }
+                        if last_sent.contains_key(market_id) {
+                            continue;
+                        }
+                    }
+                    
+                    let mut snapshot = snapshot(*market_id, &orderbook, depth, now_micros());
+                    snapshot.gap_detected = gap_detected;
+                    barrier.record(*market_id, snapshot.sequence);
+                    last_sent.insert(*market_id, Instant::now());
                     let _ = tx.send(Ok(snapshot)).await;
                 }
             }
 
-            // Stream delta updates
-            while let Ok(update) = rx.recv().await {
-                if requested_markets.contains(&update.market_id) {
-                    // Convert deltas to snapshot format for now
-                    // In a production system, we'd have a separate delta message type
-                    if let Some(orderbook) = orderbooks.get(&update.market_id) {
-                        let (bids, asks) = orderbook.get_snapshot(50);
+            // Cold and throttled markets are published on this ticker instead of per update
+            let publish_ticker = |update_interval: Duration| {
+                let cold_interval = MarketTier::Cold.min_publish_interval();
+                let mut tick = if update_interval.is_zero() { cold_interval } else { update_interval.min(cold_interval) };
+                if best_effort {
+                    tick = tick.min(degradation.conflation_interval());
+                }
+                let mut ticker = tokio::time::interval(tick);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                ticker
+            };
+            let mut ticker = publish_ticker(update_interval);
+            // Goes out with the next snapshot after falling behind
+            let mut lag = None;
+
+            loop {
+                tokio::select! {
+                    result = rx.recv() => {
+                        let update = match result {
+                            Ok(update) => update,
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => match lag_policy {
+                                None | Some(LagPolicy::Disconnect) => {
+                                    warn!("Orderbook stream lagged by {} updates, disconnecting", skipped);
+                                    let _ = tx.send(Err(fell_behind(skipped))).await;
+                                    break;
+                                }
+                                Some(policy) => {
+                                    warn!("Orderbook stream lagged by {} updates ({})", skipped, policy.as_str());
+                                    lag = Some(lag_notice(skipped, &requested_markets, policy));
+                                    // Every market is then published on the ticker
+                                    if policy == LagPolicy::SnapshotOnly {
+                                        update_interval = update_interval.max(SNAPSHOT_ONLY_INTERVAL);
+                                    }
+                                    continue;
+                                }
+                            },
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+                        degradation.record_fanout_lag(rx.queued());
+                        
+                        if !update_interval.is_zero()
+                            || !market_tiers.is_hot(update.market_id)
+                            || (best_effort && degradation.is_degraded())
+                        {
+                            continue;
+                        }
+                        
+                        // Already covered by the last book sent
+                        if barrier.admit(&update).is_none() {
+                            continue;
+                        }
+                        
+                        // Convert deltas to snapshot format for now
+                        // In a production system, we'd have a separate delta message type
+                        if let Some(orderbook) = orderbooks.get(&update.market_id) {
+                            let mut snapshot = snapshot(update.market_id, &orderbook, depth, (update.timestamp_ns / 1000) as i64);
+                            snapshot.lag = lag.take();
+                            // The book may have moved past this update already
+                            barrier.record(update.market_id, snapshot.sequence);
+                            last_sent.insert(update.market_id, Instant::now());
+                            if tx.send(Ok(snapshot)).await.is_err() {
+                                break;
+                            }
+                            if let (Some(latency), Some(event_ms)) = (&latency, update.event_ms) {
+                                latency.record(update.market_id, Stage::Sent, &[event_ms]);
+                            }
+                        }
+                    }
+                    result = resnapshot_rx.recv() => {
+                        let market_ids = match result {
+                            Ok(market_ids) => market_ids,
+                            // Missed requests: resnapshot everything this stream watches
+                            Err(broadcast::error::RecvError::Lagged(_)) => {
+                                Arc::new(requested_markets.iter().copied().collect())
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
                         
-                        let snapshot = PbOrderbookSnapshot {
-                            market_id: update.market_id,
-                            symbol: orderbook.symbol.clone(),
-                            timestamp: (update.timestamp_ns / 1000) as i64,
-                            sequence: update.sequence,
-                            bids: bids
-                                .into_iter()
-                                .map(|(price, quantity)| Level {
-                                    price,
-                                    quantity,
-                                        })
-                                .collect(),
-                            asks: asks
-                                .into_iter()
-                                .map(|(price, quantity)| Level {
-                                    price,
-                                    quantity,
-                                        })
-                                .collect(),
+                        for market_id in market_ids.iter().filter(|id| requested_markets.contains(id)) {
+                            if let Some(orderbook) = orderbooks.get(market_id) {
+                                let mut snapshot = snapshot(*market_id, &orderbook, depth, now_micros());
+                                snapshot.resync = true;
+                                snapshot.lag = lag.take();
+                                barrier.record(*market_id, snapshot.sequence);
+                                last_sent.insert(*market_id, Instant::now());
+                                if tx.send(Ok(snapshot)).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    _ = ticker.tick() => {
+                        let now = Instant::now();
+                        let conflated = best_effort && degradation.is_degraded();
+                        for market_id in &requested_markets {
+                            let mut interval = degradation.publish_interval(market_tiers.tier(*market_id)).max(update_interval);
+                            if conflated {
+                                interval = interval.max(degradation.conflation_interval());
+                            }
+                            if interval.is_zero() {
+                                continue;
+                            }
+                            
+                            if let Some(orderbook) = orderbooks.get(market_id) {
+                                let sequence = orderbook.snapshot().sequence;
+                                let unchanged = barrier.sequence(*market_id) == Some(sequence);
+                                if unchanged || last_sent.get(market_id).is_some_and(|t| now.duration_since(*t) < interval) {
+                                    continue;
+                                }
+                                
+                                let mut snapshot = snapshot(*market_id, &orderbook, depth, now_micros());
+                                snapshot.conflated = conflated;
+                                snapshot.lag = lag.take();
+                                barrier.record(*market_id, snapshot.sequence);
+                                last_sent.insert(*market_id, now);
+                                if tx.send(Ok(snapshot)).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    change = next_change(&mut changes) => {
+                        let Some(change) = change else {
+                            // The client stopped sending changes but may still read
+                            changes = None;
+                            continue;
                         };
-                        if tx.send(Ok(snapshot)).await.is_err() {
+                        if let Some(missing) = change.add_market_ids.iter().find(|id| !orderbooks.contains_key(id)) {
+                            let _ = tx.send(Err(Status::not_found(format!("Market {} not found", missing)))).await;
+                            break;
+                        }
+                        if let Err(status) = permissions.check_markets(&change.add_market_ids) {
+                            let _ = tx.send(Err(status)).await;
+                            break;
+                        }
+                        let mut markets = requested_markets.clone();
+                        for market_id in &change.remove_market_ids {
+                            markets.remove(market_id);
+                        }
+                        markets.extend(change.add_market_ids);
+                        if let Err(status) = subscriber.resize_quota(markets.len()) {
+                            let _ = tx.send(Err(status)).await;
                             break;
                         }
+                        
+                        if let Some(interval_ms) = change.update_interval_ms {
+                            update_interval = Duration::from_millis(interval_ms as u64);
+                            ticker = publish_ticker(update_interval);
+                        }
+                        let new_depth = change.depth.map(stream_depth).filter(|new_depth| *new_depth != depth);
+                        depth = new_depth.unwrap_or(depth);
+                        
+                        let previous = std::mem::replace(&mut requested_markets, markets);
+                        if requested_markets != previous {
+                            // Updates still queued on the old receiver are covered
+                            // by the catch-up snapshots below
+                            rx = fanout.subscribe(requested_markets.iter().copied());
+                            subscriber.set_markets(requested_markets.iter().copied());
+                            info!("Managed subscription now watches markets: {:?}", requested_markets);
+                        }
+                        
+                        // Added markets start from a snapshot, a new depth resends
+                        // every market, and kept ones catch up if they moved
+                        for market_id in &requested_markets {
+                            if let Some(orderbook) = orderbooks.get(market_id) {
+                                let moved = barrier.sequence(*market_id) != Some(orderbook.snapshot().sequence);
+                                if previous.contains(market_id) && new_depth.is_none() && !moved {
+                                    continue;
+                                }
+                                let snapshot = snapshot(*market_id, &orderbook, depth, now_micros());
+                                barrier.record(*market_id, snapshot.sequence);
+                                last_sent.insert(*market_id, Instant::now());
+                                if tx.send(Ok(snapshot)).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
                     }
                 }
             }
         });
 
-        let stream = tokio_stream::wrappers::ReceiverStream::new(rx_stream);
-        Ok(Response::new(Box::pin(stream) as Self::SubscribeOrderbookStream))
+        Box::pin(teed(rx_stream, tee))
     }
+}
 
-    async fn get_orderbook(
-        &self,
-        request: Request<GetOrderbookRequest>,
-    ) -> Result<Response<PbOrderbookSnapshot>, Status> {
-        let req = request.into_inner();
-        let depth = req.depth as usize;
+/// Levels a stream sends of each market; 0 asks for the default
+fn stream_depth(depth: u32) -> usize {
+    if depth == 0 { STREAM_DEPTH } else { depth as usize }
+}
 
-        match self.orderbooks.get(&req.market_id) {
+/// The next change to a ManageSubscription stream; other streams never get one
+async fn next_change(
+    changes: &mut Option<tokio::sync::mpsc::Receiver<SubscriptionChange>>,
+) -> Option<SubscriptionChange> {
+    match changes {
+        Some(changes) => changes.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[tonic::async_trait]
+impl OrderbookService for DeltaStreamingService {
+    type SubscribeOrderbookStream =
+        Pin<Box<dyn Stream<Item = Result<PbOrderbookSnapshot, Status>> + Send>>;
+
+    async fn subscribe_orderbook(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeOrderbookStream>, Status> {
+        let peer = request.remote_addr();
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let quota = auth_interceptor::stream_quota(&request);
+        let subscribe_request = request.into_inner();
+        let symbol_ids = self.resolve_symbols(&subscribe_request.symbols).await?;
+        let market_ids: HashSet<u32> = subscribe_request.market_ids.into_iter().chain(symbol_ids).collect();
+        permissions.check_markets(&market_ids)?;
+        let options = StreamOptions {
+            quota: quota.acquire(market_ids.len())?,
+            market_ids,
+            depth: stream_depth(subscribe_request.depth),
+            update_interval: Duration::from_millis(subscribe_request.update_interval_ms as u64),
+            min_quantity: 0.0,
+            replay: Duration::from_millis(subscribe_request.replay_ms as u64).min(self.replay_cache.window()),
+            sizes: SizeOptions {
+                normalized: subscribe_request.normalized_sizes,
+                notional: subscribe_request.notional,
+            },
+            best_effort: subscribe_request.best_effort,
+            resume: subscribe_request.resume_from_sequence,
+            lag_policy: lag_policy(&subscribe_request.lag_policy)?,
+            permissions,
+        };
+
+        info!("New delta subscription for markets: {:?}", options.market_ids);
+
+        Ok(Response::new(self.spawn_orderbook_stream("SubscribeOrderbook", peer, options, None)))
+    }
+
+    type ManageSubscriptionStream = Self::SubscribeOrderbookStream;
+
+    async fn manage_subscription(
+        &self,
+        request: Request<Streaming<SubscriptionChange>>,
+    ) -> Result<Response<Self::ManageSubscriptionStream>, Status> {
+        let peer = request.remote_addr();
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let quota = auth_interceptor::stream_quota(&request).acquire(0)?;
+        let mut requests = request.into_inner();
+        let (changes_tx, changes) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Ok(Some(change)) = requests.message().await {
+                if changes_tx.send(change).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let options = StreamOptions {
+            market_ids: HashSet::new(),
+            depth: STREAM_DEPTH,
+            update_interval: Duration::ZERO,
+            min_quantity: 0.0,
+            replay: Duration::ZERO,
+            sizes: SizeOptions::default(),
+            best_effort: false,
+            resume: HashMap::new(),
+            lag_policy: None,
+            permissions,
+            quota,
+        };
+
+        info!("New managed subscription from {:?}", peer);
+
+        Ok(Response::new(self.spawn_orderbook_stream("ManageSubscription", peer, options, Some(changes))))
+    }
+
+    type SubscribeDeltasStream =
+        Pin<Box<dyn Stream<Item = Result<DeltaMessage, Status>> + Send + 'static>>;
+
+    async fn subscribe_deltas(
+        &self,
+        request: Request<DeltaSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeDeltasStream>, Status> {
+        // Listeners send the shared encodings as they are; only in-process
+        // callers see them decoded
+        let stream = self.encoded_deltas(request)?;
+        let decoded = tokio_stream::StreamExt::map(stream, |item| item.and_then(|encoded| encoded.decode()));
+        Ok(Response::new(Box::pin(decoded) as Self::SubscribeDeltasStream))
+    }
+
+    type ReplayDeltasStream =
+        Pin<Box<dyn Stream<Item = Result<PbOrderbookDelta, Status>> + Send + 'static>>;
+
+    async fn replay_deltas(
+        &self,
+        request: Request<ReplayDeltasRequest>,
+    ) -> Result<Response<Self::ReplayDeltasStream>, Status> {
+        let wal = self.delta_wal.clone().ok_or_else(|| Status::unavailable("The delta write-ahead log is not enabled"))?;
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        permissions.check_markets(&[req.market_id])?;
+        let range = wal_range(req.from_sequence, req.to_sequence, req.from_timestamp_ms, req.to_timestamp_ms)?;
+
+        info!("Replaying deltas for market {}: {:?}", req.market_id, range);
+
+        let tick_size = self.orderbooks.get(&req.market_id).map_or_else(TickSize::default, |orderbook| orderbook.tick_size());
+        let quota = quota.acquire(1)?;
+
+        let subscriber = self.subscribers.register("ReplayDeltas", peer, [req.market_id]).with_quota(quota);
+        let tee = subscriber.tee();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::task::spawn_blocking(move || {
+            let _subscriber = subscriber;
+            let replayed = wal.replay(req.market_id, range, |update| {
+                tx.blocking_send(Ok(update_to_delta(&update, &update.deltas, tick_size))).is_ok()
+            });
+            if let Err(e) = replayed {
+                let _ = tx.blocking_send(Err(Status::internal(format!("Reading the write-ahead log failed: {}", e))));
+            }
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::ReplayDeltasStream))
+    }
+
+    type SubscribeBBOStream =
+        Pin<Box<dyn Stream<Item = Result<Bbo, Status>> + Send + 'static>>;
+
+    async fn subscribe_bbo(
+        &self,
+        request: Request<BboSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeBBOStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let market_ids: HashSet<u32> = request.into_inner().market_ids.into_iter().collect();
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
+        }
+        permissions.check_markets(&market_ids)?;
+
+        info!("New BBO subscription for markets: {:?}", market_ids);
+
+        let mut rx = self.fanout.subscribe(market_ids.iter().copied());
+        let orderbooks = self.orderbooks.clone();
+        let degradation = self.degradation.clone();
+        let suppress = self.stuffing_action(StuffingAction::Suppress);
+        let quota = quota.acquire(market_ids.len())?;
+
+        let subscriber = self.subscribers.register("SubscribeBBO", peer, market_ids.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            // Touch last sent per market
+            let mut last_sent: HashMap<u32, (Option<Level>, Option<Level>)> = HashMap::new();
+            let mut changed = |market_id: u32| {
+                let published = orderbooks.get(&market_id)?.snapshot();
+                let bbo = match suppress.as_ref().and_then(|stuffing| stuffing.without_flagged(market_id, &published)) {
+                    Some(unstuffed) => bbo(market_id, &unstuffed),
+                    None => bbo(market_id, &published),
+                };
+                let touch = (bbo.bid.clone(), bbo.ask.clone());
+                (last_sent.insert(market_id, touch.clone()) != Some(touch)).then_some(bbo)
+            };
+
+            let mut pending: Vec<Bbo> = market_ids.iter().filter_map(|id| changed(*id)).collect();
+            loop {
+                for bbo in pending.drain(..) {
+                    if tx.send(Ok(bbo)).await.is_err() {
+                        return;
+                    }
+                }
+
+                match rx.recv().await {
+                    Ok(update) => {
+                        degradation.record_fanout_lag(rx.queued());
+                        if market_ids.contains(&update.market_id) && update.deltas.iter().any(|d| d.at_touch) {
+                            pending.extend(changed(update.market_id));
+                        }
+                    }
+                    // Only the latest touch matters, so just recheck every market
+                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                        pending.extend(market_ids.iter().filter_map(|id| changed(*id)));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeBBOStream))
+    }
+
+    type SubscribeTradesStream =
+        Pin<Box<dyn Stream<Item = Result<PbTrade, Status>> + Send + 'static>>;
+
+    async fn subscribe_trades(
+        &self,
+        request: Request<TradesSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeTradesStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let fills = self
+            .fills
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("Trade ingestion is not enabled"))?;
+        let market_ids: HashSet<u32> = request.into_inner().market_ids.into_iter().collect();
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
+        }
+        permissions.check_markets(&market_ids)?;
+
+        info!("New trades subscription for markets: {:?}", market_ids);
+
+        let mut trades = fills.subscribe();
+        let quota = quota.acquire(self.quota_markets(&market_ids))?;
+
+        let subscriber = self.subscribers.register("SubscribeTrades", peer, market_ids.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            loop {
+                match trades.recv().await {
+                    Ok(trade) => {
+                        if (!market_ids.is_empty() && !market_ids.contains(&trade.market_id)) || !permissions.allows_market(trade.market_id) {
+                            continue;
+                        }
+                        let trade = PbTrade {
+                            market_id: trade.market_id,
+                            price: trade.price,
+                            size: trade.size,
+                            side: trade.side.clone(),
+                            oid: trade.oid,
+                            tid: trade.tid,
+                            timestamp: trade.timestamp_ms as i64,
+                        };
+                        if tx.send(Ok(trade)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Trades stream lagged, skipped {} trades", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeTradesStream))
+    }
+
+    async fn get_open_interest(
+        &self,
+        request: Request<OpenInterestRequest>,
+    ) -> Result<Response<OpenInterestResponse>, Status> {
+        let open_interest = self
+            .fills
+            .as_ref()
+            .map(|fills| fills.open_interest())
+            .ok_or_else(|| Status::unavailable("Trade ingestion is not enabled"))?;
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let market_ids = request.into_inner().market_ids;
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
+        }
+        permissions.check_markets(&market_ids)?;
+
+        let markets = open_interest
+            .all()
+            .into_iter()
+            .filter(|oi| market_ids.is_empty() || market_ids.contains(&oi.market_id))
+            .filter(|oi| permissions.allows_market(oi.market_id))
+            .filter_map(|oi| {
+                let orderbook = self.orderbooks.get(&oi.market_id)?;
+                let notional_price = crate::position_pnl::mark_price(&orderbook).unwrap_or(0.0);
+                Some(PbOpenInterest {
+                    market_id: oi.market_id,
+                    symbol: orderbook.symbol.clone(),
+                    open_interest: oi.size,
+                    notional_price,
+                    notional: oi.size * notional_price,
+                    updated_ms: oi.updated_ms as i64,
+                })
+            })
+            .collect();
+        Ok(Response::new(OpenInterestResponse { markets }))
+    }
+
+    async fn get_book_drift(
+        &self,
+        request: Request<BookDriftRequest>,
+    ) -> Result<Response<BookDriftResponse>, Status> {
+        let monitor = self
+            .book_drift
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("Book drift checks are not enabled"))?;
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let market_ids = request.into_inner().market_ids;
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
+        }
+        permissions.check_markets(&market_ids)?;
+
+        let markets = monitor
+            .all()
+            .iter()
+            .filter(|drift| market_ids.is_empty() || market_ids.contains(&drift.market_id))
+            .filter(|drift| permissions.allows_market(drift.market_id))
+            .map(|drift| self.pb_book_drift(drift))
+            .collect();
+        Ok(Response::new(BookDriftResponse { markets }))
+    }
+
+    async fn get_candles(
+        &self,
+        request: Request<CandlesRequest>,
+    ) -> Result<Response<CandlesResponse>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        permissions.check_markets(&[req.market_id])?;
+        let (interval, interval_ms, source) = candle_series(&req.interval, &req.source)?;
+        if !self.orderbooks.contains_key(&req.market_id) {
+            return Err(Status::not_found(format!("Market {} not found", req.market_id)));
+        }
+
+        let start_ms = req.start_time.max(0) as u64;
+        let candles = self
+            .candles
+            .candles(req.market_id, source, interval_ms, start_ms, req.limit as usize)
+            .iter()
+            .map(|candle| candle_to_pb(req.market_id, interval, source, candle))
+            .collect();
+        Ok(Response::new(CandlesResponse { candles }))
+    }
+
+    type SubscribeCandlesStream =
+        Pin<Box<dyn Stream<Item = Result<PbCandle, Status>> + Send + 'static>>;
+
+    async fn subscribe_candles(
+        &self,
+        request: Request<CandlesSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeCandlesStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        let (interval, interval_ms, source) = candle_series(&req.interval, &req.source)?;
+        let market_ids: HashSet<u32> = req.market_ids.into_iter().collect();
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
+        }
+        permissions.check_markets(&market_ids)?;
+
+        info!("New {} {} candle subscription for markets: {:?}", interval, source.as_str(), market_ids);
+
+        let mut updates = self.candles.subscribe();
+        let candles = self.candles.clone();
+        let all_markets: Vec<u32> = self.orderbooks.keys().into_iter().filter(|id| permissions.allows_market(*id)).collect();
+        let quota = quota.acquire(self.quota_markets(&market_ids))?;
+
+        let subscriber = self.subscribers.register("SubscribeCandles", peer, market_ids.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            let watched = if market_ids.is_empty() { all_markets } else { market_ids.iter().copied().collect() };
+            // The bar in progress of every market
+            let latest = || {
+                watched
+                    .iter()
+                    .filter_map(|market_id| {
+                        let candle = candles.latest(*market_id, source, interval_ms)?;
+                        Some(candle_to_pb(*market_id, interval, source, &candle))
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let mut pending = latest();
+            loop {
+                for candle in pending.drain(..) {
+                    if tx.send(Ok(candle)).await.is_err() {
+                        return;
+                    }
+                }
+
+                match updates.recv().await {
+                    Ok(update) => {
+                        if update.source == source
+                            && update.interval_ms == interval_ms
+                            && (market_ids.is_empty() || market_ids.contains(&update.market_id))
+                            && permissions.allows_market(update.market_id)
+                        {
+                            pending.push(candle_to_pb(update.market_id, interval, source, &update.candle));
+                        }
+                    }
+                    // Bars are cumulative, so the latest of each makes up for missed changes
+                    Err(broadcast::error::RecvError::Lagged(_)) => pending = latest(),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeCandlesStream))
+    }
+
+    type SubscribeL3Stream =
+        Pin<Box<dyn Stream<Item = Result<L3Message, Status>> + Send + 'static>>;
+
+    async fn subscribe_l3(
+        &self,
+        request: Request<L3SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeL3Stream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        let market_ids: HashSet<u32> = req.market_ids.into_iter().collect();
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
+        }
+        permissions.check_markets(&market_ids)?;
+        let lag_policy = lag_policy(&req.lag_policy)?;
+
+        info!("New L3 subscription for markets: {:?}", market_ids);
+
+        // Subscribe before taking snapshots so no update falls in between
+        let mut rx = self.fanout.subscribe(market_ids.iter().copied());
+        let mut resnapshot_rx = self.resnapshot_tx.subscribe();
+        let orderbooks = self.orderbooks.clone();
+        let market_tiers = self.market_tiers.clone();
+        let degradation = self.degradation.clone();
+        let tag = self.stuffing_action(StuffingAction::Tag);
+        let quota = quota.acquire(market_ids.len())?;
+
+        let subscriber = self.subscribers.register("SubscribeL3", peer, market_ids.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            // Sequence each market's client book is at
+            let mut barrier = SnapshotBarrier::default();
+            let send_snapshots = |market_ids: Vec<u32>, resync: bool, barrier: &mut SnapshotBarrier| {
+                market_ids
+                    .into_iter()
+                    .filter_map(|market_id| {
+                        let orderbook = orderbooks.get(&market_id)?;
+                        let mut snapshot = l3_snapshot(market_id, &orderbook);
+                        snapshot.resync = resync;
+                        if let Some(stuffing) = &tag {
+                            for order in snapshot.bids.iter_mut().chain(snapshot.asks.iter_mut()) {
+                                order.stuffing = stuffing.is_flagged(market_id, order.oid);
+                            }
+                        }
+                        barrier.record(market_id, snapshot.sequence);
+                        Some(L3Message { payload: Some(pb::l3_message::Payload::Snapshot(snapshot)) })
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            let mut pending = send_snapshots(market_ids.iter().copied().collect(), false, &mut barrier);
+            let mut snapshot_only = false;
+            let mut snapshot_ticker = tokio::time::interval(SNAPSHOT_ONLY_INTERVAL);
+            snapshot_ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                for message in pending.drain(..) {
+                    if tx.send(Ok(message)).await.is_err() {
+                        return;
+                    }
+                }
+
+                tokio::select! {
+                    result = rx.recv() => match result {
+                        Ok(_) if snapshot_only => degradation.record_fanout_lag(rx.queued()),
+                        // Cold markets are resnapshotted on the ticker instead
+                        Ok(update) if !market_tiers.is_hot(update.market_id) => degradation.record_fanout_lag(rx.queued()),
+                        Ok(update) => {
+                            degradation.record_fanout_lag(rx.queued());
+                            // A market that just turned hot missed the events
+                            // skipped while it was cold
+                            if barrier.has_gap(&update) {
+                                pending.extend(send_snapshots(vec![update.market_id], true, &mut barrier));
+                            }
+                            if let Some(deltas) = barrier.admit(&update) {
+                                let mut events = update_to_l3(&update, deltas);
+                                if let Some(stuffing) = &tag {
+                                    for event in events.events.iter_mut().filter(|event| event.action == "add") {
+                                        event.stuffing = stuffing.is_flagged(update.market_id, event.oid);
+                                    }
+                                }
+                                pending.push(L3Message { payload: Some(pb::l3_message::Payload::Update(events)) });
+                            }
+                        }
+                        // Missed events can't be patched; replace every book
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => match lag_policy {
+                            None => {
+                                warn!("L3 stream lagged by {} updates, resending snapshots", skipped);
+                                pending = send_snapshots(market_ids.iter().copied().collect(), true, &mut barrier);
+                            }
+                            Some(LagPolicy::Disconnect) => {
+                                warn!("L3 stream lagged by {} updates, disconnecting", skipped);
+                                let _ = tx.send(Err(fell_behind(skipped))).await;
+                                return;
+                            }
+                            Some(policy) => {
+                                warn!("L3 stream lagged by {} updates ({})", skipped, policy.as_str());
+                                let notice = lag_notice(skipped, &market_ids, policy);
+                                pending.push(L3Message { payload: Some(pb::l3_message::Payload::Lag(notice)) });
+                                snapshot_only |= policy == LagPolicy::SnapshotOnly;
+                            }
+                        },
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = snapshot_ticker.tick() => {
+                        let moved = market_ids
+                            .iter()
+                            .copied()
+                            .filter(|id| snapshot_only || !market_tiers.is_hot(*id))
+                            .filter(|id| {
+                                let published = orderbooks.get(id).map(|orderbook| orderbook.snapshot().sequence);
+                                barrier.sequence(*id).zip(published).is_none_or(|(sent, published)| sent < published)
+                            })
+                            .collect();
+                        pending = send_snapshots(moved, true, &mut barrier);
+                    }
+                    result = resnapshot_rx.recv() => match result {
+                        Ok(requested) => {
+                            let requested = requested.iter().copied().filter(|id| market_ids.contains(id)).collect();
+                            pending = send_snapshots(requested, true, &mut barrier);
+                        }
+                        Err(broadcast::error::RecvError::Lagged(_)) => {
+                            pending = send_snapshots(market_ids.iter().copied().collect(), true, &mut barrier);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                }
+            }
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeL3Stream))
+    }
+
+    type SubscribeByTemplateStream = Self::SubscribeOrderbookStream;
+
+    async fn subscribe_by_template(
+        &self,
+        request: Request<TemplateSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeByTemplateStream>, Status> {
+        let peer = request.remote_addr();
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let quota = auth_interceptor::stream_quota(&request);
+        let name = request.into_inner().name;
+        let template = self
+            .templates
+            .get(&name)
+            .ok_or_else(|| Status::not_found(format!("Subscription template {} not found", name)))?;
+
+        let market_ids: HashSet<u32> = self.resolve_template_markets(&name, template).await?.into_iter().collect();
+        permissions.check_markets(&market_ids)?;
+        let options = StreamOptions {
+            quota: quota.acquire(market_ids.len())?,
+            market_ids,
+            depth: template.depth as usize,
+            update_interval: Duration::from_millis(template.update_interval_ms as u64),
+            min_quantity: template.min_quantity,
+            replay: Duration::from_millis(template.replay_ms as u64).min(self.replay_cache.window()),
+            sizes: SizeOptions {
+                normalized: template.normalized_sizes,
+                notional: template.notional,
+            },
+            best_effort: false,
+            resume: HashMap::new(),
+            lag_policy: None,
+            permissions,
+        };
+
+        info!("New subscription from template {} for markets: {:?}", name, options.market_ids);
+
+        Ok(Response::new(self.spawn_orderbook_stream("SubscribeByTemplate", peer, options, None)))
+    }
+
+    async fn list_templates(
+        &self,
+        _request: Request<GetMarketsRequest>,
+    ) -> Result<Response<TemplatesResponse>, Status> {
+        let mut templates = Vec::with_capacity(self.templates.len());
+        for (name, template) in self.templates.iter() {
+            templates.push(PbSubscriptionTemplate {
+                name: name.clone(),
+                description: template.description.clone(),
+                market_ids: self.resolve_template_markets(name, template).await.unwrap_or_default(),
+                depth: template.depth,
+                update_interval_ms: template.update_interval_ms,
+                min_quantity: template.min_quantity,
+                replay_ms: template.replay_ms,
+                normalized_sizes: template.normalized_sizes,
+                notional: template.notional,
+            });
+        }
+
+        Ok(Response::new(TemplatesResponse { templates }))
+    }
+
+    async fn get_orderbook(
+        &self,
+        request: Request<GetOrderbookRequest>,
+    ) -> Result<Response<PbOrderbookSnapshot>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        let depth = req.depth as usize;
+        let market_id = if req.symbol.is_empty() { req.market_id } else { self.resolve_symbol(&req.symbol).await? };
+        permissions.check_markets(&[market_id])?;
+
+        match self.orderbooks.get(&market_id) {
             Some(orderbook) => {
-                let (bids, asks) = orderbook.get_snapshot(depth);
+                let depth = depth.min(self.market_tiers.tier(market_id).max_depth());
+                let sizes = SizeOptions { normalized: req.normalized_sizes, notional: req.notional };
+                let version = (
+                    orderbook.snapshot().sequence,
+                    self.size_normalizer.sz_decimals(market_id).filter(|_| sizes.normalized),
+                    crate::position_pnl::mark_price(&orderbook).filter(|_| sizes.notional).map(f64::to_bits),
+                );
+                let key = (market_id, depth, sizes.normalized, sizes.notional);
+                let snapshot = self.orderbook_cache.get_or_build(key, version, || {
+                    let mut snapshot = build_snapshot(market_id, &orderbook, depth, now_micros());
+                    annotate_sizes(&mut snapshot, &orderbook, &self.size_normalizer, sizes);
+                    snapshot
+                });
+                Ok(Response::new(snapshot))
+            }
+            None => Err(Status::not_found(format!(
+                "Market {} not found",
+                market_id
+            ))),
+        }
+    }
+
+    async fn get_orderbook_at(
+        &self,
+        request: Request<GetOrderbookAtRequest>,
+    ) -> Result<Response<PbOrderbookSnapshot>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        permissions.check_markets(&[req.market_id])?;
+        let orderbook = self
+            .orderbooks
+            .get(&req.market_id)
+            .ok_or_else(|| Status::not_found(format!("Market {} not found", req.market_id)))?;
+        if req.timestamp_ms <= 0 {
+            return Err(Status::invalid_argument("timestamp_ms must be positive"));
+        }
+        if req.timestamp_ms > now_micros() / 1000 {
+            return Err(Status::invalid_argument("timestamp_ms is in the future"));
+        }
+        let max_depth = self.market_tiers.tier(req.market_id).max_depth();
+        let depth = if req.depth == 0 { max_depth } else { (req.depth as usize).min(max_depth) };
+        // Through the end of the requested millisecond
+        let timestamp_ns = req.timestamp_ms as u64 * 1_000_000 + 999_999;
+
+        // Recent moments are still in the book's event log
+        let recent = {
+            let log = orderbook.event_log().lock();
+            log.first_timestamp_ns()
+                .filter(|first| *first <= timestamp_ns)
+                .and_then(|_| log.state_at_time(timestamp_ns))
+        };
+        let state = match (recent, &self.delta_wal) {
+            (Some(state), _) => Some(state),
+            (None, Some(wal)) => {
+                let (wal, market_id) = (wal.clone(), req.market_id);
+                tokio::task::spawn_blocking(move || wal.state_at(market_id, timestamp_ns))
+                    .await
+                    .map_err(|e| Status::internal(e.to_string()))?
+                    .map_err(|e| Status::internal(format!("Reading the write-ahead log failed: {}", e)))?
+            }
+            (None, None) => None,
+        };
+        let state = state.ok_or_else(|| {
+            Status::not_found(format!("No history for market {} at {}", req.market_id, req.timestamp_ms))
+        })?;
+
+        let (bids, asks) = state.levels(depth);
+        let mut snapshot = levels_snapshot(
+            req.market_id,
+            &orderbook.symbol,
+            bids,
+            asks,
+            req.timestamp_ms * 1000,
+            state.sequence,
+        );
+        annotate_ticks(&mut snapshot, orderbook.tick_size());
+        Ok(Response::new(snapshot))
+    }
+
+    async fn get_aggregated_depth(
+        &self,
+        request: Request<AggregatedDepthRequest>,
+    ) -> Result<Response<PbOrderbookSnapshot>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        permissions.check_markets(&[req.market_id])?;
+        if !(req.bucket_size.is_finite() && req.bucket_size > 0.0) {
+            return Err(Status::invalid_argument("bucket_size must be positive"));
+        }
+        let orderbook = self
+            .orderbooks
+            .get(&req.market_id)
+            .ok_or_else(|| Status::not_found(format!("Market {} not found", req.market_id)))?;
+
+        let max_depth = self.market_tiers.tier(req.market_id).max_depth();
+        let depth = if req.depth == 0 { max_depth } else { (req.depth as usize).min(max_depth) };
+        let published = orderbook.snapshot();
+        let aggregate = |levels: &[(f64, f64)], is_bid| {
+            let mut buckets = fast_orderbook::aggregate_levels(levels, req.bucket_size, is_bid);
+            buckets.truncate(depth);
+            buckets
+        };
+
+        let mut snapshot = levels_snapshot(
+            req.market_id,
+            &orderbook.symbol,
+            aggregate(&published.bids, true),
+            aggregate(&published.asks, false),
+            now_micros(),
+            published.sequence,
+        );
+        // Derived from the raw levels, not the buckets
+        snapshot.microprice = published.microprice;
+        snapshot.depth_weighted_mid = published.depth_weighted_mid;
+        let sizes = SizeOptions { normalized: req.normalized_sizes, notional: req.notional };
+        annotate_sizes(&mut snapshot, &orderbook, &self.size_normalizer, sizes);
+        Ok(Response::new(snapshot))
+    }
+
+    async fn estimate_impact(
+        &self,
+        request: Request<ImpactRequest>,
+    ) -> Result<Response<ImpactResponse>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        permissions.check_markets(&[req.market_id])?;
+        let is_buy = match req.side.as_str() {
+            "B" => true,
+            "A" => false,
+            other => return Err(Status::invalid_argument(format!("side must be B or A, got {:?}", other))),
+        };
+        let target = match req.amount {
+            Some(impact_request::Amount::Size(size)) if size.is_finite() && size > 0.0 => FillTarget::Size(size),
+            Some(impact_request::Amount::Notional(notional)) if notional.is_finite() && notional > 0.0 => {
+                FillTarget::Notional(notional)
+            }
+            _ => return Err(Status::invalid_argument("size or notional must be positive")),
+        };
+        let orderbook = self
+            .orderbooks
+            .get(&req.market_id)
+            .ok_or_else(|| Status::not_found(format!("Market {} not found", req.market_id)))?;
+
+        // The published book is consistent with its sequence; the whole of
+        // the side is walked, whatever the market's depth tier
+        let published = orderbook.snapshot();
+        let levels = if is_buy { &published.asks } else { &published.bids };
+        let walk = fast_orderbook::walk_levels(levels, target);
+        let mid = match (published.bids.first(), published.asks.first()) {
+            (Some((bid, _)), Some((ask, _))) => Some((bid + ask) / 2.0),
+            _ => None,
+        };
+
+        Ok(Response::new(ImpactResponse {
+            market_id: req.market_id,
+            sequence: published.sequence,
+            avg_price: walk.avg_price(),
+            slippage_bps: levels.first().and_then(|(best, _)| walk.slippage_bps(*best, is_buy)),
+            mid_slippage_bps: mid.and_then(|mid| walk.slippage_bps(mid, is_buy)),
+            levels_consumed: walk.levels_consumed as u32,
+            worst_price: walk.worst_price,
+            filled_size: walk.filled_size,
+            filled_notional: walk.filled_notional,
+            complete: walk.complete,
+        }))
+    }
+
+    async fn get_markets(
+        &self,
+        _request: Request<GetMarketsRequest>,
+    ) -> Result<Response<GetMarketsResponse>, Status> {
+        // Open interest changes with every fill, so it is filled in after the cache
+        let with_open_interest = |mut markets: Vec<Market>| {
+            if let Some(fills) = &self.fills {
+                for market in &mut markets {
+                    market.open_interest = fills.open_interest().get(market.id).map_or(0.0, |oi| oi.size);
+                }
+            }
+            markets
+        };
+        let generation = self.market_registry.generation();
+        if let Some(markets) = self.markets_cache.get(&(), &generation) {
+            return Ok(Response::new(GetMarketsResponse { markets: with_open_interest(markets) }));
+        }
+        let keys = self.market_registry.get_market_keys().await;
+        let markets = self
+            .orderbooks
+            .all()
+            .iter()
+            .map(|(market_id, orderbook)| {
+                let key = keys.get(market_id);
+                Market {
+                    id: *market_id,
+                    symbol: orderbook.symbol.clone(),
+                    source: key.map(|k| k.source().to_string()).unwrap_or_default(),
+                    native_key: key.map(|k| k.native_key()).unwrap_or_default(),
+                    open_interest: 0.0,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.markets_cache.insert((), generation, markets.clone());
+
+        Ok(Response::new(GetMarketsResponse { markets: with_open_interest(markets) }))
+    }
+
+    async fn search_symbols(
+        &self,
+        request: Request<SearchSymbolsRequest>,
+    ) -> Result<Response<SearchSymbolsResponse>, Status> {
+        let req = request.into_inner();
+        let limit = match req.limit {
+            0 => 20,
+            limit => (limit as usize).min(200),
+        };
+        
+        let mut ranked: Vec<_> = self
+            .market_registry
+            .search_markets(&req.query)
+            .await
+            .into_iter()
+            .map(|(kind, info)| {
+                let liquidity = self
+                    .orderbooks
+                    .get(&info.id)
+                    .map(|orderbook| {
+                        let published = orderbook.snapshot();
+                        fast_orderbook::notional_within(&published.bids, &published.asks, 100.0)
+                    })
+                    .unwrap_or(0.0);
+                (kind, liquidity, info)
+            })
+            .collect();
+        // Stable, so equally liquid markets stay in symbol order
+        ranked.sort_by(|(a, a_liquidity, _), (b, b_liquidity, _)| a.cmp(b).then(b_liquidity.total_cmp(a_liquidity)));
+        
+        let matches = ranked
+            .into_iter()
+            .take(limit)
+            .map(|(kind, liquidity, info)| PbSymbolMatch {
+                market_id: info.id,
+                symbol: info.symbol.symbol().to_string(),
+                base: info.symbol.base().to_string(),
+                quote: info.symbol.quote().to_string(),
+                exchange: info.symbol.exchange().to_string(),
+                match_type: kind.as_str().to_string(),
+                liquidity,
+            })
+            .collect();
+        Ok(Response::new(SearchSymbolsResponse { matches }))
+    }
+
+    async fn get_proto_descriptors(
+        &self,
+        _request: Request<GetMarketsRequest>,
+    ) -> Result<Response<ProtoDescriptorsResponse>, Status> {
+        Ok(Response::new(ProtoDescriptorsResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            files: proto_descriptors::PROTO_FILES
+                .iter()
+                .map(|file| PbProtoFile {
+                    path: file.path.to_string(),
+                    version: file.version.to_string(),
+                    content: file.content.to_string(),
+                })
+                .collect(),
+            file_descriptor_set: proto_descriptors::FILE_DESCRIPTOR_SET.to_vec(),
+        }))
+    }
+
+    async fn get_conformance_vectors(
+        &self,
+        _request: Request<GetMarketsRequest>,
+    ) -> Result<Response<ConformanceVectorsResponse>, Status> {
+        Ok(Response::new(ConformanceVectorsResponse {
+            format_version: conformance_vectors::FORMAT_VERSION,
+            checksum_algorithm: conformance_vectors::CHECKSUM_ALGORITHM.to_string(),
+            vectors: conformance_vectors::generate().into_iter().map(vector_to_pb).collect(),
+        }))
+    }
+
+    async fn get_stop_orders(
+        &self,
+        request: Request<StopOrdersRequest>,
+    ) -> Result<Response<StopOrdersResponse>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::StopOrders)?;
+        let mut req = request.into_inner();
+        let filter = match req.filter.take() {
+            Some(pb::stop_orders_request::Filter::Symbol(symbol)) => {
+                Some(pb::stop_orders_request::Filter::MarketId(self.resolve_symbol(&symbol).await?))
+            }
+            filter => filter,
+        };
+        if let Some(pb::stop_orders_request::Filter::MarketId(market_id)) = &filter {
+            permissions.check_markets(&[*market_id])?;
+        }
+        
+        // Get base list of orders based on primary filter
+        let mut orders = match filter {
+            Some(pb::stop_orders_request::Filter::MarketId(market_id)) => {
+                // Scan only the trigger prices within the distance limit
+                let mid = self
+                    .orderbooks
+                    .get(&market_id)
+                    .and_then(|orderbook| orderbook.get_best_bid_ask())
+                    .map(|(best_bid, best_ask)| (best_bid + best_ask) / 2.0);
+                let orders = match mid {
+                    Some(mid) if req.max_distance_from_mid_bps > 0.0 => {
+                        let band = mid * req.max_distance_from_mid_bps / 10000.0;
+                        self.stop_order_manager.get_stop_orders_in_range(market_id, mid - band, mid + band)
+                    }
+                    _ => self.stop_order_manager.get_stop_orders_by_market(market_id),
+                };
+                orders.into_iter().map(|order| (market_id, order)).collect()
+            }
+            Some(pb::stop_orders_request::Filter::User(user)) => {
+                self.stop_order_manager.get_stop_orders_by_user(&user)
+            }
+            _ => {
+                self.stop_order_manager.live_orders()
+            }
+        };
+
+        // Apply additional filters
+        orders.retain(|(market_id, _)| permissions.allows_market(*market_id));
+        if req.min_notional > 0.0 || req.max_notional > 0.0 {
+            orders.retain(|(_, order)| {
+                let notional = order.price * order.size;
+                (req.min_notional == 0.0 || notional >= req.min_notional) &&
+                (req.max_notional == 0.0 || notional <= req.max_notional)
+            });
+        }
+
+        if !req.side.is_empty() {
+            orders.retain(|(_, order)| order.side == req.side);
+        }
+
+        // If ranking is requested, collect market data and rank orders
+        if req.rank_by_risk {
+            // Collect current mid prices and orderbooks
+            let mut mid_prices = HashMap::new();
+            let mut orderbooks = HashMap::new();
+            
+            for (market_id, _) in &orders {
+                if mid_prices.contains_key(market_id) {
+                    continue;
+                }
+                if let Some(orderbook) = self.orderbooks.get(market_id) {
+                    if let Some((best_bid, best_ask)) = orderbook.get_best_bid_ask() {
+                        let mid = (best_bid + best_ask) / 2.0;
+                        mid_prices.insert(*market_id, mid);
+                        
+                        // Get orderbook snapshot for slippage calculation
+                        let (bids, asks) = orderbook.get_snapshot(50);
+                        orderbooks.insert(*market_id, (bids, asks));
+                    }
+                }
+            }
+            
+            // Use default weights if not specified
+            let distance_weight = if req.distance_weight > 0.0 { req.distance_weight } else { 0.6 };
+            let slippage_weight = if req.slippage_weight > 0.0 { req.slippage_weight } else { 0.4 };
+            
+            // Rank the orders
+            let ranked_orders = self.stop_order_manager.rank_stop_orders(
+                orders,
+                &mid_prices,
+                &orderbooks,
+                distance_weight,
+                slippage_weight,
+            );
+            
+            // Convert to protobuf format with ranking information
+            let pb_orders: Vec<PbRankedStopOrder> = ranked_orders
+                .into_iter()
+                .filter_map(|ranked| {
+                    let market_id = ranked.market_id;
+                    let current_mid = mid_prices.get(&market_id).copied().unwrap_or(0.0);
+                    
+                    // Apply distance filter if specified
+                    if req.max_distance_from_mid_bps > 0.0 && ranked.distance_to_trigger_bps > req.max_distance_from_mid_bps {
+                        return None;
+                    }
+                    
+                    // Determine risk level
+                    let risk_level = if ranked.risk_score >= 80.0 {
+                        "HIGH".to_string()
+                    } else if ranked.risk_score >= 50.0 {
+                        "MEDIUM".to_string()
+                    } else {
+                        "LOW".to_string()
+                    };
+                    
+                    Some(PbRankedStopOrder {
+                        order: Some(PbStopOrder {
+                            id: ranked.order.id,
+                            user: ranked.order.user,
+                            market_id,
+                            coin: ranked.order.coin,
+                            side: ranked.order.side,
+                            price: ranked.order.price,
+                            size: ranked.order.size,
+                            trigger_condition: ranked.order.trigger_condition,
+                            timestamp: ranked.order.timestamp,
+                            notional: ranked.notional_value,
+                            distance_from_mid_bps: ranked.distance_to_trigger_bps,
+                            current_mid_price: current_mid,
+                        }),
+                        distance_to_trigger_bps: ranked.distance_to_trigger_bps,
+                        expected_slippage_bps: ranked.expected_slippage_bps,
+                        risk_score: ranked.risk_score,
+                        risk_level,
+                    })
+                })
+                .collect();
                 
-                let snapshot = PbOrderbookSnapshot {
-                    market_id: req.market_id,
-                    symbol: orderbook.symbol.clone(),
-                    timestamp: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_micros() as i64,
-                    sequence: orderbook.sequence.load(std::sync::atomic::Ordering::Relaxed),
-                    bids: bids
-                        .into_iter()
-                        .map(|(price, quantity)| Level {
-                            price,
-                            quantity,
+            Ok(Response::new(StopOrdersResponse { orders: pb_orders }))
+        } else {
+            // Non-ranked response - convert to simple format
+            let pb_orders: Vec<PbRankedStopOrder> = orders
+                .into_iter()
+                .filter_map(|(market_id, order)| {
+                    let notional = order.price * order.size;
+                    
+                    // Get current mid price for distance calculation
+                    let (current_mid, distance_bps) = if let Some(orderbook) = self.orderbooks.get(&market_id) {
+                        if let Some((best_bid, best_ask)) = orderbook.get_best_bid_ask() {
+                            let mid = (best_bid + best_ask) / 2.0;
+                            let distance = ((order.price - mid).abs() / mid) * 10000.0;
+                            (mid, distance)
+                        } else {
+                            (0.0, 0.0)
+                        }
+                    } else {
+                        (0.0, 0.0)
+                    };
+
+                    // Apply distance filter if specified
+                    if req.max_distance_from_mid_bps > 0.0 && distance_bps > req.max_distance_from_mid_bps {
+                        return None;
+                    }
+
+                    Some(PbRankedStopOrder {
+                        order: Some(PbStopOrder {
+                            id: order.id,
+                            user: order.user,
+                            market_id,
+                            coin: order.coin,
+                            side: order.side,
+                            price: order.price,
+                            size: order.size,
+                            trigger_condition: order.trigger_condition,
+                            timestamp: order.timestamp,
+                            notional,
+                            distance_from_mid_bps: distance_bps,
+                            current_mid_price: current_mid,
+                        }),
+                        distance_to_trigger_bps: distance_bps,
+                        expected_slippage_bps: 0.0,
+                        risk_score: 0.0,
+                        risk_level: "UNKNOWN".to_string(),
+                    })
+                })
+                .collect();
+
+            Ok(Response::new(StopOrdersResponse { orders: pb_orders }))
+        }
+    }
+
+    async fn get_stop_order_heatmap(
+        &self,
+        request: Request<StopOrderHeatmapRequest>,
+    ) -> Result<Response<StopOrderHeatmap>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::StopOrders)?;
+        let req = request.into_inner();
+        permissions.check_markets(&[req.market_id])?;
+        let orderbook = self
+            .orderbooks
+            .get(&req.market_id)
+            .ok_or_else(|| Status::not_found(format!("Market {} not found", req.market_id)))?;
+        let (best_bid, best_ask) = orderbook
+            .get_best_bid_ask()
+            .ok_or_else(|| Status::failed_precondition(format!("Market {} has no mid price", req.market_id)))?;
+        let mid_price = (best_bid + best_ask) / 2.0;
+        let bucket_bps = if req.bucket_bps > 0.0 { req.bucket_bps } else { 10.0 };
+
+        let price_at = |bps: f64| mid_price * (1.0 + bps / 10000.0);
+        let buckets = self
+            .stop_order_manager
+            .heatmap(req.market_id, mid_price, bucket_bps, req.max_distance_bps)
+            .into_iter()
+            .map(|bucket| {
+                let from_bps = bucket.index as f64 * bucket_bps;
+                StopOrderHeatmapBucket {
+                    from_bps,
+                    to_bps: from_bps + bucket_bps,
+                    from_price: price_at(from_bps),
+                    to_price: price_at(from_bps + bucket_bps),
+                    buy_notional: bucket.buy_notional,
+                    sell_notional: bucket.sell_notional,
+                    buy_orders: bucket.buy_orders,
+                    sell_orders: bucket.sell_orders,
+                }
+            })
+            .collect();
+
+        Ok(Response::new(StopOrderHeatmap { market_id: req.market_id, mid_price, bucket_bps, buckets }))
+    }
+
+    async fn simulate_trigger_cascade(
+        &self,
+        request: Request<TriggerCascadeRequest>,
+    ) -> Result<Response<TriggerCascadeResponse>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::StopOrders)?;
+        let req = request.into_inner();
+        permissions.check_markets(&[req.market_id])?;
+        if !req.move_bps.is_finite() || req.move_bps <= -10000.0 {
+            return Err(Status::invalid_argument("move_bps must be finite and above -10000"));
+        }
+        let orderbook = self
+            .orderbooks
+            .get(&req.market_id)
+            .ok_or_else(|| Status::not_found(format!("Market {} not found", req.market_id)))?;
+        let max_rounds = if req.max_rounds > 0 { req.max_rounds as usize } else { 20 };
+
+        let published = orderbook.snapshot();
+        // Fills can't carry the price past the deepest levels, so stops
+        // beyond them and the shock can never fire
+        let shock = published.bids.first().zip(published.asks.first()).map_or(0.0, |((bid, _), (ask, _))| (bid + ask) / 2.0)
+            * (1.0 + req.move_bps / 10000.0);
+        let low = published.bids.last().map_or(shock, |(price, _)| price.min(shock));
+        let high = published.asks.last().map_or(shock, |(price, _)| price.max(shock));
+        let orders = self.stop_order_manager.get_stop_orders_in_range(req.market_id, low, high);
+        let cascade = trigger_cascade::simulate(&orders, &published.bids, &published.asks, req.move_bps, max_rounds)
+            .ok_or_else(|| Status::failed_precondition(format!("Market {} has no mid price", req.market_id)))?;
+
+        Ok(Response::new(TriggerCascadeResponse {
+            market_id: req.market_id,
+            sequence: published.sequence,
+            mid_price: cascade.mid_price,
+            shock_price: cascade.shock_price,
+            rounds: cascade
+                .rounds
+                .iter()
+                .map(|round| TriggerCascadeRound {
+                    triggered_orders: round.triggered_orders as u32,
+                    buy_size: round.buy_size,
+                    sell_size: round.sell_size,
+                    filled_notional: round.filled_notional,
+                    unfilled_size: round.unfilled_size,
+                    price: round.price,
+                })
+                .collect(),
+            final_price: cascade.final_price,
+            low_price: cascade.low_price,
+            high_price: cascade.high_price,
+            total_move_bps: (cascade.final_price - cascade.mid_price) / cascade.mid_price * 10000.0,
+            triggered_orders: cascade.triggered_orders as u32,
+            triggered_notional: cascade.triggered_notional,
+            exhausted: cascade.exhausted,
+        }))
+    }
+
+    type SubscribeStopOrdersStream =
+        Pin<Box<dyn Stream<Item = Result<PbStopOrderEvent, Status>> + Send>>;
+
+    async fn subscribe_stop_orders(
+        &self,
+        request: Request<StopOrdersSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStopOrdersStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::StopOrders)?;
+        let req = request.into_inner();
+        let mut market_ids: HashSet<u32> = req.market_ids.into_iter().collect();
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
+        }
+        market_ids.extend(self.resolve_symbols(&req.symbols).await?);
+        permissions.check_markets(&market_ids)?;
+        let quota = quota.acquire(self.quota_markets(&market_ids))?;
+
+        let subscriber = self.subscribers.register("SubscribeStopOrders", peer, market_ids.iter().copied()).with_quota(quota);
+        let (user, min_notional) = (req.user, req.min_notional);
+        let matches = move |market_id: u32, order: &StopOrder| {
+            (market_ids.is_empty() || market_ids.contains(&market_id))
+                && permissions.allows_market(market_id)
+                && (user.is_empty() || order.user == user)
+                && order.price * order.size >= min_notional
+        };
+
+        info!("New stop orders subscription (snapshot: {})", req.snapshot);
+
+        // Subscribed before the snapshot is taken so nothing falls between them
+        let mut events = self.stop_order_manager.subscribe();
+        let snapshot: Vec<(u32, StopOrder)> = if req.snapshot {
+            self.stop_order_manager.live_orders().into_iter().filter(|(market_id, order)| matches(*market_id, order)).collect()
+        } else {
+            Vec::new()
+        };
+        let tee = subscriber.tee();
+        let orderbooks = self.orderbooks.clone();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            // Adds already sent in the snapshot aren't repeated
+            let sent: HashSet<u64> = snapshot.iter().map(|(_, order)| order.id).collect();
+            for (market_id, order) in snapshot {
+                let event = PbStopOrderEvent {
+                    kind: StopOrderEventKind::Added.as_str().to_string(),
+                    timestamp: order.timestamp,
+                    order: Some(stop_order_to_pb(&orderbooks, market_id, order)),
+                };
+                if tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        if !matches(event.market_id, &event.order)
+                            || (event.kind == StopOrderEventKind::Added && sent.contains(&event.order.id))
+                        {
+                            continue;
+                        }
+                        let event = PbStopOrderEvent {
+                            kind: event.kind.as_str().to_string(),
+                            order: Some(stop_order_to_pb(&orderbooks, event.market_id, event.order.clone())),
+                            timestamp: event.timestamp_ms,
+                        };
+                        if tx.send(Ok(event)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Stop orders stream lagged, skipped {} events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeStopOrdersStream))
+    }
+
+    type SubscribeMarkPricesStream =
+        Pin<Box<dyn Stream<Item = Result<MarkPriceUpdate, Status>> + Send>>;
+
+    async fn subscribe_mark_prices(
+        &self,
+        request: Request<MarkPriceSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeMarkPricesStream>, Status> {
+        let mark_prices = self.mark_prices.clone().ok_or_else(|| Status::unavailable("Mark prices are not being computed"))?;
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let req = request.into_inner();
+        let requested_markets: HashSet<u32> = req.market_ids.into_iter().collect();
+        permissions.check_markets(&requested_markets)?;
+        let update_interval = Duration::from_millis(if req.update_interval_ms == 0 { 1000 } else { req.update_interval_ms as u64 });
+
+        info!("New mark price subscription for markets: {:?} every {:?}", requested_markets, update_interval);
+
+        let mut mark_price_rx = mark_prices.subscribe();
+        let quota = quota.acquire(self.quota_markets(&requested_markets))?;
+
+        let subscriber = self.subscribers.register("SubscribeMarkPrices", peer, requested_markets.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            let mut last_sent: HashMap<u32, Instant> = HashMap::new();
+            loop {
+                let event = match mark_price_rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if (!requested_markets.is_empty() && !requested_markets.contains(&event.market_id)) || !permissions.allows_market(event.market_id) {
+                    continue;
+                }
+                // Slower than the service's cadence: the first price of each interval
+                if last_sent.get(&event.market_id).is_some_and(|sent| sent.elapsed() < update_interval) {
+                    continue;
+                }
+                last_sent.insert(event.market_id, Instant::now());
+
+                let update = MarkPriceUpdate {
+                    market_id: event.market_id,
+                    symbol: event.symbol.clone(),
+                    timestamp: event.timestamp_ms,
+                    hl_mark_price: Some(hl_mark_price_to_pb(&event)),
+                    calculation_version: CALCULATION_VERSION,
+                };
+                if tx.send(Ok(update)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeMarkPricesStream))
+    }
+
+    async fn get_mark_price(
+        &self,
+        request: Request<GetMarkPriceRequest>,
+    ) -> Result<Response<MarkPriceResponse>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let market_id = request.into_inner().market_id;
+        permissions.check_markets(&[market_id])?;
+        let mark_prices = self.mark_prices.as_ref().ok_or_else(|| Status::unavailable("Mark prices are not being computed"))?;
+        if !self.orderbooks.contains_key(&market_id) {
+            return Err(Status::not_found(format!("Market {} not found", market_id)));
+        }
+        let event = mark_prices
+            .latest(market_id)
+            .ok_or_else(|| Status::unavailable(format!("No mark price yet for market {}; its book is one-sided", market_id)))?;
+
+        Ok(Response::new(MarkPriceResponse {
+            market_id,
+            symbol: event.symbol.clone(),
+            timestamp: event.timestamp_ms,
+            hl_mark_price: Some(hl_mark_price_to_pb(&event)),
+            from_cache: true,
+            cache_age_ms: (chrono::Utc::now().timestamp_millis() - event.timestamp_ms).max(0),
+        }))
+    }
+
+    async fn set_market_tier(
+        &self,
+        request: Request<SetMarketTierRequest>,
+    ) -> Result<Response<PbMarketTier>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let req = request.into_inner();
+        
+        let orderbook = self.orderbooks.get(&req.market_id).ok_or_else(|| {
+            Status::not_found(format!("Market {} not found", req.market_id))
+        })?;
+        let tier: MarketTier = req
+            .tier
+            .parse()
+            .map_err(|e: anyhow::Error| Status::invalid_argument(e.to_string()))?;
+        
+        let previous = self.market_tiers.set_tier(req.market_id, tier);
+        self.market_tiers.apply_retention(&orderbook);
+        info!(
+            "Market {} ({}) tier changed: {} -> {}",
+            req.market_id,
+            orderbook.symbol,
+            previous.as_str(),
+            tier.as_str()
+        );
+        
+        Ok(Response::new(PbMarketTier {
+            market_id: req.market_id,
+            symbol: orderbook.symbol.clone(),
+            tier: tier.as_str().to_string(),
+        }))
+    }
+
+    async fn get_market_tiers(
+        &self,
+        _request: Request<GetMarketsRequest>,
+    ) -> Result<Response<MarketTiersResponse>, Status> {
+        let mut tiers: Vec<PbMarketTier> = self
+            .orderbooks
+            .all()
+            .iter()
+            .map(|(market_id, orderbook)| PbMarketTier {
+                market_id: *market_id,
+                symbol: orderbook.symbol.clone(),
+                tier: self.market_tiers.tier(*market_id).as_str().to_string(),
+            })
+            .collect();
+        tiers.sort_by_key(|t| t.market_id);
+        
+        Ok(Response::new(MarketTiersResponse {
+            default_tier: self.market_tiers.default_tier().as_str().to_string(),
+            tiers,
+        }))
+    }
+
+    type SubscribeAnalyticsStream =
+        Pin<Box<dyn Stream<Item = Result<AnalyticsUpdate, Status>> + Send>>;
+
+    async fn subscribe_analytics(
+        &self,
+        request: Request<AnalyticsSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeAnalyticsStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let requested_markets: std::collections::HashSet<u32> =
+            request.into_inner().market_ids.into_iter().collect();
+        permissions.check_markets(&requested_markets)?;
+
+        info!("New analytics subscription for markets: {:?}", requested_markets);
+
+        let mut ofi_rx = self.ofi_engine.subscribe();
+        let orderbooks = self.orderbooks.clone();
+        let quota = quota.acquire(self.quota_markets(&requested_markets))?;
+
+        let subscriber = self.subscribers.register("SubscribeAnalytics", peer, requested_markets.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            loop {
+                let snapshot = match ofi_rx.recv().await {
+                    Ok(snapshot) => snapshot,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                
+                if (!requested_markets.is_empty() && !requested_markets.contains(&snapshot.market_id)) || !permissions.allows_market(snapshot.market_id) {
+                    continue;
+                }
+                
+                let orderbook = orderbooks.get(&snapshot.market_id);
+                let published = orderbook.as_ref().map(|ob| ob.snapshot());
+                let update = AnalyticsUpdate {
+                    market_id: snapshot.market_id,
+                    symbol: orderbook.map(|ob| ob.symbol.clone()).unwrap_or_default(),
+                    timestamp: snapshot.timestamp_us,
+                    signal: Some(pb::analytics_update::Signal::Ofi(OrderFlowImbalance {
+                        horizons: snapshot
+                            .values
+                            .iter()
+                            .map(|v| OfiHorizon {
+                                horizon_ms: v.horizon.as_millis() as u32,
+                                ofi: v.ofi,
+                                event_count: v.event_count,
+                            })
+                            .collect(),
+                    })),
+                    microprice: published.as_ref().and_then(|p| p.microprice),
+                    depth_weighted_mid: published.as_ref().and_then(|p| p.depth_weighted_mid),
+                };
+                if tx.send(Ok(update)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeAnalyticsStream))
+    }
+
+    type SubscribeBookMetricsStream =
+        Pin<Box<dyn Stream<Item = Result<PbBookMetrics, Status>> + Send + 'static>>;
+
+    async fn subscribe_book_metrics(
+        &self,
+        request: Request<BookMetricsSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeBookMetricsStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let requested_markets: HashSet<u32> = request.into_inner().market_ids.into_iter().collect();
+        permissions.check_markets(&requested_markets)?;
+
+        info!("New book metrics subscription for markets: {:?}", requested_markets);
+
+        let mut metrics_rx = self.book_metrics.subscribe();
+        let orderbooks = self.orderbooks.clone();
+        let quota = quota.acquire(self.quota_markets(&requested_markets))?;
+
+        let subscriber = self.subscribers.register("SubscribeBookMetrics", peer, requested_markets.iter().copied()).with_quota(quota);
+        let tee = subscriber.tee();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(1000);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            loop {
+                let metrics = match metrics_rx.recv().await {
+                    Ok(metrics) => metrics,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if (!requested_markets.is_empty() && !requested_markets.contains(&metrics.market_id)) || !permissions.allows_market(metrics.market_id) {
+                    continue;
+                }
+
+                let update = PbBookMetrics {
+                    market_id: metrics.market_id,
+                    symbol: orderbooks.get(&metrics.market_id).map(|ob| ob.symbol.clone()).unwrap_or_default(),
+                    sequence: metrics.sequence,
+                    timestamp: metrics.timestamp_us,
+                    mid: metrics.mid,
+                    spread_bps: metrics.spread_bps,
+                    microprice: metrics.microprice,
+                    depth_weighted_mid: metrics.depth_weighted_mid,
+                    imbalances: metrics
+                        .imbalances
+                        .iter()
+                        .map(|level| LevelImbalance {
+                            levels: level.levels as u32,
+                            bid_size: level.bid_size,
+                            ask_size: level.ask_size,
+                            imbalance: level.imbalance(),
                         })
                         .collect(),
-                    asks: asks
-                        .into_iter()
-                        .map(|(price, quantity)| Level {
-                            price,
-                            quantity,
-                        })
+                    depths: metrics
+                        .depths
+                        .iter()
+                        .map(|band| BandDepth { bps: band.bps, bid_size: band.bid_size, ask_size: band.ask_size })
                         .collect(),
                 };
-                Ok(Response::new(snapshot))
+                if tx.send(Ok(update)).await.is_err() {
+                    break;
+                }
             }
-            None => Err(Status::not_found(format!(
-                "Market {} not found",
-                req.market_id
-            ))),
+        });
+
+        let stream = teed(rx_stream, tee);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeBookMetricsStream))
+    }
+
+    type SubscribeSessionEventsStream =
+        Pin<Box<dyn Stream<Item = Result<PbSessionEvent, Status>> + Send + 'static>>;
+
+    async fn subscribe_session_events(
+        &self,
+        request: Request<SessionEventsRequest>,
+    ) -> Result<Response<Self::SubscribeSessionEventsStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request).acquire(0)?;
+        let include_recent = request.into_inner().include_recent;
+        
+        // Subscribe before reading recent events so none fall in between
+        let mut events_rx = self.session_events.subscribe();
+        let recent = if include_recent { self.session_events.recent() } else { Vec::new() };
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(100);
+        let subscriber = self.subscribers.register("SubscribeSessionEvents", peer, []).with_quota(quota);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            let mut last_event_id = 0;
+            for event in recent {
+                last_event_id = event.event_id;
+                if tx.send(Ok(session_event_to_pb(&event))).await.is_err() {
+                    return;
+                }
+            }
+
+            loop {
+                let event = match events_rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if event.event_id <= last_event_id {
+                    continue;
+                }
+                if tx.send(Ok(session_event_to_pb(&event))).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream = tokio_stream::wrappers::ReceiverStream::new(rx_stream);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeSessionEventsStream))
+    }
+
+    type SubscribeWatermarksStream =
+        Pin<Box<dyn Stream<Item = Result<PbWatermark, Status>> + Send + 'static>>;
+
+    async fn subscribe_watermarks(
+        &self,
+        request: Request<WatermarksRequest>,
+    ) -> Result<Response<Self::SubscribeWatermarksStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request);
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let market_ids: HashSet<u32> = request.into_inner().market_ids.into_iter().collect();
+        if let Some(missing) = market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+            return Err(Status::not_found(format!("Market {} not found", missing)));
         }
+        permissions.check_markets(&market_ids)?;
+        
+        let mut watermarks_rx = self.watermarks.subscribe();
+        let quota = quota.acquire(self.quota_markets(&market_ids))?;
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(100);
+        let subscriber = self.subscribers.register("SubscribeWatermarks", peer, market_ids.iter().copied()).with_quota(quota);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            loop {
+                let watermarks = match watermarks_rx.recv().await {
+                    Ok(watermarks) => watermarks,
+                    // Watermarks are cumulative, the next batch supersedes missed ones
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                for watermark in watermarks.iter() {
+                    if (!market_ids.is_empty() && !market_ids.contains(&watermark.market_id)) || !permissions.allows_market(watermark.market_id) {
+                        continue;
+                    }
+                    let message = PbWatermark {
+                        market_id: watermark.market_id,
+                        watermark: watermark.watermark_ms as i64,
+                        sequence: watermark.sequence,
+                        late_events: watermark.late_events,
+                        timestamp: (watermark.timestamp_ns / 1000) as i64,
+                    };
+                    if tx.send(Ok(message)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        let stream = tokio_stream::wrappers::ReceiverStream::new(rx_stream);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeWatermarksStream))
+    }
+
+    type SubscribePositionPnlStream =
+        Pin<Box<dyn Stream<Item = Result<PositionPnlUpdate, Status>> + Send + 'static>>;
+
+    async fn subscribe_position_pnl(
+        &self,
+        request: Request<PositionPnlSubscribeRequest>,
+    ) -> Result<Response<Self::SubscribePositionPnlStream>, Status> {
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request).acquire(0)?;
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let users: std::collections::HashSet<String> =
+            request.into_inner().users.into_iter().collect();
+
+        info!("New position PnL subscription for users: {:?}", users);
+
+        let mut pnl_rx = self.pnl_tracker.subscribe();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(100);
+        let subscriber = self.subscribers.register("SubscribePositionPnl", peer, []).with_quota(quota);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            loop {
+                let positions = match pnl_rx.recv().await {
+                    Ok(positions) => positions,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                let timestamp = positions.first().map(|p| p.timestamp_us).unwrap_or_else(now_micros);
+                let positions: Vec<PbPositionPnl> = positions
+                    .iter()
+                    .filter(|p| users.is_empty() || users.contains(&p.user))
+                    .filter(|p| permissions.allows_market(p.market_id))
+                    .map(|p| PbPositionPnl {
+                        user: p.user.clone(),
+                        market_id: p.market_id,
+                        coin: p.coin.clone(),
+                        size: p.size,
+                        entry_price: p.entry_price,
+                        mark_price: p.mark_price,
+                        unrealized_pnl: p.unrealized_pnl,
+                        accrued_funding: p.accrued_funding,
+                        funding_rate: p.funding_rate,
+                        net_pnl: p.net_pnl,
+                    })
+                    .collect();
+
+                if positions.is_empty() {
+                    continue;
+                }
+
+                let update = PositionPnlUpdate {
+                    timestamp,
+                    positions,
+                };
+                if tx.send(Ok(update)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream = tokio_stream::wrappers::ReceiverStream::new(rx_stream);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribePositionPnlStream))
+    }
+
+    async fn get_errors(
+        &self,
+        request: Request<ErrorsRequest>,
+    ) -> Result<Response<ErrorsResponse>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let req = request.into_inner();
+        
+        let query = ErrorQuery {
+            category: match req.category.as_str() {
+                "" => None,
+                category => Some(
+                    category
+                        .parse::<ErrorCategory>()
+                        .map_err(|e| Status::invalid_argument(e.to_string()))?,
+                ),
+            },
+            min_severity: match req.min_severity.as_str() {
+                "" => None,
+                severity => Some(
+                    severity
+                        .parse::<Severity>()
+                        .map_err(|e| Status::invalid_argument(e.to_string()))?,
+                ),
+            },
+            market_id: req.market_id,
+            since_ms: (req.since > 0).then_some(req.since as u64),
+            limit: (req.limit > 0).then_some(req.limit as usize),
+        };
+        
+        let errors = self
+            .error_buffer
+            .query(&query)
+            .into_iter()
+            .map(|record| PbErrorRecord {
+                error_id: record.error_id,
+                category: record.category.as_str().to_string(),
+                severity: record.severity.as_str().to_string(),
+                market_id: record.market_id,
+                message: record.message,
+                sample: record.sample,
+                timestamp: record.timestamp_ms as i64,
+            })
+            .collect();
+        
+        let totals = self
+            .error_buffer
+            .totals()
+            .into_iter()
+            .map(|(category, total)| ErrorCount {
+                category: category.as_str().to_string(),
+                total,
+            })
+            .collect();
+        
+        Ok(Response::new(ErrorsResponse { errors, totals }))
     }
 
-    async fn get_markets(
+    async fn get_market_timings(
         &self,
-        _request: Request<GetMarketsRequest>,
-    ) -> Result<Response<GetMarketsResponse>, Status> {
+        request: Request<MarketTimingsRequest>,
+    ) -> Result<Response<MarketTimingsResponse>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let requested: HashSet<u32> = request.into_inner().market_ids.into_iter().collect();
+        permissions.check_markets(&requested)?;
+        let config = self.market_scheduler.config();
+        
         let markets = self
-            .orderbooks
-            .iter()
-            .map(|(market_id, orderbook)| Market {
-                id: *market_id,
-                symbol: orderbook.symbol.clone(),
+            .market_scheduler
+            .timings()
+            .into_iter()
+            .filter(|timing| requested.is_empty() || requested.contains(&timing.market_id))
+            .filter(|timing| permissions.allows_market(timing.market_id))
+            .map(|timing| {
+                let batching = self.publish_batching.stats(timing.market_id);
+                let bounds = BATCH_SIZE_BUCKETS.iter().map(|&max| max as u32).chain([0]);
+                PbMarketTiming {
+                    market_id: timing.market_id,
+                    busy_us: timing.busy.as_micros() as u64,
+                    batches: timing.batches,
+                    throttles: timing.throttles,
+                    throttled_us: timing.throttled.as_micros() as u64,
+                    max_wait_us: timing.max_wait.as_micros() as u64,
+                    backlog: timing.backlog as u32,
+                    slice_busy_us: timing.slice_busy.as_micros() as u64,
+                    batch_window_us: self.publish_batching.window(timing.market_id).as_micros() as u32,
+                    updates: batching.updates,
+                    deltas: batching.deltas,
+                    max_batch_deltas: batching.max_deltas as u32,
+                    batch_sizes: bounds
+                        .zip(batching.buckets)
+                        .map(|(max_deltas, updates)| BatchSizeBucket { max_deltas, updates })
+                        .collect(),
+                }
             })
             .collect();
+        
+        Ok(Response::new(MarketTimingsResponse {
+            interval_ms: config.interval.as_millis() as u32,
+            budget_us: config.budget.map(|b| b.as_micros() as u32).unwrap_or(0),
+            markets,
+        }))
+    }
 
-        Ok(Response::new(GetMarketsResponse { markets }))
+    async fn list_subscribers(
+        &self,
+        request: Request<GetMarketsRequest>,
+    ) -> Result<Response<SubscribersResponse>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let subscribers = self
+            .subscribers
+            .list()
+            .into_iter()
+            .map(|(info, teeing)| PbSubscriberInfo {
+                id: info.id,
+                method: info.method.to_string(),
+                peer: info.peer.map(|peer| peer.to_string()).unwrap_or_default(),
+                market_ids: info.market_ids,
+                started_at: info.started_ms,
+                teeing,
+            })
+            .collect();
+        Ok(Response::new(SubscribersResponse { subscribers }))
     }
 
-    async fn get_stop_orders(
+    async fn start_tee(
         &self,
-        request: Request<StopOrdersRequest>,
-    ) -> Result<Response<StopOrdersResponse>, Status> {
+        request: Request<TeeRequest>,
+    ) -> Result<Response<TeeResponse>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
         let req = request.into_inner();
-        
-        // Get base list of orders based on primary filter
-        let mut orders = match req.filter {
-            Some(pb::stop_orders_request::Filter::MarketId(market_id)) => {
-                self.stop_order_manager.get_stop_orders_by_market(market_id)
-            }
-            Some(pb::stop_orders_request::Filter::User(user)) => {
-                self.stop_order_manager.get_stop_orders_by_user(&user)
-            }
-            None => {
-                self.stop_order_manager.get_all_stop_orders()
-            }
+        let (info, slot) = self
+            .subscribers
+            .tee(req.subscriber_id)
+            .ok_or_else(|| Status::not_found(format!("Subscriber {} not found", req.subscriber_id)))?;
+        let duration = match req.duration_secs {
+            0 => Duration::from_secs(60),
+            secs => Duration::from_secs(secs.min(3600) as u64),
+        };
+        let max_bytes = match req.max_bytes {
+            0 => 64 << 20,
+            bytes => bytes.min(1 << 30),
         };
 
-        // Apply additional filters
-        if req.min_notional > 0.0 || req.max_notional > 0.0 {
-            orders.retain(|order| {
-                let notional = order.price * order.size;
-                (req.min_notional == 0.0 || notional >= req.min_notional) &&
-                (req.max_notional == 0.0 || notional <= req.max_notional)
-            });
-        }
+        let path = self.tee_dir.join(format!("{}-{}-{}.tee", info.method, info.id, now_micros() / 1000));
+        let writer = TeeWriter::create(path.clone(), info.method, duration, max_bytes)
+            .map_err(|e| Status::internal(format!("Failed to create {}: {}", path.display(), e)))?;
+        slot.start(writer).map_err(|e| Status::failed_precondition(e.to_string()))?;
 
-        if !req.side.is_empty() {
-            orders.retain(|order| order.side == req.side);
-        }
+        Ok(Response::new(TeeResponse {
+            path: path.display().to_string(),
+            duration_secs: duration.as_secs() as u32,
+            max_bytes,
+        }))
+    }
 
-        // If ranking is requested, collect market data and rank orders
-        if req.rank_by_risk {
-            // Collect current mid prices and orderbooks
-            let mut mid_prices = HashMap::new();
-            let mut orderbooks = HashMap::new();
-            
-            for order in &orders {
-                if let Some(market_id) = self.market_registry.get_market_id(&order.coin).await {
-                    if let Some(orderbook) = self.orderbooks.get(&market_id) {
-                        if let Some((best_bid, best_ask)) = orderbook.get_best_bid_ask() {
-                            let mid = (best_bid + best_ask) / 2.0;
-                            mid_prices.insert(market_id, mid);
-                            
-                            // Get orderbook snapshot for slippage calculation
-                            let (bids, asks) = orderbook.get_snapshot(50);
-                            orderbooks.insert(market_id, (bids, asks));
-                        }
-                    }
+    async fn get_memory_stats(
+        &self,
+        request: Request<GetMarketsRequest>,
+    ) -> Result<Response<MemoryStatsResponse>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let report = self.memory_report();
+        Ok(Response::new(MemoryStatsResponse {
+            rss_bytes: report.rss_bytes,
+            allocator: report.allocator.map(|stats| PbAllocatorStats {
+                allocated: stats.allocated,
+                active: stats.active,
+                resident: stats.resident,
+                retained: stats.retained,
+            }),
+            unattributed_bytes: report.unattributed_bytes(),
+            components: report
+                .components
+                .into_iter()
+                .map(|(name, usage)| MemoryComponent {
+                    name: name.to_string(),
+                    items: usage.items as u64,
+                    bytes: usage.bytes as u64,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_stats(
+        &self,
+        request: Request<GetMarketsRequest>,
+    ) -> Result<Response<StatsResponse>, Status> {
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let progress = self.processor.as_ref().map(|p| p.progress());
+        let (circuits, validation_circuit) = self
+            .processor
+            .as_ref()
+            .map(|p| p.circuit_states())
+            .unwrap_or_default();
+        let subscribers = self.subscribers.per_market();
+        let node_format = self.processor.as_ref().map(|p| p.node_format());
+        let stop_order_counts = self.stop_order_manager.counts_by_market();
+        let stop_orders = self.stop_order_manager.stats();
+
+        let mut markets: Vec<MarketStats> = self
+            .orderbooks
+            .all()
+            .iter()
+            .filter(|(market_id, _)| permissions.allows_market(**market_id))
+            .map(|(market_id, orderbook)| {
+                let market = progress.as_ref().and_then(|p| p.markets.get(market_id));
+                let integrity = orderbook.integrity.stats();
+                MarketStats {
+                    market_id: *market_id,
+                    symbol: orderbook.symbol.clone(),
+                    sequence: orderbook.sequence.load(std::sync::atomic::Ordering::Relaxed),
+                    bid_levels: orderbook.bid_count.load(std::sync::atomic::Ordering::Relaxed) as u32,
+                    ask_levels: orderbook.ask_count.load(std::sync::atomic::Ordering::Relaxed) as u32,
+                    orders: orderbook.total_orders.load(std::sync::atomic::Ordering::Relaxed) as u32,
+                    lag_ms: market.map(|m| now_ms.saturating_sub(m.last_order_timestamp_ms)),
+                    idle_ms: market.map(|m| now_ms.saturating_sub(m.processed_at_ms)),
+                    circuit: circuits.get(market_id).copied().unwrap_or("CLOSED").to_string(),
+                    tier: self.market_tiers.tier(*market_id).as_str().to_string(),
+                    subscribers: subscribers.get(market_id).copied().unwrap_or(0) as u32,
+                    integrity: Some(BookIntegrity {
+                        duplicate_opens: integrity.duplicate_opens,
+                        oid_reuses: integrity.oid_reuses,
+                        oid_wraparounds: integrity.oid_wraparounds,
+                        crossed_books: integrity.crossed_books,
+                        crossed_evictions: integrity.crossed_evictions,
+                    }),
+                    stop_orders: stop_order_counts.get(market_id).copied().unwrap_or(0) as u32,
+                    drift: self
+                        .book_drift
+                        .as_ref()
+                        .and_then(|monitor| monitor.get(*market_id))
+                        .map(|drift| self.pb_book_drift(&drift)),
                 }
-            }
-            
-            // Use default weights if not specified
-            let distance_weight = if req.distance_weight > 0.0 { req.distance_weight } else { 0.6 };
-            let slippage_weight = if req.slippage_weight > 0.0 { req.slippage_weight } else { 0.4 };
-            
-            // Rank the orders
-            let ranked_orders = self.stop_order_manager.rank_stop_orders(
-                orders,
-                &mid_prices,
-                &orderbooks,
-                distance_weight,
-                slippage_weight,
-            );
-            
-            // Convert to protobuf format with ranking information
-            let pb_orders: Vec<PbRankedStopOrder> = ranked_orders
+            })
+            .collect();
+        markets.sort_by_key(|m| m.market_id);
+
+        Ok(Response::new(StatsResponse {
+            uptime_secs: self.started.elapsed().as_secs(),
+            lines_read: progress.as_ref().map(|p| p.lines_read).unwrap_or(0),
+            bytes_read: progress.as_ref().map(|p| p.bytes_read).unwrap_or(0),
+            subscribers: self.subscribers.total() as u32,
+            degraded: self.degradation.is_degraded(),
+            validation_circuit: if validation_circuit.is_empty() { "CLOSED".to_string() } else { validation_circuit },
+            markets,
+            trades_read: self.fills.as_ref().map(|fills| fills.trades()).unwrap_or(0),
+            node_format: node_format.map(|d| d.format.as_str().to_string()).unwrap_or_default(),
+            node_format_source: node_format.map(|d| d.source.to_string()).unwrap_or_default(),
+            rss_bytes: memory_profile::rss_bytes().unwrap_or(0),
+            rpc_caches: vec![
+                rpc_cache_stats("GetOrderbook", self.orderbook_cache.stats()),
+                rpc_cache_stats("GetMarkets", self.markets_cache.stats()),
+            ],
+            stop_orders: Some(PbStopOrderStats {
+                live: stop_orders.live,
+                added: stop_orders.added,
+                canceled: stop_orders.canceled,
+                triggered: stop_orders.triggered,
+                filled: stop_orders.filled,
+            }),
+            book_shards: self
+                .book_shards
+                .as_ref()
+                .map(|shards| shards.stats())
+                .unwrap_or_default()
                 .into_iter()
-                .filter_map(|ranked| {
-                    let market_id = crate::markets::get_market_id(&ranked.order.coin).unwrap_or(0);
-                    let current_mid = mid_prices.get(&market_id).copied().unwrap_or(0.0);
-                    
-                    // Apply distance filter if specified
-                    if req.max_distance_from_mid_bps > 0.0 && ranked.distance_to_trigger_bps > req.max_distance_from_mid_bps {
-                        return None;
-                    }
-                    
-                    // Determine risk level
-                    let risk_level = if ranked.risk_score >= 80.0 {
-                        "HIGH".to_string()
-                    } else if ranked.risk_score >= 50.0 {
-                        "MEDIUM".to_string()
-                    } else {
-                        "LOW".to_string()
-                    };
-                    
-                    Some(PbRankedStopOrder {
-                        order: Some(PbStopOrder {
-                            id: ranked.order.id,
-                            user: ranked.order.user,
-                            market_id,
-                            coin: ranked.order.coin,
-                            side: ranked.order.side,
-                            price: ranked.order.price,
-                            size: ranked.order.size,
-                            trigger_condition: ranked.order.trigger_condition,
-                            timestamp: ranked.order.timestamp,
-                            notional: ranked.notional_value,
-                            distance_from_mid_bps: ranked.distance_to_trigger_bps,
-                            current_mid_price: current_mid,
-                        }),
-                        distance_to_trigger_bps: ranked.distance_to_trigger_bps,
-                        expected_slippage_bps: ranked.expected_slippage_bps,
-                        risk_score: ranked.risk_score,
-                        risk_level,
+                .map(|stats| BookShard {
+                    shard: stats.shard as u32,
+                    core: stats.core.map(|core| core as u32),
+                    markets: stats.markets as u32,
+                    queued: stats.queued as u64,
+                    batches: stats.batches,
+                    commands: stats.commands,
+                    busy_ms: stats.busy_ms,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_service_health(
+        &self,
+        request: Request<GetMarketsRequest>,
+    ) -> Result<Response<ServiceHealthResponse>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let processor = self.processor.as_ref().ok_or_else(|| Status::unavailable("Order processing is not running"))?;
+        let ServiceHealth { parser, circuits, rotations } = ServiceHealth::of(processor);
+        Ok(Response::new(ServiceHealthResponse {
+            parser: Some(ParserHealth {
+                total_messages: parser.total_messages,
+                parse_failures: parser.parse_failures,
+                validation_failures: parser.validation_failures,
+                success_rate: parser.success_rate,
+            }),
+            circuits: Some(CircuitBreakerHealth {
+                total_markets: circuits.total_markets as u32,
+                open_markets: circuits
+                    .open_markets
+                    .into_iter()
+                    .map(|(market_id, reason)| OpenCircuit {
+                        market_id,
+                        symbol: self.orderbooks.get(&market_id).map(|o| o.symbol.clone()).unwrap_or_default(),
+                        reason,
                     })
+                    .collect(),
+                half_open_market_ids: circuits.half_open_markets,
+                closed_markets: circuits.closed_markets as u32,
+                validation_circuit: circuits.validation_circuit_state,
+                validation_failures: circuits.validation_failures,
+            }),
+            feed_rotations: rotations,
+        }))
+    }
+
+    async fn get_latency_stats(
+        &self,
+        request: Request<LatencyStatsRequest>,
+    ) -> Result<Response<LatencyStatsResponse>, Status> {
+        let latency = self.latency.as_ref().ok_or_else(|| Status::unavailable("Latency stats are not enabled"))?;
+        let permissions = auth_interceptor::permissions(&request);
+        permissions.require(Scope::ReadOnly)?;
+        let market_ids = request.into_inner().market_ids;
+        permissions.check_markets(&market_ids)?;
+        let stages = |stages: Vec<StageLatency>| {
+            stages
+                .into_iter()
+                .map(|stage| {
+                    let [p50_us, p90_us, p99_us, p999_us] = stage.quantiles_us;
+                    PbStageLatency {
+                        stage: stage.stage.as_str().to_string(),
+                        count: stage.count,
+                        p50_us,
+                        p90_us,
+                        p99_us,
+                        p999_us,
+                        max_us: stage.max_us,
+                    }
                 })
-                .collect();
-                
-            Ok(Response::new(StopOrdersResponse { orders: pb_orders }))
-        } else {
-            // Non-ranked response - convert to simple format
-            let pb_orders: Vec<PbRankedStopOrder> = orders
+                .collect()
+        };
+        Ok(Response::new(LatencyStatsResponse {
+            overall: stages(latency.overall()),
+            markets: latency
+                .markets(&market_ids)
                 .into_iter()
-                .filter_map(|order| {
-                    let notional = order.price * order.size;
-                    
-                    // Get current mid price for distance calculation
-                    let market_id = crate::markets::get_market_id(&order.coin).unwrap_or(0);
-                    let (current_mid, distance_bps) = if let Some(orderbook) = self.orderbooks.get(&market_id) {
-                        if let Some((best_bid, best_ask)) = orderbook.get_best_bid_ask() {
-                            let mid = (best_bid + best_ask) / 2.0;
-                            let distance = ((order.price - mid).abs() / mid) * 10000.0;
-                            (mid, distance)
-                        } else {
-                            (0.0, 0.0)
-                        }
-                    } else {
-                        (0.0, 0.0)
-                    };
+                .filter(|market| permissions.allows_market(market.market_id))
+                .map(|market| PbMarketLatency { market_id: market.market_id, stages: stages(market.stages) })
+                .collect(),
+        }))
+    }
 
-                    // Apply distance filter if specified
-                    if req.max_distance_from_mid_bps > 0.0 && distance_bps > req.max_distance_from_mid_bps {
-                        return None;
-                    }
+    type SubscribeDegradationStream =
+        Pin<Box<dyn Stream<Item = Result<DegradationState, Status>> + Send + 'static>>;
 
-                    Some(PbRankedStopOrder {
-                        order: Some(PbStopOrder {
-                            id: order.id,
-                            user: order.user,
-                            market_id,
-                            coin: order.coin,
-                            side: order.side,
-                            price: order.price,
-                            size: order.size,
-                            trigger_condition: order.trigger_condition,
-                            timestamp: order.timestamp,
-                            notional,
-                            distance_from_mid_bps: distance_bps,
-                            current_mid_price: current_mid,
-                        }),
-                        distance_to_trigger_bps: distance_bps,
-                        expected_slippage_bps: 0.0,
-                        risk_score: 0.0,
-                        risk_level: "UNKNOWN".to_string(),
-                    })
-                })
-                .collect();
+    async fn subscribe_degradation(
+        &self,
+        request: Request<GetMarketsRequest>,
+    ) -> Result<Response<Self::SubscribeDegradationStream>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let quota = auth_interceptor::stream_quota(&request).acquire(0)?;
+        // Subscribe before reading the current state so no change falls in between
+        let mut events_rx = self.degradation.subscribe();
+        let current = self.degradation.current();
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(16);
+        let subscriber = self.subscribers.register("SubscribeDegradation", request.remote_addr(), []).with_quota(quota);
 
-            Ok(Response::new(StopOrdersResponse { orders: pb_orders }))
-        }
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            let mut last_event_id = current.event_id;
+            if tx.send(Ok(degradation_to_pb(&current))).await.is_err() {
+                return;
+            }
+
+            loop {
+                let event = match events_rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if event.event_id <= last_event_id {
+                    continue;
+                }
+                last_event_id = event.event_id;
+                if tx.send(Ok(degradation_to_pb(&event))).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream = tokio_stream::wrappers::ReceiverStream::new(rx_stream);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeDegradationStream))
     }
 
-    type SubscribeMarkPricesStream =
-        Pin<Box<dyn Stream<Item = Result<MarkPriceUpdate, Status>> + Send>>;
+    type SubscribeAlertsStream =
+        Pin<Box<dyn Stream<Item = Result<PbAlert, Status>> + Send + 'static>>;
 
-    async fn subscribe_mark_prices(
+    async fn subscribe_alerts(
         &self,
-        _request: Request<MarkPriceSubscribeRequest>,
-    ) -> Result<Response<Self::SubscribeMarkPricesStream>, Status> {
-        Err(Status::unimplemented("Mark price service temporarily disabled"))
+        request: Request<AlertsRequest>,
+    ) -> Result<Response<Self::SubscribeAlertsStream>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let peer = request.remote_addr();
+        let quota = auth_interceptor::stream_quota(&request).acquire(0)?;
+        let include_recent = request.into_inner().include_recent;
+        
+        // Subscribe before reading recent alerts so none fall in between
+        let mut alerts_rx = self.alerts.subscribe();
+        let recent = if include_recent { self.alerts.recent() } else { Vec::new() };
+        let (tx, rx_stream) = tokio::sync::mpsc::channel(100);
+        let subscriber = self.subscribers.register("SubscribeAlerts", peer, []).with_quota(quota);
+
+        tokio::spawn(async move {
+            let _subscriber = subscriber;
+            let mut last_alert_id = 0;
+            for alert in recent {
+                last_alert_id = alert.alert_id;
+                if tx.send(Ok(alert_to_pb(&alert))).await.is_err() {
+                    return;
+                }
+            }
+
+            loop {
+                let alert = match alerts_rx.recv().await {
+                    Ok(alert) => alert,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if alert.alert_id <= last_alert_id {
+                    continue;
+                }
+                if tx.send(Ok(alert_to_pb(&alert))).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream = tokio_stream::wrappers::ReceiverStream::new(rx_stream);
+        Ok(Response::new(Box::pin(stream) as Self::SubscribeAlertsStream))
     }
 
-    async fn get_mark_price(
+    async fn force_resnapshot(
         &self,
-        _request: Request<GetMarkPriceRequest>,
-    ) -> Result<Response<MarkPriceResponse>, Status> {
-        Err(Status::unimplemented("Mark price service temporarily disabled"))
+        request: Request<ResnapshotRequest>,
+    ) -> Result<Response<ResnapshotResponse>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let req = request.into_inner();
+        
+        let mut market_ids: Vec<u32> = if req.market_ids.is_empty() {
+            self.orderbooks.keys()
+        } else {
+            if let Some(missing) = req.market_ids.iter().find(|id| !self.orderbooks.contains_key(id)) {
+                return Err(Status::not_found(format!("Market {} not found", missing)));
+            }
+            req.market_ids
+        };
+        market_ids.sort_unstable();
+        market_ids.dedup();
+        
+        // No receivers just means no streams are open
+        let active_streams = self.resnapshot_tx.send(Arc::new(market_ids.clone())).unwrap_or(0);
+        info!(
+            "Forced resnapshot of {} markets to {} active streams",
+            market_ids.len(),
+            active_streams
+        );
+        
+        Ok(Response::new(ResnapshotResponse {
+            market_ids,
+            active_streams: active_streams as u32,
+        }))
+    }
+
+    async fn get_user_activity(
+        &self,
+        request: Request<UserActivityRequest>,
+    ) -> Result<Response<PbUserActivity>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let user = request.into_inner().user;
+        match self.user_activity.user(&user) {
+            Some(activity) => Ok(Response::new(user_activity_to_pb(activity, self.user_activity.window()))),
+            None => Err(Status::not_found(format!("No activity for user {} in the window", user))),
+        }
+    }
+
+    async fn get_user_leaderboard(
+        &self,
+        request: Request<UserLeaderboardRequest>,
+    ) -> Result<Response<UserLeaderboardResponse>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let req = request.into_inner();
+        let metric = match req.metric.as_str() {
+            "" => ActivityMetric::OrderRate,
+            metric => metric
+                .parse::<ActivityMetric>()
+                .map_err(|e| Status::invalid_argument(e.to_string()))?,
+        };
+        let limit = if req.limit > 0 { req.limit as usize } else { DEFAULT_LEADERBOARD_LIMIT };
+        let window = self.user_activity.window();
+
+        Ok(Response::new(UserLeaderboardResponse {
+            metric: metric.as_str().to_string(),
+            users: self
+                .user_activity
+                .leaderboard(metric, limit)
+                .into_iter()
+                .map(|activity| user_activity_to_pb(activity, window))
+                .collect(),
+        }))
+    }
+
+    async fn get_stuffing_stats(
+        &self,
+        request: Request<StuffingStatsRequest>,
+    ) -> Result<Response<StuffingStatsResponse>, Status> {
+        auth_interceptor::permissions(&request).require(Scope::Admin)?;
+        let stuffing = self
+            .stuffing
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("Quote stuffing detection is not enabled"))?;
+        let limit = match request.into_inner().limit {
+            0 => DEFAULT_LEADERBOARD_LIMIT,
+            limit => limit as usize,
+        };
+        let config = stuffing.config();
+        let to_pb = |stats: StuffingStats| PbStuffingStats {
+            cycles: stats.cycles,
+            flagged_orders: stats.flagged_orders,
+            mean_lifetime_ms: stats.mean_lifetime_ms(),
+        };
+
+        let mut markets: Vec<MarketStuffing> = stuffing
+            .market_stats()
+            .into_iter()
+            .map(|(market_id, stats)| MarketStuffing { market_id, stats: Some(to_pb(stats)) })
+            .collect();
+        markets.sort_by_key(|m| m.market_id);
+
+        Ok(Response::new(StuffingStatsResponse {
+            action: config.action.as_str().to_string(),
+            max_lifetime_ms: config.max_lifetime.as_millis() as u32,
+            burst: config.burst,
+            users: stuffing
+                .user_stats(limit)
+                .into_iter()
+                .map(|(user, stats)| UserStuffing { user, stats: Some(to_pb(stats)) })
+                .collect(),
+            markets,
+        }))
     }
 }
 
 pub fn create_delta_streaming_service(
-    orderbooks: HashMap<u32, Arc<FastOrderbook>>,
-    update_rx: broadcast::Receiver<MarketUpdate>,
+    orderbooks: MarketBooks,
+    fanout: Arc<MarketFanout>,
     stop_order_manager: Arc<StopOrderManager>,
     market_registry: Arc<DynamicMarketRegistry>,
+    market_tiers: Arc<MarketTiers>,
+    ofi_engine: Arc<OfiEngine>,
+    pnl_tracker: Arc<PositionPnlTracker>,
+    replay_cache: Arc<ReplayCache>,
+    session_events: Arc<SessionEvents>,
+    watermarks: Arc<WatermarkTracker>,
+    error_buffer: Arc<ErrorBuffer>,
+    alerts: Arc<Alerts>,
 ) -> DeltaStreamingService {
-    DeltaStreamingService::new(orderbooks, update_rx, stop_order_manager, market_registry)
-}
\ No newline at end of file
+    DeltaStreamingService::new(
+        orderbooks,
+        fanout,
+        stop_order_manager,
+        market_registry,
+        market_tiers,
+        ofi_engine,
+        pnl_tracker,
+        replay_cache,
+        session_events,
+        watermarks,
+        error_buffer,
+        alerts,
+    )
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth_interceptor::{ApiKeys, ListenerPolicy, StreamLimits};
+    use crate::hourly_path::HourlyLayout;
+    use tonic::service::Interceptor;
+
+    fn service() -> DeltaStreamingService {
+        let books: HashMap<u32, Arc<FastOrderbook>> =
+            (0..3).map(|id| (id, Arc::new(FastOrderbook::new(id, format!("M{}", id))))).collect();
+        DeltaStreamingService::new(
+            books.clone().into(),
+            Arc::new(MarketFanout::default()),
+            Arc::new(StopOrderManager::new()),
+            Arc::new(DynamicMarketRegistry::new()),
+            Arc::new(MarketTiers::new(MarketTier::Hot)),
+            Arc::new(OfiEngine::new(Vec::new())),
+            Arc::new(PositionPnlTracker::new(Vec::new())),
+            Arc::new(ReplayCache::new(Duration::from_secs(1))),
+            Arc::new(SessionEvents::new(books.clone(), HourlyLayout::new("/data"))),
+            Arc::new(WatermarkTracker::new(books, Duration::from_secs(1))),
+            Arc::new(ErrorBuffer::new(16)),
+            Arc::new(Alerts::new()),
+        )
+    }
+
+    /// A request from a read-only key limited to markets 0 and 1
+    fn restricted<T>(message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.extensions_mut().insert(Arc::new(KeyPermissions {
+            scopes: HashSet::from([Scope::ReadOnly]),
+            markets: Some(HashSet::from([0, 1])),
+            max_streams: None,
+            max_markets: None,
+        }));
+        request
+    }
+
+    fn code<T>(result: Result<Response<T>, Status>) -> Option<tonic::Code> {
+        result.err().map(|e| e.code())
+    }
+
+    #[tokio::test]
+    async fn test_restricted_keys_only_stream_their_markets() {
+        let service = service();
+
+        let deltas = |market_ids: Vec<u32>| restricted(DeltaSubscribeRequest { market_ids, ..Default::default() });
+        assert_eq!(code(service.subscribe_deltas(deltas(vec![0, 2])).await), Some(tonic::Code::PermissionDenied));
+        assert_eq!(code(service.subscribe_deltas(deltas(vec![0, 1])).await), None);
+
+        let l3 = |market_ids: Vec<u32>| restricted(L3SubscribeRequest { market_ids, ..Default::default() });
+        assert_eq!(code(service.subscribe_l3(l3(vec![2])).await), Some(tonic::Code::PermissionDenied));
+        assert_eq!(code(service.subscribe_l3(l3(vec![1])).await), None);
+
+        // Unknown markets are still reported as such
+        assert_eq!(code(service.subscribe_l3(l3(vec![9])).await), Some(tonic::Code::NotFound));
+    }
+
+    #[tokio::test]
+    async fn test_restricted_keys_only_see_their_markets_stats() {
+        let service = service();
+        let stats = service.get_stats(restricted(GetMarketsRequest::default())).await.unwrap().into_inner();
+        let markets: Vec<u32> = stats.markets.iter().map(|m| m.market_id).collect();
+        assert_eq!(markets, vec![0, 1]);
+        assert_eq!(service.get_stats(Request::new(GetMarketsRequest::default())).await.unwrap().into_inner().markets.len(), 3);
+
+        // Operational state is for admins
+        assert_eq!(code(service.get_memory_stats(restricted(GetMarketsRequest::default())).await), Some(tonic::Code::PermissionDenied));
+        assert_eq!(code(service.subscribe_alerts(restricted(AlertsRequest::default())).await), Some(tonic::Code::PermissionDenied));
+        assert_eq!(code(service.subscribe_degradation(restricted(GetMarketsRequest::default())).await), Some(tonic::Code::PermissionDenied));
+    }
+
+    /// A request carrying what `policy` attaches for the key "key"
+    fn keyed<T>(policy: &mut ListenerPolicy, message: T) -> Request<T> {
+        let mut request = Request::new(());
+        request.metadata_mut().insert("x-api-key", "key".parse().unwrap());
+        let (metadata, extensions, ()) = policy.call(request).unwrap().into_parts();
+        Request::from_parts(metadata, extensions, message)
+    }
+
+    #[tokio::test]
+    async fn test_streams_without_markets_count_against_the_quota() {
+        let service = service();
+        let mut keys = ApiKeys::new(HashSet::new(), HashMap::from([("key".to_string(), KeyPermissions::full())]));
+        keys.set_stream_limits(StreamLimits { max_streams: Some(1), max_markets: None });
+        let mut policy = ListenerPolicy::new(keys, true, None);
+
+        let _alerts = service.subscribe_alerts(keyed(&mut policy, AlertsRequest::default())).await.unwrap();
+        let degradation = service.subscribe_degradation(keyed(&mut policy, GetMarketsRequest::default())).await;
+        assert_eq!(code(degradation), Some(tonic::Code::ResourceExhausted));
+        let events = service.subscribe_session_events(keyed(&mut policy, SessionEventsRequest::default())).await;
+        assert_eq!(code(events), Some(tonic::Code::ResourceExhausted));
+    }
+}
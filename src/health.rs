@@ -0,0 +1,151 @@
+//! The standard `grpc.health.v1.Health` service, for load balancers and
+//! Kubernetes probes.
+//!
+//! Serving means the processor is tailing the node's feed, the market
+//! registry refreshed recently and the update broadcast isn't overflowing.
+//! Both the overall status (service `""`) and `orderbook.OrderbookService`
+//! report this; other names are unknown.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+
+use crate::dynamic_markets::DynamicMarketRegistry;
+use crate::robust_order_processor::RobustOrderProcessor;
+use crate::market_processor::MarketUpdate;
+
+pub mod pb {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+use pb::health_check_response::ServingStatus;
+use pb::health_server::Health;
+use pb::{HealthCheckRequest, HealthCheckResponse};
+
+const SERVICES: [&str; 2] = ["", "orderbook.OrderbookService"];
+
+/// How often Watch re-evaluates the status
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct HealthService {
+    /// None when the service doesn't follow a live feed
+    processor: Option<Arc<RobustOrderProcessor>>,
+    registry: Arc<DynamicMarketRegistry>,
+    updates: broadcast::Sender<MarketUpdate>,
+    updates_capacity: usize,
+    max_registry_age: Duration,
+}
+
+impl HealthService {
+    pub fn new(
+        processor: Option<Arc<RobustOrderProcessor>>,
+        registry: Arc<DynamicMarketRegistry>,
+        updates: broadcast::Sender<MarketUpdate>,
+        updates_capacity: usize,
+        max_registry_age: Duration,
+    ) -> Self {
+        Self { processor, registry, updates, updates_capacity, max_registry_age }
+    }
+
+    /// Why the service can't serve, if it can't
+    pub async fn unhealthy_reasons(&self) -> Vec<String> {
+        let mut reasons = Vec::new();
+        if self.processor.as_ref().is_some_and(|processor| !processor.is_tailing()) {
+            reasons.push("not tailing the node feed".to_string());
+        }
+        let registry_age = self.registry.last_update_elapsed().await;
+        if registry_age > self.max_registry_age {
+            reasons.push(format!("market registry last refreshed {}s ago", registry_age.as_secs()));
+        }
+        if self.updates.receiver_count() == 0 {
+            reasons.push("update broadcast has no receivers".to_string());
+        } else if self.updates.len() >= self.updates_capacity {
+            reasons.push("update broadcast is full".to_string());
+        }
+        reasons
+    }
+
+    async fn status(&self, service: &str) -> ServingStatus {
+        if !SERVICES.contains(&service) {
+            return ServingStatus::ServiceUnknown;
+        }
+        if self.unhealthy_reasons().await.is_empty() {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        }
+    }
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse { status: status as i32 }
+}
+
+#[tonic::async_trait]
+impl Health for Arc<HealthService> {
+    async fn check(&self, request: Request<HealthCheckRequest>) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        match self.status(&service).await {
+            ServingStatus::ServiceUnknown => Err(Status::not_found(format!("Unknown service: {}", service))),
+            status => Ok(Response::new(response(status))),
+        }
+    }
+
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send>>;
+
+    async fn watch(&self, request: Request<HealthCheckRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+        let health = self.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        tokio::spawn(async move {
+            // The current status first, then each change
+            let mut last = None;
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if tx.is_closed() {
+                    break;
+                }
+                let status = health.status(&service).await;
+                if last != Some(status) {
+                    if tx.send(Ok(response(status))).await.is_err() {
+                        break;
+                    }
+                    last = Some(status);
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_status_follows_internal_state() {
+        let (updates, rx) = broadcast::channel(2);
+        let health = Arc::new(HealthService::new(None, Arc::new(DynamicMarketRegistry::new()), updates.clone(), 2, Duration::from_secs(60)));
+        let check = |service: &str| {
+            let health = health.clone();
+            let request = Request::new(HealthCheckRequest { service: service.to_string() });
+            async move { health.check(request).await.map(|response| response.into_inner().status) }
+        };
+
+        assert_eq!(check("").await.unwrap(), ServingStatus::Serving as i32);
+        assert_eq!(check("orderbook.OrderbookService").await.unwrap(), ServingStatus::Serving as i32);
+        assert_eq!(check("other").await.unwrap_err().code(), tonic::Code::NotFound);
+
+        // A broadcast at capacity is about to drop updates
+        for market_id in 0..2 {
+            updates.send(MarketUpdate { market_id, sequence: 0, timestamp_ns: 0, deltas: Vec::new(), checksum: None, event_ms: None }).unwrap();
+        }
+        assert_eq!(check("").await.unwrap(), ServingStatus::NotServing as i32);
+        drop(rx);
+        assert_eq!(health.unhealthy_reasons().await, vec!["update broadcast has no receivers".to_string()]);
+    }
+}
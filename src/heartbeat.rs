@@ -0,0 +1,206 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::fast_orderbook::FastOrderbook;
+use crate::robust_order_processor::RobustOrderProcessor;
+
+/// Liveness artifact for supervisors that don't speak gRPC
+#[derive(Debug, Serialize)]
+pub struct Heartbeat {
+    pub timestamp_ms: u64,
+    pub pid: u32,
+    pub uptime_secs: u64,
+    pub data_path: String,
+    pub lines_read: u64,
+    pub bytes_read: u64,
+    pub markets: BTreeMap<u32, MarketHeartbeat>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarketHeartbeat {
+    pub symbol: String,
+    pub sequence: u64,
+    pub last_order_timestamp_ms: Option<u64>,
+    /// Wall clock minus the exchange timestamp of the last order
+    pub lag_ms: Option<u64>,
+    /// Time since the processor last handled an order for this market
+    pub idle_ms: Option<u64>,
+}
+
+pub struct HeartbeatWriter {
+    path: PathBuf,
+    interval: Duration,
+    started: Instant,
+    processor: Arc<RobustOrderProcessor>,
+    orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+}
+
+impl HeartbeatWriter {
+    pub fn new(
+        path: PathBuf,
+        interval: Duration,
+        processor: Arc<RobustOrderProcessor>,
+        orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+    ) -> Self {
+        Self {
+            path,
+            interval,
+            started: Instant::now(),
+            processor,
+            orderbooks,
+        }
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let progress = self.processor.progress();
+
+        let markets = self
+            .orderbooks
+            .iter()
+            .map(|(market_id, orderbook)| {
+                let market = progress.markets.get(market_id);
+                (
+                    *market_id,
+                    MarketHeartbeat {
+                        symbol: orderbook.symbol.clone(),
+                        sequence: orderbook.sequence.load(Ordering::Relaxed),
+                        last_order_timestamp_ms: market.map(|m| m.last_order_timestamp_ms),
+                        lag_ms: market.map(|m| now_ms.saturating_sub(m.last_order_timestamp_ms)),
+                        idle_ms: market.map(|m| now_ms.saturating_sub(m.processed_at_ms)),
+                    },
+                )
+            })
+            .collect();
+
+        Heartbeat {
+            timestamp_ms: now_ms,
+            pid: std::process::id(),
+            uptime_secs: self.started.elapsed().as_secs(),
+            data_path: progress.data_path,
+            lines_read: progress.lines_read,
+            bytes_read: progress.bytes_read,
+            markets,
+        }
+    }
+
+    /// Write one heartbeat, replacing the file atomically
+    pub fn write(&self) -> Result<()> {
+        let json = serde_json::to_vec_pretty(&self.heartbeat())?;
+        crate::event_log::write_atomic(&self.path, &json)
+    }
+
+    pub fn start(self) {
+        info!(
+            "Writing heartbeat to {} every {:?}",
+            self.path.display(),
+            self.interval
+        );
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.write() {
+                    warn!("Failed to write heartbeat {}: {}", self.path.display(), e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::Alerts;
+    use crate::book_actor::{ActorOptions, BookActors};
+    use crate::dynamic_markets::DynamicMarketRegistry;
+    use crate::hourly_path::HourlyLayout;
+    use crate::market_ids::MarketKey;
+    use crate::market_scheduler::{MarketScheduler, SchedulerConfig};
+    use crate::robust_order_processor::ProcessorConfig;
+    use crate::session_events::SessionEvents;
+    use crate::stop_orders::StopOrderManager;
+    use crate::symbology::TradableProduct;
+    use crate::user_activity::UserActivityTracker;
+    use crate::virtual_time::VirtualClock;
+    use crate::watermarks::WatermarkTracker;
+    use tokio::sync::broadcast;
+
+    const T0: u64 = 1_740_812_400_000;
+
+    fn status(oid: u64, side: &str, price: &str, timestamp: u64) -> String {
+        format!(
+            r#"{{"time":"2025-03-01T07:00:00.1","user":"0xabc","status":"open","order":{{"coin":"BTC","side":"{}","limitPx":"{}","sz":"1.0","oid":{},"timestamp":{},"isTrigger":false,"triggerCondition":"N/A"}}}}"#,
+            side, price, oid, timestamp
+        )
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_reports_offsets_and_lag() {
+        let registry = Arc::new(DynamicMarketRegistry::new());
+        let key = MarketKey::External { source: "capture".to_string(), symbol: "BTC".to_string() };
+        let btc = registry.register_market(key, TradableProduct::from_hyperliquid_coin("BTC"), 5).await.unwrap();
+        let idle = btc + 1;
+        let orderbooks: HashMap<u32, Arc<FastOrderbook>> = [(btc, "BTC"), (idle, "ETH")]
+            .into_iter()
+            .map(|(id, symbol)| (id, Arc::new(FastOrderbook::new(id, symbol.to_string()))))
+            .collect();
+
+        let clock = Arc::new(VirtualClock::new());
+        let actors = BookActors::spawn(
+            &orderbooks,
+            broadcast::channel(64).0,
+            Arc::new(WatermarkTracker::new(orderbooks.clone(), Duration::ZERO)),
+            Arc::new(MarketScheduler::new(SchedulerConfig::default())),
+            Arc::default(),
+            ActorOptions { clock: Some(clock.clone()), ..Default::default() },
+        );
+        let processor = Arc::new(RobustOrderProcessor::new(
+            ProcessorConfig::default(),
+            registry,
+            Arc::new(SessionEvents::new(orderbooks.clone(), HourlyLayout::new("/data"))),
+            Arc::new(UserActivityTracker::new(Duration::from_secs(10))),
+            Arc::new(Alerts::new()),
+        ));
+
+        let dir = std::env::temp_dir().join(format!("heartbeat_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let capture = dir.join("orders.log");
+        let feed = [status(1, "B", "100.0", T0), status(2, "A", "101.0", T0 + 250)].join("\n") + "\n";
+        std::fs::write(&capture, &feed).unwrap();
+        processor
+            .replay_capture(&capture, 0.0, &clock, &actors, &Arc::new(StopOrderManager::new()))
+            .await
+            .unwrap();
+
+        let path = dir.join("heartbeat.json");
+        let writer = HeartbeatWriter::new(path.clone(), Duration::from_secs(5), processor, orderbooks);
+        writer.write().unwrap();
+        let heartbeat: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+
+        assert_eq!(heartbeat["data_path"], capture.display().to_string());
+        assert_eq!(heartbeat["lines_read"], 2);
+        assert_eq!(heartbeat["bytes_read"], feed.len());
+
+        let market = &heartbeat["markets"][btc.to_string()];
+        assert_eq!(market["symbol"], "BTC");
+        assert_eq!(market["sequence"], 2);
+        assert_eq!(market["last_order_timestamp_ms"], T0 + 250);
+        // Lag is measured from the order's exchange time to the heartbeat
+        assert_eq!(market["lag_ms"].as_u64().unwrap(), heartbeat["timestamp_ms"].as_u64().unwrap() - (T0 + 250));
+
+        // A market without orders has no lag to report
+        let market = &heartbeat["markets"][idle.to_string()];
+        assert!(market["lag_ms"].is_null());
+        assert!(market["idle_ms"].is_null());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
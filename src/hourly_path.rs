@@ -0,0 +1,433 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::info;
+
+/// Default location of the node's hourly order status files
+pub const DEFAULT_HOURLY_DIR: &str = "/home/hluser/hl/data/node_order_statuses/hourly";
+
+/// Timezone the node uses to name hourly files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileTimezone {
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+impl FileTimezone {
+    fn naive(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            FileTimezone::Local => time.with_timezone(&Local).naive_local(),
+            FileTimezone::Utc => time.naive_utc(),
+            FileTimezone::Fixed(offset) => time.with_timezone(offset).naive_local(),
+        }
+    }
+}
+
+impl std::fmt::Display for FileTimezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileTimezone::Local => write!(f, "local"),
+            FileTimezone::Utc => write!(f, "utc"),
+            FileTimezone::Fixed(offset) => write!(f, "{}", offset),
+        }
+    }
+}
+
+impl FromStr for FileTimezone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "local" => Ok(FileTimezone::Local),
+            "utc" => Ok(FileTimezone::Utc),
+            other => other
+                .parse::<FixedOffset>()
+                .map(FileTimezone::Fixed)
+                .map_err(|_| anyhow!("Unknown timezone: {} (expected local, utc or an offset like +09:00)", s)),
+        }
+    }
+}
+
+/// How the hour component of the file name is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HourFormat {
+    /// `0` .. `23`
+    Unpadded,
+    /// `00` .. `23`
+    Padded,
+}
+
+impl HourFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HourFormat::Unpadded => "unpadded",
+            HourFormat::Padded => "padded",
+        }
+    }
+}
+
+impl FromStr for HourFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "unpadded" => Ok(HourFormat::Unpadded),
+            "padded" => Ok(HourFormat::Padded),
+            other => Err(anyhow!("Unknown hour format: {} (expected padded or unpadded)", other)),
+        }
+    }
+}
+
+/// One hourly file: its name relative to the root and when it starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HourFile {
+    pub name: String,
+    pub date: NaiveDate,
+    pub start: DateTime<Utc>,
+}
+
+/// Naming convention of the node's hourly files, `{root}/{YYYYMMDD}/{H}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HourlyLayout {
+    pub root: PathBuf,
+    pub timezone: FileTimezone,
+    pub hour_format: HourFormat,
+    /// How long after the top of the hour the node switches files
+    pub rollover_offset: Duration,
+}
+
+impl HourlyLayout {
+    /// Local time, unpadded hours, rollover on the hour
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            timezone: FileTimezone::Local,
+            hour_format: HourFormat::Unpadded,
+            rollover_offset: Duration::zero(),
+        }
+    }
+
+    /// Fill in whatever isn't configured by probing `root`, falling back to
+    /// the defaults of `new` when the directory doesn't tell
+    pub fn resolve(
+        root: impl Into<PathBuf>,
+        timezone: Option<FileTimezone>,
+        hour_format: Option<HourFormat>,
+        rollover_offset: Duration,
+    ) -> Self {
+        let mut layout = Self::new(root);
+        layout.rollover_offset = rollover_offset;
+
+        let probed = if timezone.is_none() || hour_format.is_none() {
+            probe(&layout.root, Utc::now(), rollover_offset)
+        } else {
+            Probe::default()
+        };
+        layout.timezone = timezone.or(probed.timezone).unwrap_or(layout.timezone);
+        layout.hour_format = hour_format.or(probed.hour_format).unwrap_or(layout.hour_format);
+
+        info!(
+            "Hourly files: {} in {} time, {} hours, rollover offset {}s{}",
+            layout.root.display(),
+            layout.timezone,
+            layout.hour_format.as_str(),
+            rollover_offset.num_seconds(),
+            probed.newest.map(|newest| format!(" (newest on disk: {})", newest)).unwrap_or_default()
+        );
+        layout
+    }
+
+    /// The file the node writes orders at `time` to
+    pub fn hour_of(&self, time: DateTime<Utc>) -> HourFile {
+        let naive = self.timezone.naive(time - self.rollover_offset);
+        let hour_start = naive.date().and_hms_opt(naive.hour(), 0, 0).unwrap();
+        let name = match self.hour_format {
+            HourFormat::Unpadded => format!("{}/{}", naive.format("%Y%m%d"), naive.hour()),
+            HourFormat::Padded => format!("{}/{:02}", naive.format("%Y%m%d"), naive.hour()),
+        };
+
+        HourFile {
+            name,
+            date: naive.date(),
+            start: time - (naive - hour_start),
+        }
+    }
+
+    pub fn hour_of_ms(&self, timestamp_ms: u64) -> Option<HourFile> {
+        Utc.timestamp_millis_opt(timestamp_ms as i64).single().map(|time| self.hour_of(time))
+    }
+
+    pub fn path_for(&self, time: DateTime<Utc>) -> PathBuf {
+        self.root.join(self.hour_of(time).name)
+    }
+
+    pub fn current_path(&self) -> PathBuf {
+        self.path_for(Utc::now())
+    }
+
+    /// Every hour of the node's current day up to and including the one
+    /// `now` falls in, oldest first
+    pub fn hours_so_far_today(&self, now: DateTime<Utc>) -> Vec<HourFile> {
+        let current = self.hour_of(now);
+        let mut hours = vec![current.clone()];
+        let mut time = current.start;
+        loop {
+            time -= Duration::hours(1);
+            let hour = self.hour_of(time);
+            if hour.date != current.date {
+                break;
+            }
+            // A repeated local hour when clocks go back is one file
+            if hours.last().map(|h: &HourFile| &h.name) != Some(&hour.name) {
+                hours.push(hour);
+            }
+        }
+        hours.reverse();
+        hours
+    }
+
+    /// Every hour from the one `since` falls in through the one `now` falls
+    /// in, oldest first
+    pub fn hours_since(&self, since: DateTime<Utc>, now: DateTime<Utc>) -> Vec<HourFile> {
+        let last = self.hour_of(now);
+        let mut hours: Vec<HourFile> = Vec::new();
+        let mut time = since;
+        while time <= now {
+            let hour = self.hour_of(time);
+            time = hour.start + Duration::hours(1);
+            if hours.last().map(|h| &h.name) != Some(&hour.name) {
+                hours.push(hour);
+            }
+        }
+        if hours.last() != Some(&last) {
+            hours.push(last);
+        }
+        hours
+    }
+
+    /// The latest hour file on disk, whatever the naming convention
+    pub fn newest_file(&self) -> Option<PathBuf> {
+        let date = digit_names(&self.root).into_iter().filter(|name| name.len() == 8).max()?;
+        let dir = self.root.join(date);
+        let hour = digit_names(&dir).into_iter().max_by_key(|hour| hour.parse::<u32>().unwrap_or(0))?;
+        Some(dir.join(hour))
+    }
+}
+
+/// When a reader moves from one hour's file to the next. Once the hour has
+/// changed, the old file is read until it goes quiet, so orders the node
+/// writes late to it aren't lost, and the new file is then read from its
+/// start.
+#[derive(Debug)]
+pub struct HourlyRollover {
+    path: PathBuf,
+    next: Option<PathBuf>,
+    quiet_for: std::time::Duration,
+    last_line: std::time::Instant,
+}
+
+impl HourlyRollover {
+    pub fn new(path: PathBuf, quiet_for: std::time::Duration) -> Self {
+        Self { path, next: None, quiet_for, last_line: std::time::Instant::now() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn line_read(&mut self, at: std::time::Instant) {
+        self.last_line = at;
+    }
+
+    /// The file to switch to, given the one the node writes now. Returned
+    /// once, after the current file has been quiet for long enough.
+    pub fn poll(&mut self, expected: PathBuf, now: std::time::Instant) -> Option<PathBuf> {
+        if self.next.is_none() && expected != self.path {
+            info!("Hour rolled over: draining {} before {}", self.path.display(), expected.display());
+            self.next = Some(expected);
+        }
+        if self.next.is_none() || now.duration_since(self.last_line) < self.quiet_for {
+            return None;
+        }
+        self.path = self.next.take()?;
+        self.last_line = now;
+        Some(self.path.clone())
+    }
+}
+
+/// What the files on disk say about the naming convention
+#[derive(Debug, Default)]
+struct Probe {
+    timezone: Option<FileTimezone>,
+    hour_format: Option<HourFormat>,
+    newest: Option<NaiveDateTime>,
+}
+
+fn digit_names(dir: &Path) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()))
+        .collect()
+}
+
+/// Look at the newest date directory: zero-padded or single-digit hour names
+/// give the format, and the newest hour file is matched against the current
+/// hour in each candidate timezone
+fn probe(root: &Path, now: DateTime<Utc>, rollover_offset: Duration) -> Probe {
+    let Some(date) = digit_names(root)
+        .iter()
+        .filter_map(|name| NaiveDate::parse_from_str(name, "%Y%m%d").ok())
+        .max()
+    else {
+        return Probe::default();
+    };
+
+    let hours = digit_names(&root.join(date.format("%Y%m%d").to_string()));
+    let hour_format = if hours.iter().any(|h| h.len() == 2 && h.starts_with('0')) {
+        Some(HourFormat::Padded)
+    } else if hours.iter().any(|h| h.len() == 1) {
+        Some(HourFormat::Unpadded)
+    } else {
+        None
+    };
+
+    let newest = hours
+        .iter()
+        .filter_map(|h| h.parse::<u32>().ok())
+        .filter(|h| *h < 24)
+        .max()
+        .and_then(|hour| date.and_hms_opt(hour, 0, 0));
+
+    // The node may not have opened the new hour's file yet, so the newest
+    // file can be one hour behind; ties keep local, the historical default
+    let timezone = newest.and_then(|newest| {
+        [FileTimezone::Local, FileTimezone::Utc]
+            .into_iter()
+            .map(|timezone| {
+                let naive = timezone.naive(now - rollover_offset);
+                let current = naive.date().and_hms_opt(naive.hour(), 0, 0).unwrap();
+                (timezone, (current - newest).num_hours())
+            })
+            .filter(|(_, behind)| (0..=1).contains(behind))
+            .min_by_key(|(_, behind)| *behind)
+            .map(|(timezone, _)| timezone)
+    });
+
+    Probe {
+        timezone,
+        hour_format,
+        newest,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_file_names_and_offsets() {
+        let mut layout = HourlyLayout::new("/data");
+        layout.timezone = FileTimezone::Utc;
+
+        let hour = layout.hour_of(utc(2025, 3, 1, 7, 30));
+        assert_eq!(hour.name, "20250301/7");
+        assert_eq!(hour.start, utc(2025, 3, 1, 7, 0));
+
+        layout.hour_format = HourFormat::Padded;
+        assert_eq!(layout.path_for(utc(2025, 3, 1, 7, 30)), PathBuf::from("/data/20250301/07"));
+
+        // A node 30 minutes behind UTC+9 rolls over at half past
+        layout.timezone = "+09:00".parse().unwrap();
+        layout.rollover_offset = Duration::minutes(30);
+        let hour = layout.hour_of(utc(2025, 3, 1, 15, 10));
+        assert_eq!(hour.name, "20250301/23");
+        assert_eq!(hour.start, utc(2025, 3, 1, 14, 30));
+        assert_eq!(layout.hour_of(utc(2025, 3, 1, 15, 40)).name, "20250302/00");
+    }
+
+    #[test]
+    fn test_probe_detects_format_and_current_file() {
+        let root = std::env::temp_dir().join(format!("hourly_path_{}", std::process::id()));
+        let now = Utc::now();
+        let local = HourlyLayout {
+            timezone: FileTimezone::Local,
+            hour_format: HourFormat::Padded,
+            ..HourlyLayout::new(&root)
+        };
+        let current = local.path_for(now);
+        std::fs::create_dir_all(current.parent().unwrap()).unwrap();
+        std::fs::write(&current, b"").unwrap();
+        // An older padded file decides the format even late in the day
+        std::fs::write(current.parent().unwrap().join("00"), b"").unwrap();
+
+        let probed = probe(&root, now, Duration::zero());
+        assert_eq!(probed.hour_format, Some(HourFormat::Padded));
+        assert!(probed.timezone.is_some());
+
+        let layout = HourlyLayout::resolve(&root, None, None, Duration::zero());
+        assert_eq!(layout.current_path(), current);
+
+        // Explicit settings win over the probe
+        let layout = HourlyLayout::resolve(&root, Some(FileTimezone::Utc), Some(HourFormat::Unpadded), Duration::zero());
+        assert_eq!(layout.hour_format, HourFormat::Unpadded);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_hours_so_far_today() {
+        let mut layout = HourlyLayout::new("/data");
+        layout.timezone = FileTimezone::Utc;
+        let names = |layout: &HourlyLayout, now| layout.hours_so_far_today(now).into_iter().map(|h| h.name).collect::<Vec<_>>();
+
+        assert_eq!(names(&layout, utc(2025, 3, 1, 2, 30)), vec!["20250301/0", "20250301/1", "20250301/2"]);
+        assert_eq!(names(&layout, utc(2025, 3, 1, 0, 5)), vec!["20250301/0"]);
+
+        // The node's day, not UTC's
+        layout.timezone = "+09:00".parse().unwrap();
+        let hours = layout.hours_so_far_today(utc(2025, 3, 1, 16, 30));
+        assert_eq!(hours.len(), 2);
+        assert_eq!(hours[0].name, "20250302/0");
+        assert_eq!(hours[0].start, utc(2025, 3, 1, 15, 0));
+    }
+
+    #[test]
+    fn test_hours_since() {
+        let mut layout = HourlyLayout::new("/data");
+        layout.timezone = FileTimezone::Utc;
+        let names = |since, now| layout.hours_since(since, now).into_iter().map(|h| h.name).collect::<Vec<_>>();
+
+        assert_eq!(names(utc(2025, 3, 1, 22, 59), utc(2025, 3, 2, 1, 0)), vec!["20250301/22", "20250301/23", "20250302/0", "20250302/1"]);
+        assert_eq!(names(utc(2025, 3, 1, 22, 10), utc(2025, 3, 1, 22, 50)), vec!["20250301/22"]);
+    }
+
+    #[test]
+    fn test_rollover_drains_before_switching() {
+        let quiet = std::time::Duration::from_secs(2);
+        let start = std::time::Instant::now();
+        let at = |secs| start + std::time::Duration::from_secs(secs);
+        let mut rollover = HourlyRollover::new(PathBuf::from("/data/20250301/7"), quiet);
+
+        assert_eq!(rollover.poll(PathBuf::from("/data/20250301/7"), at(5)), None);
+
+        // The hour changes while the old file is still being written
+        rollover.line_read(at(10));
+        assert_eq!(rollover.poll(PathBuf::from("/data/20250301/8"), at(11)), None);
+        rollover.line_read(at(11));
+        assert_eq!(rollover.poll(PathBuf::from("/data/20250301/8"), at(12)), None);
+
+        // Quiet long enough: switch once
+        assert_eq!(rollover.poll(PathBuf::from("/data/20250301/8"), at(13)), Some(PathBuf::from("/data/20250301/8")));
+        assert_eq!(rollover.path(), Path::new("/data/20250301/8"));
+        assert_eq!(rollover.poll(PathBuf::from("/data/20250301/8"), at(20)), None);
+    }
+}
@@ -0,0 +1,369 @@
+//! Addresses the service is exposed on. Each listener has its own transport
+//! and policy, so one process can offer an authenticated public TLS port
+//! next to an open localhost port or Unix socket.
+
+use anyhow::{anyhow, bail, Context, Result};
+use http::header::{HeaderName, HeaderValue};
+use http::Method;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::Server;
+use tonic_web::GrpcWebLayer;
+use tower::util::option_layer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{error, info};
+
+use crate::auth_interceptor::{ApiKeys, ListenerPolicy};
+use crate::flight_service::pb::flight_service_server::FlightServiceServer;
+use crate::flight_service::ArrowFlightService;
+use crate::grpc_server::DeltaStreamingService;
+use crate::health::pb::health_server::HealthServer;
+use crate::health::HealthService;
+use crate::reflection::pb::server_reflection_server::ServerReflectionServer;
+use crate::reflection::ReflectionService;
+use crate::shared_encoding::SharedEncodingRoutes;
+use crate::socket_tuning::SocketTuning;
+use crate::tls_config::ServerTls;
+
+/// Socket file permissions for Unix listeners that don't set `uds_mode`
+pub const DEFAULT_UDS_MODE: u32 = 0o660;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Require client certificates signed by this CA
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    /// How often the files are checked for renewed certificates; 0 never
+    #[serde(default = "default_tls_reload_secs")]
+    pub reload_secs: u64,
+}
+
+pub fn default_tls_reload_secs() -> u64 {
+    60
+}
+
+/// Browser access through gRPC-Web, without a translating proxy
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcWebConfig {
+    /// Origins pages may call from, e.g. "https://app.example.com"; empty
+    /// allows any
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// How long browsers may cache a preflight answer
+    #[serde(default = "default_cors_max_age_secs")]
+    pub max_age_secs: u64,
+}
+
+fn default_cors_max_age_secs() -> u64 {
+    24 * 60 * 60
+}
+
+impl GrpcWebConfig {
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        Self { allowed_origins, max_age_secs: default_cors_max_age_secs() }
+    }
+
+    /// CORS answering for the gRPC-Web headers browsers send and read,
+    /// including the API key
+    fn cors(&self) -> Result<CorsLayer> {
+        let origins = if self.allowed_origins.is_empty() {
+            AllowOrigin::mirror_request()
+        } else {
+            let origins = self
+                .allowed_origins
+                .iter()
+                .map(|origin| origin.parse().with_context(|| format!("invalid origin {}", origin)))
+                .collect::<Result<Vec<HeaderValue>>>()?;
+            AllowOrigin::list(origins)
+        };
+        Ok(CorsLayer::new()
+            .allow_origin(origins)
+            .allow_credentials(true)
+            .allow_methods([Method::POST])
+            .allow_headers(["x-grpc-web", "content-type", "x-user-agent", "grpc-timeout", "x-api-key"].map(HeaderName::from_static))
+            .expose_headers(["grpc-status", "grpc-message", "grpc-status-details-bin"].map(HeaderName::from_static))
+            .max_age(Duration::from_secs(self.max_age_secs)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Shown in logs
+    pub name: String,
+    /// `host:port`, `[v6 host]:port` or `unix:/path/to.sock`
+    pub address: String,
+    #[serde(default)]
+    pub tls: Option<TlsFiles>,
+    #[serde(default)]
+    pub require_auth: bool,
+    /// Calls and stream opens per API key per minute
+    #[serde(default)]
+    pub rate_limit_per_minute: Option<u32>,
+    /// HTTP/2 streams per connection
+    #[serde(default)]
+    pub max_concurrent_streams: Option<u32>,
+    /// Requests in flight per connection
+    #[serde(default)]
+    pub concurrency_limit_per_connection: Option<usize>,
+    /// Octal permissions of a Unix socket file, e.g. "660"
+    #[serde(default)]
+    pub uds_mode: Option<String>,
+    /// Also accept gRPC-Web over HTTP/1.1 and HTTP/2
+    #[serde(default)]
+    pub grpc_web: Option<GrpcWebConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ListenerConfig {
+    /// Plaintext listener without limits
+    pub fn plain(name: &str, address: String, require_auth: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            address,
+            tls: None,
+            require_auth,
+            rate_limit_per_minute: None,
+            max_concurrent_streams: None,
+            concurrency_limit_per_connection: None,
+            uds_mode: None,
+            grpc_web: None,
+        }
+    }
+
+    pub fn listen_address(&self) -> Result<ListenAddress> {
+        match self.address.strip_prefix("unix:") {
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => self
+                .address
+                .parse()
+                .map(ListenAddress::Tcp)
+                .with_context(|| format!("Listener {}: invalid address {}", self.name, self.address)),
+        }
+    }
+
+    fn uds_mode(&self) -> Result<u32> {
+        match &self.uds_mode {
+            Some(mode) => parse_octal_mode(mode).map_err(|e| anyhow!("Listener {}: {}", self.name, e)),
+            None => Ok(DEFAULT_UDS_MODE),
+        }
+    }
+}
+
+/// Listeners from a JSON array
+pub fn load(path: &Path) -> Result<Vec<ListenerConfig>> {
+    let listeners: Vec<ListenerConfig> = serde_json::from_str(&std::fs::read_to_string(path)?)
+        .with_context(|| format!("Invalid listeners file {}", path.display()))?;
+    validate(&listeners)?;
+    Ok(listeners)
+}
+
+fn validate(listeners: &[ListenerConfig]) -> Result<()> {
+    if listeners.is_empty() {
+        bail!("No listeners configured");
+    }
+    let mut names = HashSet::new();
+    let mut addresses = HashSet::new();
+    for listener in listeners {
+        if !names.insert(&listener.name) {
+            bail!("Duplicate listener name {}", listener.name);
+        }
+        if !addresses.insert(listener.listen_address()?) {
+            bail!("Listener {} reuses address {}", listener.name, listener.address);
+        }
+        listener.uds_mode()?;
+        if let Some(web) = &listener.grpc_web {
+            let _ = web.cors().with_context(|| format!("Listener {}", listener.name))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn parse_octal_mode(s: &str) -> std::result::Result<u32, String> {
+    u32::from_str_radix(s, 8).map_err(|e| format!("invalid octal mode {}: {}", s, e))
+}
+
+/// Bind a Unix domain socket, replacing a stale socket file from a previous run
+#[cfg(unix)]
+fn bind_uds(path: &Path, mode: u32) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a socket", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+/// Serve on every listener. All of them are bound before this returns, so a
+/// bad address or certificate fails startup. Each task ends when its
+/// listener stops. Health checks skip auth, so probes need no key;
+/// reflection has the listener's policy like the API it describes.
+pub fn serve(
+    listeners: &[ListenerConfig],
+    service: Arc<DeltaStreamingService>,
+    health: Arc<HealthService>,
+    reflection: Arc<ReflectionService>,
+    flight: Arc<ArrowFlightService>,
+    tuning: &SocketTuning,
+    api_keys: &ApiKeys,
+) -> Result<JoinSet<()>> {
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let policy = ListenerPolicy::new(api_keys.clone(), listener.require_auth, listener.rate_limit_per_minute);
+        let routes = InterceptedService::new(SharedEncodingRoutes::new(service.clone()), policy.clone());
+        let reflection_routes = InterceptedService::new(ServerReflectionServer::new(reflection.clone()), policy.clone());
+        let flight_routes = InterceptedService::new(FlightServiceServer::from_arc(flight.clone()), policy);
+
+        let mut builder = tuning.configure(Server::builder()).max_concurrent_streams(listener.max_concurrent_streams);
+        if let Some(limit) = listener.concurrency_limit_per_connection {
+            builder = builder.concurrency_limit_per_connection(limit);
+        }
+        // TLS is terminated by the listener's acceptor, so certificates can
+        // be renewed in place
+        let tls = match &listener.tls {
+            Some(files) => {
+                let tls = Arc::new(
+                    ServerTls::load(&listener.name, files, listener.grpc_web.is_some())
+                        .with_context(|| format!("Listener {}: loading TLS files", listener.name))?,
+                );
+                if files.reload_secs > 0 {
+                    tls.start_reloading(Duration::from_secs(files.reload_secs));
+                }
+                Some(tls)
+            }
+            None => None,
+        };
+        // gRPC-Web requests arrive over HTTP/1.1 as well as HTTP/2
+        let cors = listener.grpc_web.as_ref().map(GrpcWebConfig::cors).transpose()?;
+        let router = builder
+            .accept_http1(listener.grpc_web.is_some())
+            .layer(option_layer(cors))
+            .layer(option_layer(listener.grpc_web.as_ref().map(|_| GrpcWebLayer::new())))
+            .add_service(routes)
+            .add_service(reflection_routes)
+            .add_service(flight_routes)
+            .add_service(HealthServer::new(health.clone()));
+
+        info!(
+            "Starting gRPC listener {} on {} (tls {}, mtls {}, auth {}, rate limit {:?}/min, grpc-web {})",
+            listener.name,
+            listener.address,
+            listener.tls.is_some(),
+            listener.tls.as_ref().is_some_and(|tls| tls.client_ca.is_some()),
+            listener.require_auth,
+            listener.rate_limit_per_minute,
+            listener.grpc_web.is_some()
+        );
+        let name = listener.name.clone();
+        match listener.listen_address()? {
+            ListenAddress::Tcp(addr) => {
+                let incoming = tuning.bind(addr)?;
+                servers.spawn(async move {
+                    let served = match tls {
+                        Some(tls) => router.serve_with_incoming(tls.accept(incoming)).await,
+                        None => router.serve_with_incoming(incoming).await,
+                    };
+                    if let Err(e) = served {
+                        error!("gRPC listener {} error: {}", name, e);
+                    }
+                });
+            }
+            #[cfg(unix)]
+            ListenAddress::Unix(path) => {
+                let incoming = tokio_stream::wrappers::UnixListenerStream::new(bind_uds(&path, listener.uds_mode()?)?);
+                servers.spawn(async move {
+                    let served = match tls {
+                        Some(tls) => router.serve_with_incoming(tls.accept(incoming)).await,
+                        None => router.serve_with_incoming(incoming).await,
+                    };
+                    if let Err(e) = served {
+                        error!("gRPC listener {} error: {}", name, e);
+                    }
+                });
+            }
+            #[cfg(not(unix))]
+            ListenAddress::Unix(_) => bail!("Listener {}: Unix sockets are not supported here", listener.name),
+        }
+    }
+    Ok(servers)
+}
+
+/// Remove the socket files of Unix listeners
+pub fn cleanup(listeners: &[ListenerConfig]) {
+    for listener in listeners {
+        if let Ok(ListenAddress::Unix(path)) = listener.listen_address() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_validate() {
+        let listeners: Vec<ListenerConfig> = serde_json::from_str(
+            r#"[
+                {"name": "public", "address": "[::]:50052", "require_auth": true, "rate_limit_per_minute": 600,
+                 "tls": {"cert": "server.pem", "key": "server-key.pem"}},
+                {"name": "v4", "address": "0.0.0.0:50052"},
+                {"name": "local", "address": "unix:/run/orderbook.sock", "uds_mode": "600"}
+            ]"#,
+        )
+        .unwrap();
+        validate(&listeners).unwrap();
+
+        assert!(matches!(listeners[0].listen_address().unwrap(), ListenAddress::Tcp(addr) if addr.is_ipv6()));
+        assert_eq!(listeners[2].listen_address().unwrap(), ListenAddress::Unix("/run/orderbook.sock".into()));
+        assert_eq!(listeners[2].uds_mode().unwrap(), 0o600);
+        assert_eq!(listeners[1].uds_mode().unwrap(), DEFAULT_UDS_MODE);
+
+        let mut duplicate = listeners.clone();
+        duplicate[1].address = "[::]:50052".to_string();
+        assert!(validate(&duplicate).is_err());
+        let mut bad_mode = listeners.clone();
+        bad_mode[2].uds_mode = Some("rw".to_string());
+        assert!(validate(&bad_mode).is_err());
+        assert!(validate(&[]).is_err());
+    }
+
+    #[test]
+    fn test_grpc_web_config() {
+        let listeners: Vec<ListenerConfig> = serde_json::from_str(
+            r#"[
+                {"name": "browser", "address": "0.0.0.0:8443",
+                 "grpc_web": {"allowed_origins": ["https://app.example.com"]}},
+                {"name": "any", "address": "0.0.0.0:8444", "grpc_web": {}}
+            ]"#,
+        )
+        .unwrap();
+        validate(&listeners).unwrap();
+        assert_eq!(listeners[1].grpc_web, Some(GrpcWebConfig::new(Vec::new())));
+
+        let mut bad_origin = listeners.clone();
+        bad_origin[0].grpc_web = Some(GrpcWebConfig::new(vec!["https://app\nexample.com".to_string()]));
+        assert!(validate(&bad_origin).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+//! Load test of a running instance.
+//!
+//! Opens many `SubscribeOrderbook` streams at once, spread round-robin over
+//! the markets and over a few HTTP/2 connections, and reports what each one
+//! got: message rate, staleness and lag. Staleness is receive time minus the
+//! snapshot's timestamp, so it includes any clock offset between this host
+//! and the server; run it on the same host or a well-synced one.
+
+use anyhow::Result;
+use clap::Args;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Request;
+use tracing::{info, warn};
+
+use crate::grpc_server::pb::orderbook_service_client::OrderbookServiceClient;
+use crate::grpc_server::pb::SubscribeRequest;
+
+#[derive(Args, Debug, Clone)]
+pub struct LoadtestArgs {
+    /// Instance endpoint
+    #[arg(long, default_value = "http://127.0.0.1:50052")]
+    pub endpoint: String,
+
+    /// Concurrent subscriptions
+    #[arg(long, default_value = "100")]
+    pub streams: usize,
+
+    /// Market IDs the streams are spread over (comma-separated), one market per stream
+    #[arg(long, value_delimiter = ',', default_value = "0,1,5")]
+    pub markets: Vec<u32>,
+
+    /// HTTP/2 connections the streams are spread over
+    #[arg(long, default_value = "8")]
+    pub connections: usize,
+
+    /// Levels per side to subscribe to
+    #[arg(long, default_value = "20")]
+    pub depth: u32,
+
+    /// Lag policy of every stream: disconnect, flag_gap or snapshot_only
+    #[arg(long, default_value = "flag_gap")]
+    pub lag_policy: String,
+
+    /// How long to measure, after every stream has been opened
+    #[arg(long, default_value = "60")]
+    pub duration_secs: u64,
+
+    /// Slowest streams listed in the report
+    #[arg(long, default_value = "10")]
+    pub rows: usize,
+
+    /// API key sent as x-api-key
+    #[arg(long)]
+    pub api_key: Option<String>,
+}
+
+/// What one stream received
+#[derive(Debug, Default)]
+pub struct StreamStats {
+    pub stream: usize,
+    pub market_id: u32,
+    pub messages: u64,
+    /// Receive time minus snapshot timestamp, in microseconds
+    pub staleness_us: Vec<i64>,
+    /// Snapshots flagged as following lost updates
+    pub lags: u64,
+    pub missed_updates: u64,
+    /// Why the stream ended early
+    pub error: Option<String>,
+}
+
+impl StreamStats {
+    pub fn staleness_percentile(&self, percentile: f64) -> Option<i64> {
+        percentile_of(&self.staleness_us, percentile)
+    }
+}
+
+fn percentile_of(values: &[i64], percentile: f64) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+    Some(sorted[index])
+}
+
+/// Totals of a group of streams
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub streams: usize,
+    pub failed: usize,
+    pub messages: u64,
+    pub lags: u64,
+    /// Messages per second of the slowest, median and fastest stream
+    pub rates: Option<[f64; 3]>,
+    /// Staleness p50, p99 and max over every message, in microseconds
+    pub staleness_us: Option<[i64; 3]>,
+}
+
+impl Summary {
+    pub fn of<'a>(streams: impl IntoIterator<Item = &'a StreamStats>, elapsed: Duration) -> Self {
+        let mut summary = Summary::default();
+        let mut rates = Vec::new();
+        let mut staleness = Vec::new();
+        for stream in streams {
+            summary.streams += 1;
+            summary.failed += stream.error.is_some() as usize;
+            summary.messages += stream.messages;
+            summary.lags += stream.lags;
+            rates.push(stream.messages as f64 / elapsed.as_secs_f64().max(f64::EPSILON));
+            staleness.extend_from_slice(&stream.staleness_us);
+        }
+        if !rates.is_empty() {
+            rates.sort_by(f64::total_cmp);
+            summary.rates = Some([rates[0], rates[rates.len() / 2], rates[rates.len() - 1]]);
+        }
+        staleness.sort_unstable();
+        if let Some(max) = staleness.last() {
+            let at = |percentile: f64| staleness[((staleness.len() - 1) as f64 * percentile).round() as usize];
+            summary.staleness_us = Some([at(0.5), at(0.99), *max]);
+        }
+        summary
+    }
+}
+
+async fn stream(stream: usize, market_id: u32, channel: Channel, args: LoadtestArgs, deadline: Instant) -> StreamStats {
+    let mut stats = StreamStats { stream, market_id, ..Default::default() };
+    if let Err(e) = receive(&mut stats, channel, &args, deadline).await {
+        stats.error = Some(e.to_string());
+    }
+    stats
+}
+
+async fn receive(stats: &mut StreamStats, channel: Channel, args: &LoadtestArgs, deadline: Instant) -> Result<()> {
+    let mut client = OrderbookServiceClient::new(channel);
+    let mut request = Request::new(SubscribeRequest {
+        market_ids: vec![stats.market_id],
+        depth: args.depth,
+        lag_policy: args.lag_policy.clone(),
+        ..Default::default()
+    });
+    if let Some(key) = &args.api_key {
+        request.metadata_mut().insert("x-api-key", key.parse()?);
+    }
+    let mut updates = client.subscribe_orderbook(request).await?.into_inner();
+
+    loop {
+        let snapshot = match tokio::time::timeout_at(deadline, updates.next()).await {
+            Err(_) => return Ok(()),
+            Ok(None) => anyhow::bail!("stream ended"),
+            Ok(Some(snapshot)) => snapshot?,
+        };
+        let now_us = chrono::Utc::now().timestamp_micros();
+        stats.messages += 1;
+        stats.staleness_us.push(now_us - snapshot.timestamp);
+        if let Some(lag) = snapshot.lag {
+            stats.lags += 1;
+            stats.missed_updates += lag.missed_updates;
+        }
+    }
+}
+
+fn format_ms(us: i64) -> String {
+    format!("{:.2}", us as f64 / 1000.0)
+}
+
+fn print_summary(label: &str, summary: &Summary) {
+    let [slowest, median, fastest] = summary.rates.unwrap_or_default();
+    let staleness = summary.staleness_us.map(|s| s.map(format_ms)).unwrap_or_else(|| ["-"; 3].map(str::to_string));
+    println!(
+        "{:>8} {:>8} {:>7} {:>10} {:>9.1} {:>9.1} {:>9.1} {:>9} {:>9} {:>9} {:>7}",
+        label,
+        summary.streams,
+        summary.failed,
+        summary.messages,
+        slowest,
+        median,
+        fastest,
+        staleness[0],
+        staleness[1],
+        staleness[2],
+        summary.lags,
+    );
+}
+
+fn print_report(args: &LoadtestArgs, streams: &[StreamStats], elapsed: Duration) {
+    let total = Summary::of(streams, elapsed);
+    println!();
+    println!(
+        "{} streams on {} markets over {} connections to {} for {:.1}s",
+        streams.len(),
+        args.markets.len(),
+        args.connections,
+        args.endpoint,
+        elapsed.as_secs_f64()
+    );
+    println!(
+        "{} messages, {:.0}/s in total; msg/s is per stream, staleness in ms",
+        total.messages,
+        total.messages as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+    println!();
+    println!(
+        "{:>8} {:>8} {:>7} {:>10} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>7}",
+        "market", "streams", "failed", "msgs", "min msg/s", "p50 msg/s", "max msg/s", "p50 ms", "p99 ms", "max ms", "lags"
+    );
+    let mut by_market: BTreeMap<u32, Vec<&StreamStats>> = BTreeMap::new();
+    for stream in streams {
+        by_market.entry(stream.market_id).or_default().push(stream);
+    }
+    for (market_id, streams) in &by_market {
+        print_summary(&market_id.to_string(), &Summary::of(streams.iter().copied(), elapsed));
+    }
+    print_summary("all", &total);
+
+    let mut slowest: Vec<&StreamStats> = streams.iter().collect();
+    slowest.sort_by_key(|stream| std::cmp::Reverse(stream.staleness_percentile(0.99).unwrap_or(i64::MAX)));
+    println!();
+    println!("Slowest streams by p99 staleness:");
+    println!("{:>8} {:>8} {:>10} {:>9} {:>9} {:>7} {:>8}  error", "stream", "market", "msgs", "p50 ms", "p99 ms", "lags", "missed");
+    for stream in slowest.into_iter().take(args.rows) {
+        let staleness = |percentile| stream.staleness_percentile(percentile).map(format_ms).unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:>8} {:>8} {:>10} {:>9} {:>9} {:>7} {:>8}  {}",
+            stream.stream,
+            stream.market_id,
+            stream.messages,
+            staleness(0.5),
+            staleness(0.99),
+            stream.lags,
+            stream.missed_updates,
+            stream.error.as_deref().unwrap_or(""),
+        );
+    }
+}
+
+pub async fn run(args: LoadtestArgs) -> Result<()> {
+    if args.markets.is_empty() || args.streams == 0 || args.connections == 0 {
+        anyhow::bail!("loadtest needs at least one market, stream and connection");
+    }
+
+    let mut channels = Vec::with_capacity(args.connections);
+    for _ in 0..args.connections {
+        channels.push(Endpoint::from_shared(args.endpoint.clone())?.connect().await?);
+    }
+    info!("Opened {} connections to {}", channels.len(), args.endpoint);
+
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.duration_secs);
+    let mut tasks = JoinSet::new();
+    for i in 0..args.streams {
+        let market_id = args.markets[i % args.markets.len()];
+        let channel = channels[i % channels.len()].clone();
+        tasks.spawn(stream(i, market_id, channel, args.clone(), deadline));
+    }
+
+    let mut streams = Vec::with_capacity(args.streams);
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(stats) => streams.push(stats),
+            Err(e) => warn!("Stream task failed: {}", e),
+        }
+    }
+    streams.sort_unstable_by_key(|stream| stream.stream);
+
+    print_report(&args, &streams, started.elapsed());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_of_streams() {
+        let stream = |market_id, messages: u64, staleness_us: Vec<i64>, error: Option<&str>| StreamStats {
+            market_id,
+            messages,
+            staleness_us,
+            lags: (messages == 0) as u64,
+            error: error.map(str::to_string),
+            ..Default::default()
+        };
+        let streams = [
+            stream(0, 20, (1..=20).map(|ms| ms * 1000).collect(), None),
+            stream(0, 10, vec![500; 10], None),
+            stream(1, 0, vec![], Some("stream ended")),
+        ];
+
+        let summary = Summary::of(&streams, Duration::from_secs(10));
+        assert_eq!((summary.streams, summary.failed, summary.messages, summary.lags), (3, 1, 30, 1));
+        assert_eq!(summary.rates, Some([0.0, 1.0, 2.0]));
+        assert_eq!(summary.staleness_us, Some([6_000, 20_000, 20_000]));
+        assert_eq!(streams[0].staleness_percentile(0.5), Some(11_000));
+
+        let idle = Summary::of(&streams[2..], Duration::from_secs(10));
+        assert_eq!((idle.rates, idle.staleness_us), (Some([0.0; 3]), None));
+    }
+}
@@ -1,42 +1,120 @@
+// Handlers and the helpers they call return tonic's `Status`, which is
+// large; boxing it would only mean unboxing again at every RPC boundary.
+#![allow(clippy::result_large_err)]
+
 mod fast_orderbook;
 mod market_processor;
 mod grpc_server;
 mod types;
-mod markets;
 mod dynamic_markets;
 mod stop_orders;
 mod mark_price;
 mod mark_price_v2;
 mod oracle_client;
-// mod mark_price_service; // COMMENTED OUT DUE TO COMPILATION ERRORS
+mod mark_price_service;
 mod order_parser;
 mod robust_order_processor;
 mod hourly_file_monitor;
 mod per_market_circuit_breaker;
 mod symbology;
+mod market_tiers;
+mod event_log;
+mod heartbeat;
+mod order_flow_imbalance;
+mod position_pnl;
+mod feed_compare;
+mod subscription_templates;
+mod replay_cache;
+mod session_events;
+mod hourly_path;
+mod watermarks;
+mod size_normalization;
+mod socket_tuning;
+mod auth_interceptor;
+mod tls_config;
+mod listeners;
+mod alerts;
+mod mmap_reader;
+mod book_actor;
+mod user_activity;
+mod market_ids;
+mod market_scheduler;
+mod degradation;
+mod subscribers;
+mod monitor;
+mod proto_descriptors;
+mod resume_buffer;
+mod fills;
+mod snapshot_barrier;
+mod candles;
+mod node_format;
+mod book_metrics;
+mod memory_profile;
+mod publish_batching;
+mod stream_tee;
+mod oid_epochs;
+mod state_snapshot;
+mod quote_stuffing;
+mod health;
+mod reflection;
+mod rpc_cache;
+mod contagion;
+mod conformance_vectors;
+mod cex_feeds;
+mod open_interest;
+mod trigger_cascade;
+mod market_recorder;
+mod delta_wal;
+mod rest_gateway;
+mod flight_service;
+mod market_fanout;
+mod shared_encoding;
+mod file_wakeups;
+mod price_ticks;
+mod crossed_books;
+mod book_drift;
+mod market_lifecycle;
+mod service_health;
+mod file_rotation;
+mod parse_pool;
+mod book_shards;
+mod binary_format;
+mod pipeline_latency;
+mod loadtest;
+mod virtual_time;
 // mod robust_order_processor_v2; // TODO: Update to use DynamicMarketRegistry
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use fast_orderbook::FastOrderbook;
+use price_ticks::TickSize;
 use market_processor::MarketUpdate;
 use robust_order_processor::{RobustOrderProcessor, ProcessorConfig};
 use dynamic_markets::DynamicMarketRegistry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::broadcast;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::Command;
-use tonic::transport::Server;
 use tracing::{error, info, warn};
 
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Updates the broadcast holds before its slowest receiver lags
+const UPDATE_CHANNEL_CAPACITY: usize = 100000;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<SubCommand>,
+    
     #[arg(short, long, default_value = "50052")]
     grpc_port: u16,
     
-    /// Enable metrics endpoint
+    /// Serve parser and circuit breaker gauges for Prometheus at /metrics
     #[arg(long, default_value = "false")]
     enable_metrics: bool,
     
@@ -51,8 +129,455 @@ struct Args {
     /// API keys (comma-separated)
     #[arg(long)]
     api_keys: Option<String>,
+    
+    /// JSON object of API keys to their scopes and allowed markets, e.g.
+    /// {"key": {"scopes": ["read-only"], "markets": [0, 1]}}
+    #[arg(long)]
+    api_key_permissions: Option<std::path::PathBuf>,
+    
+    /// Streams one API key may have open at once; unlimited if unset
+    #[arg(long)]
+    max_streams_per_key: Option<usize>,
+    
+    /// Markets one API key may subscribe to across its open streams
+    #[arg(long)]
+    max_markets_per_key: Option<usize>,
+    
+    /// Tier for markets not listed in --hot-markets/--cold-markets (hot or cold)
+    #[arg(long, default_value = "hot")]
+    default_tier: market_tiers::MarketTier,
+    
+    /// Markets to serve at full depth in real time (comma-separated coins)
+    #[arg(long)]
+    hot_markets: Option<String>,
+    
+    /// Markets to serve as BBO-only 1s snapshots (comma-separated coins)
+    #[arg(long)]
+    cold_markets: Option<String>,
+    
+    /// Also serve gRPC on this Unix domain socket (for same-host consumers)
+    #[arg(long)]
+    uds_path: Option<std::path::PathBuf>,
+    
+    /// Persist each book's event log to this directory
+    #[arg(long)]
+    event_log_dir: Option<std::path::PathBuf>,
+    
+    /// Directory StartTee writes subscriber recordings to (default: the
+    /// system temp dir)
+    #[arg(long)]
+    tee_dir: Option<std::path::PathBuf>,
+    
+    /// Seconds between event log persists
+    #[arg(long, default_value = "60")]
+    event_log_interval_secs: u64,
+    
+    /// Serve books restored from the event logs in this directory (as
+    /// written by --event-log-dir) without reading from a node
+    #[arg(long)]
+    archive_only: Option<std::path::PathBuf>,
+    
+    /// Feed this captured node order-status file through the pipeline in
+    /// virtual time instead of following the node, then keep serving the
+    /// final books
+    #[arg(long = "replay", value_name = "FILE", conflicts_with = "archive_only")]
+    replay_file: Option<std::path::PathBuf>,
+    
+    /// Replay speed as a multiple of the capture's pace; 0 replays as fast
+    /// as the books apply it
+    #[arg(long = "speed", default_value = "1.0", requires = "replay_file")]
+    replay_speed: f64,
+    
+    /// Order flow imbalance horizons in milliseconds (comma-separated)
+    #[arg(long, default_value = "100,1000,10000", value_delimiter = ',')]
+    ofi_horizons_ms: Vec<u64>,
+    
+    /// Milliseconds between mark price computations
+    #[arg(long, default_value = "1000")]
+    mark_price_interval_ms: u64,
+    
+    /// Stream Binance, OKX, Bybit, Gate and MEXC perp prices into the mark
+    /// price's CEX median (live mode only)
+    #[arg(long, default_value = "false")]
+    cex_feeds: bool,
+    
+    /// Milliseconds after which a venue's CEX price is left out as stale
+    #[arg(long, default_value = "10000")]
+    cex_max_age_ms: u64,
+    
+    /// Correlated market groups to watch for contagion, e.g.
+    /// `majors=BTC,ETH,SOL;l2=ARB,OP`
+    #[arg(long)]
+    contagion_groups: Option<String>,
+    
+    /// A spread this many times its baseline counts as a blowout
+    #[arg(long, default_value = "3.0")]
+    contagion_spread_factor: f64,
+    
+    /// Depth near the mid under this fraction of its baseline counts as a
+    /// collapse
+    #[arg(long, default_value = "0.3")]
+    contagion_depth_fraction: f64,
+    
+    /// Stressed markets of one group that raise a contagion alert
+    #[arg(long, default_value = "2")]
+    contagion_min_markets: usize,
+    
+    /// Milliseconds within which their stress counts as simultaneous
+    #[arg(long, default_value = "5000")]
+    contagion_window_ms: u64,
+    
+    /// Write a JSON heartbeat (offsets, per-market lag) to this path
+    #[arg(long)]
+    heartbeat_path: Option<std::path::PathBuf>,
+    
+    /// Seconds between heartbeat writes
+    #[arg(long, default_value = "5")]
+    heartbeat_interval_secs: u64,
+    
+    /// JSON file of watched positions: [{"user", "coin", "size", "entry_price"}]
+    #[arg(long)]
+    positions_file: Option<std::path::PathBuf>,
+    
+    /// Longest history a new subscriber may request with replay_ms
+    #[arg(long, default_value = "2000")]
+    replay_window_ms: u64,
+    
+    /// Updates kept per market for subscribers resuming from a sequence
+    #[arg(long, default_value = "1024")]
+    resume_buffer_updates: usize,
+    
+    /// JSON file of named subscription templates for SubscribeByTemplate
+    #[arg(long)]
+    templates_file: Option<std::path::PathBuf>,
+    
+    /// Socket tuning preset: default, lan-hft or wan-analytics
+    #[arg(long, default_value = "default")]
+    socket_preset: socket_tuning::SocketPreset,
+    
+    /// Override TCP_NODELAY from the preset
+    #[arg(long)]
+    tcp_nodelay: Option<bool>,
+    
+    /// Override SO_SNDBUF in bytes
+    #[arg(long)]
+    so_sndbuf: Option<usize>,
+    
+    /// Override SO_RCVBUF in bytes
+    #[arg(long)]
+    so_rcvbuf: Option<usize>,
+    
+    /// Override the TCP keepalive interval
+    #[arg(long)]
+    tcp_keepalive_secs: Option<u64>,
+    
+    /// Override the HTTP/2 keepalive ping interval
+    #[arg(long)]
+    http2_keepalive_interval_secs: Option<u64>,
+    
+    /// Override the HTTP/2 keepalive ping timeout
+    #[arg(long)]
+    http2_keepalive_timeout_secs: Option<u64>,
+    
+    /// Override the HTTP/2 initial stream window in bytes
+    #[arg(long)]
+    http2_stream_window: Option<u32>,
+    
+    /// Override the HTTP/2 initial connection window in bytes
+    #[arg(long)]
+    http2_connection_window: Option<u32>,
+    
+    /// Override HTTP/2 adaptive flow control
+    #[arg(long)]
+    http2_adaptive_window: Option<bool>,
+    
+    /// Directory of the node's hourly order status files
+    #[arg(long, default_value = hourly_path::DEFAULT_HOURLY_DIR)]
+    hourly_dir: std::path::PathBuf,
+    
+    /// Directory of the node's hourly fill files, named like the order files
+    #[arg(long, default_value = fills::DEFAULT_FILLS_DIR)]
+    fills_dir: std::path::PathBuf,
+    
+    /// Don't read fills (no last trade prices, SubscribeTrades or open interest)
+    #[arg(long, default_value = "false")]
+    disable_fills: bool,
+    
+    /// Seconds between open interest baselines from the Hyperliquid API;
+    /// fills move it in between
+    #[arg(long, default_value = "300")]
+    open_interest_baseline_secs: u64,
+    
+    /// Candles kept per market, source and interval
+    #[arg(long, default_value = "1000")]
+    candle_history: usize,
+    
+    /// Timezone of hourly file names: local, utc or an offset like +09:00
+    /// (probed from --hourly-dir when unset)
+    #[arg(long, allow_hyphen_values = true)]
+    hourly_timezone: Option<hourly_path::FileTimezone>,
+    
+    /// Hour component of file names: padded (07) or unpadded (7)
+    /// (probed from --hourly-dir when unset)
+    #[arg(long)]
+    hourly_hour_format: Option<hourly_path::HourFormat>,
+    
+    /// Feed format: statuses (one per line) or statuses-by-block
+    /// (detected from --hourly-dir when unset)
+    #[arg(long)]
+    node_format: Option<node_format::NodeFormat>,
+    
+    /// Seconds after the top of the hour at which the node switches files
+    #[arg(long, default_value = "0", allow_hyphen_values = true)]
+    hourly_rollover_offset_secs: i64,
+    
+    /// Rebuild the books from the day's earlier hourly files (read from
+    /// --hourly-dir on this host) before following the current one
+    #[arg(long, default_value = "false")]
+    warm_start: bool,
+    
+    /// Threads parsing the node feed. Above 1, lines already written are
+    /// read in batches and parsed in parallel, then applied in feed order.
+    #[arg(long, default_value = "1")]
+    parse_workers: usize,
+    
+    /// Snapshot every book with the feed position it reflects to this
+    /// directory, and resume from the last snapshot on startup
+    #[arg(long)]
+    state_dir: Option<std::path::PathBuf>,
+    
+    /// Seconds between state snapshots
+    #[arg(long, default_value = "10")]
+    state_interval_secs: u64,
+    
+    /// Shell command replicating state snapshots off this host, run on a
+    /// staged copy with `{dir}` replaced by its path, e.g.
+    /// `aws s3 sync {dir} s3://bucket/orderbook`
+    #[arg(long)]
+    state_replicate_cmd: Option<String>,
+    
+    /// Seconds between state replications
+    #[arg(long, default_value = "60")]
+    state_replicate_interval_secs: u64,
+    
+    /// Shell command fetching replicated state into `{dir}` when --state-dir
+    /// has no snapshot, e.g. `aws s3 sync s3://bucket/orderbook {dir}`
+    #[arg(long)]
+    state_bootstrap_cmd: Option<String>,
+    
+    /// Report not serving on the gRPC health service once the market
+    /// registry hasn't refreshed for this many seconds
+    #[arg(long, default_value = "900")]
+    health_max_registry_age_secs: u64,
+    
+    /// Milliseconds between watermark messages
+    #[arg(long, default_value = "1000")]
+    watermark_interval_ms: u64,
+    
+    /// How far behind the newest applied order timestamp the watermark
+    /// trails, to absorb out-of-order node writes
+    #[arg(long, default_value = "250")]
+    watermark_lateness_ms: u64,
+    
+    /// Seconds of order history behind per-user activity metrics
+    #[arg(long, default_value = "300")]
+    user_activity_window_secs: u64,
+    
+    /// Detect quote stuffing and tag flagged orders in SubscribeL3 (tag),
+    /// leave them out of book metrics (exclude) or out of SubscribeBBO
+    /// (suppress)
+    #[arg(long)]
+    stuffing_filter: Option<quote_stuffing::StuffingAction>,
+    
+    /// Longest open-to-cancel time at one price that counts as a stuffing
+    /// cycle
+    #[arg(long, default_value = "100")]
+    stuffing_max_lifetime_ms: u64,
+    
+    /// Stuffing cycles by a user at one price within a second that flag
+    /// their next orders there
+    #[arg(long, default_value = "5")]
+    stuffing_burst: u32,
+    
+    /// Processing time each market's book may use per scheduling slice, in
+    /// microseconds (0 = unlimited)
+    #[arg(long, default_value = "25000")]
+    market_budget_us: u64,
+    
+    /// Length of one scheduling slice
+    #[arg(long, default_value = "100")]
+    market_budget_interval_ms: u64,
+    
+    /// Queued commands per extra budget granted to a lagging market
+    #[arg(long, default_value = "1024")]
+    market_budget_boost_backlog: usize,
+    
+    /// Most budgets a lagging market can be granted per slice
+    #[arg(long, default_value = "4")]
+    market_budget_max_boost: u32,
+    
+    /// Hold each book's deltas this long after the first before publishing
+    /// them as one update, in microseconds (0 = publish every batch)
+    #[arg(long, default_value = "0")]
+    batch_window_us: u64,
+    
+    /// Per-market batching windows overriding --batch-window-us
+    /// (comma-separated COIN=MICROS)
+    #[arg(long)]
+    batch_window_markets: Option<String>,
+    
+    /// Crossed orders a book evicts when its best bid reaches its best ask:
+    /// off, older (whichever rested first), bids or asks
+    #[arg(long, default_value = "older")]
+    crossed_book_repair: crossed_books::CrossRepair,
+    
+    /// Run book actors on this many dedicated threads, each pinned to a
+    /// core, with markets assigned by a consistent hash of their id
+    /// (0 = tasks on the shared runtime)
+    #[arg(long, default_value = "0")]
+    book_shards: usize,
+    
+    /// Time each open order from its node timestamp to applied, published
+    /// and sent, for GetLatencyStats and /metrics
+    #[arg(long, default_value = "false")]
+    latency_stats: bool,
+    
+    /// Cross-check books against Hyperliquid's public L2 API this often, in
+    /// seconds (0 = off; live mode only)
+    #[arg(long, default_value = "0")]
+    book_drift_interval_secs: u64,
+    
+    /// Coins to cross-check (comma-separated); all Hyperliquid markets if unset
+    #[arg(long)]
+    book_drift_markets: Option<String>,
+    
+    /// Size drift of a level, in bps, beyond which a check is divergent
+    #[arg(long, default_value = "50")]
+    book_drift_threshold_bps: f64,
+    
+    /// Book backlog (queued commands) that counts as overload; 0 ignores it
+    #[arg(long, default_value = "4096")]
+    degrade_max_backlog: usize,
+    
+    /// Updates a subscriber stream may fall behind before it counts as
+    /// overload; 0 ignores it
+    #[arg(long, default_value = "10000")]
+    degrade_max_fanout_lag: usize,
+    
+    /// Share of a second a market may be held back by its budget before it
+    /// counts as overload; 0 ignores it
+    #[arg(long, default_value = "0.5")]
+    degrade_max_throttled_share: f64,
+    
+    /// Snapshot interval for best-effort streams while degraded
+    #[arg(long, default_value = "250")]
+    degrade_conflation_ms: u64,
+    
+    /// Permissions for the Unix domain socket file (octal)
+    #[arg(long, default_value = "660", value_parser = listeners::parse_octal_mode)]
+    uds_mode: u32,
+    
+    /// JSON list of listeners, each with its own address, TLS, auth and
+    /// limits; replaces --grpc-port and --uds-path
+    #[arg(long)]
+    listeners_file: Option<std::path::PathBuf>,
+    
+    /// JSON config for recording deltas, snapshots, trades and mark prices
+    /// to partitioned Parquet files: {"dir", "streams", "compression", ...}
+    #[arg(long)]
+    recorder_config: Option<std::path::PathBuf>,
+    
+    /// Directory for a per-market write-ahead log of every delta, served by
+    /// ReplayDeltas
+    #[arg(long)]
+    wal_dir: Option<std::path::PathBuf>,
+    
+    /// Size at which a WAL segment is closed and a new one started
+    #[arg(long, default_value = "64")]
+    wal_segment_mb: u64,
+    
+    /// Delete WAL segments older than this; 0 keeps them all
+    #[arg(long, default_value = "24")]
+    wal_retention_hours: u64,
+    
+    /// Serve GetOrderbook, GetMarkets, GetMarkPrice and GetStopOrders as
+    /// JSON over HTTP on this port, with --require-auth applied
+    #[arg(long)]
+    rest_port: Option<u16>,
+    
+    /// Accept gRPC-Web from browsers on the TCP port, without a proxy
+    #[arg(long, default_value = "false")]
+    grpc_web: bool,
+    
+    /// Comma-separated origins allowed to call over gRPC-Web; any if unset
+    #[arg(long)]
+    grpc_web_origins: Option<String>,
+    
+    /// PEM certificate chain to serve TLS with on the TCP port
+    #[arg(long, requires = "tls_key", conflicts_with = "listeners_file")]
+    tls_cert: Option<std::path::PathBuf>,
+    
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<std::path::PathBuf>,
+    
+    /// PEM CA certificates; clients must present a certificate signed by one
+    #[arg(long, requires = "tls_cert")]
+    tls_ca: Option<std::path::PathBuf>,
+    
+    /// How often the TLS files are checked for renewed certificates; 0 never
+    #[arg(long, default_value_t = listeners::default_tls_reload_secs())]
+    tls_reload_secs: u64,
 }
 
+/// Socket tuning from the preset with individual overrides applied
+fn socket_tuning(args: &Args) -> socket_tuning::SocketTuning {
+    let mut tuning = socket_tuning::SocketTuning::preset(args.socket_preset);
+    let secs = |s: Option<u64>| s.map(tokio::time::Duration::from_secs);
+    
+    if let Some(nodelay) = args.tcp_nodelay {
+        tuning.tcp_nodelay = nodelay;
+    }
+    tuning.send_buffer_bytes = args.so_sndbuf.or(tuning.send_buffer_bytes);
+    tuning.recv_buffer_bytes = args.so_rcvbuf.or(tuning.recv_buffer_bytes);
+    tuning.tcp_keepalive = secs(args.tcp_keepalive_secs).or(tuning.tcp_keepalive);
+    tuning.http2_keepalive_interval = secs(args.http2_keepalive_interval_secs).or(tuning.http2_keepalive_interval);
+    tuning.http2_keepalive_timeout = secs(args.http2_keepalive_timeout_secs).or(tuning.http2_keepalive_timeout);
+    tuning.initial_stream_window_size = args.http2_stream_window.or(tuning.initial_stream_window_size);
+    tuning.initial_connection_window_size = args.http2_connection_window.or(tuning.initial_connection_window_size);
+    tuning.http2_adaptive_window = args.http2_adaptive_window.or(tuning.http2_adaptive_window);
+    
+    tuning
+}
+
+#[derive(Subcommand, Debug)]
+enum SubCommand {
+    /// Run the service on the live node feed (the default)
+    #[command(alias = "serve-realtime")]
+    Serve,
+    /// Run the service on books restored from event logs, like --archive-only
+    Replay {
+        /// Event log directory, as written by --event-log-dir
+        dir: std::path::PathBuf,
+    },
+    /// Run the service recording to Parquet, like --recorder-config
+    Record {
+        /// Recorder JSON config
+        config: std::path::PathBuf,
+    },
+    /// Compare latency, completeness and book agreement of two running instances
+    Compare(feed_compare::CompareArgs),
+    /// Open many concurrent subscriptions to a running instance and report their message rates and staleness
+    Loadtest(loadtest::LoadtestArgs),
+    /// Terminal dashboard of a running instance: rates, lag, depth, circuits, subscribers, alerts
+    Monitor(monitor::MonitorArgs),
+    /// Write the v1 and v2 proto files and the v2 descriptor set for client code generation
+    ExportProtos(proto_descriptors::ExportArgs),
+    /// Write the book reconstruction conformance vectors as JSON
+    ExportConformanceVectors(conformance_vectors::ExportArgs),
+    /// Print the messages a StartTee file recorded, with when each was sent
+    TeeDump(stream_tee::DumpArgs),
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -63,7 +588,33 @@ async fn main() -> Result<()> {
         .with_level(true)
         .init();
 
-    let args = Args::parse();
+    let mut args = Args::parse();
+    
+    // Service modes share every flag; they only preset one
+    match args.command.take() {
+        Some(SubCommand::Compare(compare_args)) => return feed_compare::run(compare_args).await,
+        Some(SubCommand::Loadtest(loadtest_args)) => return loadtest::run(loadtest_args).await,
+        Some(SubCommand::Monitor(monitor_args)) => return monitor::run(monitor_args).await,
+        Some(SubCommand::ExportProtos(export_args)) => return proto_descriptors::run(export_args),
+        Some(SubCommand::ExportConformanceVectors(export_args)) => return conformance_vectors::run_export(export_args),
+        Some(SubCommand::TeeDump(dump_args)) => return stream_tee::run(dump_args),
+        Some(SubCommand::Replay { dir }) => {
+            if args.archive_only.is_some() {
+                anyhow::bail!("replay takes the archive directory; drop --archive-only");
+            }
+            if args.replay_file.is_some() {
+                anyhow::bail!("replay restores event logs and --replay feeds a capture; use one");
+            }
+            args.archive_only = Some(dir);
+        }
+        Some(SubCommand::Record { config }) => {
+            if args.recorder_config.is_some() {
+                anyhow::bail!("record takes the recorder config; drop --recorder-config");
+            }
+            args.recorder_config = Some(config);
+        }
+        Some(SubCommand::Serve) | None => {}
+    }
 
     info!("Starting real-time orderbook service");
     info!("gRPC port: {}", args.grpc_port);
@@ -72,6 +623,7 @@ async fn main() -> Result<()> {
 
     // Initialize dynamic market registry
     let market_registry = Arc::new(DynamicMarketRegistry::new());
+    let registry_refresh_rx = market_registry.subscribe_refreshes();
     market_registry.refresh_markets().await?;
     let market_count = market_registry.market_count().await;
     info!("Loaded {} active markets from Hyperliquid", market_count);
@@ -85,61 +637,247 @@ async fn main() -> Result<()> {
     info!("Tracking {} markets", market_configs.len());
 
     // Create broadcast channel for updates
-    let (update_tx, update_rx) = broadcast::channel::<MarketUpdate>(100000);
+    let (update_tx, update_rx) = broadcast::channel::<MarketUpdate>(UPDATE_CHANNEL_CAPACITY);
 
-    // Create orderbooks
+    // Create orderbooks, keyed by tick on each market's price grid
+    let tick_sizes = market_registry.get_tick_sizes().await;
     let mut orderbooks = HashMap::new();
     for (market_id, symbol) in &market_configs {
-        let orderbook = Arc::new(FastOrderbook::new(*market_id, symbol.clone()));
+        let tick_size = tick_sizes.get(market_id).and_then(|size| TickSize::new(*size)).unwrap_or_default();
+        let orderbook = Arc::new(FastOrderbook::with_tick_size(*market_id, symbol.clone(), tick_size));
         orderbooks.insert(*market_id, orderbook);
     }
     
+    // Archive-only: books come from persisted event logs and nothing reads the node.
+    // Replay: books come from a captured feed, in virtual time.
+    let live = args.archive_only.is_none() && args.replay_file.is_none();
+    let replay_clock = args.replay_file.is_some().then(|| Arc::new(virtual_time::VirtualClock::new()));
+    if let Some(dir) = &args.archive_only {
+        let restored = event_log::restore_books(dir, &orderbooks)?;
+        info!("Archive-only mode: restored {} of {} books from {}", restored, orderbooks.len(), dir.display());
+        if args.event_log_dir.is_some() {
+            warn!("Ignoring --event-log-dir in archive-only mode");
+        }
+    }
+    
+    // Books restored from the last state snapshot resume where it had read to
+    let (state_snapshots, resume) = match args.state_dir.clone().filter(|_| live) {
+        Some(dir) => {
+            let interval = tokio::time::Duration::from_secs(args.state_interval_secs.max(1));
+            let mut snapshots = state_snapshot::StateSnapshots::new(dir, interval, orderbooks.clone())?;
+            if let Some(command) = &args.state_bootstrap_cmd {
+                if let Err(e) = snapshots.bootstrap(command) {
+                    warn!("Starting without replicated state: {}", e);
+                }
+            }
+            if let Some(command) = args.state_replicate_cmd.clone() {
+                let every = tokio::time::Duration::from_secs(args.state_replicate_interval_secs.max(1));
+                snapshots = snapshots.with_replication(command, every);
+            }
+            let resume = snapshots.restore()?;
+            if resume.is_some() && args.warm_start {
+                info!("Resuming from the state snapshot instead of a warm start");
+            }
+            (Some(snapshots), resume)
+        }
+        None => (None, None),
+    };
+    
+    // Periodically persist compacted event logs
+    if let Some(dir) = args.event_log_dir.clone().filter(|_| live) {
+        std::fs::create_dir_all(&dir)?;
+        info!("Persisting event logs to {} every {}s", dir.display(), args.event_log_interval_secs);
+        
+        let orderbooks_for_log = orderbooks.clone();
+        let persist_interval = tokio::time::Duration::from_secs(args.event_log_interval_secs.max(1));
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(persist_interval);
+            loop {
+                interval.tick().await;
+                for (market_id, orderbook) in &orderbooks_for_log {
+                    let path = event_log::log_path(&dir, *market_id);
+                    // Encode under the lock, write outside it
+                    let encoded = orderbook.event_log().lock().encode();
+                    if let Err(e) = encoded.and_then(|bytes| event_log::write_atomic(&path, &bytes)) {
+                        warn!("Failed to persist event log for market {}: {}", market_id, e);
+                    }
+                }
+            }
+        });
+    }
+    
+    // Log every update before processing starts so none are missed
+    let delta_wal = match args.wal_dir.clone().filter(|_| live) {
+        Some(dir) => {
+            let wal = Arc::new(delta_wal::DeltaWal::new(delta_wal::WalConfig {
+                dir,
+                segment_bytes: args.wal_segment_mb.max(1) << 20,
+                retention: (args.wal_retention_hours > 0).then(|| std::time::Duration::from_secs(args.wal_retention_hours * 3600)),
+            })?);
+            wal.start(update_tx.subscribe(), orderbooks.clone())?;
+            Some(wal)
+        }
+        None => None,
+    };
+    
+    // Assign market tiers (switchable at runtime via SetMarketTier)
+    let market_tiers = Arc::new(market_tiers::MarketTiers::new(args.default_tier));
+    for (coins, tier) in [
+        (&args.hot_markets, market_tiers::MarketTier::Hot),
+        (&args.cold_markets, market_tiers::MarketTier::Cold),
+    ] {
+        for coin in coins.iter().flat_map(|c| c.split(',')).map(str::trim).filter(|c| !c.is_empty()) {
+            match market_registry.get_market_id(coin).await {
+                Some(market_id) => {
+                    market_tiers.set_tier(market_id, tier);
+                }
+                None => warn!("Unknown market in tier config: {}", coin),
+            }
+        }
+    }
+    for orderbook in orderbooks.values() {
+        market_tiers.apply_retention(orderbook);
+    }
+    info!(
+        "Market tiers: default {}, {} overrides",
+        args.default_tier.as_str(),
+        market_tiers.overrides().len()
+    );
+    
     // Create stop order manager
     let stop_order_manager = Arc::new(stop_orders::StopOrderManager::new());
     
-    // Create oracle client and start feed
-    let oracle_client = Arc::new(oracle_client::OracleClient::new());
-    oracle_client.start_oracle_feed(tokio::time::Duration::from_secs(3)).await;
-    info!("Started oracle price feed (updates every 3 seconds)");
-
     // Get current hour for the data file
-    let hour_str = chrono::Local::now().format("%H").to_string();
-    let hour = hour_str.trim_start_matches('0');
-    let date = chrono::Local::now().format("%Y%m%d").to_string();
-    let data_path = format!("/home/hluser/hl/data/node_order_statuses/hourly/{}/{}", date, hour);
-
-    info!("Reading real-time orders from: {}", data_path);
-
-    // Spawn oracle price updater
-    let orderbooks_for_oracle = orderbooks.clone();
-    let oracle_client_clone = oracle_client.clone();
-    let market_configs_clone = market_configs.clone();
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3));
-        loop {
-            interval.tick().await;
-            
-            // Get all oracle prices
-            let prices = oracle_client_clone.get_all_cached_prices().await;
-            
-            // Update each orderbook with its oracle price
-            for (market_id, orderbook) in &orderbooks_for_oracle {
-                if let Some(symbol) = market_configs_clone.get(market_id) {
-                    // Extract base currency from TradableProduct format (e.g., "BTC/USD" -> "BTC")
-                    let base_currency = if symbol.contains('/') {
-                        symbol.split('/').next().unwrap_or(symbol)
-                    } else {
-                        symbol
-                    };
-                    
-                    if let Some(oracle_price) = prices.get(base_currency) {
-                        orderbook.update_oracle_price(*oracle_price);
-                        log::debug!("{} oracle price updated: ${:.2}", symbol, oracle_price);
+    let hourly_layout = hourly_path::HourlyLayout::resolve(
+        &args.hourly_dir,
+        args.hourly_timezone,
+        args.hourly_hour_format,
+        chrono::Duration::seconds(args.hourly_rollover_offset_secs),
+    );
+    let data_path = hourly_layout.current_path().display().to_string();
+    let feed_format = match &args.replay_file {
+        Some(path) => node_format::detect_file(path, args.node_format),
+        None => node_format::detect(&hourly_layout, args.node_format),
+    };
+
+    // Event-time watermarks derived from ingestion progress
+    let watermarks = Arc::new(watermarks::WatermarkTracker::new(
+        orderbooks.clone(),
+        tokio::time::Duration::from_millis(args.watermark_lateness_ms),
+    ));
+    watermarks.clone().start(tokio::time::Duration::from_millis(args.watermark_interval_ms.max(1)));
+    
+    // Per-market processing budgets, so busy books can't starve quiet ones
+    let market_scheduler = Arc::new(market_scheduler::MarketScheduler::new(market_scheduler::SchedulerConfig {
+        interval: tokio::time::Duration::from_millis(args.market_budget_interval_ms),
+        budget: (args.market_budget_us > 0).then(|| tokio::time::Duration::from_micros(args.market_budget_us)),
+        boost_backlog: args.market_budget_boost_backlog,
+        max_boost: args.market_budget_max_boost,
+    }));
+    
+    // Micro-batching of published updates, per market
+    let mut batch_windows = HashMap::new();
+    for (coin, window) in publish_batching::parse_overrides(args.batch_window_markets.as_deref().unwrap_or(""))? {
+        match market_registry.get_market_id(&coin).await {
+            Some(market_id) => {
+                batch_windows.insert(market_id, window);
+            }
+            None => warn!("Unknown market in batch window config: {}", coin),
+        }
+    }
+    if args.batch_window_us > 0 || !batch_windows.is_empty() {
+        info!("Batching updates: {}us default, {} overrides", args.batch_window_us, batch_windows.len());
+    }
+    let publish_batching = Arc::new(publish_batching::BatchWindows::new(
+        tokio::time::Duration::from_micros(args.batch_window_us),
+        batch_windows,
+    ));
+    
+    let book_shards = match args.book_shards {
+        0 => None,
+        count => Some(Arc::new(book_shards::BookShards::start(count)?)),
+    };
+    let pipeline_latency = args.latency_stats.then(|| Arc::new(pipeline_latency::PipelineLatency::new()));
+    
+    // Each book is mutated only by its actor; everything else reads
+    // published snapshots
+    let book_actors = Arc::new(book_actor::BookActors::spawn(
+        &orderbooks,
+        update_tx.clone(),
+        watermarks.clone(),
+        market_scheduler.clone(),
+        publish_batching.clone(),
+        book_actor::ActorOptions {
+            crossed_repair: args.crossed_book_repair,
+            shards: book_shards.clone(),
+            latency: pipeline_latency.clone(),
+            clock: replay_clock.clone(),
+        },
+    ));
+    
+    // Books for markets listed after startup, shared with the gRPC service
+    let market_books = market_lifecycle::MarketBooks::from(orderbooks.clone());
+    if live {
+        let lifecycle = Arc::new(market_lifecycle::MarketLifecycle::new(
+            market_books.clone(),
+            book_actors.clone(),
+            market_registry.clone(),
+            market_tiers.clone(),
+        ));
+        lifecycle.start(market_registry.subscribe_refreshes());
+    }
+    
+    if live {
+        // Create oracle client and start feed
+        let oracle_client = Arc::new(oracle_client::OracleClient::new());
+        oracle_client.start_oracle_feed(tokio::time::Duration::from_secs(3)).await;
+        info!("Started oracle price feed (updates every 3 seconds)");
+        
+        // Spawn oracle price updater
+        let book_actors_for_oracle = book_actors.clone();
+        let oracle_client_clone = oracle_client.clone();
+        let market_configs_clone = market_configs.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3));
+            loop {
+                interval.tick().await;
+                
+                // Get all oracle prices
+                let prices = oracle_client_clone.get_all_cached_prices().await;
+                
+                // Update each orderbook with its oracle price
+                for (market_id, symbol) in market_configs_clone.iter() {
+                    if let Some(book) = book_actors_for_oracle.handle(*market_id) {
+                        // Extract base currency from TradableProduct format (e.g., "BTC/USD" -> "BTC")
+                        let base_currency = if symbol.contains('/') {
+                            symbol.split('/').next().unwrap_or(symbol)
+                        } else {
+                            symbol
+                        };
+                        
+                        if let Some(oracle_price) = prices.get(base_currency) {
+                            if book.send(book_actor::BookCommand::OraclePrice(*oracle_price)).await.is_err() {
+                                continue;
+                            }
+                            log::debug!("{} oracle price updated: ${:.2}", symbol, oracle_price);
+                        }
                     }
                 }
             }
+        });
+        
+        if args.cex_feeds {
+            let markets: Vec<(u32, String)> = market_configs
+                .iter()
+                .map(|(market_id, symbol)| (*market_id, symbol.split('/').next().unwrap_or(symbol).to_string()))
+                .collect();
+            let feeds = Arc::new(cex_feeds::CexFeeds::new(
+                markets.iter().map(|(_, coin)| coin.clone()),
+                tokio::time::Duration::from_millis(args.cex_max_age_ms),
+            ));
+            feeds.start(markets, book_actors.clone());
         }
-    });
+    }
 
     // Create robust order processor with configuration
     let processor_config = ProcessorConfig {
@@ -150,85 +888,374 @@ async fn main() -> Result<()> {
         log_sample_rate: 10,        // Log every 10th error
     };
     
+    // Hour/day boundaries and registry refreshes, stamped with book sequences
+    let session_events = Arc::new(session_events::SessionEvents::new(orderbooks.clone(), hourly_layout.clone()));
+    session_events.clone().start(registry_refresh_rx);
+    
+    // Operational alerts (error-rate spikes, degradation), streamed via SubscribeAlerts
+    let alerts = Arc::new(alerts::Alerts::new());
+    
+    // Shed cold-market cadence, best-effort fidelity and analytics under overload
+    let degradation = Arc::new(degradation::DegradationMonitor::new(degradation::DegradationConfig {
+        max_backlog: args.degrade_max_backlog,
+        max_fanout_lag: args.degrade_max_fanout_lag,
+        max_throttled_share: args.degrade_max_throttled_share,
+        conflation_interval: tokio::time::Duration::from_millis(args.degrade_conflation_ms),
+        ..Default::default()
+    }));
+    degradation.clone().start(market_scheduler.clone(), alerts.clone());
+    
+    // Per-user order activity for surveillance queries
+    let user_activity = Arc::new(user_activity::UserActivityTracker::new(
+        tokio::time::Duration::from_secs(args.user_activity_window_secs),
+    ));
+    user_activity.clone().start(tokio::time::Duration::from_secs(10));
+    
+    let stuffing = args.stuffing_filter.map(|action| {
+        info!(
+            "Quote stuffing filter: {} orders of users with {}+ cycles under {}ms at a price within 1s",
+            action.as_str(),
+            args.stuffing_burst,
+            args.stuffing_max_lifetime_ms
+        );
+        Arc::new(quote_stuffing::QuoteStuffingFilter::new(quote_stuffing::StuffingConfig {
+            action,
+            max_lifetime: tokio::time::Duration::from_millis(args.stuffing_max_lifetime_ms),
+            burst: args.stuffing_burst.max(1),
+        }))
+    });
+    
     // Pass market registry to processor
-    let processor = Arc::new(RobustOrderProcessor::new(processor_config, market_registry.clone()));
+    let mut processor = RobustOrderProcessor::new(
+        processor_config,
+        market_registry.clone(),
+        session_events.clone(),
+        user_activity.clone(),
+        alerts.clone(),
+    )
+    .with_node_format(feed_format)
+    .with_warm_start(args.warm_start);
+    if let Some(snapshots) = state_snapshots {
+        processor = processor.with_state_snapshots(snapshots, resume);
+    }
+    if let Some(stuffing) = &stuffing {
+        processor = processor.with_stuffing_filter(stuffing.clone());
+    }
+    if args.parse_workers > 1 {
+        let pool = parse_pool::ParsePool::new(args.parse_workers)?;
+        info!("Parsing the node feed on {} threads", pool.workers());
+        processor = processor.with_parse_pool(pool);
+    }
+    let processor = Arc::new(processor);
     
     // Spawn robust order processor
-    let orderbooks_arc = Arc::new(orderbooks.clone());
-    let orderbooks_clone = orderbooks_arc.clone();
-    let update_tx_clone = update_tx.clone();
-    let stop_order_manager_clone = stop_order_manager.clone();
-    let processor_clone = processor.clone();
-    
-    tokio::spawn(async move {
-        if let Err(e) = processor_clone
-            .start(data_path, orderbooks_clone, update_tx_clone, stop_order_manager_clone)
-            .await
-        {
-            error!("Order processor failed: {}", e);
-        }
+    if live {
+        info!("Reading real-time orders from: {}", data_path);
+        
+        let book_actors_clone = book_actors.clone();
+        let stop_order_manager_clone = stop_order_manager.clone();
+        let processor_clone = processor.clone();
+        let processor_layout = hourly_layout.clone();
+        
+        tokio::spawn(async move {
+            if let Err(e) = processor_clone
+                .start(processor_layout, book_actors_clone, stop_order_manager_clone)
+                .await
+            {
+                error!("Order processor failed: {}", e);
+            }
+        });
+    }
+    if let (Some(path), Some(clock)) = (args.replay_file.clone(), replay_clock.clone()) {
+        let book_actors_clone = book_actors.clone();
+        let stop_order_manager_clone = stop_order_manager.clone();
+        let processor_clone = processor.clone();
+        let speed = args.replay_speed;
+        
+        tokio::spawn(async move {
+            if let Err(e) = processor_clone
+                .replay_capture(&path, speed, &clock, &book_actors_clone, &stop_order_manager_clone)
+                .await
+            {
+                error!("Replay of {} failed: {}", path.display(), e);
+            }
+        });
+    }
+
+    // Trades from the node's fills, alongside the order feed
+    let fill_monitor = (live && !args.disable_fills).then(|| {
+        let fills_layout = hourly_path::HourlyLayout { root: args.fills_dir.clone(), ..hourly_layout.clone() };
+        let monitor = Arc::new(fills::FillMonitor::new(market_registry.clone(), processor.error_buffer()));
+        monitor.clone().start(fills_layout.current_path().display().to_string(), book_actors.clone());
+        monitor.open_interest().start_baseline_feed(
+            market_registry.clone(),
+            tokio::time::Duration::from_secs(args.open_interest_baseline_secs.max(1)),
+        );
+        monitor
     });
 
-    // Create mark price service (1Hz updates)
-    // COMMENTED OUT DUE TO COMPILATION ERRORS
-    // let mark_price_service = Arc::new(mark_price_service::MarkPriceService::new(
-    //     orderbooks.clone(),
-    //     oracle_client.clone(),
-    //     tokio::time::Duration::from_secs(1),
-    // ));
-    
-    // // Start mark price calculations
-    // let mark_price_rx = mark_price_service.clone().start().await;
-    // info!("Started mark price service (1Hz updates)");
-
-    // Create gRPC server
-    let addr = format!("0.0.0.0:{}", args.grpc_port).parse()?;
-    info!("Starting gRPC server on {}", addr);
-
-    let mut service = crate::grpc_server::create_delta_streaming_service(orderbooks, update_rx, stop_order_manager, market_registry.clone());
-    
-    // Inject mark price service
-    // COMMENTED OUT DUE TO COMPILATION ERRORS
-    // service.set_mark_price_service(mark_price_service, mark_price_rx);
-    
-    // Setup authentication if required
-    if args.require_auth {
-        info!("Authentication enabled");
-        if let Some(keys) = args.api_keys {
-            let valid_keys: std::collections::HashSet<String> = keys
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .filter(|s| !s.is_empty())
-                .collect();
-            info!("Loaded {} API keys", valid_keys.len());
-            // Note: We'll need to add auth wrapper to the service
-            // For now, just log that auth is requested
-        } else {
-            warn!("Authentication required but no API keys provided");
+    if let Some(path) = args.heartbeat_path.clone() {
+        heartbeat::HeartbeatWriter::new(
+            path,
+            tokio::time::Duration::from_secs(args.heartbeat_interval_secs.max(1)),
+            processor.clone(),
+            orderbooks.clone(),
+        )
+        .start();
+    }
+
+    // Start order flow imbalance analytics
+    let ofi_engine = Arc::new(order_flow_imbalance::OfiEngine::new(
+        args.ofi_horizons_ms.iter().map(|ms| tokio::time::Duration::from_millis(*ms)).collect(),
+    ));
+    ofi_engine.clone().start(orderbooks.clone(), update_tx.subscribe(), market_tiers.clone(), degradation.clone());
+    
+    // Imbalance and depth metrics on every book update
+    let book_metrics = Arc::new(book_metrics::BookMetricsEngine::default());
+    let exclude_stuffing = stuffing.clone().filter(|s| s.config().action == quote_stuffing::StuffingAction::Exclude);
+    book_metrics.clone().start(orderbooks.clone(), update_tx.subscribe(), market_tiers.clone(), degradation.clone(), exclude_stuffing);
+    
+    // Alert when correlated markets are stressed together
+    if let Some(spec) = &args.contagion_groups {
+        let mut groups = Vec::new();
+        for (name, coins) in contagion::parse_groups(spec)? {
+            let mut market_ids = Vec::new();
+            for coin in coins {
+                match market_registry.get_market_id(&coin).await {
+                    Some(market_id) => market_ids.push(market_id),
+                    None => warn!("Unknown market in contagion group {}: {}", name, coin),
+                }
+            }
+            groups.push(contagion::MarketGroup { name, market_ids });
         }
+        let config = contagion::ContagionConfig {
+            spread_factor: args.contagion_spread_factor,
+            depth_fraction: args.contagion_depth_fraction,
+            min_markets: args.contagion_min_markets.max(2),
+            window: tokio::time::Duration::from_millis(args.contagion_window_ms),
+        };
+        let symbols = orderbooks.iter().map(|(market_id, orderbook)| (*market_id, orderbook.symbol.clone())).collect();
+        Arc::new(contagion::ContagionDetector::new(config, groups, symbols)).start(book_metrics.subscribe(), alerts.clone());
     }
+
+    // Remember recent update boundaries so late joiners can replay them
+    let replay_cache = Arc::new(replay_cache::ReplayCache::new(tokio::time::Duration::from_millis(args.replay_window_ms)));
+    replay_cache.clone().start(update_tx.subscribe());
+    
+    // Recent updates per market for reconnecting subscribers
+    let resume_buffer = Arc::new(resume_buffer::ResumeBuffer::new(args.resume_buffer_updates));
+    resume_buffer.clone().start(update_tx.subscribe());
     
-    let service_server = crate::grpc_server::pb::orderbook_service_server::OrderbookServiceServer::new(service);
+    // OHLCV bars from trades and book mids
+    let candle_aggregator = Arc::new(candles::CandleAggregator::new(args.candle_history));
+    candle_aggregator.clone().start(orderbooks.clone(), update_tx.subscribe(), fill_monitor.as_ref().map(|fills| fills.subscribe()));
+    if args.archive_only.is_some() {
+        replay_cache.seed_from_archive(&orderbooks);
+    }
+
+    // Track PnL and funding for watched positions
+    let watched_positions = match &args.positions_file {
+        Some(path) => position_pnl::PositionPnlTracker::resolve_markets(
+            position_pnl::PositionPnlTracker::load_configs(path)?,
+            &market_registry,
+        )
+        .await,
+        None => Vec::new(),
+    };
+    let pnl_tracker = Arc::new(position_pnl::PositionPnlTracker::new(watched_positions));
+    if pnl_tracker.position_count().await > 0 {
+        pnl_tracker.start_funding_feed(tokio::time::Duration::from_secs(60));
+        pnl_tracker.clone().start(orderbooks.clone(), tokio::time::Duration::from_secs(1));
+    }
+
+    // Mark prices from the oracle and trade prices the books receive
+    let mark_price_service = Arc::new(mark_price_service::MarkPriceService::new(
+        orderbooks.clone(),
+        tokio::time::Duration::from_millis(args.mark_price_interval_ms.max(1)),
+    ));
+    mark_price_service.clone().start();
+
+    // Parquet recordings for research
+    let recorder = match &args.recorder_config {
+        Some(path) => Some(market_recorder::Recorder::start(
+            market_recorder::RecorderConfig::load(path)?,
+            &orderbooks,
+            update_tx.subscribe(),
+            fill_monitor.as_ref().map(|fills| fills.subscribe()),
+            mark_price_service.subscribe(),
+        )?),
+        None => None,
+    };
 
-    let server_handle = tokio::spawn(async move {
-        if let Err(e) = Server::builder()
-            .add_service(service_server)
-            .serve(addr)
-            .await
-        {
-            error!("gRPC server error: {}", e);
+    // Listeners: from the file, else the TCP port plus the optional socket
+    let listener_configs = match &args.listeners_file {
+        Some(path) => listeners::load(path)?,
+        None => {
+            let mut tcp = listeners::ListenerConfig::plain("tcp", format!("0.0.0.0:{}", args.grpc_port), args.require_auth);
+            if args.grpc_web {
+                let origins = args.grpc_web_origins.as_deref().unwrap_or_default();
+                tcp.grpc_web = Some(listeners::GrpcWebConfig::new(
+                    origins.split(',').map(str::trim).filter(|o| !o.is_empty()).map(String::from).collect(),
+                ));
+            }
+            if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+                tcp.tls = Some(listeners::TlsFiles {
+                    cert: cert.clone(),
+                    key: key.clone(),
+                    client_ca: args.tls_ca.clone(),
+                    reload_secs: args.tls_reload_secs,
+                });
+            }
+            let mut configs = vec![tcp];
+            if let Some(path) = &args.uds_path {
+                let mut uds = listeners::ListenerConfig::plain("uds", format!("unix:{}", path.display()), args.require_auth);
+                uds.uds_mode = Some(format!("{:o}", args.uds_mode));
+                configs.push(uds);
+            }
+            configs
         }
+    };
+    
+    let tuning = socket_tuning(&args);
+    info!("Socket tuning ({}): {:?}", args.socket_preset.as_str(), tuning);
+
+    let flight = Arc::new(flight_service::ArrowFlightService::new(
+        orderbooks.clone(),
+        market_tiers.clone(),
+        candle_aggregator.clone(),
+        delta_wal.clone(),
+    ));
+
+    // Streams receive only their own markets' updates
+    let fanout = Arc::new(market_fanout::MarketFanout::default());
+    fanout.clone().start(update_rx);
+
+    // Periodic diffs of the books against the public L2 API
+    let book_drift = if live && args.book_drift_interval_secs > 0 {
+        let mut coins = market_registry.get_hyperliquid_coins().await;
+        if let Some(markets) = &args.book_drift_markets {
+            let wanted: HashSet<&str> = markets.split(',').map(str::trim).filter(|c| !c.is_empty()).collect();
+            for coin in &wanted {
+                if !coins.values().any(|known| known == coin) {
+                    warn!("Unknown market in book drift config: {}", coin);
+                }
+            }
+            coins.retain(|_, coin| wanted.contains(coin.as_str()));
+        }
+        let monitor = Arc::new(book_drift::BookDriftMonitor::new(args.book_drift_threshold_bps));
+        monitor.start(orderbooks.clone(), coins, tokio::time::Duration::from_secs(args.book_drift_interval_secs));
+        Some(monitor)
+    } else {
+        None
+    };
+
+    let mut service = crate::grpc_server::create_delta_streaming_service(market_books, fanout, stop_order_manager, market_registry.clone(), market_tiers.clone(), ofi_engine.clone(), pnl_tracker.clone(), replay_cache.clone(), session_events.clone(), watermarks.clone(), processor.error_buffer(), alerts.clone());
+    
+    service.set_mark_price_service(mark_price_service);
+    
+    if let Some(wal) = delta_wal {
+        service.set_delta_wal(wal);
+    }
+    
+    if let Some(monitor) = book_drift {
+        service.set_book_drift(monitor);
+    }
+    
+    if let Some(path) = &args.templates_file {
+        let templates = subscription_templates::SubscriptionTemplates::load(path)?;
+        info!("Loaded {} subscription templates from {}", templates.len(), path.display());
+        service.set_subscription_templates(templates);
+    }
+    
+    // Size decimals for subscribers that ask for normalized sizes
+    let size_normalizer = Arc::new(size_normalization::SizeNormalizer::new(market_registry.get_sz_decimals().await));
+    size_normalizer.clone().start(market_registry.clone(), market_registry.subscribe_refreshes());
+    service.set_size_normalizer(size_normalizer);
+    service.set_user_activity(user_activity);
+    service.set_market_scheduler(market_scheduler);
+    service.set_publish_batching(publish_batching);
+    service.set_degradation(degradation);
+    service.set_processor(processor.clone());
+    if let Some(book_shards) = book_shards {
+        service.set_book_shards(book_shards);
+    }
+    if let Some(latency) = pipeline_latency.clone() {
+        service.set_pipeline_latency(latency);
+    }
+    service.set_resume_buffer(resume_buffer);
+    service.set_candles(candle_aggregator);
+    service.set_book_metrics(book_metrics);
+    if let Some(stuffing) = stuffing {
+        service.set_stuffing_filter(stuffing);
+    }
+    if let Some(dir) = args.tee_dir.clone() {
+        service.set_tee_dir(dir);
+    }
+    if let Some(fills) = fill_monitor {
+        service.set_fills(fills);
+    }
+    
+    let api_keys: std::collections::HashSet<String> = args
+        .api_keys
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+    let permissions = match &args.api_key_permissions {
+        Some(path) => auth_interceptor::load_permissions(path)?,
+        None => HashMap::new(),
+    };
+    let mut api_keys = auth_interceptor::ApiKeys::new(api_keys, permissions);
+    api_keys.set_stream_limits(auth_interceptor::StreamLimits {
+        max_streams: args.max_streams_per_key,
+        max_markets: args.max_markets_per_key,
     });
+    if listener_configs.iter().any(|l| l.require_auth) || (args.rest_port.is_some() && args.require_auth) {
+        info!("Loaded {} API keys, {} with permissions entries; the rest are read-only", api_keys.len(), api_keys.restricted());
+        for (key, permissions) in api_keys.effective_permissions() {
+            info!("API key {}: {}", key, permissions);
+        }
+        if api_keys.is_empty() {
+            warn!("Authentication required but no API keys provided");
+        }
+    }
+    
+    let health = Arc::new(health::HealthService::new(
+        live.then(|| processor.clone()),
+        market_registry.clone(),
+        update_tx.clone(),
+        UPDATE_CHANNEL_CAPACITY,
+        tokio::time::Duration::from_secs(args.health_max_registry_age_secs),
+    ));
+    
+    let reflection = Arc::new(reflection::ReflectionService::new()?);
+    
+    let service = Arc::new(service);
+    let mut servers = listeners::serve(&listener_configs, service.clone(), health, reflection, flight, &tuning, &api_keys)?;
+    if args.enable_metrics {
+        service_health::serve(&mut servers, ([0, 0, 0, 0], args.metrics_port).into(), processor.clone(), pipeline_latency)?;
+    }
+    if let Some(port) = args.rest_port {
+        let policy = auth_interceptor::ListenerPolicy::new(api_keys.clone(), args.require_auth, None);
+        rest_gateway::serve(&mut servers, ([0, 0, 0, 0], port).into(), service, market_registry.clone(), policy)?;
+    }
 
     // Wait for shutdown
     tokio::select! {
-        _ = server_handle => {
-            error!("gRPC server task exited");
+        _ = servers.join_next() => {
+            error!("Listener task exited");
         }
         _ = tokio::signal::ctrl_c() => {
             info!("Received shutdown signal");
         }
     }
+    
+    listeners::cleanup(&listener_configs);
+    if let Some(recorder) = recorder {
+        recorder.finish();
+    }
 
     info!("Shutting down real-time orderbook service");
     Ok(())
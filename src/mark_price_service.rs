@@ -0,0 +1,119 @@
+//! Mark prices by Hyperliquid's method for every market, recomputed on a
+//! fixed cadence.
+//!
+//! Oracle prices and last trades already reach the books through their
+//! actors. Each tick this computes every two-sided book's mark price, keeps
+//! the latest per market for `GetMarkPrice` and publishes it to
+//! `SubscribeMarkPrices` streams.
+
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::fast_orderbook::FastOrderbook;
+use crate::mark_price_v2::{CEXPrices, MarkPriceResult};
+
+/// Reported with every mark price; bump when the calculation changes
+pub const CALCULATION_VERSION: u64 = 2;
+
+/// One market's mark price and the inputs it came from
+#[derive(Debug, Clone)]
+pub struct MarkPriceUpdateEvent {
+    pub market_id: u32,
+    pub symbol: String,
+    /// When it was computed, ms since epoch
+    pub timestamp_ms: i64,
+    pub result: MarkPriceResult,
+    pub oracle_price: Option<f64>,
+    pub last_trade: Option<f64>,
+    pub cex_prices: Option<CEXPrices>,
+}
+
+pub struct MarkPriceService {
+    orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+    interval: Duration,
+    latest: RwLock<HashMap<u32, Arc<MarkPriceUpdateEvent>>>,
+    output_tx: broadcast::Sender<Arc<MarkPriceUpdateEvent>>,
+}
+
+impl MarkPriceService {
+    pub fn new(orderbooks: HashMap<u32, Arc<FastOrderbook>>, interval: Duration) -> Self {
+        let capacity = (orderbooks.len() * 4).max(64);
+        Self { orderbooks, interval, latest: RwLock::new(HashMap::new()), output_tx: broadcast::channel(capacity).0 }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<MarkPriceUpdateEvent>> {
+        self.output_tx.subscribe()
+    }
+
+    /// The last mark price computed for `market_id`
+    pub fn latest(&self, market_id: u32) -> Option<Arc<MarkPriceUpdateEvent>> {
+        self.latest.read().get(&market_id).cloned()
+    }
+
+    /// Compute every market's mark price once; books with an empty side
+    /// keep their previous one
+    pub fn tick(&self) -> usize {
+        let timestamp_ms = chrono::Utc::now().timestamp_millis();
+        let mut computed = 0;
+        for (market_id, orderbook) in &self.orderbooks {
+            let Some(result) = orderbook.calculate_hl_mark_price() else { continue };
+            let event = Arc::new(MarkPriceUpdateEvent {
+                market_id: *market_id,
+                symbol: orderbook.symbol.clone(),
+                timestamp_ms,
+                result,
+                oracle_price: orderbook.get_oracle_price(),
+                last_trade: orderbook.get_last_trade_price(),
+                cex_prices: orderbook.get_cex_prices(),
+            });
+            self.latest.write().insert(*market_id, event.clone());
+            let _ = self.output_tx.send(event);
+            computed += 1;
+        }
+        computed
+    }
+
+    pub fn start(self: Arc<Self>) {
+        info!("Starting mark price service ({:?} updates, {} markets)", self.interval, self.orderbooks.len());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                interval.tick().await;
+                self.tick();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::Order;
+
+    #[test]
+    fn test_tick_publishes_two_sided_books() {
+        let btc = Arc::new(FastOrderbook::new(0, "BTC".to_string()));
+        let eth = Arc::new(FastOrderbook::new(1, "ETH".to_string()));
+        btc.add_order(Order { id: 1, price: 99.0, size: 1.0, timestamp: 0 }, true);
+        btc.add_order(Order { id: 2, price: 101.0, size: 1.0, timestamp: 0 }, false);
+        eth.add_order(Order { id: 3, price: 10.0, size: 1.0, timestamp: 0 }, true);
+        btc.update_oracle_price(100.0);
+        btc.publish();
+        eth.publish();
+
+        let service = MarkPriceService::new([(0, btc), (1, eth)].into_iter().collect(), Duration::from_secs(1));
+        let mut rx = service.subscribe();
+        assert_eq!(service.tick(), 1);
+
+        let event = rx.try_recv().unwrap();
+        assert_eq!((event.market_id, event.symbol.as_str(), event.oracle_price), (0, "BTC", Some(100.0)));
+        assert!((event.result.mark_price - 100.0).abs() < 1e-9);
+        assert!(rx.try_recv().is_err());
+        assert!(service.latest(0).is_some());
+        assert!(service.latest(1).is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::time::{Duration, Instant};
 
@@ -29,6 +30,20 @@ pub struct EMACalculator {
     last_update: Option<Instant>,
 }
 
+/// What an EMA has accumulated, to carry it across restarts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmaState {
+    pub numerator: f64,
+    pub denominator: f64,
+}
+
+/// Accumulated state of both mark price EMAs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct MarkEmaState {
+    pub oracle_basis: EmaState,
+    pub fallback_mid: EmaState,
+}
+
 #[derive(Debug, Clone)]
 pub struct CEXPrices {
     pub binance: Option<f64>,
@@ -98,6 +113,19 @@ impl EMACalculator {
             None
         }
     }
+    
+    pub fn state(&self) -> EmaState {
+        EmaState { numerator: self.numerator, denominator: self.denominator }
+    }
+    
+    /// Continue from `state` as if it was last updated now
+    pub fn restore(&mut self, state: EmaState) {
+        if state.denominator > 0.0 {
+            self.numerator = state.numerator;
+            self.denominator = state.denominator;
+            self.last_update = Some(Instant::now());
+        }
+    }
 }
 
 impl HyperliquidMarkPriceCalculator {
@@ -111,6 +139,18 @@ impl HyperliquidMarkPriceCalculator {
         }
     }
     
+    pub fn ema_state(&self) -> MarkEmaState {
+        MarkEmaState {
+            oracle_basis: self.oracle_basis_ema.state(),
+            fallback_mid: self.fallback_mid_ema.state(),
+        }
+    }
+    
+    pub fn restore_ema(&mut self, state: MarkEmaState) {
+        self.oracle_basis_ema.restore(state.oracle_basis);
+        self.fallback_mid_ema.restore(state.fallback_mid);
+    }
+    
     pub fn calculate_mark_price(&mut self, inputs: &MarkPriceInputs) -> MarkPriceResult {
         let now = Instant::now();
         let mid_price = (inputs.best_bid + inputs.best_ask) / 2.0;
@@ -0,0 +1,287 @@
+use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::market_processor::MarketUpdate;
+use crate::memory_profile::MemoryUsage;
+use crate::shared_encoding::Encoded;
+
+/// Updates a stream can fall behind by before it lags, when none is
+/// configured
+pub const DEFAULT_STREAM_CAPACITY: usize = 16_384;
+
+/// What a stream does once it falls behind its updates, when the client
+/// chose. Without a policy each stream keeps its own: orderbook streams
+/// disconnect, delta and L3 streams resync with snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// End the stream with RESOURCE_EXHAUSTED
+    Disconnect,
+    /// Send a lag notice and carry on with the updates that follow
+    FlagGap,
+    /// Send a lag notice, then only periodic snapshots of the markets that moved
+    SnapshotOnly,
+}
+
+impl LagPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LagPolicy::Disconnect => "disconnect",
+            LagPolicy::FlagGap => "flag_gap",
+            LagPolicy::SnapshotOnly => "snapshot_only",
+        }
+    }
+}
+
+impl std::str::FromStr for LagPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "disconnect" => Ok(LagPolicy::Disconnect),
+            "flag_gap" => Ok(LagPolicy::FlagGap),
+            "snapshot_only" => Ok(LagPolicy::SnapshotOnly),
+            _ => anyhow::bail!("Unknown lag policy: {} (expected disconnect, flag_gap or snapshot_only)", s),
+        }
+    }
+}
+
+/// An update as every stream of its market receives it. Its wire encoding
+/// is made by the first stream that sends it whole and shared by the rest.
+#[derive(Debug)]
+pub struct SharedUpdate {
+    update: MarketUpdate,
+    encoded: OnceLock<Encoded>,
+}
+
+impl SharedUpdate {
+    pub fn new(update: MarketUpdate) -> Self {
+        Self { update, encoded: OnceLock::new() }
+    }
+
+    /// The update's message, encoded by `encode` only if no stream has yet
+    pub fn encoded(&self, encode: impl FnOnce(&MarketUpdate) -> Encoded) -> Encoded {
+        self.encoded.get_or_init(|| encode(&self.update)).clone()
+    }
+}
+
+impl Deref for SharedUpdate {
+    type Target = MarketUpdate;
+
+    fn deref(&self) -> &MarketUpdate {
+        &self.update
+    }
+}
+
+/// One stream's channel, under one of its markets
+#[derive(Clone)]
+struct Route {
+    id: u64,
+    tx: broadcast::Sender<Arc<SharedUpdate>>,
+    /// Updates the router skipped while the stream was registered
+    missed: Arc<AtomicU64>,
+}
+
+/// Routes market updates to the streams watching their markets, so a stream
+/// never receives another market's updates. Streams of one market share
+/// each update and its encoding.
+///
+/// Every stream gets its own channel, registered under each of its markets.
+/// A single router task reads the update broadcast and sends each update to
+/// the channels registered for its market only.
+pub struct MarketFanout {
+    capacity: usize,
+    routes: RwLock<HashMap<u32, Vec<Route>>>,
+    next_id: AtomicU64,
+}
+
+impl Default for MarketFanout {
+    fn default() -> Self {
+        Self::new(DEFAULT_STREAM_CAPACITY)
+    }
+}
+
+impl MarketFanout {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            routes: RwLock::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Updates of `market_ids` from now on
+    pub fn subscribe(self: &Arc<Self>, market_ids: impl IntoIterator<Item = u32>) -> FanoutReceiver {
+        let (tx, rx) = broadcast::channel(self.capacity);
+        let route = Route { id: self.next_id.fetch_add(1, Ordering::Relaxed), tx, missed: Arc::default() };
+        let market_ids: HashSet<u32> = market_ids.into_iter().collect();
+        let mut routes = self.routes.write();
+        for market_id in &market_ids {
+            routes.entry(*market_id).or_default().push(route.clone());
+        }
+        FanoutReceiver { route, rx, market_ids, fanout: self.clone() }
+    }
+
+    fn route(&self, update: MarketUpdate) {
+        let routes = self.routes.read();
+        let Some(routes) = routes.get(&update.market_id) else {
+            return;
+        };
+        let update = Arc::new(SharedUpdate::new(update));
+        for route in routes {
+            // A stream that has just closed has no receiver left
+            let _ = route.tx.send(update.clone());
+        }
+    }
+
+    /// Tell every stream it missed updates, as it can't know which markets
+    /// they were for
+    fn lagged(&self, skipped: u64) {
+        let routes = self.routes.read();
+        let mut told = HashSet::new();
+        for route in routes.values().flatten() {
+            if told.insert(route.id) {
+                route.missed.fetch_add(skipped, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn unsubscribe(&self, id: u64, market_ids: &HashSet<u32>) {
+        let mut routes = self.routes.write();
+        for market_id in market_ids {
+            if let Some(market) = routes.get_mut(market_id) {
+                market.retain(|route| route.id != id);
+                if market.is_empty() {
+                    routes.remove(market_id);
+                }
+            }
+        }
+    }
+
+    /// Updates queued for streams, counting each stream once
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let routes = self.routes.read();
+        let mut counted = HashSet::new();
+        let mut queued = 0;
+        let mut bytes = 0;
+        for route in routes.values().flatten() {
+            if counted.insert(route.id) {
+                queued += route.tx.len();
+                // Channels preallocate every slot
+                bytes += self.capacity * std::mem::size_of::<Option<Arc<SharedUpdate>>>();
+            }
+        }
+        MemoryUsage::new(queued, bytes)
+    }
+
+    pub fn start(self: Arc<Self>, mut update_rx: broadcast::Receiver<MarketUpdate>) {
+        info!("Routing market updates to streams by market ({} queued updates per stream)", self.capacity);
+
+        tokio::spawn(async move {
+            loop {
+                match update_rx.recv().await {
+                    Ok(update) => self.route(update),
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Market update router lagged by {} updates", skipped);
+                        self.lagged(skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+/// A stream's updates, received like a broadcast receiver. Unregisters when
+/// dropped.
+pub struct FanoutReceiver {
+    route: Route,
+    rx: broadcast::Receiver<Arc<SharedUpdate>>,
+    market_ids: HashSet<u32>,
+    fanout: Arc<MarketFanout>,
+}
+
+impl FanoutReceiver {
+    /// The next update, or `Lagged` once the stream or the router fell
+    /// behind
+    pub async fn recv(&mut self) -> Result<Arc<SharedUpdate>, RecvError> {
+        match self.route.missed.swap(0, Ordering::Relaxed) {
+            0 => self.rx.recv().await,
+            missed => Err(RecvError::Lagged(missed)),
+        }
+    }
+
+    /// Updates queued for this stream
+    pub fn queued(&self) -> usize {
+        self.rx.len()
+    }
+}
+
+impl Drop for FanoutReceiver {
+    fn drop(&mut self) {
+        self.fanout.unsubscribe(self.route.id, &self.market_ids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::{FastOrderbook, Order};
+
+    fn update(orderbook: &FastOrderbook, id: u64) -> MarketUpdate {
+        let delta = orderbook.add_order(Order { id, price: 100.0, size: 1.0, timestamp: 0 }, true);
+        MarketUpdate::from_deltas(orderbook.market_id, 0, vec![delta]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_streams_only_see_their_markets() {
+        let fanout = Arc::new(MarketFanout::new(4));
+        let (btc, eth) = (FastOrderbook::new(0, "BTC".to_string()), FastOrderbook::new(1, "ETH".to_string()));
+        let mut btc_only = fanout.subscribe([0]);
+        let mut both = fanout.subscribe([0, 1]);
+        let watched = |fanout: &MarketFanout| fanout.routes.read().len();
+        assert_eq!(watched(&fanout), 2);
+
+        fanout.route(update(&eth, 1));
+        fanout.route(update(&btc, 2));
+        fanout.route(update(&btc, 3));
+        assert_eq!(btc_only.queued(), 2);
+        assert_eq!(btc_only.recv().await.unwrap().market_id, 0);
+        assert_eq!(both.recv().await.unwrap().market_id, 1);
+        assert_eq!(both.recv().await.unwrap().market_id, 0);
+        // Streams of a market share the update and its encoding
+        let (first, second) = (btc_only.recv().await.unwrap(), both.recv().await.unwrap());
+        assert!(Arc::ptr_eq(&first, &second));
+        let encoded = first.encoded(|update| Encoded::new(&update.sequence));
+        assert_eq!(second.encoded(|_| unreachable!()), encoded);
+
+        // Falling behind lags only the slow stream
+        for id in 3..8 {
+            fanout.route(update(&eth, id));
+        }
+        assert!(matches!(both.recv().await, Err(RecvError::Lagged(1))));
+        assert_eq!(btc_only.queued(), 0);
+
+        // So does the router falling behind, for every stream
+        fanout.lagged(7);
+        assert!(matches!(btc_only.recv().await, Err(RecvError::Lagged(7))));
+
+        drop(both);
+        assert_eq!(watched(&fanout), 1);
+        drop(btc_only);
+        assert_eq!(watched(&fanout), 0);
+    }
+
+    #[test]
+    fn test_lag_policies_parse() {
+        for policy in [LagPolicy::Disconnect, LagPolicy::FlagGap, LagPolicy::SnapshotOnly] {
+            assert_eq!(policy.as_str().parse::<LagPolicy>().unwrap(), policy);
+        }
+        assert_eq!("FLAG_GAP".parse::<LagPolicy>().unwrap(), LagPolicy::FlagGap);
+        assert!("resync".parse::<LagPolicy>().is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Hyperliquid numbers spot assets from here, so spot ids match its asset ids
+pub const SPOT_ID_OFFSET: u32 = 10_000;
+
+/// First id handed out to markets from other sources
+pub const EXTERNAL_ID_BASE: u32 = 1_000_000;
+
+/// A market as its source identifies it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MarketKey {
+    /// Hyperliquid perp, by universe index
+    HyperliquidPerp(u32),
+    /// Hyperliquid spot pair, by spot universe index
+    HyperliquidSpot(u32),
+    /// Any other source (mirrored CEX books, synthetic indices), by the
+    /// source's own name for the market
+    External { source: String, symbol: String },
+}
+
+impl MarketKey {
+    pub fn source(&self) -> &str {
+        match self {
+            MarketKey::HyperliquidPerp(_) => "hyperliquid-perp",
+            MarketKey::HyperliquidSpot(_) => "hyperliquid-spot",
+            MarketKey::External { source, .. } => source,
+        }
+    }
+
+    /// The market's key within its source
+    pub fn native_key(&self) -> String {
+        match self {
+            MarketKey::HyperliquidPerp(index) | MarketKey::HyperliquidSpot(index) => index.to_string(),
+            MarketKey::External { symbol, .. } => symbol.clone(),
+        }
+    }
+}
+
+impl fmt::Display for MarketKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.source(), self.native_key())
+    }
+}
+
+/// Assigns the internal u32 market ids used by books and the API.
+///
+/// Hyperliquid markets keep the ids they have always had: perps their
+/// universe index, spot pairs `SPOT_ID_OFFSET` plus their spot index.
+/// Other sources get ids from `EXTERNAL_ID_BASE` up, in registration order,
+/// so they can never collide with a Hyperliquid id. Assigning a key twice
+/// returns the same id.
+#[derive(Debug)]
+pub struct MarketIdAllocator {
+    ids: HashMap<MarketKey, u32>,
+    next_external: u32,
+}
+
+impl Default for MarketIdAllocator {
+    fn default() -> Self {
+        Self {
+            ids: HashMap::new(),
+            next_external: EXTERNAL_ID_BASE,
+        }
+    }
+}
+
+impl MarketIdAllocator {
+    pub fn assign(&mut self, key: MarketKey) -> Result<u32> {
+        if let Some(id) = self.id_of(&key) {
+            return Ok(id);
+        }
+
+        let id = match &key {
+            MarketKey::HyperliquidPerp(index) if *index < SPOT_ID_OFFSET => *index,
+            MarketKey::HyperliquidSpot(index) if *index < EXTERNAL_ID_BASE - SPOT_ID_OFFSET => SPOT_ID_OFFSET + index,
+            MarketKey::External { .. } => {
+                let id = self.next_external;
+                self.next_external = id
+                    .checked_add(1)
+                    .ok_or_else(|| anyhow::anyhow!("Market id space exhausted"))?;
+                id
+            }
+            _ => bail!("Market {} is outside its source's id range", key),
+        };
+
+        self.ids.insert(key, id);
+        Ok(id)
+    }
+
+    pub fn id_of(&self, key: &MarketKey) -> Option<u32> {
+        self.ids.get(key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_get_disjoint_stable_ids() {
+        let mut allocator = MarketIdAllocator::default();
+        let binance = MarketKey::External { source: "binance".to_string(), symbol: "BTCUSDT".to_string() };
+
+        assert_eq!(allocator.assign(MarketKey::HyperliquidPerp(0)).unwrap(), 0);
+        assert_eq!(allocator.assign(MarketKey::HyperliquidSpot(3)).unwrap(), 10_003);
+        assert_eq!(allocator.assign(binance.clone()).unwrap(), EXTERNAL_ID_BASE);
+        assert_eq!(
+            allocator
+                .assign(MarketKey::External { source: "synthetic".to_string(), symbol: "BTC-INDEX".to_string() })
+                .unwrap(),
+            EXTERNAL_ID_BASE + 1
+        );
+
+        // Re-registering is idempotent
+        assert_eq!(allocator.assign(binance.clone()).unwrap(), EXTERNAL_ID_BASE);
+        assert_eq!(allocator.id_of(&binance), Some(EXTERNAL_ID_BASE));
+        assert_eq!(binance.to_string(), "binance:BTCUSDT");
+
+        assert!(allocator.assign(MarketKey::HyperliquidPerp(SPOT_ID_OFFSET)).is_err());
+    }
+}
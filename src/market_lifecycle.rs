@@ -0,0 +1,199 @@
+//! Books for markets listed and delisted while the service runs.
+//!
+//! Every registry refresh is compared with the books that exist. A newly
+//! listed market gets a book on its tick grid and an actor, and is then
+//! routed orders and served like any other. A delisted market's actor is
+//! stopped and its book retired: lookups no longer find it, so its orders
+//! are rejected as for an unknown market and streams stop receiving it.
+
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::book_actor::BookActors;
+use crate::dynamic_markets::DynamicMarketRegistry;
+use crate::fast_orderbook::FastOrderbook;
+use crate::market_tiers::MarketTiers;
+use crate::price_ticks::TickSize;
+
+/// Every live market's book by id, shared by whatever looks books up, as
+/// books come and go
+#[derive(Clone, Default)]
+pub struct MarketBooks {
+    books: Arc<RwLock<HashMap<u32, Arc<FastOrderbook>>>>,
+}
+
+impl From<HashMap<u32, Arc<FastOrderbook>>> for MarketBooks {
+    fn from(books: HashMap<u32, Arc<FastOrderbook>>) -> Self {
+        Self { books: Arc::new(RwLock::new(books)) }
+    }
+}
+
+impl MarketBooks {
+    pub fn get(&self, market_id: &u32) -> Option<Arc<FastOrderbook>> {
+        self.books.read().get(market_id).cloned()
+    }
+
+    pub fn contains_key(&self, market_id: &u32) -> bool {
+        self.books.read().contains_key(market_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.books.read().len()
+    }
+
+    pub fn keys(&self) -> Vec<u32> {
+        self.books.read().keys().copied().collect()
+    }
+
+    /// The books as of now; later listings aren't included
+    pub fn all(&self) -> HashMap<u32, Arc<FastOrderbook>> {
+        self.books.read().clone()
+    }
+
+    fn insert(&self, orderbook: Arc<FastOrderbook>) {
+        self.books.write().insert(orderbook.market_id, orderbook);
+    }
+
+    fn remove(&self, market_id: u32) -> Option<Arc<FastOrderbook>> {
+        self.books.write().remove(&market_id)
+    }
+}
+
+/// What one refresh changed
+#[derive(Debug, Default, PartialEq)]
+pub struct LifecycleChanges {
+    pub listed: Vec<u32>,
+    pub retired: Vec<u32>,
+}
+
+pub struct MarketLifecycle {
+    books: MarketBooks,
+    actors: Arc<BookActors>,
+    registry: Arc<DynamicMarketRegistry>,
+    market_tiers: Arc<MarketTiers>,
+}
+
+impl MarketLifecycle {
+    pub fn new(
+        books: MarketBooks,
+        actors: Arc<BookActors>,
+        registry: Arc<DynamicMarketRegistry>,
+        market_tiers: Arc<MarketTiers>,
+    ) -> Self {
+        Self { books, actors, registry, market_tiers }
+    }
+
+    /// Bring the books in line with the registry's markets
+    pub async fn reconcile(&self) -> LifecycleChanges {
+        let markets = self.registry.get_all_markets().await;
+        let tick_sizes = self.registry.get_tick_sizes().await;
+        let mut changes = LifecycleChanges::default();
+        // An empty market list is a bad fetch, not every market delisting
+        if markets.is_empty() {
+            warn!("Registry lists no markets; leaving {} books as they are", self.books.len());
+            return changes;
+        }
+
+        for (market_id, symbol) in &markets {
+            if self.books.contains_key(market_id) {
+                continue;
+            }
+            let tick_size = tick_sizes.get(market_id).and_then(|size| TickSize::new(*size)).unwrap_or_default();
+            let orderbook = Arc::new(FastOrderbook::with_tick_size(*market_id, symbol.clone(), tick_size));
+            self.market_tiers.apply_retention(&orderbook);
+            // Routed orders need the actor before lookups find the book
+            self.actors.add(orderbook.clone());
+            self.books.insert(orderbook);
+            info!("Market {} ({}) listed; created its book", market_id, symbol);
+            changes.listed.push(*market_id);
+        }
+
+        for market_id in self.books.keys() {
+            if markets.contains_key(&market_id) {
+                continue;
+            }
+            if let Some(orderbook) = self.books.remove(market_id) {
+                self.actors.retire(market_id);
+                warn!(
+                    "Market {} ({}) delisted; retired its book at sequence {}",
+                    market_id,
+                    orderbook.symbol,
+                    orderbook.snapshot().sequence
+                );
+                changes.retired.push(market_id);
+            }
+        }
+
+        changes.listed.sort_unstable();
+        changes.retired.sort_unstable();
+        changes
+    }
+
+    /// Reconcile after every registry refresh
+    pub fn start(self: Arc<Self>, mut refresh_rx: broadcast::Receiver<usize>) {
+        tokio::spawn(async move {
+            // A missed refresh is covered by reconciling now
+            while !matches!(refresh_rx.recv().await, Err(RecvError::Closed)) {
+                let changes = self.reconcile().await;
+                if !changes.listed.is_empty() || !changes.retired.is_empty() {
+                    info!(
+                        "Market lifecycle: {} listed, {} retired, {} books",
+                        changes.listed.len(),
+                        changes.retired.len(),
+                        self.books.len()
+                    );
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_actor::ActorOptions;
+    use crate::market_ids::{MarketKey, EXTERNAL_ID_BASE};
+    use crate::market_tiers::MarketTier;
+    use crate::market_scheduler::{MarketScheduler, SchedulerConfig};
+    use crate::symbology::TradableProduct;
+    use crate::watermarks::WatermarkTracker;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_books_follow_listings() {
+        let registry = Arc::new(DynamicMarketRegistry::new());
+        let actors = Arc::new(BookActors::spawn(
+            &HashMap::new(),
+            broadcast::channel(16).0,
+            Arc::new(WatermarkTracker::new(HashMap::new(), Duration::ZERO)),
+            Arc::new(MarketScheduler::new(SchedulerConfig::default())),
+            Arc::default(),
+            ActorOptions::default(),
+        ));
+        let books = MarketBooks::from(HashMap::from([(7, Arc::new(FastOrderbook::new(7, "OLD".to_string())))]));
+        actors.add(books.get(&7).unwrap());
+        let tiers = Arc::new(MarketTiers::new(MarketTier::Hot));
+        let lifecycle = MarketLifecycle::new(books.clone(), actors.clone(), registry.clone(), tiers);
+
+        // Nothing listed yet reads as a failed fetch
+        assert_eq!(lifecycle.reconcile().await, LifecycleChanges::default());
+        assert!(books.contains_key(&7));
+
+        let key = MarketKey::External { source: "binance".to_string(), symbol: "BTCUSDT".to_string() };
+        let symbol = TradableProduct::from_str("BINANCE-BTC/USDT-SPOT").unwrap();
+        let id = registry.register_market(key, symbol, 5).await.unwrap();
+        assert_eq!(id, EXTERNAL_ID_BASE);
+
+        let changes = lifecycle.reconcile().await;
+        assert_eq!(changes, LifecycleChanges { listed: vec![id], retired: vec![7] });
+        assert_eq!(books.keys(), vec![id]);
+        assert!(actors.handle(id).is_some());
+        assert!(actors.handle(7).is_none());
+
+        // Already in line
+        assert_eq!(lifecycle.reconcile().await, LifecycleChanges::default());
+    }
+}
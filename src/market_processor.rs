@@ -1,14 +1,19 @@
-use crate::fast_orderbook::{FastOrderbook, Order, OrderbookDelta};
-use anyhow::Result;
-use memmap2::MmapOptions;
+use crate::binary_format::{self, BinaryFormat};
+use crate::fast_orderbook::{FastOrderbook, Order, SequencedDelta};
+use crate::file_rotation::{FileChange, FileWatch};
+use crate::file_wakeups::FileWakeups;
+use crate::mmap_reader::{crc32, MappedFile, Refresh};
+use anyhow::{anyhow, bail, Result};
 use serde::Deserialize;
-use std::fs::{File, OpenOptions};
+use std::fs::File;
 use std::io::{BufRead, BufReader, Seek, SeekFrom};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
 use tracing::{error, info, warn};
+use zerocopy::little_endian::{F64, U32, U64};
+use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
 
 // Binary order format constants - Format 1 (market_id first)
 const BINARY_ORDER_SIZE: usize = 38;
@@ -29,12 +34,170 @@ const OFFSET2_IS_BUY: usize = 28;    // 1 byte - IMPORTANT: Binary format uses i
 const OFFSET2_TIMESTAMP: usize = 29; // 8 bytes
 const OFFSET2_STATUS: usize = 37;    // 1 byte
 
+// Checksummed records carry a little-endian CRC-32 of the 38 record bytes
+const CHECKSUMMED_ORDER_SIZE: usize = BINARY_ORDER_SIZE + 4;
+
+// A v2 frame of one record: magic, length, record, CRC-32
+const FRAMED_ORDER_SIZE: usize = 4 + 2 + BINARY_ORDER_SIZE + 4;
+
+/// Records decoded per batch
+const BINARY_READ_RECORDS: usize = 1024;
+
+/// A Format 2 record as it lies in the file. Every field is unaligned and
+/// little-endian, so a run of records is cast in place over the mapping.
+#[derive(FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct RawOrder {
+    order_id: U64,
+    market_id: U32,
+    price: F64,
+    size: F64,
+    is_buy: u8,
+    timestamp_ns: U64,
+    status: u8,
+}
+
+#[derive(FromBytes, IntoBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+struct ChecksummedRawOrder {
+    order: RawOrder,
+    crc32: U32,
+}
+
+const _: () = {
+    assert!(std::mem::size_of::<RawOrder>() == BINARY_ORDER_SIZE);
+    assert!(std::mem::size_of::<ChecksummedRawOrder>() == CHECKSUMMED_ORDER_SIZE);
+    assert!(std::mem::offset_of!(RawOrder, order_id) == OFFSET2_ORDER_ID);
+    assert!(std::mem::offset_of!(RawOrder, market_id) == OFFSET2_MARKET_ID);
+    assert!(std::mem::offset_of!(RawOrder, price) == OFFSET2_PRICE);
+    assert!(std::mem::offset_of!(RawOrder, size) == OFFSET2_SIZE);
+    assert!(std::mem::offset_of!(RawOrder, is_buy) == OFFSET2_IS_BUY);
+    assert!(std::mem::offset_of!(RawOrder, timestamp_ns) == OFFSET2_TIMESTAMP);
+    assert!(std::mem::offset_of!(RawOrder, status) == OFFSET2_STATUS);
+};
+
+/// A validated Format 2 binary record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct BinaryOrder {
+    pub(crate) order_id: u64,
+    pub(crate) market_id: u32,
+    pub(crate) price: f64,
+    pub(crate) size: f64,
+    pub(crate) is_buy: bool,
+    pub(crate) timestamp_ns: u64,
+    pub(crate) status: u8,
+}
+
+impl BinaryOrder {
+    /// Decode the whole v1 records at the start of `bytes` into `out`, each
+    /// with where it ends. Records are `CHECKSUMMED_ORDER_SIZE` bytes when
+    /// `checksummed`.
+    pub(crate) fn decode_batch(bytes: &[u8], checksummed: bool, out: &mut Vec<(usize, Result<Self>)>) {
+        if checksummed {
+            let count = bytes.len() / CHECKSUMMED_ORDER_SIZE;
+            let (records, _) = <[ChecksummedRawOrder]>::ref_from_prefix_with_elems(bytes, count)
+                .expect("unaligned records always cast");
+            out.extend(
+                records
+                    .iter()
+                    .enumerate()
+                    .map(|(i, record)| ((i + 1) * CHECKSUMMED_ORDER_SIZE, Self::decode_checksummed(record))),
+            );
+        } else {
+            let count = bytes.len() / BINARY_ORDER_SIZE;
+            let (records, _) =
+                <[RawOrder]>::ref_from_prefix_with_elems(bytes, count).expect("unaligned records always cast");
+            out.extend(records.iter().enumerate().map(|(i, record)| ((i + 1) * BINARY_ORDER_SIZE, Self::decode(record))));
+        }
+    }
+
+    /// Decode the whole v2 frames at the start of `bytes` into `out`, each
+    /// with where it ends
+    pub(crate) fn decode_frames(bytes: &[u8], out: &mut Vec<(usize, Result<Self>)>) {
+        out.extend(binary_format::frames(bytes).map(|(end, payload)| {
+            let order = payload.and_then(|payload| {
+                let (record, _) = RawOrder::ref_from_prefix(payload)
+                    .map_err(|_| anyhow!("payload is {} bytes, expected {}", payload.len(), BINARY_ORDER_SIZE))?;
+                Self::decode(record)
+            });
+            (end, order)
+        }));
+    }
+
+    fn decode_checksummed(record: &ChecksummedRawOrder) -> Result<Self> {
+        let (stored, computed) = (record.crc32.get(), crc32(record.order.as_bytes()));
+        if stored != computed {
+            bail!("checksum mismatch: stored {:08x}, computed {:08x}", stored, computed);
+        }
+        Self::decode(&record.order)
+    }
+
+    fn decode(record: &RawOrder) -> Result<Self> {
+        let order = Self {
+            order_id: record.order_id.get(),
+            market_id: record.market_id.get(),
+            price: record.price.get(),
+            size: record.size.get(),
+            is_buy: record.is_buy != 0,
+            timestamp_ns: record.timestamp_ns.get(),
+            status: record.status,
+        };
+
+        if order.status > 2 {
+            bail!("unknown status {}", order.status);
+        }
+        if !order.price.is_finite() || order.price <= 0.0 {
+            bail!("invalid price {}", order.price);
+        }
+        if !order.size.is_finite() || order.size < 0.0 {
+            bail!("invalid size {}", order.size);
+        }
+        Ok(order)
+    }
+}
+
+/// A batch of deltas for one market.
+///
+/// Ordering contract:
+/// - `deltas` are in the order they were applied to the book.
+/// - Each delta carries the book sequence its mutation produced; sequences
+///   within a batch are strictly increasing and contiguous.
+/// - `sequence` equals the sequence of the last delta in the batch, so the
+///   first delta of the next batch is `sequence + 1` unless the book was
+///   cleared in between.
 #[derive(Debug, Clone)]
 pub struct MarketUpdate {
     pub market_id: u32,
     pub sequence: u64,
     pub timestamp_ns: u64,
-    pub deltas: Vec<OrderbookDelta>,
+    pub deltas: Vec<SequencedDelta>,
+    /// The book's checksum at `sequence`, when the update came from the
+    /// live book rather than the write-ahead log
+    pub checksum: Option<u32>,
+    /// Node timestamp of the oldest order the update opened, in
+    /// milliseconds, for latency
+    pub event_ms: Option<u64>,
+}
+
+impl MarketUpdate {
+    /// Build an update from deltas in application order. Returns `None` for
+    /// an empty batch.
+    pub fn from_deltas(market_id: u32, timestamp_ns: u64, deltas: Vec<SequencedDelta>) -> Option<Self> {
+        let sequence = deltas.last()?.sequence;
+        debug_assert!(
+            deltas.windows(2).all(|w| w[1].sequence == w[0].sequence + 1),
+            "deltas must be contiguous and in application order"
+        );
+        
+        Some(Self {
+            market_id,
+            sequence,
+            timestamp_ns,
+            deltas,
+            checksum: None,
+            event_ms: None,
+        })
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -68,10 +231,22 @@ pub struct MarketProcessor {
     update_tx: broadcast::Sender<MarketUpdate>,
     file_path: PathBuf,
     last_position: u64,
+    json_watch: FileWatch,
+    
+    // Binary input: persistent mapping and record layout
+    binary_file: Option<MappedFile>,
+    /// `None` until the file is long enough to tell
+    binary_format: Option<BinaryFormat>,
+    binary_checksums: bool,
+    binary_buffer: Vec<u8>,
+    binary_batch: Vec<(usize, Result<BinaryOrder>)>,
+    invalid_records: u64,
     
     // Performance counters
     orders_processed: u64,
     bytes_processed: u64,
+    /// Times the file was truncated or replaced and read over
+    rotations: u64,
     start_time: Instant,
 }
 
@@ -89,14 +264,29 @@ impl MarketProcessor {
             symbol,
             orderbook,
             update_tx,
+            json_watch: FileWatch::new(&file_path),
             file_path,
             last_position: 0,
+            binary_file: None,
+            binary_format: None,
+            binary_checksums: false,
+            binary_buffer: Vec::new(),
+            binary_batch: Vec::new(),
+            invalid_records: 0,
             orders_processed: 0,
             bytes_processed: 0,
+            rotations: 0,
             start_time: Instant::now(),
         }
     }
     
+    /// Expect v1 binary records with a trailing CRC-32. v2 files are
+    /// recognized by their header and always checksummed.
+    pub fn with_binary_checksums(mut self, enabled: bool) -> Self {
+        self.binary_checksums = enabled;
+        self
+    }
+    
     pub fn orderbook(&self) -> Arc<FastOrderbook> {
         self.orderbook.clone()
     }
@@ -112,29 +302,29 @@ impl MarketProcessor {
             }
         }
         
-        let mut interval = tokio::time::interval(Duration::from_millis(10));
+        // Woken as soon as the writer appends, instead of polling
+        let wakeups = FileWakeups::watch(&self.file_path);
         let mut deltas = Vec::with_capacity(100);
         
         loop {
-            interval.tick().await;
-            
             // Process new orders
-            if let Err(e) = self.process_updates(&mut deltas).await {
-                error!("Error processing updates: {}", e);
+            let more = match self.process_updates(&mut deltas).await {
+                Ok(more) => more,
+                Err(e) => {
+                    error!("Error processing updates: {}", e);
+                    false
+                }
+            };
+            if !deltas.is_empty() {
+                self.orderbook.publish();
             }
             
             // Send batched updates
-            if !deltas.is_empty() {
-                let update = MarketUpdate {
-                    market_id: self.market_id,
-                    sequence: self.orderbook.sequence.load(std::sync::atomic::Ordering::Relaxed),
-                    timestamp_ns: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_nanos() as u64,
-                    deltas: std::mem::take(&mut deltas),
-                };
-                
+            let timestamp_ns = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64;
+            if let Some(update) = MarketUpdate::from_deltas(self.market_id, timestamp_ns, std::mem::take(&mut deltas)) {
                 // Non-blocking send
                 let _ = self.update_tx.send(update);
             }
@@ -143,10 +333,19 @@ impl MarketProcessor {
             if self.orders_processed % 100 == 0 && self.orders_processed > 0 {
                 self.log_performance();
             }
+            
+            // A batch cut short leaves more to read right away
+            if more {
+                tokio::task::yield_now().await;
+            } else {
+                wakeups.wait().await;
+            }
         }
     }
     
-    async fn process_updates(&mut self, deltas: &mut Vec<OrderbookDelta>) -> Result<()> {
+    /// Read new orders into `deltas`. Returns whether the batch or time
+    /// limit stopped it before the end of the file.
+    async fn process_updates(&mut self, deltas: &mut Vec<SequencedDelta>) -> Result<bool> {
         // Check if file is binary or JSON
         let is_binary = self.file_path.extension()
             .map(|ext| ext == "bin")
@@ -159,92 +358,132 @@ impl MarketProcessor {
         }
     }
     
-    async fn process_binary_updates(&mut self, deltas: &mut Vec<OrderbookDelta>) -> Result<()> {
-        use std::io::Read;
+    async fn process_binary_updates(&mut self, deltas: &mut Vec<SequencedDelta>) -> Result<bool> {
+        let file = match &mut self.binary_file {
+            Some(file) => file,
+            None => self.binary_file.insert(MappedFile::open(&self.file_path)?),
+        };
         
-        // Try memory-mapped approach first for better performance
-        if let Ok(file) = OpenOptions::new().read(true).open(&self.file_path) {
-            if let Ok(metadata) = file.metadata() {
-                let file_size = metadata.len();
-                
-                // Use memory-mapped I/O for files under 100MB
-                if file_size < 100_000_000 && file_size > self.last_position {
-                    return self.process_binary_mmap(deltas, file_size).await;
-                }
-            }
+        if file.refresh()? == Refresh::Truncated {
+            warn!(
+                "{} shrank or was replaced at position {}, restarting from the beginning",
+                self.file_path.display(),
+                self.last_position
+            );
+            self.last_position = 0;
+            self.binary_format = None;
+            self.rotations += 1;
         }
         
-        // Fall back to regular file I/O
-        let mut file = File::open(&self.file_path)?;
-        file.seek(SeekFrom::Start(self.last_position))?;
+        let format = match self.binary_format {
+            Some(format) => format,
+            None => {
+                let header = binary_format::HEADER_LEN;
+                let Some(format) = file.with_bytes(0, header, &mut self.binary_buffer, BinaryFormat::detect)?? else {
+                    return Ok(false);
+                };
+                info!("Reading {} as {} binary orders", self.file_path.display(), format.as_str());
+                self.last_position = self.last_position.max(format.data_offset());
+                *self.binary_format.insert(format)
+            }
+        };
+        let (checksummed, record_size) = match format {
+            BinaryFormat::V1 if self.binary_checksums => (true, CHECKSUMMED_ORDER_SIZE),
+            BinaryFormat::V1 => (false, BINARY_ORDER_SIZE),
+            BinaryFormat::V2 => (true, FRAMED_ORDER_SIZE),
+        };
         
-        const ORDER_SIZE: usize = 38; // Binary format size
-        let mut buffer = [0u8; ORDER_SIZE];
         let mut orders_processed = 0;
         let start = Instant::now();
         
         loop {
-            // Limit processing time to maintain low latency
-            if start.elapsed() > Duration::from_micros(5000) {
+            // Only whole records are decoded; a partial tail waits for the writer
+            let batch = &mut self.binary_batch;
+            file.with_bytes(self.last_position, record_size * BINARY_READ_RECORDS, &mut self.binary_buffer, |bytes| {
+                batch.clear();
+                match format {
+                    BinaryFormat::V1 => BinaryOrder::decode_batch(bytes, checksummed, batch),
+                    BinaryFormat::V2 => BinaryOrder::decode_frames(bytes, batch),
+                }
+            })?;
+            if batch.is_empty() {
                 break;
             }
             
-            match file.read_exact(&mut buffer) {
-                Ok(_) => {
-                    self.last_position += ORDER_SIZE as u64;
-                    self.bytes_processed += ORDER_SIZE as u64;
-                    
-                    // Parse binary order: order_id(8), market_id(4), price(8), size(8), is_buy(1), timestamp_ns(8), status(1)
-                    let order_id = u64::from_le_bytes(buffer[0..8].try_into().unwrap());
-                    let market_id = u32::from_le_bytes(buffer[8..12].try_into().unwrap());
-                    
-                    // Skip if not our market
-                    if market_id != self.market_id {
+            let batch_start = self.last_position;
+            for (end, order) in batch.drain(..) {
+                // Limit processing time to maintain low latency
+                if start.elapsed() > Duration::from_micros(5000) {
+                    return Ok(true);
+                }
+                
+                let record_start = self.last_position;
+                self.last_position = batch_start + end as u64;
+                self.bytes_processed += self.last_position - record_start;
+                
+                let order = match order {
+                    Ok(order) => order,
+                    Err(e) => {
+                        self.invalid_records += 1;
+                        warn!(
+                            "Skipping invalid record at {} in {} ({} so far): {}",
+                            record_start,
+                            self.file_path.display(),
+                            self.invalid_records,
+                            e
+                        );
                         continue;
                     }
-                    
-                    let price = f64::from_le_bytes(buffer[OFFSET2_PRICE..OFFSET2_PRICE + 8].try_into().unwrap());
-                    let size = f64::from_le_bytes(buffer[OFFSET2_SIZE..OFFSET2_SIZE + 8].try_into().unwrap());
-                    let is_buy = buffer[OFFSET2_IS_BUY] != 0;
-                    let timestamp_ns = u64::from_le_bytes(buffer[OFFSET2_TIMESTAMP..OFFSET2_TIMESTAMP + 8].try_into().unwrap());
-                    let status = buffer[OFFSET2_STATUS];
-                    
-                    // Process based on status
-                    let delta = match status {
-                        0 => { // Open
-                            let order = Order {
-                                id: order_id,
-                                price,
-                                size,
-                                timestamp: timestamp_ns / 1000, // Convert to microseconds
-                            };
-                            Some(self.orderbook.add_order(order, is_buy))
-                        }
-                        1 | 2 => { // Filled or Cancelled
-                            self.orderbook.remove_order(order_id, price, is_buy)
-                        }
-                        _ => None,
-                    };
-                    
-                    if let Some(d) = delta {
-                        deltas.push(d);
-                        self.orders_processed += 1;
-                        orders_processed += 1;
-                    }
-                    
-                    // Batch size limit
-                    if orders_processed >= 100 {
-                        break;
+                };
+                
+                // Skip if not our market
+                if order.market_id != self.market_id {
+                    continue;
+                }
+                
+                // Process based on status
+                let delta = match order.status {
+                    0 => { // Open
+                        let book_order = Order {
+                            id: order.order_id,
+                            price: order.price,
+                            size: order.size,
+                            timestamp: order.timestamp_ns / 1000, // Convert to microseconds
+                        };
+                        Some(self.orderbook.add_order(book_order, order.is_buy))
                     }
+                    // Filled or Cancelled
+                    _ => self.orderbook.remove_order(order.order_id),
+                };
+                
+                if let Some(d) = delta {
+                    deltas.push(d);
+                    self.orders_processed += 1;
+                    orders_processed += 1;
+                }
+                
+                // Batch size limit
+                if orders_processed >= 100 {
+                    return Ok(true);
                 }
-                Err(_) => break, // EOF or error
             }
         }
         
-        Ok(())
+        Ok(false)
     }
     
-    async fn process_json_updates(&mut self, deltas: &mut Vec<OrderbookDelta>) -> Result<()> {
+    async fn process_json_updates(&mut self, deltas: &mut Vec<SequencedDelta>) -> Result<bool> {
+        let change = self.json_watch.check(Some(self.last_position));
+        if change != FileChange::Unchanged {
+            warn!(
+                "{} was {} at position {}, restarting from the beginning",
+                self.file_path.display(),
+                change.as_str(),
+                self.last_position
+            );
+            self.last_position = 0;
+            self.rotations += 1;
+        }
         let file = File::open(&self.file_path)?;
         let mut reader = BufReader::new(file);
         reader.seek(SeekFrom::Start(self.last_position))?;
@@ -255,7 +494,7 @@ impl MarketProcessor {
         for line_result in reader.lines() {
             // Limit processing time to maintain low latency
             if start.elapsed() > Duration::from_micros(5000) {
-                break;
+                return Ok(true);
             }
             
             let line = line_result?;
@@ -284,14 +523,14 @@ impl MarketProcessor {
             
             // Batch size limit
             if lines_processed >= 100 {
-                break;
+                return Ok(true);
             }
         }
         
-        Ok(())
+        Ok(false)
     }
     
-    fn process_order(&self, update: OrderStatusUpdate) -> Option<OrderbookDelta> {
+    fn process_order(&self, update: OrderStatusUpdate) -> Option<SequencedDelta> {
         // Parse price and size
         let price = update.order.limit_px.parse::<f64>().ok()?;
         let size = update.order.sz.parse::<f64>().ok()?;
@@ -310,7 +549,7 @@ impl MarketProcessor {
             }
             "filled" | "canceled" | "cancelled" => {
                 // Remove order
-                self.orderbook.remove_order(update.order.oid, price, is_buy)
+                self.orderbook.remove_order(update.order.oid)
             }
             _ => None,
         }
@@ -322,90 +561,17 @@ impl MarketProcessor {
         let mb_per_sec = (self.bytes_processed as f64 / 1_048_576.0) / elapsed;
         
         info!(
-            "{} processor: {} orders/sec, {:.2} MB/sec, {} total orders, {} bid levels, {} ask levels",
+            "{} processor: {} orders/sec, {:.2} MB/sec, {} total orders, {} bid levels, {} ask levels, {} rotations",
             self.symbol,
             orders_per_sec as u64,
             mb_per_sec,
             self.orderbook.total_orders.load(std::sync::atomic::Ordering::Relaxed),
             self.orderbook.bid_count.load(std::sync::atomic::Ordering::Relaxed),
             self.orderbook.ask_count.load(std::sync::atomic::Ordering::Relaxed),
+            self.rotations,
         );
     }
     
-    async fn process_binary_mmap(&mut self, deltas: &mut Vec<OrderbookDelta>, file_size: u64) -> Result<()> {
-        let file = OpenOptions::new().read(true).open(&self.file_path)?;
-        
-        unsafe {
-            let mmap = MmapOptions::new().map(&file)?;
-            let data = &mmap[self.last_position as usize..file_size as usize];
-            
-            const ORDER_SIZE: usize = 38;
-            let mut offset = 0;
-            let mut orders_processed = 0;
-            let start = Instant::now();
-            
-            while offset + ORDER_SIZE <= data.len() {
-                // Limit processing time to maintain low latency
-                if start.elapsed() > Duration::from_micros(5000) {
-                    break;
-                }
-                
-                let order_data = &data[offset..offset + ORDER_SIZE];
-                
-                // Parse binary order (Format 2: order_id first)
-                let order_id = u64::from_le_bytes(order_data[OFFSET2_ORDER_ID..OFFSET2_ORDER_ID + 8].try_into().unwrap());
-                let market_id = u32::from_le_bytes(order_data[OFFSET2_MARKET_ID..OFFSET2_MARKET_ID + 4].try_into().unwrap());
-                
-                // Skip if not our market
-                if market_id != self.market_id {
-                    offset += ORDER_SIZE;
-                    continue;
-                }
-                
-                let price = f64::from_le_bytes(order_data[OFFSET2_PRICE..OFFSET2_PRICE + 8].try_into().unwrap());
-                let size = f64::from_le_bytes(order_data[OFFSET2_SIZE..OFFSET2_SIZE + 8].try_into().unwrap());
-                let is_buy = order_data[OFFSET2_IS_BUY] != 0;
-                let timestamp_ns = u64::from_le_bytes(order_data[OFFSET2_TIMESTAMP..OFFSET2_TIMESTAMP + 8].try_into().unwrap());
-                let status = order_data[OFFSET2_STATUS];
-                
-                // Process based on status
-                let delta = match status {
-                    0 => { // Open
-                        let order = Order {
-                            id: order_id,
-                            price,
-                            size,
-                            timestamp: timestamp_ns / 1000, // Convert to microseconds
-                        };
-                        Some(self.orderbook.add_order(order, is_buy))
-                    }
-                    1 | 2 => { // Filled or Cancelled
-                        self.orderbook.remove_order(order_id, price, is_buy)
-                    }
-                    _ => None,
-                };
-                
-                if let Some(d) = delta {
-                    deltas.push(d);
-                    self.orders_processed += 1;
-                    orders_processed += 1;
-                }
-                
-                offset += ORDER_SIZE;
-                
-                // Batch size limit
-                if orders_processed >= 100 {
-                    break;
-                }
-            }
-            
-            self.last_position += offset as u64;
-            self.bytes_processed += offset as u64;
-        }
-        
-        Ok(())
-    }
-    
     #[cfg(target_os = "linux")]
     fn set_cpu_affinity(&self) -> Result<()> {
         use core_affinity::CoreId;
@@ -417,4 +583,135 @@ impl MarketProcessor {
         info!("Pinned {} processor to CPU core {}", self.symbol, core_id.id);
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(order_id: u64, market_id: u32, price: f64, status: u8) -> Vec<u8> {
+        let mut record = vec![0u8; BINARY_ORDER_SIZE];
+        record[OFFSET2_ORDER_ID..OFFSET2_ORDER_ID + 8].copy_from_slice(&order_id.to_le_bytes());
+        record[OFFSET2_MARKET_ID..OFFSET2_MARKET_ID + 4].copy_from_slice(&market_id.to_le_bytes());
+        record[OFFSET2_PRICE..OFFSET2_PRICE + 8].copy_from_slice(&price.to_le_bytes());
+        record[OFFSET2_SIZE..OFFSET2_SIZE + 8].copy_from_slice(&1.5f64.to_le_bytes());
+        record[OFFSET2_IS_BUY] = 1;
+        record[OFFSET2_TIMESTAMP..OFFSET2_TIMESTAMP + 8].copy_from_slice(&5_000u64.to_le_bytes());
+        record[OFFSET2_STATUS] = status;
+        record
+    }
+
+    fn checksummed(mut record: Vec<u8>) -> Vec<u8> {
+        let crc = crc32(&record);
+        record.extend_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    fn parse(record: &[u8], checksummed: bool) -> Result<BinaryOrder> {
+        let mut batch = Vec::new();
+        BinaryOrder::decode_batch(record, checksummed, &mut batch);
+        assert!(batch.len() <= 1);
+        batch.pop().map(|(_, order)| order).unwrap_or_else(|| Err(anyhow!("partial record")))
+    }
+
+    #[test]
+    fn test_binary_record_validation() {
+        let order = parse(&record(9, 3, 101.5, 0), false).unwrap();
+        assert_eq!((order.order_id, order.market_id, order.price, order.size), (9, 3, 101.5, 1.5));
+
+        assert!(parse(&record(9, 3, 101.5, 7), false).is_err());
+        assert!(parse(&record(9, 3, f64::NAN, 0), false).is_err());
+        assert!(parse(&record(9, 3, 101.5, 0)[..20], false).is_err());
+
+        let mut framed = checksummed(record(9, 3, 101.5, 1));
+        assert!(parse(&framed, true).is_ok());
+        framed[OFFSET2_PRICE] ^= 1;
+        assert!(parse(&framed, true).is_err());
+    }
+
+    #[test]
+    fn test_binary_batch_casts_unaligned_records() {
+        // Offset by one byte, so no record is aligned
+        let mut bytes = vec![0u8];
+        for order_id in 0..3 {
+            bytes.extend(record(order_id, 3, 100.0 + order_id as f64, 0));
+        }
+        bytes.extend(&record(3, 3, 100.0, 0)[..10]);
+
+        let mut batch = Vec::new();
+        BinaryOrder::decode_batch(&bytes[1..], false, &mut batch);
+        assert_eq!(batch.last().unwrap().0, 3 * BINARY_ORDER_SIZE);
+        let orders: Vec<_> = batch.into_iter().map(|(_, order)| order.unwrap()).collect();
+        assert_eq!(orders.iter().map(|order| order.order_id).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(orders[2], BinaryOrder {
+            order_id: 2,
+            market_id: 3,
+            price: 102.0,
+            size: 1.5,
+            is_buy: true,
+            timestamp_ns: 5_000,
+            status: 0,
+        });
+    }
+
+    #[tokio::test]
+    async fn test_binary_ingestion_skips_corrupt_and_partial_records() {
+        let path = std::env::temp_dir().join(format!("market_processor_{}.bin", std::process::id()));
+        let mut data = checksummed(record(1, 3, 100.0, 0));
+        let mut corrupt = checksummed(record(2, 3, 100.0, 0));
+        corrupt[0] ^= 0xFF;
+        data.extend(corrupt);
+        data.extend(checksummed(record(3, 4, 100.0, 0)));
+        data.extend(&checksummed(record(4, 3, 99.0, 0))[..10]);
+        std::fs::write(&path, &data).unwrap();
+
+        let (update_tx, _) = broadcast::channel(16);
+        let mut processor = MarketProcessor::new(3, "BTC".to_string(), update_tx, path.clone())
+            .with_binary_checksums(true);
+        let mut deltas = Vec::new();
+        processor.process_updates(&mut deltas).await.unwrap();
+
+        assert_eq!(deltas.len(), 1);
+        assert_eq!(processor.invalid_records, 1);
+        assert_eq!(processor.last_position, 3 * CHECKSUMMED_ORDER_SIZE as u64);
+
+        // Truncation restarts from the beginning of the new contents
+        std::fs::write(&path, checksummed(record(5, 3, 98.0, 0))).unwrap();
+        processor.process_updates(&mut deltas).await.unwrap();
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(processor.last_position, CHECKSUMMED_ORDER_SIZE as u64);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_framed_ingestion_resynchronizes() {
+        let path = std::env::temp_dir().join(format!("market_processor_v2_{}.bin", std::process::id()));
+        let mut data = binary_format::file_header();
+        binary_format::write_frame(&mut data, &record(1, 3, 100.0, 0));
+        // A frame torn by a crashed writer, which then carried on
+        let mut torn = Vec::new();
+        binary_format::write_frame(&mut torn, &record(2, 3, 100.0, 0));
+        data.extend(&torn[..20]);
+        binary_format::write_frame(&mut data, &record(3, 3, 99.0, 0));
+        let complete = data.len();
+        binary_format::write_frame(&mut data, &record(4, 3, 98.0, 0));
+        data.truncate(data.len() - 5);
+        std::fs::write(&path, &data).unwrap();
+
+        let (update_tx, _) = broadcast::channel(16);
+        let mut processor = MarketProcessor::new(3, "BTC".to_string(), update_tx, path.clone())
+            .with_binary_checksums(false);
+        let mut deltas = Vec::new();
+        processor.process_updates(&mut deltas).await.unwrap();
+
+        assert_eq!(processor.binary_format, Some(BinaryFormat::V2));
+        assert_eq!(deltas.len(), 2);
+        assert_eq!(processor.invalid_records, 1);
+        assert_eq!(processor.last_position, complete as u64);
+        let bids: Vec<_> = processor.orderbook.orders().bids.iter().map(|order| order.id).collect();
+        assert_eq!(bids, vec![1, 3]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
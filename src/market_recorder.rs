@@ -0,0 +1,935 @@
+//! Records deltas, sampled snapshots, trades and mark prices to Parquet for
+//! research backtests.
+//!
+//! Files are partitioned hive-style as
+//! `<dir>/<stream>/date=YYYY-MM-DD/market=<symbol>/part-<ms>-<n>.parquet`,
+//! so query engines can prune by date and market from the path alone. A file
+//! is written as `.parquet.inprogress` and renamed once its footer is
+//! written. It is closed when its UTC date ends, after `rotate_secs` or at
+//! `max_file_rows`, and every open file is closed on shutdown.
+//!
+//! Events reach a single writer thread through a bounded queue. When the
+//! queue is full they are dropped and counted rather than slowing the feed.
+
+use anyhow::{bail, Context, Result};
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, TimestampMillisecondArray, TimestampNanosecondArray,
+    UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, NaiveDate};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::Deserialize;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+use crate::fast_orderbook::{BookSnapshot, FastOrderbook, OrderbookDelta};
+use crate::fills::Trade;
+use crate::mark_price_service::MarkPriceUpdateEvent;
+use crate::market_processor::MarketUpdate;
+
+/// Rows buffered per file before they are written as a batch
+const BATCH_ROWS: usize = 8192;
+
+/// Buffered rows are written at least this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the writer checks for files to rotate and reports drops
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stream {
+    Deltas,
+    Snapshots,
+    Trades,
+    MarkPrices,
+}
+
+impl Stream {
+    pub const ALL: [Stream; 4] = [Stream::Deltas, Stream::Snapshots, Stream::Trades, Stream::MarkPrices];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stream::Deltas => "deltas",
+            Stream::Snapshots => "snapshots",
+            Stream::Trades => "trades",
+            Stream::MarkPrices => "mark_prices",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    None,
+    Snappy,
+    #[default]
+    Zstd,
+}
+
+fn all_streams() -> Vec<Stream> {
+    Stream::ALL.to_vec()
+}
+fn default_zstd_level() -> i32 {
+    3
+}
+fn default_rotate_secs() -> u64 {
+    3600
+}
+fn default_max_file_rows() -> usize {
+    10_000_000
+}
+fn default_row_group_rows() -> usize {
+    1_000_000
+}
+fn default_snapshot_interval_ms() -> u64 {
+    1000
+}
+fn default_snapshot_depth() -> usize {
+    20
+}
+fn default_queue_capacity() -> usize {
+    65_536
+}
+
+/// The recorder's JSON config; only `dir` is required
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    #[serde(default = "all_streams")]
+    pub streams: Vec<Stream>,
+    /// Coins to record; empty for every market
+    #[serde(default)]
+    pub markets: Vec<String>,
+    #[serde(default)]
+    pub compression: Codec,
+    #[serde(default = "default_zstd_level")]
+    pub zstd_level: i32,
+    /// Longest a file stays open
+    #[serde(default = "default_rotate_secs")]
+    pub rotate_secs: u64,
+    #[serde(default = "default_max_file_rows")]
+    pub max_file_rows: usize,
+    #[serde(default = "default_row_group_rows")]
+    pub row_group_rows: usize,
+    /// How often books are sampled; unchanged books are skipped
+    #[serde(default = "default_snapshot_interval_ms")]
+    pub snapshot_interval_ms: u64,
+    /// Levels per side in sampled snapshots; 0 for the full book
+    #[serde(default = "default_snapshot_depth")]
+    pub snapshot_depth: usize,
+    /// Events queued for the writer before they are dropped
+    #[serde(default = "default_queue_capacity")]
+    pub queue_capacity: usize,
+}
+
+impl RecorderConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let config: Self = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .with_context(|| format!("Invalid recorder config {}", path.display()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.streams.is_empty() {
+            bail!("Recorder config has no streams");
+        }
+        if self.rotate_secs == 0 || self.max_file_rows == 0 || self.row_group_rows == 0 || self.queue_capacity == 0 {
+            bail!("rotate_secs, max_file_rows, row_group_rows and queue_capacity must be positive");
+        }
+        ZstdLevel::try_new(self.zstd_level).with_context(|| format!("Invalid zstd_level {}", self.zstd_level))?;
+        Ok(())
+    }
+
+    fn writer_properties(&self) -> Result<WriterProperties> {
+        let compression = match self.compression {
+            Codec::None => Compression::UNCOMPRESSED,
+            Codec::Snappy => Compression::SNAPPY,
+            Codec::Zstd => Compression::ZSTD(ZstdLevel::try_new(self.zstd_level)?),
+        };
+        Ok(WriterProperties::builder()
+            .set_compression(compression)
+            .set_max_row_group_size(self.row_group_rows)
+            .build())
+    }
+}
+
+fn utc_ms() -> DataType {
+    DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+}
+
+/// One stream's rows: their schema and how they become columns. Arrow
+/// Flight serves the same columns.
+pub(crate) trait Row: Sized {
+    const STREAM: Stream;
+    fn schema() -> SchemaRef;
+    fn market_id(&self) -> u32;
+    /// Picks the date partition
+    fn timestamp_ms(&self) -> i64;
+    fn columns(rows: &[Self]) -> Vec<ArrayRef>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DeltaRow {
+    market_id: u32,
+    sequence: u64,
+    timestamp_ns: u64,
+    /// "add", "change", "remove" or "clear"
+    action: &'static str,
+    side: &'static str,
+    price: f64,
+    level_size: f64,
+    order_id: u64,
+    order_size: f64,
+    queue_position: u32,
+    at_touch: bool,
+}
+
+impl Row for DeltaRow {
+    const STREAM: Stream = Stream::Deltas;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("market_id", DataType::UInt32, false),
+            Field::new("sequence", DataType::UInt64, false),
+            Field::new("timestamp", DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())), false),
+            Field::new("action", DataType::Utf8, false),
+            Field::new("side", DataType::Utf8, false),
+            Field::new("price", DataType::Float64, false),
+            Field::new("level_size", DataType::Float64, false),
+            Field::new("order_id", DataType::UInt64, false),
+            Field::new("order_size", DataType::Float64, false),
+            Field::new("queue_position", DataType::UInt32, false),
+            Field::new("at_touch", DataType::Boolean, false),
+        ]))
+    }
+
+    fn market_id(&self) -> u32 {
+        self.market_id
+    }
+
+    fn timestamp_ms(&self) -> i64 {
+        (self.timestamp_ns / 1_000_000) as i64
+    }
+
+    fn columns(rows: &[Self]) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.market_id))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.sequence))),
+            Arc::new(TimestampNanosecondArray::from_iter_values(rows.iter().map(|r| r.timestamp_ns as i64)).with_timezone("UTC")),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.action))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.side))),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.price))),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.level_size))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.order_id))),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.order_size))),
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.queue_position))),
+            Arc::new(BooleanArray::from(rows.iter().map(|r| r.at_touch).collect::<Vec<_>>())),
+        ]
+    }
+}
+
+/// One level of a sampled snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LevelRow {
+    market_id: u32,
+    sequence: u64,
+    timestamp_ms: i64,
+    side: &'static str,
+    /// 0 at the touch
+    level: u32,
+    price: f64,
+    size: f64,
+}
+
+impl Row for LevelRow {
+    const STREAM: Stream = Stream::Snapshots;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("market_id", DataType::UInt32, false),
+            Field::new("sequence", DataType::UInt64, false),
+            Field::new("timestamp", utc_ms(), false),
+            Field::new("side", DataType::Utf8, false),
+            Field::new("level", DataType::UInt32, false),
+            Field::new("price", DataType::Float64, false),
+            Field::new("size", DataType::Float64, false),
+        ]))
+    }
+
+    fn market_id(&self) -> u32 {
+        self.market_id
+    }
+
+    fn timestamp_ms(&self) -> i64 {
+        self.timestamp_ms
+    }
+
+    fn columns(rows: &[Self]) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.market_id))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.sequence))),
+            Arc::new(TimestampMillisecondArray::from_iter_values(rows.iter().map(|r| r.timestamp_ms)).with_timezone("UTC")),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.side))),
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.level))),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.price))),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.size))),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TradeRow {
+    market_id: u32,
+    timestamp_ms: i64,
+    price: f64,
+    size: f64,
+    side: String,
+    oid: u64,
+    tid: u64,
+}
+
+impl Row for TradeRow {
+    const STREAM: Stream = Stream::Trades;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("market_id", DataType::UInt32, false),
+            Field::new("timestamp", utc_ms(), false),
+            Field::new("price", DataType::Float64, false),
+            Field::new("size", DataType::Float64, false),
+            Field::new("side", DataType::Utf8, false),
+            Field::new("oid", DataType::UInt64, false),
+            Field::new("tid", DataType::UInt64, false),
+        ]))
+    }
+
+    fn market_id(&self) -> u32 {
+        self.market_id
+    }
+
+    fn timestamp_ms(&self) -> i64 {
+        self.timestamp_ms
+    }
+
+    fn columns(rows: &[Self]) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.market_id))),
+            Arc::new(TimestampMillisecondArray::from_iter_values(rows.iter().map(|r| r.timestamp_ms)).with_timezone("UTC")),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.price))),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.size))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.side.as_str()))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.oid))),
+            Arc::new(UInt64Array::from_iter_values(rows.iter().map(|r| r.tid))),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct MarkPriceRow {
+    market_id: u32,
+    timestamp_ms: i64,
+    mark_price: f64,
+    oracle_price: Option<f64>,
+    last_trade: Option<f64>,
+    internal_median: f64,
+    cex_median: Option<f64>,
+    used_fallback: bool,
+}
+
+impl Row for MarkPriceRow {
+    const STREAM: Stream = Stream::MarkPrices;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("market_id", DataType::UInt32, false),
+            Field::new("timestamp", utc_ms(), false),
+            Field::new("mark_price", DataType::Float64, false),
+            Field::new("oracle_price", DataType::Float64, true),
+            Field::new("last_trade", DataType::Float64, true),
+            Field::new("internal_median", DataType::Float64, false),
+            Field::new("cex_median", DataType::Float64, true),
+            Field::new("used_fallback", DataType::Boolean, false),
+        ]))
+    }
+
+    fn market_id(&self) -> u32 {
+        self.market_id
+    }
+
+    fn timestamp_ms(&self) -> i64 {
+        self.timestamp_ms
+    }
+
+    fn columns(rows: &[Self]) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(UInt32Array::from_iter_values(rows.iter().map(|r| r.market_id))),
+            Arc::new(TimestampMillisecondArray::from_iter_values(rows.iter().map(|r| r.timestamp_ms)).with_timezone("UTC")),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.mark_price))),
+            Arc::new(Float64Array::from(rows.iter().map(|r| r.oracle_price).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from(rows.iter().map(|r| r.last_trade).collect::<Vec<_>>())),
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(|r| r.internal_median))),
+            Arc::new(Float64Array::from(rows.iter().map(|r| r.cex_median).collect::<Vec<_>>())),
+            Arc::new(BooleanArray::from(rows.iter().map(|r| r.used_fallback).collect::<Vec<_>>())),
+        ]
+    }
+}
+
+pub(crate) fn delta_rows(update: &MarketUpdate) -> Vec<DeltaRow> {
+    update
+        .deltas
+        .iter()
+        .map(|delta| {
+            let (action, side, price) = delta.level_action();
+            let order_id = match delta.delta {
+                OrderbookDelta::AddBid { order_id, .. }
+                | OrderbookDelta::AddAsk { order_id, .. }
+                | OrderbookDelta::RemoveBid { order_id, .. }
+                | OrderbookDelta::RemoveAsk { order_id, .. }
+                | OrderbookDelta::ChangeLevel { order_id, .. } => order_id,
+                OrderbookDelta::Clear => 0,
+            };
+            DeltaRow {
+                market_id: update.market_id,
+                sequence: delta.sequence,
+                timestamp_ns: update.timestamp_ns,
+                action,
+                side,
+                price,
+                level_size: delta.level_size,
+                order_id,
+                order_size: delta.order_size,
+                queue_position: delta.queue_position as u32,
+                at_touch: delta.at_touch,
+            }
+        })
+        .collect()
+}
+
+/// The top `depth` levels of each side of `published`, bids first
+pub(crate) fn level_rows(market_id: u32, timestamp_ms: i64, published: &BookSnapshot, depth: usize) -> Vec<LevelRow> {
+    let sequence = published.sequence;
+    let level = |side: &'static str| {
+        move |(level, (price, size)): (usize, &(f64, f64))| LevelRow {
+            market_id,
+            sequence,
+            timestamp_ms,
+            side,
+            level: level as u32,
+            price: *price,
+            size: *size,
+        }
+    };
+    published
+        .bids
+        .iter()
+        .take(depth)
+        .enumerate()
+        .map(level("B"))
+        .chain(published.asks.iter().take(depth).enumerate().map(level("A")))
+        .collect()
+}
+
+enum Message {
+    Deltas(Vec<DeltaRow>),
+    Levels(Vec<LevelRow>),
+    Trade(TradeRow),
+    MarkPrice(MarkPriceRow),
+    Finish,
+}
+
+/// An open file and the rows not yet written to it
+struct Partition<R> {
+    date: NaiveDate,
+    path: PathBuf,
+    writer: ArrowWriter<File>,
+    opened: Instant,
+    last_write: Instant,
+    rows: usize,
+    pending: Vec<R>,
+}
+
+fn in_progress(path: &Path) -> PathBuf {
+    path.with_extension("parquet.inprogress")
+}
+
+/// A symbol as a path segment; spot pairs contain a slash
+fn partition_value(symbol: &str) -> String {
+    symbol.chars().map(|c| if c == '/' || c == '\\' || c == '=' { '-' } else { c }).collect()
+}
+
+/// Where files go and how they are written
+struct Files {
+    dir: PathBuf,
+    properties: WriterProperties,
+    symbols: HashMap<u32, String>,
+    rotate_after: Duration,
+    max_file_rows: usize,
+    opened: u64,
+}
+
+impl Files {
+    fn open<R: Row>(&mut self, market_id: u32, date: NaiveDate) -> Result<Partition<R>> {
+        let symbol = self.symbols.get(&market_id).map_or_else(|| market_id.to_string(), |symbol| partition_value(symbol));
+        let dir = self
+            .dir
+            .join(R::STREAM.as_str())
+            .join(format!("date={}", date.format("%Y-%m-%d")))
+            .join(format!("market={}", symbol));
+        std::fs::create_dir_all(&dir).with_context(|| format!("Creating {}", dir.display()))?;
+        self.opened += 1;
+        let path = dir.join(format!("part-{}-{}.parquet", chrono::Utc::now().timestamp_millis(), self.opened));
+        let file = File::create(in_progress(&path)).with_context(|| format!("Creating {}", path.display()))?;
+        let writer = ArrowWriter::try_new(file, R::schema(), Some(self.properties.clone()))?;
+        let now = Instant::now();
+        Ok(Partition { date, path, writer, opened: now, last_write: now, rows: 0, pending: Vec::new() })
+    }
+
+    fn write<R: Row>(partition: &mut Partition<R>) -> Result<()> {
+        if !partition.pending.is_empty() {
+            let batch = RecordBatch::try_new(R::schema(), R::columns(&partition.pending))?;
+            partition.writer.write(&batch)?;
+            partition.pending.clear();
+        }
+        partition.last_write = Instant::now();
+        Ok(())
+    }
+
+    fn close<R: Row>(mut partition: Partition<R>) -> Result<()> {
+        Self::write(&mut partition)?;
+        partition.writer.close()?;
+        std::fs::rename(in_progress(&partition.path), &partition.path)?;
+        Ok(())
+    }
+}
+
+/// One stream's open files, by market
+struct Partitions<R> {
+    open: HashMap<u32, Partition<R>>,
+}
+
+impl<R: Row> Partitions<R> {
+    fn new() -> Self {
+        Self { open: HashMap::new() }
+    }
+
+    fn push(&mut self, files: &mut Files, rows: impl IntoIterator<Item = R>) {
+        for row in rows {
+            let market_id = row.market_id();
+            let date = DateTime::from_timestamp_millis(row.timestamp_ms()).unwrap_or_default().date_naive();
+            if self.open.get(&market_id).is_some_and(|p| p.date != date || p.rows >= files.max_file_rows) {
+                self.close(market_id);
+            }
+            let partition = match self.open.entry(market_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match files.open::<R>(market_id, date) {
+                    Ok(partition) => entry.insert(partition),
+                    Err(e) => {
+                        error!("Failed to open {} file for market {}: {:#}", R::STREAM.as_str(), market_id, e);
+                        continue;
+                    }
+                },
+            };
+            partition.pending.push(row);
+            partition.rows += 1;
+            if partition.pending.len() >= BATCH_ROWS {
+                if let Err(e) = Files::write(partition) {
+                    error!("Failed to write {}: {:#}", partition.path.display(), e);
+                    self.open.remove(&market_id);
+                }
+            }
+        }
+    }
+
+    /// Rotate files open too long and write rows buffered too long
+    fn tick(&mut self, files: &Files) {
+        let expired: Vec<u32> =
+            self.open.iter().filter(|(_, p)| p.opened.elapsed() >= files.rotate_after).map(|(market_id, _)| *market_id).collect();
+        for market_id in expired {
+            self.close(market_id);
+        }
+        let mut failed = Vec::new();
+        for (market_id, partition) in &mut self.open {
+            if !partition.pending.is_empty() && partition.last_write.elapsed() >= FLUSH_INTERVAL {
+                if let Err(e) = Files::write(partition) {
+                    error!("Failed to write {}: {:#}", partition.path.display(), e);
+                    failed.push(*market_id);
+                }
+            }
+        }
+        for market_id in failed {
+            self.open.remove(&market_id);
+        }
+    }
+
+    fn close(&mut self, market_id: u32) {
+        if let Some(partition) = self.open.remove(&market_id) {
+            let path = partition.path.clone();
+            if let Err(e) = Files::close(partition) {
+                error!("Failed to close {}: {:#}", path.display(), e);
+            }
+        }
+    }
+
+    fn close_all(&mut self) {
+        let market_ids: Vec<u32> = self.open.keys().copied().collect();
+        for market_id in market_ids {
+            self.close(market_id);
+        }
+    }
+}
+
+struct Writer {
+    files: Files,
+    deltas: Partitions<DeltaRow>,
+    snapshots: Partitions<LevelRow>,
+    trades: Partitions<TradeRow>,
+    mark_prices: Partitions<MarkPriceRow>,
+}
+
+impl Writer {
+    fn new(files: Files) -> Self {
+        Self {
+            files,
+            deltas: Partitions::new(),
+            snapshots: Partitions::new(),
+            trades: Partitions::new(),
+            mark_prices: Partitions::new(),
+        }
+    }
+
+    /// False once told to finish
+    fn handle(&mut self, message: Message) -> bool {
+        match message {
+            Message::Deltas(rows) => self.deltas.push(&mut self.files, rows),
+            Message::Levels(rows) => self.snapshots.push(&mut self.files, rows),
+            Message::Trade(row) => self.trades.push(&mut self.files, [row]),
+            Message::MarkPrice(row) => self.mark_prices.push(&mut self.files, [row]),
+            Message::Finish => return false,
+        }
+        true
+    }
+
+    fn tick(&mut self) {
+        self.deltas.tick(&self.files);
+        self.snapshots.tick(&self.files);
+        self.trades.tick(&self.files);
+        self.mark_prices.tick(&self.files);
+    }
+
+    fn close_all(&mut self) {
+        self.deltas.close_all();
+        self.snapshots.close_all();
+        self.trades.close_all();
+        self.mark_prices.close_all();
+    }
+
+    fn run(mut self, rx: mpsc::Receiver<Message>, dropped: Arc<AtomicU64>) {
+        let mut last_tick = Instant::now();
+        let mut reported_drops = 0;
+        loop {
+            match rx.recv_timeout(TICK) {
+                Ok(message) => {
+                    if !self.handle(message) {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if last_tick.elapsed() >= TICK {
+                self.tick();
+                let drops = dropped.load(Ordering::Relaxed);
+                if drops > reported_drops {
+                    warn!("Recorder dropped {} events ({} total); the writer is falling behind", drops - reported_drops, drops);
+                    reported_drops = drops;
+                }
+                last_tick = Instant::now();
+            }
+        }
+        self.close_all();
+        info!("Recorder closed its files");
+    }
+}
+
+pub struct Recorder {
+    tx: SyncSender<Message>,
+    dropped: Arc<AtomicU64>,
+    thread: parking_lot::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Recorder {
+    /// Start writing the configured streams. Trades are only recorded when
+    /// `trades` is given.
+    pub fn start(
+        config: RecorderConfig,
+        orderbooks: &HashMap<u32, Arc<FastOrderbook>>,
+        updates: broadcast::Receiver<MarketUpdate>,
+        trades: Option<broadcast::Receiver<Arc<Trade>>>,
+        mark_prices: broadcast::Receiver<Arc<MarkPriceUpdateEvent>>,
+    ) -> Result<Arc<Self>> {
+        std::fs::create_dir_all(&config.dir).with_context(|| format!("Creating {}", config.dir.display()))?;
+        let symbols: HashMap<u32, String> =
+            orderbooks.iter().map(|(market_id, orderbook)| (*market_id, orderbook.symbol.clone())).collect();
+        let markets: Option<HashSet<u32>> = (!config.markets.is_empty()).then(|| {
+            config
+                .markets
+                .iter()
+                .filter_map(|coin| {
+                    let market_id = symbols.iter().find(|(_, symbol)| *symbol == coin).map(|(market_id, _)| *market_id);
+                    if market_id.is_none() {
+                        warn!("Unknown market in recorder config: {}", coin);
+                    }
+                    market_id
+                })
+                .collect()
+        });
+
+        let files = Files {
+            dir: config.dir.clone(),
+            properties: config.writer_properties()?,
+            symbols,
+            rotate_after: Duration::from_secs(config.rotate_secs),
+            max_file_rows: config.max_file_rows,
+            opened: 0,
+        };
+        let (tx, rx) = mpsc::sync_channel(config.queue_capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let thread = std::thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn({
+                let dropped = dropped.clone();
+                move || Writer::new(files).run(rx, dropped)
+            })?;
+        let recorder = Arc::new(Self { tx, dropped, thread: parking_lot::Mutex::new(Some(thread)) });
+
+        info!(
+            "Recording {:?} for {} markets to {} ({:?} compression)",
+            config.streams.iter().map(|stream| stream.as_str()).collect::<Vec<_>>(),
+            markets.as_ref().map_or(orderbooks.len(), HashSet::len),
+            config.dir.display(),
+            config.compression
+        );
+        let recorded = move |market_id: u32| markets.as_ref().is_none_or(|markets| markets.contains(&market_id));
+        let streams: HashSet<Stream> = config.streams.iter().copied().collect();
+
+        if streams.contains(&Stream::Deltas) {
+            let recorded = recorded.clone();
+            recorder.forward(updates, move |update: MarketUpdate| {
+                recorded(update.market_id).then(|| Message::Deltas(delta_rows(&update)))
+            });
+        }
+        if streams.contains(&Stream::Trades) {
+            match trades {
+                Some(trades) => {
+                    let recorded = recorded.clone();
+                    recorder.forward(trades, move |trade: Arc<Trade>| {
+                        recorded(trade.market_id).then(|| {
+                            Message::Trade(TradeRow {
+                                market_id: trade.market_id,
+                                timestamp_ms: trade.timestamp_ms as i64,
+                                price: trade.price,
+                                size: trade.size,
+                                side: trade.side.clone(),
+                                oid: trade.oid,
+                                tid: trade.tid,
+                            })
+                        })
+                    });
+                }
+                None => warn!("Trades can't be recorded while fills are disabled"),
+            }
+        }
+        if streams.contains(&Stream::MarkPrices) {
+            let recorded = recorded.clone();
+            recorder.forward(mark_prices, move |event: Arc<MarkPriceUpdateEvent>| {
+                recorded(event.market_id).then(|| {
+                    Message::MarkPrice(MarkPriceRow {
+                        market_id: event.market_id,
+                        timestamp_ms: event.timestamp_ms,
+                        mark_price: event.result.mark_price,
+                        oracle_price: event.oracle_price,
+                        last_trade: event.last_trade,
+                        internal_median: event.result.internal_median,
+                        cex_median: event.result.cex_median,
+                        used_fallback: event.result.used_fallback,
+                    })
+                })
+            });
+        }
+        if streams.contains(&Stream::Snapshots) {
+            let books: Vec<(u32, Arc<FastOrderbook>)> =
+                orderbooks.iter().filter(|(market_id, _)| recorded(**market_id)).map(|(id, book)| (*id, book.clone())).collect();
+            recorder.clone().sample_snapshots(books, Duration::from_millis(config.snapshot_interval_ms.max(1)), config.snapshot_depth);
+        }
+        Ok(recorder)
+    }
+
+    fn send(&self, message: Message) {
+        match self.tx.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    fn forward<T: Clone + Send + 'static>(
+        self: &Arc<Self>,
+        mut rx: broadcast::Receiver<T>,
+        to_message: impl Fn(T) -> Option<Message> + Send + 'static,
+    ) {
+        let recorder = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => {
+                        if let Some(message) = to_message(event) {
+                            recorder.send(message);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        recorder.dropped.fetch_add(skipped, Ordering::Relaxed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn sample_snapshots(self: Arc<Self>, books: Vec<(u32, Arc<FastOrderbook>)>, interval: Duration, depth: usize) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut sampled: HashMap<u32, u64> = HashMap::new();
+            loop {
+                ticker.tick().await;
+                let timestamp_ms = chrono::Utc::now().timestamp_millis();
+                for (market_id, orderbook) in &books {
+                    let published = orderbook.snapshot();
+                    if sampled.insert(*market_id, published.sequence) == Some(published.sequence) {
+                        continue;
+                    }
+                    let depth = if depth == 0 { usize::MAX } else { depth };
+                    self.send(Message::Levels(level_rows(*market_id, timestamp_ms, &published, depth)));
+                }
+            }
+        });
+    }
+
+    /// Write what is buffered and close every file
+    pub fn finish(&self) {
+        let _ = self.tx.send(Message::Finish);
+        if let Some(thread) = self.thread.lock().take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn trade(market_id: u32, timestamp_ms: i64, tid: u64) -> TradeRow {
+        TradeRow { market_id, timestamp_ms, price: 100.0, size: 1.0, side: "B".to_string(), oid: 1, tid }
+    }
+
+    fn parquet_files(dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(parquet_files(&path));
+            } else {
+                files.push(path);
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config: RecorderConfig = serde_json::from_str(r#"{"dir": "/data/recordings"}"#).unwrap();
+        assert_eq!(config.streams, Stream::ALL.to_vec());
+        assert_eq!((config.compression, config.rotate_secs, config.snapshot_depth), (Codec::Zstd, 3600, 20));
+        config.validate().unwrap();
+
+        let config: RecorderConfig =
+            serde_json::from_str(r#"{"dir": "d", "streams": ["trades"], "compression": "snappy", "zstd_level": 99}"#).unwrap();
+        assert!(config.validate().is_err());
+        assert!(serde_json::from_str::<RecorderConfig>(r#"{"dir": "d", "rotate": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_writes_partitioned_files() {
+        let dir = std::env::temp_dir().join(format!("market-recorder-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config: RecorderConfig = serde_json::from_str(&format!(r#"{{"dir": {:?}, "max_file_rows": 3}}"#, dir)).unwrap();
+        let files = Files {
+            dir: dir.clone(),
+            properties: config.writer_properties().unwrap(),
+            symbols: HashMap::from([(0, "BTC".to_string()), (1, "PURR/USDC".to_string())]),
+            rotate_after: Duration::from_secs(3600),
+            max_file_rows: config.max_file_rows,
+            opened: 0,
+        };
+        let mut writer = Writer::new(files);
+
+        // 2024-01-01 23:59:59 then the next day
+        let day_end = 1_704_153_599_000;
+        for tid in 0..4 {
+            writer.handle(Message::Trade(trade(0, day_end, tid)));
+        }
+        writer.handle(Message::Trade(trade(0, day_end + 1000, 4)));
+        writer.handle(Message::Trade(trade(1, day_end, 5)));
+        // Files still open are in progress
+        let in_progress = parquet_files(&dir).iter().filter(|path| path.to_string_lossy().ends_with(".inprogress")).count();
+        assert_eq!(in_progress, 2);
+        writer.close_all();
+
+        let files = parquet_files(&dir);
+        let relative: Vec<String> = files
+            .iter()
+            .map(|path| path.parent().unwrap().strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            relative,
+            vec![
+                "trades/date=2024-01-01/market=BTC",
+                "trades/date=2024-01-01/market=BTC",
+                "trades/date=2024-01-01/market=PURR-USDC",
+                "trades/date=2024-01-02/market=BTC",
+            ]
+        );
+
+        let mut rows = 0;
+        for path in &files {
+            assert_eq!(path.extension().unwrap(), "parquet");
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap().build().unwrap();
+            for batch in reader {
+                let batch = batch.unwrap();
+                assert_eq!(batch.schema(), TradeRow::schema());
+                rows += batch.num_rows();
+            }
+        }
+        // The first file holds max_file_rows
+        assert_eq!(rows, 6);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How much processing time each market may use
+#[derive(Debug, Clone, Copy)]
+pub struct SchedulerConfig {
+    /// Length of one time slice
+    pub interval: Duration,
+    /// Processing time per market per slice; `None` measures without limiting
+    pub budget: Option<Duration>,
+    /// Each time this many commands are queued, the budget grows by one more budget
+    pub boost_backlog: usize,
+    /// Upper bound on the boosted budget, in budgets
+    pub max_boost: u32,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(100),
+            budget: Some(Duration::from_millis(25)),
+            boost_backlog: 1024,
+            max_boost: 4,
+        }
+    }
+}
+
+/// Processing time accounting for one market
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MarketTiming {
+    pub market_id: u32,
+    /// Time spent applying commands, since startup
+    pub busy: Duration,
+    pub batches: u64,
+    /// Batches held back because the market had used its budget
+    pub throttles: u64,
+    pub throttled: Duration,
+    /// Longest single hold, the worst case a market waited with work queued
+    pub max_wait: Duration,
+    /// Commands queued at the last admission
+    pub backlog: usize,
+    /// Time spent in the current slice
+    pub slice_busy: Duration,
+}
+
+#[derive(Debug, Default)]
+struct MarketState {
+    timing: MarketTiming,
+    slice: u64,
+}
+
+/// Time-sliced processing budgets for book actors. A market that has used
+/// its budget waits for the next slice, which leaves the runtime's workers
+/// to markets that haven't. Lagging markets get a larger budget so a burst
+/// drains instead of piling up.
+pub struct MarketScheduler {
+    config: SchedulerConfig,
+    epoch: Instant,
+    markets: Mutex<HashMap<u32, MarketState>>,
+}
+
+impl MarketScheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config: SchedulerConfig {
+                interval: config.interval.max(Duration::from_millis(1)),
+                boost_backlog: config.boost_backlog.max(1),
+                max_boost: config.max_boost.max(1),
+                ..config
+            },
+            epoch: Instant::now(),
+            markets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> SchedulerConfig {
+        self.config
+    }
+
+    fn slice_of(&self, now: Instant) -> u64 {
+        (now.duration_since(self.epoch).as_nanos() / self.config.interval.as_nanos()) as u64
+    }
+
+    /// Budget per slice for a market with `backlog` commands queued
+    pub fn budget_for(&self, backlog: usize) -> Option<Duration> {
+        let boost = (1 + backlog / self.config.boost_backlog).min(self.config.max_boost as usize);
+        self.config.budget.map(|budget| budget * boost as u32)
+    }
+
+    /// Wait until `market_id` may process its next batch. Returns how long
+    /// it was held back.
+    pub async fn admit(&self, market_id: u32, backlog: usize) -> Duration {
+        let start = Instant::now();
+        let mut held = false;
+
+        loop {
+            let now = Instant::now();
+            let slice = self.slice_of(now);
+            let spent = {
+                let mut markets = self.markets.lock();
+                let state = markets.entry(market_id).or_default();
+                state.timing.market_id = market_id;
+                state.timing.backlog = backlog;
+                if state.slice != slice {
+                    state.slice = slice;
+                    state.timing.slice_busy = Duration::ZERO;
+                }
+                state.timing.slice_busy
+            };
+
+            match self.budget_for(backlog) {
+                Some(budget) if spent >= budget => {
+                    held = true;
+                    let next_slice = self.epoch + Duration::from_nanos(self.config.interval.as_nanos() as u64 * (slice + 1));
+                    tokio::time::sleep_until(next_slice).await;
+                }
+                _ => break,
+            }
+        }
+
+        if !held {
+            return Duration::ZERO;
+        }
+        let waited = start.elapsed();
+        if let Some(state) = self.markets.lock().get_mut(&market_id) {
+            state.timing.throttles += 1;
+            state.timing.throttled += waited;
+            state.timing.max_wait = state.timing.max_wait.max(waited);
+        }
+        waited
+    }
+
+    /// Record time spent processing one admitted batch
+    pub fn charge(&self, market_id: u32, busy: Duration) {
+        let slice = self.slice_of(Instant::now());
+        let mut markets = self.markets.lock();
+        let state = markets.entry(market_id).or_default();
+        state.timing.market_id = market_id;
+        if state.slice != slice {
+            state.slice = slice;
+            state.timing.slice_busy = Duration::ZERO;
+        }
+        state.timing.busy += busy;
+        state.timing.slice_busy += busy;
+        state.timing.batches += 1;
+    }
+
+    /// Timings of every market that has processed anything, by market id
+    pub fn timings(&self) -> Vec<MarketTiming> {
+        let slice = self.slice_of(Instant::now());
+        let mut timings: Vec<MarketTiming> = self
+            .markets
+            .lock()
+            .values()
+            .map(|state| MarketTiming {
+                slice_busy: if state.slice == slice { state.timing.slice_busy } else { Duration::ZERO },
+                ..state.timing
+            })
+            .collect();
+        timings.sort_unstable_by_key(|t| t.market_id);
+        timings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_over_budget_market_waits_for_next_slice() {
+        let scheduler = MarketScheduler::new(SchedulerConfig {
+            interval: Duration::from_millis(50),
+            budget: Some(Duration::from_millis(1)),
+            boost_backlog: 100,
+            max_boost: 4,
+        });
+
+        // Market 1 used up its budget; market 2 is unaffected
+        assert_eq!(scheduler.admit(1, 0).await, Duration::ZERO);
+        scheduler.charge(1, Duration::from_millis(2));
+        assert_eq!(scheduler.admit(2, 0).await, Duration::ZERO);
+        // A backlog raises the budget past what market 1 has used
+        assert_eq!(scheduler.admit(1, 300).await, Duration::ZERO);
+
+        let waited = scheduler.admit(1, 0).await;
+        assert!(waited > Duration::ZERO && waited <= Duration::from_millis(60));
+
+        let timings = scheduler.timings();
+        assert_eq!(timings.len(), 2);
+        assert_eq!(timings[0].busy, Duration::from_millis(2));
+        assert_eq!(timings[0].throttles, 1);
+        assert_eq!(timings[0].max_wait, waited);
+        assert_eq!(timings[1].throttles, 0);
+
+        assert_eq!(scheduler.budget_for(10_000), Some(Duration::from_millis(4)));
+    }
+}
@@ -0,0 +1,175 @@
+//! Hot/cold service tiers.
+//!
+//! Hot markets get full depth, L3 and delta streams in real time, analytics
+//! and book metrics, and the full event-log history. Cold markets are
+//! served as BBO snapshots at most once per second: depth is capped at one
+//! level, L3 and delta streams fall back to resnapshotting them on that
+//! interval instead of streaming their events, analytics and book metrics
+//! skip them, and their event logs keep only `COLD_MAX_EVENTS`, so little
+//! point-in-time history is available for them.
+//!
+//! The L3 book itself is still maintained for every market, as the BBO and
+//! mark prices are computed from it.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use parking_lot::RwLock;
+
+use crate::event_log::DEFAULT_MAX_EVENTS;
+use crate::fast_orderbook::FastOrderbook;
+
+/// Events a cold market's log retains before compacting
+pub const COLD_MAX_EVENTS: usize = 256;
+
+/// Service tier for a market
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MarketTier {
+    /// Full depth, every update fanned out immediately
+    Hot,
+    /// Best bid/offer only, throttled to one snapshot per interval
+    Cold,
+}
+
+impl MarketTier {
+    /// Maximum book depth published for this tier
+    pub fn max_depth(&self) -> usize {
+        match self {
+            MarketTier::Hot => usize::MAX,
+            MarketTier::Cold => 1,
+        }
+    }
+
+    /// Minimum time between published snapshots for this tier
+    pub const fn min_publish_interval(&self) -> Duration {
+        match self {
+            MarketTier::Hot => Duration::ZERO,
+            MarketTier::Cold => Duration::from_secs(1),
+        }
+    }
+
+    /// Events retained in the book's event log for this tier
+    pub fn max_events(&self) -> usize {
+        match self {
+            MarketTier::Hot => DEFAULT_MAX_EVENTS,
+            MarketTier::Cold => COLD_MAX_EVENTS,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MarketTier::Hot => "HOT",
+            MarketTier::Cold => "COLD",
+        }
+    }
+}
+
+impl std::str::FromStr for MarketTier {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "HOT" => Ok(MarketTier::Hot),
+            "COLD" => Ok(MarketTier::Cold),
+            _ => anyhow::bail!("Unknown market tier: {} (expected hot or cold)", s),
+        }
+    }
+}
+
+/// Runtime-switchable tier assignment for all markets
+pub struct MarketTiers {
+    default_tier: MarketTier,
+    overrides: RwLock<HashMap<u32, MarketTier>>,
+}
+
+impl MarketTiers {
+    pub fn new(default_tier: MarketTier) -> Self {
+        Self {
+            default_tier,
+            overrides: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn tier(&self, market_id: u32) -> MarketTier {
+        self.overrides
+            .read()
+            .get(&market_id)
+            .copied()
+            .unwrap_or(self.default_tier)
+    }
+
+    pub fn is_hot(&self, market_id: u32) -> bool {
+        self.tier(market_id) == MarketTier::Hot
+    }
+
+    /// Assign a tier to a market, returning the previous tier
+    pub fn set_tier(&self, market_id: u32, tier: MarketTier) -> MarketTier {
+        let mut overrides = self.overrides.write();
+        let previous = overrides.get(&market_id).copied().unwrap_or(self.default_tier);
+
+        if tier == self.default_tier {
+            overrides.remove(&market_id);
+        } else {
+            overrides.insert(market_id, tier);
+        }
+
+        previous
+    }
+
+    /// Size the book's event log for its market's current tier
+    pub fn apply_retention(&self, orderbook: &FastOrderbook) {
+        orderbook.set_event_retention(self.tier(orderbook.market_id).max_events());
+    }
+
+    pub fn default_tier(&self) -> MarketTier {
+        self.default_tier
+    }
+
+    /// Markets whose tier differs from the default
+    pub fn overrides(&self) -> HashMap<u32, MarketTier> {
+        self.overrides.read().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_switch() {
+        let tiers = MarketTiers::new(MarketTier::Cold);
+        assert_eq!(tiers.tier(0), MarketTier::Cold);
+
+        assert_eq!(tiers.set_tier(0, MarketTier::Hot), MarketTier::Cold);
+        assert!(tiers.is_hot(0));
+        assert!(!tiers.is_hot(1));
+
+        // Switching back to the default drops the override
+        tiers.set_tier(0, MarketTier::Cold);
+        assert!(tiers.overrides().is_empty());
+    }
+
+    #[test]
+    fn test_cold_markets_retain_fewer_events() {
+        let tiers = MarketTiers::new(MarketTier::Hot);
+        let book = FastOrderbook::new(0, "BTC".to_string());
+        for id in 0..(COLD_MAX_EVENTS as u64 * 2) {
+            book.add_order(crate::fast_orderbook::Order { id, price: 100.0, size: 1.0, timestamp: 0 }, true);
+        }
+        tiers.apply_retention(&book);
+        assert_eq!(book.event_log().lock().len(), COLD_MAX_EVENTS * 2);
+
+        tiers.set_tier(0, MarketTier::Cold);
+        tiers.apply_retention(&book);
+        let log = book.event_log().lock();
+        assert!(log.len() <= COLD_MAX_EVENTS);
+        // Compaction folds history into the base, never drops orders
+        assert_eq!(log.current_state().orders.len(), COLD_MAX_EVENTS * 2);
+    }
+
+    #[test]
+    fn test_parse_tier() {
+        assert_eq!("hot".parse::<MarketTier>().unwrap(), MarketTier::Hot);
+        assert_eq!("COLD".parse::<MarketTier>().unwrap(), MarketTier::Cold);
+        assert!("warm".parse::<MarketTier>().is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Attribution of the process's memory to the subsystems that retain it.
+//!
+//! Each retaining subsystem reports an estimate of its heap from the sizes
+//! and capacities of what it holds. Built with the `jemalloc` feature, the
+//! allocator's own totals are reported too, so whatever the estimates don't
+//! cover shows up as unattributed.
+
+use std::ops::AddAssign;
+
+/// Entries held and their estimated heap bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub items: usize,
+    pub bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn new(items: usize, bytes: usize) -> Self {
+        Self { items, bytes }
+    }
+}
+
+impl AddAssign for MemoryUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.items += other.items;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Totals from jemalloc's stats, in bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    /// Handed out to the program
+    pub allocated: u64,
+    /// In pages backing allocations
+    pub active: u64,
+    /// Resident pages the allocator maps, including its metadata
+    pub resident: u64,
+    /// Unmapped but kept for reuse
+    pub retained: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryReport {
+    pub rss_bytes: Option<u64>,
+    pub allocator: Option<AllocatorStats>,
+    pub components: Vec<(&'static str, MemoryUsage)>,
+}
+
+impl MemoryReport {
+    pub fn new(components: Vec<(&'static str, MemoryUsage)>) -> Self {
+        Self { rss_bytes: rss_bytes(), allocator: allocator_stats(), components }
+    }
+
+    /// Allocated bytes the components don't account for
+    pub fn unattributed_bytes(&self) -> Option<u64> {
+        let attributed: usize = self.components.iter().map(|(_, usage)| usage.bytes).sum();
+        Some(self.allocator?.allocated.saturating_sub(attributed as u64))
+    }
+}
+
+/// Resident set size from /proc, where there is one
+pub fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status.lines().find_map(|line| line.strip_prefix("VmRSS:"))?;
+    Some(kb.trim().trim_end_matches("kB").trim().parse::<u64>().ok()? * 1024)
+}
+
+#[cfg(feature = "jemalloc")]
+pub fn allocator_stats() -> Option<AllocatorStats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // Stats are cached until the epoch advances
+    epoch::advance().ok()?;
+    Some(AllocatorStats {
+        allocated: stats::allocated::read().ok()? as u64,
+        active: stats::active::read().ok()? as u64,
+        resident: stats::resident::read().ok()? as u64,
+        retained: stats::retained::read().ok()? as u64,
+    })
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn allocator_stats() -> Option<AllocatorStats> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::{FastOrderbook, Order};
+
+    #[test]
+    fn test_book_usage_grows_with_orders() {
+        let book = FastOrderbook::new(0, "BTC".to_string());
+        let empty = book.memory_usage();
+        for id in 0..500 {
+            book.add_order(Order { id, price: 100.0 + (id % 50) as f64, size: 1.0, timestamp: 0 }, id % 2 == 0);
+        }
+        book.publish();
+
+        let usage = book.memory_usage();
+        assert_eq!(usage.items, 500);
+        assert!(usage.bytes > empty.bytes);
+        assert!(book.event_log().lock().memory_usage().bytes >= 500 * std::mem::size_of::<crate::event_log::BookEvent>());
+
+        let mut report = MemoryReport { rss_bytes: None, allocator: None, components: vec![("books", usage)] };
+        assert_eq!(report.unattributed_bytes(), None);
+        report.allocator = Some(AllocatorStats { allocated: usage.bytes as u64 + 100, ..Default::default() });
+        assert_eq!(report.unattributed_bytes(), Some(100));
+        if cfg!(target_os = "linux") {
+            assert!(rss_bytes().unwrap() > 0);
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Persistent, crash-safe memory mapping of an append-only data file.
+//!
+//! The mapping is kept across polls and only remapped when the file grows.
+//! Reads never go past the length observed at the last `refresh`, and a
+//! shrinking file drops the mapping. Truncation can still race a read, so on
+//! Linux mapped pages are read under a SIGBUS guard: a fault past EOF is
+//! patched with a zero page, the mapping is discarded and the read is
+//! retried with `pread`, which just returns short.
+
+use anyhow::Result;
+use memmap2::{Mmap, MmapOptions};
+use std::fs::File;
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// CRC-32 (IEEE) lookup table
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE), as used by zlib and the `crc32` trailer of binary records
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+/// What `refresh` found since the previous call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refresh {
+    Unchanged,
+    Grown,
+    /// The file shrank or was replaced; previously read offsets are invalid
+    Truncated,
+}
+
+/// A mapping together with its SIGBUS guard. The guard is declared first so
+/// it is released before the pages are unmapped.
+struct Mapping {
+    guard: sigbus::GuardedRegion,
+    mmap: Mmap,
+}
+
+pub struct MappedFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    mapping: Option<Mapping>,
+    mmap_enabled: bool,
+    fallback_reads: u64,
+}
+
+impl MappedFile {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let len = file.metadata()?.len();
+        let mmap_enabled = sigbus::install();
+        if !mmap_enabled {
+            warn!("SIGBUS guard unavailable, reading {} with pread", path.display());
+        }
+
+        Ok(Self {
+            path,
+            file,
+            len,
+            mapping: None,
+            mmap_enabled,
+            fallback_reads: 0,
+        })
+    }
+
+    /// Length as of the last `refresh`; reads never go past it
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads served by `pread` because the mapping was unavailable or faulted
+    pub fn fallback_reads(&self) -> u64 {
+        self.fallback_reads
+    }
+
+    /// Re-check the file length, reopening the path if the file was replaced.
+    /// The mapping is dropped on any change and recreated on the next read.
+    pub fn refresh(&mut self) -> Result<Refresh> {
+        let opened = self.file.metadata()?;
+        if let Ok(current) = std::fs::metadata(&self.path) {
+            if (current.dev(), current.ino()) != (opened.dev(), opened.ino()) {
+                info!("{} was replaced, reopening", self.path.display());
+                self.mapping = None;
+                self.file = File::open(&self.path)?;
+                self.len = self.file.metadata()?.len();
+                return Ok(Refresh::Truncated);
+            }
+        }
+
+        let len = opened.len();
+        let refresh = if len < self.len {
+            Refresh::Truncated
+        } else if len > self.len {
+            Refresh::Grown
+        } else {
+            return Ok(Refresh::Unchanged);
+        };
+
+        self.mapping = None;
+        self.len = len;
+        Ok(refresh)
+    }
+
+    /// Copy bytes at `offset` into `buf`, up to the refreshed length. Returns
+    /// the number of bytes copied, which is short at the end of the file.
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let end = self.len.min(offset.saturating_add(buf.len() as u64));
+        if offset >= end {
+            return Ok(0);
+        }
+        let n = (end - offset) as usize;
+
+        if let Some(mapping) = self.mapping() {
+            let start = offset as usize;
+            buf[..n].copy_from_slice(&mapping.mmap[start..start + n]);
+            if !mapping.guard.faulted() {
+                return Ok(n);
+            }
+            warn!(
+                "{} was truncated under the mapping at offset {}, falling back to pread",
+                self.path.display(),
+                offset
+            );
+            self.mapping = None;
+        }
+
+        self.pread(offset, &mut buf[..n])
+    }
+
+    /// Run `decode` over up to `max_len` bytes at `offset`, in place in the
+    /// mapping, or over a `pread` into `scratch` without one. The mapped run
+    /// is redone from `scratch` if the file was truncated under it, so
+    /// `decode` must have no effects beyond its result.
+    pub fn with_bytes<R>(
+        &mut self,
+        offset: u64,
+        max_len: usize,
+        scratch: &mut Vec<u8>,
+        mut decode: impl FnMut(&[u8]) -> R,
+    ) -> Result<R> {
+        let end = self.len.min(offset.saturating_add(max_len as u64));
+        if offset >= end {
+            return Ok(decode(&[]));
+        }
+        let n = (end - offset) as usize;
+
+        if let Some(mapping) = self.mapping() {
+            let start = offset as usize;
+            let decoded = decode(&mapping.mmap[start..start + n]);
+            if !mapping.guard.faulted() {
+                return Ok(decoded);
+            }
+            warn!(
+                "{} was truncated under the mapping at offset {}, falling back to pread",
+                self.path.display(),
+                offset
+            );
+            self.mapping = None;
+        }
+
+        scratch.resize(n, 0);
+        let read = self.pread(offset, scratch)?;
+        Ok(decode(&scratch[..read]))
+    }
+
+    fn pread(&mut self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.fallback_reads += 1;
+        let mut read = 0;
+        while read < buf.len() {
+            match self.file.read_at(&mut buf[read..], offset + read as u64)? {
+                0 => break,
+                bytes => read += bytes,
+            }
+        }
+        Ok(read)
+    }
+
+    fn mapping(&mut self) -> Option<&Mapping> {
+        if self.mapping.is_none() && self.mmap_enabled && !self.is_empty() {
+            // SAFETY: the file is only read through this mapping within the
+            // length observed by `refresh`, and reads run under the SIGBUS
+            // guard, so concurrent truncation cannot crash the process.
+            match unsafe { MmapOptions::new().len(self.len as usize).map(&self.file) } {
+                Ok(mmap) => {
+                    self.mapping = sigbus::GuardedRegion::register(mmap.as_ptr() as usize, mmap.len())
+                        .map(|guard| Mapping { guard, mmap });
+                }
+                Err(e) => warn!("Failed to map {}: {}", self.path.display(), e),
+            }
+        }
+        self.mapping.as_ref()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sigbus {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Once, OnceLock};
+
+    const SLOTS: usize = 64;
+
+    static IN_USE: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+    static STARTS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+    static LENS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+    static FAULTED: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+    static PREVIOUS: OnceLock<libc::sigaction> = OnceLock::new();
+    static INSTALL: Once = Once::new();
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+    /// Install the process-wide SIGBUS handler. Returns whether it is active.
+    pub fn install() -> bool {
+        INSTALL.call_once(|| unsafe {
+            PAGE_SIZE.store(libc::sysconf(libc::_SC_PAGESIZE) as usize, Ordering::Relaxed);
+
+            let mut action: libc::sigaction = std::mem::zeroed();
+            let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) = on_sigbus;
+            action.sa_sigaction = handler as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGBUS, &action, &mut previous) == 0 {
+                let _ = PREVIOUS.set(previous);
+                INSTALLED.store(true, Ordering::Release);
+            }
+        });
+        INSTALLED.load(Ordering::Acquire)
+    }
+
+    extern "C" fn on_sigbus(signum: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+        let addr = unsafe { (*info).si_addr() } as usize;
+        let page_size = PAGE_SIZE.load(Ordering::Relaxed);
+
+        for slot in 0..SLOTS {
+            let start = STARTS[slot].load(Ordering::Acquire);
+            if start == 0 || addr < start || addr >= start + LENS[slot].load(Ordering::Acquire) {
+                continue;
+            }
+            // Replace the page past EOF with zeros so the faulting copy can
+            // complete; the reader sees the flag and discards the data
+            let page = addr & !(page_size - 1);
+            let patched = unsafe {
+                libc::mmap(
+                    page as *mut libc::c_void,
+                    page_size,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                    -1,
+                    0,
+                )
+            };
+            if patched != libc::MAP_FAILED {
+                FAULTED[slot].store(true, Ordering::Release);
+                return;
+            }
+        }
+
+        // Not one of ours: hand over to whatever was installed before
+        unsafe {
+            match PREVIOUS.get() {
+                Some(previous)
+                    if previous.sa_sigaction != libc::SIG_DFL && previous.sa_sigaction != libc::SIG_IGN =>
+                {
+                    if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                        let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                            std::mem::transmute(previous.sa_sigaction);
+                        handler(signum, info, context);
+                    } else {
+                        let handler: extern "C" fn(libc::c_int) = std::mem::transmute(previous.sa_sigaction);
+                        handler(signum);
+                    }
+                }
+                // Restore the default action; returning re-runs the faulting
+                // access, which now terminates the process as it would have
+                _ => {
+                    libc::signal(signum, libc::SIG_DFL);
+                }
+            }
+        }
+    }
+
+    /// An address range whose read faults are absorbed while registered
+    pub struct GuardedRegion {
+        slot: usize,
+    }
+
+    impl GuardedRegion {
+        pub fn register(start: usize, len: usize) -> Option<Self> {
+            let slot = (0..SLOTS).find(|slot| {
+                IN_USE[*slot]
+                    .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            })?;
+            FAULTED[slot].store(false, Ordering::Release);
+            LENS[slot].store(len, Ordering::Release);
+            STARTS[slot].store(start, Ordering::Release);
+            Some(Self { slot })
+        }
+
+        pub fn faulted(&self) -> bool {
+            FAULTED[self.slot].load(Ordering::Acquire)
+        }
+    }
+
+    impl Drop for GuardedRegion {
+        fn drop(&mut self) {
+            STARTS[self.slot].store(0, Ordering::Release);
+            LENS[self.slot].store(0, Ordering::Release);
+            IN_USE[self.slot].store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Without a SIGBUS guard the mapping is never used and reads go to `pread`
+#[cfg(not(target_os = "linux"))]
+mod sigbus {
+    pub fn install() -> bool {
+        false
+    }
+
+    pub struct GuardedRegion;
+
+    impl GuardedRegion {
+        pub fn register(_start: usize, _len: usize) -> Option<Self> {
+            None
+        }
+
+        pub fn faulted(&self) -> bool {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("mmap_reader_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_remap_on_growth() {
+        let path = temp_path("growth");
+        std::fs::write(&path, b"abcd").unwrap();
+        let mut file = MappedFile::open(&path).unwrap();
+
+        let mut buf = [0u8; 8];
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"abcd");
+
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"efgh").unwrap();
+        // Nothing past the refreshed length is visible
+        assert_eq!(file.read_at(4, &mut buf).unwrap(), 0);
+        assert_eq!(file.refresh().unwrap(), Refresh::Grown);
+        assert_eq!(file.read_at(2, &mut buf).unwrap(), 6);
+        assert_eq!(&buf[..6], b"cdefgh");
+        assert_eq!(file.refresh().unwrap(), Refresh::Unchanged);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_truncation_under_mapping_falls_back() {
+        let path = temp_path("truncate");
+        std::fs::write(&path, vec![7u8; 3 * 4096]).unwrap();
+        let mut file = MappedFile::open(&path).unwrap();
+
+        let mut buf = [0u8; 64];
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), 64);
+        let fallbacks = file.fallback_reads();
+
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(100).unwrap();
+        // Still within the stale length: the mapped page is gone, pread is short
+        assert_eq!(file.read_at(8192, &mut buf).unwrap(), 0);
+        assert_eq!(file.fallback_reads(), fallbacks + 1);
+        // Decoding in place over the lost page is redone from pread
+        let mut scratch = Vec::new();
+        let sum = file.with_bytes(4096, 64, &mut scratch, |bytes| bytes.iter().map(|b| *b as usize).sum::<usize>());
+        assert_eq!(sum.unwrap(), 0);
+        assert_eq!(file.fallback_reads(), fallbacks + 2);
+
+        assert_eq!(file.refresh().unwrap(), Refresh::Truncated);
+        assert_eq!(file.len(), 100);
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), 64);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replaced_file_is_reopened() {
+        let path = temp_path("replace");
+        std::fs::write(&path, b"old contents").unwrap();
+        let mut file = MappedFile::open(&path).unwrap();
+
+        let replacement = temp_path("replace.new");
+        std::fs::write(&replacement, b"new contents, longer").unwrap();
+        std::fs::rename(&replacement, &path).unwrap();
+
+        assert_eq!(file.refresh().unwrap(), Refresh::Truncated);
+        let mut buf = [0u8; 3];
+        assert_eq!(file.read_at(0, &mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"new");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
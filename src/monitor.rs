@@ -0,0 +1,297 @@
+//! Terminal dashboard for a running instance.
+//!
+//! Polls `GetStats` and follows `SubscribeAlerts`, redrawing the screen in
+//! place with plain ANSI escapes so it works in any terminal or ssh session.
+//! Update rates are sequence increments between polls.
+
+use anyhow::Result;
+use clap::Args;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+use tonic::Request;
+
+use crate::grpc_server::pb::orderbook_service_client::OrderbookServiceClient;
+use crate::grpc_server::pb::{alert, Alert, AlertsRequest, Empty, StatsResponse};
+
+#[derive(Args, Debug, Clone)]
+pub struct MonitorArgs {
+    /// Instance endpoint
+    #[arg(long, default_value = "http://127.0.0.1:50052")]
+    pub endpoint: String,
+
+    /// Milliseconds between refreshes
+    #[arg(long, default_value = "1000")]
+    pub interval_ms: u64,
+
+    /// Markets shown, busiest first
+    #[arg(long, default_value = "20")]
+    pub rows: usize,
+
+    /// Recent alerts shown
+    #[arg(long, default_value = "5")]
+    pub alerts: usize,
+
+    /// API key sent as x-api-key
+    #[arg(long)]
+    pub api_key: Option<String>,
+}
+
+fn request<T>(message: T, api_key: &Option<String>) -> Result<Request<T>> {
+    let mut request = Request::new(message);
+    if let Some(key) = api_key {
+        request.metadata_mut().insert("x-api-key", key.parse()?);
+    }
+    Ok(request)
+}
+
+/// Keep the newest `limit` alerts, reconnecting whenever the stream ends
+async fn follow_alerts(args: MonitorArgs, alerts: Arc<Mutex<VecDeque<Alert>>>) {
+    loop {
+        let result: Result<()> = async {
+            let mut client = OrderbookServiceClient::connect(args.endpoint.clone()).await?;
+            let mut stream = client
+                .subscribe_alerts(request(AlertsRequest { include_recent: true }, &args.api_key)?)
+                .await?
+                .into_inner();
+            alerts.lock().clear();
+            while let Some(alert) = stream.next().await {
+                let mut alerts = alerts.lock();
+                alerts.push_front(alert?);
+                alerts.truncate(args.alerts);
+            }
+            Ok(())
+        }
+        .await;
+        // The dashboard header already shows connection problems
+        let _ = result;
+        tokio::time::sleep(Duration::from_millis(args.interval_ms.max(100))).await;
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        s if s >= 3600 => format!("{}h{:02}m{:02}s", s / 3600, s / 60 % 60, s % 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+fn format_ms(ms: Option<u64>) -> String {
+    match ms {
+        Some(ms) if ms >= 10_000 => format_duration(ms / 1000),
+        Some(ms) => format!("{}ms", ms),
+        None => "-".to_string(),
+    }
+}
+
+fn describe_alert(alert: &Alert) -> String {
+    match &alert.kind {
+        Some(alert::Kind::ErrorRateSpike(spike)) => {
+            format!("{} errors: {} in {}s", spike.category, spike.count, spike.window_secs)
+        }
+        Some(alert::Kind::Degradation(state)) if state.degraded => {
+            format!("degraded: {}", state.reasons.join(", "))
+        }
+        Some(alert::Kind::Degradation(_)) => "recovered from degradation".to_string(),
+        Some(alert::Kind::Contagion(contagion)) => {
+            let symbols: Vec<&str> = contagion.markets.iter().map(|m| m.symbol.as_str()).collect();
+            format!("contagion in {}: {} of {} stressed", contagion.group, symbols.join(", "), contagion.group_size)
+        }
+        None => String::new(),
+    }
+}
+
+/// One frame of the dashboard. `rates` are updates per second by market id.
+fn render(
+    endpoint: &str,
+    stats: &StatsResponse,
+    rates: &HashMap<u32, f64>,
+    alerts: &[Alert],
+    rows: usize,
+    error: Option<&str>,
+) -> String {
+    let mut frame = String::new();
+    let total_rate: f64 = rates.values().sum();
+    let _ = writeln!(
+        frame,
+        "{}  up {}  {:.0} upd/s  lines {}  {:.1} MB  trades {}  subscribers {}  {}",
+        endpoint,
+        format_duration(stats.uptime_secs),
+        total_rate,
+        stats.lines_read,
+        stats.bytes_read as f64 / 1_048_576.0,
+        stats.trades_read,
+        stats.subscribers,
+        if stats.degraded { "DEGRADED" } else { "ok" },
+    );
+
+    let open: Vec<&str> = stats
+        .markets
+        .iter()
+        .filter(|m| m.circuit != "CLOSED")
+        .map(|m| m.symbol.as_str())
+        .collect();
+    let _ = writeln!(
+        frame,
+        "validation circuit {}  open market circuits {}{}",
+        stats.validation_circuit,
+        open.len(),
+        if open.is_empty() { String::new() } else { format!(" ({})", open.join(", ")) },
+    );
+    if let Some(error) = error {
+        let _ = writeln!(frame, "last refresh failed: {}", error);
+    }
+
+    let _ = writeln!(
+        frame,
+        "\n{:>7} {:<14} {:>9} {:>12} {:>8} {:>8} {:>6} {:>6} {:>7} {:<9} {:<5} {:>4}",
+        "ID", "SYMBOL", "UPD/S", "SEQUENCE", "LAG", "IDLE", "BIDS", "ASKS", "ORDERS", "CIRCUIT", "TIER", "SUBS"
+    );
+    let mut markets: Vec<_> = stats.markets.iter().collect();
+    markets.sort_by(|a, b| {
+        let rate = |id| rates.get(id).copied().unwrap_or(0.0);
+        rate(&b.market_id)
+            .total_cmp(&rate(&a.market_id))
+            .then(b.subscribers.cmp(&a.subscribers))
+            .then(a.market_id.cmp(&b.market_id))
+    });
+    for market in markets.into_iter().take(rows) {
+        let _ = writeln!(
+            frame,
+            "{:>7} {:<14} {:>9.1} {:>12} {:>8} {:>8} {:>6} {:>6} {:>7} {:<9} {:<5} {:>4}",
+            market.market_id,
+            market.symbol,
+            rates.get(&market.market_id).copied().unwrap_or(0.0),
+            market.sequence,
+            format_ms(market.lag_ms),
+            format_ms(market.idle_ms),
+            market.bid_levels,
+            market.ask_levels,
+            market.orders,
+            market.circuit,
+            market.tier,
+            market.subscribers,
+        );
+    }
+
+    let _ = writeln!(frame, "\nRecent alerts");
+    if alerts.is_empty() {
+        let _ = writeln!(frame, "  none");
+    }
+    for alert in alerts {
+        let time = chrono::DateTime::from_timestamp_millis(alert.timestamp)
+            .map(|t| t.format("%H:%M:%S").to_string())
+            .unwrap_or_default();
+        let _ = writeln!(frame, "  {} {:<8} {}", time, alert.severity, describe_alert(alert));
+    }
+    frame
+}
+
+pub async fn run(args: MonitorArgs) -> Result<()> {
+    let interval = Duration::from_millis(args.interval_ms.max(100));
+    let alerts = Arc::new(Mutex::new(VecDeque::new()));
+    tokio::spawn(follow_alerts(args.clone(), alerts.clone()));
+
+    let mut client: Option<OrderbookServiceClient<Channel>> = None;
+    let mut stats = StatsResponse::default();
+    let mut previous: Option<(Instant, HashMap<u32, u64>)> = None;
+    let mut rates = HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+
+        let result: Result<StatsResponse> = async {
+            if client.is_none() {
+                client = Some(OrderbookServiceClient::connect(args.endpoint.clone()).await?);
+            }
+            let response = client.as_mut().unwrap().get_stats(request(Empty {}, &args.api_key)?).await;
+            Ok(response.inspect_err(|_| client = None)?.into_inner())
+        }
+        .await;
+
+        let error = match result {
+            Ok(latest) => {
+                let now = Instant::now();
+                let sequences: HashMap<u32, u64> = latest.markets.iter().map(|m| (m.market_id, m.sequence)).collect();
+                if let Some((then, before)) = &previous {
+                    let elapsed = now.duration_since(*then).as_secs_f64().max(1e-3);
+                    rates = sequences
+                        .iter()
+                        .map(|(id, sequence)| {
+                            let delta = sequence.saturating_sub(before.get(id).copied().unwrap_or(*sequence));
+                            (*id, delta as f64 / elapsed)
+                        })
+                        .collect();
+                }
+                previous = Some((now, sequences));
+                stats = latest;
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        };
+
+        let alerts: Vec<Alert> = alerts.lock().iter().cloned().collect();
+        let frame = render(&args.endpoint, &stats, &rates, &alerts, args.rows, error.as_deref());
+        // Home the cursor and clear, then draw
+        let mut stdout = std::io::stdout().lock();
+        write!(stdout, "\x1b[H\x1b[2J{}", frame)?;
+        stdout.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc_server::pb::{DegradationState, MarketStats};
+
+    #[test]
+    fn test_render_orders_markets_by_rate() {
+        let market = |market_id: u32, symbol: &str, circuit: &str| MarketStats {
+            market_id,
+            symbol: symbol.to_string(),
+            circuit: circuit.to_string(),
+            tier: "HOT".to_string(),
+            lag_ms: Some(12),
+            ..Default::default()
+        };
+        let stats = StatsResponse {
+            uptime_secs: 3725,
+            subscribers: 2,
+            degraded: true,
+            validation_circuit: "CLOSED".to_string(),
+            markets: vec![market(0, "BTC", "CLOSED"), market(1, "ETH", "OPEN"), market(5, "SOL", "CLOSED")],
+            ..Default::default()
+        };
+        let rates = [(0, 10.0), (1, 50.0), (5, 1.0)].into_iter().collect();
+        let alerts = vec![Alert {
+            timestamp: 0,
+            severity: "critical".to_string(),
+            kind: Some(alert::Kind::Degradation(DegradationState {
+                degraded: true,
+                reasons: vec!["book backlog 5000 > 4096".to_string()],
+                ..Default::default()
+            })),
+            ..Default::default()
+        }];
+
+        let frame = render("http://host:50052", &stats, &rates, &alerts, 2, None);
+        assert!(frame.contains("up 1h02m05s") && frame.contains("61 upd/s") && frame.contains("DEGRADED"));
+        assert!(frame.contains("open market circuits 1 (ETH)"));
+        assert!(frame.contains("degraded: book backlog 5000 > 4096"));
+
+        // Busiest first, cut at the row limit
+        let (eth, btc) = (frame.find(" ETH ").unwrap(), frame.find(" BTC ").unwrap());
+        assert!(eth < btc);
+        assert!(!frame.contains(" SOL "));
+    }
+}
@@ -0,0 +1,175 @@
+//! Layout of the order status feed across node versions.
+//!
+//! Older nodes write one status per line to `node_order_statuses`. Newer
+//! ones can instead write one line per block to
+//! `node_order_statuses_by_block`, wrapping the block's statuses in an
+//! envelope. The format is detected from the first line of the newest file,
+//! or from the directory name when there is no file yet.
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::info;
+
+use crate::hourly_path::HourlyLayout;
+
+/// Bytes of a file read to fingerprint it. Block lines can run to megabytes,
+/// but their envelope keys come first.
+const FINGERPRINT_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeFormat {
+    /// `{"time", "user", "status", "order"}` per line
+    Statuses,
+    /// `{"block_number", "block_time", "events": [status, ...]}` per line
+    StatusesByBlock,
+}
+
+impl NodeFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeFormat::Statuses => "statuses",
+            NodeFormat::StatusesByBlock => "statuses-by-block",
+        }
+    }
+
+    /// The order statuses in one line of the feed
+    pub fn statuses<'a>(&self, line: &'a str) -> Result<Vec<Cow<'a, str>>> {
+        match self {
+            NodeFormat::Statuses => Ok(vec![Cow::Borrowed(line)]),
+            NodeFormat::StatusesByBlock => match serde_json::from_str::<Value>(line)?.get("events") {
+                Some(Value::Array(events)) => Ok(events.iter().map(|e| Cow::Owned(e.to_string())).collect()),
+                _ => Err(anyhow!("Block without events")),
+            },
+        }
+    }
+
+    /// Which format the start of a file is in, if it says
+    fn fingerprint(head: &str) -> Option<Self> {
+        let events = head.find("\"events\"");
+        let order = head.find("\"order\"");
+        match (events, order) {
+            (Some(events), Some(order)) if events < order => Some(NodeFormat::StatusesByBlock),
+            (Some(_), None) => Some(NodeFormat::StatusesByBlock),
+            (_, Some(_)) => Some(NodeFormat::Statuses),
+            (None, None) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for NodeFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for NodeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "statuses" => Ok(NodeFormat::Statuses),
+            "statuses-by-block" => Ok(NodeFormat::StatusesByBlock),
+            other => Err(anyhow!("Unknown node format: {} (expected statuses or statuses-by-block)", other)),
+        }
+    }
+}
+
+/// The format in use and what it was decided from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detection {
+    pub format: NodeFormat,
+    /// configured, file, directory or default
+    pub source: &'static str,
+}
+
+impl Default for Detection {
+    fn default() -> Self {
+        Self { format: NodeFormat::Statuses, source: "default" }
+    }
+}
+
+fn file_head(path: &Path) -> Option<String> {
+    let mut head = Vec::new();
+    std::fs::File::open(path).ok()?.take(FINGERPRINT_BYTES).read_to_end(&mut head).ok()?;
+    let first_line = head.split(|b| *b == b'\n').next()?;
+    Some(String::from_utf8_lossy(first_line).into_owned())
+}
+
+/// Use `configured` if set, else detect the format of the files under `layout`
+pub fn detect(layout: &HourlyLayout, configured: Option<NodeFormat>) -> Detection {
+    let detection = match configured {
+        Some(format) => Detection { format, source: "configured" },
+        None => {
+            let from_file = layout
+                .newest_file()
+                .and_then(|path| file_head(&path))
+                .and_then(|head| NodeFormat::fingerprint(&head))
+                .map(|format| Detection { format, source: "file" });
+            let from_directory = || {
+                let by_block = layout.root.components().any(|c| c.as_os_str().to_string_lossy().ends_with("_by_block"));
+                by_block.then_some(Detection { format: NodeFormat::StatusesByBlock, source: "directory" })
+            };
+            from_file.or_else(from_directory).unwrap_or_default()
+        }
+    };
+
+    info!("Node feed format: {} ({})", detection.format, detection.source);
+    detection
+}
+
+/// Use `configured` if set, else detect the format of the file at `path`
+pub fn detect_file(path: &Path, configured: Option<NodeFormat>) -> Detection {
+    let detection = match configured {
+        Some(format) => Detection { format, source: "configured" },
+        None => file_head(path)
+            .and_then(|head| NodeFormat::fingerprint(&head))
+            .map(|format| Detection { format, source: "file" })
+            .unwrap_or_default(),
+    };
+
+    info!("Node feed format: {} ({})", detection.format, detection.source);
+    detection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = r#"{"time":"2025-03-01T07:00:00.1","user":"0xabc","status":"open","order":{"coin":"BTC","side":"B","limitPx":"105000.0","sz":"0.1","oid":1,"timestamp":1740812400000}}"#;
+
+    #[test]
+    fn test_detect_and_split() {
+        let block = format!(r#"{{"local_time":"2025-03-01T07:00:00.2","block_time":"2025-03-01T07:00:00.1","block_number":7,"events":[{},{}]}}"#, STATUS, STATUS);
+
+        let dir = std::env::temp_dir().join(format!("node_format_test_{}", std::process::id()));
+        let root = dir.join("node_order_statuses_by_block").join("hourly");
+        std::fs::create_dir_all(root.join("20250301")).unwrap();
+        let layout = HourlyLayout::new(&root);
+
+        // Nothing written yet: the directory name decides
+        assert_eq!(detect(&layout, None), Detection { format: NodeFormat::StatusesByBlock, source: "directory" });
+
+        // The first line wins over the directory
+        std::fs::write(root.join("20250301").join("7"), format!("{}\n", STATUS)).unwrap();
+        assert_eq!(detect(&layout, None), Detection { format: NodeFormat::Statuses, source: "file" });
+        std::fs::write(root.join("20250301").join("8"), format!("{}\n", block)).unwrap();
+        assert_eq!(detect(&layout, None).format, NodeFormat::StatusesByBlock);
+        assert_eq!(detect(&layout, Some(NodeFormat::Statuses)).source, "configured");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Each status of a block parses on its own
+        let statuses = NodeFormat::StatusesByBlock.statuses(&block).unwrap();
+        assert_eq!(statuses.len(), 2);
+        let parser = crate::order_parser::OrderParser::new();
+        assert!(statuses.iter().all(|status| parser.parse_line(status).is_ok()));
+        assert!(NodeFormat::StatusesByBlock.statuses(STATUS).is_err());
+        assert_eq!(NodeFormat::Statuses.statuses(STATUS).unwrap(), vec![Cow::Borrowed(STATUS)]);
+
+        assert_eq!(NodeFormat::fingerprint("garbage"), None);
+        assert_eq!("statuses-by-block".parse::<NodeFormat>().unwrap(), NodeFormat::StatusesByBlock);
+    }
+}
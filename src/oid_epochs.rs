@@ -0,0 +1,177 @@
+//! Detection of order ids the node reuses.
+//!
+//! Books key resting orders by oid, so an oid opened again while its first
+//! order still rests would silently merge two orders. Each book's actor
+//! remembers when, on which side and at what price every live oid was
+//! opened. A repeat open with the same details is the same order delivered
+//! twice and is dropped. A different one is a new generation of the oid and
+//! replaces the old order. Both, and oids dropping far below the highest
+//! seen, are counted in the book's integrity stats.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::fast_orderbook::{FastOrderbook, Order};
+
+/// Feed anomalies a book has absorbed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IntegrityStats {
+    /// Opens of a live oid with identical details, dropped
+    pub duplicate_opens: u64,
+    /// Opens of a live oid as a different order, replacing it
+    pub oid_reuses: u64,
+    /// Opens with an oid below half the highest seen
+    pub oid_wraparounds: u64,
+    /// Times the book's best bid reached its best ask
+    pub crossed_books: u64,
+    /// Crossed orders evicted to repair the book
+    pub crossed_evictions: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct IntegrityCounters {
+    duplicate_opens: AtomicU64,
+    oid_reuses: AtomicU64,
+    oid_wraparounds: AtomicU64,
+    crossed_books: AtomicU64,
+    crossed_evictions: AtomicU64,
+}
+
+impl IntegrityCounters {
+    pub fn stats(&self) -> IntegrityStats {
+        IntegrityStats {
+            duplicate_opens: self.duplicate_opens.load(Ordering::Relaxed),
+            oid_reuses: self.oid_reuses.load(Ordering::Relaxed),
+            oid_wraparounds: self.oid_wraparounds.load(Ordering::Relaxed),
+            crossed_books: self.crossed_books.load(Ordering::Relaxed),
+            crossed_evictions: self.crossed_evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn crossed(&self) {
+        self.crossed_books.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn evicted(&self, orders: usize) {
+        self.crossed_evictions.fetch_add(orders as u64, Ordering::Relaxed);
+    }
+}
+
+/// When and where a live oid was opened
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OidEpoch {
+    /// 0 for orders restored without their open time
+    pub first_seen_ms: u64,
+    pub price: f64,
+    pub is_buy: bool,
+    /// Times the oid was reused while live
+    pub generation: u32,
+}
+
+/// What to do with an open
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenCheck {
+    New,
+    /// Already resting as exactly this order; skip it
+    Duplicate,
+    /// A new generation; the previous order must leave the book first
+    Reused(OidEpoch),
+}
+
+/// Live oids of one book
+#[derive(Debug, Default)]
+pub struct OidEpochs {
+    live: HashMap<u64, OidEpoch>,
+    highest: u64,
+}
+
+impl OidEpochs {
+    /// Track the orders already resting in `orderbook`, e.g. restored ones
+    pub fn from_book(orderbook: &FastOrderbook) -> Self {
+        let orders = orderbook.orders();
+        let mut epochs = Self::default();
+        for (resting, is_buy) in [(orders.bids, true), (orders.asks, false)] {
+            for order in resting {
+                epochs.highest = epochs.highest.max(order.id);
+                let epoch = OidEpoch { first_seen_ms: order.timestamp, price: order.price, is_buy, generation: 0 };
+                epochs.live.insert(order.id, epoch);
+            }
+        }
+        epochs
+    }
+
+    pub fn open(&mut self, order: &Order, is_buy: bool, counters: &IntegrityCounters) -> OpenCheck {
+        if order.id < self.highest / 2 {
+            counters.oid_wraparounds.fetch_add(1, Ordering::Relaxed);
+            self.highest = order.id;
+        }
+        self.highest = self.highest.max(order.id);
+
+        let epoch = OidEpoch { first_seen_ms: order.timestamp, price: order.price, is_buy, generation: 0 };
+        match self.live.insert(order.id, epoch) {
+            None => OpenCheck::New,
+            Some(previous)
+                if (previous.first_seen_ms == epoch.first_seen_ms || previous.first_seen_ms == 0)
+                    && previous.price == epoch.price
+                    && previous.is_buy == epoch.is_buy =>
+            {
+                self.live.insert(order.id, previous);
+                counters.duplicate_opens.fetch_add(1, Ordering::Relaxed);
+                OpenCheck::Duplicate
+            }
+            Some(previous) => {
+                self.live.insert(order.id, OidEpoch { generation: previous.generation + 1, ..epoch });
+                counters.oid_reuses.fetch_add(1, Ordering::Relaxed);
+                OpenCheck::Reused(previous)
+            }
+        }
+    }
+
+    /// The oid's order now rests at `price`, so a repeat of the modify's
+    /// open isn't taken for a new generation
+    pub fn modified(&mut self, oid: u64, price: f64) {
+        if let Some(epoch) = self.live.get_mut(&oid) {
+            epoch.price = price;
+        }
+    }
+
+    pub fn close(&mut self, oid: u64) {
+        self.live.remove(&oid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_reuse_and_wraparound() {
+        let counters = IntegrityCounters::default();
+        let mut epochs = OidEpochs::default();
+        let order = |id, price, timestamp| Order { id, price, size: 1.0, timestamp };
+
+        assert_eq!(epochs.open(&order(1000, 100.0, 5), true, &counters), OpenCheck::New);
+        assert_eq!(epochs.open(&order(1000, 100.0, 5), true, &counters), OpenCheck::Duplicate);
+
+        let reused = epochs.open(&order(1000, 90.0, 9), false, &counters);
+        assert_eq!(reused, OpenCheck::Reused(OidEpoch { first_seen_ms: 5, price: 100.0, is_buy: true, generation: 0 }));
+        let again = epochs.open(&order(1000, 91.0, 10), false, &counters);
+        assert_eq!(again, OpenCheck::Reused(OidEpoch { first_seen_ms: 9, price: 90.0, is_buy: false, generation: 1 }));
+
+        // Once closed, the oid opens as new
+        epochs.close(1000);
+        assert_eq!(epochs.open(&order(1000, 90.0, 12), false, &counters), OpenCheck::New);
+
+        // A small step back is late writes, not a wrap
+        assert_eq!(epochs.open(&order(990, 100.0, 13), true, &counters), OpenCheck::New);
+        assert_eq!(epochs.open(&order(3, 100.0, 14), true, &counters), OpenCheck::New);
+        assert_eq!(counters.stats(), IntegrityStats { duplicate_opens: 1, oid_reuses: 2, oid_wraparounds: 1, ..Default::default() });
+
+        // Restored orders have no open time, so any matching open is theirs
+        let book = FastOrderbook::new(0, "BTC".to_string());
+        book.add_order(Order { id: 7, price: 50.0, size: 1.0, timestamp: 0 }, false);
+        let mut epochs = OidEpochs::from_book(&book);
+        assert_eq!(epochs.open(&order(7, 50.0, 30), false, &counters), OpenCheck::Duplicate);
+        assert!(matches!(epochs.open(&order(7, 51.0, 31), false, &counters), OpenCheck::Reused(_)));
+    }
+}
@@ -0,0 +1,166 @@
+//! Open interest per perp market, kept current from the node's fills.
+//!
+//! Each fill carries its user's signed position before it, so the change it
+//! makes to that user's long size is known without tracking users. Summed
+//! over both sides of every match, those changes move the market's total
+//! long size, which is its open interest. The running sums start from the
+//! public API's asset contexts, which are fetched again periodically to
+//! correct drift from missed fills; markets only appear once the API
+//! reports them, so spot markets never do.
+
+use anyhow::Result;
+use parking_lot::RwLock;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::dynamic_markets::DynamicMarketRegistry;
+
+const API_URL: &str = "https://api.hyperliquid.xyz/info";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpenInterest {
+    pub market_id: u32,
+    /// Total long size, equal to the total short size, in coins
+    pub size: f64,
+    /// Time of the last fill or baseline applied, ms since epoch
+    pub updated_ms: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Meta {
+    universe: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AssetCtx {
+    #[serde(rename = "openInterest")]
+    open_interest: Option<String>,
+}
+
+#[derive(Default)]
+pub struct OpenInterestTracker {
+    markets: RwLock<HashMap<u32, OpenInterest>>,
+}
+
+impl OpenInterestTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply one side of a match: the user's signed position before it, and
+    /// the fill's size and side
+    pub fn apply_fill(&self, market_id: u32, start_position: f64, size: f64, is_buy: bool, time_ms: u64) {
+        let end_position = start_position + if is_buy { size } else { -size };
+        let long_change = end_position.max(0.0) - start_position.max(0.0);
+        if let Some(market) = self.markets.write().get_mut(&market_id) {
+            market.size = (market.size + long_change).max(0.0);
+            market.updated_ms = market.updated_ms.max(time_ms);
+        }
+    }
+
+    /// Replace a market's running sum with an authoritative value
+    pub fn set_baseline(&self, market_id: u32, size: f64, time_ms: u64) {
+        self.markets.write().insert(market_id, OpenInterest { market_id, size, updated_ms: time_ms });
+    }
+
+    pub fn get(&self, market_id: u32) -> Option<OpenInterest> {
+        self.markets.read().get(&market_id).copied()
+    }
+
+    /// Every tracked market, by id
+    pub fn all(&self) -> Vec<OpenInterest> {
+        let mut markets: Vec<_> = self.markets.read().values().copied().collect();
+        markets.sort_by_key(|market| market.market_id);
+        markets
+    }
+
+    /// Fetch baselines from the Hyperliquid API now and every `interval`
+    pub fn start_baseline_feed(self: &Arc<Self>, registry: Arc<DynamicMarketRegistry>, interval: Duration) {
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match Self::fetch_baselines(&client).await {
+                    Ok(baselines) => {
+                        let time_ms = chrono::Utc::now().timestamp_millis() as u64;
+                        let mut applied = 0;
+                        for (coin, size) in baselines {
+                            if let Some(market_id) = registry.get_market_id(&coin).await {
+                                tracker.set_baseline(market_id, size, time_ms);
+                                applied += 1;
+                            }
+                        }
+                        info!("Open interest baseline set for {} markets", applied);
+                    }
+                    Err(e) => error!("Failed to fetch open interest: {}", e),
+                }
+            }
+        });
+    }
+
+    async fn fetch_baselines(client: &reqwest::Client) -> Result<Vec<(String, f64)>> {
+        let (meta, ctxs): (Meta, Vec<AssetCtx>) = client
+            .post(API_URL)
+            .json(&serde_json::json!({"type": "metaAndAssetCtxs"}))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        Ok(meta
+            .universe
+            .into_iter()
+            .zip(ctxs)
+            .filter_map(|(asset, ctx)| Some((asset.name, ctx.open_interest?.parse::<f64>().ok()?)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fills_move_open_interest() {
+        let tracker = OpenInterestTracker::new();
+        // Untracked until a baseline arrives
+        tracker.apply_fill(0, 0.0, 1.0, true, 1);
+        assert_eq!(tracker.get(0), None);
+
+        tracker.set_baseline(0, 10.0, 100);
+        // A new long against a new short: both sides open
+        tracker.apply_fill(0, 0.0, 2.0, true, 101);
+        tracker.apply_fill(0, 0.0, 2.0, false, 101);
+        assert_eq!(tracker.get(0).unwrap().size, 12.0);
+
+        // A long closing against a short closing
+        tracker.apply_fill(0, 2.0, 1.5, false, 102);
+        tracker.apply_fill(0, -2.0, 1.5, true, 102);
+        assert_eq!(tracker.get(0).unwrap().size, 10.5);
+
+        // A long flipping short (3 long -> 1 short) against a new long of 4:
+        // 3 longs close and 4 open
+        tracker.apply_fill(0, 3.0, 4.0, false, 103);
+        tracker.apply_fill(0, 0.0, 4.0, true, 103);
+        assert_eq!(tracker.get(0).unwrap(), OpenInterest { market_id: 0, size: 11.5, updated_ms: 103 });
+
+        // Transferring a long from one user to another leaves it unchanged
+        tracker.apply_fill(0, 5.0, 5.0, false, 104);
+        tracker.apply_fill(0, 0.0, 5.0, true, 104);
+        assert_eq!(tracker.get(0).unwrap().size, 11.5);
+
+        tracker.set_baseline(1, 3.0, 104);
+        assert_eq!(tracker.all().iter().map(|oi| oi.market_id).collect::<Vec<_>>(), vec![0, 1]);
+    }
+}
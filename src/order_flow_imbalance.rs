@@ -0,0 +1,284 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::degradation::DegradationMonitor;
+use crate::fast_orderbook::FastOrderbook;
+use crate::market_processor::MarketUpdate;
+use crate::market_tiers::MarketTiers;
+
+/// Top of book: (bid price, bid size, ask price, ask size)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TopOfBook {
+    pub bid_price: f64,
+    pub bid_size: f64,
+    pub ask_price: f64,
+    pub ask_size: f64,
+}
+
+impl TopOfBook {
+    pub fn from_orderbook(orderbook: &FastOrderbook) -> Option<Self> {
+        let (bids, asks) = orderbook.get_snapshot(1);
+        match (bids.first(), asks.first()) {
+            (Some(&(bid_price, bid_size)), Some(&(ask_price, ask_size))) => Some(Self {
+                bid_price,
+                bid_size,
+                ask_price,
+                ask_size,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Order flow imbalance over one horizon
+#[derive(Debug, Clone, Copy)]
+pub struct OfiValue {
+    pub horizon: Duration,
+    pub ofi: f64,
+    pub event_count: u32,
+}
+
+/// Rolling OFI (Cont, Kukanov & Stoikov 2014) over several horizons.
+///
+/// Each top-of-book change contributes
+/// `e = 1{Pb >= Pb'}·qb − 1{Pb <= Pb'}·qb' − 1{Pa <= Pa'}·qa + 1{Pa >= Pa'}·qa'`
+/// where primes denote the previous quote; OFI over a horizon is the sum of
+/// `e` for changes inside that window.
+#[derive(Debug, Clone)]
+pub struct OfiCalculator {
+    horizons: Vec<Duration>,
+    max_horizon: Duration,
+    last_top: Option<TopOfBook>,
+    events: VecDeque<(Instant, f64)>,
+}
+
+impl OfiCalculator {
+    pub fn new(mut horizons: Vec<Duration>) -> Self {
+        horizons.sort();
+        horizons.dedup();
+        let max_horizon = horizons.last().copied().unwrap_or_default();
+
+        Self {
+            horizons,
+            max_horizon,
+            last_top: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Feed the current top of book; unchanged quotes contribute nothing
+    pub fn on_top_of_book(&mut self, now: Instant, top: TopOfBook) {
+        if let Some(prev) = self.last_top {
+            if prev != top {
+                let mut e = 0.0;
+                if top.bid_price >= prev.bid_price {
+                    e += top.bid_size;
+                }
+                if top.bid_price <= prev.bid_price {
+                    e -= prev.bid_size;
+                }
+                if top.ask_price <= prev.ask_price {
+                    e -= top.ask_size;
+                }
+                if top.ask_price >= prev.ask_price {
+                    e += prev.ask_size;
+                }
+                self.events.push_back((now, e));
+            }
+        }
+        self.last_top = Some(top);
+        self.prune(now);
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((t, _)) = self.events.front() {
+            if now.duration_since(*t) > self.max_horizon {
+                self.events.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    pub fn values(&mut self, now: Instant) -> Vec<OfiValue> {
+        self.prune(now);
+
+        self.horizons
+            .iter()
+            .map(|horizon| {
+                let mut ofi = 0.0;
+                let mut event_count = 0;
+                for (t, e) in self.events.iter().rev() {
+                    if now.duration_since(*t) > *horizon {
+                        break;
+                    }
+                    ofi += e;
+                    event_count += 1;
+                }
+                OfiValue {
+                    horizon: *horizon,
+                    ofi,
+                    event_count,
+                }
+            })
+            .collect()
+    }
+
+    /// Whether any change is still inside the longest horizon
+    pub fn is_active(&self) -> bool {
+        !self.events.is_empty()
+    }
+}
+
+/// OFI values for one market at one instant
+#[derive(Debug, Clone)]
+pub struct OfiSnapshot {
+    pub market_id: u32,
+    pub timestamp_us: i64,
+    pub values: Vec<OfiValue>,
+}
+
+/// Computes OFI for hot markets from the update stream and publishes it
+/// every `publish_interval`
+pub struct OfiEngine {
+    horizons: Vec<Duration>,
+    publish_interval: Duration,
+    output_tx: broadcast::Sender<Arc<OfiSnapshot>>,
+}
+
+impl OfiEngine {
+    pub fn new(horizons: Vec<Duration>) -> Self {
+        let publish_interval = horizons
+            .iter()
+            .min()
+            .copied()
+            .unwrap_or(Duration::from_millis(100))
+            .max(Duration::from_millis(10));
+
+        Self {
+            horizons,
+            publish_interval,
+            output_tx: broadcast::channel(10_000).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<OfiSnapshot>> {
+        self.output_tx.subscribe()
+    }
+
+    pub fn start(
+        self: Arc<Self>,
+        orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+        mut update_rx: broadcast::Receiver<MarketUpdate>,
+        market_tiers: Arc<MarketTiers>,
+        degradation: Arc<DegradationMonitor>,
+    ) {
+        info!(
+            "Starting OFI engine (horizons: {:?}, publish every {:?})",
+            self.horizons, self.publish_interval
+        );
+
+        tokio::spawn(async move {
+            let mut calculators: HashMap<u32, OfiCalculator> = HashMap::new();
+            let mut ticker = tokio::time::interval(self.publish_interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+            loop {
+                tokio::select! {
+                    result = update_rx.recv() => {
+                        let update = match result {
+                            Ok(update) => update,
+                            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+
+                        // Analytics are only computed for hot markets, and
+                        // pause while the service is degraded
+                        if !market_tiers.is_hot(update.market_id) || degradation.is_degraded() {
+                            continue;
+                        }
+
+                        if let Some(top) = orderbooks.get(&update.market_id).and_then(|ob| TopOfBook::from_orderbook(ob)) {
+                            calculators
+                                .entry(update.market_id)
+                                .or_insert_with(|| OfiCalculator::new(self.horizons.clone()))
+                                .on_top_of_book(Instant::now(), top);
+                        }
+                    }
+                    _ = ticker.tick() => {
+                        // Restart from fresh books afterwards rather than
+                        // attributing the whole pause to one interval
+                        if degradation.is_degraded() {
+                            calculators.clear();
+                            continue;
+                        }
+                        if self.output_tx.receiver_count() == 0 {
+                            continue;
+                        }
+
+                        let now = Instant::now();
+                        let timestamp_us = chrono::Utc::now().timestamp_micros();
+                        for (market_id, calculator) in calculators.iter_mut() {
+                            if !calculator.is_active() {
+                                continue;
+                            }
+                            let _ = self.output_tx.send(Arc::new(OfiSnapshot {
+                                market_id: *market_id,
+                                timestamp_us,
+                                values: calculator.values(now),
+                            }));
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn top(bid_price: f64, bid_size: f64, ask_price: f64, ask_size: f64) -> TopOfBook {
+        TopOfBook { bid_price, bid_size, ask_price, ask_size }
+    }
+
+    #[test]
+    fn test_ofi_contributions() {
+        let mut calc = OfiCalculator::new(vec![Duration::from_secs(1)]);
+        let t0 = Instant::now();
+
+        calc.on_top_of_book(t0, top(100.0, 5.0, 101.0, 5.0));
+        // Bid size grows at same price: +3
+        calc.on_top_of_book(t0, top(100.0, 8.0, 101.0, 5.0));
+        // Ask price improves with size 2: previous ask not included, -2
+        calc.on_top_of_book(t0, top(100.0, 8.0, 100.5, 2.0));
+        // Bid level removed, new lower bid: -8
+        calc.on_top_of_book(t0, top(99.5, 1.0, 100.5, 2.0));
+
+        let values = calc.values(t0);
+        assert_eq!(values[0].event_count, 3);
+        assert!((values[0].ofi - (3.0 - 2.0 - 8.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_horizons_expire_independently() {
+        let mut calc = OfiCalculator::new(vec![Duration::from_millis(100), Duration::from_secs(1)]);
+        let t0 = Instant::now();
+
+        calc.on_top_of_book(t0, top(100.0, 1.0, 101.0, 1.0));
+        calc.on_top_of_book(t0, top(100.0, 2.0, 101.0, 1.0));
+
+        let values = calc.values(t0 + Duration::from_millis(500));
+        assert_eq!(values[0].event_count, 0);
+        assert_eq!(values[1].event_count, 1);
+        assert!((values[1].ofi - 1.0).abs() < 1e-9);
+
+        let values = calc.values(t0 + Duration::from_secs(2));
+        assert!(values.iter().all(|v| v.event_count == 0));
+        assert!(!calc.is_active());
+    }
+}
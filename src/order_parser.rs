@@ -1,10 +1,14 @@
 use anyhow::{bail, Result};
 use serde::{Deserialize, Deserializer};
 use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::{debug, error, warn};
 
+use crate::alerts::{AlertKind, Alerts, Severity};
+
 /// Structured order message matching Hyperliquid's format
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -38,7 +42,7 @@ pub struct RawOrder {
 }
 
 /// Deserialize price from either string or number
-fn deserialize_price<'de, D>(deserializer: D) -> Result<f64, D::Error>
+pub(crate) fn deserialize_price<'de, D>(deserializer: D) -> Result<f64, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -57,7 +61,7 @@ where
 }
 
 /// Deserialize size from either string or number
-fn deserialize_size<'de, D>(deserializer: D) -> Result<f64, D::Error>
+pub(crate) fn deserialize_size<'de, D>(deserializer: D) -> Result<f64, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -94,7 +98,12 @@ pub struct ValidatedOrder {
 pub enum OrderStatus {
     Open,
     Filled,
+    /// Every cancel reason: user, margin, reduce-only, sibling filled, ...
     Canceled,
+    /// A trigger order's price was hit; it becomes a regular order
+    Triggered,
+    /// A resting order's size or price was changed, keeping its oid
+    Modified,
     Rejected(String),  // Store rejection reason
     Unknown(String),   // Store unknown status
 }
@@ -104,7 +113,10 @@ impl From<&str> for OrderStatus {
         match s {
             "open" => OrderStatus::Open,
             "filled" => OrderStatus::Filled,
-            "canceled" | "cancelled" => OrderStatus::Canceled,
+            "canceled" | "cancelled" | "scheduledCancel" => OrderStatus::Canceled,
+            "triggered" => OrderStatus::Triggered,
+            "modified" => OrderStatus::Modified,
+            s if s.ends_with("Canceled") => OrderStatus::Canceled,
             s if s.contains("Rejected") => OrderStatus::Rejected(s.to_string()),
             s => OrderStatus::Unknown(s.to_string()),
         }
@@ -157,11 +169,15 @@ impl OrderParser {
             Err(e) => {
                 self.parse_failures.fetch_add(1, Ordering::Relaxed);
                 
-                // Log sample of bad line for debugging
-                let sample = &line[..line.len().min(200)];
-                error!("JSON parse error: {}, sample: {}...", e, sample);
+                // Log sample of bad line for debugging, cut on a char boundary
+                let mut end = line.len().min(200);
+                while !line.is_char_boundary(end) {
+                    end -= 1;
+                }
+                error!("JSON parse error: {}, sample: {}...", e, &line[..end]);
                 
-                bail!("Failed to parse JSON: {}", e);
+                let message = format!("Failed to parse JSON: {}", e);
+                return Err(anyhow::Error::new(e).context(message));
             }
         };
         
@@ -271,38 +287,232 @@ pub struct ParserStats {
     pub success_rate: f64,
 }
 
-/// Error recovery buffer for debugging
+/// Where in the pipeline an error happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorCategory {
+    /// Line was not valid order JSON
+    Parse,
+    /// Order parsed but failed a field check or names an unknown market
+    Validation,
+    /// Reading the node feed failed
+    Io,
+    /// Applying an order to a book failed
+    Book,
+}
+
+impl ErrorCategory {
+    pub const ALL: [ErrorCategory; 4] = [
+        ErrorCategory::Parse,
+        ErrorCategory::Validation,
+        ErrorCategory::Io,
+        ErrorCategory::Book,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::Parse => "parse",
+            ErrorCategory::Validation => "validation",
+            ErrorCategory::Io => "io",
+            ErrorCategory::Book => "book",
+        }
+    }
+
+    /// Classify an error returned by `OrderParser::parse_line`
+    pub fn of_parse_error(error: &anyhow::Error) -> Self {
+        if error.downcast_ref::<serde_json::Error>().is_some() {
+            ErrorCategory::Parse
+        } else {
+            ErrorCategory::Validation
+        }
+    }
+}
+
+impl std::str::FromStr for ErrorCategory {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ErrorCategory::ALL
+            .into_iter()
+            .find(|c| c.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| anyhow::anyhow!("Unknown error category: {}", s))
+    }
+}
+
+/// Longest input sample kept per error
+const MAX_SAMPLE_BYTES: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct ErrorRecord {
+    pub error_id: u64,
+    pub category: ErrorCategory,
+    pub severity: Severity,
+    pub market_id: Option<u32>,
+    pub message: String,
+    pub sample: String,
+    pub timestamp_ms: u64,
+}
+
+/// Filters for `ErrorBuffer::query`; `None` matches everything
+#[derive(Debug, Clone, Default)]
+pub struct ErrorQuery {
+    pub category: Option<ErrorCategory>,
+    pub min_severity: Option<Severity>,
+    pub market_id: Option<u32>,
+    pub since_ms: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct CategoryErrors {
+    records: VecDeque<ErrorRecord>,
+    total: u64,
+    /// Times of the most recent errors, at most `spike_threshold` of them
+    recent: VecDeque<Instant>,
+    spiking: bool,
+}
+
+/// Error recovery buffer for debugging.
+///
+/// Keeps the last `capacity` errors of each category, so a flood of one kind
+/// can't evict the rest, and raises an alert when a category reaches
+/// `spike_threshold` errors within `spike_window`.
 pub struct ErrorBuffer {
     capacity: usize,
-    errors: parking_lot::Mutex<Vec<(String, String, std::time::Instant)>>,
+    spike_threshold: usize,
+    spike_window: Duration,
+    next_error_id: AtomicU64,
+    categories: parking_lot::Mutex<HashMap<ErrorCategory, CategoryErrors>>,
+    alerts: Option<Arc<Alerts>>,
 }
 
 impl ErrorBuffer {
     pub fn new(capacity: usize) -> Self {
         Self {
-            capacity,
-            errors: parking_lot::Mutex::new(Vec::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            spike_threshold: usize::MAX,
+            spike_window: Duration::from_secs(60),
+            next_error_id: AtomicU64::new(1),
+            categories: parking_lot::Mutex::new(HashMap::new()),
+            alerts: None,
         }
     }
     
-    pub fn add(&self, error: String, sample: String) {
-        let mut errors = self.errors.lock();
+    pub fn with_spike_alerts(mut self, alerts: Arc<Alerts>, threshold: usize, window: Duration) -> Self {
+        self.alerts = Some(alerts);
+        self.spike_threshold = threshold.max(1);
+        self.spike_window = window;
+        self
+    }
+    
+    pub fn record(
+        &self,
+        category: ErrorCategory,
+        severity: Severity,
+        market_id: Option<u32>,
+        message: String,
+        sample: &str,
+    ) {
+        let mut end = sample.len().min(MAX_SAMPLE_BYTES);
+        while !sample.is_char_boundary(end) {
+            end -= 1;
+        }
+        
+        let record = ErrorRecord {
+            error_id: self.next_error_id.fetch_add(1, Ordering::Relaxed),
+            category,
+            severity,
+            market_id,
+            message,
+            sample: sample[..end].to_string(),
+            timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+        };
         
-        if errors.len() >= self.capacity {
-            errors.remove(0);
+        let now = Instant::now();
+        let spike = {
+            let mut categories = self.categories.lock();
+            let errors = categories.entry(category).or_default();
+            
+            if errors.records.len() >= self.capacity {
+                errors.records.pop_front();
+            }
+            errors.records.push_back(record);
+            errors.total += 1;
+            
+            if self.alerts.is_none() {
+                None
+            } else {
+                while errors.recent.front().is_some_and(|t| now.duration_since(*t) > self.spike_window) {
+                    errors.recent.pop_front();
+                }
+                if errors.recent.len() >= self.spike_threshold {
+                    errors.recent.pop_front();
+                }
+                errors.recent.push_back(now);
+                
+                // Alert once per spike; re-arm after the rate halves
+                let count = errors.recent.len();
+                if !errors.spiking && count >= self.spike_threshold {
+                    errors.spiking = true;
+                    Some(count)
+                } else {
+                    if errors.spiking && count < self.spike_threshold / 2 {
+                        errors.spiking = false;
+                    }
+                    None
+                }
+            }
+        };
+        
+        if let (Some(count), Some(alerts)) = (spike, &self.alerts) {
+            alerts.raise(
+                Severity::Error,
+                AlertKind::ErrorRateSpike {
+                    category,
+                    count,
+                    window: self.spike_window,
+                },
+            );
         }
+    }
+    
+    /// Matching errors, newest first
+    pub fn query(&self, query: &ErrorQuery) -> Vec<ErrorRecord> {
+        let categories = self.categories.lock();
+        let mut matches: Vec<ErrorRecord> = categories
+            .iter()
+            .filter(|(category, _)| query.category.is_none_or(|c| c == **category))
+            .flat_map(|(_, errors)| errors.records.iter())
+            .filter(|r| query.min_severity.is_none_or(|s| r.severity >= s))
+            .filter(|r| query.market_id.is_none_or(|m| r.market_id == Some(m)))
+            .filter(|r| query.since_ms.is_none_or(|t| r.timestamp_ms >= t))
+            .cloned()
+            .collect();
         
-        errors.push((error, sample, std::time::Instant::now()));
+        matches.sort_by_key(|r| std::cmp::Reverse(r.error_id));
+        if let Some(limit) = query.limit {
+            matches.truncate(limit);
+        }
+        matches
     }
     
-    pub fn recent_errors(&self) -> Vec<(String, String, std::time::Duration)> {
-        let now = std::time::Instant::now();
-        self.errors
-            .lock()
+    /// Errors recorded per category since startup
+    pub fn totals(&self) -> Vec<(ErrorCategory, u64)> {
+        let categories = self.categories.lock();
+        ErrorCategory::ALL
             .iter()
-            .map(|(err, sample, time)| (err.clone(), sample.clone(), now - *time))
+            .map(|c| (*c, categories.get(c).map_or(0, |e| e.total)))
             .collect()
     }
+    
+    /// Retained errors recorded within `window`, across all categories
+    pub fn count_within(&self, window: Duration) -> usize {
+        let since_ms = (chrono::Utc::now().timestamp_millis() as u64).saturating_sub(window.as_millis() as u64);
+        self.categories
+            .lock()
+            .values()
+            .map(|e| e.records.iter().filter(|r| r.timestamp_ms >= since_ms).count())
+            .sum()
+    }
 }
 
 #[cfg(test)]
@@ -379,4 +589,78 @@ mod tests {
         assert_eq!(order.price, 3000.0);
         assert_eq!(order.size, 1.5);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_long_bad_line_with_multibyte_chars() {
+        let parser = OrderParser::new();
+        
+        // The logged sample would end inside a 'é'
+        let line = format!("{{\"coin\":\"{}\"", "é".repeat(150));
+        assert!(line.len() > 200 && !line.is_char_boundary(200));
+        assert!(parser.parse_line(&line).is_err());
+    }
+    
+    #[test]
+    fn test_parse_errors_are_classified() {
+        let parser = OrderParser::new();
+        
+        let err = parser.parse_line("{not json").unwrap_err();
+        assert_eq!(ErrorCategory::of_parse_error(&err), ErrorCategory::Parse);
+        assert!(err.to_string().starts_with("Failed to parse JSON"));
+        
+        let json = r#"{"order": {"oid": 1, "coin": "BTC", "side": "X", "limitPx": "1", "sz": "1", "timestamp": 1}, "status": "open", "user": "0x1"}"#;
+        let err = parser.parse_line(json).unwrap_err();
+        assert_eq!(ErrorCategory::of_parse_error(&err), ErrorCategory::Validation);
+    }
+    
+    #[test]
+    fn test_status_names() {
+        for canceled in ["canceled", "marginCanceled", "siblingFilledCanceled", "scheduledCancel"] {
+            assert_eq!(OrderStatus::from(canceled), OrderStatus::Canceled, "{}", canceled);
+        }
+        assert_eq!(OrderStatus::from("triggered"), OrderStatus::Triggered);
+        assert_eq!(OrderStatus::from("modified"), OrderStatus::Modified);
+        assert_eq!(OrderStatus::from("badAloPxRejected"), OrderStatus::Rejected("badAloPxRejected".to_string()));
+        assert_eq!(OrderStatus::from("other"), OrderStatus::Unknown("other".to_string()));
+    }
+    
+    #[test]
+    fn test_error_buffer_is_bounded_per_category() {
+        let buffer = ErrorBuffer::new(2);
+        for i in 0..5 {
+            buffer.record(ErrorCategory::Parse, Severity::Warning, None, format!("parse {}", i), "x");
+        }
+        buffer.record(ErrorCategory::Book, Severity::Error, Some(3), "book".to_string(), &"é".repeat(200));
+        
+        let all = buffer.query(&ErrorQuery::default());
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].message, "book");
+        assert!(all[0].sample.len() <= MAX_SAMPLE_BYTES);
+        assert_eq!(all[2].message, "parse 3");
+        
+        let errors_only = buffer.query(&ErrorQuery { min_severity: Some(Severity::Error), ..Default::default() });
+        assert_eq!(errors_only.len(), 1);
+        let market = buffer.query(&ErrorQuery { market_id: Some(3), ..Default::default() });
+        assert_eq!(market.len(), 1);
+        
+        assert_eq!(buffer.totals()[0], (ErrorCategory::Parse, 5));
+    }
+    
+    #[test]
+    fn test_error_spike_raises_one_alert() {
+        let alerts = Arc::new(Alerts::new());
+        let buffer = ErrorBuffer::new(10).with_spike_alerts(alerts.clone(), 3, Duration::from_secs(60));
+        
+        for _ in 0..10 {
+            buffer.record(ErrorCategory::Io, Severity::Critical, None, "read failed".to_string(), "");
+        }
+        buffer.record(ErrorCategory::Parse, Severity::Warning, None, "bad".to_string(), "");
+        
+        let raised = alerts.recent();
+        assert_eq!(raised.len(), 1);
+        assert_eq!(
+            raised[0].kind,
+            AlertKind::ErrorRateSpike { category: ErrorCategory::Io, count: 3, window: Duration::from_secs(60) }
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Parsing the node feed on a pool of threads.
+//!
+//! In bursts a single reader is held back by JSON parsing, not by the books,
+//! which apply orders on their own actors. With a pool, lines are read in
+//! batches of whatever is already available, parsed in parallel, and handed
+//! back in feed order. The reader then applies them one by one, so each book
+//! still sees its orders in the order the node wrote them.
+
+use anyhow::Result;
+use rayon::prelude::*;
+use std::sync::Arc;
+
+use crate::node_format::NodeFormat;
+use crate::order_parser::{OrderParser, ValidatedOrder};
+
+/// Most lines parsed as one batch
+pub const PARSE_BATCH: usize = 1024;
+
+/// One line of the feed, parsed ahead of being applied
+#[derive(Debug)]
+pub enum ParsedLine {
+    /// Each order status in the line with its parse
+    Statuses(Vec<(String, Result<ValidatedOrder>)>),
+    /// A block line that couldn't be split into statuses, with the line
+    InvalidBlock(anyhow::Error, String),
+}
+
+pub fn parse_line(parser: &OrderParser, format: NodeFormat, line: &str) -> ParsedLine {
+    match format.statuses(line) {
+        Ok(statuses) => ParsedLine::Statuses(
+            statuses
+                .into_iter()
+                .map(|status| {
+                    let order = parser.parse_line(&status);
+                    (status.into_owned(), order)
+                })
+                .collect(),
+        ),
+        Err(e) => ParsedLine::InvalidBlock(e, line.to_string()),
+    }
+}
+
+/// Threads that parse batches of lines
+pub struct ParsePool {
+    pool: rayon::ThreadPool,
+}
+
+impl ParsePool {
+    pub fn new(workers: usize) -> Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(workers)
+            .thread_name(|i| format!("parse-{}", i))
+            .build()?;
+        Ok(Self { pool })
+    }
+
+    pub fn workers(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Parse `lines` in parallel, returned in their order
+    pub async fn parse(&self, parser: Arc<OrderParser>, format: NodeFormat, lines: Vec<String>) -> Vec<ParsedLine> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.pool.spawn(move || {
+            let parsed = lines.par_iter().map(|line| parse_line(&parser, format, line)).collect();
+            let _ = tx.send(parsed);
+        });
+        // The pool only drops the sender by panicking in the parser
+        rx.await.expect("Parse worker panicked")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(oid: u64, coin: &str) -> String {
+        format!(
+            r#"{{"order":{{"oid":{},"coin":"{}","side":"B","limitPx":"100.0","sz":"1.0","isTrigger":false,"triggerCondition":"","timestamp":1740787200000}},"status":"open","user":"0xabc"}}"#,
+            oid, coin
+        )
+    }
+
+    #[tokio::test]
+    async fn test_batches_parse_in_order() {
+        let pool = ParsePool::new(4).unwrap();
+        let parser = Arc::new(OrderParser::new());
+        let mut lines: Vec<String> = (0..PARSE_BATCH as u64).map(|oid| status(oid, "BTC")).collect();
+        lines[5] = "not json".to_string();
+
+        let parsed = pool.parse(parser.clone(), NodeFormat::Statuses, lines).await;
+        assert_eq!(parsed.len(), PARSE_BATCH);
+        for (i, line) in parsed.iter().enumerate() {
+            let ParsedLine::Statuses(statuses) = line else { panic!("line {} isn't statuses", i) };
+            match &statuses[0].1 {
+                Ok(order) => assert_eq!(order.id, i as u64),
+                Err(_) => assert_eq!(i, 5),
+            }
+        }
+        assert_eq!((parser.stats().total_messages, parser.stats().parse_failures), (PARSE_BATCH as u64, 1));
+
+        let block = format!(r#"{{"events":[{},{}]}}"#, status(1, "BTC"), status(2, "ETH"));
+        let parsed = pool.parse(parser, NodeFormat::StatusesByBlock, vec![block, "{}".to_string()]).await;
+        assert!(matches!(&parsed[0], ParsedLine::Statuses(statuses) if statuses.len() == 2));
+        assert!(matches!(&parsed[1], ParsedLine::InvalidBlock(_, line) if line == "{}"));
+    }
+}
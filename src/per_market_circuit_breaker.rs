@@ -38,6 +38,16 @@ enum CircuitState {
     },
 }
 
+impl CircuitState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Open { .. } => "OPEN",
+            CircuitState::HalfOpen { .. } => "HALF-OPEN",
+            CircuitState::Closed { .. } => "CLOSED",
+        }
+    }
+}
+
 /// Per-market circuit breaker
 struct MarketCircuitBreaker {
     state: CircuitState,
@@ -194,6 +204,15 @@ impl PerMarketCircuitBreaker {
         }
     }
 
+    /// Circuit state of every market that has recorded a result
+    pub fn market_states(&self) -> HashMap<u32, &'static str> {
+        self.breakers
+            .read()
+            .iter()
+            .map(|(market_id, breaker)| (*market_id, breaker.state.as_str()))
+            .collect()
+    }
+
     /// Get circuit statistics
     pub fn get_stats(&self) -> CircuitBreakerStats {
         let breakers = self.breakers.read();
@@ -217,18 +236,12 @@ impl PerMarketCircuitBreaker {
             }
         }
         
-        let validation_state = match &validation_breaker.state {
-            CircuitState::Open { .. } => "OPEN",
-            CircuitState::HalfOpen { .. } => "HALF-OPEN",
-            CircuitState::Closed { .. } => "CLOSED",
-        };
-        
         CircuitBreakerStats {
             total_markets: breakers.len(),
             open_markets,
             half_open_markets,
             closed_markets,
-            validation_circuit_state: validation_state.to_string(),
+            validation_circuit_state: validation_breaker.state.as_str().to_string(),
             validation_failures: validation_breaker.total_failures,
         }
     }
@@ -263,6 +276,10 @@ mod tests {
         // ETH should still work
         cb.record_market_success(1);
         assert!(!cb.is_market_open(1));
+        
+        let states = cb.market_states();
+        assert_eq!(states.get(&0), Some(&"OPEN"));
+        assert_eq!(states.get(&1), Some(&"CLOSED"));
     }
     
     #[test]
@@ -0,0 +1,201 @@
+//! End-to-end latency of the feed, per market.
+//!
+//! Each open order carries the node's timestamp, taken when it was written
+//! to the node's file. Against that, three stages are timed: the order
+//! applied to its book, published in an update, and sent on a stream (the
+//! oldest open in the update). Latencies go into HDR histograms, so
+//! percentiles hold to two significant figures from a microsecond to an
+//! hour at a fixed size. The node stamps in milliseconds, so that is the
+//! resolution of every stage.
+
+use hdrhistogram::Histogram;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+/// Longest latency told apart, in microseconds
+const MAX_LATENCY_US: u64 = 3_600_000_000;
+const SIGNIFICANT_FIGURES: u8 = 2;
+
+/// Percentiles reported for every stage
+pub const QUANTILES: [f64; 4] = [0.5, 0.9, 0.99, 0.999];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Applied to its book
+    Applied,
+    /// In an update broadcast to streams
+    Published,
+    /// Sent on a client's stream
+    Sent,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 3] = [Stage::Applied, Stage::Published, Stage::Sent];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Applied => "applied",
+            Stage::Published => "published",
+            Stage::Sent => "sent",
+        }
+    }
+}
+
+/// One stage's latency distribution
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageLatency {
+    pub stage: Stage,
+    pub count: u64,
+    /// Microseconds at each of `QUANTILES`
+    pub quantiles_us: [u64; 4],
+    pub max_us: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketLatency {
+    pub market_id: u32,
+    pub stages: Vec<StageLatency>,
+}
+
+fn histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_LATENCY_US, SIGNIFICANT_FIGURES).expect("valid histogram bounds")
+}
+
+fn summarize(stage: Stage, histogram: &Histogram<u64>) -> StageLatency {
+    StageLatency {
+        stage,
+        count: histogram.len(),
+        quantiles_us: QUANTILES.map(|quantile| histogram.value_at_quantile(quantile)),
+        max_us: histogram.max(),
+    }
+}
+
+/// One market's histograms, indexed by `Stage`
+type StageHistograms = [Mutex<Histogram<u64>>; 3];
+
+/// Latency histograms of every market that has recorded any
+#[derive(Default)]
+pub struct PipelineLatency {
+    markets: RwLock<HashMap<u32, Arc<StageHistograms>>>,
+}
+
+impl PipelineLatency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `stage` reached now by orders the node stamped `events_ms`
+    pub fn record(&self, market_id: u32, stage: Stage, events_ms: &[u64]) {
+        if events_ms.is_empty() {
+            return;
+        }
+        let now_us = chrono::Utc::now().timestamp_micros().max(0) as u64;
+        self.record_at(market_id, stage, events_ms, now_us);
+    }
+
+    fn record_at(&self, market_id: u32, stage: Stage, events_ms: &[u64], now_us: u64) {
+        let histograms = self.market(market_id);
+        let mut histogram = histograms[stage as usize].lock();
+        for event_ms in events_ms {
+            // Clock skew with the node counts as no latency, not a huge one
+            histogram.saturating_record(now_us.saturating_sub(event_ms.saturating_mul(1000)));
+        }
+    }
+
+    fn market(&self, market_id: u32) -> Arc<StageHistograms> {
+        if let Some(histograms) = self.markets.read().get(&market_id) {
+            return histograms.clone();
+        }
+        self.markets
+            .write()
+            .entry(market_id)
+            .or_insert_with(|| Arc::new([(); 3].map(|_| Mutex::new(histogram()))))
+            .clone()
+    }
+
+    /// Latencies of `market_ids`, or of every market if empty, by market id
+    pub fn markets(&self, market_ids: &[u32]) -> Vec<MarketLatency> {
+        let mut markets: Vec<MarketLatency> = self
+            .markets
+            .read()
+            .iter()
+            .filter(|(market_id, _)| market_ids.is_empty() || market_ids.contains(market_id))
+            .map(|(market_id, histograms)| MarketLatency {
+                market_id: *market_id,
+                stages: Stage::ALL.iter().map(|stage| summarize(*stage, &histograms[*stage as usize].lock())).collect(),
+            })
+            .collect();
+        markets.sort_unstable_by_key(|market| market.market_id);
+        markets
+    }
+
+    /// Every market's latencies together
+    pub fn overall(&self) -> Vec<StageLatency> {
+        let mut totals = [(); 3].map(|_| histogram());
+        for histograms in self.markets.read().values() {
+            for (total, histogram) in totals.iter_mut().zip(histograms.iter()) {
+                // Same bounds, so merging can't fail
+                let _ = total.add(&*histogram.lock());
+            }
+        }
+        Stage::ALL.iter().map(|stage| summarize(*stage, &totals[*stage as usize])).collect()
+    }
+
+    /// Prometheus summaries in seconds, per stage and market
+    pub fn render(&self) -> String {
+        let name = "orderbook_pipeline_latency_seconds";
+        let mut out = format!(
+            "# HELP {} Time from the node's order timestamp to each pipeline stage\n# TYPE {} summary\n",
+            name, name
+        );
+        for market in self.markets(&[]) {
+            for stage in &market.stages {
+                let labels = format!("market_id=\"{}\",stage=\"{}\"", market.market_id, stage.stage.as_str());
+                for (quantile, value_us) in QUANTILES.iter().zip(stage.quantiles_us) {
+                    let _ = writeln!(out, "{}{{{},quantile=\"{}\"}} {}", name, labels, quantile, value_us as f64 / 1e6);
+                }
+                let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, stage.count);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stages_record_per_market() {
+        let latency = PipelineLatency::new();
+        let now_us = 1_740_787_200_000_000;
+        let event_ms = now_us / 1000;
+        // 1ms to 100ms applied on market 3, and one slow send
+        let events: Vec<u64> = (1..=100).map(|ms| event_ms - ms).collect();
+        latency.record_at(3, Stage::Applied, &events, now_us);
+        latency.record_at(3, Stage::Sent, &[event_ms - 2_000], now_us);
+        // A node clock ahead of ours
+        latency.record_at(4, Stage::Applied, &[event_ms + 5], now_us);
+
+        let markets = latency.markets(&[]);
+        assert_eq!(markets.iter().map(|m| m.market_id).collect::<Vec<_>>(), vec![3, 4]);
+        let applied = &markets[0].stages[Stage::Applied as usize];
+        assert_eq!(applied.count, 100);
+        // Two significant figures
+        assert!((49_000..=51_000).contains(&applied.quantiles_us[0]), "{:?}", applied);
+        assert!((98_000..=100_000).contains(&applied.quantiles_us[2]), "{:?}", applied);
+        assert_eq!(markets[0].stages[Stage::Published as usize].count, 0);
+        assert!((1_980_000..=2_020_000).contains(&markets[0].stages[Stage::Sent as usize].max_us));
+        assert_eq!(markets[1].stages[Stage::Applied as usize].max_us, 0);
+
+        assert_eq!(latency.markets(&[4]).len(), 1);
+        assert_eq!(latency.overall()[Stage::Applied as usize].count, 101);
+
+        let text = latency.render();
+        assert!(text.contains("# TYPE orderbook_pipeline_latency_seconds summary"));
+        assert!(text.lines().any(|l| l == "orderbook_pipeline_latency_seconds_count{market_id=\"3\",stage=\"applied\"} 100"));
+        assert!(text.lines().any(|l| l.starts_with("orderbook_pipeline_latency_seconds{market_id=\"3\",stage=\"sent\",quantile=\"0.99\"} 2.0")));
+    }
+}
@@ -0,0 +1,298 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, RwLock};
+use tracing::{error, info, warn};
+
+use crate::dynamic_markets::DynamicMarketRegistry;
+use crate::fast_orderbook::FastOrderbook;
+
+/// A position to monitor, as configured by the operator
+#[derive(Debug, Clone, Deserialize)]
+pub struct WatchedPositionConfig {
+    pub user: String,
+    pub coin: String,
+    /// Signed size: positive long, negative short
+    pub size: f64,
+    pub entry_price: f64,
+}
+
+/// Live valuation of one watched position
+#[derive(Debug, Clone)]
+pub struct PositionPnl {
+    pub user: String,
+    pub coin: String,
+    pub market_id: u32,
+    pub size: f64,
+    pub entry_price: f64,
+    pub mark_price: f64,
+    pub unrealized_pnl: f64,
+    /// Funding received (positive) or paid (negative) since the service started
+    pub accrued_funding: f64,
+    /// Current hourly funding rate
+    pub funding_rate: f64,
+    pub net_pnl: f64,
+    pub timestamp_us: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct AssetCtx {
+    #[serde(default)]
+    funding: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UniverseEntry {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Meta {
+    universe: Vec<UniverseEntry>,
+}
+
+struct TrackedPosition {
+    config: WatchedPositionConfig,
+    market_id: u32,
+    accrued_funding: f64,
+    last_accrual: Instant,
+}
+
+/// Values watched positions against the local mark price and accrues
+/// funding continuously from the published hourly rate
+pub struct PositionPnlTracker {
+    positions: RwLock<Vec<TrackedPosition>>,
+    funding_rates: RwLock<HashMap<String, f64>>,
+    output_tx: broadcast::Sender<Arc<Vec<PositionPnl>>>,
+    api_url: String,
+}
+
+impl PositionPnlTracker {
+    pub fn new(positions: Vec<(u32, WatchedPositionConfig)>) -> Self {
+        let now = Instant::now();
+
+        Self {
+            positions: RwLock::new(
+                positions
+                    .into_iter()
+                    .map(|(market_id, config)| TrackedPosition {
+                        config,
+                        market_id,
+                        accrued_funding: 0.0,
+                        last_accrual: now,
+                    })
+                    .collect(),
+            ),
+            funding_rates: RwLock::new(HashMap::new()),
+            output_tx: broadcast::channel(1000).0,
+            api_url: "https://api.hyperliquid.xyz/info".to_string(),
+        }
+    }
+
+    /// Resolve each configured coin to a market id, dropping unknown markets
+    pub async fn resolve_markets(
+        configs: Vec<WatchedPositionConfig>,
+        market_registry: &DynamicMarketRegistry,
+    ) -> Vec<(u32, WatchedPositionConfig)> {
+        let mut positions = Vec::with_capacity(configs.len());
+        for config in configs {
+            match market_registry.get_market_id(&config.coin).await {
+                Some(market_id) => positions.push((market_id, config)),
+                None => warn!("Ignoring watched position in unknown market: {}", config.coin),
+            }
+        }
+        positions
+    }
+
+    /// Load positions from a JSON array of `{user, coin, size, entry_price}`
+    pub fn load_configs(path: impl AsRef<Path>) -> Result<Vec<WatchedPositionConfig>> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<PositionPnl>>> {
+        self.output_tx.subscribe()
+    }
+
+    pub async fn position_count(&self) -> usize {
+        self.positions.read().await.len()
+    }
+
+    /// Replace the hourly funding rates, keyed by coin
+    pub async fn set_funding_rates(&self, rates: HashMap<String, f64>) {
+        *self.funding_rates.write().await = rates;
+    }
+
+    /// Poll hourly funding rates from the Hyperliquid API
+    pub fn start_funding_feed(self: &Arc<Self>, poll_interval: Duration) {
+        let tracker = self.clone();
+
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut ticker = tokio::time::interval(poll_interval);
+
+            loop {
+                ticker.tick().await;
+
+                match Self::fetch_funding_rates(&client, &tracker.api_url).await {
+                    Ok(rates) => tracker.set_funding_rates(rates).await,
+                    Err(e) => error!("Failed to fetch funding rates: {}", e),
+                }
+            }
+        });
+    }
+
+    async fn fetch_funding_rates(client: &reqwest::Client, api_url: &str) -> Result<HashMap<String, f64>> {
+        let (meta, ctxs): (Meta, Vec<AssetCtx>) = client
+            .post(api_url)
+            .json(&serde_json::json!({"type": "metaAndAssetCtxs"}))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        Ok(meta
+            .universe
+            .into_iter()
+            .zip(ctxs)
+            .filter_map(|(asset, ctx)| {
+                let rate = ctx.funding?.parse::<f64>().ok()?;
+                Some((asset.name, rate))
+            })
+            .collect())
+    }
+
+    /// Revalue all positions and accrue funding since the last call
+    pub async fn update(&self, orderbooks: &HashMap<u32, Arc<FastOrderbook>>) -> Vec<PositionPnl> {
+        self.update_at(Instant::now(), orderbooks).await
+    }
+
+    async fn update_at(&self, now: Instant, orderbooks: &HashMap<u32, Arc<FastOrderbook>>) -> Vec<PositionPnl> {
+        let timestamp_us = chrono::Utc::now().timestamp_micros();
+        let funding_rates = self.funding_rates.read().await;
+        let mut positions = self.positions.write().await;
+
+        let mut results = Vec::with_capacity(positions.len());
+        for position in positions.iter_mut() {
+            let Some(mark_price) = orderbooks.get(&position.market_id).and_then(|ob| mark_price(ob)) else {
+                continue;
+            };
+
+            let funding_rate = funding_rates.get(&position.config.coin).copied().unwrap_or(0.0);
+            let elapsed_hours = now.duration_since(position.last_accrual).as_secs_f64() / 3600.0;
+            // Longs pay shorts when the rate is positive
+            position.accrued_funding -= position.config.size * mark_price * funding_rate * elapsed_hours;
+            position.last_accrual = now;
+
+            let unrealized_pnl = position.config.size * (mark_price - position.config.entry_price);
+
+            results.push(PositionPnl {
+                user: position.config.user.clone(),
+                coin: position.config.coin.clone(),
+                market_id: position.market_id,
+                size: position.config.size,
+                entry_price: position.config.entry_price,
+                mark_price,
+                unrealized_pnl,
+                accrued_funding: position.accrued_funding,
+                funding_rate,
+                net_pnl: unrealized_pnl + position.accrued_funding,
+                timestamp_us,
+            });
+        }
+
+        results
+    }
+
+    pub fn start(self: Arc<Self>, orderbooks: HashMap<u32, Arc<FastOrderbook>>, update_interval: Duration) {
+        tokio::spawn(async move {
+            info!(
+                "Tracking PnL for {} watched positions every {:?}",
+                self.position_count().await,
+                update_interval
+            );
+
+            let mut ticker = tokio::time::interval(update_interval);
+            loop {
+                ticker.tick().await;
+                let results = self.update(&orderbooks).await;
+                if !results.is_empty() {
+                    let _ = self.output_tx.send(Arc::new(results));
+                }
+            }
+        });
+    }
+}
+
+/// Best available mark: Hyperliquid mark, then book mid, then oracle
+pub fn mark_price(orderbook: &FastOrderbook) -> Option<f64> {
+    orderbook
+        .get_hl_mark_price_value()
+        .or_else(|| orderbook.get_best_bid_ask().map(|(bid, ask)| (bid + ask) / 2.0))
+        .or_else(|| orderbook.get_oracle_price())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::Order;
+
+    fn orderbook(bid: f64, ask: f64) -> Arc<FastOrderbook> {
+        let ob = Arc::new(FastOrderbook::new(0, "BTC".to_string()));
+        ob.add_order(Order { id: 1, price: bid, size: 1.0, timestamp: 0 }, true);
+        ob.add_order(Order { id: 2, price: ask, size: 1.0, timestamp: 0 }, false);
+        ob.publish();
+        ob
+    }
+
+    fn position(user: &str, size: f64, entry_price: f64) -> (u32, WatchedPositionConfig) {
+        (
+            0,
+            WatchedPositionConfig {
+                user: user.to_string(),
+                coin: "BTC".to_string(),
+                size,
+                entry_price,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_pnl_and_funding_accrual() {
+        let tracker = PositionPnlTracker::new(vec![position("long", 2.0, 90.0), position("short", -2.0, 90.0)]);
+        tracker.set_funding_rates([("BTC".to_string(), 0.001)].into_iter().collect()).await;
+
+        let orderbooks: HashMap<u32, Arc<FastOrderbook>> = [(0, orderbook(99.0, 101.0))].into_iter().collect();
+        let start = tracker.positions.read().await[0].last_accrual;
+
+        let results = tracker.update_at(start + Duration::from_secs(1800), &orderbooks).await;
+        let long = &results[0];
+        let short = &results[1];
+
+        assert_eq!(long.mark_price, 100.0);
+        assert!((long.unrealized_pnl - 20.0).abs() < 1e-9);
+        assert!((short.unrealized_pnl + 20.0).abs() < 1e-9);
+
+        // Half an hour at 0.1%/h on 200 notional: long pays 0.1, short receives it
+        assert!((long.accrued_funding + 0.1).abs() < 1e-9);
+        assert!((short.accrued_funding - 0.1).abs() < 1e-9);
+        assert!((long.net_pnl - 19.9).abs() < 1e-9);
+
+        // Accrual continues from the previous update rather than restarting
+        let results = tracker.update_at(start + Duration::from_secs(3600), &orderbooks).await;
+        assert!((results[0].accrued_funding + 0.2).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_positions_without_price_are_skipped() {
+        let tracker = PositionPnlTracker::new(vec![position("user", 1.0, 100.0)]);
+        let orderbooks: HashMap<u32, Arc<FastOrderbook>> =
+            [(0, Arc::new(FastOrderbook::new(0, "BTC".to_string())))].into_iter().collect();
+
+        assert!(tracker.update(&orderbooks).await.is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+//! Fixed-point prices: a whole number of ticks of the market's tick size.
+//!
+//! Book levels are keyed and ordered by ticks, so prices that differ only by
+//! float noise share a level and ordering never compares a NaN.
+
+/// Tick for markets whose tick size isn't known; eight decimals hold every
+/// Hyperliquid price
+pub const DEFAULT_TICK_SIZE: f64 = 1e-8;
+
+/// A price as a count of ticks
+pub type Ticks = i64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TickSize {
+    size: f64,
+    /// Ticks per unit of price, when the tick divides one exactly (0.01,
+    /// 0.5), so prices come from an exact division instead of a product
+    per_unit: Option<f64>,
+}
+
+impl Default for TickSize {
+    fn default() -> Self {
+        Self::new(DEFAULT_TICK_SIZE).unwrap()
+    }
+}
+
+impl TickSize {
+    /// `None` unless `size` is positive and finite
+    pub fn new(size: f64) -> Option<Self> {
+        if !size.is_finite() || size <= 0.0 {
+            return None;
+        }
+        let per_unit = (1.0 / size).round();
+        let per_unit = (per_unit >= 1.0 && (per_unit * size - 1.0).abs() < 1e-9).then_some(per_unit);
+        Some(Self { size, per_unit })
+    }
+
+    pub fn size(self) -> f64 {
+        self.size
+    }
+
+    /// The nearest tick to `price`. Prices are validated at ingest; a NaN
+    /// maps to 0 and out-of-range prices saturate rather than panic.
+    pub fn to_ticks(self, price: f64) -> Ticks {
+        let ticks = match self.per_unit {
+            Some(per_unit) => price * per_unit,
+            None => price / self.size,
+        };
+        ticks.round() as Ticks
+    }
+
+    /// The price of `ticks`, as the closest float to its exact value
+    pub fn to_price(self, ticks: Ticks) -> f64 {
+        match self.per_unit {
+            Some(per_unit) => ticks as f64 / per_unit,
+            None => ticks as f64 * self.size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prices_round_trip_through_ticks() {
+        let cents = TickSize::new(0.01).unwrap();
+        assert_eq!(cents.to_ticks(105_000.07), 10_500_007);
+        assert_eq!(cents.to_price(10_500_007), 105_000.07);
+        // Float noise lands on the same tick
+        assert_eq!(cents.to_ticks(0.1 + 0.2), cents.to_ticks(0.3));
+        assert_eq!(TickSize::new(0.1).unwrap().to_price(3), 0.3);
+
+        let fives = TickSize::new(5.0).unwrap();
+        assert_eq!((fives.to_ticks(102.0), fives.to_price(20)), (20, 100.0));
+
+        let fine = TickSize::default();
+        assert_eq!(fine.to_price(fine.to_ticks(0.00001234)), 0.00001234);
+        assert_eq!(fine.to_ticks(f64::NAN), 0);
+
+        assert!(TickSize::new(0.0).is_none());
+        assert!(TickSize::new(f64::NAN).is_none());
+    }
+}
@@ -0,0 +1,101 @@
+//! The service's protobuf definitions, embedded so client teams can fetch
+//! them from a running instance (`GetProtoDescriptors`) or from the binary
+//! (`export-protos`) instead of cloning this repository.
+
+use anyhow::Result;
+use clap::Args;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Compiled `FileDescriptorSet` of the v2 API
+pub const FILE_DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/orderbook_descriptor.bin"));
+
+pub struct ProtoFile {
+    /// Path within an exported artifact
+    pub path: &'static str,
+    pub version: &'static str,
+    pub content: &'static str,
+}
+
+pub const PROTO_FILES: &[ProtoFile] = &[
+    ProtoFile { path: "v2/subscribe.proto", version: "v2", content: include_str!("../subscribe.proto") },
+    // Both versions declare package `orderbook`; generate them separately
+    ProtoFile { path: "v1/orderbook.proto", version: "v1", content: include_str!("../proto/orderbook.proto") },
+];
+
+#[derive(Args, Debug, Clone)]
+pub struct ExportArgs {
+    /// Directory to write the proto files and descriptor set into
+    #[arg(long, default_value = "protos")]
+    pub out_dir: PathBuf,
+}
+
+/// Write every proto file, the v2 descriptor set and a VERSION file under `dir`
+pub fn export(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut written = Vec::new();
+    let mut write = |path: PathBuf, content: &[u8]| -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, content)?;
+        written.push(path);
+        Ok(())
+    };
+
+    for file in PROTO_FILES {
+        write(dir.join(file.path), file.content.as_bytes())?;
+    }
+    write(dir.join("v2/orderbook_descriptor.bin"), FILE_DESCRIPTOR_SET)?;
+    write(dir.join("VERSION"), format!("{}\n", env!("CARGO_PKG_VERSION")).as_bytes())?;
+    Ok(written)
+}
+
+pub fn run(args: ExportArgs) -> Result<()> {
+    for path in export(&args.out_dir)? {
+        info!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_export_writes_every_file() {
+        let dir = std::env::temp_dir().join(format!("proto-export-{}", std::process::id()));
+        let written = export(&dir).unwrap();
+        assert_eq!(written.len(), PROTO_FILES.len() + 2);
+
+        let v2 = std::fs::read_to_string(dir.join("v2/subscribe.proto")).unwrap();
+        assert!(v2.contains("rpc GetProtoDescriptors"));
+        // Descriptor names are stored as plain strings
+        let descriptors = std::fs::read(dir.join("v2/orderbook_descriptor.bin")).unwrap();
+        assert!(descriptors.windows(19).any(|w| w == b"GetProtoDescriptors"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Lint both modules and check for breaking changes against
+    /// `BUF_BREAKING_AGAINST` (default: the last commit)
+    #[test]
+    #[ignore = "needs buf installed; run with `cargo test buf -- --ignored`"]
+    fn test_buf_lint_and_breaking() {
+        let against = std::env::var("BUF_BREAKING_AGAINST").unwrap_or_else(|_| ".git#ref=HEAD".to_string());
+        let buf = |args: &[&str]| {
+            let output = Command::new("buf").args(args).current_dir(env!("CARGO_MANIFEST_DIR")).output().unwrap();
+            assert!(
+                output.status.success(),
+                "buf {}:\n{}{}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stdout),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        };
+
+        buf(&["lint"]);
+        buf(&["lint", "proto"]);
+        buf(&["breaking", "--against", &against]);
+        buf(&["breaking", "proto", "--against", &format!("{},subdir=proto", against)]);
+    }
+}
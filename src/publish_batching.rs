@@ -0,0 +1,108 @@
+//! Micro-batching of book updates before they are broadcast.
+//!
+//! With a window set, a book's actor holds applied deltas for up to that long
+//! after the first one and then publishes them as one `MarketUpdate`. Bursty
+//! markets send far fewer, larger messages for at most the window's extra
+//! latency. Markets without a window publish every applied batch.
+
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Upper bounds, in deltas, of the batch size histogram's buckets. Larger
+/// updates fall in a final overflow bucket.
+pub const BATCH_SIZE_BUCKETS: [usize; 6] = [1, 4, 16, 64, 256, 1024];
+
+/// Updates a market published and how many deltas each carried
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    pub updates: u64,
+    pub deltas: u64,
+    pub max_deltas: usize,
+    /// Updates per bucket of `BATCH_SIZE_BUCKETS`, then the overflow bucket
+    pub buckets: [u64; BATCH_SIZE_BUCKETS.len() + 1],
+}
+
+impl BatchStats {
+    fn record(&mut self, deltas: usize) {
+        self.updates += 1;
+        self.deltas += deltas as u64;
+        self.max_deltas = self.max_deltas.max(deltas);
+        let bucket = BATCH_SIZE_BUCKETS.iter().position(|&max| deltas <= max).unwrap_or(BATCH_SIZE_BUCKETS.len());
+        self.buckets[bucket] += 1;
+    }
+}
+
+/// Batching window per market, and the batch sizes that resulted
+pub struct BatchWindows {
+    default: Duration,
+    overrides: HashMap<u32, Duration>,
+    stats: Mutex<HashMap<u32, BatchStats>>,
+}
+
+impl Default for BatchWindows {
+    fn default() -> Self {
+        Self::new(Duration::ZERO, HashMap::new())
+    }
+}
+
+impl BatchWindows {
+    pub fn new(default: Duration, overrides: HashMap<u32, Duration>) -> Self {
+        Self { default, overrides, stats: Mutex::new(HashMap::new()) }
+    }
+
+    /// How long `market_id` holds deltas before publishing; zero publishes
+    /// every applied batch
+    pub fn window(&self, market_id: u32) -> Duration {
+        self.overrides.get(&market_id).copied().unwrap_or(self.default)
+    }
+
+    pub fn record(&self, market_id: u32, deltas: usize) {
+        self.stats.lock().entry(market_id).or_default().record(deltas);
+    }
+
+    pub fn stats(&self, market_id: u32) -> BatchStats {
+        self.stats.lock().get(&market_id).copied().unwrap_or_default()
+    }
+}
+
+/// Parse `COIN=MICROS` pairs, comma-separated
+pub fn parse_overrides(spec: &str) -> Result<Vec<(String, Duration)>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (coin, micros) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected COIN=MICROS, got {}", pair))?;
+            let micros: u64 = micros.trim().parse().map_err(|_| anyhow!("Invalid window for {}: {}", coin, micros))?;
+            Ok((coin.trim().to_string(), Duration::from_micros(micros)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_and_histogram() {
+        let windows = BatchWindows::new(Duration::from_millis(1), [(3, Duration::ZERO)].into_iter().collect());
+        assert_eq!(windows.window(0), Duration::from_millis(1));
+        assert_eq!(windows.window(3), Duration::ZERO);
+
+        for deltas in [1, 1, 3, 16, 17, 5000] {
+            windows.record(0, deltas);
+        }
+        let stats = windows.stats(0);
+        assert_eq!((stats.updates, stats.deltas, stats.max_deltas), (6, 5038, 5000));
+        assert_eq!(stats.buckets, [2, 1, 1, 1, 0, 0, 1]);
+        assert_eq!(windows.stats(1), BatchStats::default());
+
+        let parsed = parse_overrides("BTC=2000, ETH = 500,").unwrap();
+        assert_eq!(parsed, vec![("BTC".to_string(), Duration::from_millis(2)), ("ETH".to_string(), Duration::from_micros(500))]);
+        assert!(parse_overrides("BTC").is_err());
+        assert!(parse_overrides("BTC=fast").is_err());
+    }
+}
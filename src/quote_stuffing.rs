@@ -0,0 +1,287 @@
+//! Detection of quote stuffing: orders placed and canceled at the same price
+//! faster than anyone could trade against them.
+//!
+//! A cancel that lands within the maximum lifetime of its open, at the open's
+//! price, is one stuffing cycle. Once a user has run the burst number of
+//! cycles at a price of a market within a second, their next orders there are
+//! flagged until the bursts stop. Flagged orders still enter the book; the
+//! configured action decides where they are hidden or marked.
+
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::fast_orderbook::BookSnapshot;
+use crate::order_parser::{OrderStatus, ValidatedOrder};
+
+/// How far back cycles count towards a burst
+const BURST_WINDOW_MS: u64 = 1_000;
+
+/// Orders observed between sweeps of idle burst windows
+const SWEEP_EVERY: u64 = 10_000;
+
+/// What happens to flagged orders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StuffingAction {
+    /// Marked in SubscribeL3
+    Tag,
+    /// Left out of book metrics
+    Exclude,
+    /// Left out of SubscribeBBO
+    Suppress,
+}
+
+impl StuffingAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StuffingAction::Tag => "tag",
+            StuffingAction::Exclude => "exclude",
+            StuffingAction::Suppress => "suppress",
+        }
+    }
+}
+
+impl FromStr for StuffingAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "tag" => Ok(StuffingAction::Tag),
+            "exclude" => Ok(StuffingAction::Exclude),
+            "suppress" => Ok(StuffingAction::Suppress),
+            other => Err(anyhow!("Unknown stuffing action: {} (expected tag, exclude or suppress)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StuffingConfig {
+    pub action: StuffingAction,
+    /// Longest open-to-cancel time that counts as a cycle
+    pub max_lifetime: Duration,
+    /// Cycles at one price within a second that flag a user there
+    pub burst: u32,
+}
+
+/// Stuffing by one user or in one market
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StuffingStats {
+    pub cycles: u64,
+    /// Orders opened while flagged
+    pub flagged_orders: u64,
+    /// Summed open-to-cancel time of the cycles
+    pub lifetime_ms: u64,
+}
+
+impl StuffingStats {
+    pub fn mean_lifetime_ms(&self) -> f64 {
+        if self.cycles == 0 {
+            0.0
+        } else {
+            self.lifetime_ms as f64 / self.cycles as f64
+        }
+    }
+}
+
+#[derive(Debug)]
+struct OpenQuote {
+    user: Arc<str>,
+    market_id: u32,
+    price: f64,
+    opened_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FlaggedOrder {
+    is_buy: bool,
+    price: f64,
+    size: f64,
+}
+
+#[derive(Default)]
+struct State {
+    open: HashMap<u64, OpenQuote>,
+    /// Recent cycle times per user, market and price
+    bursts: HashMap<(Arc<str>, u32, u64), VecDeque<u64>>,
+    flagged: HashMap<u32, HashMap<u64, FlaggedOrder>>,
+    users: HashMap<Arc<str>, StuffingStats>,
+    markets: HashMap<u32, StuffingStats>,
+    observed: u64,
+}
+
+pub struct QuoteStuffingFilter {
+    config: StuffingConfig,
+    state: Mutex<State>,
+}
+
+impl QuoteStuffingFilter {
+    pub fn new(config: StuffingConfig) -> Self {
+        Self { config, state: Mutex::new(State::default()) }
+    }
+
+    pub fn config(&self) -> StuffingConfig {
+        self.config
+    }
+
+    /// Follow a book order's lifecycle. Returns whether it opened flagged.
+    pub fn observe(&self, order: &ValidatedOrder, market_id: u32) -> bool {
+        let mut state = self.state.lock();
+        let state = &mut *state;
+        state.observed += 1;
+        if state.observed.is_multiple_of(SWEEP_EVERY) {
+            let cutoff = order.timestamp.saturating_sub(BURST_WINDOW_MS);
+            state.bursts.retain(|_, cycles| cycles.back().is_some_and(|&t| t >= cutoff));
+        }
+
+        match order.status {
+            OrderStatus::Open => {
+                let user: Arc<str> = state
+                    .users
+                    .get_key_value(order.user.as_str())
+                    .map(|(user, _)| user.clone())
+                    .unwrap_or_else(|| order.user.as_str().into());
+                let key = (user.clone(), market_id, order.price.to_bits());
+                let flagged = state.bursts.get_mut(&key).is_some_and(|cycles| {
+                    while cycles.front().is_some_and(|&t| t + BURST_WINDOW_MS < order.timestamp) {
+                        cycles.pop_front();
+                    }
+                    cycles.len() >= self.config.burst as usize
+                });
+
+                if flagged {
+                    let flagged_order = FlaggedOrder { is_buy: order.is_buy, price: order.price, size: order.size };
+                    state.flagged.entry(market_id).or_default().insert(order.id, flagged_order);
+                    state.users.entry(user.clone()).or_default().flagged_orders += 1;
+                    state.markets.entry(market_id).or_default().flagged_orders += 1;
+                }
+                state.open.insert(order.id, OpenQuote { user, market_id, price: order.price, opened_ms: order.timestamp });
+                flagged
+            }
+            OrderStatus::Filled | OrderStatus::Canceled => {
+                let Some(quote) = state.open.remove(&order.id) else { return false };
+                if let Some(flagged) = state.flagged.get_mut(&quote.market_id) {
+                    flagged.remove(&order.id);
+                }
+
+                let lifetime_ms = order.timestamp.saturating_sub(quote.opened_ms);
+                if order.status == OrderStatus::Canceled
+                    && order.price == quote.price
+                    && lifetime_ms < self.config.max_lifetime.as_millis() as u64
+                {
+                    let key = (quote.user.clone(), quote.market_id, quote.price.to_bits());
+                    state.bursts.entry(key).or_default().push_back(order.timestamp);
+                    for stats in [state.users.entry(quote.user).or_default(), state.markets.entry(quote.market_id).or_default()] {
+                        stats.cycles += 1;
+                        stats.lifetime_ms += lifetime_ms;
+                    }
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_flagged(&self, market_id: u32, oid: u64) -> bool {
+        self.state.lock().flagged.get(&market_id).is_some_and(|flagged| flagged.contains_key(&oid))
+    }
+
+    /// `book` without the flagged orders resting in it, if there are any
+    pub fn without_flagged(&self, market_id: u32, book: &BookSnapshot) -> Option<BookSnapshot> {
+        let state = self.state.lock();
+        let flagged = state.flagged.get(&market_id).filter(|flagged| !flagged.is_empty())?;
+        let mut bids = book.bids.clone();
+        let mut asks = book.asks.clone();
+        for order in flagged.values() {
+            let levels = if order.is_buy { &mut bids } else { &mut asks };
+            if let Some(level) = levels.iter_mut().find(|(price, _)| *price == order.price) {
+                level.1 -= order.size;
+            }
+        }
+        // Flagged orders the book hasn't applied yet may overshoot a level
+        bids.retain(|(_, size)| *size > 1e-12);
+        asks.retain(|(_, size)| *size > 1e-12);
+        Some(BookSnapshot::new(book.sequence, bids, asks))
+    }
+
+    /// Users with any stuffing, most cycles first
+    pub fn user_stats(&self, limit: usize) -> Vec<(String, StuffingStats)> {
+        let mut users: Vec<_> = self
+            .state
+            .lock()
+            .users
+            .iter()
+            .filter(|(_, stats)| stats.cycles > 0 || stats.flagged_orders > 0)
+            .map(|(user, stats)| (user.to_string(), *stats))
+            .collect();
+        users.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then_with(|| a.0.cmp(&b.0)));
+        if limit > 0 {
+            users.truncate(limit);
+        }
+        users
+    }
+
+    pub fn market_stats(&self) -> HashMap<u32, StuffingStats> {
+        self.state.lock().markets.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(id: u64, user: &str, price: f64, status: OrderStatus, timestamp: u64) -> ValidatedOrder {
+        ValidatedOrder {
+            id,
+            coin: "BTC".to_string(),
+            is_buy: true,
+            price,
+            size: 1.0,
+            status,
+            user: user.to_string(),
+            timestamp,
+            is_trigger: false,
+            trigger_condition: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_bursts_flag_orders_at_the_price() {
+        let filter = QuoteStuffingFilter::new(StuffingConfig {
+            action: StuffingAction::Suppress,
+            max_lifetime: Duration::from_millis(100),
+            burst: 2,
+        });
+
+        // Two fast cycles at 100 flag the next order, whose cycle is too slow to count
+        for (id, opened, canceled) in [(1, 0, 50), (2, 100, 120), (3, 200, 400)] {
+            assert_eq!(filter.observe(&order(id, "a", 100.0, OrderStatus::Open, opened), 0), id == 3);
+            filter.observe(&order(id, "a", 100.0, OrderStatus::Canceled, canceled), 0);
+        }
+        // Fills and other users don't cycle
+        filter.observe(&order(4, "b", 100.0, OrderStatus::Open, 400), 0);
+        filter.observe(&order(4, "b", 100.0, OrderStatus::Filled, 410), 0);
+
+        // Flagged at that price only, until the burst ages out
+        assert!(filter.observe(&order(5, "a", 100.0, OrderStatus::Open, 500), 0));
+        assert!(!filter.observe(&order(6, "a", 101.0, OrderStatus::Open, 500), 0));
+        assert!(filter.is_flagged(0, 5));
+
+        let book = BookSnapshot::new(7, vec![(100.0, 3.0), (99.0, 1.0)], vec![(102.0, 1.0)]);
+        assert_eq!(filter.without_flagged(0, &book).unwrap().bids, vec![(100.0, 2.0), (99.0, 1.0)]);
+        assert!(filter.without_flagged(1, &book).is_none());
+
+        filter.observe(&order(5, "a", 100.0, OrderStatus::Filled, 900), 0);
+        assert!(!filter.is_flagged(0, 5));
+        assert!(!filter.observe(&order(7, "a", 100.0, OrderStatus::Open, 1_200), 0));
+
+        let users = filter.user_stats(0);
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].0, "a");
+        assert_eq!(users[0].1, StuffingStats { cycles: 2, flagged_orders: 2, lifetime_ms: 70 });
+        assert_eq!(users[0].1.mean_lifetime_ms(), 35.0);
+        assert_eq!(filter.market_stats()[&0].cycles, 2);
+    }
+}
@@ -0,0 +1,184 @@
+//! gRPC server reflection (`grpc.reflection.v1alpha`), so grpcurl and UI
+//! clients can discover the API from a running instance.
+//!
+//! Answers come from the descriptor set embedded at build time: every
+//! service in it is listed, and files are found by name or by any message,
+//! enum, service or method they declare. Extensions aren't used.
+
+use anyhow::Result;
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use std::collections::{HashMap, HashSet};
+use std::pin::Pin;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::proto_descriptors::FILE_DESCRIPTOR_SET;
+
+pub mod pb {
+    tonic::include_proto!("grpc.reflection.v1alpha");
+}
+
+use pb::server_reflection_request::MessageRequest;
+use pb::server_reflection_response::MessageResponse;
+use pb::server_reflection_server::ServerReflection;
+use pb::{
+    ErrorResponse, FileDescriptorResponse, ListServiceResponse, ServerReflectionRequest, ServerReflectionResponse,
+    ServiceResponse,
+};
+
+pub struct ReflectionService {
+    files: HashMap<String, FileDescriptorProto>,
+    /// Fully qualified symbol to the file declaring it
+    symbols: HashMap<String, String>,
+    services: Vec<String>,
+}
+
+impl ReflectionService {
+    pub fn new() -> Result<Self> {
+        let set = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET)?;
+        let mut symbols = HashMap::new();
+        let mut services = Vec::new();
+        for file in &set.file {
+            let name = file.name().to_string();
+            let prefix = match file.package() {
+                "" => String::new(),
+                package => format!("{}.", package),
+            };
+            for message in &file.message_type {
+                index_message(&prefix, message, &name, &mut symbols);
+            }
+            for enumeration in &file.enum_type {
+                symbols.insert(format!("{}{}", prefix, enumeration.name()), name.clone());
+            }
+            for service in &file.service {
+                let service_name = format!("{}{}", prefix, service.name());
+                for method in &service.method {
+                    symbols.insert(format!("{}.{}", service_name, method.name()), name.clone());
+                }
+                symbols.insert(service_name.clone(), name.clone());
+                services.push(service_name);
+            }
+        }
+        services.sort();
+        let files = set.file.into_iter().map(|file| (file.name().to_string(), file)).collect();
+        Ok(Self { files, symbols, services })
+    }
+
+    /// `name` and every file it imports, transitively, encoded
+    fn file_with_dependencies(&self, name: &str) -> Option<Vec<Vec<u8>>> {
+        let mut encoded = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![name.to_string()];
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let file = self.files.get(&name)?;
+            encoded.push(file.encode_to_vec());
+            pending.extend(file.dependency.iter().cloned());
+        }
+        Some(encoded)
+    }
+
+    fn answer(&self, request: &MessageRequest) -> MessageResponse {
+        let files = |file: Option<&String>, what: &str| match file.and_then(|file| self.file_with_dependencies(file)) {
+            Some(file_descriptor_proto) => MessageResponse::FileDescriptorResponse(FileDescriptorResponse { file_descriptor_proto }),
+            None => error(tonic::Code::NotFound, format!("{} not found", what)),
+        };
+        match request {
+            MessageRequest::ListServices(_) => MessageResponse::ListServicesResponse(ListServiceResponse {
+                service: self.services.iter().map(|name| ServiceResponse { name: name.clone() }).collect(),
+            }),
+            MessageRequest::FileByFilename(name) => files(Some(name), name),
+            MessageRequest::FileContainingSymbol(symbol) => files(self.symbols.get(symbol), symbol),
+            MessageRequest::FileContainingExtension(_) | MessageRequest::AllExtensionNumbersOfType(_) => {
+                error(tonic::Code::NotFound, "No extensions are defined".to_string())
+            }
+        }
+    }
+}
+
+fn index_message(prefix: &str, message: &DescriptorProto, file: &str, symbols: &mut HashMap<String, String>) {
+    let name = format!("{}{}", prefix, message.name());
+    let nested = format!("{}.", name);
+    for inner in &message.nested_type {
+        index_message(&nested, inner, file, symbols);
+    }
+    for enumeration in &message.enum_type {
+        symbols.insert(format!("{}{}", nested, enumeration.name()), file.to_string());
+    }
+    symbols.insert(name, file.to_string());
+}
+
+fn error(code: tonic::Code, message: String) -> MessageResponse {
+    MessageResponse::ErrorResponse(ErrorResponse { error_code: code as i32, error_message: message })
+}
+
+#[tonic::async_trait]
+impl ServerReflection for std::sync::Arc<ReflectionService> {
+    type ServerReflectionInfoStream = Pin<Box<dyn Stream<Item = Result<ServerReflectionResponse, Status>> + Send>>;
+
+    async fn server_reflection_info(
+        &self,
+        request: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+        let reflection = self.clone();
+        let responses = request.into_inner().map(move |request| {
+            let request = request?;
+            let message_response = match &request.message_request {
+                Some(message_request) => reflection.answer(message_request),
+                None => error(tonic::Code::InvalidArgument, "Empty reflection request".to_string()),
+            };
+            Ok(ServerReflectionResponse {
+                valid_host: request.host.clone(),
+                original_request: Some(request),
+                message_response: Some(message_response),
+            })
+        });
+        Ok(Response::new(Box::pin(responses)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(response: MessageResponse) -> Vec<FileDescriptorProto> {
+        match response {
+            MessageResponse::FileDescriptorResponse(files) => {
+                files.file_descriptor_proto.iter().map(|bytes| FileDescriptorProto::decode(&bytes[..]).unwrap()).collect()
+            }
+            other => panic!("Expected files, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_services_and_symbols() {
+        let reflection = ReflectionService::new().unwrap();
+        let MessageResponse::ListServicesResponse(list) = reflection.answer(&MessageRequest::ListServices(String::new())) else {
+            panic!("Expected a service list");
+        };
+        let services: Vec<_> = list.service.into_iter().map(|service| service.name).collect();
+        assert_eq!(
+            services,
+            vec![
+                "arrow.flight.protocol.FlightService",
+                "grpc.health.v1.Health",
+                "grpc.reflection.v1alpha.ServerReflection",
+                "orderbook.OrderbookService",
+            ]
+        );
+
+        for symbol in ["orderbook.OrderbookService", "orderbook.OrderbookService.SubscribeBBO", "orderbook.Bbo"] {
+            let found = files(reflection.answer(&MessageRequest::FileContainingSymbol(symbol.to_string())));
+            assert_eq!(found[0].name(), "subscribe.proto", "{}", symbol);
+        }
+        let nested = MessageRequest::FileContainingSymbol("grpc.health.v1.HealthCheckResponse.ServingStatus".to_string());
+        assert_eq!(files(reflection.answer(&nested))[0].name(), "grpc/health/v1/health.proto");
+        assert_eq!(files(reflection.answer(&MessageRequest::FileByFilename("subscribe.proto".to_string())))[0].package(), "orderbook");
+
+        let missing = reflection.answer(&MessageRequest::FileContainingSymbol("orderbook.Nope".to_string()));
+        assert!(matches!(missing, MessageResponse::ErrorResponse(ErrorResponse { error_code: 5, .. })));
+    }
+}
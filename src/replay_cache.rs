@@ -0,0 +1,243 @@
+use parking_lot::RwLock;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::fast_orderbook::FastOrderbook;
+use crate::market_processor::MarketUpdate;
+use crate::memory_profile::MemoryUsage;
+
+/// Sequence range covered by one published update
+#[derive(Debug, Clone, Copy)]
+struct UpdateBoundary {
+    timestamp_ns: u64,
+    first_sequence: u64,
+    last_sequence: u64,
+}
+
+/// Book levels as a streaming subscriber saw them after one update
+#[derive(Debug, Clone)]
+pub struct ReplayFrame {
+    pub timestamp_ns: u64,
+    pub sequence: u64,
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+}
+
+/// Remembers how the last `window` of deltas was grouped into published
+/// updates, so a late joiner can be sent the book as of the start of the
+/// window followed by exactly the frames existing subscribers received.
+///
+/// Only update boundaries are kept here; the deltas themselves come from
+/// each book's event log.
+pub struct ReplayCache {
+    window: Duration,
+    boundaries: RwLock<HashMap<u32, VecDeque<UpdateBoundary>>>,
+    /// Time of the last archived event when serving an archive, else 0
+    archive_end_ns: AtomicU64,
+}
+
+impl ReplayCache {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            boundaries: RwLock::new(HashMap::new()),
+            archive_end_ns: AtomicU64::new(0),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for market in self.boundaries.read().values() {
+            usage += MemoryUsage::new(market.len(), market.capacity() * std::mem::size_of::<UpdateBoundary>());
+        }
+        usage
+    }
+
+    pub fn record(&self, update: &MarketUpdate) {
+        let Some(first) = update.deltas.first() else {
+            return;
+        };
+
+        self.push(update.market_id, UpdateBoundary {
+            timestamp_ns: update.timestamp_ns,
+            first_sequence: first.sequence,
+            last_sequence: update.sequence,
+        });
+    }
+
+    fn push(&self, market_id: u32, boundary: UpdateBoundary) {
+        let cutoff = boundary.timestamp_ns.saturating_sub(self.window.as_nanos() as u64);
+        let mut boundaries = self.boundaries.write();
+        let market = boundaries.entry(market_id).or_default();
+
+        market.push_back(boundary);
+        while market.front().is_some_and(|b| b.timestamp_ns < cutoff) {
+            market.pop_front();
+        }
+    }
+
+    /// Serve an archive instead of a live feed: every event retained in the
+    /// restored logs becomes its own frame, and replay windows end at the
+    /// last archived event rather than now
+    pub fn seed_from_archive(&self, orderbooks: &HashMap<u32, Arc<FastOrderbook>>) {
+        let mut end_ns = 0;
+        for (market_id, orderbook) in orderbooks {
+            let events = {
+                let log = orderbook.event_log().lock();
+                log.events_since(log.base_sequence()).unwrap_or_default()
+            };
+            for event in events {
+                end_ns = end_ns.max(event.timestamp_ns);
+                self.push(*market_id, UpdateBoundary {
+                    timestamp_ns: event.timestamp_ns,
+                    first_sequence: event.sequence,
+                    last_sequence: event.sequence,
+                });
+            }
+        }
+        self.archive_end_ns.store(end_ns, Ordering::Relaxed);
+    }
+
+    /// The time replay windows end at
+    pub fn now_ns(&self) -> u64 {
+        match self.archive_end_ns.load(Ordering::Relaxed) {
+            0 => chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64,
+            end_ns => end_ns,
+        }
+    }
+
+    /// Frames for updates published at or after `since_ns`, preceded by the
+    /// book as it stood just before the first of them. Returns `None` if the
+    /// event log no longer holds the deltas, and an empty list if nothing was
+    /// published in the window.
+    pub fn replay(&self, market_id: u32, orderbook: &FastOrderbook, since_ns: u64, depth: usize) -> Option<Vec<ReplayFrame>> {
+        let boundaries: Vec<UpdateBoundary> = self
+            .boundaries
+            .read()
+            .get(&market_id)
+            .map(|market| market.iter().filter(|b| b.timestamp_ns >= since_ns).copied().collect())
+            .unwrap_or_default();
+
+        let Some(first) = boundaries.first() else {
+            return Some(Vec::new());
+        };
+        let base_sequence = first.first_sequence - 1;
+
+        let (mut state, events) = {
+            let log = orderbook.event_log().lock();
+            (log.state_at(base_sequence)?, log.events_since(base_sequence)?)
+        };
+
+        let mut frames = Vec::with_capacity(boundaries.len() + 1);
+        let (bids, asks) = state.levels(depth);
+        frames.push(ReplayFrame {
+            timestamp_ns: first.timestamp_ns,
+            sequence: base_sequence,
+            bids,
+            asks,
+        });
+
+        let mut events = events.into_iter().peekable();
+        for boundary in &boundaries {
+            while let Some(event) = events.next_if(|e| e.sequence <= boundary.last_sequence) {
+                state.apply(&event);
+            }
+            let (bids, asks) = state.levels(depth);
+            frames.push(ReplayFrame {
+                timestamp_ns: boundary.timestamp_ns,
+                sequence: boundary.last_sequence,
+                bids,
+                asks,
+            });
+        }
+
+        Some(frames)
+    }
+
+    pub fn start(self: Arc<Self>, mut update_rx: broadcast::Receiver<MarketUpdate>) {
+        info!("Keeping {:?} of update history for late joiners", self.window);
+
+        tokio::spawn(async move {
+            loop {
+                match update_rx.recv().await {
+                    Ok(update) => self.record(&update),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::Order;
+
+    fn add(orderbook: &FastOrderbook, id: u64, price: f64, is_buy: bool, timestamp_ns: u64) -> MarketUpdate {
+        let delta = orderbook.add_order(Order { id, price, size: 1.0, timestamp: 0 }, is_buy);
+        MarketUpdate::from_deltas(0, timestamp_ns, vec![delta]).unwrap()
+    }
+
+    #[test]
+    fn test_replay_matches_published_updates() {
+        let orderbook = FastOrderbook::new(0, "BTC".to_string());
+        let cache = ReplayCache::new(Duration::from_secs(2));
+
+        // Published before the window
+        cache.record(&add(&orderbook, 1, 100.0, true, 1_000_000_000));
+        // Two deltas published as one update, then a single one
+        let first = orderbook.add_order(Order { id: 2, price: 101.0, size: 1.0, timestamp: 0 }, false);
+        let second = orderbook.add_order(Order { id: 3, price: 99.0, size: 1.0, timestamp: 0 }, true);
+        cache.record(&MarketUpdate::from_deltas(0, 4_000_000_000, vec![first, second]).unwrap());
+        cache.record(&add(&orderbook, 4, 102.0, false, 4_500_000_000));
+
+        let frames = cache.replay(0, &orderbook, 3_000_000_000, 10).unwrap();
+        let sequences: Vec<u64> = frames.iter().map(|f| f.sequence).collect();
+        assert_eq!(sequences, vec![1, 3, 4]);
+
+        assert_eq!(frames[0].bids, vec![(100.0, 1.0)]);
+        assert!(frames[0].asks.is_empty());
+        assert_eq!(frames[1].bids, vec![(100.0, 1.0), (99.0, 1.0)]);
+        assert_eq!(frames[1].asks, vec![(101.0, 1.0)]);
+        assert_eq!(frames[2].asks, vec![(101.0, 1.0), (102.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_window_prunes_old_boundaries() {
+        let orderbook = FastOrderbook::new(0, "BTC".to_string());
+        let cache = ReplayCache::new(Duration::from_secs(1));
+
+        cache.record(&add(&orderbook, 1, 100.0, true, 1_000_000_000));
+        cache.record(&add(&orderbook, 2, 99.0, true, 5_000_000_000));
+
+        assert_eq!(cache.boundaries.read()[&0].len(), 1);
+        assert!(cache.replay(0, &orderbook, 6_000_000_000, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_archive_seeds_one_frame_per_event() {
+        let orderbook = Arc::new(FastOrderbook::new(0, "BTC".to_string()));
+        add(&orderbook, 1, 100.0, true, 0);
+        add(&orderbook, 2, 101.0, false, 0);
+        let last = orderbook.event_log().lock().events_since(0).unwrap().last().unwrap().timestamp_ns;
+
+        let cache = ReplayCache::new(Duration::from_secs(3600));
+        cache.seed_from_archive(&[(0, orderbook.clone())].into_iter().collect());
+        assert_eq!(cache.now_ns(), last);
+
+        let since = cache.now_ns() - cache.window().as_nanos() as u64;
+        let frames = cache.replay(0, &orderbook, since, 10).unwrap();
+        let sequences: Vec<u64> = frames.iter().map(|f| f.sequence).collect();
+        assert_eq!(sequences, vec![0, 1, 2]);
+        assert_eq!(frames[2].asks, vec![(101.0, 1.0)]);
+    }
+}
@@ -0,0 +1,239 @@
+//! JSON over HTTP for the unary snapshot calls, for integrations that can't
+//! speak gRPC.
+//!
+//! Each route builds the gRPC request, passes it through the same API key
+//! check and rate limit as a gRPC listener, and calls the service, so bodies
+//! are the gRPC responses with their proto field names. Markets in paths and
+//! queries are ids or symbols. Errors carry the gRPC code and message under
+//! the matching HTTP status.
+
+use anyhow::Result;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tonic::service::Interceptor;
+use tonic::{Code, Request, Status};
+use tracing::{error, info};
+
+use crate::auth_interceptor::ListenerPolicy;
+use crate::dynamic_markets::DynamicMarketRegistry;
+use crate::grpc_server::pb::orderbook_service_server::OrderbookService;
+use crate::grpc_server::pb::{
+    stop_orders_request, Empty, GetMarkPriceRequest, GetOrderbookRequest, MarkPriceResponse, MarketsResponse,
+    OrderbookSnapshot, StopOrdersRequest, StopOrdersResponse,
+};
+use crate::grpc_server::DeltaStreamingService;
+
+/// Levels per side when a request doesn't give a depth
+const DEFAULT_DEPTH: u32 = 20;
+
+#[derive(Clone)]
+struct Gateway {
+    service: Arc<DeltaStreamingService>,
+    registry: Arc<DynamicMarketRegistry>,
+    policy: ListenerPolicy,
+}
+
+struct ApiError(Status);
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        Self(status)
+    }
+}
+
+/// The HTTP status for a gRPC code, as mapped by Google's HTTP APIs
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::Cancelled | Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "code": format!("{:?}", self.0.code()), "message": self.0.message() });
+        (http_status(self.0.code()), Json(body)).into_response()
+    }
+}
+
+/// `message` as a gRPC request carrying the caller's API key, once the
+/// listener policy has let it through
+fn authorize<T>(policy: &ListenerPolicy, headers: &HeaderMap, message: T) -> Result<Request<T>, Status> {
+    let mut request = Request::new(());
+    if let Some(key) = headers.get("x-api-key").and_then(|key| key.to_str().ok()).and_then(|key| key.parse().ok()) {
+        request.metadata_mut().insert("x-api-key", key);
+    }
+    let (metadata, extensions, ()) = policy.clone().call(request)?.into_parts();
+    Ok(Request::from_parts(metadata, extensions, message))
+}
+
+impl Gateway {
+    fn request<T>(&self, headers: &HeaderMap, message: T) -> Result<Request<T>, ApiError> {
+        Ok(authorize(&self.policy, headers, message)?)
+    }
+
+    async fn market_id(&self, market: &str) -> Result<u32, ApiError> {
+        if let Ok(id) = market.parse() {
+            return Ok(id);
+        }
+        self.registry
+            .get_market_id(market)
+            .await
+            .ok_or_else(|| ApiError(Status::not_found(format!("Market {} not found", market))))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct OrderbookQuery {
+    depth: Option<u32>,
+    normalized_sizes: bool,
+    notional: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StopOrdersQuery {
+    market: Option<String>,
+    user: Option<String>,
+    min_notional: f64,
+    max_notional: f64,
+    max_distance_from_mid_bps: f64,
+    side: String,
+    rank_by_risk: bool,
+    distance_weight: f64,
+    slippage_weight: f64,
+}
+
+async fn orderbook(
+    State(gateway): State<Gateway>,
+    Path(market): Path<String>,
+    Query(query): Query<OrderbookQuery>,
+    headers: HeaderMap,
+) -> Result<Json<OrderbookSnapshot>, ApiError> {
+    let message = GetOrderbookRequest {
+        market_id: gateway.market_id(&market).await?,
+        depth: query.depth.unwrap_or(DEFAULT_DEPTH),
+        normalized_sizes: query.normalized_sizes,
+        notional: query.notional,
+        symbol: String::new(),
+    };
+    let request = gateway.request(&headers, message)?;
+    Ok(Json(gateway.service.get_orderbook(request).await?.into_inner()))
+}
+
+async fn markets(State(gateway): State<Gateway>, headers: HeaderMap) -> Result<Json<MarketsResponse>, ApiError> {
+    let request = gateway.request(&headers, Empty {})?;
+    Ok(Json(gateway.service.get_markets(request).await?.into_inner()))
+}
+
+async fn mark_price(
+    State(gateway): State<Gateway>,
+    Path(market): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<MarkPriceResponse>, ApiError> {
+    let message = GetMarkPriceRequest { market_id: gateway.market_id(&market).await? };
+    let request = gateway.request(&headers, message)?;
+    Ok(Json(gateway.service.get_mark_price(request).await?.into_inner()))
+}
+
+async fn stop_orders(
+    State(gateway): State<Gateway>,
+    Query(query): Query<StopOrdersQuery>,
+    headers: HeaderMap,
+) -> Result<Json<StopOrdersResponse>, ApiError> {
+    let filter = match (query.market, query.user) {
+        (Some(_), Some(_)) => return Err(ApiError(Status::invalid_argument("Filter by market or by user, not both"))),
+        (Some(market), None) => Some(stop_orders_request::Filter::MarketId(gateway.market_id(&market).await?)),
+        (None, Some(user)) => Some(stop_orders_request::Filter::User(user)),
+        (None, None) => None,
+    };
+    let message = StopOrdersRequest {
+        filter,
+        min_notional: query.min_notional,
+        max_notional: query.max_notional,
+        max_distance_from_mid_bps: query.max_distance_from_mid_bps,
+        side: query.side,
+        rank_by_risk: query.rank_by_risk,
+        distance_weight: query.distance_weight,
+        slippage_weight: query.slippage_weight,
+    };
+    let request = gateway.request(&headers, message)?;
+    Ok(Json(gateway.service.get_stop_orders(request).await?.into_inner()))
+}
+
+/// Serve the gateway on `addr` as a task of `servers`. Binds before
+/// returning, so a taken port fails startup.
+pub fn serve(
+    servers: &mut JoinSet<()>,
+    addr: SocketAddr,
+    service: Arc<DeltaStreamingService>,
+    registry: Arc<DynamicMarketRegistry>,
+    policy: ListenerPolicy,
+) -> Result<()> {
+    let router = Router::new()
+        .route("/orderbook/:market", get(orderbook))
+        .route("/markets", get(markets))
+        .route("/markprice/:market", get(mark_price))
+        .route("/stoporders", get(stop_orders))
+        .with_state(Gateway { service, registry, policy });
+
+    let server = axum::Server::try_bind(&addr)?;
+    info!("Starting REST gateway on {}", addr);
+    servers.spawn(async move {
+        if let Err(e) = server.serve(router.into_make_service()).await {
+            error!("REST gateway error: {}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grpc_codes_map_to_http_statuses() {
+        assert_eq!(http_status(Code::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(http_status(Code::Unauthenticated), StatusCode::UNAUTHORIZED);
+        assert_eq!(http_status(Code::ResourceExhausted), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(http_status(Code::FailedPrecondition), StatusCode::BAD_REQUEST);
+        assert_eq!(http_status(Code::Unavailable), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn test_requests_pass_the_listener_policy() {
+        let policy = ListenerPolicy::new(std::collections::HashSet::from(["secret".to_string()]).into(), true, None);
+        let check = |key: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(key) = key {
+                headers.insert("x-api-key", key.parse().unwrap());
+            }
+            authorize(&policy, &headers, 7u32)
+        };
+        let request = check(Some("secret")).unwrap();
+        assert_eq!((request.metadata().get("x-api-key").unwrap().to_str().unwrap(), *request.get_ref()), ("secret", 7));
+        assert_eq!(check(Some("wrong")).unwrap_err().code(), Code::Unauthenticated);
+        assert_eq!(check(None).unwrap_err().code(), Code::Unauthenticated);
+
+        // Without auth the key is optional
+        let open = ListenerPolicy::new(Default::default(), false, None);
+        assert!(authorize(&open, &HeaderMap::new(), ()).is_ok());
+    }
+}
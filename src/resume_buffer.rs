@@ -0,0 +1,123 @@
+use parking_lot::RwLock;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::fast_orderbook::SequencedDelta;
+use crate::market_processor::MarketUpdate;
+use crate::memory_profile::MemoryUsage;
+
+/// Updates kept per market when none is configured
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The last few updates of every market, so a reconnecting subscriber can
+/// pick up after the last sequence it applied instead of starting over
+pub struct ResumeBuffer {
+    capacity: usize,
+    markets: RwLock<HashMap<u32, VecDeque<MarketUpdate>>>,
+}
+
+impl Default for ResumeBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl ResumeBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            markets: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, update: &MarketUpdate) {
+        if update.deltas.is_empty() {
+            return;
+        }
+        let mut markets = self.markets.write();
+        let market = markets.entry(update.market_id).or_default();
+        if market.len() == self.capacity {
+            market.pop_front();
+        }
+        market.push_back(update.clone());
+    }
+
+    /// Every buffered update holding deltas after `sequence`, oldest first.
+    /// `None` if some of those deltas are no longer buffered; an empty list
+    /// if `sequence` is the latest.
+    pub fn since(&self, market_id: u32, sequence: u64) -> Option<Vec<MarketUpdate>> {
+        let markets = self.markets.read();
+        let Some(market) = markets.get(&market_id).filter(|m| !m.is_empty()) else {
+            return Some(Vec::new()).filter(|_| sequence == 0);
+        };
+
+        let latest = market.back()?.sequence;
+        let oldest = market.front()?.deltas.first()?.sequence;
+        // Ahead of the book (another instance's sequence) or aged out
+        if sequence > latest || sequence + 1 < oldest {
+            return None;
+        }
+
+        Some(market.iter().filter(|u| u.sequence > sequence).cloned().collect())
+    }
+
+    /// Buffered updates and the heap they hold
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let mut usage = MemoryUsage::default();
+        for market in self.markets.read().values() {
+            let deltas: usize = market.iter().map(|u| u.deltas.capacity() * std::mem::size_of::<SequencedDelta>()).sum();
+            usage += MemoryUsage::new(market.len(), market.capacity() * std::mem::size_of::<MarketUpdate>() + deltas);
+        }
+        usage
+    }
+
+    pub fn start(self: std::sync::Arc<Self>, mut update_rx: broadcast::Receiver<MarketUpdate>) {
+        info!("Keeping the last {} updates per market for resuming subscribers", self.capacity);
+
+        tokio::spawn(async move {
+            loop {
+                match update_rx.recv().await {
+                    Ok(update) => self.record(&update),
+                    // Resumes spanning the missed updates fall back to a snapshot
+                    Err(broadcast::error::RecvError::Lagged(_)) => self.markets.write().clear(),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::{FastOrderbook, Order};
+
+    #[test]
+    fn test_since_covers_only_buffered_sequences() {
+        let orderbook = FastOrderbook::new(0, "BTC".to_string());
+        let buffer = ResumeBuffer::new(2);
+        let publish = |ids: &[u64]| {
+            let deltas = ids
+                .iter()
+                .map(|id| orderbook.add_order(Order { id: *id, price: 100.0, size: 1.0, timestamp: 0 }, true))
+                .collect();
+            buffer.record(&MarketUpdate::from_deltas(0, 0, deltas).unwrap());
+        };
+
+        // Nothing buffered yet: only an empty book can resume
+        assert_eq!(buffer.since(0, 0).map(|u| u.len()), Some(0));
+        assert!(buffer.since(0, 3).is_none());
+
+        publish(&[1, 2]); // sequences 1-2
+        publish(&[3]); // 3
+        publish(&[4, 5]); // 4-5, evicts 1-2
+
+        let sequences = |after| buffer.since(0, after).map(|u| u.iter().map(|u| u.sequence).collect::<Vec<_>>());
+        assert_eq!(sequences(2), Some(vec![3, 5]));
+        assert_eq!(sequences(4), Some(vec![5]));
+        assert_eq!(sequences(5), Some(vec![]));
+        assert_eq!(sequences(1), None);
+        assert_eq!(sequences(9), None);
+    }
+}
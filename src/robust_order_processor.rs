@@ -1,18 +1,82 @@
 use anyhow::Result;
+use futures_util::FutureExt;
+use chrono::{TimeZone, Utc};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use tokio::sync::broadcast;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdout, Command};
 use tracing::{error, info, warn};
 
-use crate::fast_orderbook::{FastOrderbook, OrderbookDelta, Order};
-use crate::market_processor::MarketUpdate;
-use crate::markets;
+use crate::book_actor::{BookActors, BookCommand};
+use crate::fast_orderbook::Order;
+use crate::hourly_path::{HourlyLayout, HourlyRollover};
+use crate::node_format::Detection;
 use crate::dynamic_markets::DynamicMarketRegistry;
-use crate::order_parser::{OrderParser, ValidatedOrder, OrderStatus};
-use crate::stop_orders::{StopOrderManager, StopOrder};
-use crate::per_market_circuit_breaker::{PerMarketCircuitBreaker, CircuitBreakerConfig};
+use crate::session_events::SessionEvents;
+use crate::user_activity::UserActivityTracker;
+use crate::order_parser::{OrderParser, ParserStats, ValidatedOrder, OrderStatus, ErrorBuffer, ErrorCategory};
+use crate::alerts::{Alerts, Severity};
+use crate::stop_orders::{StopOrderEnd, StopOrderManager, StopOrder};
+use crate::per_market_circuit_breaker::{PerMarketCircuitBreaker, CircuitBreakerConfig, CircuitBreakerStats};
+use crate::quote_stuffing::QuoteStuffingFilter;
+use crate::state_snapshot::{ReadPosition, StateSnapshots};
+use crate::file_rotation::{FileChange, FileWatch};
+use crate::parse_pool::{parse_line, ParsePool, ParsedLine, PARSE_BATCH};
+use crate::virtual_time::{Pacer, VirtualClock};
+
+/// How long the previous hour's file must go quiet before switching to the next
+const ROLLOVER_QUIET: Duration = Duration::from_secs(2);
+
+/// How often to check whether the node has moved to a new hourly file
+const ROLLOVER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where in a file to start following it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TailFrom {
+    End,
+    /// Also waits for the file if it doesn't exist yet
+    Start,
+    /// The first byte not yet read
+    Byte(u64),
+}
+
+/// Follow `path` in the node container
+fn tail(path: &str, from: TailFrom) -> Result<(Child, Lines<BufReader<ChildStdout>>)> {
+    let (unit, start, follow) = match from {
+        TailFrom::End => ("-n", "0".to_string(), "-f"),
+        TailFrom::Start => ("-n", "+1".to_string(), "-F"),
+        TailFrom::Byte(offset) => ("-c", format!("+{}", offset + 1), "-f"),
+    };
+    let mut child = Command::new("docker")
+        .args(["exec", "hyperliquid-node-1", "tail", unit, &start, follow, path])
+        .stdout(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let stdout = child.stdout.take().expect("Failed to get stdout");
+    Ok((child, BufReader::new(stdout).lines()))
+}
+
+/// Where the last complete line of `path` on this host ends
+fn line_end(path: &Path) -> Option<u64> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut end = file.metadata().ok()?.len();
+    let mut chunk = [0u8; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(chunk.len() as u64);
+        let chunk = &mut chunk[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_exact(chunk).ok()?;
+        if let Some(i) = chunk.iter().rposition(|&b| b == b'\n') {
+            return Some(start + i as u64 + 1);
+        }
+        end = start;
+    }
+    Some(0)
+}
 
 /// Configuration for robust order processing
 pub struct ProcessorConfig {
@@ -35,17 +99,58 @@ impl Default for ProcessorConfig {
     }
 }
 
+/// Last order seen for a market
+#[derive(Debug, Clone, Copy)]
+pub struct MarketProgress {
+    /// Exchange timestamp of the last order (ms since epoch)
+    pub last_order_timestamp_ms: u64,
+    /// When the processor handled it (ms since epoch)
+    pub processed_at_ms: u64,
+}
+
+/// Snapshot of how far the processor has read
+#[derive(Debug, Clone)]
+pub struct ProcessorProgress {
+    pub data_path: String,
+    pub lines_read: u64,
+    pub bytes_read: u64,
+    pub markets: HashMap<u32, MarketProgress>,
+}
+
 /// Robust order processor with error recovery
 pub struct RobustOrderProcessor {
     parser: Arc<OrderParser>,
     config: ProcessorConfig,
-    error_buffer: Arc<crate::order_parser::ErrorBuffer>,
+    error_buffer: Arc<ErrorBuffer>,
     circuit_breaker: Arc<PerMarketCircuitBreaker>,
     market_registry: Arc<DynamicMarketRegistry>,
+    session_events: Arc<SessionEvents>,
+    user_activity: Arc<UserActivityTracker>,
+    node_format: Detection,
+    warm_start: bool,
+    snapshots: Option<StateSnapshots>,
+    resume: Option<ReadPosition>,
+    stuffing: Option<Arc<QuoteStuffingFilter>>,
+    parse_pool: Option<ParsePool>,
+    
+    // Read progress, exposed for liveness monitoring
+    data_path: parking_lot::RwLock<String>,
+    lines_read: AtomicU64,
+    bytes_read: AtomicU64,
+    tailing: AtomicBool,
+    /// Times the followed file was found truncated or replaced
+    rotations: AtomicU64,
+    market_progress: parking_lot::RwLock<HashMap<u32, MarketProgress>>,
 }
 
 impl RobustOrderProcessor {
-    pub fn new(config: ProcessorConfig, market_registry: Arc<DynamicMarketRegistry>) -> Self {
+    pub fn new(
+        config: ProcessorConfig,
+        market_registry: Arc<DynamicMarketRegistry>,
+        session_events: Arc<SessionEvents>,
+        user_activity: Arc<UserActivityTracker>,
+        alerts: Arc<Alerts>,
+    ) -> Self {
         // No need for static allowed_coins list anymore
         let parser = OrderParser::new()
             .with_limits(config.max_price, config.max_size)
@@ -58,23 +163,122 @@ impl RobustOrderProcessor {
             error_window: config.error_window,
         };
         
+        // Alert when a category reaches the error threshold within the window
+        let error_buffer = ErrorBuffer::new(100)
+            .with_spike_alerts(alerts, config.error_threshold as usize, config.error_window);
+        
         Self {
             parser: Arc::new(parser),
             config,
-            error_buffer: Arc::new(crate::order_parser::ErrorBuffer::new(100)),
+            error_buffer: Arc::new(error_buffer),
             circuit_breaker: Arc::new(PerMarketCircuitBreaker::new(cb_config)),
             market_registry,
+            session_events,
+            user_activity,
+            node_format: Detection::default(),
+            warm_start: false,
+            snapshots: None,
+            resume: None,
+            stuffing: None,
+            parse_pool: None,
+            data_path: parking_lot::RwLock::new(String::new()),
+            lines_read: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            tailing: AtomicBool::new(false),
+            rotations: AtomicU64::new(0),
+            market_progress: parking_lot::RwLock::new(HashMap::new()),
         }
     }
     
+    /// How lines of the feed are split into order statuses
+    pub fn with_node_format(mut self, node_format: Detection) -> Self {
+        self.node_format = node_format;
+        self
+    }
+    
+    /// Parse the feed in batches on `pool` rather than line by line on the
+    /// reading task
+    pub fn with_parse_pool(mut self, pool: ParsePool) -> Self {
+        self.parse_pool = Some(pool);
+        self
+    }
+    
+    /// Rebuild the books from the day's earlier hourly files before
+    /// following the current one
+    pub fn with_warm_start(mut self, warm_start: bool) -> Self {
+        self.warm_start = warm_start;
+        self
+    }
+    
+    /// Snapshot the books with the position they reflect, and resume from
+    /// `resume`, the last snapshot's position, with the books it restored
+    pub fn with_state_snapshots(mut self, snapshots: StateSnapshots, resume: Option<ReadPosition>) -> Self {
+        self.snapshots = Some(snapshots);
+        self.resume = resume;
+        self
+    }
+    
+    /// Watch book orders for quote stuffing
+    pub fn with_stuffing_filter(mut self, stuffing: Arc<QuoteStuffingFilter>) -> Self {
+        self.stuffing = Some(stuffing);
+        self
+    }
+    
+    pub fn node_format(&self) -> Detection {
+        self.node_format
+    }
+    
+    pub fn error_buffer(&self) -> Arc<ErrorBuffer> {
+        self.error_buffer.clone()
+    }
+    
+    /// Circuit state per market, plus the shared validation circuit
+    pub fn circuit_states(&self) -> (HashMap<u32, &'static str>, String) {
+        (
+            self.circuit_breaker.market_states(),
+            self.circuit_breaker.get_stats().validation_circuit_state,
+        )
+    }
+    
+    pub fn parser_stats(&self) -> ParserStats {
+        self.parser.stats()
+    }
+
+    pub fn circuit_breaker_stats(&self) -> CircuitBreakerStats {
+        self.circuit_breaker.get_stats()
+    }
+
+    /// Times the followed file was truncated or replaced and read over
+    pub fn rotations(&self) -> u64 {
+        self.rotations.load(Ordering::Relaxed)
+    }
+    
+    /// Following the node's current file, past any startup replay
+    pub fn is_tailing(&self) -> bool {
+        self.tailing.load(Ordering::Relaxed)
+    }
+    
+    pub fn progress(&self) -> ProcessorProgress {
+        ProcessorProgress {
+            data_path: self.data_path.read().clone(),
+            lines_read: self.lines_read.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            markets: self.market_progress.read().clone(),
+        }
+    }
+    
+    /// Follow the node's current hourly file, moving to each next hour's as
+    /// the node rolls over
     pub async fn start(
         self: Arc<Self>,
-        data_path: String,
-        orderbooks: Arc<std::collections::HashMap<u32, Arc<FastOrderbook>>>,
-        update_tx: broadcast::Sender<MarketUpdate>,
+        layout: HourlyLayout,
+        book_actors: Arc<BookActors>,
         stop_order_manager: Arc<StopOrderManager>,
     ) -> Result<()> {
-        info!("Starting robust order processor for: {}", data_path);
+        let now = Utc::now();
+        let data_path = layout.path_for(now);
+        info!("Starting robust order processor for: {}", data_path.display());
+        *self.data_path.write() = data_path.display().to_string();
         
         // Start monitoring task
         let monitor_self = self.clone();
@@ -82,91 +286,380 @@ impl RobustOrderProcessor {
             monitor_self.monitor_stats().await;
         });
         
+        // What the books are missing before the current file's last complete
+        // line: the rest of the restored snapshot's files, or for a warm
+        // start the whole day. Otherwise they fill in from the live tail.
+        let replay: Vec<(PathBuf, u64)> = match (&self.resume, self.warm_start) {
+            (Some(position), _) => {
+                let since = Utc.timestamp_millis_opt(position.saved_ms as i64).single().unwrap_or(now);
+                let later = layout
+                    .hours_since(since, now)
+                    .into_iter()
+                    .map(|hour| (layout.root.join(hour.name), 0))
+                    .filter(|(path, _)| *path != position.path);
+                position.resume_files().into_iter().chain(later).collect()
+            }
+            (None, true) => layout
+                .hours_so_far_today(now)
+                .into_iter()
+                .map(|hour| (layout.root.join(hour.name), 0))
+                .collect(),
+            (None, false) => Vec::new(),
+        };
+        let end = if replay.is_empty() {
+            line_end(&data_path)
+        } else {
+            self.replay(&replay, &book_actors, &stop_order_manager).await
+        };
+        // If the current file can't be read here, its position is unknown
+        let from = end.map_or(TailFrom::End, TailFrom::Byte);
+        
         // Main processing loop
-        self.process_orders(data_path, orderbooks, update_tx, stop_order_manager).await
+        let result = self.process_orders(layout, HourlyRollover::new(data_path, ROLLOVER_QUIET), from, book_actors, stop_order_manager).await;
+        self.tailing.store(false, Ordering::Relaxed);
+        result
+    }
+    
+    /// Replay each file from its offset, the last, current one up to its
+    /// last complete line. Returns that line's end, where following the
+    /// current file picks up, or `None` if it couldn't be read.
+    async fn replay(
+        &self,
+        files: &[(PathBuf, u64)],
+        book_actors: &BookActors,
+        stop_order_manager: &Arc<StopOrderManager>,
+    ) -> Option<u64> {
+        let started = Instant::now();
+        let mut replayed_files = 0;
+        let mut orders = 0;
+        let mut resume = None;
+        
+        for (i, (path, offset)) in files.iter().enumerate() {
+            match self.replay_file(path, *offset, book_actors, stop_order_manager).await {
+                Ok((end, replayed)) => {
+                    replayed_files += 1;
+                    orders += replayed;
+                    if i == files.len() - 1 {
+                        resume = Some(end);
+                    }
+                }
+                // Hours the node wasn't running have no file
+                Err(e) if e.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) => {}
+                Err(e) => warn!("Couldn't replay {}: {}", path.display(), e),
+            }
+        }
+        
+        book_actors.barrier().await;
+        info!(
+            "Replayed {} orders from {} of {} hourly files in {:.1}s",
+            orders,
+            replayed_files,
+            files.len(),
+            started.elapsed().as_secs_f64()
+        );
+        if resume.is_none() {
+            warn!("Current file unreadable, following it from its end");
+        }
+        resume
+    }
+    
+    /// Apply every complete line of `path` from byte `offset`, returning the
+    /// offset just past the last one and the orders that changed a book
+    async fn replay_file(
+        &self,
+        path: &Path,
+        offset: u64,
+        book_actors: &BookActors,
+        stop_order_manager: &Arc<StopOrderManager>,
+    ) -> Result<(u64, u64)> {
+        let mut file = tokio::fs::File::open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut reader = BufReader::new(file);
+        let mut line = Vec::new();
+        let mut batch = Vec::with_capacity(self.batch_size());
+        let mut end = offset;
+        let mut orders = 0;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line).await?;
+            // A partial last line is still being written; the tail reads it
+            let complete = read > 0 && line.last() == Some(&b'\n');
+            if complete {
+                end += read as u64;
+                self.lines_read.fetch_add(1, Ordering::Relaxed);
+                self.bytes_read.fetch_add(read as u64, Ordering::Relaxed);
+                batch.push(String::from_utf8_lossy(&line[..read - 1]).into_owned());
+                if batch.len() < self.batch_size() {
+                    continue;
+                }
+            }
+            
+            for parsed in self.parse_batch(std::mem::take(&mut batch)).await {
+                let (processed, _) = self.apply_line(parsed, book_actors, stop_order_manager).await;
+                orders += processed;
+            }
+            if !complete {
+                break;
+            }
+        }
+        Ok((end, orders))
+    }
+    
+    /// Replay a captured feed file in virtual time, `speed` times as fast as
+    /// the node wrote it or, at 0, as fast as the books apply it. Before the
+    /// clock moves to a line's newest order timestamp, the books publish
+    /// everything before it, so every run publishes the same updates.
+    /// Returns the orders that changed a book.
+    pub async fn replay_capture(
+        &self,
+        path: &Path,
+        speed: f64,
+        clock: &VirtualClock,
+        book_actors: &BookActors,
+        stop_order_manager: &Arc<StopOrderManager>,
+    ) -> Result<u64> {
+        info!("Replaying {} at {}x", path.display(), speed);
+        *self.data_path.write() = path.display().to_string();
+        let mut reader = BufReader::new(tokio::fs::File::open(path).await?);
+        let mut line = Vec::new();
+        let mut pacer = Pacer::new(speed);
+        let mut orders = 0;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line).await?;
+            if read == 0 {
+                break;
+            }
+            self.lines_read.fetch_add(1, Ordering::Relaxed);
+            self.bytes_read.fetch_add(read as u64, Ordering::Relaxed);
+            let text = String::from_utf8_lossy(line.strip_suffix(b"\n").unwrap_or(&line));
+            let parsed = parse_line(&self.parser, self.node_format.format, &text);
+            
+            let newest_ms = match &parsed {
+                ParsedLine::Statuses(statuses) => statuses.iter().filter_map(|(_, order)| order.as_ref().ok()).map(|order| order.timestamp).max(),
+                ParsedLine::InvalidBlock(..) => None,
+            };
+            if let Some(event_ms) = newest_ms.filter(|ms| *ms > clock.now_ms()) {
+                book_actors.barrier().await;
+                tokio::time::sleep(pacer.delay(event_ms, Instant::now())).await;
+                clock.advance_to(event_ms);
+            }
+            orders += self.apply_line(parsed, book_actors, stop_order_manager).await.0;
+        }
+        book_actors.barrier().await;
+        info!("Replayed {}: {} lines, {} orders applied", path.display(), self.lines_read.load(Ordering::Relaxed), orders);
+        Ok(orders)
     }
     
     async fn process_orders(
         &self,
-        data_path: String,
-        orderbooks: Arc<std::collections::HashMap<u32, Arc<FastOrderbook>>>,
-        update_tx: broadcast::Sender<MarketUpdate>,
+        layout: HourlyLayout,
+        mut rollover: HourlyRollover,
+        from: TailFrom,
+        book_actors: Arc<BookActors>,
         stop_order_manager: Arc<StopOrderManager>,
     ) -> Result<()> {
         // Start tailing the file
-        let mut cmd = Command::new("docker")
-            .args(&["exec", "hyperliquid-node-1", "tail", "-n", "0", "-f", &data_path])
-            .stdout(std::process::Stdio::piped())
-            .spawn()?;
-        
-        let stdout = cmd.stdout.take().expect("Failed to get stdout");
-        let reader = BufReader::new(stdout);
-        let mut lines = reader.lines();
+        let mut data_path = rollover.path().display().to_string();
+        let (mut cmd, mut lines) = tail(&data_path, from)?;
+        let mut watch = FileWatch::new(rollover.path());
+        self.tailing.store(true, Ordering::Relaxed);
+        let mut rollover_check = tokio::time::interval(ROLLOVER_CHECK_INTERVAL);
+        // The first byte of `data_path` not yet read, where known
+        let mut offset = match from {
+            TailFrom::End => None,
+            TailFrom::Start => Some(0),
+            TailFrom::Byte(offset) => Some(offset),
+        };
+        let snapshot_interval = self.snapshots.as_ref().map_or(Duration::from_secs(3600), |s| s.interval);
+        let mut snapshot_tick = tokio::time::interval_at(tokio::time::Instant::now() + snapshot_interval, snapshot_interval);
         
         let mut error_count = 0u32;
         let mut window_start = Instant::now();
         let mut order_count = 0u64;
         let start_time = Instant::now();
         
-        while let Ok(Some(line)) = lines.next_line().await {
+        loop {
+            let next = tokio::select! {
+                next = lines.next_line() => next,
+                _ = rollover_check.tick() => {
+                    // The old tail is stopped only once its file has gone quiet,
+                    // and the new file is read from its first line
+                    if let Some(next_path) = rollover.poll(layout.current_path(), Instant::now()) {
+                        let _ = cmd.kill().await;
+                        data_path = next_path.display().to_string();
+                        (cmd, lines) = tail(&data_path, TailFrom::Start)?;
+                        watch = FileWatch::new(&next_path);
+                        offset = Some(0);
+                        *self.data_path.write() = data_path.clone();
+                        info!("Reading orders from: {}", data_path);
+                        continue;
+                    }
+                    // Past the end of a truncated file, or on a replaced one,
+                    // the tail would never see another line
+                    let change = watch.check(offset);
+                    if change != FileChange::Unchanged {
+                        let _ = cmd.kill().await;
+                        (cmd, lines) = tail(&data_path, TailFrom::Start)?;
+                        offset = Some(0);
+                        self.rotations.fetch_add(1, Ordering::Relaxed);
+                        warn!("Node feed {} was {}, reading it from the start", data_path, change.as_str());
+                        self.error_buffer.record(
+                            ErrorCategory::Io,
+                            Severity::Warning,
+                            None,
+                            format!("Node feed {} was {}, reading it from the start", data_path, change.as_str()),
+                            "",
+                        );
+                    }
+                    continue;
+                }
+                _ = snapshot_tick.tick(), if self.snapshots.is_some() => {
+                    self.save_snapshot(&book_actors, &data_path, offset).await;
+                    continue;
+                }
+            };
+            // With a parse pool, take every line already there along with the
+            // first, up to a batch
+            let mut batch = Vec::with_capacity(self.batch_size());
+            let mut next = Some(next);
+            let mut ended = None;
+            while let Some(next_line) = next.take() {
+                match next_line {
+                    Ok(Some(line)) => batch.push(line),
+                    Ok(None) => ended = Some(format!("Node feed {} ended", data_path)),
+                    Err(e) => ended = Some(format!("Failed to read node feed {}: {}", data_path, e)),
+                }
+                if ended.is_none() && batch.len() < self.batch_size() {
+                    next = lines.next_line().now_or_never();
+                }
+            }
+            if !batch.is_empty() {
+                rollover.line_read(Instant::now());
+            }
+            for line in &batch {
+                if let Some(offset) = offset.as_mut() {
+                    *offset += line.len() as u64 + 1;
+                }
+                self.lines_read.fetch_add(1, Ordering::Relaxed);
+                self.bytes_read.fetch_add(line.len() as u64 + 1, Ordering::Relaxed);
+            }
+            
             // Reset error window
             if window_start.elapsed() > self.config.error_window {
                 error_count = 0;
                 window_start = Instant::now();
             }
             
-            // Process line with per-market circuit breaker
-            match self.process_single_order_with_circuit_breaker(&line, &orderbooks, &update_tx, &stop_order_manager).await {
-                Ok(processed) => {
-                    if processed {
-                        order_count += 1;
-                        
-                        // Log progress
-                        if order_count % 1000 == 0 {
-                            let elapsed = start_time.elapsed().as_secs_f64();
-                            let rate = order_count as f64 / elapsed;
-                            let stats = self.parser.stats();
-                            
-                            info!(
-                                "Processed {} orders, {:.0} orders/sec, success rate: {:.1}%",
-                                order_count, rate, stats.success_rate
-                            );
-                        }
+            for parsed in self.parse_batch(batch).await {
+                let (processed, errors) = self
+                    .apply_line(parsed, &book_actors, &stop_order_manager)
+                    .await;
+                for _ in 0..processed {
+                    order_count += 1;
+
+                    // Log progress
+                    if order_count.is_multiple_of(1000) {
+                        let elapsed = start_time.elapsed().as_secs_f64();
+                        let rate = order_count as f64 / elapsed;
+                        let stats = self.parser.stats();
+
+                        info!(
+                            "Processed {} orders, {:.0} orders/sec, success rate: {:.1}%",
+                            order_count, rate, stats.success_rate
+                        );
                     }
                 }
-                Err(e) => {
+                for e in errors {
                     error_count += 1;
-                    self.error_buffer.add(e.to_string(), line.clone());
-                    
+
                     // Sample error logging
                     if error_count % self.config.log_sample_rate == 1 {
-                        let recent_errors = self.error_buffer.recent_errors();
                         error!(
                             "Order processing error: {}, recent errors: {} in last minute",
                             e,
-                            recent_errors.len()
+                            self.error_buffer.count_within(Duration::from_secs(60))
                         );
                     }
                 }
             }
+            if let Some(message) = ended {
+                self.error_buffer.record(ErrorCategory::Io, Severity::Critical, None, message, "");
+                break;
+            }
         }
         
         Ok(())
     }
     
+    /// Snapshot the books once they've applied every line read so far
+    async fn save_snapshot(&self, book_actors: &BookActors, data_path: &str, offset: Option<u64>) {
+        let Some(snapshots) = &self.snapshots else { return };
+        let Some(offset) = offset else {
+            warn!("Skipping state snapshot: position in {} unknown until the next hour", data_path);
+            return;
+        };
+        book_actors.barrier().await;
+        if let Err(e) = snapshots.save(Path::new(data_path), offset) {
+            warn!("Failed to save state snapshot: {}", e);
+        }
+    }
+    
+    /// Parse a batch of lines, on the pool if there is one
+    async fn parse_batch(&self, lines: Vec<String>) -> Vec<ParsedLine> {
+        match &self.parse_pool {
+            Some(pool) => pool.parse(self.parser.clone(), self.node_format.format, lines).await,
+            None => lines.iter().map(|line| parse_line(&self.parser, self.node_format.format, line)).collect(),
+        }
+    }
+    
+    /// Lines read and parsed together; one at a time without a pool
+    fn batch_size(&self) -> usize {
+        if self.parse_pool.is_some() { PARSE_BATCH } else { 1 }
+    }
+    
+    /// Apply each order status in one parsed line of the feed. Returns how
+    /// many changed a book and the errors of those that failed.
+    async fn apply_line(
+        &self,
+        parsed: ParsedLine,
+        book_actors: &BookActors,
+        stop_order_manager: &Arc<StopOrderManager>,
+    ) -> (u64, Vec<anyhow::Error>) {
+        let statuses = match parsed {
+            ParsedLine::Statuses(statuses) => statuses,
+            ParsedLine::InvalidBlock(e, line) => {
+                self.error_buffer.record(ErrorCategory::Parse, Severity::Warning, None, format!("Invalid block: {}", e), &line);
+                return (0, Vec::new());
+            }
+        };
+        
+        let mut processed = 0;
+        let mut errors = Vec::new();
+        for (status, order) in statuses {
+            // Process each status with per-market circuit breaker
+            match self.process_single_order_with_circuit_breaker(&status, order, book_actors, stop_order_manager).await {
+                Ok(true) => processed += 1,
+                Ok(false) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+        (processed, errors)
+    }
+    
     async fn process_single_order_with_circuit_breaker(
         &self,
         line: &str,
-        orderbooks: &Arc<std::collections::HashMap<u32, Arc<FastOrderbook>>>,
-        update_tx: &broadcast::Sender<MarketUpdate>,
+        order: Result<ValidatedOrder>,
+        book_actors: &BookActors,
         stop_order_manager: &Arc<StopOrderManager>,
     ) -> Result<bool> {
-        // First parse to check what we're dealing with
-        let order = match self.parser.parse_line(line) {
+        let order = match order {
             Ok(order) => order,
             Err(e) => {
                 // Validation errors (size, price) go to validation circuit
                 self.circuit_breaker.record_validation_failure(e.to_string());
+                self.error_buffer.record(ErrorCategory::of_parse_error(&e), Severity::Warning, None, e.to_string(), line);
                 return Err(e);
             }
         };
@@ -187,7 +680,7 @@ impl RobustOrderProcessor {
                 }
                 
                 // Process the order
-                match self.process_market_order(order, market_id, orderbooks, update_tx, stop_order_manager).await {
+                match self.process_market_order(order, market_id, book_actors, stop_order_manager).await {
                     Ok(processed) => {
                         if processed {
                             self.circuit_breaker.record_market_success(market_id);
@@ -196,6 +689,7 @@ impl RobustOrderProcessor {
                     }
                     Err(e) => {
                         self.circuit_breaker.record_market_failure(market_id, e.to_string());
+                        self.error_buffer.record(ErrorCategory::Book, Severity::Error, Some(market_id), e.to_string(), line);
                         Err(e)
                     }
                 }
@@ -208,6 +702,7 @@ impl RobustOrderProcessor {
                 
                 let err = anyhow::anyhow!("Unknown market: {}", order.coin);
                 self.circuit_breaker.record_validation_failure(err.to_string());
+                self.error_buffer.record(ErrorCategory::Validation, Severity::Info, None, err.to_string(), line);
                 Err(err)
             }
         }
@@ -217,64 +712,76 @@ impl RobustOrderProcessor {
         &self,
         order: ValidatedOrder,
         market_id: u32,
-        orderbooks: &Arc<std::collections::HashMap<u32, Arc<FastOrderbook>>>,
-        update_tx: &broadcast::Sender<MarketUpdate>,
+        book_actors: &BookActors,
         stop_order_manager: &Arc<StopOrderManager>,
     ) -> Result<bool> {
-        // Get orderbook
-        let orderbook = orderbooks.get(&market_id)
+        let book = book_actors.handle(market_id)
             .ok_or_else(|| anyhow::anyhow!("No orderbook for market {}", market_id))?;
         
-        // Process based on order type
-        let delta = self.process_validated_order(order, orderbook, stop_order_manager, market_id)?;
+        // Boundaries are stamped before the first order of the new hour is
+        // applied, once the books have applied everything before it
+        if self.session_events.crosses_boundary(order.timestamp) {
+            book_actors.barrier().await;
+        }
+        self.session_events.observe_order_time(order.timestamp);
         
-        if let Some(delta) = delta {
-            // Send update
-            let update = MarketUpdate {
-                market_id,
-                sequence: orderbook.sequence.load(std::sync::atomic::Ordering::Relaxed),
-                timestamp_ns: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_nanos() as u64,
-                deltas: vec![delta],
-            };
-            
-            let _ = update_tx.send(update);
-            Ok(true)
-        } else {
-            Ok(false)
+        self.market_progress.write().insert(market_id, MarketProgress {
+            last_order_timestamp_ms: order.timestamp,
+            processed_at_ms: chrono::Utc::now().timestamp_millis() as u64,
+        });
+        
+        self.user_activity.observe(&order.user, market_id, &order.status, order.price, order.size, order.timestamp);
+        if let Some(stuffing) = self.stuffing.as_ref().filter(|_| !order.is_trigger) {
+            stuffing.observe(&order, market_id);
+        }
+        
+        // The book's actor applies the mutation and publishes the update
+        match Self::book_command(order, stop_order_manager, market_id) {
+            Some(command) => {
+                book.send(command).await?;
+                Ok(true)
+            }
+            None => Ok(false),
         }
     }
     
-    fn process_validated_order(
-        &self,
+    /// The book mutation for an order, if any. Stop orders are tracked
+    /// separately and never touch the book.
+    fn book_command(
         order: ValidatedOrder,
-        orderbook: &Arc<FastOrderbook>,
         stop_order_manager: &Arc<StopOrderManager>,
         market_id: u32,
-    ) -> Result<Option<OrderbookDelta>> {
+    ) -> Option<BookCommand> {
         // Skip rejected orders
         if matches!(order.status, OrderStatus::Rejected(_)) {
-            return Ok(None);
+            return None;
         }
         
         // Handle trigger/stop orders
         if order.is_trigger {
-            if matches!(order.status, OrderStatus::Open) {
-                let stop_order = StopOrder {
-                    id: order.id,
-                    user: order.user,
-                    coin: order.coin,
-                    side: if order.is_buy { "B" } else { "A" }.to_string(),
-                    price: order.price,
-                    size: order.size,
-                    trigger_condition: order.trigger_condition,
-                    timestamp: order.timestamp,
-                };
-                stop_order_manager.add_stop_order(market_id, stop_order);
-            }
-            return Ok(None);
+            let end = match order.status {
+                // A modified stop replaces the one it was
+                OrderStatus::Open | OrderStatus::Modified => {
+                    let stop_order = StopOrder {
+                        id: order.id,
+                        user: order.user,
+                        coin: order.coin,
+                        side: if order.is_buy { "B" } else { "A" }.to_string(),
+                        price: order.price,
+                        size: order.size,
+                        trigger_condition: order.trigger_condition,
+                        timestamp: order.timestamp,
+                    };
+                    stop_order_manager.add_stop_order(market_id, stop_order);
+                    return None;
+                }
+                OrderStatus::Triggered => StopOrderEnd::Triggered,
+                OrderStatus::Canceled => StopOrderEnd::Canceled,
+                OrderStatus::Filled => StopOrderEnd::Filled,
+                _ => return None,
+            };
+            stop_order_manager.end_stop_order(order.id, end, order.timestamp);
+            return None;
         }
         
         // Process regular orders
@@ -287,13 +794,15 @@ impl RobustOrderProcessor {
                     timestamp: order.timestamp,
                 };
                 
-                let delta = orderbook.add_order(book_order, order.is_buy);
-                Ok(Some(delta))
+                Some(BookCommand::Add { order: book_order, is_buy: order.is_buy })
             }
             OrderStatus::Filled | OrderStatus::Canceled => {
-                Ok(orderbook.remove_order(order.id, order.price, order.is_buy))
+                Some(BookCommand::Remove { order_id: order.id })
+            }
+            OrderStatus::Modified => {
+                Some(BookCommand::Modify { order_id: order.id, price: order.price, size: order.size })
             }
-            _ => Ok(None),
+            _ => None,
         }
     }
     
@@ -336,3 +845,92 @@ impl RobustOrderProcessor {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book_actor::ActorOptions;
+    use crate::fast_orderbook::{BookSnapshot, FastOrderbook};
+    use crate::market_ids::MarketKey;
+    use crate::market_processor::MarketUpdate;
+    use crate::market_scheduler::{MarketScheduler, SchedulerConfig};
+    use crate::symbology::TradableProduct;
+    use crate::watermarks::WatermarkTracker;
+    use tokio::sync::broadcast;
+
+    const T0: u64 = 1_740_812_400_000;
+
+    fn status(oid: u64, side: &str, price: &str, size: &str, status: &str, timestamp: u64) -> String {
+        format!(
+            r#"{{"time":"2025-03-01T07:00:00.1","user":"0xabc","status":"{}","order":{{"coin":"BTC","side":"{}","limitPx":"{}","sz":"{}","oid":{},"timestamp":{},"isTrigger":false,"triggerCondition":"N/A"}}}}"#,
+            status, side, price, size, oid, timestamp
+        )
+    }
+
+    /// Replay `capture` into a fresh book, returning what it published and
+    /// its final snapshot
+    async fn replay(capture: &Path, speed: f64) -> (Vec<MarketUpdate>, Arc<BookSnapshot>) {
+        let registry = Arc::new(DynamicMarketRegistry::new());
+        let key = MarketKey::External { source: "capture".to_string(), symbol: "BTC".to_string() };
+        let market_id = registry.register_market(key, TradableProduct::from_hyperliquid_coin("BTC"), 5).await.unwrap();
+        let orderbooks = HashMap::from([(market_id, Arc::new(FastOrderbook::new(market_id, "BTC".to_string())))]);
+
+        let (update_tx, mut update_rx) = broadcast::channel(64);
+        let clock = Arc::new(VirtualClock::new());
+        let actors = BookActors::spawn(
+            &orderbooks,
+            update_tx,
+            Arc::new(WatermarkTracker::new(orderbooks.clone(), Duration::ZERO)),
+            Arc::new(MarketScheduler::new(SchedulerConfig::default())),
+            Arc::default(),
+            ActorOptions { clock: Some(clock.clone()), ..Default::default() },
+        );
+        let processor = RobustOrderProcessor::new(
+            ProcessorConfig::default(),
+            registry,
+            Arc::new(SessionEvents::new(orderbooks.clone(), HourlyLayout::new("/data"))),
+            Arc::new(UserActivityTracker::new(Duration::from_secs(10))),
+            Arc::new(Alerts::new()),
+        );
+        let orders = processor
+            .replay_capture(capture, speed, &clock, &actors, &Arc::new(StopOrderManager::new()))
+            .await
+            .unwrap();
+        assert_eq!(orders, 5);
+        assert_eq!(clock.now_ms(), T0 + 500);
+
+        let mut updates = Vec::new();
+        while let Ok(update) = update_rx.try_recv() {
+            updates.push(update);
+        }
+        (updates, orderbooks[&market_id].snapshot())
+    }
+
+    #[tokio::test]
+    async fn test_replay_is_deterministic() {
+        let lines = [
+            status(1, "B", "100.0", "1.0", "open", T0),
+            status(2, "A", "101.0", "2.0", "open", T0),
+            status(3, "B", "99.5", "3.0", "open", T0 + 250),
+            // Older than the clock, so it doesn't move it
+            status(1, "B", "100.0", "1.0", "canceled", T0),
+            status(4, "A", "102.0", "1.0", "open", T0 + 500),
+        ];
+        let capture = std::env::temp_dir().join(format!("replay_capture_test_{}.log", std::process::id()));
+        std::fs::write(&capture, lines.join("\n") + "\n").unwrap();
+
+        let (updates, book) = replay(&capture, 0.0).await;
+        // One update per virtual time step, stamped with it
+        let golden: Vec<(u64, u64, usize)> = updates.iter().map(|u| (u.timestamp_ns / 1_000_000, u.sequence, u.deltas.len())).collect();
+        assert_eq!(golden, vec![(T0, 2, 2), (T0 + 250, 4, 2), (T0 + 500, 5, 1)]);
+        assert!(updates.iter().all(|u| u.timestamp_ns % 1_000_000 == 0));
+        assert_eq!(book.bids, vec![(99.5, 3.0)]);
+        assert_eq!(book.asks, vec![(101.0, 2.0), (102.0, 1.0)]);
+
+        // Paced or not, every run publishes the same
+        let (paced, paced_book) = replay(&capture, 100.0).await;
+        assert_eq!(format!("{:?}", paced), format!("{:?}", updates));
+        assert_eq!(paced_book, book);
+        std::fs::remove_file(&capture).unwrap();
+    }
+}
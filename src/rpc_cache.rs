@@ -0,0 +1,109 @@
+//! Read-through cache for frequently polled unary RPCs.
+//!
+//! Each entry remembers the version of the state it was built from, e.g. a
+//! book's sequence. A request whose key's current version matches is
+//! answered from the entry without touching the book, so polling clients
+//! always get exactly what a fresh build would return, at the cost of a
+//! version read and a clone.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Lookups one RPC's cache answered and built
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+pub struct RpcCache<K, Ver, T> {
+    entries: Mutex<HashMap<K, (Ver, T)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<K, Ver, T> Default for RpcCache<K, Ver, T> {
+    fn default() -> Self {
+        Self { entries: Mutex::new(HashMap::new()), hits: AtomicU64::new(0), misses: AtomicU64::new(0) }
+    }
+}
+
+impl<K: Hash + Eq, Ver: PartialEq, T: Clone> RpcCache<K, Ver, T> {
+    /// The entry for `key`, if built at `version`. A miss should be
+    /// followed by `insert`.
+    pub fn get(&self, key: &K, version: &Ver) -> Option<T> {
+        match self.entries.lock().get(key) {
+            Some((cached, value)) if cached == version => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value.clone())
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: K, version: Ver, value: T) {
+        self.entries.lock().insert(key, (version, value));
+    }
+
+    /// The entry for `key` if built at `version`, otherwise `build()`'s
+    /// result, which replaces it. Builds run outside the lock.
+    pub fn get_or_build(&self, key: K, version: Ver, build: impl FnOnce() -> T) -> T {
+        if let Some(value) = self.get(&key, &version) {
+            return value;
+        }
+        let value = build();
+        self.insert(key, version, value.clone());
+        value
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebuilds_only_on_new_versions() {
+        let cache: RpcCache<u32, u64, String> = RpcCache::default();
+        let mut builds = 0;
+        let mut get = |key, version| {
+            cache.get_or_build(key, version, || {
+                builds += 1;
+                format!("{}@{}", key, version)
+            })
+        };
+
+        assert_eq!(get(1, 10), "1@10");
+        assert_eq!(get(1, 10), "1@10");
+        assert_eq!(get(2, 10), "2@10");
+        assert_eq!(get(1, 11), "1@11");
+        assert_eq!(get(1, 11), "1@11");
+        assert_eq!(builds, 3);
+
+        let stats = cache.stats();
+        assert_eq!(stats, CacheStats { hits: 2, misses: 3, entries: 2 });
+        assert!((stats.hit_rate() - 0.4).abs() < 1e-9);
+    }
+}
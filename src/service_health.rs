@@ -0,0 +1,181 @@
+//! Order parser and circuit breaker health, for alerting.
+//!
+//! `GetServiceHealth` returns it over gRPC. With `--enable-metrics` the same
+//! numbers are served as Prometheus text on `/metrics`, read from the
+//! processor at each scrape, so nothing is registered or kept in between.
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tracing::{error, info};
+
+use crate::order_parser::ParserStats;
+use crate::per_market_circuit_breaker::CircuitBreakerStats;
+use crate::pipeline_latency::PipelineLatency;
+use crate::robust_order_processor::RobustOrderProcessor;
+
+/// Validation circuit states, as the breaker names them
+const CIRCUIT_STATES: [&str; 3] = ["CLOSED", "OPEN", "HALF-OPEN"];
+
+#[derive(Debug)]
+pub struct ServiceHealth {
+    pub parser: ParserStats,
+    pub circuits: CircuitBreakerStats,
+    /// Times the followed node file was truncated or replaced
+    pub rotations: u64,
+}
+
+impl ServiceHealth {
+    pub fn of(processor: &RobustOrderProcessor) -> Self {
+        let mut circuits = processor.circuit_breaker_stats();
+        circuits.open_markets.sort_by_key(|(market_id, _)| *market_id);
+        circuits.half_open_markets.sort_unstable();
+        Self { parser: processor.parser_stats(), circuits, rotations: processor.rotations() }
+    }
+
+    /// Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let (parser, circuits) = (&self.parser, &self.circuits);
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, f64)]| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+
+        let total = |value: u64| vec![(String::new(), value as f64)];
+        metric("orderbook_parser_messages_total", "counter", "Order lines read from the node feed", &total(parser.total_messages));
+        metric("orderbook_parser_parse_failures_total", "counter", "Order lines that weren't valid order JSON", &total(parser.parse_failures));
+        metric(
+            "orderbook_parser_validation_failures_total",
+            "counter",
+            "Parsed orders that failed validation",
+            &total(parser.validation_failures),
+        );
+        metric(
+            "orderbook_feed_rotations_total",
+            "counter",
+            "Times the followed node file was truncated or replaced and read from the start",
+            &total(self.rotations),
+        );
+        metric(
+            "orderbook_parser_success_ratio",
+            "gauge",
+            "Fraction of order lines parsed and validated",
+            &[(String::new(), parser.success_rate / 100.0)],
+        );
+
+        metric(
+            "orderbook_circuit_breaker_markets",
+            "gauge",
+            "Markets by circuit state",
+            &[
+                ("{state=\"open\"}".to_string(), circuits.open_markets.len() as f64),
+                ("{state=\"half_open\"}".to_string(), circuits.half_open_markets.len() as f64),
+                ("{state=\"closed\"}".to_string(), circuits.closed_markets as f64),
+            ],
+        );
+        let open: Vec<_> = circuits
+            .open_markets
+            .iter()
+            .map(|(market_id, _)| (format!("{{market_id=\"{}\"}}", market_id), 1.0))
+            .collect();
+        metric("orderbook_circuit_breaker_open", "gauge", "1 for each market whose circuit is open", &open);
+        let states: Vec<_> = CIRCUIT_STATES
+            .iter()
+            .map(|state| {
+                let current = (*state == circuits.validation_circuit_state) as u8 as f64;
+                (format!("{{state=\"{}\"}}", state), current)
+            })
+            .collect();
+        metric("orderbook_validation_circuit_state", "gauge", "1 for the validation circuit's current state", &states);
+        metric(
+            "orderbook_validation_circuit_failures",
+            "gauge",
+            "Failures counted by the validation circuit",
+            &[(String::new(), circuits.validation_failures as f64)],
+        );
+        out
+    }
+}
+
+/// Serve `/metrics` on `addr` as a task of `servers`, with pipeline
+/// latencies when `latency` is given. Binds before returning, so a taken
+/// port fails startup.
+pub fn serve(
+    servers: &mut JoinSet<()>,
+    addr: SocketAddr,
+    processor: Arc<RobustOrderProcessor>,
+    latency: Option<Arc<PipelineLatency>>,
+) -> Result<()> {
+    let router = Router::new().route("/metrics", get(metrics)).with_state(Sources { processor, latency });
+
+    let server = axum::Server::try_bind(&addr)?;
+    info!("Serving Prometheus metrics on {}/metrics", addr);
+    servers.spawn(async move {
+        if let Err(e) = server.serve(router.into_make_service()).await {
+            error!("Metrics endpoint error: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// What `/metrics` reads at each scrape
+#[derive(Clone)]
+struct Sources {
+    processor: Arc<RobustOrderProcessor>,
+    latency: Option<Arc<PipelineLatency>>,
+}
+
+async fn metrics(State(sources): State<Sources>) -> impl IntoResponse {
+    let mut text = ServiceHealth::of(&sources.processor).render();
+    if let Some(latency) = &sources.latency {
+        text.push_str(&latency.render());
+    }
+    ([(CONTENT_TYPE, "text/plain; version=0.0.4")], text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_health_renders_as_prometheus_text() {
+        let health = ServiceHealth {
+            parser: ParserStats { total_messages: 200, parse_failures: 6, validation_failures: 4, success_rate: 95.0 },
+            circuits: CircuitBreakerStats {
+                total_markets: 4,
+                open_markets: vec![(3, "too many failures".to_string())],
+                half_open_markets: vec![5],
+                closed_markets: 2,
+                validation_circuit_state: "OPEN".to_string(),
+                validation_failures: 12,
+            },
+            rotations: 1,
+        };
+        let text = health.render();
+        for line in [
+            "# TYPE orderbook_parser_messages_total counter",
+            "orderbook_parser_messages_total 200",
+            "orderbook_parser_parse_failures_total 6",
+            "orderbook_parser_success_ratio 0.95",
+            "orderbook_feed_rotations_total 1",
+            "orderbook_circuit_breaker_markets{state=\"open\"} 1",
+            "orderbook_circuit_breaker_markets{state=\"closed\"} 2",
+            "orderbook_circuit_breaker_open{market_id=\"3\"} 1",
+            "orderbook_validation_circuit_state{state=\"OPEN\"} 1",
+            "orderbook_validation_circuit_state{state=\"CLOSED\"} 0",
+            "orderbook_validation_circuit_failures 12",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::fast_orderbook::FastOrderbook;
+use crate::hourly_path::{HourFile, HourlyLayout};
+
+/// Recent events kept for subscribers that join mid-session
+const RECENT_EVENTS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEventKind {
+    /// Order timestamps crossed into a new hour, i.e. a new node hourly file
+    HourRollover { hour_file: String, hour_start_ms: u64 },
+    /// Order timestamps crossed into a new day
+    DayChange { date: String },
+    /// A market registry refresh was applied
+    RegistryRefresh { market_count: usize },
+}
+
+/// A session boundary, stamped with every book's sequence at the boundary so
+/// recorders can split their archives at exactly the same point
+#[derive(Debug, Clone)]
+pub struct SessionEvent {
+    pub event_id: u64,
+    pub timestamp_ns: u64,
+    pub kind: SessionEventKind,
+    pub sequences: BTreeMap<u32, u64>,
+}
+
+pub struct SessionEvents {
+    orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+    hourly: HourlyLayout,
+    next_event_id: AtomicU64,
+    current_hour: Mutex<Option<HourFile>>,
+    recent: Mutex<VecDeque<Arc<SessionEvent>>>,
+    output_tx: broadcast::Sender<Arc<SessionEvent>>,
+}
+
+impl SessionEvents {
+    /// Hour boundaries follow the node's hourly file naming in `hourly`
+    pub fn new(orderbooks: HashMap<u32, Arc<FastOrderbook>>, hourly: HourlyLayout) -> Self {
+        Self {
+            orderbooks,
+            hourly,
+            next_event_id: AtomicU64::new(1),
+            current_hour: Mutex::new(None),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_EVENTS)),
+            output_tx: broadcast::channel(256).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<SessionEvent>> {
+        self.output_tx.subscribe()
+    }
+
+    pub fn recent(&self) -> Vec<Arc<SessionEvent>> {
+        self.recent.lock().iter().cloned().collect()
+    }
+
+    /// Whether `observe_order_time` would emit a boundary for this timestamp
+    pub fn crosses_boundary(&self, timestamp_ms: u64) -> bool {
+        let Some(hour) = self.hourly.hour_of_ms(timestamp_ms) else {
+            return false;
+        };
+        matches!(&*self.current_hour.lock(), Some(current_hour) if hour.start > current_hour.start)
+    }
+
+    /// Feed the exchange timestamp of an order about to be applied. Emits
+    /// hour and day boundaries before the first order of the new period.
+    pub fn observe_order_time(&self, timestamp_ms: u64) {
+        let Some(hour) = self.hourly.hour_of_ms(timestamp_ms) else {
+            return;
+        };
+
+        let previous = {
+            let mut current = self.current_hour.lock();
+            match &*current {
+                // Late orders from the previous hour don't move the boundary back
+                Some(current_hour) if hour.start <= current_hour.start => return,
+                _ => current.replace(hour.clone()),
+            }
+        };
+
+        // The first order only establishes the current hour
+        let Some(previous) = previous else {
+            return;
+        };
+
+        if hour.date != previous.date {
+            self.emit(SessionEventKind::DayChange {
+                date: hour.date.format("%Y%m%d").to_string(),
+            });
+        }
+        self.emit(SessionEventKind::HourRollover {
+            hour_start_ms: hour.start.timestamp_millis() as u64,
+            hour_file: hour.name,
+        });
+    }
+
+    pub fn emit(&self, kind: SessionEventKind) -> Arc<SessionEvent> {
+        let event = Arc::new(SessionEvent {
+            event_id: self.next_event_id.fetch_add(1, Ordering::Relaxed),
+            timestamp_ns: chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64,
+            kind,
+            sequences: self
+                .orderbooks
+                .iter()
+                .map(|(market_id, orderbook)| (*market_id, orderbook.sequence.load(Ordering::Relaxed)))
+                .collect(),
+        });
+
+        info!("Session event {}: {:?}", event.event_id, event.kind);
+
+        {
+            let mut recent = self.recent.lock();
+            if recent.len() == RECENT_EVENTS {
+                recent.pop_front();
+            }
+            recent.push_back(event.clone());
+        }
+        let _ = self.output_tx.send(event.clone());
+        event
+    }
+
+    /// Emit a registry refresh event for every refresh the registry applies
+    pub fn start(self: Arc<Self>, mut refresh_rx: broadcast::Receiver<usize>) {
+        tokio::spawn(async move {
+            loop {
+                match refresh_rx.recv().await {
+                    Ok(market_count) => {
+                        self.emit(SessionEventKind::RegistryRefresh { market_count });
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    fn local_ms(y: i32, m: u32, d: u32, h: u32, min: u32) -> u64 {
+        Local.with_ymd_and_hms(y, m, d, h, min, 0).single().unwrap().timestamp_millis() as u64
+    }
+
+    #[test]
+    fn test_hour_and_day_boundaries() {
+        let events = SessionEvents::new(HashMap::new(), HourlyLayout::new("/data"));
+
+        events.observe_order_time(local_ms(2025, 3, 1, 22, 10));
+        assert!(events.recent().is_empty());
+
+        events.observe_order_time(local_ms(2025, 3, 1, 23, 0));
+        // A straggler from the previous hour is ignored
+        events.observe_order_time(local_ms(2025, 3, 1, 22, 59));
+        events.observe_order_time(local_ms(2025, 3, 2, 0, 5));
+
+        let kinds: Vec<SessionEventKind> = events.recent().iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SessionEventKind::HourRollover {
+                    hour_file: "20250301/23".to_string(),
+                    hour_start_ms: local_ms(2025, 3, 1, 23, 0),
+                },
+                SessionEventKind::DayChange { date: "20250302".to_string() },
+                SessionEventKind::HourRollover {
+                    hour_file: "20250302/0".to_string(),
+                    hour_start_ms: local_ms(2025, 3, 2, 0, 0),
+                },
+            ]
+        );
+
+        let ids: Vec<u64> = events.recent().iter().map(|e| e.event_id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_events_carry_book_sequences() {
+        let orderbook = Arc::new(FastOrderbook::new(7, "BTC".to_string()));
+        orderbook.add_order(crate::fast_orderbook::Order { id: 1, price: 100.0, size: 1.0, timestamp: 0 }, true);
+        let events = SessionEvents::new([(7, orderbook)].into_iter().collect(), HourlyLayout::new("/data"));
+
+        let event = events.emit(SessionEventKind::RegistryRefresh { market_count: 1 });
+        assert_eq!(event.sequences.get(&7), Some(&1));
+    }
+}
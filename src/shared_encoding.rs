@@ -0,0 +1,143 @@
+//! Messages encoded once and sent as the same bytes to every subscriber.
+//!
+//! Generated handlers encode each stream's messages themselves, so a delta
+//! sent to N subscribers of a market would be built and encoded N times.
+//! [`SharedEncodingRoutes`] serves `OrderbookService` with SubscribeDeltas
+//! replaced by a handler whose stream yields [`Encoded`] messages, which
+//! write their bytes unchanged; every other call goes to the generated
+//! handlers.
+
+use prost::bytes::{Buf, BufMut, Bytes};
+use prost::encoding::{DecodeContext, WireType};
+use prost::{DecodeError, Message};
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_stream::Stream;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Body, BoxFuture, Service, StdError};
+use tonic::server::{Grpc, NamedService, ServerStreamingService};
+use tonic::{Request, Response, Status};
+
+use crate::grpc_server::pb::orderbook_service_server::OrderbookServiceServer;
+use crate::grpc_server::pb::DeltaSubscribeRequest;
+use crate::grpc_server::DeltaStreamingService;
+
+const SUBSCRIBE_DELTAS: &str = "/orderbook.OrderbookService/SubscribeDeltas";
+
+pub type EncodedStream = Pin<Box<dyn Stream<Item = Result<Encoded, Status>> + Send + 'static>>;
+
+/// A message already in its wire encoding. Clones share the bytes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Encoded(Bytes);
+
+impl Encoded {
+    pub fn new<M: Message>(message: &M) -> Self {
+        Self(message.encode_to_vec().into())
+    }
+
+    pub fn decode<M: Message + Default>(&self) -> Result<M, Status> {
+        M::decode(self.0.clone()).map_err(|e| Status::internal(format!("Decoding a shared message failed: {}", e)))
+    }
+}
+
+/// Encoding writes the bytes as they are. Decoding skips every field, as
+/// the type doesn't know its message's fields.
+impl Message for Encoded {
+    fn encode_raw<B: BufMut>(&self, buf: &mut B) {
+        buf.put_slice(&self.0);
+    }
+
+    fn merge_field<B: Buf>(
+        &mut self,
+        tag: u32,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError> {
+        prost::encoding::skip_field(wire_type, tag, buf, ctx)
+    }
+
+    fn encoded_len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn clear(&mut self) {
+        self.0 = Bytes::new();
+    }
+}
+
+struct SubscribeDeltas(Arc<DeltaStreamingService>);
+
+impl ServerStreamingService<DeltaSubscribeRequest> for SubscribeDeltas {
+    type Response = Encoded;
+    type ResponseStream = EncodedStream;
+    type Future = BoxFuture<Response<EncodedStream>, Status>;
+
+    fn call(&mut self, request: Request<DeltaSubscribeRequest>) -> Self::Future {
+        let result = self.0.encoded_deltas(request).map(Response::new);
+        Box::pin(async move { result })
+    }
+}
+
+/// `OrderbookService` with SubscribeDeltas sending shared encodings
+#[derive(Clone)]
+pub struct SharedEncodingRoutes {
+    service: Arc<DeltaStreamingService>,
+    generated: OrderbookServiceServer<DeltaStreamingService>,
+}
+
+impl SharedEncodingRoutes {
+    pub fn new(service: Arc<DeltaStreamingService>) -> Self {
+        Self { generated: OrderbookServiceServer::from_arc(service.clone()), service }
+    }
+}
+
+impl<B> Service<http::Request<B>> for SharedEncodingRoutes
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        if request.uri().path() != SUBSCRIBE_DELTAS {
+            return self.generated.call(request);
+        }
+        let method = SubscribeDeltas(self.service.clone());
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::<Encoded, DeltaSubscribeRequest>::default());
+            Ok(grpc.server_streaming(method, request).await)
+        })
+    }
+}
+
+impl NamedService for SharedEncodingRoutes {
+    const NAME: &'static str = <OrderbookServiceServer<DeltaStreamingService> as NamedService>::NAME;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grpc_server::pb::{delta_message, DeltaMessage, OrderbookDelta};
+
+    #[test]
+    fn test_encoded_messages_write_their_bytes() {
+        let message = DeltaMessage {
+            payload: Some(delta_message::Payload::Delta(OrderbookDelta { market_id: 3, sequence: 7, ..Default::default() })),
+        };
+        let encoded = Encoded::new(&message);
+        assert_eq!(encoded.encoded_len(), message.encoded_len());
+        // Framed the way the codec frames stream messages
+        assert_eq!(encoded.encode_length_delimited_to_vec(), message.encode_length_delimited_to_vec());
+        assert_eq!(encoded.clone().decode::<DeltaMessage>().unwrap(), message);
+    }
+}
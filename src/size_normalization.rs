@@ -0,0 +1,72 @@
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::dynamic_markets::DynamicMarketRegistry;
+
+/// Extra size fields a subscriber asked for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeOptions {
+    /// Integer lots of 10^-sz_decimals alongside the raw quantity
+    pub normalized: bool,
+    /// Quantity times mark price, in USD
+    pub notional: bool,
+}
+
+/// Size precision per market, kept in step with the market registry
+#[derive(Debug, Default)]
+pub struct SizeNormalizer {
+    sz_decimals: RwLock<HashMap<u32, u32>>,
+}
+
+impl SizeNormalizer {
+    pub fn new(sz_decimals: HashMap<u32, u32>) -> Self {
+        Self {
+            sz_decimals: RwLock::new(sz_decimals),
+        }
+    }
+
+    pub fn sz_decimals(&self, market_id: u32) -> Option<u32> {
+        self.sz_decimals.read().get(&market_id).copied()
+    }
+
+    /// Quantity in whole lots. Book quantities are sums of node sizes, so
+    /// rounding removes float noise rather than real size.
+    pub fn lots(quantity: f64, sz_decimals: u32) -> u64 {
+        (quantity * 10f64.powi(sz_decimals as i32)).round() as u64
+    }
+
+    /// Reload precisions whenever the registry applies a refresh
+    pub fn start(self: Arc<Self>, registry: Arc<DynamicMarketRegistry>, mut refresh_rx: broadcast::Receiver<usize>) {
+        tokio::spawn(async move {
+            // Runs until the registry closes the channel
+            while let Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) = refresh_rx.recv().await {
+                let sz_decimals = registry.get_sz_decimals().await;
+                if sz_decimals.is_empty() {
+                    warn!("Registry refresh carried no size decimals, keeping previous");
+                    continue;
+                }
+                info!("Reloaded size decimals for {} markets", sz_decimals.len());
+                *self.sz_decimals.write() = sz_decimals;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lots_absorb_float_noise() {
+        assert_eq!(SizeNormalizer::lots(0.1 + 0.2, 5), 30_000);
+        assert_eq!(SizeNormalizer::lots(12.0, 0), 12);
+        assert_eq!(SizeNormalizer::lots(1.23456, 4), 12_346);
+
+        let normalizer = SizeNormalizer::new([(0, 5)].into_iter().collect());
+        assert_eq!(normalizer.sz_decimals(0), Some(5));
+        assert_eq!(normalizer.sz_decimals(1), None);
+    }
+}
@@ -0,0 +1,125 @@
+//! Per-subscriber record of the sequence each market's client book is at.
+//!
+//! A stream subscribes to updates before it reads a snapshot, so updates
+//! published in between are both in the snapshot and still queued for the
+//! stream. Forwarding only the deltas past the snapshot's sequence keeps a
+//! client from applying a change twice.
+
+use std::collections::HashMap;
+
+use crate::fast_orderbook::SequencedDelta;
+use crate::market_processor::MarketUpdate;
+
+#[derive(Debug, Default)]
+pub struct SnapshotBarrier {
+    sequences: HashMap<u32, u64>,
+}
+
+impl SnapshotBarrier {
+    /// The client now holds `market_id` as of `sequence`, e.g. after being
+    /// sent a snapshot. Replaces any earlier barrier, as a resync does.
+    pub fn record(&mut self, market_id: u32, sequence: u64) {
+        self.sequences.insert(market_id, sequence);
+    }
+
+    pub fn sequence(&self, market_id: u32) -> Option<u64> {
+        self.sequences.get(&market_id).copied()
+    }
+
+    /// The deltas of `update` the client doesn't have yet, advancing the
+    /// barrier past them. `None` for markets without a snapshot yet and for
+    /// updates the snapshot already covers.
+    pub fn admit<'a>(&mut self, update: &'a MarketUpdate) -> Option<&'a [SequencedDelta]> {
+        let sequence = self.sequences.get_mut(&update.market_id)?;
+        let start = update.deltas.partition_point(|d| d.sequence <= *sequence);
+        let fresh = &update.deltas[start..];
+        if fresh.is_empty() {
+            return None;
+        }
+        *sequence = update.sequence;
+        Some(fresh)
+    }
+
+    /// Whether `update` starts past the next sequence the client needs, so
+    /// deltas in between were missed
+    pub fn has_gap(&self, update: &MarketUpdate) -> bool {
+        let Some(sequence) = self.sequence(update.market_id) else { return false };
+        update
+            .deltas
+            .iter()
+            .find(|d| d.sequence > sequence)
+            .is_some_and(|d| d.sequence > sequence + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::{FastOrderbook, Order};
+    use tokio::sync::broadcast;
+
+    fn order(id: u64, price: f64) -> Order {
+        Order { id, price, size: 1.0, timestamp: 0 }
+    }
+
+    /// Apply a batch the way the book actor does: publish, then broadcast
+    fn apply(book: &FastOrderbook, tx: &broadcast::Sender<MarketUpdate>, orders: &[(u64, f64)]) {
+        let deltas = orders.iter().map(|(id, price)| book.add_order(order(*id, *price), true)).collect();
+        book.publish();
+        let _ = tx.send(MarketUpdate::from_deltas(0, 0, deltas).unwrap());
+    }
+
+    #[test]
+    fn test_updates_queued_before_the_snapshot_are_dropped() {
+        let book = FastOrderbook::new(0, "BTC".to_string());
+        let (tx, _) = broadcast::channel(16);
+        apply(&book, &tx, &[(1, 100.0)]);
+
+        // The stream subscribes, then two batches land before it reads the snapshot
+        let mut rx = tx.subscribe();
+        apply(&book, &tx, &[(2, 100.0), (3, 99.0)]);
+        apply(&book, &tx, &[(4, 98.0)]);
+        let snapshot = book.snapshot();
+        assert_eq!(snapshot.sequence, 4);
+        let mut barrier = SnapshotBarrier::default();
+        barrier.record(0, snapshot.sequence);
+
+        apply(&book, &tx, &[(5, 97.0)]);
+
+        let mut forwarded = Vec::new();
+        while let Ok(update) = rx.try_recv() {
+            assert!(!barrier.has_gap(&update));
+            if let Some(deltas) = barrier.admit(&update) {
+                forwarded.extend(deltas.iter().map(|d| d.sequence));
+            }
+        }
+        // Only the batch after the snapshot, applied once
+        assert_eq!(forwarded, vec![5]);
+        assert_eq!(barrier.sequence(0), Some(5));
+    }
+
+    #[test]
+    fn test_batch_straddling_the_snapshot_is_trimmed() {
+        let book = FastOrderbook::new(0, "BTC".to_string());
+        let deltas: Vec<_> = (1..=4).map(|id| book.add_order(order(id, 100.0), true)).collect();
+        let update = MarketUpdate::from_deltas(0, 0, deltas).unwrap();
+
+        // A snapshot taken mid-batch, e.g. from the live book
+        let mut barrier = SnapshotBarrier::default();
+        barrier.record(0, 2);
+        let fresh = barrier.admit(&update).unwrap();
+        assert_eq!(fresh.iter().map(|d| d.sequence).collect::<Vec<_>>(), vec![3, 4]);
+
+        // Redelivery, e.g. after a resume, forwards nothing
+        assert!(barrier.admit(&update).is_none());
+        // Markets without a snapshot are not forwarded
+        let other = MarketUpdate { market_id: 1, ..update.clone() };
+        assert!(barrier.admit(&other).is_none());
+
+        // Deltas past a hole need a catch-up first
+        barrier.record(0, 1);
+        let later = MarketUpdate::from_deltas(0, 0, vec![book.add_order(order(5, 100.0), true)]).unwrap();
+        assert!(barrier.has_gap(&later));
+        assert!(!barrier.has_gap(&update));
+    }
+}
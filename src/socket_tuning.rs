@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tracing::info;
+
+/// Starting points for socket tuning; individual options override the preset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketPreset {
+    /// Kernel and tonic defaults
+    Default,
+    /// Same-datacenter latency-sensitive consumers: no Nagle, modest buffers
+    /// so queues stay short, flow-control windows large enough to never stall
+    LanHft,
+    /// Remote analytics consumers over high-latency links: large buffers and
+    /// adaptive windows to fill the pipe, Nagle left on to batch small writes
+    WanAnalytics,
+}
+
+impl SocketPreset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SocketPreset::Default => "default",
+            SocketPreset::LanHft => "lan-hft",
+            SocketPreset::WanAnalytics => "wan-analytics",
+        }
+    }
+}
+
+impl FromStr for SocketPreset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "default" => Ok(SocketPreset::Default),
+            "lan-hft" => Ok(SocketPreset::LanHft),
+            "wan-analytics" => Ok(SocketPreset::WanAnalytics),
+            other => Err(anyhow!(
+                "Unknown socket preset: {} (expected default, lan-hft or wan-analytics)",
+                other
+            )),
+        }
+    }
+}
+
+/// TCP and HTTP/2 settings for the gRPC listener. `None` keeps the default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocketTuning {
+    pub tcp_nodelay: bool,
+    pub tcp_keepalive: Option<Duration>,
+    pub send_buffer_bytes: Option<usize>,
+    pub recv_buffer_bytes: Option<usize>,
+    pub http2_keepalive_interval: Option<Duration>,
+    pub http2_keepalive_timeout: Option<Duration>,
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    pub http2_adaptive_window: Option<bool>,
+}
+
+impl SocketTuning {
+    pub fn preset(preset: SocketPreset) -> Self {
+        match preset {
+            SocketPreset::Default => Self {
+                tcp_nodelay: false,
+                tcp_keepalive: None,
+                send_buffer_bytes: None,
+                recv_buffer_bytes: None,
+                http2_keepalive_interval: None,
+                http2_keepalive_timeout: None,
+                initial_stream_window_size: None,
+                initial_connection_window_size: None,
+                http2_adaptive_window: None,
+            },
+            SocketPreset::LanHft => Self {
+                tcp_nodelay: true,
+                tcp_keepalive: Some(Duration::from_secs(30)),
+                send_buffer_bytes: Some(256 * 1024),
+                recv_buffer_bytes: Some(64 * 1024),
+                http2_keepalive_interval: Some(Duration::from_secs(10)),
+                http2_keepalive_timeout: Some(Duration::from_secs(5)),
+                initial_stream_window_size: Some(1024 * 1024),
+                initial_connection_window_size: Some(4 * 1024 * 1024),
+                http2_adaptive_window: Some(false),
+            },
+            SocketPreset::WanAnalytics => Self {
+                tcp_nodelay: false,
+                tcp_keepalive: Some(Duration::from_secs(60)),
+                send_buffer_bytes: Some(4 * 1024 * 1024),
+                recv_buffer_bytes: Some(1024 * 1024),
+                http2_keepalive_interval: Some(Duration::from_secs(30)),
+                http2_keepalive_timeout: Some(Duration::from_secs(20)),
+                initial_stream_window_size: Some(8 * 1024 * 1024),
+                initial_connection_window_size: Some(32 * 1024 * 1024),
+                http2_adaptive_window: Some(true),
+            },
+        }
+    }
+
+    /// Apply the HTTP/2 settings to a server builder
+    pub fn configure(&self, builder: Server) -> Server {
+        builder
+            .tcp_nodelay(self.tcp_nodelay)
+            .tcp_keepalive(self.tcp_keepalive)
+            .http2_keepalive_interval(self.http2_keepalive_interval)
+            .http2_keepalive_timeout(self.http2_keepalive_timeout)
+            .initial_stream_window_size(self.initial_stream_window_size)
+            .initial_connection_window_size(self.initial_connection_window_size)
+            .http2_adaptive_window(self.http2_adaptive_window)
+    }
+
+    /// Bind a TCP listener with the configured buffer sizes. Accepted
+    /// connections inherit the listener's buffers, and the receive buffer
+    /// must be set before listening for window scaling to account for it.
+    pub fn bind(&self, addr: SocketAddr) -> Result<TcpIncoming> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        // Leave IPv4 on the same port to a separate listener
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        if let Some(bytes) = self.send_buffer_bytes {
+            socket.set_send_buffer_size(bytes)?;
+        }
+        if let Some(bytes) = self.recv_buffer_bytes {
+            socket.set_recv_buffer_size(bytes)?;
+        }
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+
+        // The kernel may clamp or double the requested sizes
+        info!(
+            "gRPC listener {} buffers: send {} bytes, recv {} bytes, nodelay {}",
+            addr,
+            socket.send_buffer_size()?,
+            socket.recv_buffer_size()?,
+            self.tcp_nodelay
+        );
+
+        let listener = tokio::net::TcpListener::from_std(socket.into())?;
+        TcpIncoming::from_listener(listener, self.tcp_nodelay, self.tcp_keepalive).map_err(|e| anyhow!(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_names_round_trip() {
+        for preset in [SocketPreset::Default, SocketPreset::LanHft, SocketPreset::WanAnalytics] {
+            assert_eq!(preset.as_str().parse::<SocketPreset>().unwrap(), preset);
+        }
+        assert!("lan".parse::<SocketPreset>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_applies_buffers() {
+        let tuning = SocketTuning::preset(SocketPreset::LanHft);
+        assert!(tuning.bind("127.0.0.1:0".parse().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,360 @@
+//! Periodic snapshots of every book with the feed position they reflect.
+//!
+//! The order processor pauses at a line boundary, waits for the books to
+//! apply everything it sent, then writes each book's event log and last the
+//! file and byte offset it had read to. On restart the books are restored
+//! and reading resumes from that byte, so only what the node wrote since the
+//! snapshot is replayed instead of whole hourly files. The file's inode is
+//! saved with the offset, so a file rotated or truncated meanwhile is
+//! detected rather than resumed mid-way through different contents.
+//!
+//! Snapshots can also be replicated off the host with an operator's command,
+//! e.g. `aws s3 sync`, so a replacement host can bootstrap from them when
+//! the original is lost. The command runs on a staged copy, so a snapshot
+//! written meanwhile can't mix into the upload.
+
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::event_log::{self, EventLog};
+use crate::fast_orderbook::FastOrderbook;
+use crate::mark_price_v2::MarkEmaState;
+
+const POSITION_FILE: &str = "position.json";
+const EMA_FILE: &str = "mark_ema.json";
+
+/// How far into the node's files the books reflect
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadPosition {
+    pub path: PathBuf,
+    /// The first byte not yet applied
+    pub offset: u64,
+    /// When the snapshot was taken, ms since epoch
+    pub saved_ms: u64,
+    /// The file read, which keeps its inode if rotated to another name;
+    /// absent from snapshots written before inodes were saved
+    #[serde(default)]
+    pub inode: Option<u64>,
+}
+
+impl ReadPosition {
+    /// The files to replay to catch up from this position, each from its
+    /// offset. The saved file is resumed at the offset while it's the same
+    /// file and long enough. If it was rotated, the rest of it is read under
+    /// its new name in the same directory, then the file now at the path
+    /// from the start; a truncated or lost file is read from the start.
+    pub fn resume_files(&self) -> Vec<(PathBuf, u64)> {
+        let current = std::fs::metadata(&self.path).ok();
+        let same = |metadata: &std::fs::Metadata| self.inode.is_none_or(|inode| metadata.ino() == inode);
+        match &current {
+            Some(metadata) if same(metadata) && metadata.len() >= self.offset => return vec![(self.path.clone(), self.offset)],
+            Some(metadata) if same(metadata) => {
+                warn!(
+                    "{} was truncated to {} bytes since byte {} was saved; reading it from the start",
+                    self.path.display(),
+                    metadata.len(),
+                    self.offset
+                );
+                return vec![(self.path.clone(), 0)];
+            }
+            _ => {}
+        }
+
+        let rotated = self.inode.and_then(|inode| self.find_rotated(inode));
+        let mut files = Vec::with_capacity(2);
+        match rotated {
+            Some(rotated) => {
+                info!("{} was rotated to {}; resuming there at byte {}", self.path.display(), rotated.display(), self.offset);
+                files.push((rotated, self.offset));
+            }
+            None if current.is_some() => {
+                warn!("{} was replaced and the saved file is gone; reading the new one from the start", self.path.display())
+            }
+            // Not there at all, as for an hour the node wasn't running
+            None => return vec![(self.path.clone(), self.offset)],
+        }
+        if current.is_some() {
+            files.push((self.path.clone(), 0));
+        }
+        files
+    }
+
+    /// The file in the saved file's directory with `inode`
+    fn find_rotated(&self, inode: u64) -> Option<PathBuf> {
+        let dir = self.path.parent()?;
+        std::fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            (metadata.is_file() && metadata.ino() == inode).then(|| entry.path())
+        })
+    }
+}
+
+/// Copying snapshots off the host
+struct Replication {
+    /// Shell command; `{dir}` is replaced by the staged snapshot
+    command: String,
+    interval: Duration,
+    last: Mutex<Option<Instant>>,
+    running: Arc<AtomicBool>,
+}
+
+/// Where and how often the books are snapshotted
+pub struct StateSnapshots {
+    dir: PathBuf,
+    pub interval: Duration,
+    orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+    replication: Option<Replication>,
+}
+
+impl StateSnapshots {
+    pub fn new(dir: PathBuf, interval: Duration, orderbooks: HashMap<u32, Arc<FastOrderbook>>) -> Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, interval, orderbooks, replication: None })
+    }
+
+    /// Run `command` on a copy of the latest snapshot at most every
+    /// `interval`, skipping while the previous run is still going
+    pub fn with_replication(mut self, command: String, interval: Duration) -> Self {
+        self.replication = Some(Replication {
+            command,
+            interval,
+            last: Mutex::new(None),
+            running: Arc::new(AtomicBool::new(false)),
+        });
+        self
+    }
+
+    /// Where snapshots are staged for replication, beside the state dir
+    fn staging_dir(&self) -> PathBuf {
+        let mut name = self.dir.file_name().unwrap_or_default().to_os_string();
+        name.push(".replica");
+        self.dir.with_file_name(name)
+    }
+
+    /// Write every book and its mark price EMAs, then the position they
+    /// reflect. Books must not change while this runs.
+    pub fn save(&self, path: &Path, offset: u64) -> Result<()> {
+        let mut emas = HashMap::with_capacity(self.orderbooks.len());
+        for (market_id, orderbook) in &self.orderbooks {
+            let encoded = orderbook.event_log().lock().encode()?;
+            event_log::write_atomic(event_log::log_path(&self.dir, *market_id), &encoded)?;
+            emas.insert(*market_id, orderbook.mark_ema_state());
+        }
+        event_log::write_atomic(self.dir.join(EMA_FILE), &serde_json::to_vec(&emas)?)?;
+        let position = ReadPosition {
+            path: path.to_path_buf(),
+            offset,
+            saved_ms: chrono::Utc::now().timestamp_millis() as u64,
+            inode: std::fs::metadata(path).ok().map(|metadata| metadata.ino()),
+        };
+        event_log::write_atomic(self.dir.join(POSITION_FILE), &serde_json::to_vec_pretty(&position)?)?;
+        self.replicate()
+    }
+
+    /// Stage the snapshot just saved and start the replication command on
+    /// it, if one is due
+    fn replicate(&self) -> Result<()> {
+        let Some(replication) = &self.replication else { return Ok(()) };
+        let mut last = replication.last.lock();
+        if last.is_some_and(|last| last.elapsed() < replication.interval) || replication.running.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        *last = Some(Instant::now());
+
+        let staging = self.staging_dir();
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        std::fs::create_dir_all(&staging)?;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                std::fs::copy(entry.path(), staging.join(entry.file_name()))?;
+            }
+        }
+
+        let command = replication.command.replace("{dir}", &staging.to_string_lossy());
+        let running = replication.running.clone();
+        running.store(true, Ordering::Release);
+        tokio::spawn(async move {
+            let started = Instant::now();
+            match tokio::process::Command::new("sh").arg("-c").arg(&command).status().await {
+                Ok(status) if status.success() => info!("Replicated state snapshot in {:?}", started.elapsed()),
+                Ok(status) => warn!("State replication command failed: {}", status),
+                Err(e) => warn!("Failed to run state replication command: {}", e),
+            }
+            running.store(false, Ordering::Release);
+        });
+        Ok(())
+    }
+
+    /// Fetch a replicated snapshot into the state dir with `command`, `{dir}`
+    /// replaced by the dir. Only when there is no local snapshot, so a host
+    /// that merely restarted keeps its own newer one.
+    pub fn bootstrap(&self, command: &str) -> Result<()> {
+        if self.dir.join(POSITION_FILE).exists() {
+            return Ok(());
+        }
+        let command = command.replace("{dir}", &self.dir.to_string_lossy());
+        info!("No local state snapshot, bootstrapping: {}", command);
+        let status = std::process::Command::new("sh").arg("-c").arg(&command).status()?;
+        if !status.success() {
+            bail!("State bootstrap command failed: {}", status);
+        }
+        Ok(())
+    }
+
+    /// Restore the books of the last snapshot and return where it had read
+    /// to. Only before the books' writers start; without a snapshot, nothing
+    /// changes.
+    pub fn restore(&self) -> Result<Option<ReadPosition>> {
+        let path = self.dir.join(POSITION_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let position: ReadPosition = serde_json::from_slice(&std::fs::read(&path)?)?;
+
+        // Load every log before touching a book, so a bad snapshot leaves
+        // them all empty rather than some restored
+        let mut logs = Vec::with_capacity(self.orderbooks.len());
+        for (market_id, orderbook) in &self.orderbooks {
+            let path = event_log::log_path(&self.dir, *market_id);
+            if !path.exists() {
+                continue;
+            }
+            match EventLog::load(&path) {
+                Ok(log) => logs.push((orderbook, log)),
+                Err(e) => {
+                    warn!("Ignoring state snapshot in {}: {}: {}", self.dir.display(), path.display(), e);
+                    return Ok(None);
+                }
+            }
+        }
+
+        let restored = logs.len();
+        for (orderbook, log) in logs {
+            orderbook.restore(log);
+        }
+        // Snapshots from before EMAs were saved restore the books alone
+        let ema_path = self.dir.join(EMA_FILE);
+        if ema_path.exists() {
+            let emas: HashMap<u32, MarkEmaState> = serde_json::from_slice(&std::fs::read(&ema_path)?)?;
+            for (market_id, state) in emas {
+                if let Some(orderbook) = self.orderbooks.get(&market_id) {
+                    orderbook.restore_mark_ema(state);
+                }
+            }
+        }
+        info!(
+            "Restored {} books from {}, resuming {} at byte {}",
+            restored,
+            self.dir.display(),
+            position.path.display(),
+            position.offset
+        );
+        Ok(Some(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::Order;
+    use crate::mark_price_v2::EmaState;
+
+    #[test]
+    fn test_save_and_restore() {
+        let dir = std::env::temp_dir().join(format!("state-snapshot-test-{}", std::process::id()));
+        let books = |symbols: &[&str]| -> HashMap<u32, Arc<FastOrderbook>> {
+            symbols.iter().enumerate().map(|(id, symbol)| (id as u32, Arc::new(FastOrderbook::new(id as u32, symbol.to_string())))).collect()
+        };
+
+        let live = books(&["BTC", "ETH"]);
+        let snapshots = StateSnapshots::new(dir.clone(), Duration::from_secs(10), live.clone()).unwrap();
+        assert_eq!(snapshots.restore().unwrap(), None);
+
+        live[&0].add_order(Order { id: 1, price: 100.0, size: 2.0, timestamp: 0 }, true);
+        live[&1].add_order(Order { id: 2, price: 10.0, size: 1.0, timestamp: 0 }, false);
+        let ema = MarkEmaState { oracle_basis: EmaState { numerator: 3.0, denominator: 2.0 }, ..Default::default() };
+        live[&0].restore_mark_ema(ema);
+        snapshots.save(Path::new("/data/20250301/7"), 4096).unwrap();
+
+        let restarted = books(&["BTC", "ETH"]);
+        let position = StateSnapshots::new(dir.clone(), Duration::from_secs(10), restarted.clone()).unwrap().restore().unwrap().unwrap();
+        assert_eq!((position.path.as_path(), position.offset), (Path::new("/data/20250301/7"), 4096));
+        assert_eq!(restarted[&0].snapshot().bids, vec![(100.0, 2.0)]);
+        assert_eq!(restarted[&1].snapshot().asks, vec![(10.0, 1.0)]);
+        assert_eq!(restarted[&1].sequence.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(restarted[&0].mark_ema_state(), ema);
+        assert_eq!(restarted[&1].mark_ema_state(), MarkEmaState::default());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resume_detects_rotation_and_truncation() {
+        let dir = std::env::temp_dir().join(format!("state-rotation-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("7");
+        std::fs::write(&path, "a\nb\nc\n").unwrap();
+        let position = |offset| ReadPosition {
+            path: path.clone(),
+            offset,
+            saved_ms: 0,
+            inode: Some(std::fs::metadata(&path).unwrap().ino()),
+        };
+        let saved = position(4);
+        assert_eq!(saved.resume_files(), vec![(path.clone(), 4)]);
+        // Positions saved without an inode resume as before
+        assert_eq!(ReadPosition { inode: None, ..saved.clone() }.resume_files(), vec![(path.clone(), 4)]);
+        assert_eq!(position(100).resume_files(), vec![(path.clone(), 0)]);
+
+        let rotated = dir.join("7.1");
+        std::fs::rename(&path, &rotated).unwrap();
+        assert_eq!(saved.resume_files(), vec![(rotated.clone(), 4)]);
+        std::fs::write(&path, "d\n").unwrap();
+        assert_eq!(saved.resume_files(), vec![(rotated.clone(), 4), (path.clone(), 0)]);
+        std::fs::remove_file(&rotated).unwrap();
+        assert_eq!(saved.resume_files(), vec![(path.clone(), 0)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replicate_and_bootstrap() {
+        let root = std::env::temp_dir().join(format!("state-replication-test-{}", std::process::id()));
+        let remote = root.join("remote");
+        let books = || -> HashMap<u32, Arc<FastOrderbook>> { [(0, Arc::new(FastOrderbook::new(0, "BTC".to_string())))].into_iter().collect() };
+
+        let live = books();
+        let snapshots = StateSnapshots::new(root.join("lost"), Duration::from_secs(10), live.clone())
+            .unwrap()
+            .with_replication(format!("cp -r {{dir}} {}", remote.display()), Duration::from_secs(60));
+        live[&0].add_order(Order { id: 1, price: 100.0, size: 2.0, timestamp: 0 }, true);
+        snapshots.save(Path::new("/data/20250301/7"), 4096).unwrap();
+        // Not due again yet
+        snapshots.save(Path::new("/data/20250301/7"), 8192).unwrap();
+        while snapshots.replication.as_ref().unwrap().running.load(Ordering::Acquire) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let replacement = books();
+        let snapshots = StateSnapshots::new(root.join("replacement"), Duration::from_secs(10), replacement.clone()).unwrap();
+        snapshots.bootstrap(&format!("cp {}/* {{dir}}", remote.display())).unwrap();
+        let position = snapshots.restore().unwrap().unwrap();
+        assert_eq!(position.offset, 4096);
+        assert_eq!(replacement[&0].snapshot().bids, vec![(100.0, 2.0)]);
+        // A local snapshot wins over the replica
+        snapshots.bootstrap("exit 1").unwrap();
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,6 +1,11 @@
-use std::collections::HashMap;
-use std::sync::RwLock;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use parking_lot::RwLock;
 use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+
+use crate::fast_orderbook::{walk_levels, FillTarget};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StopOrder {
@@ -14,8 +19,27 @@ pub struct StopOrder {
     pub timestamp: u64,
 }
 
+impl StopOrder {
+    /// The price in the node's "Price above X" or "Price below X" trigger
+    /// condition, and whether the price must rise to reach it
+    pub fn trigger(&self) -> Option<(f64, bool)> {
+        let parsed = |prefix: &str| self.trigger_condition.strip_prefix(prefix).and_then(|price| price.trim().parse::<f64>().ok());
+        match (parsed("Price above"), parsed("Price below")) {
+            (Some(price), _) => Some((price, true)),
+            (_, Some(price)) => Some((price, false)),
+            _ => None,
+        }
+    }
+
+    /// The trigger price, or the order price where the condition has none
+    pub fn trigger_price(&self) -> f64 {
+        self.trigger().map_or(self.price, |(price, _)| price)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RankedStopOrder {
+    pub market_id: u32,
     pub order: StopOrder,
     pub distance_to_trigger_bps: f64,
     pub expected_slippage_bps: f64,
@@ -23,90 +47,287 @@ pub struct RankedStopOrder {
     pub notional_value: f64,
 }
 
+/// How a stop order left the live set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOrderEnd {
+    Canceled,
+    /// Its trigger price was hit and it became a regular order
+    Triggered,
+    Filled,
+}
+
+/// A change to the live set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopOrderEventKind {
+    Added,
+    /// A live order was replaced by a new version of itself
+    Modified,
+    Canceled,
+    Triggered,
+    Filled,
+}
+
+impl StopOrderEventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StopOrderEventKind::Added => "add",
+            StopOrderEventKind::Modified => "modify",
+            StopOrderEventKind::Canceled => "cancel",
+            StopOrderEventKind::Triggered => "trigger",
+            StopOrderEventKind::Filled => "fill",
+        }
+    }
+}
+
+impl From<StopOrderEnd> for StopOrderEventKind {
+    fn from(end: StopOrderEnd) -> Self {
+        match end {
+            StopOrderEnd::Canceled => StopOrderEventKind::Canceled,
+            StopOrderEnd::Triggered => StopOrderEventKind::Triggered,
+            StopOrderEnd::Filled => StopOrderEventKind::Filled,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StopOrderEvent {
+    pub kind: StopOrderEventKind,
+    pub market_id: u32,
+    /// The order as added, or as it was when it left the live set
+    pub order: StopOrder,
+    /// Exchange time of the status behind the event, ms
+    pub timestamp_ms: u64,
+}
+
+/// Notional of the stop orders whose trigger prices lie in one band of
+/// distance from the mid
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeatmapBucket {
+    /// Covers `index * bucket_bps` up to `(index + 1) * bucket_bps` from
+    /// the mid; negative below it
+    pub index: i64,
+    pub buy_notional: f64,
+    pub sell_notional: f64,
+    pub buy_orders: u32,
+    pub sell_orders: u32,
+}
+
+/// Events buffered per subscriber before it lags
+const EVENT_CAPACITY: usize = 4096;
+
+/// Live stop orders and how many have come and gone since startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StopOrderStats {
+    pub live: u64,
+    pub added: u64,
+    pub canceled: u64,
+    pub triggered: u64,
+    pub filled: u64,
+}
+
+/// A trigger price with a total order, to key the price index
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TriggerKey(f64);
+
+impl Eq for TriggerKey {}
+
+impl PartialOrd for TriggerKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TriggerKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Default)]
+struct StopOrderIndex {
+    // Every live stop order with its market
+    orders: HashMap<u64, (u32, StopOrder)>,
+    // Market ID -> (trigger price, order ID)
+    by_market: HashMap<u32, BTreeSet<(TriggerKey, u64)>>,
+    // User -> order IDs
+    by_user: HashMap<String, HashSet<u64>>,
+}
+
+impl StopOrderIndex {
+    fn link(&mut self, market_id: u32, order: &StopOrder) {
+        self.by_market.entry(market_id).or_default().insert((TriggerKey(order.trigger_price()), order.id));
+        self.by_user.entry(order.user.clone()).or_default().insert(order.id);
+    }
+
+    fn unlink(&mut self, market_id: u32, order: &StopOrder) {
+        if let Some(market_orders) = self.by_market.get_mut(&market_id) {
+            market_orders.remove(&(TriggerKey(order.trigger_price()), order.id));
+            if market_orders.is_empty() {
+                self.by_market.remove(&market_id);
+            }
+        }
+        if let Some(user_orders) = self.by_user.get_mut(&order.user) {
+            user_orders.remove(&order.id);
+            if user_orders.is_empty() {
+                self.by_user.remove(&order.user);
+            }
+        }
+    }
+
+    /// A market's orders with trigger prices in `low..=high`, ascending
+    fn in_range(&self, market_id: u32, low: f64, high: f64) -> impl Iterator<Item = &StopOrder> {
+        let range = (low <= high).then_some((TriggerKey(low), u64::MIN)..=(TriggerKey(high), u64::MAX));
+        self.by_market
+            .get(&market_id)
+            .zip(range)
+            .into_iter()
+            .flat_map(|(market_orders, range)| market_orders.range(range))
+            .map(|(_, id)| &self.orders[id].1)
+    }
+}
+
 pub struct StopOrderManager {
-    // Market ID -> User -> Vec<StopOrder>
-    orders_by_market: RwLock<HashMap<u32, HashMap<String, Vec<StopOrder>>>>,
-    // Global list of all stop orders
-    all_orders: RwLock<HashMap<u64, StopOrder>>,
+    index: RwLock<StopOrderIndex>,
+    added: AtomicU64,
+    canceled: AtomicU64,
+    triggered: AtomicU64,
+    filled: AtomicU64,
+    events: broadcast::Sender<Arc<StopOrderEvent>>,
 }
 
 impl StopOrderManager {
     pub fn new() -> Self {
         Self {
-            orders_by_market: RwLock::new(HashMap::new()),
-            all_orders: RwLock::new(HashMap::new()),
+            index: RwLock::new(StopOrderIndex::default()),
+            added: AtomicU64::new(0),
+            canceled: AtomicU64::new(0),
+            triggered: AtomicU64::new(0),
+            filled: AtomicU64::new(0),
+            events: broadcast::channel(EVENT_CAPACITY).0,
         }
     }
 
+    /// Every add, modification and end from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<StopOrderEvent>> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, kind: StopOrderEventKind, market_id: u32, order: StopOrder, timestamp_ms: u64) {
+        let _ = self.events.send(Arc::new(StopOrderEvent { kind, market_id, order, timestamp_ms }));
+    }
+
+    /// Track a stop order. An id that is already live is replaced, as when
+    /// the order is modified.
     pub fn add_stop_order(&self, market_id: u32, order: StopOrder) {
-        let mut orders_by_market = self.orders_by_market.write().unwrap();
-        let mut all_orders = self.all_orders.write().unwrap();
-        
-        // Add to global list
-        all_orders.insert(order.id, order.clone());
-        
-        // Add to market/user map
-        let market_orders = orders_by_market.entry(market_id).or_insert_with(HashMap::new);
-        let user_orders = market_orders.entry(order.user.clone()).or_insert_with(Vec::new);
-        user_orders.push(order);
-    }
-
-    pub fn remove_stop_order(&self, order_id: u64) {
-        let mut all_orders = self.all_orders.write().unwrap();
-        
-        if let Some(order) = all_orders.remove(&order_id) {
-            let mut orders_by_market = self.orders_by_market.write().unwrap();
-            
-            // Find and remove from market/user map
-            for (_, market_orders) in orders_by_market.iter_mut() {
-                if let Some(user_orders) = market_orders.get_mut(&order.user) {
-                    user_orders.retain(|o| o.id != order_id);
-                    if user_orders.is_empty() {
-                        market_orders.remove(&order.user);
-                    }
-                    break;
-                }
-            }
+        let timestamp_ms = order.timestamp;
+        let kind = {
+            let mut index = self.index.write();
+            let kind = if let Some((previous_market, previous)) = index.orders.insert(order.id, (market_id, order.clone())) {
+                index.unlink(previous_market, &previous);
+                StopOrderEventKind::Modified
+            } else {
+                self.added.fetch_add(1, Ordering::Relaxed);
+                StopOrderEventKind::Added
+            };
+            index.link(market_id, &order);
+            kind
+        };
+        self.publish(kind, market_id, order, timestamp_ms);
+    }
+
+    /// Stop tracking an order; the order and its market, if it was live
+    pub fn remove_stop_order(&self, order_id: u64) -> Option<(u32, StopOrder)> {
+        let mut index = self.index.write();
+        let (market_id, order) = index.orders.remove(&order_id)?;
+        index.unlink(market_id, &order);
+        Some((market_id, order))
+    }
+
+    /// Remove a stop order that was canceled, triggered or filled at
+    /// `timestamp_ms`, counting how it ended. Statuses for orders that
+    /// aren't live are ignored.
+    pub fn end_stop_order(&self, order_id: u64, end: StopOrderEnd, timestamp_ms: u64) -> Option<StopOrder> {
+        let (market_id, order) = self.remove_stop_order(order_id)?;
+        let counter = match end {
+            StopOrderEnd::Canceled => &self.canceled,
+            StopOrderEnd::Triggered => &self.triggered,
+            StopOrderEnd::Filled => &self.filled,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.publish(end.into(), market_id, order.clone(), timestamp_ms);
+        Some(order)
+    }
+
+    pub fn stats(&self) -> StopOrderStats {
+        StopOrderStats {
+            live: self.get_stop_order_count() as u64,
+            added: self.added.load(Ordering::Relaxed),
+            canceled: self.canceled.load(Ordering::Relaxed),
+            triggered: self.triggered.load(Ordering::Relaxed),
+            filled: self.filled.load(Ordering::Relaxed),
         }
     }
 
+    /// Live stop orders per market
+    pub fn counts_by_market(&self) -> HashMap<u32, usize> {
+        let index = self.index.read();
+        index.by_market.iter().map(|(market_id, orders)| (*market_id, orders.len())).collect()
+    }
+
+    /// A market's stop orders, ascending by trigger price
     pub fn get_stop_orders_by_market(&self, market_id: u32) -> Vec<StopOrder> {
-        let orders_by_market = self.orders_by_market.read().unwrap();
-        
-        if let Some(market_orders) = orders_by_market.get(&market_id) {
-            market_orders
-                .values()
-                .flat_map(|user_orders| user_orders.iter().cloned())
-                .collect()
-        } else {
-            Vec::new()
-        }
+        self.get_stop_orders_in_range(market_id, f64::NEG_INFINITY, f64::INFINITY)
+    }
+
+    /// A market's stop orders with trigger prices in `low..=high`, ascending
+    pub fn get_stop_orders_in_range(&self, market_id: u32, low: f64, high: f64) -> Vec<StopOrder> {
+        self.index.read().in_range(market_id, low, high).cloned().collect()
+    }
+
+    /// A user's stop orders with their markets
+    pub fn get_stop_orders_by_user(&self, user: &str) -> Vec<(u32, StopOrder)> {
+        let index = self.index.read();
+        index
+            .by_user
+            .get(user)
+            .into_iter()
+            .flatten()
+            .map(|id| index.orders[id].clone())
+            .collect()
     }
 
-    pub fn get_stop_orders_by_user(&self, user: &str) -> Vec<StopOrder> {
-        let orders_by_market = self.orders_by_market.read().unwrap();
-        let mut result = Vec::new();
-        
-        for market_orders in orders_by_market.values() {
-            if let Some(user_orders) = market_orders.get(user) {
-                result.extend(user_orders.iter().cloned());
+    /// A market's live stop orders bucketed by the signed distance of their
+    /// trigger prices from `mid`, ascending by price. Orders past
+    /// `max_distance_bps` are left out unless it is 0.
+    pub fn heatmap(&self, market_id: u32, mid: f64, bucket_bps: f64, max_distance_bps: f64) -> Vec<HeatmapBucket> {
+        let (low, high) = if max_distance_bps > 0.0 {
+            (mid * (1.0 - max_distance_bps / 10000.0), mid * (1.0 + max_distance_bps / 10000.0))
+        } else {
+            (f64::NEG_INFINITY, f64::INFINITY)
+        };
+        let mut buckets: BTreeMap<i64, HeatmapBucket> = BTreeMap::new();
+        for order in self.index.read().in_range(market_id, low, high) {
+            let distance_bps = (order.trigger_price() - mid) / mid * 10000.0;
+            let index = (distance_bps / bucket_bps).floor() as i64;
+            let bucket = buckets.entry(index).or_insert(HeatmapBucket { index, ..Default::default() });
+            if order.side == "B" {
+                bucket.buy_notional += order.price * order.size;
+                bucket.buy_orders += 1;
+            } else {
+                bucket.sell_notional += order.price * order.size;
+                bucket.sell_orders += 1;
             }
         }
-        
-        result
+        buckets.into_values().collect()
     }
 
-    pub fn get_all_stop_orders(&self) -> Vec<StopOrder> {
-        let all_orders = self.all_orders.read().unwrap();
-        all_orders.values().cloned().collect()
+    /// Every live stop order with its market
+    pub fn live_orders(&self) -> Vec<(u32, StopOrder)> {
+        self.index.read().orders.values().cloned().collect()
     }
 
     pub fn get_stop_order_count(&self) -> usize {
-        self.all_orders.read().unwrap().len()
-    }
-    
-    pub fn get_market_id_for_coin(&self, coin: &str) -> Option<u32> {
-        crate::markets::get_market_id(coin)
+        self.index.read().orders.len()
     }
 
     pub fn calculate_slippage(
@@ -115,32 +336,16 @@ impl StopOrderManager {
         orderbook_levels: &[(f64, f64)], // (price, size) pairs
         is_buy: bool,
     ) -> f64 {
-        let mut remaining_size = order.size;
-        let mut total_cost = 0.0;
-        let mut filled_size = 0.0;
-
-        for (price, size) in orderbook_levels {
-            if remaining_size <= 0.0 {
-                break;
-            }
-
-            let fill_size = remaining_size.min(*size);
-            total_cost += fill_size * price;
-            filled_size += fill_size;
-            remaining_size -= fill_size;
-        }
-
-        if filled_size > 0.0 {
-            let avg_fill_price = total_cost / filled_size;
-            ((avg_fill_price - order.price).abs() / order.price) * 10000.0 // Return slippage in bps
-        } else {
-            1000.0 // Return 10% slippage if can't fill
+        match walk_levels(orderbook_levels, FillTarget::Size(order.size)).avg_price() {
+            Some(avg_fill_price) => ((avg_fill_price - order.price).abs() / order.price) * 10000.0, // Return slippage in bps
+            None => 1000.0, // Return 10% slippage if can't fill
         }
     }
 
+    /// Rank orders, each with the market it was indexed under
     pub fn rank_stop_orders(
         &self,
-        orders: Vec<StopOrder>,
+        orders: Vec<(u32, StopOrder)>,
         mid_prices: &HashMap<u32, f64>,
         orderbooks: &HashMap<u32, (Vec<(f64, f64)>, Vec<(f64, f64)>)>, // market_id -> (bids, asks)
         distance_weight: f64,
@@ -148,52 +353,157 @@ impl StopOrderManager {
     ) -> Vec<RankedStopOrder> {
         let mut ranked_orders = Vec::new();
 
-        for order in orders {
-            if let Some(market_id) = self.get_market_id_for_coin(&order.coin) {
-                if let (Some(mid_price), Some(book)) = (mid_prices.get(&market_id), orderbooks.get(&market_id)) {
-                    let is_buy = order.side == "B";
-                    let is_stop_loss = (is_buy && order.price > *mid_price) || (!is_buy && order.price < *mid_price);
-                    
-                    // Calculate distance to trigger
-                    let distance_to_trigger_bps = if is_stop_loss {
-                        if is_buy {
-                            ((order.price - mid_price) / mid_price) * 10000.0
-                        } else {
-                            ((mid_price - order.price) / mid_price) * 10000.0
-                        }
+        for (market_id, order) in orders {
+            if let (Some(mid_price), Some(book)) = (mid_prices.get(&market_id), orderbooks.get(&market_id)) {
+                let is_buy = order.side == "B";
+                let is_stop_loss = (is_buy && order.price > *mid_price) || (!is_buy && order.price < *mid_price);
+                
+                // Calculate distance to trigger
+                let distance_to_trigger_bps = if is_stop_loss {
+                    if is_buy {
+                        ((order.price - mid_price) / mid_price) * 10000.0
+                    } else {
+                        ((mid_price - order.price) / mid_price) * 10000.0
+                    }
+                } else {
+                    // Take profit orders
+                    if is_buy {
+                        ((mid_price - order.price) / mid_price) * 10000.0
                     } else {
-                        // Take profit orders
-                        if is_buy {
-                            ((mid_price - order.price) / mid_price) * 10000.0
-                        } else {
-                            ((order.price - mid_price) / mid_price) * 10000.0
-                        }
-                    };
-
-                    // Calculate expected slippage
-                    let orderbook_levels = if is_buy { &book.1 } else { &book.0 }; // Buy from asks, sell to bids
-                    let expected_slippage_bps = self.calculate_slippage(&order, orderbook_levels, is_buy);
-
-                    // Calculate risk score (0-100, higher = higher risk)
-                    let distance_score = (100.0 - distance_to_trigger_bps.min(100.0)).max(0.0);
-                    let slippage_score = expected_slippage_bps.min(100.0);
-                    let risk_score = distance_weight * distance_score + slippage_weight * slippage_score;
-
-                    let notional_value = order.price * order.size;
-
-                    ranked_orders.push(RankedStopOrder {
-                        order,
-                        distance_to_trigger_bps,
-                        expected_slippage_bps,
-                        risk_score,
-                        notional_value,
-                    });
-                }
+                        ((order.price - mid_price) / mid_price) * 10000.0
+                    }
+                };
+
+                // Calculate expected slippage
+                let orderbook_levels = if is_buy { &book.1 } else { &book.0 }; // Buy from asks, sell to bids
+                let expected_slippage_bps = self.calculate_slippage(&order, orderbook_levels, is_buy);
+
+                // Calculate risk score (0-100, higher = higher risk)
+                let distance_score = (100.0 - distance_to_trigger_bps.min(100.0)).max(0.0);
+                let slippage_score = expected_slippage_bps.min(100.0);
+                let risk_score = distance_weight * distance_score + slippage_weight * slippage_score;
+
+                let notional_value = order.price * order.size;
+
+                ranked_orders.push(RankedStopOrder {
+                    market_id,
+                    order,
+                    distance_to_trigger_bps,
+                    expected_slippage_bps,
+                    risk_score,
+                    notional_value,
+                });
             }
         }
 
         // Sort by risk score (descending - highest risk first)
-        ranked_orders.sort_by(|a, b| b.risk_score.partial_cmp(&a.risk_score).unwrap());
+        ranked_orders.sort_by(|a, b| b.risk_score.total_cmp(&a.risk_score));
         ranked_orders
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(id: u64, user: &str, price: f64) -> StopOrder {
+        StopOrder {
+            id,
+            user: user.to_string(),
+            coin: "BTC".to_string(),
+            side: "A".to_string(),
+            price,
+            size: 1.0,
+            trigger_condition: format!("Price below {}", price),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_lifecycle_keeps_live_set_accurate() {
+        let manager = StopOrderManager::new();
+        manager.add_stop_order(0, stop(1, "a", 90.0));
+        manager.add_stop_order(0, stop(2, "a", 80.0));
+        manager.add_stop_order(1, stop(3, "a", 70.0));
+        // A modification replaces the live order
+        manager.add_stop_order(0, stop(2, "a", 85.0));
+        assert_eq!(manager.get_stop_orders_by_market(0).len(), 2);
+        let (market_id, order) = manager.get_stop_orders_by_user("a").into_iter().find(|(_, o)| o.id == 2).unwrap();
+        assert_eq!((market_id, order.price), (0, 85.0));
+
+        assert!(manager.end_stop_order(1, StopOrderEnd::Triggered, 5).is_some());
+        assert!(manager.end_stop_order(3, StopOrderEnd::Canceled, 5).is_some());
+        // Already gone
+        assert!(manager.end_stop_order(3, StopOrderEnd::Filled, 5).is_none());
+
+        assert_eq!(manager.stats(), StopOrderStats { live: 1, added: 3, canceled: 1, triggered: 1, filled: 0 });
+        assert_eq!(manager.counts_by_market(), HashMap::from([(0, 1)]));
+        assert_eq!(manager.get_stop_orders_by_user("a").len(), 1);
+        assert!(manager.get_stop_orders_by_market(1).is_empty());
+    }
+
+    #[test]
+    fn test_heatmap_buckets_by_distance_from_mid() {
+        let manager = StopOrderManager::new();
+        // 5 and 8 bps below the mid share a bucket; 25 bps above is two buckets up
+        manager.add_stop_order(0, stop(1, "a", 99.95));
+        manager.add_stop_order(0, stop(2, "b", 99.92));
+        manager.add_stop_order(0, StopOrder { side: "B".to_string(), size: 2.0, ..stop(3, "a", 100.25) });
+        manager.add_stop_order(0, stop(4, "a", 90.0));
+        manager.add_stop_order(1, stop(5, "a", 99.95));
+
+        let buckets = manager.heatmap(0, 100.0, 10.0, 500.0);
+        let summary: Vec<_> = buckets.iter().map(|b| (b.index, b.buy_orders, b.sell_orders)).collect();
+        assert_eq!(summary, vec![(-1, 0, 2), (2, 1, 0)]);
+        assert!((buckets[0].sell_notional - (99.95 + 99.92)).abs() < 1e-9);
+        assert!((buckets[1].buy_notional - 200.5).abs() < 1e-9);
+
+        // Without a limit the far order gets its own bucket
+        assert_eq!(manager.heatmap(0, 100.0, 10.0, 0.0)[0].index, -100);
+    }
+
+    #[test]
+    fn test_price_index_range_scans() {
+        let manager = StopOrderManager::new();
+        for (id, price) in [(1, 95.0), (2, 98.0), (3, 99.0), (4, 101.0)] {
+            manager.add_stop_order(0, stop(id, "a", price));
+        }
+        let buy_stop = StopOrder { side: "B".to_string(), trigger_condition: "Price above 102".to_string(), ..stop(5, "b", 102.5) };
+        manager.add_stop_order(0, buy_stop);
+        // Modified to a new trigger price
+        manager.add_stop_order(0, stop(1, "a", 97.0));
+
+        let ids = |orders: Vec<StopOrder>| orders.iter().map(|o| o.id).collect::<Vec<_>>();
+        assert_eq!(ids(manager.get_stop_orders_by_market(0)), vec![1, 2, 3, 4, 5]);
+        assert_eq!(ids(manager.get_stop_orders_in_range(0, 97.0, 99.0)), vec![1, 2, 3]);
+        assert!(manager.get_stop_orders_in_range(0, 99.0, 97.0).is_empty());
+        assert!(manager.get_stop_orders_in_range(1, 0.0, 200.0).is_empty());
+        // Keyed by the trigger condition's price, not the order price
+        assert_eq!(ids(manager.get_stop_orders_in_range(0, 101.5, 102.0)), vec![5]);
+        let by_user = manager.get_stop_orders_by_user("b");
+        assert_eq!(by_user.iter().map(|(market_id, order)| (*market_id, order.id)).collect::<Vec<_>>(), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn test_changes_are_published() {
+        let manager = StopOrderManager::new();
+        let mut events = manager.subscribe();
+        manager.add_stop_order(0, stop(1, "a", 90.0));
+        manager.add_stop_order(0, stop(1, "a", 95.0));
+        manager.end_stop_order(1, StopOrderEnd::Filled, 7);
+        manager.end_stop_order(1, StopOrderEnd::Canceled, 8);
+
+        let published: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| (event.kind, event.market_id, event.order.price, event.timestamp_ms))
+            .collect();
+        assert_eq!(
+            published,
+            vec![
+                (StopOrderEventKind::Added, 0, 90.0, 0),
+                (StopOrderEventKind::Modified, 0, 95.0, 0),
+                (StopOrderEventKind::Filled, 0, 95.0, 7),
+            ]
+        );
+        assert!(manager.live_orders().is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+//! Recording what one subscriber was sent, for reproducing client bugs.
+//!
+//! An operator attaches a tee to an open stream by subscriber id. Until the
+//! tee's time or size limit, every message the stream yields to the client is
+//! written to a file as the protobuf bytes gRPC frames. Dumping the file
+//! shows exactly what the client received and when, so a desync can be
+//! pinned on the server or the client.
+//!
+//! The file starts with `HPTEE1 <method>\n`. Each frame follows as the
+//! nanoseconds since the tee started (u64 LE), the message length (u32 LE)
+//! and the encoded message.
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use parking_lot::Mutex;
+use prost::Message;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::grpc_server::pb;
+
+const MAGIC: &str = "HPTEE1";
+
+/// Open tee file for one subscriber
+pub struct TeeWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    started: Instant,
+    duration: Duration,
+    max_bytes: u64,
+    bytes: u64,
+    frames: u64,
+}
+
+impl TeeWriter {
+    pub fn create(path: PathBuf, method: &str, duration: Duration, max_bytes: u64) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = BufWriter::new(File::create(&path)?);
+        writeln!(file, "{} {}", MAGIC, method)?;
+        Ok(Self { path, file, started: Instant::now(), duration, max_bytes, bytes: 0, frames: 0 })
+    }
+
+    /// Append a frame; false once the tee is over its limits and should close
+    fn write(&mut self, message: &[u8]) -> std::io::Result<bool> {
+        let offset = self.started.elapsed();
+        if offset >= self.duration || self.bytes + message.len() as u64 > self.max_bytes {
+            return Ok(false);
+        }
+        self.file.write_all(&(offset.as_nanos() as u64).to_le_bytes())?;
+        self.file.write_all(&(message.len() as u32).to_le_bytes())?;
+        self.file.write_all(message)?;
+        self.bytes += message.len() as u64;
+        self.frames += 1;
+        Ok(true)
+    }
+}
+
+/// Where a stream's tee goes while one is attached
+#[derive(Default)]
+pub struct TeeSlot {
+    active: AtomicBool,
+    writer: Mutex<Option<TeeWriter>>,
+}
+
+impl TeeSlot {
+    /// Attach `writer`, unless a tee is already running
+    pub fn start(&self, writer: TeeWriter) -> Result<()> {
+        let mut slot = self.writer.lock();
+        if slot.is_some() {
+            bail!("Already teeing to {}", slot.as_ref().unwrap().path.display());
+        }
+        info!("Teeing subscriber stream to {}", writer.path.display());
+        *slot = Some(writer);
+        self.active.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// Write `message` if a tee is attached, closing it at its limits
+    pub fn record<M: Message>(&self, message: &M) {
+        if !self.is_active() {
+            return;
+        }
+        let mut slot = self.writer.lock();
+        let Some(writer) = slot.as_mut() else { return };
+        match writer.write(&message.encode_to_vec()) {
+            Ok(true) => return,
+            Ok(false) => info!("Tee {} complete: {} frames, {} bytes", writer.path.display(), writer.frames, writer.bytes),
+            Err(e) => warn!("Tee {} failed: {}", writer.path.display(), e),
+        }
+        if let Some(mut writer) = slot.take() {
+            let _ = writer.file.flush();
+        }
+        self.active.store(false, Ordering::Release);
+    }
+}
+
+/// One message as the subscriber was sent it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeeFrame {
+    /// Since the tee started
+    pub offset: Duration,
+    pub message: Vec<u8>,
+}
+
+/// The method a tee file recorded and its frames
+pub fn read(path: &Path) -> Result<(String, Vec<TeeFrame>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let method = header
+        .trim_end()
+        .strip_prefix(MAGIC)
+        .map(|method| method.trim().to_string())
+        .ok_or_else(|| anyhow!("{} is not a tee file", path.display()))?;
+
+    let mut frames = Vec::new();
+    let mut head = [0u8; 12];
+    loop {
+        match reader.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let offset = Duration::from_nanos(u64::from_le_bytes(head[..8].try_into().unwrap()));
+        let mut message = vec![0; u32::from_le_bytes(head[8..].try_into().unwrap()) as usize];
+        reader.read_exact(&mut message)?;
+        frames.push(TeeFrame { offset, message });
+    }
+    Ok((method, frames))
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct DumpArgs {
+    /// Tee file written by StartTee
+    pub file: PathBuf,
+}
+
+fn decode<M: Message + Default + std::fmt::Debug>(bytes: &[u8]) -> Result<String> {
+    Ok(format!("{:?}", M::decode(bytes)?))
+}
+
+/// Print every frame of a tee file, decoded as its method's message
+pub fn run(args: DumpArgs) -> Result<()> {
+    let (method, frames) = read(&args.file)?;
+    let decode: fn(&[u8]) -> Result<String> = match method.as_str() {
+        "SubscribeOrderbook" | "SubscribeByTemplate" | "ManageSubscription" => decode::<pb::OrderbookSnapshot>,
+        "SubscribeDeltas" => decode::<pb::DeltaMessage>,
+        "SubscribeL3" => decode::<pb::L3Message>,
+        "SubscribeBBO" => decode::<pb::Bbo>,
+        "SubscribeTrades" => decode::<pb::Trade>,
+        "SubscribeCandles" => decode::<pb::Candle>,
+        "SubscribeAnalytics" => decode::<pb::AnalyticsUpdate>,
+        "SubscribeBookMetrics" => decode::<pb::BookMetrics>,
+        "SubscribeMarkPrices" => decode::<pb::MarkPriceUpdate>,
+        other => bail!("Unknown stream method in tee file: {}", other),
+    };
+
+    println!("# {}: {} frames", method, frames.len());
+    for frame in frames {
+        println!("{:.3}ms {}B {}", frame.offset.as_secs_f64() * 1000.0, frame.message.len(), decode(&frame.message)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tee_records_until_its_limits() {
+        let path = std::env::temp_dir().join(format!("tee-test-{}", std::process::id()));
+        let bbo = |market_id| pb::Bbo { market_id, ..Default::default() };
+        let slot = TeeSlot::default();
+
+        // Nothing is written without a tee
+        slot.record(&bbo(1));
+        assert!(!slot.is_active());
+
+        let first = bbo(7).encode_to_vec().len() as u64;
+        slot.start(TeeWriter::create(path.clone(), "SubscribeBBO", Duration::from_secs(60), first * 2).unwrap()).unwrap();
+        assert!(slot.start(TeeWriter::create(path.with_extension("2"), "SubscribeBBO", Duration::from_secs(60), 1).unwrap()).is_err());
+        slot.record(&bbo(7));
+        slot.record(&bbo(8));
+        assert!(slot.is_active());
+        // Over the size limit: closed, and this message isn't written
+        slot.record(&bbo(9));
+        assert!(!slot.is_active());
+
+        let (method, frames) = read(&path).unwrap();
+        assert_eq!(method, "SubscribeBBO");
+        let markets: Vec<u32> = frames.iter().map(|f| pb::Bbo::decode(&f.message[..]).unwrap().market_id).collect();
+        assert_eq!(markets, vec![7, 8]);
+        assert!(frames[0].offset <= frames[1].offset);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("2")).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tonic::Status;
+
+use crate::auth_interceptor::QuotaGuard;
+use crate::stream_tee::TeeSlot;
+
+/// One open stream, as operators see it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriberInfo {
+    pub id: u64,
+    /// The RPC, e.g. `SubscribeDeltas`
+    pub method: &'static str,
+    pub peer: Option<SocketAddr>,
+    pub market_ids: Vec<u32>,
+    /// When it subscribed, ms since epoch
+    pub started_ms: u64,
+}
+
+/// Open subscriber streams, overall and per market
+#[derive(Default)]
+pub struct Subscribers {
+    total: AtomicUsize,
+    markets: Mutex<HashMap<u32, usize>>,
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, (SubscriberInfo, Arc<TeeSlot>)>>,
+}
+
+impl Subscribers {
+    /// Count a stream of `method` over `market_ids` until the guard is dropped
+    pub fn register(
+        self: &Arc<Self>,
+        method: &'static str,
+        peer: Option<SocketAddr>,
+        market_ids: impl IntoIterator<Item = u32>,
+    ) -> SubscriberGuard {
+        let market_ids: Vec<u32> = market_ids.into_iter().collect();
+        self.total.fetch_add(1, Ordering::Relaxed);
+        let mut markets = self.markets.lock();
+        for market_id in &market_ids {
+            *markets.entry(*market_id).or_default() += 1;
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let started_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let info = SubscriberInfo { id, method, peer, market_ids: market_ids.clone(), started_ms };
+        let tee = Arc::new(TeeSlot::default());
+        self.open.lock().insert(id, (info, tee.clone()));
+
+        SubscriberGuard {
+            subscribers: self.clone(),
+            id,
+            market_ids,
+            tee,
+            quota: None,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Streams per market; markets nobody watches are absent
+    pub fn per_market(&self) -> HashMap<u32, usize> {
+        self.markets.lock().clone()
+    }
+
+    /// Every open stream, oldest first, and whether it is being teed
+    pub fn list(&self) -> Vec<(SubscriberInfo, bool)> {
+        let mut open: Vec<_> = self.open.lock().values().map(|(info, tee)| (info.clone(), tee.is_active())).collect();
+        open.sort_by_key(|(info, _)| info.id);
+        open
+    }
+
+    /// Where an open stream's tee goes
+    pub fn tee(&self, id: u64) -> Option<(SubscriberInfo, Arc<TeeSlot>)> {
+        self.open.lock().get(&id).cloned()
+    }
+}
+
+/// Keeps a stream counted; move it into the stream's task
+pub struct SubscriberGuard {
+    subscribers: Arc<Subscribers>,
+    id: u64,
+    market_ids: Vec<u32>,
+    tee: Arc<TeeSlot>,
+    quota: Option<QuotaGuard>,
+}
+
+impl SubscriberGuard {
+    /// Keep the stream counted against its API key's quota too
+    pub fn with_quota(mut self, quota: QuotaGuard) -> Self {
+        self.quota = Some(quota);
+        self
+    }
+
+    /// Count the stream under `markets` markets in its key's quota, before
+    /// it changes markets
+    pub fn resize_quota(&mut self, markets: usize) -> Result<(), Status> {
+        match &mut self.quota {
+            Some(quota) => quota.resize(markets),
+            None => Ok(()),
+        }
+    }
+
+    /// Records what the stream sends while an operator tees it
+    pub fn tee(&self) -> Arc<TeeSlot> {
+        self.tee.clone()
+    }
+
+    /// Count the stream under `market_ids` instead, once it changes markets
+    pub fn set_markets(&mut self, market_ids: impl IntoIterator<Item = u32>) {
+        let market_ids: Vec<u32> = market_ids.into_iter().collect();
+        let mut markets = self.subscribers.markets.lock();
+        release(&mut markets, &self.market_ids);
+        for market_id in &market_ids {
+            *markets.entry(*market_id).or_default() += 1;
+        }
+        if let Some((info, _)) = self.subscribers.open.lock().get_mut(&self.id) {
+            info.market_ids = market_ids.clone();
+        }
+        self.market_ids = market_ids;
+    }
+}
+
+fn release(markets: &mut HashMap<u32, usize>, market_ids: &[u32]) {
+    for market_id in market_ids {
+        if let Some(count) = markets.get_mut(market_id) {
+            *count -= 1;
+            if *count == 0 {
+                markets.remove(market_id);
+            }
+        }
+    }
+}
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        self.subscribers.total.fetch_sub(1, Ordering::Relaxed);
+        self.subscribers.open.lock().remove(&self.id);
+        release(&mut self.subscribers.markets.lock(), &self.market_ids);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guards_count_until_dropped() {
+        let subscribers = Arc::new(Subscribers::default());
+        let a = subscribers.register("SubscribeDeltas", None, [0, 1]);
+        let b = subscribers.register("SubscribeBBO", "127.0.0.1:5000".parse().ok(), [1]);
+        let all = subscribers.register("SubscribeTrades", None, []);
+
+        assert_eq!(subscribers.total(), 3);
+        assert_eq!(subscribers.per_market(), [(0, 1), (1, 2)].into_iter().collect());
+        let open = subscribers.list();
+        assert_eq!(open.iter().map(|(info, _)| info.method).collect::<Vec<_>>(), vec!["SubscribeDeltas", "SubscribeBBO", "SubscribeTrades"]);
+        assert_eq!(open[1].0.peer, "127.0.0.1:5000".parse().ok());
+        let b_id = open[1].0.id;
+
+        drop(a);
+        drop(all);
+        assert_eq!(subscribers.total(), 1);
+        assert_eq!(subscribers.per_market(), [(1, 1)].into_iter().collect());
+        assert!(subscribers.tee(b_id).is_some());
+
+        // Managed streams move between markets
+        let mut c = subscribers.register("ManageSubscription", None, []);
+        c.set_markets([1, 2]);
+        assert_eq!(subscribers.per_market(), [(1, 2), (2, 1)].into_iter().collect());
+        c.set_markets([2]);
+        assert_eq!(subscribers.per_market(), [(1, 1), (2, 1)].into_iter().collect());
+        assert_eq!(subscribers.list()[1].0.market_ids, vec![2]);
+        drop(c);
+
+        drop(b);
+        assert!(subscribers.per_market().is_empty());
+        assert!(subscribers.list().is_empty());
+        assert!(subscribers.tee(b_id).is_none());
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A market referenced by id or by coin name
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum MarketRef {
+    Id(u32),
+    Coin(String),
+}
+
+fn default_depth() -> u32 {
+    50
+}
+
+/// Operator-defined stream settings that clients request by name
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionTemplate {
+    pub markets: Vec<MarketRef>,
+    #[serde(default = "default_depth")]
+    pub depth: u32,
+    /// Minimum time between snapshots of one market; 0 = every update
+    #[serde(default)]
+    pub update_interval_ms: u32,
+    /// Levels smaller than this are left out of snapshots
+    #[serde(default)]
+    pub min_quantity: f64,
+    /// Recent history replayed to new subscribers before going live
+    #[serde(default)]
+    pub replay_ms: u32,
+    /// Add integer lots based on the market's size decimals
+    #[serde(default)]
+    pub normalized_sizes: bool,
+    /// Add USD notionals at the mark price
+    #[serde(default)]
+    pub notional: bool,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Named subscription templates loaded from a JSON object of name -> template
+#[derive(Debug, Default)]
+pub struct SubscriptionTemplates {
+    templates: BTreeMap<String, SubscriptionTemplate>,
+}
+
+impl SubscriptionTemplates {
+    pub fn from_json(json: &str) -> Result<Self> {
+        let templates: BTreeMap<String, SubscriptionTemplate> = serde_json::from_str(json)?;
+
+        for (name, template) in &templates {
+            if template.markets.is_empty() {
+                bail!("Subscription template {} has no markets", name);
+            }
+            if template.depth == 0 {
+                bail!("Subscription template {} has zero depth", name);
+            }
+        }
+
+        Ok(Self { templates })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SubscriptionTemplate> {
+        self.templates.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &SubscriptionTemplate)> {
+        self.templates.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_templates() {
+        let templates = SubscriptionTemplates::from_json(
+            r#"{
+                "hft-majors": {"markets": ["BTC", "ETH", 5], "depth": 10},
+                "dashboards": {"markets": ["BTC"], "update_interval_ms": 1000, "min_quantity": 0.5}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(templates.len(), 2);
+
+        let majors = templates.get("hft-majors").unwrap();
+        assert_eq!(
+            majors.markets,
+            vec![MarketRef::Coin("BTC".into()), MarketRef::Coin("ETH".into()), MarketRef::Id(5)]
+        );
+        assert_eq!(majors.depth, 10);
+        assert_eq!(majors.update_interval_ms, 0);
+
+        let dashboards = templates.get("dashboards").unwrap();
+        assert_eq!(dashboards.depth, 50);
+        assert_eq!(dashboards.update_interval_ms, 1000);
+    }
+
+    #[test]
+    fn test_rejects_invalid_templates() {
+        assert!(SubscriptionTemplates::from_json(r#"{"empty": {"markets": []}}"#).is_err());
+        assert!(SubscriptionTemplates::from_json(r#"{"typo": {"markets": ["BTC"], "dpeth": 5}}"#).is_err());
+    }
+}
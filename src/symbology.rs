@@ -2,6 +2,13 @@ use anyhow::{Result, bail};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
+use crate::market_ids::MarketKey;
+use crate::price_ticks::DEFAULT_TICK_SIZE;
+
+/// Decimals a Hyperliquid perp price may have, before subtracting the
+/// market's size decimals
+const MAX_PERP_PRICE_DECIMALS: i32 = 6;
+
 /// Type of financial instrument
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum InstrumentType {
@@ -155,7 +162,8 @@ pub struct ProductInfo {
 /// Complete market information combining all metadata
 #[derive(Debug, Clone)]
 pub struct MarketInfo {
-    pub id: u32,                    // Hyperliquid market ID
+    pub id: u32,                    // Internal market ID
+    pub key: MarketKey,             // How the source identifies the market
     pub symbol: TradableProduct,    // Standardized symbol
     pub execution_info: ExecutionInfo,
     pub product_info: ProductInfo,
@@ -172,12 +180,13 @@ impl MarketInfo {
     ) -> Self {
         let symbol = TradableProduct::from_hyperliquid_coin(&name);
         
-        // Derive tick size from decimals (Hyperliquid specific)
-        let tick_size = 10f64.powi(-(sz_decimals as i32));
-        let step_size = tick_size; // Usually same as tick size
+        // Hyperliquid perp prices have at most 6 - szDecimals decimals
+        let tick_size = 1.0 / 10f64.powi(MAX_PERP_PRICE_DECIMALS - sz_decimals as i32);
+        let step_size = 10f64.powi(-(sz_decimals as i32));
         
         Self {
             id,
+            key: MarketKey::HyperliquidPerp(id),
             symbol: symbol.clone(),
             execution_info: ExecutionInfo {
                 execution_venue: "HYPERLIQUID".to_string(),
@@ -197,6 +206,96 @@ impl MarketInfo {
             },
         }
     }
+    
+    /// A market from a source other than Hyperliquid perps; the symbol's
+    /// exchange is the execution venue
+    pub fn external(id: u32, key: MarketKey, symbol: TradableProduct, sz_decimals: u32) -> Self {
+        let step_size = 10f64.powi(-(sz_decimals as i32));
+        
+        Self {
+            id,
+            key,
+            execution_info: ExecutionInfo {
+                execution_venue: symbol.exchange().to_string(),
+                exchange_symbol: None,
+                // Other sources don't give their price precision
+                tick_size: DEFAULT_TICK_SIZE,
+                step_size,
+                min_order_quantity: step_size,
+                max_leverage: 1,
+                is_delisted: false,
+            },
+            product_info: ProductInfo {
+                product_type: symbol.instrument_type.to_string(),
+                display_name: symbol.simple_symbol(),
+                base_currency: symbol.base().to_string(),
+                quote_currency: symbol.quote().to_string(),
+                sz_decimals,
+            },
+            symbol,
+        }
+    }
+}
+
+/// How closely a symbol matches a search, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SymbolMatch {
+    /// The base, simple or full symbol
+    Exact,
+    /// The start of the base or full symbol
+    Prefix,
+    /// Anywhere in the full symbol
+    Contains,
+    /// The base with letters left out, or with one typo
+    Fuzzy,
+}
+
+impl SymbolMatch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymbolMatch::Exact => "exact",
+            SymbolMatch::Prefix => "prefix",
+            SymbolMatch::Contains => "contains",
+            SymbolMatch::Fuzzy => "fuzzy",
+        }
+    }
+
+    /// How `symbol` matches `query`, ignoring case. An empty query matches
+    /// everything as a prefix.
+    pub fn of(query: &str, symbol: &TradableProduct) -> Option<Self> {
+        let query = query.trim().to_uppercase();
+        let base = symbol.base().to_uppercase();
+        let full = symbol.symbol().to_uppercase();
+
+        if query == base || query == full || query == symbol.simple_symbol().to_uppercase() {
+            Some(SymbolMatch::Exact)
+        } else if base.starts_with(&query) || full.starts_with(&query) {
+            Some(SymbolMatch::Prefix)
+        } else if full.contains(&query) {
+            Some(SymbolMatch::Contains)
+        } else if (query.len() >= 2 && is_subsequence(&query, &base)) || (query.len() >= 3 && within_one_edit(&query, &base)) {
+            Some(SymbolMatch::Fuzzy)
+        } else {
+            None
+        }
+    }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut rest = haystack.chars();
+    needle.chars().all(|c| rest.any(|h| h == c))
+}
+
+/// One substitution, insertion or deletion apart
+fn within_one_edit(a: &str, b: &str) -> bool {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let (short, long) = if a.len() <= b.len() { (&a, &b) } else { (&b, &a) };
+    if long.len() - short.len() > 1 {
+        return false;
+    }
+    let prefix = short.iter().zip(long.iter()).take_while(|(x, y)| x == y).count();
+    let skip = if short.len() == long.len() { prefix + 1 } else { prefix };
+    short.get(skip..).unwrap_or_default() == long.get(prefix + 1..).unwrap_or_default()
 }
 
 /// Symbology service interface (following architect pattern)
@@ -256,8 +355,33 @@ mod tests {
         );
         
         assert_eq!(info.symbol.symbol(), "HYPERLIQUID-BTC/USD-PERP");
-        assert_eq!(info.execution_info.tick_size, 0.1);
+        assert_eq!(info.execution_info.tick_size, 1e-5);
+        assert_eq!(info.execution_info.step_size, 0.1);
         assert_eq!(info.execution_info.max_leverage, 50);
         assert_eq!(info.product_info.product_type, "PERP");
     }
+    
+    #[test]
+    fn test_symbol_match() {
+        let btc = TradableProduct::from_hyperliquid_coin("BTC");
+        let pepe = TradableProduct::from_hyperliquid_coin("kPEPE");
+        let spot = TradableProduct::from_str("BINANCE-BTC/USDT-SPOT").unwrap();
+        
+        assert_eq!(SymbolMatch::of("btc", &btc), Some(SymbolMatch::Exact));
+        assert_eq!(SymbolMatch::of("BTC/USD", &btc), Some(SymbolMatch::Exact));
+        assert_eq!(SymbolMatch::of("kpe", &pepe), Some(SymbolMatch::Prefix));
+        assert_eq!(SymbolMatch::of("binance", &spot), Some(SymbolMatch::Prefix));
+        assert_eq!(SymbolMatch::of("PEPE", &pepe), Some(SymbolMatch::Contains));
+        assert_eq!(SymbolMatch::of("usdt", &spot), Some(SymbolMatch::Contains));
+        
+        // Letters left out, or one typo
+        assert_eq!(SymbolMatch::of("kpp", &pepe), Some(SymbolMatch::Fuzzy));
+        assert_eq!(SymbolMatch::of("BCT", &btc), None);
+        assert_eq!(SymbolMatch::of("BTX", &btc), Some(SymbolMatch::Fuzzy));
+        assert_eq!(SymbolMatch::of("KPEEPE", &pepe), Some(SymbolMatch::Fuzzy));
+        assert_eq!(SymbolMatch::of("ETH", &btc), None);
+        
+        assert_eq!(SymbolMatch::of("", &btc), Some(SymbolMatch::Prefix));
+        assert!(SymbolMatch::Exact < SymbolMatch::Fuzzy);
+    }
 }
\ No newline at end of file
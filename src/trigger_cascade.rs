@@ -0,0 +1,201 @@
+//! Estimates of how far a price move could run once it starts triggering
+//! stop orders.
+//!
+//! A hypothetical move from the mid sweeps the resting liquidity it passes
+//! through. Stops whose trigger prices the move reached fire together as one
+//! round and are filled as market orders against what is left of the book,
+//! which can push the price further and fire the next round. Rounds continue
+//! until one triggers nothing, so the number of rounds is the cascade depth.
+//! Stop-limit prices are ignored, making the estimate a worst case.
+
+use crate::fast_orderbook::{walk_levels, FillTarget, LevelWalk};
+use crate::stop_orders::StopOrder;
+
+/// One wave of stops that fired together
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CascadeRound {
+    pub triggered_orders: usize,
+    pub buy_size: f64,
+    pub sell_size: f64,
+    pub filled_notional: f64,
+    /// Left when a side of the book ran out
+    pub unfilled_size: f64,
+    /// Last fill price after the round
+    pub price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cascade {
+    pub mid_price: f64,
+    pub shock_price: f64,
+    pub rounds: Vec<CascadeRound>,
+    pub final_price: f64,
+    pub low_price: f64,
+    pub high_price: f64,
+    pub triggered_orders: usize,
+    pub triggered_notional: f64,
+    /// Stopped at the round limit with stops still firing
+    pub exhausted: bool,
+}
+
+/// A stop's trigger price and whether the price must rise to reach it.
+/// Without a trigger condition, the order price on whichever side of the
+/// mid it sits.
+fn trigger(order: &StopOrder, mid: f64) -> (f64, bool) {
+    order.trigger().unwrap_or((order.price, order.price > mid))
+}
+
+/// Fill `size` from the front of `levels`, removing what it takes
+fn take(levels: &mut Vec<(f64, f64)>, size: f64) -> LevelWalk {
+    let walk = walk_levels(levels, FillTarget::Size(size));
+    let mut remaining = walk.filled_size;
+    let mut emptied = 0;
+    for level in levels.iter_mut() {
+        if remaining <= 0.0 {
+            break;
+        }
+        let fill = remaining.min(level.1);
+        level.1 -= fill;
+        remaining -= fill;
+        if level.1 <= 1e-12 {
+            emptied += 1;
+        }
+    }
+    levels.drain(..emptied);
+    walk
+}
+
+/// Simulate a move of `move_bps` from the mid through the book and the
+/// given stops; `None` while the book has no mid
+pub fn simulate(
+    orders: &[StopOrder],
+    bids: &[(f64, f64)],
+    asks: &[(f64, f64)],
+    move_bps: f64,
+    max_rounds: usize,
+) -> Option<Cascade> {
+    let mid_price = (bids.first()?.0 + asks.first()?.0) / 2.0;
+    let shock_price = mid_price * (1.0 + move_bps / 10000.0);
+
+    // The move itself takes the liquidity between the mid and its end
+    let mut bids: Vec<(f64, f64)> = bids.iter().copied().filter(|(price, _)| *price <= shock_price).collect();
+    let mut asks: Vec<(f64, f64)> = asks.iter().copied().filter(|(price, _)| *price >= shock_price).collect();
+
+    let mut pending: Vec<(f64, bool, &StopOrder)> = orders
+        .iter()
+        .map(|order| {
+            let (price, above) = trigger(order, mid_price);
+            (price, above, order)
+        })
+        .collect();
+    let mut cascade = Cascade {
+        mid_price,
+        shock_price,
+        rounds: Vec::new(),
+        final_price: shock_price,
+        low_price: mid_price.min(shock_price),
+        high_price: mid_price.max(shock_price),
+        triggered_orders: 0,
+        triggered_notional: 0.0,
+        exhausted: false,
+    };
+
+    loop {
+        let (low, high) = (cascade.low_price, cascade.high_price);
+        let (fired, rest): (Vec<_>, Vec<_>) =
+            pending.into_iter().partition(|(price, above, _)| if *above { high >= *price } else { low <= *price });
+        pending = rest;
+        if fired.is_empty() {
+            break;
+        }
+        if cascade.rounds.len() == max_rounds {
+            cascade.exhausted = true;
+            break;
+        }
+
+        let mut round = CascadeRound { triggered_orders: fired.len(), ..Default::default() };
+        for (_, _, order) in &fired {
+            if order.side == "B" {
+                round.buy_size += order.size;
+            } else {
+                round.sell_size += order.size;
+            }
+            cascade.triggered_notional += order.price * order.size;
+        }
+
+        let sells = take(&mut bids, round.sell_size);
+        let buys = take(&mut asks, round.buy_size);
+        if let Some(worst) = sells.worst_price {
+            cascade.low_price = cascade.low_price.min(worst);
+        }
+        if let Some(worst) = buys.worst_price {
+            cascade.high_price = cascade.high_price.max(worst);
+        }
+        round.filled_notional = sells.filled_notional + buys.filled_notional;
+        round.unfilled_size = (round.sell_size - sells.filled_size) + (round.buy_size - buys.filled_size);
+        // The heavier side sets the price
+        let dominant = if round.sell_size >= round.buy_size { sells } else { buys };
+        round.price = dominant.worst_price.unwrap_or(cascade.final_price);
+        cascade.final_price = round.price;
+        cascade.triggered_orders += round.triggered_orders;
+        cascade.rounds.push(round);
+    }
+    Some(cascade)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(id: u64, side: &str, trigger_price: f64, size: f64, condition: &str) -> StopOrder {
+        StopOrder {
+            id,
+            user: "a".to_string(),
+            coin: "BTC".to_string(),
+            side: side.to_string(),
+            price: trigger_price,
+            size,
+            trigger_condition: format!("Price {} {}", condition, trigger_price),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_stops_cascade_until_none_fire() {
+        let bids: Vec<(f64, f64)> = (1..=10).map(|i| (100.0 - i as f64, 1.0)).collect();
+        let asks: Vec<(f64, f64)> = (1..=10).map(|i| (100.0 + i as f64, 1.0)).collect();
+        let orders = vec![
+            // Reached by the shock to 98: sells 2 into the bids at 98 and 97
+            stop(1, "A", 98.0, 2.0, "below"),
+            // Reached at 97: sells 3 more, down to 94
+            stop(2, "A", 97.0, 3.0, "below"),
+            // Never reached
+            stop(3, "A", 80.0, 1.0, "below"),
+            stop(4, "B", 105.0, 1.0, "above"),
+        ];
+
+        let cascade = simulate(&orders, &bids, &asks, -200.0, 20).unwrap();
+        assert_eq!(cascade.shock_price, 98.0);
+        let rounds: Vec<_> = cascade.rounds.iter().map(|r| (r.triggered_orders, r.sell_size, r.price)).collect();
+        assert_eq!(rounds, vec![(1, 2.0, 97.0), (1, 3.0, 94.0)]);
+        assert_eq!((cascade.final_price, cascade.low_price, cascade.triggered_orders), (94.0, 94.0, 2));
+        assert!(!cascade.exhausted);
+
+        // A round limit stops the cascade early
+        let limited = simulate(&orders, &bids, &asks, -200.0, 1).unwrap();
+        assert_eq!((limited.rounds.len(), limited.exhausted), (1, true));
+
+        // Fills beyond the book are reported rather than priced
+        let cascade = simulate(&[stop(5, "A", 99.5, 20.0, "below")], &bids, &asks, -100.0, 20).unwrap();
+        assert_eq!((cascade.rounds[0].unfilled_size, cascade.final_price), (10.0, 90.0));
+
+        assert!(simulate(&orders, &[], &asks, -100.0, 20).is_none());
+    }
+
+    #[test]
+    fn test_trigger_falls_back_to_price_side() {
+        let order = StopOrder { trigger_condition: "N/A".to_string(), ..stop(1, "B", 110.0, 1.0, "above") };
+        assert_eq!(trigger(&order, 100.0), (110.0, true));
+        assert_eq!(trigger(&stop(2, "B", 90.0, 1.0, "above"), 100.0), (90.0, true));
+    }
+}
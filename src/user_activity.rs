@@ -0,0 +1,276 @@
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::order_parser::OrderStatus;
+
+/// Window used when none is configured
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(300);
+
+/// What a leaderboard is ranked by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityMetric {
+    OrderRate,
+    CancelToTrade,
+    NotionalVelocity,
+}
+
+impl ActivityMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActivityMetric::OrderRate => "order_rate",
+            ActivityMetric::CancelToTrade => "cancel_to_trade",
+            ActivityMetric::NotionalVelocity => "notional_velocity",
+        }
+    }
+
+    fn value(&self, activity: &UserActivity) -> f64 {
+        match self {
+            ActivityMetric::OrderRate => activity.order_rate,
+            ActivityMetric::CancelToTrade => activity.cancel_to_trade,
+            ActivityMetric::NotionalVelocity => activity.notional_velocity,
+        }
+    }
+}
+
+impl FromStr for ActivityMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "order_rate" => Ok(ActivityMetric::OrderRate),
+            "cancel_to_trade" => Ok(ActivityMetric::CancelToTrade),
+            "notional_velocity" => Ok(ActivityMetric::NotionalVelocity),
+            other => Err(anyhow!(
+                "Unknown metric: {} (expected order_rate, cancel_to_trade or notional_velocity)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Place,
+    Cancel,
+    Fill,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Event {
+    timestamp_ms: u64,
+    market_id: u32,
+    action: Action,
+    notional: f64,
+}
+
+/// One user's behaviour over the window, ranked against every active user
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserActivity {
+    pub user: String,
+    pub orders: u64,
+    pub cancels: u64,
+    pub fills: u64,
+    /// Orders placed per second
+    pub order_rate: f64,
+    /// Cancels per fill; the cancel count when nothing filled
+    pub cancel_to_trade: f64,
+    /// USD placed per second
+    pub notional_velocity: f64,
+    pub market_count: usize,
+    /// Share of active users at or below this user, 0-100
+    pub order_rate_percentile: f64,
+    pub cancel_to_trade_percentile: f64,
+    pub notional_velocity_percentile: f64,
+}
+
+/// Rolling per-user order activity across all markets. Windows follow order
+/// timestamps, so replayed or archived feeds rank the same as live ones.
+pub struct UserActivityTracker {
+    window: Duration,
+    latest_ms: AtomicU64,
+    users: Mutex<HashMap<String, VecDeque<Event>>>,
+}
+
+impl Default for UserActivityTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
+
+impl UserActivityTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.max(Duration::from_secs(1)),
+            latest_ms: AtomicU64::new(0),
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    fn cutoff_ms(&self) -> u64 {
+        self.latest_ms.load(Ordering::Relaxed).saturating_sub(self.window.as_millis() as u64)
+    }
+
+    /// Record one order status from the feed. Rejected and unknown statuses
+    /// are not activity.
+    pub fn observe(&self, user: &str, market_id: u32, status: &OrderStatus, price: f64, size: f64, timestamp_ms: u64) {
+        let action = match status {
+            OrderStatus::Open => Action::Place,
+            OrderStatus::Canceled => Action::Cancel,
+            OrderStatus::Filled => Action::Fill,
+            _ => return,
+        };
+
+        self.latest_ms.fetch_max(timestamp_ms, Ordering::Relaxed);
+        let cutoff = self.cutoff_ms();
+
+        let mut users = self.users.lock();
+        let events = users.entry(user.to_string()).or_default();
+        events.push_back(Event {
+            timestamp_ms,
+            market_id,
+            action,
+            notional: price * size,
+        });
+        while events.front().is_some_and(|e| e.timestamp_ms < cutoff) {
+            events.pop_front();
+        }
+    }
+
+    fn summarize(&self, user: &str, events: &VecDeque<Event>, cutoff: u64) -> UserActivity {
+        let window_secs = self.window.as_secs_f64();
+        let mut activity = UserActivity {
+            user: user.to_string(),
+            ..Default::default()
+        };
+        let mut markets = HashSet::new();
+        let mut placed_notional = 0.0;
+
+        for event in events.iter().filter(|e| e.timestamp_ms >= cutoff) {
+            markets.insert(event.market_id);
+            match event.action {
+                Action::Place => {
+                    activity.orders += 1;
+                    placed_notional += event.notional;
+                }
+                Action::Cancel => activity.cancels += 1,
+                Action::Fill => activity.fills += 1,
+            }
+        }
+
+        activity.order_rate = activity.orders as f64 / window_secs;
+        activity.cancel_to_trade = activity.cancels as f64 / activity.fills.max(1) as f64;
+        activity.notional_velocity = placed_notional / window_secs;
+        activity.market_count = markets.len();
+        activity
+    }
+
+    /// Every user active in the window, with percentiles filled in
+    pub fn all(&self) -> Vec<UserActivity> {
+        let cutoff = self.cutoff_ms();
+        let mut activities: Vec<UserActivity> = self
+            .users
+            .lock()
+            .iter()
+            .map(|(user, events)| self.summarize(user, events, cutoff))
+            .filter(|a| a.orders + a.cancels + a.fills > 0)
+            .collect();
+
+        for metric in [ActivityMetric::OrderRate, ActivityMetric::CancelToTrade, ActivityMetric::NotionalVelocity] {
+            let mut values: Vec<f64> = activities.iter().map(|a| metric.value(a)).collect();
+            values.sort_unstable_by(|a, b| a.total_cmp(b));
+            let count = values.len() as f64;
+
+            for activity in &mut activities {
+                let value = metric.value(activity);
+                let percentile = values.partition_point(|v| *v <= value) as f64 / count * 100.0;
+                match metric {
+                    ActivityMetric::OrderRate => activity.order_rate_percentile = percentile,
+                    ActivityMetric::CancelToTrade => activity.cancel_to_trade_percentile = percentile,
+                    ActivityMetric::NotionalVelocity => activity.notional_velocity_percentile = percentile,
+                }
+            }
+        }
+
+        activities
+    }
+
+    pub fn user(&self, user: &str) -> Option<UserActivity> {
+        self.all().into_iter().find(|a| a.user == user)
+    }
+
+    /// Top `limit` users by `metric`, highest first
+    pub fn leaderboard(&self, metric: ActivityMetric, limit: usize) -> Vec<UserActivity> {
+        let mut activities = self.all();
+        activities.sort_by(|a, b| metric.value(b).total_cmp(&metric.value(a)).then_with(|| a.user.cmp(&b.user)));
+        activities.truncate(limit);
+        activities
+    }
+
+    /// Drop events that left the window and users with none left
+    pub fn start(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let cutoff = self.cutoff_ms();
+                let mut users = self.users.lock();
+                for events in users.values_mut() {
+                    while events.front().is_some_and(|e| e.timestamp_ms < cutoff) {
+                        events.pop_front();
+                    }
+                }
+                users.retain(|_, events| !events.is_empty());
+                debug!("Tracking activity of {} users", users.len());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rates_ratios_and_rankings() {
+        let tracker = UserActivityTracker::new(Duration::from_secs(10));
+        // A quotes on two markets and cancels most of it
+        for i in 0..10 {
+            tracker.observe("a", i % 2, &OrderStatus::Open, 100.0, 1.0, 1_000 + i as u64);
+        }
+        for _ in 0..8 {
+            tracker.observe("a", 0, &OrderStatus::Canceled, 100.0, 1.0, 2_000);
+        }
+        tracker.observe("a", 0, &OrderStatus::Filled, 100.0, 1.0, 2_000);
+        // B places one large order that fills
+        tracker.observe("b", 0, &OrderStatus::Open, 50_000.0, 1.0, 3_000);
+        tracker.observe("b", 0, &OrderStatus::Filled, 50_000.0, 1.0, 3_000);
+        tracker.observe("b", 0, &OrderStatus::Rejected("margin".to_string()), 1.0, 1.0, 3_000);
+
+        let a = tracker.user("a").unwrap();
+        assert_eq!((a.orders, a.cancels, a.fills, a.market_count), (10, 8, 1, 2));
+        assert_eq!(a.order_rate, 1.0);
+        assert_eq!(a.cancel_to_trade, 8.0);
+        assert_eq!(a.order_rate_percentile, 100.0);
+        assert_eq!(a.notional_velocity_percentile, 50.0);
+
+        let board = tracker.leaderboard(ActivityMetric::NotionalVelocity, 1);
+        assert_eq!(board.len(), 1);
+        assert_eq!(board[0].user, "b");
+        assert_eq!(board[0].notional_velocity, 5_000.0);
+
+        // Everything of A's falls out of the window
+        tracker.observe("b", 0, &OrderStatus::Canceled, 1.0, 1.0, 12_500);
+        assert!(tracker.user("a").is_none());
+        assert_eq!(tracker.all().len(), 1);
+    }
+}
@@ -0,0 +1,91 @@
+//! Virtual time for replaying a captured feed.
+//!
+//! In a replay the books' time is the feed's: the clock stands at the newest
+//! node timestamp applied so far, and updates are stamped with it instead of
+//! the wall clock. The pacer spaces the feed out as the node wrote it, sped
+//! up or not at all, without changing what is published: every run of the
+//! same capture publishes the same updates.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Milliseconds since the epoch, moved forward by the replay
+#[derive(Debug, Default)]
+pub struct VirtualClock {
+    now_ms: AtomicU64,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::Acquire)
+    }
+
+    pub fn now_ns(&self) -> u64 {
+        self.now_ms().saturating_mul(1_000_000)
+    }
+
+    /// Move to `event_ms` if it's later; the clock never goes back
+    pub fn advance_to(&self, event_ms: u64) {
+        self.now_ms.fetch_max(event_ms, Ordering::AcqRel);
+    }
+}
+
+/// When to replay each node timestamp, `speed` times as fast as the node
+/// wrote them from the first one on
+#[derive(Debug)]
+pub struct Pacer {
+    speed: f64,
+    /// The first timestamp and when it was replayed
+    origin: Option<(u64, Instant)>,
+}
+
+impl Pacer {
+    /// A speed of 0 (or less) never waits
+    pub fn new(speed: f64) -> Self {
+        Self { speed, origin: None }
+    }
+
+    /// How long from `now` until `event_ms` is due
+    pub fn delay(&mut self, event_ms: u64, now: Instant) -> Duration {
+        if self.speed <= 0.0 || !self.speed.is_finite() {
+            return Duration::ZERO;
+        }
+        let (origin_ms, origin) = *self.origin.get_or_insert((event_ms, now));
+        let elapsed_ms = event_ms.saturating_sub(origin_ms) as f64 / self.speed;
+        (origin + Duration::from_secs_f64(elapsed_ms / 1000.0)).saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_only_moves_forward() {
+        let clock = VirtualClock::new();
+        clock.advance_to(1_740_812_400_000);
+        clock.advance_to(1_740_812_399_000);
+        assert_eq!(clock.now_ms(), 1_740_812_400_000);
+        assert_eq!(clock.now_ns(), 1_740_812_400_000_000_000);
+    }
+
+    #[test]
+    fn test_pacer_scales_the_feed() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(2.0);
+        assert_eq!(pacer.delay(10_000, start), Duration::ZERO);
+        // A second of feed is half a second at 2x, less what has passed
+        assert_eq!(pacer.delay(11_000, start), Duration::from_millis(500));
+        assert_eq!(pacer.delay(11_000, start + Duration::from_millis(200)), Duration::from_millis(300));
+        // Running behind doesn't wait
+        assert_eq!(pacer.delay(11_000, start + Duration::from_secs(1)), Duration::ZERO);
+
+        let mut unpaced = Pacer::new(0.0);
+        assert_eq!(unpaced.delay(10_000, start), Duration::ZERO);
+        assert_eq!(unpaced.delay(3_600_000, start), Duration::ZERO);
+    }
+}
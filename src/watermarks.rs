@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::debug;
+
+use crate::fast_orderbook::FastOrderbook;
+
+/// Event-time progress for one market: every node event with a timestamp at
+/// or before `watermark_ms` is reflected in the book by `sequence`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watermark {
+    pub market_id: u32,
+    pub watermark_ms: u64,
+    pub sequence: u64,
+    /// Open orders applied after the watermark had already passed them
+    pub late_events: u64,
+    pub timestamp_ns: u64,
+}
+
+/// Derives watermarks from ingestion progress. The node writes one file for
+/// all markets in roughly timestamp order, so the newest applied timestamp
+/// minus the allowed lateness bounds what can still arrive, for quiet
+/// markets as much as busy ones.
+pub struct WatermarkTracker {
+    orderbooks: HashMap<u32, Arc<FastOrderbook>>,
+    allowed_lateness_ms: u64,
+    max_applied_ms: AtomicU64,
+    watermark_ms: AtomicU64,
+    late_events: AtomicU64,
+    output_tx: broadcast::Sender<Arc<Vec<Watermark>>>,
+}
+
+impl WatermarkTracker {
+    pub fn new(orderbooks: HashMap<u32, Arc<FastOrderbook>>, allowed_lateness: Duration) -> Self {
+        Self {
+            orderbooks,
+            allowed_lateness_ms: allowed_lateness.as_millis() as u64,
+            max_applied_ms: AtomicU64::new(0),
+            watermark_ms: AtomicU64::new(0),
+            late_events: AtomicU64::new(0),
+            output_tx: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<Watermark>>> {
+        self.output_tx.subscribe()
+    }
+
+    /// Record an order after it was applied. Fills and cancels carry the
+    /// order's placement time, so only opens move the watermark.
+    pub fn observe_applied(&self, timestamp_ms: u64, is_open: bool) {
+        if !is_open {
+            return;
+        }
+        if timestamp_ms <= self.watermark_ms.load(Ordering::Acquire) {
+            self.late_events.fetch_add(1, Ordering::Relaxed);
+        }
+        self.max_applied_ms.fetch_max(timestamp_ms, Ordering::AcqRel);
+    }
+
+    /// Move the watermark up to the applied progress and stamp every book.
+    /// Never moves backwards; `None` until the first order is applied.
+    pub fn advance(&self) -> Option<Arc<Vec<Watermark>>> {
+        // Read progress before sequences, so each sequence covers every
+        // order the watermark claims
+        let candidate = self
+            .max_applied_ms
+            .load(Ordering::Acquire)
+            .saturating_sub(self.allowed_lateness_ms);
+        let watermark_ms = self.watermark_ms.fetch_max(candidate, Ordering::AcqRel).max(candidate);
+        if watermark_ms == 0 {
+            return None;
+        }
+
+        let timestamp_ns = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+        let late_events = self.late_events.load(Ordering::Relaxed);
+        let mut watermarks: Vec<Watermark> = self
+            .orderbooks
+            .iter()
+            .map(|(market_id, orderbook)| Watermark {
+                market_id: *market_id,
+                watermark_ms,
+                sequence: orderbook.sequence.load(Ordering::Acquire),
+                late_events,
+                timestamp_ns,
+            })
+            .collect();
+        watermarks.sort_unstable_by_key(|w| w.market_id);
+
+        let watermarks = Arc::new(watermarks);
+        let _ = self.output_tx.send(watermarks.clone());
+        Some(watermarks)
+    }
+
+    /// Publish watermarks for every market each `interval`, whether or not
+    /// they moved, so consumers can tell an idle feed from a stalled stream
+    pub fn start(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Some(watermark) = self.advance().and_then(|w| w.first().copied()) {
+                    debug!("Watermark {} ms at {} late events", watermark.watermark_ms, watermark.late_events);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fast_orderbook::Order;
+
+    #[test]
+    fn test_watermark_trails_progress_and_never_regresses() {
+        let orderbook = Arc::new(FastOrderbook::new(3, "BTC".to_string()));
+        let tracker = WatermarkTracker::new(
+            [(3, orderbook.clone())].into_iter().collect(),
+            Duration::from_millis(100),
+        );
+        assert!(tracker.advance().is_none());
+
+        orderbook.add_order(Order { id: 1, price: 100.0, size: 1.0, timestamp: 1_000 }, true);
+        tracker.observe_applied(1_000, true);
+        let watermarks = tracker.advance().unwrap();
+        assert_eq!(watermarks[0].watermark_ms, 900);
+        assert_eq!(watermarks[0].sequence, 1);
+
+        // Cancels carry old placement times and don't count
+        tracker.observe_applied(10, false);
+        // An open behind the watermark is late and doesn't move it back
+        tracker.observe_applied(800, true);
+        let watermarks = tracker.advance().unwrap();
+        assert_eq!(watermarks[0].watermark_ms, 900);
+        assert_eq!(watermarks[0].late_events, 1);
+
+        tracker.observe_applied(1_500, true);
+        assert_eq!(tracker.advance().unwrap()[0].watermark_ms, 1_400);
+    }
+}